@@ -0,0 +1,36 @@
+/* C ABI of src/ffi.rs, exported by the ash_rt shared library when built with the winit feature.
+ * Each renderer opens a window of its own, create and use them on the main thread, one at a
+ * time. */
+#ifndef ASH_RT_H
+#define ASH_RT_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define ASH_RT_OK 0
+#define ASH_RT_INVALID_ARGUMENT (-1)
+#define ASH_RT_RENDER_FAILED (-2)
+
+typedef struct AshRtRenderer AshRtRenderer;
+
+/* NULL when the event loop cannot be created, the device is set up by the first render */
+AshRtRenderer *ash_rt_create(void);
+int ash_rt_load_scene(AshRtRenderer *renderer, const char *path);
+/* Three floats each, a field of view of 0 or less keeps the default */
+int ash_rt_set_camera(AshRtRenderer *renderer, const float *position, const float *target,
+                      float fov_y_degrees);
+/* As the executable's --name=value options, e.g. "spp", or a texture quality setting */
+int ash_rt_set_option(AshRtRenderer *renderer, const char *name, const char *value);
+/* A .exr path saves the HDR image and the AOVs */
+int ash_rt_render_to_file(AshRtRenderer *renderer, const char *path, uint32_t columns,
+                          uint32_t rows);
+void ash_rt_destroy(AshRtRenderer *renderer);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,98 @@
+"""ctypes bindings of bindings/ash_rt.h, for scripting batch renders from Python. Renderers
+open a window of their own, create and use them on the main thread, one at a time.
+
+    with Renderer() as renderer:
+        renderer.set_camera((0, 1, -3), (0, 0, 0), fov_y_degrees=60)
+        renderer.set_option("spp", 64)
+        renderer.render_to_file("frame.png")
+"""
+
+import ctypes
+import ctypes.util
+import os
+
+OK = 0
+INVALID_ARGUMENT = -1
+RENDER_FAILED = -2
+
+
+def _load_library(path=None):
+    path = path or os.environ.get("ASH_RT_LIBRARY") or ctypes.util.find_library("ash_rt")
+    if path is None:
+        raise OSError("ash_rt library not found, set ASH_RT_LIBRARY")
+    library = ctypes.CDLL(path)
+    renderer = ctypes.c_void_p
+    vec3 = ctypes.c_float * 3
+    library.ash_rt_create.argtypes = []
+    library.ash_rt_create.restype = renderer
+    library.ash_rt_load_scene.argtypes = [renderer, ctypes.c_char_p]
+    library.ash_rt_set_camera.argtypes = [renderer, vec3, vec3, ctypes.c_float]
+    library.ash_rt_set_option.argtypes = [renderer, ctypes.c_char_p, ctypes.c_char_p]
+    library.ash_rt_render_to_file.argtypes = [
+        renderer,
+        ctypes.c_char_p,
+        ctypes.c_uint32,
+        ctypes.c_uint32,
+    ]
+    library.ash_rt_destroy.argtypes = [renderer]
+    library.ash_rt_destroy.restype = None
+    return library
+
+
+class AshRtError(RuntimeError):
+    pass
+
+
+def _check(result, what):
+    if result != OK:
+        raise AshRtError("{} failed with {}".format(what, result))
+
+
+class Renderer:
+    def __init__(self, library=None):
+        self._library = _load_library(library)
+        self._renderer = self._library.ash_rt_create()
+        if not self._renderer:
+            raise AshRtError("ash_rt_create failed")
+
+    def load_scene(self, path):
+        _check(self._library.ash_rt_load_scene(self._renderer, str(path).encode()), "load_scene")
+
+    def set_camera(self, position, target, fov_y_degrees=0.0):
+        vec3 = ctypes.c_float * 3
+        _check(
+            self._library.ash_rt_set_camera(
+                self._renderer, vec3(*position), vec3(*target), fov_y_degrees
+            ),
+            "set_camera",
+        )
+
+    def set_option(self, name, value):
+        # Parsed as Rust booleans
+        value = str(value).lower() if isinstance(value, bool) else str(value)
+        _check(
+            self._library.ash_rt_set_option(self._renderer, name.encode(), value.encode()),
+            "set_option",
+        )
+
+    def render_to_file(self, path, columns=1, rows=1):
+        _check(
+            self._library.ash_rt_render_to_file(
+                self._renderer, str(path).encode(), columns, rows
+            ),
+            "render_to_file",
+        )
+
+    def close(self):
+        if self._renderer:
+            self._library.ash_rt_destroy(self._renderer)
+            self._renderer = None
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *_):
+        self.close()
+
+    def __del__(self):
+        self.close()
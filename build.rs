@@ -0,0 +1,200 @@
+// With the `shader-compile` feature, every shader in shaders/src is compiled into OUT_DIR before
+// the crate is built, and the renderer loads those in place of the checked-in shaders/compiled.
+// GLSL is compiled with shaderc: relative includes are resolved next to the including file first
+// and then in the bundled library in shaders/include, <...> includes only in the library. HLSL
+// is compiled with DXC, taken from the DXC environment variable or PATH. Without DXC the HLSL
+// stages are skipped with a warning and keep loading from shaders/compiled.
+
+#[cfg(feature = "shader-compile")]
+mod shader_compile {
+    use std::ffi::OsString;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    const SOURCE_DIR: &str = "shaders/src";
+    const INCLUDE_DIR: &str = "shaders/include";
+
+    fn shader_kind(extension: &str) -> Option<shaderc::ShaderKind> {
+        match extension {
+            "vert" => Some(shaderc::ShaderKind::Vertex),
+            "frag" => Some(shaderc::ShaderKind::Fragment),
+            "comp" => Some(shaderc::ShaderKind::Compute),
+            "rgen" => Some(shaderc::ShaderKind::RayGeneration),
+            "rchit" => Some(shaderc::ShaderKind::ClosestHit),
+            "rahit" => Some(shaderc::ShaderKind::AnyHit),
+            "rmiss" => Some(shaderc::ShaderKind::Miss),
+            "rint" => Some(shaderc::ShaderKind::Intersection),
+            _ => None,
+        }
+    }
+
+    // DXC target profile of the HLSL stages, ray tracing stages are built as libraries
+    fn hlsl_profile(stage: &str) -> Option<&'static str> {
+        match stage {
+            "rgen" | "rchit" | "rahit" | "rmiss" | "rint" | "lib" => Some("lib_6_3"),
+            _ => None,
+        }
+    }
+
+    // restir_initial.comp -> restir.glsl_initial_comp, shader-base.vert -> shader-base.glsl_vert,
+    // triangle_bindless.rchit.hlsl -> triangle.hlsl_bindless_rchit
+    fn output_name(stem: &str, language: &str, stage: &str) -> String {
+        match stem.split_once('_') {
+            Some((prefix, variant)) => format!("{}.{}_{}_{}", prefix, language, variant, stage),
+            None => format!("{}.{}_{}", stem, language, stage),
+        }
+    }
+
+    // DXC from the DXC environment variable or PATH, None when it does not run
+    fn find_dxc() -> Option<OsString> {
+        let dxc = std::env::var_os("DXC").unwrap_or_else(|| OsString::from("dxc"));
+        let runs = Command::new(&dxc)
+            .arg("--version")
+            .output()
+            .is_ok_and(|output| output.status.success());
+        runs.then_some(dxc)
+    }
+
+    // <name>.<stage>.hlsl holds one stage, <name>_lib.hlsl a library of them
+    fn compile_hlsl(dxc: &OsString, path: &Path, output_dir: &Path) {
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        let (output_name, stage) = match stem.rsplit_once('.') {
+            Some((name, stage)) => (output_name(name, "hlsl", stage), stage),
+            None => match stem.strip_suffix("_lib") {
+                Some(name) => (format!("{}.hlsl_lib", name), "lib"),
+                None => panic!("HLSL source without a stage: {:?}", path),
+            },
+        };
+        let profile = hlsl_profile(stage)
+            .unwrap_or_else(|| panic!("Unsupported HLSL stage {} of {:?}", stage, path));
+        let output = output_dir.join(format!("{}.spv", output_name));
+
+        // The pipeline is built on VK_NV_ray_tracing, DXC defaults to the KHR extension
+        let result = Command::new(dxc)
+            .args([
+                "-spirv",
+                "-T",
+                profile,
+                "-fspv-extension=SPV_NV_ray_tracing",
+                "-Fo",
+            ])
+            .arg(&output)
+            .arg(path)
+            .output()
+            .unwrap_or_else(|err| panic!("Failed to run {:?}: {}", dxc, err));
+        if !result.status.success() {
+            panic!(
+                "Failed to compile {:?}:\n{}",
+                path,
+                String::from_utf8_lossy(&result.stderr)
+            );
+        }
+    }
+
+    fn resolve_include(
+        requested: &str,
+        include_type: shaderc::IncludeType,
+        requesting: &str,
+    ) -> shaderc::IncludeCallbackResult {
+        let mut candidates = Vec::new();
+        if include_type == shaderc::IncludeType::Relative {
+            if let Some(parent) = Path::new(requesting).parent() {
+                candidates.push(parent.join(requested));
+            }
+        }
+        candidates.push(Path::new(INCLUDE_DIR).join(requested));
+
+        for candidate in candidates {
+            if let Ok(content) = fs::read_to_string(&candidate) {
+                return Ok(shaderc::ResolvedInclude {
+                    resolved_name: candidate.to_string_lossy().into_owned(),
+                    content,
+                });
+            }
+        }
+
+        Err(format!(
+            "Failed to resolve include \"{}\" from {}",
+            requested, requesting
+        ))
+    }
+
+    pub fn compile_shaders() {
+        println!("cargo:rerun-if-changed={}", SOURCE_DIR);
+        println!("cargo:rerun-if-changed={}", INCLUDE_DIR);
+        println!("cargo:rerun-if-env-changed=DXC");
+
+        let output_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap()).join("shaders");
+        fs::create_dir_all(&output_dir).expect("Failed to create the compiled shader directory.");
+        // Read by utility::shader_cache, which prefers these over shaders/compiled
+        println!(
+            "cargo:rustc-env=ASH_RT_COMPILED_SHADER_DIR={}",
+            output_dir.display()
+        );
+
+        let dxc = find_dxc();
+        if dxc.is_none() {
+            println!(
+                "cargo:warning=DXC not found (set DXC to its path), the HLSL shaders are loaded \
+                 from shaders/compiled"
+            );
+        }
+
+        let compiler = shaderc::Compiler::new().expect("Failed to create shader compiler.");
+        let mut options =
+            shaderc::CompileOptions::new().expect("Failed to create shader compile options.");
+        options.set_source_language(shaderc::SourceLanguage::GLSL);
+        // Ray queries need SPIR-V 1.4
+        options.set_target_env(
+            shaderc::TargetEnv::Vulkan,
+            shaderc::EnvVersion::Vulkan1_2 as u32,
+        );
+        options.set_include_callback(|requested, include_type, requesting, _depth| {
+            resolve_include(requested, include_type, requesting)
+        });
+
+        let mut sources: Vec<PathBuf> = fs::read_dir(SOURCE_DIR)
+            .expect("Failed to read the shader source directory.")
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        sources.sort();
+
+        for path in sources {
+            let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+            if extension == "hlsl" {
+                if let Some(dxc) = dxc.as_ref() {
+                    compile_hlsl(dxc, &path, &output_dir);
+                }
+                continue;
+            }
+            // .glsl files are only included
+            let kind = match shader_kind(extension) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let stem = path.file_stem().unwrap().to_str().unwrap();
+
+            let source = fs::read_to_string(&path)
+                .unwrap_or_else(|_| panic!("Failed to read shader source: {:?}", path));
+            let artifact = compiler
+                .compile_into_spirv(
+                    &source,
+                    kind,
+                    &path.to_string_lossy(),
+                    "main",
+                    Some(&options),
+                )
+                .unwrap_or_else(|err| panic!("Failed to compile {:?}:\n{}", path, err));
+
+            let output = output_dir.join(format!("{}.spv", output_name(stem, "glsl", extension)));
+            fs::write(&output, artifact.as_binary_u8())
+                .unwrap_or_else(|_| panic!("Failed to write compiled shader: {:?}", output));
+        }
+    }
+}
+
+fn main() {
+    #[cfg(feature = "shader-compile")]
+    shader_compile::compile_shaders();
+}
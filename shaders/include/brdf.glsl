@@ -0,0 +1,109 @@
+#ifndef BRDF_GLSL
+#define BRDF_GLSL
+
+#include "common.glsl"
+
+// Mirrors the InstanceData surface parameters, see Material in src/utility/material.rs
+struct SurfaceParams {
+    vec3 albedo;
+    float roughness;
+    float metallic;
+    // Stretches the highlight along the tangent for positive values, along the bitangent for
+    // negative ones
+    float anisotropy;
+};
+
+// Dielectric reflectance at normal incidence, an index of refraction of 1.5
+const vec3 DIELECTRIC_F0 = vec3(0.04);
+// Keeps the highlight of a perfectly smooth surface finite
+const float MIN_GGX_ALPHA = 0.002;
+// Irradiance of the sun, a white surface facing it comes out at 0.7 like the old flat shading
+const float SUN_IRRADIANCE = 0.7 * PI;
+// Stands in for indirect light
+const float AMBIENT_LIGHT = 0.3;
+
+vec3 lambertBrdf(vec3 albedo) {
+    return albedo * INV_PI;
+}
+
+vec3 fresnelSchlick(vec3 f0, float cosTheta) {
+    return f0 + (1.0 - f0) * pow(1.0 - clamp(cosTheta, 0.0, 1.0), 5.0);
+}
+
+vec3 specularF0(SurfaceParams surface) {
+    return mix(DIELECTRIC_F0, surface.albedo, surface.metallic);
+}
+
+// Any tangent for surfaces without a parameterization, anisotropic highlights on them follow
+// world space x or z
+vec3 fallbackTangent(vec3 n) {
+    vec3 axis = abs(n.x) < 0.9 ? vec3(1.0, 0.0, 0.0) : vec3(0.0, 0.0, 1.0);
+    return normalize(axis - n * dot(n, axis));
+}
+
+// Karis' analytic fit of the split sum GGX directional albedo, as scale and bias of f0
+vec2 ggxDirectionalAlbedo(float NoV, float roughness) {
+    const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+    const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+    vec4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * NoV)) * r.x + r.y;
+    return vec2(-1.04, 1.04) * a004 + r.zw;
+}
+
+// Anisotropic GGX normal distribution, Burley's parameterization
+float ggxDistribution(float ToH, float BoH, float NoH, float alphaT, float alphaB) {
+    float a2 = alphaT * alphaB;
+    vec3 v = vec3(alphaB * ToH, alphaT * BoH, a2 * NoH);
+    float w2 = a2 / dot(v, v);
+    return a2 * w2 * w2 * INV_PI;
+}
+
+// Height correlated Smith masking-shadowing, divided by 4 NoV NoL
+float ggxVisibility(float ToV, float BoV, float NoV, float ToL, float BoL, float NoL,
+                    float alphaT, float alphaB) {
+    float lambdaV = NoL * length(vec3(alphaT * ToV, alphaB * BoV, NoV));
+    float lambdaL = NoV * length(vec3(alphaT * ToL, alphaB * BoL, NoL));
+    return 0.5 / (lambdaV + lambdaL);
+}
+
+// Light reflected towards v per unit of irradiance from l, with the cosine of the light already
+// applied. The surface is two-sided, n is flipped towards the viewer.
+vec3 evaluateBrdf(SurfaceParams surface, vec3 n, vec3 t, vec3 v, vec3 l) {
+    n = dot(n, v) < 0.0 ? -n : n;
+    float NoL = dot(n, l);
+    if (NoL <= 0.0) {
+        return vec3(0.0);
+    }
+    float NoV = max(dot(n, v), 1e-4);
+    t = normalize(t - n * dot(n, t));
+    vec3 b = cross(n, t);
+    vec3 h = normalize(v + l);
+
+    float alpha = surface.roughness * surface.roughness;
+    float alphaT = max(alpha * (1.0 + surface.anisotropy), MIN_GGX_ALPHA);
+    float alphaB = max(alpha * (1.0 - surface.anisotropy), MIN_GGX_ALPHA);
+
+    vec3 f0 = specularF0(surface);
+    float D = ggxDistribution(dot(t, h), dot(b, h), dot(n, h), alphaT, alphaB);
+    float V = ggxVisibility(dot(t, v), dot(b, v), NoV, dot(t, l), dot(b, l), NoL, alphaT, alphaB);
+    vec3 F = fresnelSchlick(f0, dot(v, h));
+
+    // A single scattering lobe loses the energy of the paths bouncing between microfacets, rough
+    // metals get it back by scaling with the inverse of the directional albedo
+    vec2 directionalAlbedo = ggxDirectionalAlbedo(NoV, surface.roughness);
+    vec3 energyCompensation = 1.0 + f0 * (1.0 / max(directionalAlbedo.x + directionalAlbedo.y, 1e-3) - 1.0);
+
+    vec3 specular = D * V * F * energyCompensation;
+    vec3 diffuse = (1.0 - surface.metallic) * (1.0 - F) * lambertBrdf(surface.albedo);
+    return (diffuse + specular) * NoL;
+}
+
+// Reflectance under uniform ambient light, used where the constant ambient term is applied
+vec3 ambientReflectance(SurfaceParams surface, vec3 n, vec3 v) {
+    float NoV = abs(dot(n, v));
+    vec2 directionalAlbedo = ggxDirectionalAlbedo(NoV, surface.roughness);
+    vec3 specular = specularF0(surface) * directionalAlbedo.x + directionalAlbedo.y;
+    return (1.0 - surface.metallic) * surface.albedo + specular;
+}
+
+#endif
@@ -0,0 +1,51 @@
+#ifndef CAMERA_GLSL
+#define CAMERA_GLSL
+
+// Mirrors CameraPushConstants in src/utility/structures.rs
+struct CameraView {
+    vec4 origin; // w = 1 for orthographic projections
+    vec4 right;
+    vec4 up;
+    vec4 forward;
+    vec4 window; // left, right, bottom, top
+};
+
+bool isOrthographic(CameraView camera) {
+    return camera.origin.w > 0.5;
+}
+
+// Offset perpendicular to forward of the point under uv on the view window, uv = (0, 0) is the
+// top left image corner
+vec3 windowOffset(CameraView camera, vec2 uv) {
+    // Image rows go down while the window's up axis goes up
+    float u = mix(camera.window.x, camera.window.y, uv.x);
+    float v = mix(camera.window.w, camera.window.z, uv.y);
+    return camera.right.xyz * u + camera.up.xyz * v;
+}
+
+void primaryRay(CameraView camera, vec2 uv, out vec3 origin, out vec3 direction) {
+    vec3 offset = windowOffset(camera, uv);
+    bool orthographic = isOrthographic(camera);
+    origin = orthographic ? camera.origin.xyz + offset : camera.origin.xyz;
+    direction = orthographic ? camera.forward.xyz : normalize(camera.forward.xyz + offset);
+}
+
+// Distance along forward of a [0, 1] depth buffer value written with Camera::clip_from_world
+float linearizeDepth(CameraView camera, float depth, float nearPlane, float farPlane) {
+    if (isOrthographic(camera)) {
+        return mix(nearPlane, farPlane, depth);
+    }
+    return nearPlane * farPlane / (farPlane - depth * (farPlane - nearPlane));
+}
+
+vec3 reconstructWorldPosition(CameraView camera, vec2 uv, float depth, float nearPlane, float farPlane) {
+    vec3 offset = windowOffset(camera, uv);
+    float viewDepth = linearizeDepth(camera, depth, nearPlane, farPlane);
+    if (isOrthographic(camera)) {
+        return camera.origin.xyz + offset + camera.forward.xyz * viewDepth;
+    }
+    // The window lies at unit distance, so forward + offset is scaled by the forward distance
+    return camera.origin.xyz + (camera.forward.xyz + offset) * viewDepth;
+}
+
+#endif
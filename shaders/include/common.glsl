@@ -0,0 +1,11 @@
+#ifndef COMMON_GLSL
+#define COMMON_GLSL
+
+const float PI = 3.14159265359;
+const float INV_PI = 0.318309886;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+#endif
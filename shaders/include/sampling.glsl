@@ -0,0 +1,45 @@
+#ifndef SAMPLING_GLSL
+#define SAMPLING_GLSL
+
+#include "common.glsl"
+
+uint pcgHash(uint value) {
+    uint state = value * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Advances the seed and maps it to [0, 1)
+float randomFloat(inout uint seed) {
+    seed = pcgHash(seed);
+    return float(seed) / 4294967296.0;
+}
+
+// Uniform point on the triangle (v0, v1, v2) from two uniform numbers
+vec3 sampleTriangle(vec3 v0, vec3 v1, vec3 v2, vec2 u) {
+    float su = sqrt(u.x);
+    return v0 + (v1 - v0) * (1.0 - su) + (v2 - v0) * (u.y * su);
+}
+
+vec2 sampleDisk(vec2 u) {
+    float radius = sqrt(u.x);
+    float angle = 2.0 * PI * u.y;
+    return radius * vec2(cos(angle), sin(angle));
+}
+
+// Cosine weighted direction around +z, the pdf is cos(theta) / PI
+vec3 sampleCosineHemisphere(vec2 u) {
+    vec2 disk = sampleDisk(u);
+    return vec3(disk, sqrt(max(0.0, 1.0 - dot(disk, disk))));
+}
+
+// Tangent and bitangent completing the unit normal n to an orthonormal basis
+void orthonormalBasis(vec3 n, out vec3 tangent, out vec3 bitangent) {
+    float s = n.z >= 0.0 ? 1.0 : -1.0;
+    float a = -1.0 / (s + n.z);
+    float b = n.x * n.y * a;
+    tangent = vec3(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+    bitangent = vec3(b, s + n.y * n.y * a, -n.y);
+}
+
+#endif
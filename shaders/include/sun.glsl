@@ -0,0 +1,10 @@
+#ifndef SUN_GLSL
+#define SUN_GLSL
+
+// Mirrors SunParams in src/utility/structures.rs
+struct SunParams {
+    vec4 direction; // xyz = towards the sun, w = scale of SUN_IRRADIANCE
+    vec4 skyColor; // rgb = background of rays that leave the scene
+};
+
+#endif
@@ -0,0 +1,32 @@
+#ifndef TILED_LIGHTS_GLSL
+#define TILED_LIGHTS_GLSL
+
+// Mirrors ClusteredLight in src/utility/structures.rs
+struct ClusteredLight {
+    vec4 position; // w = radius past which the light is cut off
+    vec4 color; // rgb = color, a = intensity
+};
+
+// Mirror LIGHT_TILE_SIZE and LIGHT_TILE_MAX_LIGHTS in src/utility/constants.rs. Every tile of the
+// light list buffer holds its light count followed by that many light indices.
+const uint LIGHT_TILE_SIZE = 16;
+const uint LIGHT_TILE_MAX_LIGHTS = 63;
+const uint LIGHT_TILE_STRIDE = LIGHT_TILE_MAX_LIGHTS + 1;
+
+uint lightTileIndex(ivec2 pixel, ivec2 imageSize) {
+    uint tilesX = (uint(imageSize.x) + LIGHT_TILE_SIZE - 1) / LIGHT_TILE_SIZE;
+    uvec2 tile = uvec2(pixel) / LIGHT_TILE_SIZE;
+    return (tile.y * tilesX + tile.x) * LIGHT_TILE_STRIDE;
+}
+
+// Inverse square falloff windowed to reach zero at the radius, so culling by the radius is exact
+vec3 pointLightIrradiance(ClusteredLight light, vec3 position, out vec3 toLight) {
+    vec3 offset = light.position.xyz - position;
+    float distanceSquared = max(dot(offset, offset), 1e-4);
+    toLight = offset * inversesqrt(distanceSquared);
+    float ratio = distanceSquared / (light.position.w * light.position.w);
+    float window = clamp(1.0 - ratio * ratio, 0.0, 1.0);
+    return light.color.rgb * light.color.a * window * window / distanceSquared;
+}
+
+#endif
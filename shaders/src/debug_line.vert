@@ -0,0 +1,16 @@
+#version 450
+
+// Mirrors DebugDrawPushConstants in src/utility/structures.rs
+layout(push_constant) uniform DebugDrawConstants {
+    mat4 clipFromWorld;
+} constants;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec4 inColor;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    gl_Position = constants.clipFromWorld * vec4(inPosition, 1.0);
+    outColor = inColor;
+}
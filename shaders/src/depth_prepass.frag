@@ -0,0 +1,9 @@
+#version 450
+
+layout(location = 0) flat in uint inInstance;
+
+layout(location = 0) out uint outInstance;
+
+void main() {
+    outInstance = inInstance;
+}
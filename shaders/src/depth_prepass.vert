@@ -0,0 +1,16 @@
+#version 450
+
+// One clip_from_object per instance in instance data order. Draws come from the occlusion
+// culling pass with firstInstance set to the instance index.
+layout(std430, binding = 0) readonly buffer Transforms {
+    mat4 clipFromObject[];
+};
+
+layout(location = 0) in vec3 inPosition;
+
+layout(location = 0) flat out uint outInstance;
+
+void main() {
+    outInstance = gl_InstanceIndex;
+    gl_Position = clipFromObject[gl_InstanceIndex] * vec4(inPosition, 1.0);
+}
@@ -0,0 +1,12 @@
+layout(push_constant) uniform PushConstants {
+    float minLogLuminance;
+    float logLuminanceRange;
+    float deltaTime;
+    float adaptationSpeed;
+    float evCompensation;
+    uint width;
+    uint height;
+    uint toneCurve;
+    vec4 lutDomainMin;
+    vec4 lutDomainMax;
+} pc;
@@ -0,0 +1,79 @@
+#include <brdf.glsl>
+#include <common.glsl>
+#include <sampling.glsl>
+
+#include "restir_types.glsl"
+
+#define WORKGROUP_SIZE 8
+
+const vec3 BACKGROUND_COLOR = vec3(0.0, 0.1, 0.3);
+
+layout(binding = 0, set = 0, std430) readonly buffer Lights { PointLight lights[]; };
+layout(binding = 1, set = 0, std430) readonly buffer GBuffer { GBufferSample gbuffer[]; };
+// Written by the initial and temporal passes, read by the spatial pass
+layout(binding = 2, set = 0, std430) buffer CurrentReservoirs { Reservoir currentReservoirs[]; };
+// Written by the spatial pass, shaded this frame and reused temporally by the next one
+layout(binding = 3, set = 0, std430) buffer PreviousReservoirs { Reservoir previousReservoirs[]; };
+layout(binding = 4, set = 0) uniform writeonly image2D outputImage;
+
+layout(push_constant) uniform PushConstants {
+    uint frameIndex;
+    uint lightCount;
+    uint initialCandidates;
+    uint maxHistory;
+    uint spatialNeighbors;
+    float spatialRadius;
+    uint width;
+    uint height;
+} pc;
+
+uint pixelIndex(uvec2 pixel) {
+    return pixel.y * pc.width + pixel.x;
+}
+
+uint initSeed(uvec2 pixel, uint pass) {
+    return pcgHash(pixelIndex(pixel) ^ pcgHash(pc.frameIndex * 4u + pass));
+}
+
+// Lambertian response to a point light, visibility is not tested
+vec3 unshadowedContribution(GBufferSample surface, PointLight light) {
+    vec3 toLight = light.position.xyz - surface.position.xyz;
+    float distanceSquared = max(dot(toLight, toLight), 1e-4);
+    vec3 lightDirection = toLight * inversesqrt(distanceSquared);
+    float cosTheta = max(dot(surface.normal.xyz, lightDirection), 0.0);
+    return lambertBrdf(surface.albedo.rgb) * light.color.rgb * light.color.a * cosTheta / distanceSquared;
+}
+
+float targetPdf(GBufferSample surface, uint lightIndex) {
+    return luminance(unshadowedContribution(surface, lights[lightIndex]));
+}
+
+Reservoir emptyReservoir() {
+    Reservoir reservoir;
+    reservoir.lightIndex = 0u;
+    reservoir.sampleCount = 0u;
+    reservoir.weightSum = 0.0;
+    reservoir.contributionWeight = 0.0;
+    return reservoir;
+}
+
+void updateReservoir(inout Reservoir reservoir, uint lightIndex, float weight, uint sampleCount, inout uint seed) {
+    reservoir.weightSum += weight;
+    reservoir.sampleCount += sampleCount;
+    if (weight > 0.0 && randomFloat(seed) * reservoir.weightSum <= weight) {
+        reservoir.lightIndex = lightIndex;
+    }
+}
+
+// Streams another pixel's reservoir into this one, re-weighting its sample for this surface
+void combineReservoir(inout Reservoir reservoir, Reservoir other, GBufferSample surface, inout uint seed) {
+    float weight = targetPdf(surface, other.lightIndex) * other.contributionWeight * float(other.sampleCount);
+    updateReservoir(reservoir, other.lightIndex, weight, other.sampleCount, seed);
+}
+
+void finalizeReservoir(inout Reservoir reservoir, GBufferSample surface) {
+    float pdf = targetPdf(surface, reservoir.lightIndex);
+    reservoir.contributionWeight = (pdf > 0.0 && reservoir.sampleCount > 0u)
+        ? reservoir.weightSum / (float(reservoir.sampleCount) * pdf)
+        : 0.0;
+}
@@ -0,0 +1,19 @@
+// Shared between the ReSTIR ray tracing and compute shaders, mirrors src/utility/structures.rs
+
+struct PointLight {
+    vec4 position;
+    vec4 color; // rgb = color, a = intensity
+};
+
+struct GBufferSample {
+    vec4 position; // w = 1.0 when the primary ray hit geometry
+    vec4 normal;
+    vec4 albedo;
+};
+
+struct Reservoir {
+    uint lightIndex;
+    uint sampleCount;
+    float weightSum;
+    float contributionWeight;
+};
@@ -0,0 +1,188 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include <brdf.glsl>
+#include <camera.glsl>
+#include <sun.glsl>
+#include <tiled_lights.glsl>
+
+struct InstanceData {
+    vec4 color;
+    vec4 emission;
+    vec4 surface; // x = roughness, y = metallic, z = anisotropy
+    vec4 subsurface; // rgb = mean free path, zero for opaque surfaces
+};
+
+layout(binding = 0, std430) readonly buffer Instances { InstanceData instances[]; };
+// Written by the raster depth pre-pass
+layout(binding = 1) uniform sampler2D depthBuffer;
+layout(binding = 2) uniform usampler2D instanceBuffer;
+// Depth seen from the light, compared with LESS_OR_EQUAL
+layout(binding = 3) uniform sampler2DShadow shadowMap;
+// Mirrors ShadowMapUniforms in src/utility/structures.rs
+layout(binding = 4) uniform ShadowMapUniforms {
+    mat4 lightFromWorld;
+    float texelSize;
+    // The same sun the ray traced modes are lit by
+    SunParams sun;
+} shadow;
+layout(binding = 5, std430) readonly buffer Lights { ClusteredLight lights[]; };
+// The lights reaching each tile, culled by light_cull.comp
+layout(binding = 6, std430) readonly buffer Tiles { uint tileLights[]; };
+// Environment probes captured by capture_environment_probes, six faces per probe as array layers
+// with the radiance prefiltered for roughness 0 to 1 down the mips. Without probes the count is 0.
+layout(binding = 7) uniform sampler2DArray probeFaces;
+layout(binding = 8, std430) readonly buffer Probes {
+    uint probeCount;
+    vec4 probePositions[];
+};
+
+layout(push_constant) uniform Constants {
+    CameraView camera;
+    float nearPlane;
+    float farPlane;
+} constants;
+
+layout(location = 0) out vec4 outColor;
+
+// InstanceData emission.a, mirrors INSTANCE_MATERIAL_* in src/utility/constants.rs
+const float MATERIAL_CHECKER = 1.0;
+const float MATERIAL_SHADOW_CATCHER = 2.0;
+const float CHECKER_DARK_SHADE = 0.5;
+
+// Forward and up of the camera that captured each probe face, mirrors PROBE_FACES in
+// src/utility/environment_probe.rs
+const vec3 PROBE_FACE_FORWARD[6] = vec3[](
+    vec3(1.0, 0.0, 0.0), vec3(-1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0),
+    vec3(0.0, -1.0, 0.0), vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+const vec3 PROBE_FACE_UP[6] = vec3[](
+    vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, -1.0),
+    vec3(0.0, 0.0, 1.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0));
+
+vec3 positionAt(ivec2 pixel) {
+    vec2 uv = (vec2(pixel) + 0.5) / vec2(textureSize(depthBuffer, 0));
+    float depth = texelFetch(depthBuffer, pixel, 0).r;
+    return reconstructWorldPosition(constants.camera, uv, depth, constants.nearPlane, constants.farPlane);
+}
+
+// Geometric normal from the positions of the neighboring pixels, facing the camera
+vec3 reconstructNormal(ivec2 pixel, vec3 position) {
+    ivec2 size = textureSize(depthBuffer, 0);
+    ivec2 stepX = ivec2(pixel.x + 1 < size.x ? 1 : -1, 0);
+    ivec2 stepY = ivec2(0, pixel.y + 1 < size.y ? 1 : -1);
+    vec3 dx = positionAt(pixel + stepX) - position;
+    vec3 dy = positionAt(pixel + stepY) - position;
+
+    vec3 normal = normalize(cross(dy, dx));
+    vec3 toCamera = isOrthographic(constants.camera)
+        ? -constants.camera.forward.xyz
+        : constants.camera.origin.xyz - position;
+    return dot(normal, toCamera) < 0.0 ? -normal : normal;
+}
+
+// 3x3 percentage closer filtering, everything outside the light view is lit
+float shadowVisibility(vec3 position) {
+    vec3 light = (shadow.lightFromWorld * vec4(position, 1.0)).xyz;
+    vec2 uv = light.xy * 0.5 + 0.5;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || light.z > 1.0) {
+        return 1.0;
+    }
+
+    float visibility = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            visibility += texture(shadowMap, vec3(uv + vec2(x, y) * shadow.texelSize, light.z));
+        }
+    }
+    return visibility / 9.0;
+}
+
+// Every light of the pixel's tile, unshadowed
+vec3 pointLighting(ivec2 pixel, SurfaceParams surface, vec3 position, vec3 normal, vec3 toCamera) {
+    uint tile = lightTileIndex(pixel, textureSize(depthBuffer, 0));
+    vec3 lighting = vec3(0.0);
+    for (uint i = 0; i < tileLights[tile]; i++) {
+        vec3 toLight;
+        vec3 irradiance = pointLightIrradiance(lights[tileLights[tile + 1 + i]], position, toLight);
+        lighting += evaluateBrdf(surface, normal, fallbackTangent(normal), toCamera, toLight) * irradiance;
+    }
+    return lighting;
+}
+
+uint nearestProbe(vec3 position) {
+    uint nearest = 0;
+    for (uint i = 1; i < probeCount; i++) {
+        vec3 toProbe = probePositions[i].xyz - position;
+        vec3 toNearest = probePositions[nearest].xyz - position;
+        if (dot(toProbe, toProbe) < dot(toNearest, toNearest)) {
+            nearest = i;
+        }
+    }
+    return nearest;
+}
+
+// Radiance of the probe seen along `direction`, from the face the direction leaves its cube
+// through at the mip prefiltered for `roughness`
+vec3 probeRadiance(uint probe, vec3 direction, float roughness) {
+    vec3 a = abs(direction);
+    int face = a.x >= a.y && a.x >= a.z ? (direction.x >= 0.0 ? 0 : 1)
+        : a.y >= a.z ? (direction.y >= 0.0 ? 2 : 3)
+        : (direction.z >= 0.0 ? 4 : 5);
+    vec3 forward = PROBE_FACE_FORWARD[face];
+    vec3 up = PROBE_FACE_UP[face];
+    vec3 right = cross(up, forward);
+    // Image rows go down while the window's up axis goes up, like windowOffset
+    vec2 window = vec2(dot(direction, right), dot(direction, up)) / dot(direction, forward);
+    vec2 uv = vec2(window.x, -window.y) * 0.5 + 0.5;
+    float lod = roughness * float(textureQueryLevels(probeFaces) - 1);
+    return textureLod(probeFaces, vec3(uv, float(probe * 6 + face)), lod).rgb;
+}
+
+// Ambient light with the specular part reflecting the nearest probe, split like
+// ambientReflectance. n faces the viewer.
+vec3 probeAmbient(SurfaceParams surface, vec3 position, vec3 n, vec3 v) {
+    vec2 directionalAlbedo = ggxDirectionalAlbedo(abs(dot(n, v)), surface.roughness);
+    vec3 specular = specularF0(surface) * directionalAlbedo.x + directionalAlbedo.y;
+    vec3 reflected = probeRadiance(nearestProbe(position), reflect(-v, n), surface.roughness);
+    return (1.0 - surface.metallic) * surface.albedo * AMBIENT_LIGHT + specular * reflected;
+}
+
+// Same shading as the hybrid ray generation shader, only the visibility differs. Environment
+// probes, when captured, stand in for the constant ambient light of the specular reflections.
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(depthBuffer, pixel, 0).r;
+
+    vec3 color = shadow.sun.skyColor.rgb;
+    if (depth < 1.0) {
+        vec3 position = positionAt(pixel);
+        vec3 normal = reconstructNormal(pixel, position);
+        InstanceData instance = instances[texelFetch(instanceBuffer, pixel, 0).r];
+
+        // Lifted off the surface like the shadow ray origin
+        float visibility = shadowVisibility(
+            position + normal * 1e-3 * length(position - constants.camera.origin.xyz));
+
+        vec3 albedo = instance.color.rgb;
+        if (instance.emission.a == MATERIAL_CHECKER) {
+            // color.a is the square size
+            vec2 cell = floor(position.xz / instance.color.a);
+            albedo *= (int(cell.x + cell.y) & 1) != 0 ? CHECKER_DARK_SHADE : 1.0;
+        }
+        SurfaceParams surface = SurfaceParams(albedo, instance.surface.x, instance.surface.y, instance.surface.z);
+        vec3 toCamera = isOrthographic(constants.camera)
+            ? -constants.camera.forward.xyz
+            : normalize(constants.camera.origin.xyz - position);
+        vec3 direct = evaluateBrdf(surface, normal, fallbackTangent(normal), toCamera, shadow.sun.direction.xyz);
+        vec3 ambient = probeCount > 0
+            ? probeAmbient(surface, position, normal, toCamera)
+            : ambientReflectance(surface, normal, toCamera) * AMBIENT_LIGHT;
+        color = instance.emission.a == MATERIAL_SHADOW_CATCHER
+            ? shadow.sun.skyColor.rgb * mix(0.3, 1.0, visibility)
+            : ambient
+                + direct * SUN_IRRADIANCE * shadow.sun.direction.w * visibility + instance.emission.rgb
+                + pointLighting(pixel, surface, position, normal, toCamera);
+    }
+
+    outColor = vec4(color, 1.0);
+}
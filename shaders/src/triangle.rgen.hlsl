@@ -0,0 +1,87 @@
+struct Payload
+{
+    float3 hitValue;
+    // Ray cone of the pixel: width at the ray origin and spread angle, for texture filtering
+    float coneWidth;
+    float coneSpreadAngle;
+};
+
+struct CameraConstants
+{
+    float4 origin; // w = 1 for orthographic projections
+    float4 right;
+    float4 up;
+    float4 forward;
+    float4 window; // left, right, bottom, top
+    // 1 / (sampleIndex + 1), used to average the time samples and samples per pixel
+    float accumulationWeight;
+    uint sampleIndex;
+    // DepthRangePushConstants, the near and far planes along forward
+    float2 depthRange;
+    // RayMaskPushConstants, cull masks built from RenderLayers
+    uint primaryMask;
+    uint secondaryMask;
+    // ViewportPushConstants, the dispatch covers only this viewport
+    uint2 viewportOffset;
+    uint2 viewportSize;
+    // 0 traces every pixel, otherwise 1 + the parity of (x + y) of the pixels traced this frame
+    uint checkerboard;
+};
+
+[[vk::push_constant]] CameraConstants g_camera;
+
+// Overridden from the host with PRIMARY_RAY_FLAGS, which drops opaque for stochastic transparency
+[[vk::constant_id(3)]] const uint PRIMARY_RAY_FLAGS = 1;
+
+[[vk::binding(0, 0)]] RaytracingAccelerationStructure g_topLevel;
+// In whichever HdrFormat was chosen, loaded without a format
+[[vk::binding(1, 1)]] RWTexture2D<float4> g_output;
+
+[shader("raygeneration")]
+void main()
+{
+    uint2 launchIndex = DispatchRaysIndex().xy;
+    uint2 pixel = launchIndex + g_camera.viewportOffset;
+    if (g_camera.checkerboard != 0)
+    {
+        // Every other pixel of the row, the reconstruction pass fills in the rest
+        pixel.x = g_camera.viewportOffset.x + launchIndex.x * 2
+            + (g_camera.viewportOffset.x + pixel.y + g_camera.checkerboard - 1) % 2;
+        if (pixel.x >= g_camera.viewportOffset.x + g_camera.viewportSize.x)
+        {
+            return;
+        }
+    }
+    // R2 sequence sub-pixel offset, the first sample goes through the pixel center
+    float2 jitter = frac(0.5 + float(g_camera.sampleIndex) * float2(0.754877666, 0.569840291));
+    float2 uv = (float2(pixel - g_camera.viewportOffset) + jitter) / float2(g_camera.viewportSize);
+
+    // Image rows go down while the window's up axis goes up
+    float u = lerp(g_camera.window.x, g_camera.window.y, uv.x);
+    float v = lerp(g_camera.window.w, g_camera.window.z, uv.y);
+    float3 offset = g_camera.right.xyz * u + g_camera.up.xyz * v;
+
+    bool orthographic = g_camera.origin.w > 0.5;
+
+    RayDesc ray;
+    ray.Origin = orthographic ? g_camera.origin.xyz + offset : g_camera.origin.xyz;
+    ray.Direction = orthographic ? g_camera.forward.xyz : normalize(g_camera.forward.xyz + offset);
+    // The planes are distances along forward, off-center rays cross them further out
+    float forwardCosine = dot(ray.Direction, g_camera.forward.xyz);
+    ray.TMin = g_camera.depthRange.x / forwardCosine;
+    ray.TMax = g_camera.depthRange.y / forwardCosine;
+
+    // A perspective cone starts at a point and widens by the angle of a pixel, an orthographic one
+    // keeps the width of a pixel
+    float pixelSize = (g_camera.window.w - g_camera.window.z) / float(g_camera.viewportSize.y);
+
+    Payload payload;
+    payload.hitValue = float3(0.0, 0.0, 0.0);
+    payload.coneWidth = orthographic ? pixelSize : 0.0;
+    payload.coneSpreadAngle = orthographic ? 0.0 : pixelSize;
+    TraceRay(g_topLevel, PRIMARY_RAY_FLAGS, g_camera.primaryMask, 0, 0, 0, ray, payload);
+
+    float4 color = float4(payload.hitValue, 1.0);
+    float4 accumulated = lerp(g_output[pixel], color, g_camera.accumulationWeight);
+    g_output[pixel] = g_camera.accumulationWeight >= 1.0 ? color : accumulated;
+}
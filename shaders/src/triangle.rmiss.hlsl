@@ -0,0 +1,97 @@
+struct Payload
+{
+    float3 hitValue;
+    // Ray cone of the pixel: width at the ray origin and spread angle, for texture filtering
+    float coneWidth;
+    float coneSpreadAngle;
+};
+
+struct FogParams
+{
+    float4 color; // rgb = in-scattered radiance, a = uniform density
+    float4 gridMin; // w = grid density scale, 0 without a grid
+    float4 gridMax; // w = majorant of the scaled grid densities
+    uint4 gridSize;
+};
+
+struct SunParams
+{
+    float4 direction; // xyz = towards the sun, w = scale of SUN_IRRADIANCE
+    float4 skyColor; // rgb = background of rays that leave the scene
+};
+
+// Mirrors FogParams in src/utility/structures.rs
+[[vk::binding(9, 0)]] StructuredBuffer<FogParams> g_fog;
+// x varies fastest, then y, then z
+[[vk::binding(10, 0)]] StructuredBuffer<float> g_fogDensity;
+// Mirrors SunParams in src/utility/structures.rs
+[[vk::binding(11, 0)]] StructuredBuffer<SunParams> g_sun;
+
+// Same as the closest hit shader
+static const uint RATIO_TRACKING_MAX_STEPS = 256;
+
+uint pcgHash(uint value)
+{
+    uint state = value * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+float toUnitFloat(uint value)
+{
+    return float(value) * 2.3283064365386963e-10;
+}
+
+float gridDensity(FogParams fog, float3 position)
+{
+    float3 uvw = (position - fog.gridMin.xyz) / (fog.gridMax.xyz - fog.gridMin.xyz);
+    if (any(uvw < 0.0) || any(uvw >= 1.0))
+    {
+        return 0.0;
+    }
+    uint3 voxel = min(uint3(uvw * float3(fog.gridSize.xyz)), fog.gridSize.xyz - 1);
+    return g_fogDensity[(voxel.z * fog.gridSize.y + voxel.y) * fog.gridSize.x + voxel.x] * fog.gridMin.w;
+}
+
+// Transmittance along a normalized ray up to tMax: closed form for the uniform fog, ratio tracking
+// against the majorant through the density grid
+float fogTransmittance(float3 origin, float3 direction, float tMax, inout uint seed)
+{
+    FogParams fog = g_fog[0];
+    float transmittance = exp(-fog.color.a * tMax);
+    float majorant = fog.gridMax.w;
+    if (majorant <= 0.0)
+    {
+        return transmittance;
+    }
+
+    // Only the part of the ray inside the grid bounds is tracked
+    float3 inverseDirection = 1.0 / direction;
+    float3 t0 = (fog.gridMin.xyz - origin) * inverseDirection;
+    float3 t1 = (fog.gridMax.xyz - origin) * inverseDirection;
+    float3 tNear = min(t0, t1);
+    float3 tFar = max(t0, t1);
+    float t = max(max(tNear.x, tNear.y), max(tNear.z, 0.0));
+    float tExit = min(min(tFar.x, tFar.y), min(tFar.z, tMax));
+
+    for (uint step = 0; step < RATIO_TRACKING_MAX_STEPS; step++)
+    {
+        t -= log(1.0 - toUnitFloat(seed)) / majorant;
+        seed = pcgHash(seed);
+        if (t >= tExit)
+        {
+            break;
+        }
+        transmittance *= 1.0 - gridDensity(fog, origin + direction * t) / majorant;
+    }
+    return transmittance;
+}
+
+[shader("miss")]
+void main(inout Payload payload : SV_RayPayload)
+{
+    // Camera rays that leave the scene cross the fog up to their end
+    uint seed = pcgHash(DispatchRaysIndex().y * DispatchRaysDimensions().x + DispatchRaysIndex().x);
+    float transmittance = fogTransmittance(WorldRayOrigin(), WorldRayDirection(), RayTMax(), seed);
+    payload.hitValue = lerp(g_fog[0].color.rgb, g_sun[0].skyColor.rgb, transmittance);
+}
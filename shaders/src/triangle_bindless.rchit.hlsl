@@ -0,0 +1,536 @@
+struct Payload
+{
+    float3 hitValue;
+    // Ray cone of the pixel: width at the ray origin and spread angle, for texture filtering
+    float coneWidth;
+    float coneSpreadAngle;
+};
+
+struct ShadowPayload
+{
+    float visibility;
+};
+
+struct Attribute
+{
+    float2 bary;
+};
+
+struct InstanceData
+{
+    float4 color;
+    float4 emission;
+    float4 surface; // x = roughness, y = metallic, z = anisotropy, w = opacity
+    float4 subsurface; // rgb = mean free path, zero for opaque surfaces, a = opacity threshold
+};
+
+struct EmissiveTriangle
+{
+    float4 v0;
+    float4 v1;
+    float4 v2;
+    float4 emission; // rgb = radiance, a = area
+};
+
+struct FogParams
+{
+    float4 color; // rgb = in-scattered radiance, a = uniform density
+    float4 gridMin; // w = grid density scale, 0 without a grid
+    float4 gridMax; // w = majorant of the scaled grid densities
+    uint4 gridSize;
+};
+
+struct SunParams
+{
+    float4 direction; // xyz = towards the sun, w = scale of SUN_IRRADIANCE
+    float4 skyColor; // rgb = background of rays that leave the scene
+};
+
+struct AliasEntry
+{
+    float threshold;
+    uint alias;
+    float pdf;
+    uint padding;
+};
+
+// RayMaskPushConstants, ViewportPushConstants and RayBiasPushConstants, the only push constants
+// visible to closest hit shaders
+struct HitConstants
+{
+    [[vk::offset(96)]] uint primaryMask;
+    uint secondaryMask;
+    uint2 viewportOffset;
+    uint2 viewportSize;
+    uint checkerboard;
+    float rayTMin;
+    float rayOffsetScale;
+    uint rayOffset;
+};
+
+[[vk::push_constant]] HitConstants g_hitConstants;
+
+// Overridden from the host with SHADOW_RAY_FLAGS (opaque | terminate on first hit | skip closest hit)
+[[vk::constant_id(0)]] const uint SHADOW_RAY_FLAGS = 13;
+// HitGroup::index of the hit group this shader was specialized for
+[[vk::constant_id(1)]] const uint SHADING_MODEL = 0;
+// 1 while the scene mesh has a skin, the normals then come from the skinning pass
+[[vk::constant_id(2)]] const uint SKINNED = 0;
+
+[[vk::binding(0, 0)]] RaytracingAccelerationStructure g_topLevel;
+[[vk::binding(2, 0)]] StructuredBuffer<InstanceData> g_instanceData;
+[[vk::binding(4, 0)]] StructuredBuffer<EmissiveTriangle> g_emissiveTriangles;
+[[vk::binding(5, 0)]] StructuredBuffer<AliasEntry> g_emissiveAliasTable;
+// Indexed by the Aov enum in src/utility/structures.rs
+[[vk::binding(8, 1)]] RWTexture2D<float4> g_aovs[6];
+// Mirrors FogParams in src/utility/structures.rs
+[[vk::binding(9, 0)]] StructuredBuffer<FogParams> g_fog;
+// x varies fastest, then y, then z
+[[vk::binding(10, 0)]] StructuredBuffer<float> g_fogDensity;
+// Mirrors SunParams in src/utility/structures.rs
+[[vk::binding(11, 0)]] StructuredBuffer<SunParams> g_sun;
+// DebugBufferHeader (count, capacity, pixel) followed by 32 byte DebugRecords
+[[vk::binding(12, 0)]] RWByteAddressBuffer g_debugBuffer;
+// Indices and object space normals of the skinned scene mesh, see shaders/src/skinning_deform.comp
+[[vk::binding(14, 0)]] StructuredBuffer<uint> g_skinIndices;
+[[vk::binding(15, 0)]] StructuredBuffer<float4> g_skinNormals;
+
+static const uint SHADOW_MISS_INDEX = 1;
+static const float PI = 3.14159265359;
+static const float INV_PI = 0.318309886;
+// InstanceData emission.a, mirrors INSTANCE_MATERIAL_* in src/utility/constants.rs
+static const float MATERIAL_CHECKER = 1.0;
+static const float MATERIAL_SHADOW_CATCHER = 2.0;
+static const float CHECKER_DARK_SHADE = 0.5;
+static const uint AOV_ALBEDO = 0;
+static const uint AOV_NORMAL = 1;
+static const uint AOV_DEPTH = 2;
+static const uint AOV_DIRECT_LIGHT = 3;
+static const uint AOV_INDIRECT_LIGHT = 4;
+static const uint AOV_SHADOW_CATCHER_ALPHA = 5;
+// Mirrors HitGroup in src/utility/structures.rs
+static const uint SHADING_MODEL_UNLIT = 1;
+static const uint SHADING_MODEL_NORMALS = 2;
+static const uint SHADING_MODEL_SUBSURFACE = 3;
+// Mirrors RayOffset in src/utility/structures.rs
+static const uint RAY_OFFSET_NONE = 0;
+static const uint RAY_OFFSET_RELATIVE = 2;
+// Same as the miss shader, bounds the ratio tracking through dense grids
+static const uint RATIO_TRACKING_MAX_STEPS = 256;
+// Same as shaders/include/brdf.glsl
+static const float3 DIELECTRIC_F0 = float3(0.04, 0.04, 0.04);
+static const float MIN_GGX_ALPHA = 0.002;
+static const float SUN_IRRADIANCE = 0.7 * PI;
+static const float AMBIENT_LIGHT = 0.3;
+
+// HLSL copy of the BRDF in shaders/include/brdf.glsl, keep the two in sync
+struct SurfaceParams
+{
+    float3 albedo;
+    float roughness;
+    float metallic;
+    float anisotropy;
+};
+
+float3 specularF0(SurfaceParams surface)
+{
+    return lerp(DIELECTRIC_F0, surface.albedo, surface.metallic);
+}
+
+float3 fresnelSchlick(float3 f0, float cosTheta)
+{
+    return f0 + (1.0 - f0) * pow(1.0 - saturate(cosTheta), 5.0);
+}
+
+float2 ggxDirectionalAlbedo(float NoV, float roughness)
+{
+    const float4 c0 = float4(-1.0, -0.0275, -0.572, 0.022);
+    const float4 c1 = float4(1.0, 0.0425, 1.04, -0.04);
+    float4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * NoV)) * r.x + r.y;
+    return float2(-1.04, 1.04) * a004 + r.zw;
+}
+
+float ggxDistribution(float ToH, float BoH, float NoH, float alphaT, float alphaB)
+{
+    float a2 = alphaT * alphaB;
+    float3 v = float3(alphaB * ToH, alphaT * BoH, a2 * NoH);
+    float w2 = a2 / dot(v, v);
+    return a2 * w2 * w2 * INV_PI;
+}
+
+float ggxVisibility(float ToV, float BoV, float NoV, float ToL, float BoL, float NoL, float alphaT, float alphaB)
+{
+    float lambdaV = NoL * length(float3(alphaT * ToV, alphaB * BoV, NoV));
+    float lambdaL = NoV * length(float3(alphaT * ToL, alphaB * BoL, NoL));
+    return 0.5 / (lambdaV + lambdaL);
+}
+
+float3 evaluateBrdf(SurfaceParams surface, float3 n, float3 t, float3 v, float3 l)
+{
+    n = dot(n, v) < 0.0 ? -n : n;
+    float NoL = dot(n, l);
+    if (NoL <= 0.0)
+    {
+        return float3(0.0, 0.0, 0.0);
+    }
+    float NoV = max(dot(n, v), 1e-4);
+    t = normalize(t - n * dot(n, t));
+    float3 b = cross(n, t);
+    float3 h = normalize(v + l);
+
+    float alpha = surface.roughness * surface.roughness;
+    float alphaT = max(alpha * (1.0 + surface.anisotropy), MIN_GGX_ALPHA);
+    float alphaB = max(alpha * (1.0 - surface.anisotropy), MIN_GGX_ALPHA);
+
+    float3 f0 = specularF0(surface);
+    float D = ggxDistribution(dot(t, h), dot(b, h), dot(n, h), alphaT, alphaB);
+    float V = ggxVisibility(dot(t, v), dot(b, v), NoV, dot(t, l), dot(b, l), NoL, alphaT, alphaB);
+    float3 F = fresnelSchlick(f0, dot(v, h));
+
+    float2 directionalAlbedo = ggxDirectionalAlbedo(NoV, surface.roughness);
+    float3 energyCompensation = 1.0 + f0 * (1.0 / max(directionalAlbedo.x + directionalAlbedo.y, 1e-3) - 1.0);
+
+    float3 specular = D * V * F * energyCompensation;
+    float3 diffuse = (1.0 - surface.metallic) * (1.0 - F) * surface.albedo * INV_PI;
+    return (diffuse + specular) * NoL;
+}
+
+float3 ambientReflectance(SurfaceParams surface, float3 n, float3 v)
+{
+    float2 directionalAlbedo = ggxDirectionalAlbedo(abs(dot(n, v)), surface.roughness);
+    float3 specular = specularF0(surface) * directionalAlbedo.x + directionalAlbedo.y;
+    return (1.0 - surface.metallic) * surface.albedo + specular;
+}
+
+uint pcgHash(uint value)
+{
+    uint state = value * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+float toUnitFloat(uint value)
+{
+    return float(value) * 2.3283064365386963e-10;
+}
+
+float gridDensity(FogParams fog, float3 position)
+{
+    float3 uvw = (position - fog.gridMin.xyz) / (fog.gridMax.xyz - fog.gridMin.xyz);
+    if (any(uvw < 0.0) || any(uvw >= 1.0))
+    {
+        return 0.0;
+    }
+    uint3 voxel = min(uint3(uvw * float3(fog.gridSize.xyz)), fog.gridSize.xyz - 1);
+    return g_fogDensity[(voxel.z * fog.gridSize.y + voxel.y) * fog.gridSize.x + voxel.x] * fog.gridMin.w;
+}
+
+// Transmittance along a normalized ray up to tMax: closed form for the uniform fog, ratio tracking
+// against the majorant through the density grid
+float fogTransmittance(float3 origin, float3 direction, float tMax, inout uint seed)
+{
+    FogParams fog = g_fog[0];
+    float transmittance = exp(-fog.color.a * tMax);
+    float majorant = fog.gridMax.w;
+    if (majorant <= 0.0)
+    {
+        return transmittance;
+    }
+
+    // Only the part of the ray inside the grid bounds is tracked
+    float3 inverseDirection = 1.0 / direction;
+    float3 t0 = (fog.gridMin.xyz - origin) * inverseDirection;
+    float3 t1 = (fog.gridMax.xyz - origin) * inverseDirection;
+    float3 tNear = min(t0, t1);
+    float3 tFar = max(t0, t1);
+    float t = max(max(tNear.x, tNear.y), max(tNear.z, 0.0));
+    float tExit = min(min(tFar.x, tFar.y), min(tFar.z, tMax));
+
+    for (uint step = 0; step < RATIO_TRACKING_MAX_STEPS; step++)
+    {
+        t -= log(1.0 - toUnitFloat(seed)) / majorant;
+        seed = pcgHash(seed);
+        if (t >= tExit)
+        {
+            break;
+        }
+        transmittance *= 1.0 - gridDensity(fog, origin + direction * t) / majorant;
+    }
+    return transmittance;
+}
+
+// Fades the radiance leaving the hit towards the fog color over the camera ray
+float3 applyFog(float3 radiance, inout uint seed)
+{
+    float transmittance = fogTransmittance(WorldRayOrigin(), WorldRayDirection(), RayTCurrent(), seed);
+    return lerp(g_fog[0].color.rgb, radiance, transmittance);
+}
+
+// Lifts the origin of a ray leaving the surface off it along the normal, to the side of
+// `direction`. The relative offset grows with the position's magnitude as its precision drops.
+float3 offsetRayOrigin(float3 position, float3 normal, float3 direction)
+{
+    if (g_hitConstants.rayOffset == RAY_OFFSET_NONE)
+    {
+        return position;
+    }
+    float offset = g_hitConstants.rayOffsetScale;
+    if (g_hitConstants.rayOffset == RAY_OFFSET_RELATIVE)
+    {
+        float3 magnitude = abs(position);
+        offset *= max(max(magnitude.x, magnitude.y), max(magnitude.z, 1.0));
+    }
+    return position + (dot(normal, direction) < 0.0 ? -normal : normal) * offset;
+}
+
+float traceShadowRay(float3 position, float3 normal, float3 direction, float tMax)
+{
+    RayDesc ray;
+    ray.Origin = offsetRayOrigin(position, normal, direction);
+    ray.Direction = direction;
+    ray.TMin = g_hitConstants.rayTMin;
+    ray.TMax = tMax;
+
+    // Stays 0 unless the shadow miss shader runs
+    ShadowPayload shadow;
+    shadow.visibility = 0.0;
+    TraceRay(g_topLevel, SHADOW_RAY_FLAGS, g_hitConstants.secondaryMask, 0, 0, SHADOW_MISS_INDEX, ray, shadow);
+    return shadow.visibility;
+}
+
+// Burley's normalized diffusion profile as a pdf over the distance from the exit point,
+// (exp(-r / d) + exp(-r / 3d)) / 4d
+float diffusionRadiusPdf(float radius, float d)
+{
+    return (exp(-radius / d) + exp(-radius / (3.0 * d))) / (4.0 * d);
+}
+
+// Diffuse sun light that entered the surface around the shading point and leaves it here. The
+// distance is sampled from the profile of one color channel picked at random and weighted with
+// the average pdf of all three. The geometry is planar, so the entry point stays on the tangent
+// plane instead of being found by a probe ray.
+float3 subsurfaceSunLight(SurfaceParams surface, float3 meanFreePath, float3 position, float3 n, float3 t, float3 v, inout uint seed)
+{
+    float u0 = toUnitFloat(seed);
+    seed = pcgHash(seed);
+    float u1 = toUnitFloat(seed);
+    seed = pcgHash(seed);
+    float u2 = toUnitFloat(seed);
+    seed = pcgHash(seed);
+    float u3 = toUnitFloat(seed);
+    seed = pcgHash(seed);
+
+    // Profile shape from the mean free path, Christensen and Burley's fit for diffuse surface
+    // transmission
+    float3 albedo = surface.albedo;
+    float3 scale = 1.85 - albedo + 7.0 * pow(abs(albedo - 0.8), 3.0);
+    float3 d = max(meanFreePath / scale, 1e-4);
+
+    float channelD = d[min(uint(u0 * 3.0), 2u)];
+    // A quarter of the profile falls off with d, the rest with 3d
+    float radius = (u1 < 0.25 ? -channelD : -3.0 * channelD) * log(1.0 - u2);
+    float3 pdfs = float3(diffusionRadiusPdf(radius, d.r), diffusionRadiusPdf(radius, d.g), diffusionRadiusPdf(radius, d.b));
+    float averagePdf = (pdfs.r + pdfs.g + pdfs.b) / 3.0;
+
+    n = dot(n, v) < 0.0 ? -n : n;
+    t = normalize(t - n * dot(n, t));
+    float3 b = cross(n, t);
+    float angle = 2.0 * PI * u3;
+    float3 entry = position + (t * cos(angle) + b * sin(angle)) * radius;
+
+    SunParams sun = g_sun[0];
+    float3 l = sun.direction.xyz;
+    float irradiance = SUN_IRRADIANCE * sun.direction.w * max(dot(n, l), 0.0) * traceShadowRay(entry, n, l, 1000.0);
+    // What the specular reflection at the exit point does not take
+    float3 transmitted = 1.0 - fresnelSchlick(DIELECTRIC_F0, dot(n, v));
+    return albedo * (pdfs / max(averagePdf, 1e-6)) * irradiance * transmitted * INV_PI;
+}
+
+// Width of the ray cone's footprint on the surface, stretched where the ray grazes it
+float coneFootprint(Payload payload, float3 normal)
+{
+    float width = payload.coneWidth + payload.coneSpreadAngle * RayTCurrent();
+    return width / max(abs(dot(normal, WorldRayDirection())), 0.05);
+}
+
+// Fraction of the box of `filterWidth` around `p` covered by the dark squares of a unit checker
+// (Inigo Quilez' box filtered checkers), the same squares as floor(p) with an odd sum
+float filteredChecker(float2 p, float filterWidth)
+{
+    float2 w = max(filterWidth, 1e-4).xx;
+    float2 i = 2.0 * (abs(frac((p - 0.5 * w) * 0.5) - 0.5) - abs(frac((p + 0.5 * w) * 0.5) - 0.5)) / w;
+    return 0.5 - 0.5 * i.x * i.y;
+}
+
+// World space normal at the hit. The unskinned geometry lies in the object space z = 0 plane, a
+// skinned mesh interpolates the normals it was deformed with.
+float3 hitNormal(float2 bary)
+{
+    float3 n = float3(0.0, 0.0, 1.0);
+    if (SKINNED != 0)
+    {
+        uint corner = PrimitiveIndex() * 3;
+        float3 n0 = g_skinNormals[g_skinIndices[corner]].xyz;
+        float3 n1 = g_skinNormals[g_skinIndices[corner + 1]].xyz;
+        float3 n2 = g_skinNormals[g_skinIndices[corner + 2]].xyz;
+        n = n0 * (1.0 - bary.x - bary.y) + n1 * bary.x + n2 * bary.y;
+    }
+    return normalize(ObjectToWorld3x4()._m00_m10_m20 * n.x + ObjectToWorld3x4()._m01_m11_m21 * n.y + ObjectToWorld3x4()._m02_m12_m22 * n.z);
+}
+// Render target pixel of the launch, same mapping as the ray generation shader
+uint2 launchPixel()
+{
+    uint2 pixel = DispatchRaysIndex().xy + g_hitConstants.viewportOffset;
+    if (g_hitConstants.checkerboard != 0)
+    {
+        pixel.x = g_hitConstants.viewportOffset.x + DispatchRaysIndex().x * 2
+            + (g_hitConstants.viewportOffset.x + pixel.y + g_hitConstants.checkerboard - 1) % 2;
+    }
+    return pixel;
+}
+
+// Appends `value` to the debug buffer when the ray belongs to the pixel picked with
+// --debug_pixel, the host logs it with `tag` after the frame. printf works too with
+// --debug_printf, but prints for every pixel unless guarded the same way.
+void debugValue(uint tag, float4 value)
+{
+    uint2 pixel = launchPixel();
+    if (any(pixel != g_debugBuffer.Load2(8)))
+    {
+        return;
+    }
+    uint index;
+    g_debugBuffer.InterlockedAdd(0, 1, index);
+    if (index >= g_debugBuffer.Load(4))
+    {
+        return;
+    }
+    uint offset = 16 + index * 32;
+    g_debugBuffer.Store4(offset, uint4(pixel, tag, 0));
+    g_debugBuffer.Store4(offset + 16, asuint(value));
+}
+
+// Shadow rays skip this shader, so every hit here is a primary one
+void writeAovs(float3 albedo, float3 normal, float3 direct, float3 indirect, float shadowCatcherAlpha)
+{
+    uint2 pixel = launchPixel();
+    g_aovs[AOV_ALBEDO][pixel] = float4(albedo, 1.0);
+    g_aovs[AOV_NORMAL][pixel] = float4(dot(normal, WorldRayDirection()) > 0.0 ? -normal : normal, 1.0);
+    g_aovs[AOV_DEPTH][pixel] = RayTCurrent().xxxx;
+    g_aovs[AOV_DIRECT_LIGHT][pixel] = float4(direct, 1.0);
+    g_aovs[AOV_INDIRECT_LIGHT][pixel] = float4(indirect, 1.0);
+    g_aovs[AOV_SHADOW_CATCHER_ALPHA][pixel] = shadowCatcherAlpha.xxxx;
+}
+
+[shader("closesthit")]
+void main(inout Payload payload : SV_RayPayload, in Attribute attribs : SV_IntersectionAttributes)
+{
+    // InstanceID() maps to the custom index written into the TLAS instance
+    float3 surfaceNormal = hitNormal(attribs.bary);
+    InstanceData instance = g_instanceData[InstanceID()];
+
+    // Preview shading models trace no further rays
+    if (SHADING_MODEL == SHADING_MODEL_UNLIT || SHADING_MODEL == SHADING_MODEL_NORMALS)
+    {
+        float3 faceNormal = dot(surfaceNormal, WorldRayDirection()) > 0.0 ? -surfaceNormal : surfaceNormal;
+        payload.hitValue = SHADING_MODEL == SHADING_MODEL_UNLIT ? instance.color.rgb : faceNormal * 0.5 + 0.5;
+        writeAovs(instance.color.rgb, surfaceNormal, payload.hitValue, float3(0.0, 0.0, 0.0), 1.0);
+        return;
+    }
+
+    float3 position = WorldRayOrigin() + WorldRayDirection() * RayTCurrent();
+    SunParams sun = g_sun[0];
+    float sunVisibility = traceShadowRay(position, surfaceNormal, sun.direction.xyz, 1000.0);
+    if (instance.emission.a == MATERIAL_SHADOW_CATCHER)
+    {
+        writeAovs(float3(0.0, 0.0, 0.0), surfaceNormal, float3(0.0, 0.0, 0.0), float3(0.0, 0.0, 0.0), 1.0 - sunVisibility);
+        uint fogSeed = pcgHash(DispatchRaysIndex().y * DispatchRaysDimensions().x + DispatchRaysIndex().x);
+        payload.hitValue = applyFog(sun.skyColor.rgb * lerp(0.3, 1.0, sunVisibility), fogSeed);
+        return;
+    }
+
+    float3 albedo = instance.color.rgb;
+    if (instance.emission.a == MATERIAL_CHECKER)
+    {
+        // color.a is the square size. Filtered over the ray cone footprint, point sampling
+        // aliases into moire in the distance.
+        float squareSize = instance.color.a;
+        float dark = filteredChecker(position.xz / squareSize, coneFootprint(payload, surfaceNormal) / squareSize);
+        albedo *= lerp(1.0, CHECKER_DARK_SHADE, dark);
+    }
+    SurfaceParams surface;
+    surface.albedo = albedo;
+    surface.roughness = instance.surface.x;
+    surface.metallic = instance.surface.y;
+    surface.anisotropy = instance.surface.z;
+    // The geometry lies in the object space z = 0 plane, its x axis is the tangent
+    float3 surfaceTangent = ObjectToWorld3x4()._m00_m10_m20;
+    float3 toViewer = -WorldRayDirection();
+
+    float3 indirect = ambientReflectance(surface, surfaceNormal, toViewer) * AMBIENT_LIGHT;
+    float3 color = indirect;
+
+    uint seed = pcgHash(DispatchRaysIndex().y * DispatchRaysDimensions().x + DispatchRaysIndex().x);
+    if (SHADING_MODEL == SHADING_MODEL_SUBSURFACE && any(instance.subsurface.rgb > 0.0))
+    {
+        // A dielectric coat without diffuse reflection, the diffuse light comes from below
+        SurfaceParams coat = surface;
+        coat.albedo = float3(0.0, 0.0, 0.0);
+        coat.metallic = 0.0;
+        color += evaluateBrdf(coat, surfaceNormal, surfaceTangent, toViewer, sun.direction.xyz) * SUN_IRRADIANCE * sun.direction.w * sunVisibility;
+        color += subsurfaceSunLight(surface, instance.subsurface.rgb, position, surfaceNormal, surfaceTangent, toViewer, seed);
+    }
+    else
+    {
+        color += evaluateBrdf(surface, surfaceNormal, surfaceTangent, toViewer, sun.direction.xyz) * SUN_IRRADIANCE * sun.direction.w * sunVisibility;
+    }
+
+    // Pick one emissive triangle proportionally to its power through the alias table
+    float u0 = toUnitFloat(seed);
+    seed = pcgHash(seed);
+    float u1 = toUnitFloat(seed);
+    seed = pcgHash(seed);
+    float u2 = toUnitFloat(seed);
+
+    uint count;
+    uint stride;
+    g_emissiveAliasTable.GetDimensions(count, stride);
+    float scaled = u0 * float(count);
+    uint slot = min(uint(scaled), count - 1);
+    AliasEntry entry = g_emissiveAliasTable[slot];
+    uint lightIndex = frac(scaled) >= entry.threshold ? entry.alias : slot;
+
+    EmissiveTriangle light = g_emissiveTriangles[lightIndex];
+    // Converted from the triangle selection probability to a pdf over the light's area
+    float pdf = g_emissiveAliasTable[lightIndex].pdf / light.emission.a;
+
+    // Uniform point on the triangle
+    float3 e1 = light.v1.xyz - light.v0.xyz;
+    float3 e2 = light.v2.xyz - light.v0.xyz;
+    float su = sqrt(u1);
+    float3 lightPoint = light.v0.xyz + e1 * (1.0 - su) + e2 * (u2 * su);
+
+    float3 toLight = lightPoint - position;
+    float distanceSquared = dot(toLight, toLight);
+    float lightDistance = sqrt(distanceSquared);
+    float3 lightDirection = toLight * (1.0 / lightDistance);
+
+    // Both the triangles and the emitters are two-sided, the BRDF applies the cosine of the surface
+    float cosLight = abs(dot(normalize(cross(e1, e2)), lightDirection));
+
+    float lightVisibility = traceShadowRay(position, surfaceNormal, lightDirection, lightDistance * 0.999);
+    float geometry = cosLight * lightVisibility / (distanceSquared * pdf);
+    float3 emitted = evaluateBrdf(surface, surfaceNormal, surfaceTangent, toViewer, lightDirection) * light.emission.rgb * geometry;
+
+    bool valid = pdf > 0.0 && distanceSquared > 1e-6;
+    color += valid ? emitted : float3(0.0, 0.0, 0.0);
+    // Tag 0: the emitter sample, tag 1: the surface and the sun's visibility
+    debugValue(0, float4(emitted, pdf));
+    debugValue(1, float4(albedo, sunVisibility));
+
+    // The AOVs keep the light leaving the surface, without the fog in front of it
+    float3 radiance = color + instance.emission.rgb;
+    payload.hitValue = applyFog(radiance, seed);
+    writeAovs(albedo, surfaceNormal, radiance - indirect, indirect, 1.0);
+}
@@ -0,0 +1,101 @@
+struct Payload
+{
+    float3 hitValue;
+};
+
+struct ShadowPayload
+{
+    float visibility;
+};
+
+struct Attribute
+{
+    float2 bary;
+};
+
+struct CameraConstants
+{
+    float4 origin; // w = 1 for orthographic projections
+    float4 right;
+    float4 up;
+    float4 forward;
+    float4 window; // left, right, bottom, top
+    // 1 / (sampleIndex + 1), used to average the time samples and samples per pixel
+    float accumulationWeight;
+    uint sampleIndex;
+};
+
+[[vk::push_constant]] CameraConstants g_camera;
+
+// Overridden from the host with SHADOW_RAY_FLAGS (opaque | terminate on first hit | skip closest hit)
+[[vk::constant_id(0)]] const uint SHADOW_RAY_FLAGS = 13;
+// Overridden from the host with PRIMARY_RAY_FLAGS, which drops opaque for stochastic transparency
+[[vk::constant_id(3)]] const uint PRIMARY_RAY_FLAGS = 1;
+
+[[vk::binding(0, 0)]] RaytracingAccelerationStructure g_topLevel;
+// In whichever HdrFormat was chosen, loaded without a format
+[[vk::binding(1, 1)]] RWTexture2D<float4> g_output;
+
+static const float3 LIGHT_DIRECTION = float3(0.5, 1.0, -1.0);
+static const uint SHADOW_MISS_INDEX = 1;
+
+[shader("raygeneration")]
+void rgen_main()
+{
+    uint2 launchIndex = DispatchRaysIndex().xy;
+    // R2 sequence sub-pixel offset, the first sample goes through the pixel center
+    float2 jitter = frac(0.5 + float(g_camera.sampleIndex) * float2(0.754877666, 0.569840291));
+    float2 uv = (float2(launchIndex) + jitter) / float2(DispatchRaysDimensions().xy);
+
+    // Image rows go down while the window's up axis goes up
+    float u = lerp(g_camera.window.x, g_camera.window.y, uv.x);
+    float v = lerp(g_camera.window.w, g_camera.window.z, uv.y);
+    float3 offset = g_camera.right.xyz * u + g_camera.up.xyz * v;
+
+    bool orthographic = g_camera.origin.w > 0.5;
+
+    RayDesc ray;
+    ray.Origin = orthographic ? g_camera.origin.xyz + offset : g_camera.origin.xyz;
+    ray.Direction = orthographic ? g_camera.forward.xyz : normalize(g_camera.forward.xyz + offset);
+    ray.TMin = 0.001;
+    ray.TMax = 1000.0;
+
+    Payload payload;
+    payload.hitValue = float3(0.0, 0.0, 0.0);
+    TraceRay(g_topLevel, PRIMARY_RAY_FLAGS, 0xFF, 0, 0, 0, ray, payload);
+
+    float4 color = float4(payload.hitValue, 1.0);
+    float4 accumulated = lerp(g_output[launchIndex], color, g_camera.accumulationWeight);
+    g_output[launchIndex] = g_camera.accumulationWeight >= 1.0 ? color : accumulated;
+}
+
+[shader("miss")]
+void rmiss_main(inout Payload payload : SV_RayPayload)
+{
+    payload.hitValue = float3(0.0, 0.1, 0.3);
+}
+
+[shader("miss")]
+void rmiss_shadow_main(inout ShadowPayload payload : SV_RayPayload)
+{
+    payload.visibility = 1.0;
+}
+
+[shader("closesthit")]
+void rchit_main(inout Payload payload : SV_RayPayload, in Attribute attribs : SV_IntersectionAttributes)
+{
+    float3 barycentrics = float3(1.0 - attribs.bary.x - attribs.bary.y, attribs.bary.x, attribs.bary.y);
+
+    RayDesc ray;
+    ray.Origin = WorldRayOrigin() + WorldRayDirection() * RayTCurrent();
+    ray.Direction = normalize(LIGHT_DIRECTION);
+    ray.TMin = 0.001;
+    ray.TMax = 1000.0;
+
+    // Stays 0 unless the shadow miss shader runs
+    ShadowPayload shadow;
+    shadow.visibility = 0.0;
+    TraceRay(g_topLevel, SHADOW_RAY_FLAGS, 0xFF, 0, 0, SHADOW_MISS_INDEX, ray, shadow);
+
+    payload.hitValue = barycentrics * lerp(0.3, 1.0, shadow.visibility);
+}
@@ -0,0 +1,10 @@
+struct ShadowPayload
+{
+    float visibility;
+};
+
+[shader("miss")]
+void main(inout ShadowPayload payload : SV_RayPayload)
+{
+    payload.visibility = 1.0;
+}
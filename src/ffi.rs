@@ -0,0 +1,234 @@
+// C ABI for scripting batch renders from other languages, declared in bindings/ash_rt.h. A
+// handle owns the event loop, the renderer's window and the RtContext tracing into it. Handles
+// are created and used on the main thread, one at a time, as winit wants of its event loop.
+
+use std::ffi::{c_char, c_int, CStr};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Arc;
+
+use cgmath::{Deg, Point3};
+
+use crate::renderer::{Renderer, RtContext};
+use crate::utility::{
+    camera::Projection, constants::TEXTURE_QUALITY, logging, sampler_cache::TextureQuality,
+    structures::RenderSettings, user_settings::UserSettings, window::ProgramProc,
+};
+
+pub const ASH_RT_OK: c_int = 0;
+pub const ASH_RT_INVALID_ARGUMENT: c_int = -1;
+pub const ASH_RT_RENDER_FAILED: c_int = -2;
+
+struct CameraView {
+    position: Point3<f32>,
+    target: Point3<f32>,
+    // None keeps the scene's field of view
+    fov_y: Option<Deg<f32>>,
+}
+
+pub struct AshRtRenderer {
+    // Built on the first render and again after the scene changes. Declared first so it is
+    // released before the window's event loop.
+    context: Option<RtContext>,
+    program_proc: ProgramProc,
+    // From the defaults rather than the settings saved by the user's last window session
+    user_settings: UserSettings,
+    settings: RenderSettings,
+    texture_quality: TextureQuality,
+    camera: Option<CameraView>,
+}
+
+impl AshRtRenderer {
+    fn context(&mut self) -> &mut RtContext {
+        if self.context.is_none() {
+            let renderer = Renderer::builder()
+                .user_settings(self.user_settings.clone())
+                .build(&self.program_proc.event_loop);
+            self.context = Some(RtContext::builder(Arc::new(renderer)).build());
+        }
+        self.context.as_mut().unwrap()
+    }
+
+    fn render_to_file(&mut self, path: &Path, columns: u32, rows: u32) {
+        let (settings, texture_quality) = (self.settings, self.texture_quality);
+        let camera = self
+            .camera
+            .as_ref()
+            .map(|camera| (camera.position, camera.target, camera.fov_y));
+        let context = self.context();
+        // Applied to the built context so only what changed since the last render is rebuilt
+        context.apply_settings(settings);
+        context.set_texture_quality(texture_quality);
+        if let Some((position, target, fov_y)) = camera {
+            context.camera.position = position;
+            context.camera.target = target;
+            if let Some(fov_y) = fov_y {
+                context.camera.projection = Projection::Perspective { fov_y };
+            }
+        }
+        context.render_tiled(columns, rows, path);
+    }
+}
+
+// None for null pointers and strings that are not UTF-8
+unsafe fn to_str<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        return None;
+    }
+    CStr::from_ptr(string).to_str().ok()
+}
+
+unsafe fn renderer_mut<'a>(renderer: *mut AshRtRenderer) -> Option<&'a mut AshRtRenderer> {
+    renderer.as_mut()
+}
+
+/// Creates a renderer with the default settings and scene. The device and the scene are set up
+/// by the first render, logging goes to stdout as in the executable. Returns null when the event
+/// loop cannot be created.
+#[no_mangle]
+pub extern "C" fn ash_rt_create() -> *mut AshRtRenderer {
+    // Keeps a subscriber the host installed, the warnings below are lost without one
+    logging::init_logging();
+    let program_proc = match panic::catch_unwind(ProgramProc::new) {
+        Ok(program_proc) => program_proc,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let user_settings = UserSettings::default();
+    Box::into_raw(Box::new(AshRtRenderer {
+        context: None,
+        program_proc,
+        settings: user_settings.render,
+        user_settings,
+        texture_quality: TEXTURE_QUALITY,
+        camera: None,
+    }))
+}
+
+/// Sets the OBJ model of the renders that follow, which are set up again for it.
+///
+/// # Safety
+///
+/// `renderer` comes from ash_rt_create and `path` is a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ash_rt_load_scene(
+    renderer: *mut AshRtRenderer,
+    path: *const c_char,
+) -> c_int {
+    match (renderer_mut(renderer), to_str(path)) {
+        (Some(renderer), Some(path)) => {
+            if renderer.user_settings.scene != path {
+                renderer.user_settings.scene = path.to_string();
+                renderer.context = None;
+            }
+            ASH_RT_OK
+        }
+        _ => ASH_RT_INVALID_ARGUMENT,
+    }
+}
+
+/// Looks from `position` at `target`, both three floats, with a vertical field of view in
+/// degrees. A field of view of 0 or less keeps the scene's one.
+///
+/// # Safety
+///
+/// `renderer` comes from ash_rt_create, `position` and `target` point to three floats each.
+#[no_mangle]
+pub unsafe extern "C" fn ash_rt_set_camera(
+    renderer: *mut AshRtRenderer,
+    position: *const f32,
+    target: *const f32,
+    fov_y_degrees: f32,
+) -> c_int {
+    let renderer = match renderer_mut(renderer) {
+        Some(renderer) if !position.is_null() && !target.is_null() => renderer,
+        _ => return ASH_RT_INVALID_ARGUMENT,
+    };
+    let position = std::slice::from_raw_parts(position, 3);
+    let target = std::slice::from_raw_parts(target, 3);
+    if position
+        .iter()
+        .chain(target)
+        .chain([&fov_y_degrees])
+        .any(|value| !value.is_finite())
+    {
+        return ASH_RT_INVALID_ARGUMENT;
+    }
+    renderer.camera = Some(CameraView {
+        position: Point3::new(position[0], position[1], position[2]),
+        target: Point3::new(target[0], target[1], target[2]),
+        fov_y: (fov_y_degrees > 0.0).then_some(Deg(fov_y_degrees)),
+    });
+    ASH_RT_OK
+}
+
+/// Sets a render setting as the executable's `--name=value` options take it, e.g. `spp` or
+/// `depth`, or a texture quality one such as `max_anisotropy`.
+///
+/// # Safety
+///
+/// `renderer` comes from ash_rt_create, `name` and `value` are NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn ash_rt_set_option(
+    renderer: *mut AshRtRenderer,
+    name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    let (renderer, name, value) = match (renderer_mut(renderer), to_str(name), to_str(value)) {
+        (Some(renderer), Some(name), Some(value)) => (renderer, name, value),
+        _ => return ASH_RT_INVALID_ARGUMENT,
+    };
+    let edit = format!("{}={}", name, value);
+    let result = match name {
+        "max_anisotropy" | "lod_bias" | "trilinear" => renderer.texture_quality.edit(&edit),
+        _ => renderer.settings.edit(&edit),
+    };
+    match result {
+        Ok(()) => ASH_RT_OK,
+        Err(message) => {
+            tracing::warn!("{}", message);
+            ASH_RT_INVALID_ARGUMENT
+        }
+    }
+}
+
+/// Renders in `columns` x `rows` tiles of the window size and saves the image, a .exr path saves
+/// the HDR image and the AOVs. Blocks until the image is saved.
+///
+/// # Safety
+///
+/// `renderer` comes from ash_rt_create and `path` is a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ash_rt_render_to_file(
+    renderer: *mut AshRtRenderer,
+    path: *const c_char,
+    columns: u32,
+    rows: u32,
+) -> c_int {
+    let (renderer, path) = match (renderer_mut(renderer), to_str(path)) {
+        (Some(renderer), Some(path)) => (renderer, path),
+        _ => return ASH_RT_INVALID_ARGUMENT,
+    };
+    // The renderer panics on device and file errors, which must not unwind into the caller
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        renderer.render_to_file(Path::new(path), columns.max(1), rows.max(1))
+    }));
+    match result {
+        Ok(()) => ASH_RT_OK,
+        Err(_) => {
+            tracing::error!("Failed rendering {}", path);
+            ASH_RT_RENDER_FAILED
+        }
+    }
+}
+
+/// Frees the renderer, null is ignored.
+///
+/// # Safety
+///
+/// `renderer` comes from ash_rt_create and is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ash_rt_destroy(renderer: *mut AshRtRenderer) {
+    if !renderer.is_null() {
+        drop(Box::from_raw(renderer));
+    }
+}
@@ -1 +1,9 @@
+// The C ABI opens its renderers' windows itself
+#[cfg(feature = "winit")]
+pub mod ffi;
+// The renderer and the ray tracer on top of it, which the executable is built on. Without the
+// winit feature it renders into the raw handles of the host's window.
+pub mod renderer;
 pub mod utility;
+
+pub use renderer::{Renderer, RendererBuilder, RtContext, RtContextBuilder};
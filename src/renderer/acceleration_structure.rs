@@ -0,0 +1,483 @@
+use std::sync::Arc;
+
+use super::{base::Renderer, resources::BufferResource};
+use crate::utility;
+
+use ash::{
+    extensions::{khr, nv},
+    vk,
+};
+
+// Acceleration structure bound to its own memory, destroyed on drop
+pub(super) struct AccelerationStructure {
+    pub(super) handle: vk::AccelerationStructureNV,
+    memory: vk::DeviceMemory,
+    pub(super) size: vk::DeviceSize,
+    // Opaque reference TLAS instances use to point at a BLAS
+    pub(super) reference: u64,
+    base: Arc<Renderer>,
+    ray_tracing: Arc<nv::RayTracing>,
+}
+
+impl AccelerationStructure {
+    pub(super) fn new(
+        base: Arc<Renderer>,
+        ray_tracing: Arc<nv::RayTracing>,
+        info: vk::AccelerationStructureInfoNV,
+    ) -> Self {
+        unsafe {
+            let accel_info = vk::AccelerationStructureCreateInfoNV::builder()
+                .compacted_size(0)
+                .info(info)
+                .build();
+
+            let handle = ray_tracing
+                .create_acceleration_structure(&accel_info, None)
+                .expect("Failed to create acceleration structure.");
+
+            let mut acceleration_structure = AccelerationStructure {
+                handle,
+                memory: vk::DeviceMemory::null(),
+                size: 0,
+                reference: 0,
+                base,
+                ray_tracing,
+            };
+
+            let requirements = acceleration_structure
+                .memory_requirements(vk::AccelerationStructureMemoryRequirementsTypeNV::OBJECT);
+            acceleration_structure.size = requirements.size;
+            acceleration_structure.memory = acceleration_structure
+                .base
+                .device
+                .allocate_memory(
+                    &vk::MemoryAllocateInfo::builder()
+                        .allocation_size(requirements.size)
+                        .memory_type_index(
+                            utility::general::find_memorytype_index(
+                                &requirements,
+                                &acceleration_structure.base.memory_properties,
+                                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                            )
+                            .expect("Failed to find suitable AS memory type."),
+                        )
+                        .build(),
+                    None,
+                )
+                .expect("Failed to allocate AS memory.");
+
+            acceleration_structure
+                .ray_tracing
+                .bind_acceleration_structure_memory(&[
+                    vk::BindAccelerationStructureMemoryInfoNV::builder()
+                        .acceleration_structure(handle)
+                        .memory(acceleration_structure.memory)
+                        .build(),
+                ])
+                .expect("Failed to bind AS memory.");
+
+            acceleration_structure.reference = acceleration_structure
+                .ray_tracing
+                .get_acceleration_structure_handle(handle)
+                .expect("Failed to get AS handle.");
+
+            acceleration_structure
+        }
+    }
+
+    pub(super) fn memory_requirements(
+        &self,
+        ty: vk::AccelerationStructureMemoryRequirementsTypeNV,
+    ) -> vk::MemoryRequirements {
+        unsafe {
+            self.ray_tracing
+                .get_acceleration_structure_memory_requirements(
+                    &vk::AccelerationStructureMemoryRequirementsInfoNV::builder()
+                        .acceleration_structure(self.handle)
+                        .ty(ty)
+                        .build(),
+                )
+                .memory_requirements
+        }
+    }
+
+    pub(super) fn set_name(&self, name: &str) {
+        self.base.set_debug_name(self.handle, name);
+        self.base
+            .set_debug_name(self.memory, &format!("{} memory", name));
+    }
+}
+
+impl Drop for AccelerationStructure {
+    fn drop(&mut self) {
+        unsafe {
+            self.ray_tracing
+                .destroy_acceleration_structure(self.handle, None);
+            self.base.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+struct PendingAsBuild {
+    ty: vk::AccelerationStructureTypeNV,
+    flags: vk::BuildAccelerationStructureFlagsNV,
+    geometries: Vec<vk::GeometryNV>,
+    instance_count: u32,
+    instance_data: vk::Buffer,
+    instance_offset: vk::DeviceSize,
+    destination: vk::AccelerationStructureNV,
+    scratch: vk::MemoryRequirements,
+}
+
+impl PendingAsBuild {
+    // Has to match the info the structure was created with
+    fn info(&self) -> vk::AccelerationStructureInfoNV {
+        vk::AccelerationStructureInfoNV::builder()
+            .ty(self.ty)
+            .flags(self.flags)
+            .geometries(&self.geometries)
+            .instance_count(self.instance_count)
+            .build()
+    }
+}
+
+// Creates acceleration structures right away, so BLAS references can go into instance buffers,
+// and records all their builds into one command buffer with a shared scratch buffer
+pub(super) struct AsBuilder {
+    base: Arc<Renderer>,
+    ray_tracing: Arc<nv::RayTracing>,
+    builds: Vec<PendingAsBuild>,
+}
+
+impl AsBuilder {
+    pub(super) fn new(base: Arc<Renderer>, ray_tracing: Arc<nv::RayTracing>) -> Self {
+        AsBuilder {
+            base,
+            ray_tracing,
+            builds: Vec::new(),
+        }
+    }
+
+    pub(super) fn add_bottom_level(
+        &mut self,
+        geometries: &[vk::GeometryNV],
+        flags: vk::BuildAccelerationStructureFlagsNV,
+    ) -> AccelerationStructure {
+        self.add(PendingAsBuild {
+            ty: vk::AccelerationStructureTypeNV::BOTTOM_LEVEL,
+            flags,
+            geometries: geometries.to_vec(),
+            instance_count: 0,
+            instance_data: vk::Buffer::null(),
+            instance_offset: 0,
+            destination: vk::AccelerationStructureNV::null(),
+            scratch: vk::MemoryRequirements::default(),
+        })
+    }
+
+    // The instance buffer has to hold `instance_count` GeometryInstances at `instance_offset`
+    // when build() is called
+    pub(super) fn add_top_level(
+        &mut self,
+        instance_count: u32,
+        flags: vk::BuildAccelerationStructureFlagsNV,
+        instance_data: vk::Buffer,
+        instance_offset: vk::DeviceSize,
+    ) -> AccelerationStructure {
+        self.add(PendingAsBuild {
+            ty: vk::AccelerationStructureTypeNV::TOP_LEVEL,
+            flags,
+            geometries: Vec::new(),
+            instance_count,
+            instance_data,
+            instance_offset,
+            destination: vk::AccelerationStructureNV::null(),
+            scratch: vk::MemoryRequirements::default(),
+        })
+    }
+
+    fn add(&mut self, mut build: PendingAsBuild) -> AccelerationStructure {
+        let acceleration_structure =
+            AccelerationStructure::new(self.base.clone(), self.ray_tracing.clone(), build.info());
+        build.destination = acceleration_structure.handle;
+        build.scratch = acceleration_structure
+            .memory_requirements(vk::AccelerationStructureMemoryRequirementsTypeNV::BUILD_SCRATCH);
+        self.builds.push(build);
+        acceleration_structure
+    }
+
+    // Builds everything that was added and waits for it, returns the scratch buffer size used.
+    // All BLAS builds run back to back in their own scratch ranges, then the TLAS builds reuse
+    // the scratch buffer from the start once the BLASes are done.
+    pub(super) fn build(self) -> vk::DeviceSize {
+        let (bottom_builds, top_builds): (Vec<&PendingAsBuild>, Vec<&PendingAsBuild>) = self
+            .builds
+            .iter()
+            .partition(|build| build.ty == vk::AccelerationStructureTypeNV::BOTTOM_LEVEL);
+
+        let (bottom_offsets, bottom_scratch_size) = scratch_layout(&bottom_builds);
+        let (top_offsets, top_scratch_size) = scratch_layout(&top_builds);
+        let scratch_size = bottom_scratch_size.max(top_scratch_size);
+        if scratch_size == 0 {
+            return 0;
+        }
+
+        let scratch_buffer = BufferResource::new(
+            scratch_size,
+            vk::BufferUsageFlags::RAY_TRACING_NV,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            self.base.clone(),
+        );
+
+        // Builds share the scratch buffer, and the TLAS builds read the BLASes
+        let memory_barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_NV)
+            .src_access_mask(
+                vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_NV
+                    | vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_NV,
+            )
+            .dst_stage_mask(vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_NV)
+            .dst_access_mask(
+                vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_NV
+                    | vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_NV,
+            )
+            .build();
+
+        let _queue = self.base.lock_queue();
+        let command_buffer =
+            utility::general::begin_single_time_command(&self.base.device, self.base.command_pool);
+        for (builds, offsets) in [(bottom_builds, bottom_offsets), (top_builds, top_offsets)] {
+            unsafe {
+                for (build, offset) in builds.iter().zip(offsets) {
+                    self.ray_tracing.cmd_build_acceleration_structure(
+                        command_buffer,
+                        &build.info(),
+                        build.instance_data,
+                        build.instance_offset,
+                        false,
+                        build.destination,
+                        vk::AccelerationStructureNV::null(),
+                        scratch_buffer.buffer,
+                        offset,
+                    );
+                }
+            }
+            self.base
+                .synchronization
+                .pipeline_barrier(command_buffer, &[memory_barrier], &[]);
+        }
+        utility::general::end_single_time_command(
+            &self.base.device,
+            self.base.command_pool,
+            self.base.graphics_queue,
+            command_buffer,
+        );
+
+        scratch_size
+    }
+}
+
+// Offsets of the builds in one shared scratch buffer and the size it needs
+fn scratch_layout(builds: &[&PendingAsBuild]) -> (Vec<vk::DeviceSize>, vk::DeviceSize) {
+    let mut size: vk::DeviceSize = 0;
+    let offsets = builds
+        .iter()
+        .map(|build| {
+            let alignment = build.scratch.alignment.max(1);
+            let offset = size.div_ceil(alignment) * alignment;
+            size = offset + build.scratch.size;
+            offset
+        })
+        .collect();
+    (offsets, size)
+}
+
+// Spreads BLAS builds over frames, so a large scene does not stall a single frame with all of
+// them. Like with AsBuilder the structures are created up front for the instances to reference,
+// but each one is only usable once the frame that recorded its build completed. Until then the
+// TLAS keeps referencing whatever it did before, a placeholder or the BLAS being replaced.
+pub(super) struct BlasBuildQueue {
+    base: Arc<Renderer>,
+    ray_tracing: Arc<nv::RayTracing>,
+    // With their priorities, the highest ones are built first
+    queued: Vec<(PendingAsBuild, f32)>,
+    // Grown to the largest batch, shared by the builds of every frame
+    pub(super) scratch: Option<BufferResource>,
+}
+
+impl BlasBuildQueue {
+    pub(super) fn new(base: Arc<Renderer>, ray_tracing: Arc<nv::RayTracing>) -> Self {
+        BlasBuildQueue {
+            base,
+            ray_tracing,
+            queued: Vec::new(),
+            scratch: None,
+        }
+    }
+
+    // `priority` is usually how much of the screen the instances of the BLAS cover
+    pub(super) fn add(
+        &mut self,
+        geometries: &[vk::GeometryNV],
+        flags: vk::BuildAccelerationStructureFlagsNV,
+        priority: f32,
+    ) -> AccelerationStructure {
+        let mut build = PendingAsBuild {
+            ty: vk::AccelerationStructureTypeNV::BOTTOM_LEVEL,
+            flags,
+            geometries: geometries.to_vec(),
+            instance_count: 0,
+            instance_data: vk::Buffer::null(),
+            instance_offset: 0,
+            destination: vk::AccelerationStructureNV::null(),
+            scratch: vk::MemoryRequirements::default(),
+        };
+        let acceleration_structure =
+            AccelerationStructure::new(self.base.clone(), self.ray_tracing.clone(), build.info());
+        build.destination = acceleration_structure.handle;
+        build.scratch = acceleration_structure
+            .memory_requirements(vk::AccelerationStructureMemoryRequirementsTypeNV::BUILD_SCRATCH);
+        self.queued.push((build, priority));
+        acceleration_structure
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    // For a structure dropped before it was built
+    pub(super) fn remove(&mut self, handle: vk::AccelerationStructureNV) {
+        self.queued.retain(|(build, _)| build.destination != handle);
+    }
+
+    // Records the builds of up to `max_builds` BLASes of the highest priority and returns their
+    // handles. They are ready for TLAS builds and rays recorded after this into the same command
+    // buffer. The frame recorded before must have completed, its scratch range is reused.
+    pub(super) fn record(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        max_builds: u32,
+    ) -> Vec<vk::AccelerationStructureNV> {
+        self.queued
+            .sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        let batch_size = self.queued.len().min(max_builds.max(1) as usize);
+        let batch: Vec<PendingAsBuild> = self
+            .queued
+            .drain(..batch_size)
+            .map(|(build, _)| build)
+            .collect();
+
+        let builds: Vec<&PendingAsBuild> = batch.iter().collect();
+        let (offsets, scratch_size) = scratch_layout(&builds);
+        let fits = self
+            .scratch
+            .as_ref()
+            .is_some_and(|scratch| scratch.size >= scratch_size);
+        if !fits {
+            self.scratch = Some(BufferResource::new(
+                scratch_size,
+                vk::BufferUsageFlags::RAY_TRACING_NV,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                self.base.clone(),
+            ));
+        }
+        let scratch_buffer = self.scratch.as_ref().unwrap().buffer;
+
+        unsafe {
+            for (build, offset) in builds.iter().zip(offsets) {
+                self.ray_tracing.cmd_build_acceleration_structure(
+                    command_buffer,
+                    &build.info(),
+                    vk::Buffer::null(),
+                    0,
+                    false,
+                    build.destination,
+                    vk::AccelerationStructureNV::null(),
+                    scratch_buffer,
+                    offset,
+                );
+            }
+        }
+
+        // TLAS builds and rays read them, the next frame's builds reuse the scratch buffer
+        let built_barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_NV)
+            .src_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_NV)
+            .dst_stage_mask(
+                vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_NV
+                    | vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV,
+            )
+            .dst_access_mask(
+                vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_NV
+                    | vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_NV,
+            )
+            .build();
+        self.base
+            .synchronization
+            .pipeline_barrier(command_buffer, &[built_barrier], &[]);
+
+        batch.iter().map(|build| build.destination).collect()
+    }
+}
+
+// Acceleration structure of the ray query backend, placed in a buffer of its own
+pub(super) struct KhrAccelerationStructure {
+    pub(super) handle: vk::AccelerationStructureKHR,
+    // Destroyed after the handle, fields drop after Drop::drop
+    _buffer: BufferResource,
+    // What TLAS instances use to point at a BLAS
+    pub(super) address: vk::DeviceAddress,
+    acceleration_structure: Arc<khr::AccelerationStructure>,
+}
+
+impl KhrAccelerationStructure {
+    pub(super) fn new(
+        base: Arc<Renderer>,
+        acceleration_structure: Arc<khr::AccelerationStructure>,
+        ty: vk::AccelerationStructureTypeKHR,
+        size: vk::DeviceSize,
+    ) -> Self {
+        let buffer = BufferResource::new(
+            size,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            base,
+        );
+
+        unsafe {
+            let handle = acceleration_structure
+                .create_acceleration_structure(
+                    &vk::AccelerationStructureCreateInfoKHR::builder()
+                        .buffer(buffer.buffer)
+                        .size(size)
+                        .ty(ty)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create KHR acceleration structure.");
+
+            let address = acceleration_structure.get_acceleration_structure_device_address(
+                &vk::AccelerationStructureDeviceAddressInfoKHR::builder()
+                    .acceleration_structure(handle)
+                    .build(),
+            );
+
+            KhrAccelerationStructure {
+                handle,
+                _buffer: buffer,
+                address,
+                acceleration_structure,
+            }
+        }
+    }
+}
+
+impl Drop for KhrAccelerationStructure {
+    fn drop(&mut self) {
+        unsafe {
+            self.acceleration_structure
+                .destroy_acceleration_structure(self.handle, None);
+        }
+    }
+}
@@ -0,0 +1,1715 @@
+use std::{
+    path::{Path, PathBuf},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+#[cfg(feature = "winit")]
+use crate::utility::{
+    input::{Action, Input},
+    window::VulkanApp,
+};
+use crate::{
+    utility,
+    utility::{
+        bounds::Aabb,
+        camera::{Camera, DepthRange, Projection},
+        console::CommandRegistry,
+        constants::*,
+        debug::ValidationInfo,
+        feature_chain::{DeviceFeature, DeviceFeatures},
+        logging::TARGET_SWAPCHAIN,
+        low_latency::{LatencyMarker, LatencySubmissionPresentIdNV, LowLatency},
+        present_timing::{PresentStats, PresentTiming},
+        present_transfer::PresentTransfer,
+        sampler_cache::{SamplerCache, SamplerKey, TextureQuality},
+        shader_cache::ShaderModuleCache,
+        structures::*,
+        synchronization::Synchronization,
+        tools::load_model,
+        uniform_ring::UniformRing,
+        user_settings::UserSettings,
+    },
+};
+#[cfg(feature = "winit")]
+use cgmath::Zero;
+use cgmath::{Deg, EuclideanSpace, Matrix4, Point3, Vector3};
+use image::RgbaImage;
+use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
+
+use ash::{extensions::khr, vk};
+
+// What the surface is created for, kept to create it again when it is lost. A winit window is
+// shared as is, so the renderer is only Send and Sync where winit's window is.
+enum SurfaceSource {
+    #[cfg(feature = "winit")]
+    Window(winit::window::Window),
+    // A window of another toolkit, as large as the host last set with set_window_size
+    RawHandles {
+        handles: RawSurfaceHandles,
+        size: vk::Extent2D,
+    },
+}
+
+#[derive(Clone, Copy)]
+struct RawSurfaceHandles {
+    display: RawDisplayHandle,
+    window: RawWindowHandle,
+}
+
+// The renderer never dereferences the handles, it only passes them to vkCreate*SurfaceKHR, which
+// Vulkan allows on any thread. The window they point to outlives the renderer, as
+// from_raw_handles requires.
+unsafe impl Send for RawSurfaceHandles {}
+unsafe impl Sync for RawSurfaceHandles {}
+
+impl SurfaceSource {
+    fn extension_names(&self) -> Vec<*const i8> {
+        match self {
+            #[cfg(feature = "winit")]
+            SurfaceSource::Window(_) => utility::platforms::required_extension_names(),
+            SurfaceSource::RawHandles { handles, .. } => {
+                utility::platforms::surface_extension_names(handles.display)
+                    .expect("No surface extension for the display.")
+            }
+        }
+    }
+
+    fn create_surface(&self, entry: &ash::Entry, instance: &ash::Instance) -> SurfaceStuff {
+        match self {
+            #[cfg(feature = "winit")]
+            SurfaceSource::Window(window) => utility::general::create_surface(
+                entry,
+                instance,
+                window,
+                WINDOW_WIDTH,
+                WINDOW_HEIGHT,
+            ),
+            SurfaceSource::RawHandles { handles, size } => {
+                utility::general::create_surface_from_raw_handles(
+                    entry,
+                    instance,
+                    handles.display,
+                    handles.window,
+                    size.width,
+                    size.height,
+                )
+                .expect("Failed to create surface.")
+            }
+        }
+    }
+
+    // Inner size of the window
+    fn size(&self) -> vk::Extent2D {
+        match self {
+            #[cfg(feature = "winit")]
+            SurfaceSource::Window(window) => {
+                let size = window.inner_size();
+                vk::Extent2D {
+                    width: size.width,
+                    height: size.height,
+                }
+            }
+            SurfaceSource::RawHandles { size, .. } => *size,
+        }
+    }
+
+    #[cfg(feature = "winit")]
+    fn window(&self) -> Option<&winit::window::Window> {
+        match self {
+            SurfaceSource::Window(window) => Some(window),
+            SurfaceSource::RawHandles { .. } => None,
+        }
+    }
+}
+
+// The device, the window and its swapchain, which renders the model on its own and which the
+// RtContext traces with. Built with Renderer::builder().
+pub struct Renderer {
+    surface_source: SurfaceSource,
+
+    pub(super) entry: ash::Entry,
+    pub(super) instance: ash::Instance,
+    surface_loader: ash::extensions::khr::Surface,
+    pub(super) surface_format: vk::SurfaceFormatKHR,
+    surface: vk::SurfaceKHR,
+    // None when the instance lacks VK_EXT_debug_utils, the messenger is null without validation
+    pub(super) debug_utils_loader: Option<ash::extensions::ext::DebugUtils>,
+    debug_messenger: vk::DebugUtilsMessengerEXT,
+
+    pub(super) physical_device: vk::PhysicalDevice,
+    pub(super) memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pub(super) device: ash::Device,
+
+    pub(super) queue_family: QueueFamilyIndices,
+    pub(super) graphics_queue: vk::Queue,
+    present_queue: vk::Queue,
+    // Where the present family differs from the graphics family
+    present_transfer: Option<PresentTransfer>,
+    // synchronization2 barriers and submits when the device supports them
+    pub(super) synchronization: Synchronization,
+    // What the feature chain enabled, RenderMode::RayQuery needs DeviceFeature::RayQuery
+    pub(super) features: DeviceFeatures,
+    // Physical devices the device spans, more than one for `--split_frame` on a device group
+    pub(super) device_count: u32,
+    // Shared by every pipeline the renderer and the passes create
+    pub(super) shader_modules: Mutex<ShaderModuleCache>,
+    // Texture samplers of the model and the texture atlas, all filtered with one TextureQuality
+    pub(super) sampler_cache: Mutex<SamplerCache>,
+    // Held while a thread records into command_pool and submits to graphics_queue, which Vulkan
+    // requires to be externally synchronized
+    queue_lock: Mutex<()>,
+
+    swapchain_loader: ash::extensions::khr::Swapchain,
+    swapchain: vk::SwapchainKHR,
+    swapchain_images: Vec<vk::Image>,
+    swapchain_format: vk::Format,
+    pub(super) swapchain_extent: vk::Extent2D,
+    // What the swapchain was created with, past the COLOR_ATTACHMENT and TRANSFER_SRC it always
+    // asks for, and what of that the surface granted
+    requested_swapchain_usage: vk::ImageUsageFlags,
+    swapchain_usage: vk::ImageUsageFlags,
+    swapchain_imageviews: Vec<vk::ImageView>,
+    swapchain_framebuffers: Vec<vk::Framebuffer>,
+    // Present times and latencies, when the device has display timing or present wait
+    present_timing: PresentTiming,
+    // Mailbox or immediate present and one frame in flight, set with set_low_latency
+    low_latency: bool,
+    // Paces the frames of the low latency mode where the device has VK_NV_low_latency2
+    nv_low_latency: Option<LowLatency>,
+
+    render_pass: vk::RenderPass,
+    ubo_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    graphics_pipeline: vk::Pipeline,
+
+    color_image: vk::Image,
+    color_image_view: vk::ImageView,
+    color_image_memory: vk::DeviceMemory,
+
+    depth_image: vk::Image,
+    depth_image_view: vk::ImageView,
+    depth_image_memory: vk::DeviceMemory,
+
+    msaa_samples: vk::SampleCountFlags,
+
+    mip_levels: u32,
+    texture_image: vk::Image,
+    texture_image_view: vk::ImageView,
+    texture_image_memory: vk::DeviceMemory,
+
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+
+    vertex_buffer: vk::Buffer,
+    vertex_buffer_memory: vk::DeviceMemory,
+    index_buffer: vk::Buffer,
+    index_buffer_memory: vk::DeviceMemory,
+
+    uniform_transform: UniformBufferObject,
+    uniform_ring: UniformRing,
+    // Model transform before the turntable rotation
+    model: Matrix4<f32>,
+    model_camera: Camera,
+    // World space bounds of the model before the turntable rotation
+    model_scene_bounds: Aabb,
+    // Turntable angle after the last two simulation steps, frames interpolate between them
+    previous_model_rotation: Deg<f32>,
+    model_rotation: Deg<f32>,
+
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+
+    pub(super) command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+
+    image_available_semaphores: Vec<vk::Semaphore>,
+    render_finished_semaphores: Vec<vk::Semaphore>,
+    in_flight_fences: Vec<vk::Fence>,
+    current_frame: usize,
+
+    is_framebuffer_resized: bool,
+    // Where the console asked the next frame to be saved
+    pending_screenshot: Option<PathBuf>,
+    // Set when the descriptor set changed under the recorded command buffers
+    are_command_buffers_outdated: AtomicBool,
+}
+
+impl Renderer {
+    pub fn builder() -> RendererBuilder {
+        RendererBuilder {
+            validation: VALIDATION.resolve(&[]),
+            user_settings: UserSettings::default(),
+            split_frame: false,
+            swapchain_usage: vk::ImageUsageFlags::empty(),
+        }
+    }
+
+    // Opens a window with the defaults of RendererBuilder, see RendererBuilder::build
+    #[cfg(feature = "winit")]
+    pub fn new(event_loop: &winit::event_loop::EventLoopWindowTarget<()>) -> Renderer {
+        Renderer::builder().build(event_loop)
+    }
+
+    // Renders into a window the host created, which the renderer keeps until it is dropped. Its
+    // saved geometry is not restored.
+    #[cfg(feature = "winit")]
+    pub fn from_window(window: winit::window::Window) -> Renderer {
+        Renderer::builder().build_with_window(window)
+    }
+
+    // Renders into a window of another toolkit (SDL2, Qt, an engine's own), which has to outlive
+    // the renderer. The host reports its size changes with set_window_size.
+    pub fn from_raw_handles(
+        display: RawDisplayHandle,
+        window: RawWindowHandle,
+        extent: vk::Extent2D,
+    ) -> Renderer {
+        Renderer::builder().build_with_raw_handles(display, window, extent)
+    }
+
+    fn from_surface_source(
+        surface_source: SurfaceSource,
+        validation: &ValidationInfo,
+        user_settings: &UserSettings,
+        split_frame: bool,
+        swapchain_usage: vk::ImageUsageFlags,
+    ) -> Renderer {
+        let entry = ash::Entry::linked();
+        let instance = utility::general::create_instance(
+            &entry,
+            WINDOW_TITLE,
+            validation.is_enable,
+            validation.debug_printf,
+            &validation.required_validation_layers.to_vec(),
+            surface_source.extension_names(),
+        );
+        let surface_stuff = surface_source.create_surface(&entry, &instance);
+        let (debug_utils_loader, debug_messenger) = utility::debug::setup_debug_utils(
+            validation.is_enable,
+            validation.debug_printf,
+            &entry,
+            &instance,
+        );
+
+        let physical_device =
+            utility::general::pick_physcial_device(&instance, &surface_stuff, &DEVICE_EXTENSIONS);
+        let msaa_samples =
+            utility::general::get_max_usable_sample_count(&instance, physical_device);
+        let physical_device_memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        let device_group = if split_frame {
+            utility::device_group::find_device_group(
+                &instance,
+                physical_device,
+                SPLIT_FRAME_MAX_DEVICES,
+            )
+        } else {
+            Vec::new()
+        };
+        if split_frame && device_group.is_empty() {
+            tracing::warn!("No device group with another GPU, rendering on one device");
+        }
+        let (device, queue_family, features) = utility::general::create_logical_device(
+            &instance,
+            physical_device,
+            &device_group,
+            validation,
+            &DEVICE_EXTENSIONS,
+            &surface_stuff,
+        );
+        let synchronization = Synchronization::new(
+            device.clone(),
+            features.contains(DeviceFeature::Synchronization2),
+        );
+        let surface_format =
+            utility::general::create_surface_format(physical_device, &surface_stuff);
+
+        let graphics_queue =
+            unsafe { device.get_device_queue(queue_family.graphics_family.unwrap(), 0) };
+        let present_queue =
+            unsafe { device.get_device_queue(queue_family.present_family.unwrap(), 0) };
+
+        let swapchain_stuff = utility::general::create_swapchain(
+            &instance,
+            &device,
+            physical_device,
+            surface_source.size(),
+            &surface_stuff,
+            swapchain_usage,
+            false,
+            features.contains(DeviceFeature::LowLatency),
+        );
+        let swapchain_imageviews = utility::general::create_image_views(
+            &device,
+            swapchain_stuff.swapchain_format,
+            &swapchain_stuff.swapchain_images,
+        );
+        let render_pass = utility::general::create_render_pass(
+            &instance,
+            &device,
+            physical_device,
+            swapchain_stuff.swapchain_format,
+            msaa_samples,
+        );
+        let ubo_layout = utility::general::create_descriptor_set_layout(&device);
+        let mut shader_modules = ShaderModuleCache::new(device.clone());
+        let (graphics_pipeline, pipeline_layout) = utility::general::create_graphics_pipeline(
+            &device,
+            &mut shader_modules,
+            render_pass,
+            swapchain_stuff.swapchain_extent,
+            ubo_layout,
+            msaa_samples,
+        );
+        let command_pool = utility::general::create_command_pool(&device, &queue_family);
+        let (color_image, color_image_view, color_image_memory) =
+            utility::general::create_color_resources(
+                &device,
+                swapchain_stuff.swapchain_format,
+                swapchain_stuff.swapchain_extent,
+                &physical_device_memory_properties,
+                msaa_samples,
+            );
+        let (depth_image, depth_image_view, depth_image_memory) =
+            utility::general::create_depth_resources(
+                &instance,
+                &device,
+                physical_device,
+                command_pool,
+                graphics_queue,
+                swapchain_stuff.swapchain_extent,
+                &physical_device_memory_properties,
+                msaa_samples,
+            );
+        let swapchain_framebuffers = utility::general::create_framebuffers(
+            &device,
+            render_pass,
+            &swapchain_imageviews,
+            depth_image_view,
+            color_image_view,
+            swapchain_stuff.swapchain_extent,
+        );
+        let (vertices, indices, model_bounds) = load_model(Path::new(&user_settings.scene));
+        let model = Matrix4::from_angle_z(Deg(90.0));
+        let model_scene_bounds = model_bounds.scene.transformed(&model);
+        let model_camera = framed_model_camera(&model_scene_bounds);
+        utility::general::check_mipmap_support(
+            &instance,
+            physical_device,
+            vk::Format::R8G8B8A8_SRGB,
+        );
+        let (texture_image, texture_image_memory, mip_levels) =
+            utility::general::create_texture_image(
+                &device,
+                command_pool,
+                graphics_queue,
+                &physical_device_memory_properties,
+                &Path::new(TEXTURE_PATH),
+            );
+        let texture_image_view =
+            utility::general::create_texture_image_view(&device, texture_image, mip_levels);
+        let mut sampler_cache = unsafe {
+            SamplerCache::new(
+                device.clone(),
+                &instance.get_physical_device_features(physical_device),
+                &instance
+                    .get_physical_device_properties(physical_device)
+                    .limits,
+                TEXTURE_QUALITY,
+            )
+        };
+        let texture_sampler = sampler_cache.get(model_texture_sampler_key(mip_levels));
+        let (vertex_buffer, vertex_buffer_memory) = utility::general::create_vertex_buffer(
+            &device,
+            &physical_device_memory_properties,
+            command_pool,
+            graphics_queue,
+            &vertices,
+        );
+        let (index_buffer, index_buffer_memory) = utility::general::create_index_buffer(
+            &device,
+            &physical_device_memory_properties,
+            command_pool,
+            graphics_queue,
+            &indices,
+        );
+        // One frame slice per swapchain image, as the image index picks the command buffer
+        let uniform_ring = UniformRing::new(
+            &instance,
+            &device,
+            physical_device,
+            &physical_device_memory_properties,
+            UNIFORM_RING_FRAME_SIZE,
+            swapchain_stuff.swapchain_images.len(),
+        );
+        let descriptor_pool = utility::general::create_descriptor_pool(&device);
+        let descriptor_set = utility::general::create_descriptor_set(
+            &device,
+            descriptor_pool,
+            ubo_layout,
+            uniform_ring.buffer(),
+            texture_image_view,
+            texture_sampler,
+        );
+        let uniform_offsets: Vec<u32> = (0..swapchain_framebuffers.len())
+            .map(|image| uniform_ring.frame_offset(image))
+            .collect();
+        let mut present_transfer = PresentTransfer::new(
+            &instance,
+            &device,
+            physical_device,
+            &queue_family,
+            MAX_FRAMES_IN_FLIGHT,
+        );
+        let command_buffers = utility::general::create_command_buffers(
+            &device,
+            command_pool,
+            graphics_pipeline,
+            &swapchain_framebuffers,
+            render_pass,
+            swapchain_stuff.swapchain_extent,
+            vertex_buffer,
+            index_buffer,
+            pipeline_layout,
+            descriptor_set,
+            &uniform_offsets,
+            indices.len() as u32,
+            |command_buffer, image| {
+                if let Some(present_transfer) = &mut present_transfer {
+                    present_transfer
+                        .record_release(command_buffer, swapchain_stuff.swapchain_images[image]);
+                }
+            },
+        );
+        if let Some(present_transfer) = &mut present_transfer {
+            present_transfer.record_acquires(&swapchain_stuff.swapchain_images);
+        }
+        let sync_objects = utility::general::create_sync_objects(&device, MAX_FRAMES_IN_FLIGHT);
+        let present_timing = PresentTiming::new(&instance, &device, features);
+        let nv_low_latency = features
+            .contains(DeviceFeature::LowLatency)
+            .then(|| LowLatency::new(&instance, &device));
+
+        let renderer = Renderer {
+            surface_source,
+
+            entry,
+            instance,
+            surface: surface_stuff.surface,
+            surface_loader: surface_stuff.surface_loader,
+            surface_format,
+            debug_utils_loader,
+            debug_messenger,
+
+            physical_device,
+            memory_properties: physical_device_memory_properties,
+            device,
+
+            queue_family,
+            graphics_queue,
+            present_queue,
+            present_transfer,
+            synchronization,
+            features,
+            device_count: device_group.len().max(1) as u32,
+            shader_modules: Mutex::new(shader_modules),
+            sampler_cache: Mutex::new(sampler_cache),
+            queue_lock: Mutex::new(()),
+
+            swapchain_loader: swapchain_stuff.swapchain_loader,
+            swapchain: swapchain_stuff.swapchain,
+            swapchain_format: swapchain_stuff.swapchain_format,
+            swapchain_images: swapchain_stuff.swapchain_images,
+            swapchain_extent: swapchain_stuff.swapchain_extent,
+            requested_swapchain_usage: swapchain_usage,
+            swapchain_usage: swapchain_stuff.swapchain_usage,
+            swapchain_imageviews,
+            swapchain_framebuffers,
+            present_timing,
+            low_latency: false,
+            nv_low_latency,
+
+            pipeline_layout,
+            ubo_layout,
+            render_pass,
+            graphics_pipeline,
+
+            color_image,
+            color_image_view,
+            color_image_memory,
+
+            depth_image,
+            depth_image_view,
+            depth_image_memory,
+
+            msaa_samples,
+
+            mip_levels,
+            texture_image,
+            texture_image_view,
+            texture_image_memory,
+
+            vertices,
+            indices,
+
+            vertex_buffer,
+            vertex_buffer_memory,
+            index_buffer,
+            index_buffer_memory,
+
+            uniform_transform: model_transform(
+                model,
+                &model_camera,
+                &model_scene_bounds,
+                swapchain_stuff.swapchain_extent,
+            ),
+            uniform_ring,
+            model,
+            model_camera,
+            model_scene_bounds,
+            previous_model_rotation: Deg(0.0),
+            model_rotation: Deg(0.0),
+
+            descriptor_pool,
+            descriptor_set,
+
+            command_pool,
+            command_buffers,
+
+            image_available_semaphores: sync_objects.image_available_semaphores,
+            render_finished_semaphores: sync_objects.render_finished_semaphores,
+            in_flight_fences: sync_objects.inflight_fences,
+            current_frame: 0,
+
+            is_framebuffer_resized: false,
+            pending_screenshot: None,
+            are_command_buffers_outdated: AtomicBool::new(false),
+        };
+        renderer.name_model_buffers(Path::new(&user_settings.scene));
+        let texture_name = utility::debug::asset_debug_name(Path::new(TEXTURE_PATH), "texture");
+        renderer.set_debug_name(renderer.texture_image, &texture_name);
+        renderer.set_debug_name(
+            renderer.texture_image_memory,
+            &format!("{} memory", texture_name),
+        );
+        renderer.set_debug_name(
+            renderer.texture_image_view,
+            &format!("{} view", texture_name),
+        );
+        renderer
+    }
+}
+
+// What a Renderer is created with, the defaults of the executable without options
+pub struct RendererBuilder {
+    validation: ValidationInfo,
+    user_settings: UserSettings,
+    split_frame: bool,
+    swapchain_usage: vk::ImageUsageFlags,
+}
+
+impl RendererBuilder {
+    // VALIDATION resolved by default, see ValidationInfo::resolve
+    pub fn validation(mut self, validation: ValidationInfo) -> RendererBuilder {
+        self.validation = validation;
+        self
+    }
+
+    // The window geometry to restore and the OBJ model to rasterize
+    pub fn user_settings(mut self, user_settings: UserSettings) -> RendererBuilder {
+        self.user_settings = user_settings;
+        self
+    }
+
+    // Experimental, the other GPUs of a device group trace bands of each frame
+    pub fn split_frame(mut self, split_frame: bool) -> RendererBuilder {
+        self.split_frame = split_frame;
+        self
+    }
+
+    // Added to the swapchain usage where the surface and its format allow it, e.g. STORAGE
+    pub fn swapchain_usage(mut self, swapchain_usage: vk::ImageUsageFlags) -> RendererBuilder {
+        self.swapchain_usage = swapchain_usage;
+        self
+    }
+
+    // Opens a window of its own on the EventLoop of ProgramProc or the window target of an
+    // event loop the host runs
+    #[cfg(feature = "winit")]
+    pub fn build(self, event_loop: &winit::event_loop::EventLoopWindowTarget<()>) -> Renderer {
+        let window =
+            utility::window::init_window(event_loop, WINDOW_TITLE, WINDOW_WIDTH, WINDOW_HEIGHT);
+        if let Some(geometry) = &self.user_settings.window {
+            utility::window::restore_geometry(&window, geometry);
+        }
+        self.build_with_window(window)
+    }
+
+    // Renders into a window the host created, see Renderer::from_window
+    #[cfg(feature = "winit")]
+    pub fn build_with_window(self, window: winit::window::Window) -> Renderer {
+        self.build_with_surface_source(SurfaceSource::Window(window))
+    }
+
+    // Renders into a window of another toolkit, see Renderer::from_raw_handles
+    pub fn build_with_raw_handles(
+        self,
+        display: RawDisplayHandle,
+        window: RawWindowHandle,
+        extent: vk::Extent2D,
+    ) -> Renderer {
+        self.build_with_surface_source(SurfaceSource::RawHandles {
+            handles: RawSurfaceHandles { display, window },
+            size: extent,
+        })
+    }
+
+    fn build_with_surface_source(self, surface_source: SurfaceSource) -> Renderer {
+        Renderer::from_surface_source(
+            surface_source,
+            &self.validation,
+            &self.user_settings,
+            self.split_frame,
+            self.swapchain_usage,
+        )
+    }
+}
+
+// Asset loading and command recording threads share the renderer through an Arc
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Renderer>();
+};
+
+impl Renderer {
+    // Taken by every user of command_pool and graphics_queue outside the frame loop
+    pub(super) fn lock_queue(&self) -> MutexGuard<'_, ()> {
+        self.queue_lock.lock().unwrap()
+    }
+
+    // Validation messages and graphics debuggers show the name instead of the raw handle
+    pub(super) fn set_debug_name<T: vk::Handle>(&self, handle: T, name: &str) {
+        utility::debug::set_object_name(
+            self.debug_utils_loader.as_ref(),
+            self.device.handle(),
+            handle,
+            name,
+        );
+    }
+
+    // The buffers of the rasterized model are named after the file they were loaded from
+    fn name_model_buffers(&self, path: &Path) {
+        for (buffer, memory, resource) in [
+            (self.vertex_buffer, self.vertex_buffer_memory, "vertices"),
+            (self.index_buffer, self.index_buffer_memory, "indices"),
+        ] {
+            let name = utility::debug::asset_debug_name(path, resource);
+            self.set_debug_name(buffer, &name);
+            self.set_debug_name(memory, &format!("{} memory", name));
+        }
+    }
+
+    fn update_uniform_buffer(&mut self, current_image: usize, alpha: f32) {
+        let rotation = self.previous_model_rotation
+            + (self.model_rotation - self.previous_model_rotation) * alpha;
+        self.uniform_transform.model = camera_relative_model(
+            Matrix4::from_axis_angle(Vector3::new(0.0, 0.0, 1.0), rotation) * self.model,
+            self.model_camera.position,
+        );
+
+        // The command buffers were recorded with the offset of the first allocation of each
+        // image's slice, which is where the transform lands
+        self.uniform_ring.begin_frame(current_image);
+        self.uniform_ring.push(&self.uniform_transform);
+    }
+
+    // A minimized window has no area to present to, frames are skipped until it is restored
+    fn is_minimized(&self) -> bool {
+        let size = self.surface_source.size();
+        size.width == 0 || size.height == 0
+    }
+
+    // Where the host owns the window through its raw handles, the swapchain follows the size set
+    // here. Windows from winit are resized through their events.
+    pub fn set_window_size(&mut self, extent: vk::Extent2D) {
+        if let SurfaceSource::RawHandles { size, .. } = &mut self.surface_source {
+            *size = extent;
+            self.is_framebuffer_resized = true;
+        }
+    }
+
+    // Trades smoothness for latency while viewing interactively: frames are presented with
+    // mailbox, or immediate where there is no mailbox, and only one is in flight. With
+    // VK_NV_low_latency2 the driver also holds each frame back until as late as it can start.
+    pub fn set_low_latency(&mut self, enabled: bool) {
+        if self.low_latency == enabled {
+            return;
+        }
+        self.low_latency = enabled;
+        self.current_frame = 0;
+        self.recreate_swapchain();
+    }
+
+    fn frames_in_flight(&self) -> usize {
+        if self.low_latency {
+            1
+        } else {
+            MAX_FRAMES_IN_FLIGHT
+        }
+    }
+
+    // Only in the low latency mode, markers of the frame presented with `present_id`
+    // Sizes of BLASes built from meshes of (vertex count, triangle count), with the 32 bit
+    // indices, float positions and flags of the ray query BLAS. Queried without building
+    // anything, None when the device has no VK_KHR_acceleration_structure to ask.
+    pub fn estimate_blas_sizes(
+        &self,
+        meshes: &[(u32, u32)],
+    ) -> Option<Vec<vk::AccelerationStructureBuildSizesInfoKHR>> {
+        if !self.features.contains(DeviceFeature::AccelerationStructure) {
+            return None;
+        }
+        let acceleration_structure = khr::AccelerationStructure::new(&self.instance, &self.device);
+        let sizes = meshes
+            .iter()
+            .map(|&(vertex_count, triangle_count)| {
+                // The size query ignores the addresses
+                let geometries = [vk::AccelerationStructureGeometryKHR::builder()
+                    .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+                    .geometry(vk::AccelerationStructureGeometryDataKHR {
+                        triangles: vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
+                            .vertex_format(vk::Format::R32G32B32_SFLOAT)
+                            .vertex_stride(std::mem::size_of::<[f32; 3]>() as vk::DeviceSize)
+                            .max_vertex(vertex_count.saturating_sub(1))
+                            .index_type(vk::IndexType::UINT32)
+                            .build(),
+                    })
+                    .flags(vk::GeometryFlagsKHR::OPAQUE)
+                    .build()];
+                let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+                    .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+                    .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+                    .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+                    .geometries(&geometries)
+                    .build();
+                unsafe {
+                    acceleration_structure.get_acceleration_structure_build_sizes(
+                        vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                        &build_info,
+                        &[triangle_count],
+                    )
+                }
+            })
+            .collect();
+        Some(sizes)
+    }
+
+    fn set_latency_marker(&self, present_id: u64, marker: LatencyMarker) {
+        if let (true, Some(nv_low_latency)) = (self.low_latency, &self.nv_low_latency) {
+            nv_low_latency.set_marker(self.swapchain, present_id, marker);
+        }
+    }
+
+    // None when the device has neither VK_GOOGLE_display_timing nor VK_KHR_present_wait
+    pub fn present_stats(&self) -> Option<PresentStats> {
+        self.present_timing
+            .is_enabled()
+            .then(|| self.present_timing.stats())
+    }
+
+    // The swapchain and everything sized or formatted after it, on the current surface
+    fn create_swapchain_resources(&mut self) {
+        let surface_stuff = SurfaceStuff {
+            surface_loader: self.surface_loader.clone(),
+            surface: self.surface,
+            screen_width: WINDOW_WIDTH,
+            screen_height: WINDOW_HEIGHT,
+        };
+
+        let swapchain_stuff = utility::general::create_swapchain(
+            &self.instance,
+            &self.device,
+            self.physical_device,
+            self.surface_source.size(),
+            &surface_stuff,
+            self.requested_swapchain_usage,
+            self.low_latency,
+            self.nv_low_latency.is_some(),
+        );
+        self.swapchain_loader = swapchain_stuff.swapchain_loader;
+        self.swapchain = swapchain_stuff.swapchain;
+        self.present_timing.reset();
+        if let Some(nv_low_latency) = &self.nv_low_latency {
+            if let Err(err) = nv_low_latency.set_mode(self.swapchain, self.low_latency) {
+                tracing::warn!(target: TARGET_SWAPCHAIN, "Failed to set the low latency mode: {}", err);
+            }
+        }
+        self.swapchain_images = swapchain_stuff.swapchain_images;
+        self.swapchain_format = swapchain_stuff.swapchain_format;
+        self.swapchain_extent = swapchain_stuff.swapchain_extent;
+        self.swapchain_usage = swapchain_stuff.swapchain_usage;
+
+        self.swapchain_imageviews = utility::general::create_image_views(
+            &self.device,
+            self.swapchain_format,
+            &self.swapchain_images,
+        );
+        self.render_pass = utility::general::create_render_pass(
+            &self.instance,
+            &self.device,
+            self.physical_device,
+            self.swapchain_format,
+            self.msaa_samples,
+        );
+        let (graphics_pipeline, pipeline_layout) = utility::general::create_graphics_pipeline(
+            &self.device,
+            self.shader_modules.get_mut().unwrap(),
+            self.render_pass,
+            swapchain_stuff.swapchain_extent,
+            self.ubo_layout,
+            self.msaa_samples,
+        );
+        self.shader_modules.get_mut().unwrap().trim();
+        self.graphics_pipeline = graphics_pipeline;
+        self.pipeline_layout = pipeline_layout;
+
+        let color_resources = utility::general::create_color_resources(
+            &self.device,
+            self.swapchain_format,
+            self.swapchain_extent,
+            &self.memory_properties,
+            self.msaa_samples,
+        );
+        self.color_image = color_resources.0;
+        self.color_image_view = color_resources.1;
+        self.color_image_memory = color_resources.2;
+
+        let depth_resources = utility::general::create_depth_resources(
+            &self.instance,
+            &self.device,
+            self.physical_device,
+            self.command_pool,
+            self.graphics_queue,
+            self.swapchain_extent,
+            &self.memory_properties,
+            self.msaa_samples,
+        );
+        self.depth_image = depth_resources.0;
+        self.depth_image_view = depth_resources.1;
+        self.depth_image_memory = depth_resources.2;
+
+        self.swapchain_framebuffers = utility::general::create_framebuffers(
+            &self.device,
+            self.render_pass,
+            &self.swapchain_imageviews,
+            self.depth_image_view,
+            self.color_image_view,
+            self.swapchain_extent,
+        );
+        self.record_command_buffers();
+    }
+
+    // One command buffer per framebuffer, drawing the model with the current descriptor set
+    fn record_command_buffers(&mut self) {
+        let uniform_offsets: Vec<u32> = (0..self.swapchain_framebuffers.len())
+            .map(|image| self.uniform_ring.frame_offset(image))
+            .collect();
+        self.command_buffers = utility::general::create_command_buffers(
+            &self.device,
+            self.command_pool,
+            self.graphics_pipeline,
+            &self.swapchain_framebuffers,
+            self.render_pass,
+            self.swapchain_extent,
+            self.vertex_buffer,
+            self.index_buffer,
+            self.pipeline_layout,
+            self.descriptor_set,
+            &uniform_offsets,
+            self.indices.len() as u32,
+            |command_buffer, image| {
+                if let Some(present_transfer) = &mut self.present_transfer {
+                    present_transfer.record_release(command_buffer, self.swapchain_images[image]);
+                }
+            },
+        );
+        if let Some(present_transfer) = &mut self.present_transfer {
+            present_transfer.record_acquires(&self.swapchain_images);
+        }
+    }
+
+    fn free_command_buffers(&mut self) {
+        unsafe {
+            self.device
+                .free_command_buffers(self.command_pool, &self.command_buffers);
+        }
+        if let Some(present_transfer) = &mut self.present_transfer {
+            present_transfer.free_command_buffers();
+        }
+    }
+
+    // The surface itself is gone, e.g. after its monitor was disconnected or the driver was
+    // reset. A new surface is created for the same window and the swapchain is rebuilt on it.
+    fn recreate_surface(&mut self) {
+        tracing::warn!(target: TARGET_SWAPCHAIN, "Surface lost, recreating it");
+        self.wait_device_idle();
+        self.cleanup_swapchain();
+        unsafe {
+            self.surface_loader.destroy_surface(self.surface, None);
+        }
+
+        let surface_stuff = self
+            .surface_source
+            .create_surface(&self.entry, &self.instance);
+        // The device was picked for the old surface, a new device is out of reach here
+        let is_present_supported = unsafe {
+            surface_stuff
+                .surface_loader
+                .get_physical_device_surface_support(
+                    self.physical_device,
+                    self.queue_family.present_family.unwrap(),
+                    surface_stuff.surface,
+                )
+                .unwrap_or(false)
+        };
+        if !is_present_supported {
+            panic!("The present queue cannot present to the recreated surface.");
+        }
+        self.surface = surface_stuff.surface;
+        self.surface_format =
+            utility::general::create_surface_format(self.physical_device, &surface_stuff);
+
+        self.create_swapchain_resources();
+    }
+
+    // Recreates every cached texture sampler with the new filtering and points the model texture
+    // at its new sampler. Users of other cached samplers fetch theirs again, the model's command
+    // buffers are recorded again before the next frame. Returns whether anything changed.
+    pub(super) fn set_texture_quality(&self, quality: TextureQuality) -> bool {
+        self.wait_device_idle();
+        let mut sampler_cache = self.sampler_cache.lock().unwrap();
+        if !sampler_cache.set_quality(quality) {
+            return false;
+        }
+        tracing::info!("Texture quality {:?}", sampler_cache.quality());
+
+        let image_infos = [vk::DescriptorImageInfo {
+            sampler: sampler_cache.get(model_texture_sampler_key(self.mip_levels)),
+            image_view: self.texture_image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.descriptor_set)
+            .dst_binding(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build();
+        unsafe {
+            self.device.update_descriptor_sets(&[write], &[]);
+        }
+        self.are_command_buffers_outdated
+            .store(true, Ordering::Release);
+        true
+    }
+
+    // Replaces the model with another OBJ file, framed the same way. The texture stays.
+    fn load_model_file(&mut self, path: &Path) -> Result<(), String> {
+        if !path.is_file() {
+            return Err(format!("No model file at {:?}", path));
+        }
+        let (vertices, indices, model_bounds) = load_model(path);
+        self.wait_device_idle();
+        unsafe {
+            self.device.destroy_buffer(self.vertex_buffer, None);
+            self.device.free_memory(self.vertex_buffer_memory, None);
+            self.device.destroy_buffer(self.index_buffer, None);
+            self.device.free_memory(self.index_buffer_memory, None);
+        }
+        (self.vertex_buffer, self.vertex_buffer_memory) = utility::general::create_vertex_buffer(
+            &self.device,
+            &self.memory_properties,
+            self.command_pool,
+            self.graphics_queue,
+            &vertices,
+        );
+        (self.index_buffer, self.index_buffer_memory) = utility::general::create_index_buffer(
+            &self.device,
+            &self.memory_properties,
+            self.command_pool,
+            self.graphics_queue,
+            &indices,
+        );
+        self.name_model_buffers(path);
+        self.model_scene_bounds = model_bounds.scene.transformed(&self.model);
+        self.set_model_camera(framed_model_camera(&self.model_scene_bounds));
+        self.vertices = vertices;
+        self.indices = indices;
+        tracing::info!(
+            "Loaded {:?}: {} vertices, {} triangles",
+            path,
+            self.vertices.len(),
+            self.indices.len() / 3
+        );
+        self.are_command_buffers_outdated
+            .store(true, Ordering::Release);
+        Ok(())
+    }
+
+    fn set_model_camera(&mut self, camera: Camera) {
+        self.model_camera = camera;
+        self.uniform_transform = model_transform(
+            self.model,
+            &self.model_camera,
+            &self.model_scene_bounds,
+            self.swapchain_extent,
+        );
+    }
+
+    // Copies the swapchain image a frame was just rendered into out to an image file, before the
+    // frame is presented
+    fn save_swapchain_image(&self, image_index: usize, path: &Path) -> Result<(), String> {
+        if !self
+            .swapchain_usage
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            return Err("The surface does not allow copies out of the swapchain".to_string());
+        }
+        let is_bgra = match self.swapchain_format {
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => true,
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => false,
+            format => return Err(format!("Cannot save swapchain format {:?}", format)),
+        };
+        // A present family of its own owns the image by now, and orders the copy after its
+        // acquire barrier, which waited for the rendering
+        let (command_pool, queue, src_stage, src_access) = match &self.present_transfer {
+            Some(present_transfer) if !present_transfer.can_copy() => {
+                return Err("The present queue cannot copy out of the swapchain".to_string())
+            }
+            Some(present_transfer) => (
+                present_transfer.command_pool(),
+                self.present_queue,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::AccessFlags::empty(),
+            ),
+            None => (
+                self.command_pool,
+                self.graphics_queue,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ),
+        };
+        let extent = self.swapchain_extent;
+        let size = (extent.width * extent.height * 4) as vk::DeviceSize;
+        let (buffer, memory) = utility::general::create_buffer(
+            &self.device,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &self.memory_properties,
+        );
+
+        let image = self.swapchain_images[image_index];
+        let barrier = |old_layout, new_layout, src_access_mask, dst_access_mask| {
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .build()
+        };
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D::default(),
+            image_extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+        };
+        {
+            let _queue = self.lock_queue();
+            let command_buffer =
+                utility::general::begin_single_time_command(&self.device, command_pool);
+            unsafe {
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    src_stage,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier(
+                        vk::ImageLayout::PRESENT_SRC_KHR,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        src_access,
+                        vk::AccessFlags::TRANSFER_READ,
+                    )],
+                );
+                self.device.cmd_copy_image_to_buffer(
+                    command_buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    buffer,
+                    &[region],
+                );
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier(
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::PRESENT_SRC_KHR,
+                        vk::AccessFlags::TRANSFER_READ,
+                        vk::AccessFlags::empty(),
+                    )],
+                );
+            }
+            utility::general::end_single_time_command(
+                &self.device,
+                command_pool,
+                queue,
+                command_buffer,
+            );
+        }
+
+        let mut pixels = vec![0u8; size as usize];
+        unsafe {
+            let data = self
+                .device
+                .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+                .expect("Failed to map screenshot memory.") as *const u8;
+            data.copy_to_nonoverlapping(pixels.as_mut_ptr(), pixels.len());
+            self.device.unmap_memory(memory);
+            self.device.destroy_buffer(buffer, None);
+            self.device.free_memory(memory, None);
+        }
+        if is_bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        RgbaImage::from_raw(extent.width, extent.height, pixels)
+            .expect("Screenshot size does not match the swapchain.")
+            .save(path)
+            .map_err(|err| format!("Failed to save {:?}: {}", path, err))
+    }
+}
+
+// The model seen from its (1, 1, 1) diagonal, as far away as its world space bounds need
+fn framed_model_camera(bounds: &Aabb) -> Camera {
+    let mut model_camera = Camera {
+        position: Point3::new(1.0, 1.0, 1.0),
+        target: Point3::origin(),
+        up: Vector3::new(0.0, 0.0, 1.0),
+        projection: Projection::Perspective {
+            fov_y: MODEL_CAMERA_FOV_Y,
+        },
+        ..Default::default()
+    };
+    model_camera.frame_scene(bounds);
+    model_camera
+}
+
+// Moves the model by the camera position in double precision, so the GPU does not have to cancel
+// two large translations against each other in the model and view matrices
+fn camera_relative_model(model: Matrix4<f32>, camera_position: Point3<f32>) -> Matrix4<f32> {
+    let model: Matrix4<f64> = model.cast().unwrap();
+    let camera_position: Vector3<f64> = camera_position.to_vec().cast().unwrap();
+    (Matrix4::from_translation(-camera_position) * model)
+        .cast()
+        .unwrap()
+}
+
+// The depth range is fitted to the world space bounds. The view is camera relative, it only
+// rotates, update_uniform_buffer moves the model instead.
+fn model_transform(
+    model: Matrix4<f32>,
+    camera: &Camera,
+    bounds: &Aabb,
+    extent: vk::Extent2D,
+) -> UniformBufferObject {
+    let fov_y = match camera.projection {
+        Projection::Perspective { fov_y } => fov_y,
+        _ => MODEL_CAMERA_FOV_Y,
+    };
+    let depth_range = DepthRange::default().resolve(camera, bounds);
+    UniformBufferObject {
+        model: camera_relative_model(model, camera.position),
+        view: Matrix4::look_to_rh(Point3::origin(), camera.target - camera.position, camera.up),
+        proj: {
+            let mut proj = cgmath::perspective(
+                fov_y,
+                extent.width as f32 / extent.height as f32,
+                depth_range.near,
+                depth_range.far,
+            );
+            proj[1][1] = -proj[1][1];
+            proj
+        },
+    }
+}
+
+fn model_texture_sampler_key(mip_levels: u32) -> SamplerKey {
+    SamplerKey {
+        address_mode: vk::SamplerAddressMode::REPEAT,
+        mip_levels,
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        unsafe {
+            for i in 0..MAX_FRAMES_IN_FLIGHT {
+                self.device
+                    .destroy_semaphore(self.image_available_semaphores[i], None);
+                self.device
+                    .destroy_semaphore(self.render_finished_semaphores[i], None);
+                self.device.destroy_fence(self.in_flight_fences[i], None);
+            }
+
+            self.cleanup_swapchain();
+            if let Some(present_transfer) = &mut self.present_transfer {
+                present_transfer.destroy();
+            }
+            if let Some(nv_low_latency) = &self.nv_low_latency {
+                nv_low_latency.destroy();
+            }
+
+            self.device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+
+            self.uniform_ring.destroy(&self.device);
+
+            self.device.destroy_buffer(self.index_buffer, None);
+            self.device.free_memory(self.index_buffer_memory, None);
+
+            self.device.destroy_buffer(self.vertex_buffer, None);
+            self.device.free_memory(self.vertex_buffer_memory, None);
+
+            self.sampler_cache.get_mut().unwrap().destroy();
+            self.device
+                .destroy_image_view(self.texture_image_view, None);
+
+            self.device.destroy_image(self.texture_image, None);
+            self.device.free_memory(self.texture_image_memory, None);
+
+            self.device
+                .destroy_descriptor_set_layout(self.ubo_layout, None);
+
+            self.device.destroy_command_pool(self.command_pool, None);
+
+            self.shader_modules.get_mut().unwrap().destroy();
+            self.device.destroy_device(None);
+            self.surface_loader.destroy_surface(self.surface, None);
+
+            if let Some(debug_utils_loader) = &self.debug_utils_loader {
+                debug_utils_loader.destroy_debug_utils_messenger(self.debug_messenger, None);
+            }
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+// What the console of `ash_rt window` can do with the rasterized model
+pub fn renderer_console_commands() -> CommandRegistry<Renderer> {
+    CommandRegistry::<Renderer>::default()
+        .register(
+            "get",
+            "[max_anisotropy|lod_bias|trilinear]",
+            |renderer, args| {
+                let quality = renderer.sampler_cache.lock().unwrap().quality();
+                match args {
+                    [] => Ok(format!("{:?}", quality)),
+                    ["max_anisotropy"] => Ok(quality.max_anisotropy.to_string()),
+                    ["lod_bias"] => Ok(quality.lod_bias.to_string()),
+                    ["trilinear"] => Ok(quality.trilinear.to_string()),
+                    _ => Err(format!("Unknown setting {}", args.join(" "))),
+                }
+            },
+        )
+        .register(
+            "set",
+            "<max_anisotropy|lod_bias|trilinear> <value>",
+            |renderer, args| {
+                let (name, value) = match args {
+                    [name, value] => (name, value),
+                    _ => return Err("Expected a name and a value".to_string()),
+                };
+                let mut quality = renderer.sampler_cache.lock().unwrap().quality();
+                quality.edit(&format!("{}={}", name, value))?;
+                renderer.set_texture_quality(quality);
+                Ok(format!(
+                    "{:?}",
+                    renderer.sampler_cache.lock().unwrap().quality()
+                ))
+            },
+        )
+        .register(
+            "camera",
+            "[x y z target_x target_y target_z [fov_y]]",
+            |renderer, args| {
+                if !args.is_empty() {
+                    let values = args
+                        .iter()
+                        .map(|arg| arg.parse::<f32>())
+                        .collect::<Result<Vec<f32>, _>>()
+                        .map_err(|_| "Expected numbers".to_string())?;
+                    let mut camera = renderer.model_camera;
+                    match values[..] {
+                        [x, y, z, target_x, target_y, target_z, ..] if values.len() <= 7 => {
+                            camera.position = Point3::new(x, y, z);
+                            camera.target = Point3::new(target_x, target_y, target_z);
+                        }
+                        _ => return Err("Expected a position and a target".to_string()),
+                    }
+                    if let Some(&fov_y) = values.get(6) {
+                        camera.projection = Projection::Perspective { fov_y: Deg(fov_y) };
+                    }
+                    renderer.set_model_camera(camera);
+                }
+                let camera = &renderer.model_camera;
+                Ok(format!(
+                    "{:?} looking at {:?}, {:?}",
+                    camera.position, camera.target, camera.projection
+                ))
+            },
+        )
+        .register("load", "<model.obj>", |renderer, args| match args {
+            [path] => renderer
+                .load_model_file(Path::new(path))
+                .map(|()| String::new()),
+            _ => Err("Expected a model path".to_string()),
+        })
+        .register("screenshot", "[path]", |renderer, args| {
+            let path = match args {
+                [] => SCREENSHOT_OUTPUT_PATH,
+                [path] => path,
+                _ => return Err("Expected at most one path".to_string()),
+            };
+            renderer.pending_screenshot = Some(PathBuf::from(path));
+            Ok(String::new())
+        })
+        // Edited shaders hash differently in the ShaderModuleCache and are compiled again
+        .register("reload-shaders", "", |renderer, _| {
+            renderer.recreate_swapchain();
+            Ok("Rebuilt the graphics pipeline".to_string())
+        })
+        .register("low-latency", "[on|off]", |renderer, args| {
+            match args {
+                [] => {}
+                ["on"] => renderer.set_low_latency(true),
+                ["off"] => renderer.set_low_latency(false),
+                _ => return Err("Expected on or off".to_string()),
+            }
+            Ok(format!(
+                "Low latency {}{}",
+                if renderer.low_latency { "on" } else { "off" },
+                if renderer.nv_low_latency.is_some() {
+                    ", with VK_NV_low_latency2"
+                } else {
+                    ""
+                }
+            ))
+        })
+        .register("stats", "", |renderer, _| {
+            let mut stats = format!(
+                "{} vertices, {} triangles, {}x{} swapchain of {} images, {:?}",
+                renderer.vertices.len(),
+                renderer.indices.len() / 3,
+                renderer.swapchain_extent.width,
+                renderer.swapchain_extent.height,
+                renderer.swapchain_images.len(),
+                renderer.sampler_cache.lock().unwrap().quality()
+            );
+            if let Some(present) = renderer.present_stats() {
+                stats.push_str(&format!(
+                    "\n{} frames presented, {} missed vsyncs",
+                    present.presented, present.missed_vsyncs
+                ));
+                let durations = [
+                    ("refresh", present.refresh_duration),
+                    ("present margin", present.present_margin),
+                    ("latency", present.last_latency),
+                    ("mean latency", present.mean_latency),
+                ];
+                for (name, duration) in durations {
+                    if let Some(duration) = duration {
+                        stats.push_str(&format!(
+                            ", {} {:.2} ms",
+                            name,
+                            duration.as_secs_f64() * 1e3
+                        ));
+                    }
+                }
+            }
+            Ok(stats)
+        })
+}
+
+impl Renderer {
+    // Renders between the last two simulation states, `alpha` of the way from the older one.
+    // Hosts without the window loop call it once per frame of theirs.
+    pub fn draw_frame(&mut self, alpha: f32) {
+        if self.is_minimized() {
+            return;
+        }
+        if self
+            .are_command_buffers_outdated
+            .swap(false, Ordering::Acquire)
+        {
+            self.wait_device_idle();
+            self.free_command_buffers();
+            self.record_command_buffers();
+        }
+        if let (true, Some(nv_low_latency)) = (self.low_latency, &mut self.nv_low_latency) {
+            if let Err(err) = nv_low_latency.sleep(self.swapchain) {
+                tracing::debug!(target: TARGET_SWAPCHAIN, "Low latency sleep failed: {}", err);
+            }
+        }
+        let present_id = self.present_timing.next_present_id();
+        self.set_latency_marker(present_id, LatencyMarker::SimulationStart);
+        let wait_fences = [self.in_flight_fences[self.current_frame]];
+
+        unsafe {
+            self.device
+                .wait_for_fences(&wait_fences, true, std::u64::MAX)
+                .expect("Failed to wait for Fence!");
+        }
+
+        let (image_index, _is_sub_optimal) = unsafe {
+            let result = self.swapchain_loader.acquire_next_image(
+                self.swapchain,
+                std::u64::MAX,
+                self.image_available_semaphores[self.current_frame],
+                vk::Fence::null(),
+            );
+            match result {
+                Ok(image_index) => image_index,
+                Err(vk_result) => match vk_result {
+                    vk::Result::ERROR_OUT_OF_DATE_KHR => {
+                        self.recreate_swapchain();
+                        return;
+                    }
+                    vk::Result::ERROR_SURFACE_LOST_KHR => {
+                        self.recreate_surface();
+                        return;
+                    }
+                    _ => panic!("Failed to acquire Swap Chain Image!"),
+                },
+            }
+        };
+
+        self.update_uniform_buffer(image_index as usize, alpha);
+        self.set_latency_marker(present_id, LatencyMarker::SimulationEnd);
+
+        let wait_semaphores = [self.image_available_semaphores[self.current_frame]];
+        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let signal_semaphores = [self.render_finished_semaphores[self.current_frame]];
+
+        let submission_present_id = LatencySubmissionPresentIdNV::new(present_id);
+        let submit_infos = [vk::SubmitInfo {
+            s_type: vk::StructureType::SUBMIT_INFO,
+            p_next: if self.low_latency && self.nv_low_latency.is_some() {
+                &submission_present_id as *const LatencySubmissionPresentIdNV
+                    as *const std::ffi::c_void
+            } else {
+                ptr::null()
+            },
+            wait_semaphore_count: wait_semaphores.len() as u32,
+            p_wait_semaphores: wait_semaphores.as_ptr(),
+            p_wait_dst_stage_mask: wait_stages.as_ptr(),
+            command_buffer_count: 1,
+            p_command_buffers: &self.command_buffers[image_index as usize],
+            signal_semaphore_count: signal_semaphores.len() as u32,
+            p_signal_semaphores: signal_semaphores.as_ptr(),
+        }];
+
+        self.set_latency_marker(present_id, LatencyMarker::RenderSubmitStart);
+        unsafe {
+            self.device
+                .reset_fences(&wait_fences)
+                .expect("Failed to reset Fence!");
+
+            self.device
+                .queue_submit(
+                    self.graphics_queue,
+                    &submit_infos,
+                    self.in_flight_fences[self.current_frame],
+                )
+                .expect("Failed to execute queue submit.");
+        }
+        self.set_latency_marker(present_id, LatencyMarker::RenderSubmitEnd);
+
+        let present_wait_semaphores = match &self.present_transfer {
+            Some(present_transfer) => [present_transfer.submit(
+                self.present_queue,
+                self.current_frame,
+                image_index,
+                signal_semaphores[0],
+            )],
+            None => signal_semaphores,
+        };
+
+        if let Some(path) = self.pending_screenshot.take() {
+            match self.save_swapchain_image(image_index as usize, &path) {
+                Ok(()) => tracing::info!("Saved {:?}", path),
+                Err(message) => tracing::warn!("{}", message),
+            }
+        }
+
+        self.set_latency_marker(present_id, LatencyMarker::PresentStart);
+        let result = self.present_timing.queue_present(
+            &self.swapchain_loader,
+            self.present_queue,
+            self.swapchain,
+            image_index,
+            &present_wait_semaphores,
+        );
+        self.set_latency_marker(present_id, LatencyMarker::PresentEnd);
+        self.present_timing.update(self.swapchain);
+
+        let is_resized = match result {
+            Ok(_) => self.is_framebuffer_resized,
+            Err(vk_result) => match vk_result {
+                vk::Result::ERROR_OUT_OF_DATE_KHR | vk::Result::SUBOPTIMAL_KHR => true,
+                vk::Result::ERROR_SURFACE_LOST_KHR => {
+                    self.recreate_surface();
+                    self.current_frame = (self.current_frame + 1) % self.frames_in_flight();
+                    return;
+                }
+                _ => panic!("Failed to execute queue present."),
+            },
+        };
+        // A minimized window keeps the flag until it is restored
+        if is_resized && !self.is_minimized() {
+            self.is_framebuffer_resized = false;
+            self.recreate_swapchain();
+        }
+
+        self.current_frame = (self.current_frame + 1) % self.frames_in_flight();
+    }
+
+    fn recreate_swapchain(&mut self) {
+        tracing::debug!(target: TARGET_SWAPCHAIN, "Recreating swapchain");
+        self.wait_device_idle();
+        self.cleanup_swapchain();
+        self.create_swapchain_resources();
+    }
+
+    fn cleanup_swapchain(&mut self) {
+        self.free_command_buffers();
+        unsafe {
+            self.device.destroy_image(self.depth_image, None);
+            self.device.destroy_image_view(self.depth_image_view, None);
+            self.device.free_memory(self.depth_image_memory, None);
+
+            self.device.destroy_image(self.color_image, None);
+            self.device.destroy_image_view(self.color_image_view, None);
+            self.device.free_memory(self.color_image_memory, None);
+
+            for &framebuffer in self.swapchain_framebuffers.iter() {
+                self.device.destroy_framebuffer(framebuffer, None);
+            }
+            self.shader_modules
+                .lock()
+                .unwrap()
+                .destroy_pipeline(self.graphics_pipeline);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device.destroy_render_pass(self.render_pass, None);
+            for &image_view in self.swapchain_imageviews.iter() {
+                self.device.destroy_image_view(image_view, None);
+            }
+            self.swapchain_loader
+                .destroy_swapchain(self.swapchain, None);
+        }
+    }
+
+    pub(super) fn wait_device_idle(&self) {
+        unsafe {
+            self.device
+                .device_wait_idle()
+                .expect("Failed to wait device idle!")
+        };
+    }
+
+    fn resize_framebuffer(&mut self) {
+        self.is_framebuffer_resized = true;
+    }
+}
+
+#[cfg(feature = "winit")]
+impl VulkanApp for Renderer {
+    fn update(&mut self, step: f32, input: &Input) {
+        self.previous_model_rotation = self.model_rotation;
+        self.model_rotation += MODEL_ROTATION_SPEED * step;
+
+        let movement = Vector3::new(
+            input.axis(Action::MoveRight, Action::MoveLeft),
+            input.axis(Action::MoveUp, Action::MoveDown),
+            input.axis(Action::MoveForward, Action::MoveBackward),
+        );
+        let yaw = input.axis(Action::LookRight, Action::LookLeft);
+        let pitch = input.axis(Action::LookUp, Action::LookDown);
+        if movement.is_zero() && yaw == 0.0 && pitch == 0.0 {
+            return;
+        }
+        let speed = CAMERA_MOVE_SPEED * self.model_scene_bounds.radius() * step;
+        let mut camera = self.model_camera;
+        camera.fly(
+            movement * speed,
+            CAMERA_LOOK_SPEED * yaw * step,
+            CAMERA_LOOK_SPEED * pitch * step,
+        );
+        self.set_model_camera(camera);
+    }
+
+    fn draw_frame(&mut self, alpha: f32) {
+        Renderer::draw_frame(self, alpha)
+    }
+
+    fn recreate_swapchain(&mut self) {
+        Renderer::recreate_swapchain(self)
+    }
+
+    fn cleanup_swapchain(&mut self) {
+        Renderer::cleanup_swapchain(self)
+    }
+
+    fn wait_device_idle(&self) {
+        Renderer::wait_device_idle(self)
+    }
+
+    fn resize_framebuffer(&mut self) {
+        Renderer::resize_framebuffer(self)
+    }
+
+    fn window_ref(&self) -> Option<&winit::window::Window> {
+        self.surface_source.window()
+    }
+}
@@ -0,0 +1,217 @@
+use std::{path::Path, sync::Arc};
+
+use super::{base::Renderer, pipelines::create_compute_pipeline};
+use crate::utility::{constants::*, push_constant_diagnostics::PushConstantLayout, structures::*};
+
+use ash::vk;
+
+// Fills in the half of the HDR target the checkerboard trace skipped, in place. The parity of
+// the traced pixels flips every frame, so the skipped ones still hold the frame before.
+pub(super) struct CheckerboardPass {
+    base: Arc<Renderer>,
+    extent: vk::Extent2D,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    // (x + y) % 2 of the pixels traced this frame
+    parity: u32,
+    // False until a frame was reconstructed into the target
+    has_history: bool,
+}
+
+impl CheckerboardPass {
+    pub(super) fn new(base: Arc<Renderer>, extent: vk::Extent2D) -> Self {
+        CheckerboardPass {
+            base,
+            extent,
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            parity: 0,
+            has_history: false,
+        }
+    }
+
+    pub(super) fn initialize(&mut self, hdr_view: vk::ImageView) {
+        unsafe {
+            let descriptor_set_layout_bindings = [vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            }];
+            self.descriptor_set_layout = self
+                .base
+                .device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::builder()
+                        .bindings(&descriptor_set_layout_bindings)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create checkerboard descriptor set layout.");
+
+            let push_constant_ranges = [vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<CheckerboardPushConstants>() as u32,
+            }];
+            let layouts = [self.descriptor_set_layout];
+            self.pipeline_layout = self
+                .base
+                .device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder()
+                        .set_layouts(&layouts)
+                        .push_constant_ranges(&push_constant_ranges)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create checkerboard pipeline layout.");
+
+            let descriptor_sizes = [vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: 1,
+            }];
+            self.descriptor_pool = self
+                .base
+                .device
+                .create_descriptor_pool(
+                    &vk::DescriptorPoolCreateInfo::builder()
+                        .pool_sizes(&descriptor_sizes)
+                        .max_sets(1)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create checkerboard descriptor pool.");
+
+            self.descriptor_set = self
+                .base
+                .device
+                .allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::builder()
+                        .descriptor_pool(self.descriptor_pool)
+                        .set_layouts(&layouts)
+                        .build(),
+                )
+                .expect("Failed to allocate checkerboard descriptor set.")[0];
+
+            let hdr_image_info = [vk::DescriptorImageInfo {
+                image_layout: vk::ImageLayout::GENERAL,
+                image_view: hdr_view,
+                ..Default::default()
+            }];
+            let descriptor_writes = [vk::WriteDescriptorSet::builder()
+                .dst_set(self.descriptor_set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(&hdr_image_info)
+                .build()];
+            self.base
+                .device
+                .update_descriptor_sets(&descriptor_writes, &[]);
+        }
+
+        self.pipeline = create_compute_pipeline(
+            &self.base,
+            self.pipeline_layout,
+            Path::new("shaders/compiled/checkerboard.glsl_reconstruct_comp.spv"),
+            PushConstantLayout::of::<CheckerboardPushConstants>(),
+        );
+    }
+
+    // What the ray generation shader is pushed as ViewportPushConstants::checkerboard
+    pub(super) fn push_constant(&self) -> u32 {
+        1 + self.parity
+    }
+
+    // Whether the target has to keep its contents from the frame before
+    pub(super) fn has_history(&self) -> bool {
+        self.has_history
+    }
+
+    // Expects this frame's half to be traced into the HDR target, in GENERAL layout. Flips the
+    // parity for the next frame.
+    pub(super) fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        let push_constants = CheckerboardPushConstants {
+            width: self.extent.width,
+            height: self.extent.height,
+            parity: self.parity,
+            has_history: self.has_history as u32,
+        };
+
+        let trace_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .build();
+
+        unsafe {
+            self.base.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_NV,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[trace_barrier],
+                &[],
+                &[],
+            );
+            self.base.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            self.base.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            self.base.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const CheckerboardPushConstants as *const u8,
+                    std::mem::size_of::<CheckerboardPushConstants>(),
+                ),
+            );
+            self.base.device.cmd_dispatch(
+                command_buffer,
+                self.extent.width.div_ceil(CHECKERBOARD_WORKGROUP_SIZE),
+                self.extent.height.div_ceil(CHECKERBOARD_WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        self.parity ^= 1;
+        self.has_history = true;
+    }
+
+    pub(super) fn release(&mut self) {
+        unsafe {
+            self.base
+                .shader_modules
+                .lock()
+                .unwrap()
+                .destroy_pipeline(self.pipeline);
+            self.base
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.base
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.base
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
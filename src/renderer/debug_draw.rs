@@ -0,0 +1,434 @@
+use std::{ffi::CString, path::Path, sync::Arc};
+
+use super::{base::Renderer, resources::BufferResource};
+use crate::utility::structures::*;
+use cgmath::{EuclideanSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3};
+use memoffset::offset_of;
+
+use ash::vk;
+
+// Immediate mode line overlay drawn over the HDR target before tonemapping. Shapes are queued
+// on the CPU and uploaded when recorded, clear() starts the next frame's set.
+pub(super) struct DebugDraw {
+    base: Arc<Renderer>,
+    extent: vk::Extent2D,
+    vertices: Vec<DebugVertex>,
+    vertex_buffer: Option<BufferResource>,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl DebugDraw {
+    pub(super) fn new(base: Arc<Renderer>, extent: vk::Extent2D) -> Self {
+        DebugDraw {
+            base,
+            extent,
+            vertices: Vec::new(),
+            vertex_buffer: None,
+            render_pass: vk::RenderPass::null(),
+            framebuffer: vk::Framebuffer::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        }
+    }
+
+    pub(super) fn initialize(&mut self, target_view: vk::ImageView, target_format: vk::Format) {
+        self.create_render_pass(target_view, target_format);
+        self.create_pipeline();
+    }
+
+    fn create_render_pass(&mut self, target_view: vk::ImageView, target_format: vk::Format) {
+        // The HDR target stays in GENERAL for the storage image writes around this pass
+        let attachments = [vk::AttachmentDescription::builder()
+            .format(target_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::GENERAL)
+            .final_layout(vk::ImageLayout::GENERAL)
+            .build()];
+
+        let color_attachment_refs = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::GENERAL,
+        }];
+
+        let subpasses = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .build()];
+
+        let dependencies = [
+            // Drawn over whatever the rays, the ReSTIR shading or the placeholder clear wrote
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::RAY_TRACING_SHADER_NV
+                        | vk::PipelineStageFlags::COMPUTE_SHADER
+                        | vk::PipelineStageFlags::TRANSFER,
+                )
+                .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_READ
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                )
+                .build(),
+            // Metered and tonemapped by the exposure pass
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .dst_stage_mask(vk::PipelineStageFlags::COMPUTE_SHADER)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build(),
+        ];
+
+        let render_pass_create_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies)
+            .build();
+
+        unsafe {
+            self.render_pass = self
+                .base
+                .device
+                .create_render_pass(&render_pass_create_info, None)
+                .expect("Failed to create debug draw render pass.");
+
+            let framebuffer_attachments = [target_view];
+            let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(self.render_pass)
+                .attachments(&framebuffer_attachments)
+                .width(self.extent.width)
+                .height(self.extent.height)
+                .layers(1)
+                .build();
+
+            self.framebuffer = self
+                .base
+                .device
+                .create_framebuffer(&framebuffer_create_info, None)
+                .expect("Failed to create debug draw framebuffer.");
+        }
+    }
+
+    fn create_pipeline(&mut self) {
+        unsafe {
+            let push_constant_ranges = [vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: std::mem::size_of::<DebugDrawPushConstants>() as u32,
+            }];
+            let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+                .push_constant_ranges(&push_constant_ranges)
+                .build();
+
+            self.pipeline_layout = self
+                .base
+                .device
+                .create_pipeline_layout(&layout_create_info, None)
+                .expect("Failed to create debug draw pipeline layout.");
+
+            let mut shader_modules = self.base.shader_modules.lock().unwrap();
+            let mut load_module = |path: &Path| shader_modules.load(path);
+            let vert_module = load_module(Path::new("shaders/compiled/debug.glsl_line_vert.spv"));
+            let frag_module = load_module(Path::new("shaders/compiled/debug.glsl_line_frag.spv"));
+
+            let main_name = CString::new("main").unwrap();
+            let shader_stages = [
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::VERTEX)
+                    .module(vert_module)
+                    .name(&main_name)
+                    .build(),
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::FRAGMENT)
+                    .module(frag_module)
+                    .name(&main_name)
+                    .build(),
+            ];
+
+            let binding_descriptions = [vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: std::mem::size_of::<DebugVertex>() as u32,
+                input_rate: vk::VertexInputRate::VERTEX,
+            }];
+            let attribute_descriptions = [
+                vk::VertexInputAttributeDescription {
+                    binding: 0,
+                    location: 0,
+                    format: vk::Format::R32G32B32_SFLOAT,
+                    offset: offset_of!(DebugVertex, position) as u32,
+                },
+                vk::VertexInputAttributeDescription {
+                    binding: 0,
+                    location: 1,
+                    format: vk::Format::R32G32B32A32_SFLOAT,
+                    offset: offset_of!(DebugVertex, color) as u32,
+                },
+            ];
+            let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+                .vertex_binding_descriptions(&binding_descriptions)
+                .vertex_attribute_descriptions(&attribute_descriptions)
+                .build();
+            let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+                .topology(vk::PrimitiveTopology::LINE_LIST)
+                .build();
+
+            let viewports = [vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: self.extent.width as f32,
+                height: self.extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }];
+            let scissors = [vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            }];
+            let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+                .viewports(&viewports)
+                .scissors(&scissors)
+                .build();
+
+            let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+                .polygon_mode(vk::PolygonMode::FILL)
+                .cull_mode(vk::CullModeFlags::NONE)
+                .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                .line_width(1.0)
+                .build();
+            let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+                .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                .build();
+            // An overlay, lines stay visible behind geometry
+            let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+                .depth_test_enable(false)
+                .depth_write_enable(false)
+                .build();
+            let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                .alpha_blend_op(vk::BlendOp::ADD)
+                .color_write_mask(vk::ColorComponentFlags::RGBA)
+                .build()];
+            let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+                .attachments(&color_blend_attachments)
+                .build();
+
+            let pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
+                .stages(&shader_stages)
+                .vertex_input_state(&vertex_input_state)
+                .input_assembly_state(&input_assembly_state)
+                .viewport_state(&viewport_state)
+                .rasterization_state(&rasterization_state)
+                .multisample_state(&multisample_state)
+                .depth_stencil_state(&depth_stencil_state)
+                .color_blend_state(&color_blend_state)
+                .layout(self.pipeline_layout)
+                .render_pass(self.render_pass)
+                .subpass(0)
+                .build();
+
+            self.pipeline = self
+                .base
+                .device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_create_info], None)
+                .expect("Failed to create debug draw pipeline.")[0];
+
+            shader_modules.attach(self.pipeline, &[vert_module, frag_module]);
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    fn line(&mut self, from: Point3<f32>, to: Point3<f32>, color: [f32; 4]) {
+        self.vertices.push(DebugVertex {
+            position: from.into(),
+            color,
+        });
+        self.vertices.push(DebugVertex {
+            position: to.into(),
+            color,
+        });
+    }
+
+    // A small three axis cross, `size` is its full extent in world units
+    pub(super) fn point(&mut self, position: Point3<f32>, size: f32, color: [f32; 4]) {
+        let half = size / 2.0;
+        for axis in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+            self.line(position - axis * half, position + axis * half, color);
+        }
+    }
+
+    pub(super) fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: [f32; 4]) {
+        self.transformed_box(&Matrix4::identity(), min, max, color);
+    }
+
+    // Edges of the box between `min` and `max` in the space `world_from_local` maps from
+    pub(super) fn transformed_box(
+        &mut self,
+        world_from_local: &Matrix4<f32>,
+        min: Point3<f32>,
+        max: Point3<f32>,
+        color: [f32; 4],
+    ) {
+        let corner = |index: usize| {
+            let local = Point3::new(
+                if index & 1 == 0 { min.x } else { max.x },
+                if index & 2 == 0 { min.y } else { max.y },
+                if index & 4 == 0 { min.z } else { max.z },
+            );
+            world_from_local.transform_point(local)
+        };
+        self.box_edges(corner, color);
+    }
+
+    // X, y and z of the local frame in red, green and blue
+    pub(super) fn axes(&mut self, world_from_local: &Matrix4<f32>, size: f32) {
+        let origin = world_from_local.transform_point(Point3::origin());
+        let colors = [
+            [1.0, 0.0, 0.0, 1.0],
+            [0.0, 1.0, 0.0, 1.0],
+            [0.0, 0.0, 1.0, 1.0],
+        ];
+        for (axis, color) in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()]
+            .into_iter()
+            .zip(colors)
+        {
+            let tip = world_from_local.transform_point(Point3::from_vec(axis * size));
+            self.line(origin, tip, color);
+        }
+    }
+
+    // View volume of a raster transform, `clip_from_world` as from Camera::clip_from_world
+    pub(super) fn frustum(&mut self, clip_from_world: &Matrix4<f32>, color: [f32; 4]) {
+        let world_from_clip = clip_from_world
+            .invert()
+            .expect("Frustum transform is not invertible.");
+        let corner = |index: usize| {
+            let clip = Point3::new(
+                if index & 1 == 0 { -1.0 } else { 1.0 },
+                if index & 2 == 0 { -1.0 } else { 1.0 },
+                if index & 4 == 0 { 0.0 } else { 1.0 },
+            );
+            world_from_clip.transform_point(clip)
+        };
+        self.box_edges(corner, color);
+    }
+
+    // The 12 edges between 8 corners indexed by their x, y and z bits
+    fn box_edges(&mut self, corner: impl Fn(usize) -> Point3<f32>, color: [f32; 4]) {
+        for index in 0..8 {
+            for bit in [1, 2, 4] {
+                if index & bit == 0 {
+                    self.line(corner(index), corner(index | bit), color);
+                }
+            }
+        }
+    }
+
+    pub(super) fn record(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        clip_from_world: &Matrix4<f32>,
+    ) {
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        let size = std::mem::size_of_val(self.vertices.as_slice()) as vk::DeviceSize;
+        let fits = match self.vertex_buffer.as_ref() {
+            Some(vertex_buffer) => vertex_buffer.size >= size,
+            None => false,
+        };
+        if !fits {
+            // Grown to the next power of two so a slowly growing overlay does not reallocate
+            // every frame
+            self.vertex_buffer = Some(BufferResource::new(
+                size.next_power_of_two(),
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                self.base.clone(),
+            ));
+        }
+        let vertex_buffer = self.vertex_buffer.as_mut().unwrap();
+        vertex_buffer.store(&self.vertices);
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .build();
+
+        let push_constants = DebugDrawPushConstants {
+            clip_from_world: *clip_from_world,
+        };
+
+        unsafe {
+            self.base.device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            self.base.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            self.base.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[vertex_buffer.buffer],
+                &[0],
+            );
+            self.base.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const DebugDrawPushConstants as *const u8,
+                    std::mem::size_of::<DebugDrawPushConstants>(),
+                ),
+            );
+            self.base
+                .device
+                .cmd_draw(command_buffer, self.vertices.len() as u32, 1, 0, 0);
+            self.base.device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    pub(super) fn release(&mut self) {
+        self.vertex_buffer = None;
+        unsafe {
+            self.base
+                .shader_modules
+                .lock()
+                .unwrap()
+                .destroy_pipeline(self.pipeline);
+            self.base
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.base.device.destroy_framebuffer(self.framebuffer, None);
+            self.base.device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
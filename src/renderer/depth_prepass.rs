@@ -0,0 +1,709 @@
+use std::{ffi::CString, path::Path, sync::Arc};
+
+use super::{
+    base::Renderer,
+    geometry_pool::{GeometryPool, MeshAllocation},
+    occlusion_culling::OcclusionCuller,
+    resources::{BufferResource, ImageResource},
+};
+use crate::{
+    utility,
+    utility::{bounds::Aabb, constants::*, structures::*},
+};
+use cgmath::Matrix4;
+
+use ash::vk;
+
+// Raster pre-pass of the hybrid mode: renders the scene's depth and instance IDs, which the ray
+// generation shader reads in place of tracing primary rays. The draws are occlusion culled in
+// two passes, see OcclusionCuller.
+pub(super) struct DepthPrepass {
+    base: Arc<Renderer>,
+    extent: vk::Extent2D,
+    pub(super) depth_target: ImageResource,
+    pub(super) instance_target: ImageResource,
+    // Clears the targets for the early pass
+    render_pass: vk::RenderPass,
+    // Keeps the early pass' results for the late pass
+    load_render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    // clip_from_object of every instance, read by the vertex shader
+    transform_buffer: Option<BufferResource>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    culler: OcclusionCuller,
+}
+
+impl DepthPrepass {
+    pub(super) fn new(base: Arc<Renderer>, extent: vk::Extent2D) -> Self {
+        DepthPrepass {
+            base: base.clone(),
+            extent,
+            depth_target: ImageResource::new(base.clone()),
+            instance_target: ImageResource::new(base.clone()),
+            render_pass: vk::RenderPass::null(),
+            load_render_pass: vk::RenderPass::null(),
+            framebuffer: vk::Framebuffer::null(),
+            transform_buffer: None,
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            culler: OcclusionCuller::new(base, extent),
+        }
+    }
+
+    pub(super) fn initialize(&mut self) {
+        self.create_targets();
+        self.create_render_pass();
+        self.create_pipeline();
+        self.culler
+            .initialize(self.depth_target.view, self.depth_target.sampler);
+    }
+
+    fn create_targets(&mut self) {
+        let extent = vk::Extent3D::builder()
+            .width(self.extent.width)
+            .height(self.extent.height)
+            .depth(1)
+            .build();
+
+        for (target, format, usage, aspect_mask) in [
+            (
+                &mut self.depth_target,
+                DEPTH_PREPASS_FORMAT,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                vk::ImageAspectFlags::DEPTH,
+            ),
+            (
+                &mut self.instance_target,
+                INSTANCE_ID_FORMAT,
+                // Copied out for rectangle selection
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                vk::ImageAspectFlags::COLOR,
+            ),
+        ] {
+            target.create_image(
+                vk::ImageType::TYPE_2D,
+                format,
+                extent,
+                vk::ImageTiling::OPTIMAL,
+                usage | vk::ImageUsageFlags::SAMPLED,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            );
+            target.create_view(
+                vk::ImageViewType::TYPE_2D,
+                format,
+                vk::ImageSubresourceRange {
+                    aspect_mask,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+            );
+        }
+
+        // Both targets are only read with texelFetch, the sampler is shared between them
+        let sampler_create_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .build();
+        self.depth_target.sampler = unsafe {
+            self.base
+                .device
+                .create_sampler(&sampler_create_info, None)
+                .expect("Failed to create depth pre-pass sampler.")
+        };
+    }
+
+    fn create_render_pass(&mut self) {
+        self.render_pass = self.create_render_pass_with(vk::AttachmentLoadOp::CLEAR);
+        self.load_render_pass = self.create_render_pass_with(vk::AttachmentLoadOp::LOAD);
+
+        unsafe {
+            let framebuffer_attachments = [self.depth_target.view, self.instance_target.view];
+            let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(self.render_pass)
+                .attachments(&framebuffer_attachments)
+                .width(self.extent.width)
+                .height(self.extent.height)
+                .layers(1)
+                .build();
+
+            self.framebuffer = self
+                .base
+                .device
+                .create_framebuffer(&framebuffer_create_info, None)
+                .expect("Failed to create depth pre-pass framebuffer.");
+        }
+    }
+
+    fn create_render_pass_with(&self, load_op: vk::AttachmentLoadOp) -> vk::RenderPass {
+        // Both passes leave the targets in read-only layouts for the depth pyramid and the ray
+        // generation shader, the loading one starts from there
+        let (depth_initial_layout, instance_initial_layout) =
+            if load_op == vk::AttachmentLoadOp::LOAD {
+                (
+                    vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )
+            } else {
+                (vk::ImageLayout::UNDEFINED, vk::ImageLayout::UNDEFINED)
+            };
+        let attachments = [
+            vk::AttachmentDescription::builder()
+                .format(DEPTH_PREPASS_FORMAT)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(load_op)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(depth_initial_layout)
+                .final_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+                .build(),
+            vk::AttachmentDescription::builder()
+                .format(INSTANCE_ID_FORMAT)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(load_op)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(instance_initial_layout)
+                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build(),
+        ];
+
+        let depth_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+        let color_attachment_refs = [vk::AttachmentReference {
+            attachment: 1,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+
+        let subpasses = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .build()];
+
+        let attachment_stages = vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        let attachment_writes = vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            | vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
+        // The shadow map shading reads them in a fragment shader
+        let reading_stages = vk::PipelineStageFlags::RAY_TRACING_SHADER_NV
+            | vk::PipelineStageFlags::COMPUTE_SHADER
+            | vk::PipelineStageFlags::FRAGMENT_SHADER;
+        let dependencies = [
+            // The previous frame's rays and the depth pyramid must be done reading before the
+            // targets are written
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(reading_stages)
+                .dst_stage_mask(attachment_stages)
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(attachment_writes)
+                .build(),
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(attachment_stages)
+                .dst_stage_mask(reading_stages)
+                .src_access_mask(attachment_writes)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build(),
+        ];
+
+        let render_pass_create_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies)
+            .build();
+
+        unsafe {
+            self.base
+                .device
+                .create_render_pass(&render_pass_create_info, None)
+                .expect("Failed to create depth pre-pass render pass.")
+        }
+    }
+
+    fn create_pipeline(&mut self) {
+        unsafe {
+            let bindings = [vk::DescriptorSetLayoutBinding {
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                binding: 0,
+                ..Default::default()
+            }];
+            self.descriptor_set_layout = self
+                .base
+                .device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::builder()
+                        .bindings(&bindings)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create depth pre-pass descriptor set layout.");
+
+            let descriptor_sizes = [vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+            }];
+            self.descriptor_pool = self
+                .base
+                .device
+                .create_descriptor_pool(
+                    &vk::DescriptorPoolCreateInfo::builder()
+                        .pool_sizes(&descriptor_sizes)
+                        .max_sets(1)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create depth pre-pass descriptor pool.");
+
+            let set_layouts = [self.descriptor_set_layout];
+            self.descriptor_set = self
+                .base
+                .device
+                .allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::builder()
+                        .descriptor_pool(self.descriptor_pool)
+                        .set_layouts(&set_layouts)
+                        .build(),
+                )
+                .expect("Failed to allocate depth pre-pass descriptor set.")[0];
+
+            let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&set_layouts)
+                .build();
+
+            self.pipeline_layout = self
+                .base
+                .device
+                .create_pipeline_layout(&layout_create_info, None)
+                .expect("Failed to create depth pre-pass pipeline layout.");
+
+            let mut shader_modules = self.base.shader_modules.lock().unwrap();
+            let mut load_module = |path: &Path| shader_modules.load(path);
+            let vert_module =
+                load_module(Path::new("shaders/compiled/depth.glsl_prepass_vert.spv"));
+            let frag_module =
+                load_module(Path::new("shaders/compiled/depth.glsl_prepass_frag.spv"));
+
+            let main_name = CString::new("main").unwrap();
+            let shader_stages = [
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::VERTEX)
+                    .module(vert_module)
+                    .name(&main_name)
+                    .build(),
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::FRAGMENT)
+                    .module(frag_module)
+                    .name(&main_name)
+                    .build(),
+            ];
+
+            // Same vertex layout as the BLAS geometry
+            let binding_descriptions = [vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: BLAS_VERTEX_FORMAT.stride() as u32,
+                input_rate: vk::VertexInputRate::VERTEX,
+            }];
+            let attribute_descriptions = [vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: BLAS_VERTEX_FORMAT.vk_format(),
+                offset: 0,
+            }];
+            let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+                .vertex_binding_descriptions(&binding_descriptions)
+                .vertex_attribute_descriptions(&attribute_descriptions)
+                .build();
+            let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+                .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                .build();
+
+            let viewports = [vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: self.extent.width as f32,
+                height: self.extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }];
+            let scissors = [vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            }];
+            let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+                .viewports(&viewports)
+                .scissors(&scissors)
+                .build();
+
+            // The instances are two-sided, as with TRIANGLE_CULL_DISABLE in the TLAS
+            let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+                .polygon_mode(vk::PolygonMode::FILL)
+                .cull_mode(vk::CullModeFlags::NONE)
+                .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                .line_width(1.0)
+                .build();
+            let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+                .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                .build();
+            let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+                .depth_test_enable(true)
+                .depth_write_enable(true)
+                .depth_compare_op(vk::CompareOp::LESS)
+                .build();
+            let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(false)
+                .color_write_mask(vk::ColorComponentFlags::R)
+                .build()];
+            let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+                .attachments(&color_blend_attachments)
+                .build();
+
+            let pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
+                .stages(&shader_stages)
+                .vertex_input_state(&vertex_input_state)
+                .input_assembly_state(&input_assembly_state)
+                .viewport_state(&viewport_state)
+                .rasterization_state(&rasterization_state)
+                .multisample_state(&multisample_state)
+                .depth_stencil_state(&depth_stencil_state)
+                .color_blend_state(&color_blend_state)
+                .layout(self.pipeline_layout)
+                .render_pass(self.render_pass)
+                .subpass(0)
+                .build();
+
+            self.pipeline = self
+                .base
+                .device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_create_info], None)
+                .expect("Failed to create depth pre-pass pipeline.")[0];
+
+            shader_modules.attach(self.pipeline, &[vert_module, frag_module]);
+        }
+    }
+
+    // Draws every instance of the single BLAS mesh, `clip_from_objects` and `instance_bounds`
+    // hold one transform and world space box per instance in instance data order
+    pub(super) fn record(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        geometry_pool: &GeometryPool,
+        mesh: &MeshAllocation,
+        clip_from_world: Matrix4<f32>,
+        clip_from_objects: &[Matrix4<f32>],
+        instance_bounds: &[Aabb],
+    ) {
+        let instance_count = clip_from_objects.len() as u32;
+        if instance_count == 0 {
+            return;
+        }
+
+        let size = std::mem::size_of_val(clip_from_objects) as vk::DeviceSize;
+        let fits = match self.transform_buffer.as_ref() {
+            Some(transform_buffer) => transform_buffer.size >= size,
+            None => false,
+        };
+        if !fits {
+            self.transform_buffer = Some(BufferResource::new(
+                size.next_power_of_two(),
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                self.base.clone(),
+            ));
+            let buffer_info = [vk::DescriptorBufferInfo {
+                buffer: self.transform_buffer.as_ref().unwrap().buffer,
+                range: vk::WHOLE_SIZE,
+                ..Default::default()
+            }];
+            let descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(self.descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&buffer_info)
+                .build();
+            unsafe {
+                self.base
+                    .device
+                    .update_descriptor_sets(&[descriptor_write], &[]);
+            }
+        }
+        self.transform_buffer
+            .as_mut()
+            .unwrap()
+            .store(clip_from_objects);
+
+        self.culler.reserve_instances(instance_count);
+        self.culler.upload_bounds(instance_bounds);
+
+        let cull_constants = OcclusionCullPushConstants {
+            clip_from_world,
+            pyramid_size: [0; 2],
+            mip_count: 0,
+            instance_count,
+            phase: 0,
+            index_count: mesh.index_count,
+            first_index: mesh.first_index(),
+            vertex_offset: mesh.base_vertex(geometry_pool.vertex_stride),
+        };
+
+        // Early: last frame's visible set into cleared targets
+        self.culler.record_cull(command_buffer, 0, cull_constants);
+        self.record_draws(command_buffer, geometry_pool, mesh, instance_count, false);
+
+        // Late: whatever the pyramid of the early depths does not hide and was not drawn yet
+        self.culler.record_pyramid(command_buffer);
+        self.culler.record_cull(command_buffer, 1, cull_constants);
+        self.record_draws(command_buffer, geometry_pool, mesh, instance_count, true);
+    }
+
+    fn record_draws(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        geometry_pool: &GeometryPool,
+        mesh: &MeshAllocation,
+        instance_count: u32,
+        load: bool,
+    ) {
+        let clear_values = [
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    uint32: [NO_INSTANCE_ID, 0, 0, 0],
+                },
+            },
+        ];
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(if load {
+                self.load_render_pass
+            } else {
+                self.render_pass
+            })
+            .framebuffer(self.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .clear_values(&clear_values)
+            .build();
+
+        unsafe {
+            self.base.device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            self.base.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            self.base.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            self.base.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[geometry_pool.vertex_buffer(mesh)],
+                &[0],
+            );
+            self.base.device.cmd_bind_index_buffer(
+                command_buffer,
+                geometry_pool.index_buffer(mesh),
+                0,
+                vk::IndexType::UINT16,
+            );
+            // Culled instances are left in with an instance count of 0
+            self.base.device.cmd_draw_indexed_indirect(
+                command_buffer,
+                self.culler.draw_buffer(),
+                0,
+                instance_count,
+                std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32,
+            );
+
+            self.base.device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    // Every instance ID covered by `region` of the last recorded frame, sorted and without
+    // duplicates. The region is in render target pixels and clamped to the target.
+    pub(super) fn read_instance_ids(&self, region: vk::Rect2D) -> Vec<u32> {
+        let x0 = (region.offset.x.max(0) as u32).min(self.extent.width);
+        let y0 = (region.offset.y.max(0) as u32).min(self.extent.height);
+        let x1 = (region.offset.x.max(0) as u32 + region.extent.width).min(self.extent.width);
+        let y1 = (region.offset.y.max(0) as u32 + region.extent.height).min(self.extent.height);
+        if x1 <= x0 || y1 <= y0 {
+            return Vec::new();
+        }
+        let (width, height) = (x1 - x0, y1 - y0);
+
+        let size = (width * height) as vk::DeviceSize * std::mem::size_of::<u32>() as u64;
+        let mut readback_buffer = BufferResource::new(
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        // The render pass leaves the target ready for the ray generation shader, it goes back
+        // there after the copy
+        let barrier = |old_layout, new_layout, src_access_mask, dst_access_mask| {
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(self.instance_target.image)
+                .subresource_range(subresource_range)
+                .build()
+        };
+
+        let _queue = self.base.lock_queue();
+        let command_buffer =
+            utility::general::begin_single_time_command(&self.base.device, self.base.command_pool);
+        unsafe {
+            self.base.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::RAY_TRACING_SHADER_NV,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier(
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    vk::AccessFlags::TRANSFER_READ,
+                )],
+            );
+            self.base.device.cmd_copy_image_to_buffer(
+                command_buffer,
+                self.instance_target.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                readback_buffer.buffer,
+                &[vk::BufferImageCopy {
+                    buffer_offset: 0,
+                    buffer_row_length: 0,
+                    buffer_image_height: 0,
+                    image_subresource: vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: 0,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    },
+                    image_offset: vk::Offset3D {
+                        x: x0 as i32,
+                        y: y0 as i32,
+                        z: 0,
+                    },
+                    image_extent: vk::Extent3D {
+                        width,
+                        height,
+                        depth: 1,
+                    },
+                }],
+            );
+            self.base.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_NV,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier(
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::SHADER_READ,
+                )],
+            );
+        }
+        utility::general::end_single_time_command(
+            &self.base.device,
+            self.base.command_pool,
+            self.base.graphics_queue,
+            command_buffer,
+        );
+
+        let mut instance_ids = unsafe {
+            let mapped = readback_buffer.map(size) as *const u32;
+            let instance_ids =
+                std::slice::from_raw_parts(mapped, (width * height) as usize).to_vec();
+            readback_buffer.unmap();
+            instance_ids
+        };
+        instance_ids.retain(|&instance_id| instance_id != NO_INSTANCE_ID);
+        instance_ids.sort_unstable();
+        instance_ids.dedup();
+        instance_ids
+    }
+
+    pub(super) fn release(&mut self) {
+        unsafe {
+            self.base
+                .shader_modules
+                .lock()
+                .unwrap()
+                .destroy_pipeline(self.pipeline);
+            self.base
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.base
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.base
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.base.device.destroy_framebuffer(self.framebuffer, None);
+            self.base.device.destroy_render_pass(self.render_pass, None);
+            self.base
+                .device
+                .destroy_render_pass(self.load_render_pass, None);
+        }
+        self.transform_buffer = None;
+        self.culler.release();
+    }
+}
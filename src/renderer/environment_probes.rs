@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use super::{
+    base::Renderer,
+    resources::{BufferResource, ImageResource},
+};
+use crate::{
+    utility,
+    utility::{constants::*, environment_probe::CubeMap, hdr_format::HdrFormat},
+};
+use cgmath::{Point3, Vector3, Zero};
+
+use ash::vk;
+
+// Prefiltered cube maps the shadow map shading reflects, captured by capture_environment_probes.
+// The faces are the layers of one 2D array image, six per probe in PROBE_FACES order, and mip
+// level n holds them prefiltered for roughness n / (ENVIRONMENT_PROBE_MIP_LEVELS - 1). Without
+// probes there is a single black one and a count of 0, which shades with the constant ambient
+// light instead.
+pub(super) struct EnvironmentProbes {
+    pub(super) image: ImageResource,
+    // The probe count padded to 16 bytes, then the position of every probe as a vec4
+    pub(super) buffer: BufferResource,
+}
+
+impl EnvironmentProbes {
+    const FORMAT: HdrFormat = HdrFormat::Rgba16f;
+
+    // `probes` holds the position and the prefilter_levels of every probe
+    pub(super) fn new(base: Arc<Renderer>, probes: &[(Point3<f32>, Vec<CubeMap>)]) -> Self {
+        let black: Vec<CubeMap> = (0..ENVIRONMENT_PROBE_MIP_LEVELS)
+            .map(|level| {
+                let size = (ENVIRONMENT_PROBE_SIZE >> level).max(1);
+                CubeMap {
+                    size,
+                    texels: vec![Vector3::zero(); 6 * (size * size) as usize],
+                }
+            })
+            .collect();
+        let maps: Vec<&[CubeMap]> = if probes.is_empty() {
+            vec![&black]
+        } else {
+            probes.iter().map(|(_, levels)| levels.as_slice()).collect()
+        };
+        let layer_count = 6 * maps.len() as u32;
+
+        // Level after level, each with the faces of every probe in layer order
+        let mut texels = Vec::new();
+        let mut copies = Vec::with_capacity(ENVIRONMENT_PROBE_MIP_LEVELS as usize);
+        for level in 0..ENVIRONMENT_PROBE_MIP_LEVELS {
+            let size = maps[0][level as usize].size;
+            copies.push(vk::BufferImageCopy {
+                buffer_offset: texels.len() as vk::DeviceSize,
+                buffer_row_length: 0,
+                buffer_image_height: 0,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: level,
+                    base_array_layer: 0,
+                    layer_count,
+                },
+                image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                image_extent: vk::Extent3D {
+                    width: size,
+                    height: size,
+                    depth: 1,
+                },
+            });
+            for levels in &maps {
+                texels.extend(Self::FORMAT.encode(&levels[level as usize].to_rgba()));
+            }
+        }
+        let mut staging_buffer = BufferResource::new(
+            texels.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            base.clone(),
+        );
+        staging_buffer.store(&texels);
+
+        let mut image = ImageResource::new(base.clone());
+        let create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(Self::FORMAT.vk_format())
+            .extent(vk::Extent3D {
+                width: ENVIRONMENT_PROBE_SIZE,
+                height: ENVIRONMENT_PROBE_SIZE,
+                depth: 1,
+            })
+            .mip_levels(ENVIRONMENT_PROBE_MIP_LEVELS)
+            .array_layers(layer_count)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+        image.allocate_image(&create_info, vk::MemoryPropertyFlags::DEVICE_LOCAL);
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: ENVIRONMENT_PROBE_MIP_LEVELS,
+            base_array_layer: 0,
+            layer_count,
+        };
+        image.create_view(
+            vk::ImageViewType::TYPE_2D_ARRAY,
+            Self::FORMAT.vk_format(),
+            range,
+        );
+        image.set_name("environment probes");
+
+        // Trilinear between the roughness levels, the faces are looked up within their edges
+        let sampler_create_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(ENVIRONMENT_PROBE_MIP_LEVELS as f32)
+            .build();
+        image.sampler = unsafe {
+            base.device
+                .create_sampler(&sampler_create_info, None)
+                .expect("Failed to create environment probe sampler.")
+        };
+
+        let mut buffer = BufferResource::new(
+            16 * (1 + probes.len().max(1)) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            base.clone(),
+        );
+        buffer.store(&[probes.len() as u32, 0, 0, 0]);
+        if !probes.is_empty() {
+            let positions: Vec<[f32; 4]> = probes
+                .iter()
+                .map(|(position, _)| [position.x, position.y, position.z, 1.0])
+                .collect();
+            buffer.store_at(16, &positions);
+        }
+
+        let barrier = |old_layout,
+                       new_layout,
+                       src_stage_mask,
+                       src_access_mask,
+                       dst_stage_mask,
+                       dst_access_mask| {
+            vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(src_stage_mask)
+                .src_access_mask(src_access_mask)
+                .dst_stage_mask(dst_stage_mask)
+                .dst_access_mask(dst_access_mask)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image.image)
+                .subresource_range(range)
+                .build()
+        };
+
+        let _queue = base.lock_queue();
+        let command_buffer =
+            utility::general::begin_single_time_command(&base.device, base.command_pool);
+        unsafe {
+            base.synchronization.pipeline_barrier(
+                command_buffer,
+                &[],
+                &[barrier(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::PipelineStageFlags2::NONE,
+                    vk::AccessFlags2::NONE,
+                    vk::PipelineStageFlags2::TRANSFER,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                )],
+            );
+            base.device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_buffer.buffer,
+                image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &copies,
+            );
+            base.synchronization.pipeline_barrier(
+                command_buffer,
+                &[],
+                &[barrier(
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::PipelineStageFlags2::TRANSFER,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::SHADER_READ,
+                )],
+            );
+        }
+        utility::general::end_single_time_command(
+            &base.device,
+            base.command_pool,
+            base.graphics_queue,
+            command_buffer,
+        );
+
+        EnvironmentProbes { image, buffer }
+    }
+}
@@ -0,0 +1,610 @@
+use std::{path::Path, sync::Arc, time::Instant};
+
+use super::{
+    base::Renderer,
+    pipelines::create_compute_pipeline,
+    resources::{BufferResource, ImageResource},
+};
+use crate::{
+    utility,
+    utility::{
+        color_grading::CubeLut, constants::*, push_constant_diagnostics::PushConstantLayout,
+        structures::*,
+    },
+};
+
+use ash::vk;
+
+pub(super) struct ExposurePass {
+    base: Arc<Renderer>,
+    extent: vk::Extent2D,
+    histogram_buffer: Option<BufferResource>,
+    state_buffer: Option<BufferResource>,
+    pub(super) tonemapped_target: ImageResource,
+    // R16G16B16A16_SFLOAT 3D image of the CubeLut, sampled with its own linear sampler
+    pub(super) lut: ImageResource,
+    lut_domain: ([f32; 3], [f32; 3]),
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    histogram_pipeline: vk::Pipeline,
+    average_pipeline: vk::Pipeline,
+    tonemap_pipeline: vk::Pipeline,
+    adaptation_speed: f32,
+    ev_compensation: f32,
+    tone_curve: ToneCurve,
+    last_frame: Option<Instant>,
+    // Adapts by this many seconds every frame instead of the time since the last one
+    pub(super) fixed_delta_time: Option<f32>,
+}
+
+impl ExposurePass {
+    pub(super) fn new(base: Arc<Renderer>, extent: vk::Extent2D) -> Self {
+        ExposurePass {
+            base: base.clone(),
+            extent,
+            histogram_buffer: None,
+            state_buffer: None,
+            tonemapped_target: ImageResource::new(base.clone()),
+            lut: ImageResource::new(base),
+            lut_domain: ([0.0; 3], [1.0; 3]),
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            histogram_pipeline: vk::Pipeline::null(),
+            average_pipeline: vk::Pipeline::null(),
+            tonemap_pipeline: vk::Pipeline::null(),
+            adaptation_speed: EXPOSURE_ADAPTATION_SPEED,
+            ev_compensation: EXPOSURE_EV_COMPENSATION,
+            tone_curve: RENDER_SETTINGS.tone_curve,
+            last_frame: None,
+            fixed_delta_time: None,
+        }
+    }
+
+    pub(super) fn initialize(&mut self, hdr_view: vk::ImageView, lut: &CubeLut) {
+        self.create_resources();
+        self.create_pipelines();
+        self.create_descriptor_set(hdr_view);
+        self.set_lut(lut);
+    }
+
+    pub(super) fn set_adaptation_speed(&mut self, adaptation_speed: f32) {
+        self.adaptation_speed = adaptation_speed;
+    }
+
+    pub(super) fn set_ev_compensation(&mut self, ev_compensation: f32) {
+        self.ev_compensation = ev_compensation;
+    }
+
+    pub(super) fn set_tone_curve(&mut self, tone_curve: ToneCurve) {
+        self.tone_curve = tone_curve;
+    }
+
+    // Replaces the LUT image and its descriptor, the pass must not be in use
+    pub(super) fn set_lut(&mut self, lut: &CubeLut) {
+        let extent = vk::Extent3D {
+            width: lut.size,
+            height: lut.size,
+            depth: lut.size,
+        };
+        let format = vk::Format::R16G16B16A16_SFLOAT;
+        let data = lut.to_rgba_f16_bytes();
+        let mut staging_buffer = BufferResource::new(
+            data.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        staging_buffer.store(&data);
+
+        let mut image = ImageResource::new(self.base.clone());
+        image.create_image(
+            vk::ImageType::TYPE_3D,
+            format,
+            extent,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        image.create_view(vk::ImageViewType::TYPE_3D, format, range);
+        // Not one of the sampler cache, the texture quality's anisotropy and LOD bias would
+        // distort the lookup
+        let sampler_create_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .build();
+        image.sampler = unsafe {
+            self.base
+                .device
+                .create_sampler(&sampler_create_info, None)
+                .expect("Failed to create the color grading LUT sampler.")
+        };
+        image.set_name("color grading LUT");
+
+        let barrier = |old_layout,
+                       new_layout,
+                       src_stage_mask,
+                       src_access_mask,
+                       dst_stage_mask,
+                       dst_access_mask| {
+            vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(src_stage_mask)
+                .src_access_mask(src_access_mask)
+                .dst_stage_mask(dst_stage_mask)
+                .dst_access_mask(dst_access_mask)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image.image)
+                .subresource_range(range)
+                .build()
+        };
+        let _queue = self.base.lock_queue();
+        let command_buffer =
+            utility::general::begin_single_time_command(&self.base.device, self.base.command_pool);
+        unsafe {
+            self.base.synchronization.pipeline_barrier(
+                command_buffer,
+                &[],
+                &[barrier(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::PipelineStageFlags2::NONE,
+                    vk::AccessFlags2::NONE,
+                    vk::PipelineStageFlags2::TRANSFER,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                )],
+            );
+            self.base.device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_buffer.buffer,
+                image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::BufferImageCopy {
+                    buffer_offset: 0,
+                    buffer_row_length: 0,
+                    buffer_image_height: 0,
+                    image_subresource: vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: 0,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    },
+                    image_offset: vk::Offset3D::default(),
+                    image_extent: extent,
+                }],
+            );
+            self.base.synchronization.pipeline_barrier(
+                command_buffer,
+                &[],
+                &[barrier(
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::PipelineStageFlags2::TRANSFER,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_READ,
+                )],
+            );
+        }
+        utility::general::end_single_time_command(
+            &self.base.device,
+            self.base.command_pool,
+            self.base.graphics_queue,
+            command_buffer,
+        );
+
+        let lut_image_info = [vk::DescriptorImageInfo {
+            sampler: image.sampler,
+            image_view: image.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let descriptor_writes = [vk::WriteDescriptorSet::builder()
+            .dst_set(self.descriptor_set)
+            .dst_binding(4)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&lut_image_info)
+            .build()];
+        unsafe {
+            self.base
+                .device
+                .update_descriptor_sets(&descriptor_writes, &[]);
+        }
+        self.lut = image;
+        self.lut_domain = (lut.domain_min, lut.domain_max);
+    }
+
+    fn create_resources(&mut self) {
+        // Both buffers start zeroed: the average pass clears the histogram after reading it,
+        // and an adapted luminance of 0 makes the first frame adopt the metered value directly
+        let mut histogram_buffer = BufferResource::new(
+            (std::mem::size_of::<u32>() as u32 * EXPOSURE_HISTOGRAM_BINS) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        histogram_buffer.store(&vec![0u32; EXPOSURE_HISTOGRAM_BINS as usize]);
+        self.histogram_buffer = Some(histogram_buffer);
+
+        let mut state_buffer = BufferResource::new(
+            std::mem::size_of::<ExposureState>() as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        state_buffer.store(&[ExposureState::default()]);
+        self.state_buffer = Some(state_buffer);
+
+        self.tonemapped_target.create_image(
+            vk::ImageType::TYPE_2D,
+            self.base.surface_format.format,
+            vk::Extent3D::builder()
+                .width(self.extent.width)
+                .height(self.extent.height)
+                .depth(1)
+                .build(),
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        self.tonemapped_target.create_view(
+            vk::ImageViewType::TYPE_2D,
+            self.base.surface_format.format,
+            vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+        );
+        self.tonemapped_target.set_name("tonemapped target");
+    }
+
+    fn create_pipelines(&mut self) {
+        unsafe {
+            let binding = |binding, descriptor_type| vk::DescriptorSetLayoutBinding {
+                descriptor_count: 1,
+                descriptor_type,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                binding,
+                ..Default::default()
+            };
+
+            let descriptor_set_layout_bindings = [
+                // HDR input
+                binding(0, vk::DescriptorType::STORAGE_IMAGE),
+                // tonemapped output
+                binding(1, vk::DescriptorType::STORAGE_IMAGE),
+                // histogram
+                binding(2, vk::DescriptorType::STORAGE_BUFFER),
+                // adapted luminance and exposure
+                binding(3, vk::DescriptorType::STORAGE_BUFFER),
+                // color grading LUT
+                binding(4, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            ];
+
+            let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&descriptor_set_layout_bindings)
+                .build();
+
+            self.descriptor_set_layout = self
+                .base
+                .device
+                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
+                .expect("Failed to create exposure descriptor set layout.");
+
+            let push_constant_ranges = [vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<ExposurePushConstants>() as u32,
+            }];
+
+            let layouts = [self.descriptor_set_layout];
+            let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&layouts)
+                .push_constant_ranges(&push_constant_ranges)
+                .build();
+
+            self.pipeline_layout = self
+                .base
+                .device
+                .create_pipeline_layout(&layout_create_info, None)
+                .expect("Failed to create exposure pipeline layout.");
+        }
+
+        self.histogram_pipeline = create_compute_pipeline(
+            &self.base,
+            self.pipeline_layout,
+            Path::new("shaders/compiled/exposure.glsl_histogram_comp.spv"),
+            PushConstantLayout::of::<ExposurePushConstants>(),
+        );
+        self.average_pipeline = create_compute_pipeline(
+            &self.base,
+            self.pipeline_layout,
+            Path::new("shaders/compiled/exposure.glsl_average_comp.spv"),
+            PushConstantLayout::of::<ExposurePushConstants>(),
+        );
+        self.tonemap_pipeline = create_compute_pipeline(
+            &self.base,
+            self.pipeline_layout,
+            Path::new("shaders/compiled/exposure.glsl_tonemap_comp.spv"),
+            PushConstantLayout::of::<ExposurePushConstants>(),
+        );
+    }
+
+    fn create_descriptor_set(&mut self, hdr_view: vk::ImageView) {
+        unsafe {
+            let descriptor_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: 2,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 2,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 1,
+                },
+            ];
+
+            let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+                .pool_sizes(&descriptor_sizes)
+                .max_sets(1)
+                .build();
+
+            self.descriptor_pool = self
+                .base
+                .device
+                .create_descriptor_pool(&descriptor_pool_info, None)
+                .expect("Failed to create exposure descriptor pool.");
+
+            let layouts = [self.descriptor_set_layout];
+            let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(&layouts)
+                .build();
+
+            self.descriptor_set = self
+                .base
+                .device
+                .allocate_descriptor_sets(&descriptor_set_allocate_info)
+                .expect("Failed to allocate exposure descriptor set.")[0];
+
+            let hdr_image_info = [vk::DescriptorImageInfo {
+                image_layout: vk::ImageLayout::GENERAL,
+                image_view: hdr_view,
+                ..Default::default()
+            }];
+            let ldr_image_info = [vk::DescriptorImageInfo {
+                image_layout: vk::ImageLayout::GENERAL,
+                image_view: self.tonemapped_target.view,
+                ..Default::default()
+            }];
+            let histogram_info = [vk::DescriptorBufferInfo {
+                buffer: self.histogram_buffer.as_ref().unwrap().buffer,
+                range: vk::WHOLE_SIZE,
+                ..Default::default()
+            }];
+            let state_info = [vk::DescriptorBufferInfo {
+                buffer: self.state_buffer.as_ref().unwrap().buffer,
+                range: vk::WHOLE_SIZE,
+                ..Default::default()
+            }];
+
+            let descriptor_writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(0)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(&hdr_image_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(1)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(&ldr_image_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(2)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&histogram_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(3)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&state_info)
+                    .build(),
+            ];
+
+            self.base
+                .device
+                .update_descriptor_sets(&descriptor_writes, &[]);
+        }
+    }
+
+    // Expects the HDR target to be in GENERAL layout and fully written
+    pub(super) fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        let now = Instant::now();
+        let delta_time = match (self.fixed_delta_time, self.last_frame) {
+            (Some(delta_time), _) => delta_time,
+            (None, last_frame) => {
+                last_frame.map_or(0.0, |last_frame| (now - last_frame).as_secs_f32())
+            }
+        };
+        self.last_frame = Some(now);
+
+        let push_constants = ExposurePushConstants {
+            min_log_luminance: EXPOSURE_MIN_LOG_LUMINANCE,
+            log_luminance_range: EXPOSURE_LOG_LUMINANCE_RANGE,
+            delta_time,
+            adaptation_speed: self.adaptation_speed,
+            ev_compensation: self.ev_compensation,
+            width: self.extent.width,
+            height: self.extent.height,
+            tone_curve: self.tone_curve.index(),
+            lut_domain_min: [
+                self.lut_domain.0[0],
+                self.lut_domain.0[1],
+                self.lut_domain.0[2],
+                0.0,
+            ],
+            lut_domain_max: [
+                self.lut_domain.1[0],
+                self.lut_domain.1[1],
+                self.lut_domain.1[2],
+                0.0,
+            ],
+        };
+
+        let memory_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .build();
+
+        let ldr_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.tonemapped_target.image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .build();
+
+        unsafe {
+            let push_constant_bytes = std::slice::from_raw_parts(
+                &push_constants as *const ExposurePushConstants as *const u8,
+                std::mem::size_of::<ExposurePushConstants>(),
+            );
+
+            self.base.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[ldr_barrier],
+            );
+
+            self.base.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            self.base.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                push_constant_bytes,
+            );
+
+            let passes = [
+                (
+                    self.histogram_pipeline,
+                    self.extent
+                        .width
+                        .div_ceil(EXPOSURE_HISTOGRAM_WORKGROUP_SIZE),
+                    self.extent
+                        .height
+                        .div_ceil(EXPOSURE_HISTOGRAM_WORKGROUP_SIZE),
+                ),
+                (self.average_pipeline, 1, 1),
+                (
+                    self.tonemap_pipeline,
+                    self.extent.width.div_ceil(EXPOSURE_TONEMAP_WORKGROUP_SIZE),
+                    self.extent.height.div_ceil(EXPOSURE_TONEMAP_WORKGROUP_SIZE),
+                ),
+            ];
+            for (i, &(pipeline, group_count_x, group_count_y)) in passes.iter().enumerate() {
+                if i > 0 {
+                    self.base.device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::COMPUTE_SHADER,
+                        vk::PipelineStageFlags::COMPUTE_SHADER,
+                        vk::DependencyFlags::empty(),
+                        &[memory_barrier],
+                        &[],
+                        &[],
+                    );
+                }
+
+                self.base.device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    pipeline,
+                );
+                self.base
+                    .device
+                    .cmd_dispatch(command_buffer, group_count_x, group_count_y, 1);
+            }
+        }
+    }
+
+    pub(super) fn release(&mut self) {
+        unsafe {
+            for &pipeline in [
+                self.histogram_pipeline,
+                self.average_pipeline,
+                self.tonemap_pipeline,
+            ]
+            .iter()
+            {
+                self.base
+                    .shader_modules
+                    .lock()
+                    .unwrap()
+                    .destroy_pipeline(pipeline);
+            }
+            self.base
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.base
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.base
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+
+        self.histogram_buffer = None;
+        self.state_buffer = None;
+    }
+}
@@ -0,0 +1,290 @@
+use std::sync::Arc;
+
+use super::{base::Renderer, resources::BufferResource};
+use crate::{
+    utility,
+    utility::{constants::*, structures::*},
+};
+
+use ash::vk;
+
+// Where a mesh lives in the geometry pool, offsets are in bytes
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct MeshAllocation {
+    block: usize,
+    pub(super) vertex_offset: vk::DeviceSize,
+    pub(super) vertex_count: u32,
+    index_offset: vk::DeviceSize,
+    pub(super) index_count: u32,
+}
+
+impl MeshAllocation {
+    // Draw parameters for cmd_draw_indexed
+    pub(super) fn first_index(&self) -> u32 {
+        (self.index_offset / std::mem::size_of::<u16>() as vk::DeviceSize) as u32
+    }
+
+    pub(super) fn base_vertex(&self, vertex_stride: vk::DeviceSize) -> i32 {
+        (self.vertex_offset / vertex_stride) as i32
+    }
+}
+
+struct GeometryBlock {
+    vertex_buffer: BufferResource,
+    index_buffer: BufferResource,
+    // Allocation is linear, removed meshes leave holes below these until a defragmentation
+    vertex_used: vk::DeviceSize,
+    index_used: vk::DeviceSize,
+    vertex_live: vk::DeviceSize,
+    index_live: vk::DeviceSize,
+}
+
+// Suballocates the vertices and indices of every mesh from a few large DEVICE_LOCAL buffers,
+// so BLAS geometry and draws only differ in their offsets
+pub(super) struct GeometryPool {
+    base: Arc<Renderer>,
+    pub(super) vertex_stride: vk::DeviceSize,
+    blocks: Vec<GeometryBlock>,
+}
+
+impl GeometryPool {
+    pub(super) fn new(base: Arc<Renderer>, vertex_stride: usize) -> Self {
+        GeometryPool {
+            base,
+            vertex_stride: vertex_stride as vk::DeviceSize,
+            blocks: Vec::new(),
+        }
+    }
+
+    // Vertex data must be tightly packed with the pool's stride
+    pub(super) fn add_mesh(&mut self, vertex_data: &[u8], indices: &[u16]) -> MeshAllocation {
+        let vertex_size = vertex_data.len() as vk::DeviceSize;
+        let index_size = std::mem::size_of_val(indices) as vk::DeviceSize;
+        let allocation = self.allocate(vertex_size, index_size);
+
+        let block = &self.blocks[allocation.block];
+        let vertex_buffer = block.vertex_buffer.buffer;
+        let index_buffer = block.index_buffer.buffer;
+        self.upload(vertex_buffer, allocation.vertex_offset, vertex_data);
+        self.upload(index_buffer, allocation.index_offset, indices);
+
+        allocation
+    }
+
+    // The space is only reused once the whole block is empty, or after a defragmentation
+    pub(super) fn remove_mesh(&mut self, allocation: &MeshAllocation) {
+        let block = &mut self.blocks[allocation.block];
+        block.vertex_live -= allocation.vertex_count as vk::DeviceSize * self.vertex_stride;
+        block.index_live -=
+            allocation.index_count as vk::DeviceSize * std::mem::size_of::<u16>() as vk::DeviceSize;
+
+        // An empty block is reused from the start without moving anything
+        if block.vertex_live == 0 && block.index_live == 0 {
+            block.vertex_used = 0;
+            block.index_used = 0;
+        }
+    }
+
+    fn allocate(
+        &mut self,
+        vertex_size: vk::DeviceSize,
+        index_size: vk::DeviceSize,
+    ) -> MeshAllocation {
+        let fits = |block: &GeometryBlock| {
+            block.vertex_used + vertex_size <= block.vertex_buffer.size
+                && block.index_used + index_size <= block.index_buffer.size
+        };
+        let block_index = match self.blocks.iter().position(fits) {
+            Some(block_index) => block_index,
+            None => {
+                self.add_block(vertex_size, index_size);
+                self.blocks.len() - 1
+            }
+        };
+
+        let block = &mut self.blocks[block_index];
+        let allocation = MeshAllocation {
+            block: block_index,
+            vertex_offset: block.vertex_used,
+            vertex_count: (vertex_size / self.vertex_stride) as u32,
+            index_offset: block.index_used,
+            index_count: (index_size / std::mem::size_of::<u16>() as vk::DeviceSize) as u32,
+        };
+        block.vertex_used += vertex_size;
+        block.index_used += index_size;
+        block.vertex_live += vertex_size;
+        block.index_live += index_size;
+
+        allocation
+    }
+
+    pub(super) fn stats(&self) -> GeometryPoolStats {
+        let mut stats = GeometryPoolStats {
+            block_count: self.blocks.len() as u32,
+            ..Default::default()
+        };
+        for block in &self.blocks {
+            stats.capacity += block.vertex_buffer.size + block.index_buffer.size;
+            stats.used += block.vertex_used + block.index_used;
+            stats.live += block.vertex_live + block.index_live;
+        }
+        stats
+    }
+
+    // Packs the given meshes, which must be every live mesh of the pool, into new blocks and
+    // frees the old ones. There is no deferred deletion to keep the old blocks alive for frames
+    // in flight, so the device has to be idle.
+    pub(super) fn defragment(&mut self, meshes: &mut [&mut MeshAllocation]) {
+        let old_blocks = std::mem::take(&mut self.blocks);
+
+        let mut copies = Vec::new();
+        for mesh in meshes.iter_mut() {
+            let vertex_size = mesh.vertex_count as vk::DeviceSize * self.vertex_stride;
+            let index_size =
+                mesh.index_count as vk::DeviceSize * std::mem::size_of::<u16>() as vk::DeviceSize;
+            let moved = self.allocate(vertex_size, index_size);
+
+            let old_block = &old_blocks[mesh.block];
+            let new_block = &self.blocks[moved.block];
+            copies.push((
+                old_block.vertex_buffer.buffer,
+                new_block.vertex_buffer.buffer,
+                vk::BufferCopy {
+                    src_offset: mesh.vertex_offset,
+                    dst_offset: moved.vertex_offset,
+                    size: vertex_size,
+                },
+            ));
+            copies.push((
+                old_block.index_buffer.buffer,
+                new_block.index_buffer.buffer,
+                vk::BufferCopy {
+                    src_offset: mesh.index_offset,
+                    dst_offset: moved.index_offset,
+                    size: index_size,
+                },
+            ));
+            **mesh = moved;
+        }
+
+        let _queue = self.base.lock_queue();
+        let command_buffer =
+            utility::general::begin_single_time_command(&self.base.device, self.base.command_pool);
+        for (src_buffer, dst_buffer, region) in copies {
+            unsafe {
+                self.base
+                    .device
+                    .cmd_copy_buffer(command_buffer, src_buffer, dst_buffer, &[region]);
+            }
+        }
+        // Waits for the copies, after which the old blocks can go
+        utility::general::end_single_time_command(
+            &self.base.device,
+            self.base.command_pool,
+            self.base.graphics_queue,
+            command_buffer,
+        );
+    }
+
+    // Blocks are at least the default size, larger meshes get a block of their own size
+    fn add_block(&mut self, vertex_size: vk::DeviceSize, index_size: vk::DeviceSize) {
+        let vertex_buffer = BufferResource::new(
+            vertex_size.max(GEOMETRY_POOL_VERTEX_BLOCK_SIZE),
+            // Blocks are copied from when the pool is defragmented, and bound as storage buffers
+            // by the stress test
+            vk::BufferUsageFlags::VERTEX_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_SRC
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            self.base.clone(),
+        );
+        let index_buffer = BufferResource::new(
+            index_size.max(GEOMETRY_POOL_INDEX_BLOCK_SIZE),
+            vk::BufferUsageFlags::INDEX_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_SRC
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            self.base.clone(),
+        );
+        let block = self.blocks.len();
+        vertex_buffer.set_name(&format!("geometry pool block {} vertices", block));
+        index_buffer.set_name(&format!("geometry pool block {} indices", block));
+
+        self.blocks.push(GeometryBlock {
+            vertex_buffer,
+            index_buffer,
+            vertex_used: 0,
+            index_used: 0,
+            vertex_live: 0,
+            index_live: 0,
+        });
+    }
+
+    fn upload<T: Copy>(&self, buffer: vk::Buffer, offset: vk::DeviceSize, data: &[T]) {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+        let mut staging_buffer = BufferResource::new(
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        staging_buffer.store(data);
+
+        let _queue = self.base.lock_queue();
+        let command_buffer =
+            utility::general::begin_single_time_command(&self.base.device, self.base.command_pool);
+        unsafe {
+            self.base.device.cmd_copy_buffer(
+                command_buffer,
+                staging_buffer.buffer,
+                buffer,
+                &[vk::BufferCopy {
+                    src_offset: 0,
+                    dst_offset: offset,
+                    size,
+                }],
+            );
+        }
+        utility::general::end_single_time_command(
+            &self.base.device,
+            self.base.command_pool,
+            self.base.graphics_queue,
+            command_buffer,
+        );
+    }
+
+    pub(super) fn vertex_buffer(&self, allocation: &MeshAllocation) -> vk::Buffer {
+        self.blocks[allocation.block].vertex_buffer.buffer
+    }
+
+    pub(super) fn index_buffer(&self, allocation: &MeshAllocation) -> vk::Buffer {
+        self.blocks[allocation.block].index_buffer.buffer
+    }
+
+    // Opaque BLAS geometry of a mesh in the pool
+    pub(super) fn triangle_geometry(&self, allocation: &MeshAllocation) -> vk::GeometryNV {
+        vk::GeometryNV::builder()
+            .geometry_type(vk::GeometryTypeNV::TRIANGLES)
+            .geometry(
+                vk::GeometryDataNV::builder()
+                    .triangles(
+                        vk::GeometryTrianglesNV::builder()
+                            .vertex_data(self.vertex_buffer(allocation))
+                            .vertex_offset(allocation.vertex_offset)
+                            .vertex_count(allocation.vertex_count)
+                            .vertex_stride(self.vertex_stride)
+                            .vertex_format(BLAS_VERTEX_FORMAT.vk_format())
+                            .index_data(self.index_buffer(allocation))
+                            .index_offset(allocation.index_offset)
+                            .index_count(allocation.index_count)
+                            .index_type(vk::IndexType::UINT16)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .flags(vk::GeometryFlagsNV::OPAQUE)
+            .build()
+    }
+}
@@ -0,0 +1,299 @@
+use std::{path::Path, sync::Arc};
+
+use super::{base::Renderer, pipelines::create_compute_pipeline, resources::BufferResource};
+use crate::utility::{constants::*, push_constant_diagnostics::PushConstantLayout, structures::*};
+
+use ash::vk;
+
+// Bins the point lights into LIGHT_TILE_SIZE screen tiles of the depth pre-pass. Every tile gets
+// the lights whose radius reaches the bounds of the surface it shows, for the shadow map shading
+// to sum and the hybrid ray generation shader to pick shadow ray candidates from.
+pub(super) struct LightCuller {
+    base: Arc<Renderer>,
+    extent: vk::Extent2D,
+    // POINT_LIGHT_CAPACITY lights, light_count of them in use
+    light_buffer: Option<BufferResource>,
+    light_count: u32,
+    // Per tile the light count and up to LIGHT_TILE_MAX_LIGHTS light indices
+    tile_buffer: Option<BufferResource>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl LightCuller {
+    pub(super) fn new(base: Arc<Renderer>, extent: vk::Extent2D) -> Self {
+        LightCuller {
+            base,
+            extent,
+            light_buffer: None,
+            light_count: 0,
+            tile_buffer: None,
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        }
+    }
+
+    pub(super) fn initialize(&mut self, depth_view: vk::ImageView, depth_sampler: vk::Sampler) {
+        self.create_buffers();
+        self.create_pipeline();
+        self.create_descriptor_set(depth_view, depth_sampler);
+    }
+
+    fn tile_count(&self) -> (u32, u32) {
+        (
+            self.extent.width.div_ceil(LIGHT_TILE_SIZE),
+            self.extent.height.div_ceil(LIGHT_TILE_SIZE),
+        )
+    }
+
+    pub(super) fn light_buffer(&self) -> vk::Buffer {
+        self.light_buffer.as_ref().unwrap().buffer
+    }
+
+    pub(super) fn tile_buffer(&self) -> vk::Buffer {
+        self.tile_buffer.as_ref().unwrap().buffer
+    }
+
+    // Lights past POINT_LIGHT_CAPACITY are dropped. The GPU must be done with the last frame.
+    pub(super) fn set_lights(&mut self, lights: &[ClusteredLight]) {
+        let lights = &lights[..lights.len().min(POINT_LIGHT_CAPACITY as usize)];
+        self.light_buffer.as_mut().unwrap().store(lights);
+        self.light_count = lights.len() as u32;
+    }
+
+    fn create_buffers(&mut self) {
+        self.light_buffer = Some(BufferResource::new(
+            (std::mem::size_of::<ClusteredLight>() as u32 * POINT_LIGHT_CAPACITY) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        ));
+        let (tiles_x, tiles_y) = self.tile_count();
+        self.tile_buffer = Some(BufferResource::new(
+            (std::mem::size_of::<u32>() as u32 * tiles_x * tiles_y * (LIGHT_TILE_MAX_LIGHTS + 1))
+                as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            self.base.clone(),
+        ));
+    }
+
+    fn create_pipeline(&mut self) {
+        let binding = |binding, descriptor_type| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            ..Default::default()
+        };
+        let bindings = [
+            // depth of the pre-pass
+            binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            // lights
+            binding(1, vk::DescriptorType::STORAGE_BUFFER),
+            // light lists of the tiles
+            binding(2, vk::DescriptorType::STORAGE_BUFFER),
+        ];
+        unsafe {
+            self.descriptor_set_layout = self
+                .base
+                .device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::builder()
+                        .bindings(&bindings)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create light culling descriptor set layout.");
+
+            let push_constant_ranges = [vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<LightCullPushConstants>() as u32,
+            }];
+            let layouts = [self.descriptor_set_layout];
+            self.pipeline_layout = self
+                .base
+                .device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder()
+                        .set_layouts(&layouts)
+                        .push_constant_ranges(&push_constant_ranges)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create light culling pipeline layout.");
+        }
+
+        self.pipeline = create_compute_pipeline(
+            &self.base,
+            self.pipeline_layout,
+            Path::new("shaders/compiled/light.glsl_cull_comp.spv"),
+            PushConstantLayout::of::<LightCullPushConstants>(),
+        );
+    }
+
+    fn create_descriptor_set(&mut self, depth_view: vk::ImageView, depth_sampler: vk::Sampler) {
+        unsafe {
+            let descriptor_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 1,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 2,
+                },
+            ];
+            self.descriptor_pool = self
+                .base
+                .device
+                .create_descriptor_pool(
+                    &vk::DescriptorPoolCreateInfo::builder()
+                        .pool_sizes(&descriptor_sizes)
+                        .max_sets(1)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create light culling descriptor pool.");
+
+            let layouts = [self.descriptor_set_layout];
+            self.descriptor_set = self
+                .base
+                .device
+                .allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::builder()
+                        .descriptor_pool(self.descriptor_pool)
+                        .set_layouts(&layouts)
+                        .build(),
+                )
+                .expect("Failed to allocate light culling descriptor set.")[0];
+
+            let depth_info = [vk::DescriptorImageInfo {
+                sampler: depth_sampler,
+                image_view: depth_view,
+                image_layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            }];
+            let light_info = [vk::DescriptorBufferInfo {
+                buffer: self.light_buffer(),
+                range: vk::WHOLE_SIZE,
+                ..Default::default()
+            }];
+            let tile_info = [vk::DescriptorBufferInfo {
+                buffer: self.tile_buffer(),
+                range: vk::WHOLE_SIZE,
+                ..Default::default()
+            }];
+            let descriptor_writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&depth_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&light_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(2)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&tile_info)
+                    .build(),
+            ];
+            self.base
+                .device
+                .update_descriptor_sets(&descriptor_writes, &[]);
+        }
+    }
+
+    // After the depth pre-pass, before the ray generation or shadow map shading reads the tiles
+    pub(super) fn record(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        camera: CameraPushConstants,
+        depth_range: DepthRangePushConstants,
+    ) {
+        let push_constants = LightCullPushConstants {
+            camera,
+            near: depth_range.near,
+            far: depth_range.far,
+            light_count: self.light_count,
+        };
+        let (tiles_x, tiles_y) = self.tile_count();
+
+        let tile_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .build();
+
+        unsafe {
+            let device = &self.base.device;
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const LightCullPushConstants as *const u8,
+                    std::mem::size_of::<LightCullPushConstants>(),
+                ),
+            );
+            device.cmd_dispatch(command_buffer, tiles_x, tiles_y, 1);
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_NV
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[tile_barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    pub(super) fn release(&mut self) {
+        unsafe {
+            self.base
+                .shader_modules
+                .lock()
+                .unwrap()
+                .destroy_pipeline(self.pipeline);
+            self.base
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.base
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.base
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        self.light_buffer = None;
+        self.tile_buffer = None;
+    }
+}
@@ -0,0 +1,56 @@
+use crate::utility::{bounds::Aabb, constants::*, structures::*};
+
+// A grid of colored point lights in front of the triangles, enough to make
+// per-light sampling noisy without resampling
+pub(super) fn create_restir_lights(light_count: u32) -> Vec<PointLight> {
+    let grid_size = (light_count as f32).sqrt().ceil() as u32;
+
+    (0..light_count)
+        .map(|i| {
+            let x = (i % grid_size) as f32 / (grid_size - 1).max(1) as f32;
+            let y = (i / grid_size) as f32 / (grid_size - 1).max(1) as f32;
+            let [r, g, b] = rainbow_color(i as f32 / light_count as f32);
+            PointLight {
+                position: [x * 4.0 - 2.0, y * 4.0 - 2.0, -1.0, 1.0],
+                color: [r, g, b, 0.05],
+            }
+        })
+        .collect()
+}
+
+// Fully saturated color around the hue circle, `hue` in [0, 1)
+fn rainbow_color(hue: f32) -> [f32; 3] {
+    let hue = hue * 6.0;
+    [
+        ((hue - 3.0).abs() - 1.0).clamp(0.0, 1.0),
+        (2.0 - (hue - 2.0).abs()).clamp(0.0, 1.0),
+        (2.0 - (hue - 4.0).abs()).clamp(0.0, 1.0),
+    ]
+}
+
+// A square grid of `count` point lights in rainbow colors, a little above the bottom of
+// `scene_bounds`
+pub(super) fn create_point_lights(count: u32, scene_bounds: &Aabb) -> Vec<ClusteredLight> {
+    let grid_size = (count as f32).sqrt().ceil().max(1.0) as u32;
+    let size = scene_bounds.max - scene_bounds.min;
+    let spacing = size.x.max(size.z).max(1e-3) / grid_size as f32;
+    let intensity = POINT_LIGHT_IRRADIANCE * spacing * spacing;
+    let radius = (intensity / POINT_LIGHT_CUTOFF).sqrt();
+
+    (0..count)
+        .map(|i| {
+            let x = (i % grid_size) as f32 + 0.5;
+            let z = (i / grid_size) as f32 + 0.5;
+            let [r, g, b] = rainbow_color(i as f32 / count as f32);
+            ClusteredLight {
+                position: [
+                    scene_bounds.min.x + x * size.x / grid_size as f32,
+                    scene_bounds.min.y + 0.25 * size.y + 0.5 * spacing,
+                    scene_bounds.min.z + z * size.z / grid_size as f32,
+                    radius,
+                ],
+                color: [r, g, b, intensity],
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,29 @@
+// Renderer owns the device, the window and its swapchain and rasterizes the model on its own.
+// RtContext traces the scene with it, recording the passes of the other modules around the
+// ray tracing pipeline.
+mod acceleration_structure;
+mod base;
+mod checkerboard;
+mod debug_draw;
+mod depth_prepass;
+mod environment_probes;
+mod exposure;
+mod geometry_pool;
+mod light_culling;
+mod lights;
+mod occlusion_culling;
+mod pipelines;
+mod ray_query;
+mod resources;
+mod restir;
+mod rt_context;
+mod scene;
+mod selection_outline;
+mod shadow_map;
+mod skinning;
+mod split_frame;
+mod texture_atlas;
+
+pub use base::{renderer_console_commands, Renderer, RendererBuilder};
+pub use rt_context::{RtContext, RtContextBuilder};
+pub use scene::scene_triangle;
@@ -0,0 +1,612 @@
+use std::{path::Path, sync::Arc};
+
+use super::{
+    base::Renderer,
+    pipelines::create_compute_pipeline,
+    resources::{BufferResource, ImageResource},
+};
+use crate::{
+    utility,
+    utility::{
+        bounds::Aabb, constants::*, push_constant_diagnostics::PushConstantLayout, structures::*,
+    },
+};
+
+use ash::vk;
+
+// Two-pass hierarchical-Z occlusion culling for the depth pre-pass. The early pass draws what
+// was visible last frame, a pyramid of the farthest depths is built from it, and every instance
+// is tested against the pyramid; the late pass draws the ones the early pass missed.
+pub(super) struct OcclusionCuller {
+    base: Arc<Renderer>,
+    extent: vk::Extent2D,
+    mip_count: u32,
+    depth_pyramid: ImageResource,
+    // Single level views, written by the build pass and read by the next level
+    mip_views: Vec<vk::ImageView>,
+    // Capacity of the per-instance buffers
+    instance_capacity: u32,
+    bounds_buffer: Option<BufferResource>,
+    visibility_buffer: Option<BufferResource>,
+    draw_buffer: Option<BufferResource>,
+    build_set_layout: vk::DescriptorSetLayout,
+    cull_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    // One per pyramid level
+    build_sets: Vec<vk::DescriptorSet>,
+    cull_set: vk::DescriptorSet,
+    build_pipeline_layout: vk::PipelineLayout,
+    cull_pipeline_layout: vk::PipelineLayout,
+    build_pipeline: vk::Pipeline,
+    cull_pipeline: vk::Pipeline,
+}
+
+impl OcclusionCuller {
+    pub(super) fn new(base: Arc<Renderer>, extent: vk::Extent2D) -> Self {
+        OcclusionCuller {
+            base: base.clone(),
+            extent,
+            mip_count: 32 - extent.width.max(extent.height).max(1).leading_zeros(),
+            depth_pyramid: ImageResource::new(base),
+            mip_views: Vec::new(),
+            instance_capacity: 0,
+            bounds_buffer: None,
+            visibility_buffer: None,
+            draw_buffer: None,
+            build_set_layout: vk::DescriptorSetLayout::null(),
+            cull_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            build_sets: Vec::new(),
+            cull_set: vk::DescriptorSet::null(),
+            build_pipeline_layout: vk::PipelineLayout::null(),
+            cull_pipeline_layout: vk::PipelineLayout::null(),
+            build_pipeline: vk::Pipeline::null(),
+            cull_pipeline: vk::Pipeline::null(),
+        }
+    }
+
+    // `depth_view` is the pre-pass depth target, sampled by the first pyramid level
+    pub(super) fn initialize(&mut self, depth_view: vk::ImageView, depth_sampler: vk::Sampler) {
+        self.create_pyramid();
+        self.create_pipelines();
+        self.create_descriptor_sets(depth_view, depth_sampler);
+    }
+
+    fn mip_extent(&self, level: u32) -> vk::Extent2D {
+        vk::Extent2D {
+            width: (self.extent.width >> level).max(1),
+            height: (self.extent.height >> level).max(1),
+        }
+    }
+
+    fn create_pyramid(&mut self) {
+        let (image, memory) = utility::general::create_image(
+            &self.base.device,
+            self.extent.width,
+            self.extent.height,
+            self.mip_count,
+            vk::SampleCountFlags::TYPE_1,
+            DEPTH_PYRAMID_FORMAT,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &self.base.memory_properties,
+        );
+        self.depth_pyramid.image = image;
+        self.depth_pyramid.memory = memory;
+        self.depth_pyramid.size =
+            unsafe { self.base.device.get_image_memory_requirements(image) }.size;
+
+        let mip_range = |base_mip_level, level_count| vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level,
+            level_count,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        self.depth_pyramid.create_view(
+            vk::ImageViewType::TYPE_2D,
+            DEPTH_PYRAMID_FORMAT,
+            mip_range(0, self.mip_count),
+        );
+        self.mip_views = (0..self.mip_count)
+            .map(|level| unsafe {
+                self.base
+                    .device
+                    .create_image_view(
+                        &vk::ImageViewCreateInfo::builder()
+                            .view_type(vk::ImageViewType::TYPE_2D)
+                            .format(DEPTH_PYRAMID_FORMAT)
+                            .subresource_range(mip_range(level, 1))
+                            .image(image)
+                            .build(),
+                        None,
+                    )
+                    .expect("Failed to create depth pyramid level view.")
+            })
+            .collect();
+
+        // Texels are read with texelFetch while building and with textureLod at an explicit
+        // level while culling, never filtered
+        let sampler_create_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(self.mip_count as f32)
+            .build();
+        self.depth_pyramid.sampler = unsafe {
+            self.base
+                .device
+                .create_sampler(&sampler_create_info, None)
+                .expect("Failed to create depth pyramid sampler.")
+        };
+    }
+
+    fn create_pipelines(&mut self) {
+        unsafe {
+            let binding = |binding, descriptor_type| vk::DescriptorSetLayoutBinding {
+                descriptor_count: 1,
+                descriptor_type,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                binding,
+                ..Default::default()
+            };
+
+            let build_bindings = [
+                // level below, or the depth target
+                binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+                // level being written
+                binding(1, vk::DescriptorType::STORAGE_IMAGE),
+            ];
+            let cull_bindings = [
+                // depth pyramid
+                binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+                // instance bounds
+                binding(1, vk::DescriptorType::STORAGE_BUFFER),
+                // visibility of the last frame
+                binding(2, vk::DescriptorType::STORAGE_BUFFER),
+                // indirect draws
+                binding(3, vk::DescriptorType::STORAGE_BUFFER),
+            ];
+
+            for (bindings, set_layout) in [
+                (&build_bindings[..], &mut self.build_set_layout),
+                (&cull_bindings[..], &mut self.cull_set_layout),
+            ] {
+                *set_layout = self
+                    .base
+                    .device
+                    .create_descriptor_set_layout(
+                        &vk::DescriptorSetLayoutCreateInfo::builder()
+                            .bindings(bindings)
+                            .build(),
+                        None,
+                    )
+                    .expect("Failed to create occlusion culling descriptor set layout.");
+            }
+
+            for (set_layout, push_constant_size, pipeline_layout) in [
+                (
+                    self.build_set_layout,
+                    std::mem::size_of::<HizBuildPushConstants>(),
+                    &mut self.build_pipeline_layout,
+                ),
+                (
+                    self.cull_set_layout,
+                    std::mem::size_of::<OcclusionCullPushConstants>(),
+                    &mut self.cull_pipeline_layout,
+                ),
+            ] {
+                let push_constant_ranges = [vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::COMPUTE,
+                    offset: 0,
+                    size: push_constant_size as u32,
+                }];
+                let layouts = [set_layout];
+                *pipeline_layout = self
+                    .base
+                    .device
+                    .create_pipeline_layout(
+                        &vk::PipelineLayoutCreateInfo::builder()
+                            .set_layouts(&layouts)
+                            .push_constant_ranges(&push_constant_ranges)
+                            .build(),
+                        None,
+                    )
+                    .expect("Failed to create occlusion culling pipeline layout.");
+            }
+        }
+
+        self.build_pipeline = create_compute_pipeline(
+            &self.base,
+            self.build_pipeline_layout,
+            Path::new("shaders/compiled/hiz.glsl_build_comp.spv"),
+            PushConstantLayout::of::<HizBuildPushConstants>(),
+        );
+        self.cull_pipeline = create_compute_pipeline(
+            &self.base,
+            self.cull_pipeline_layout,
+            Path::new("shaders/compiled/hiz.glsl_cull_comp.spv"),
+            PushConstantLayout::of::<OcclusionCullPushConstants>(),
+        );
+    }
+
+    fn create_descriptor_sets(&mut self, depth_view: vk::ImageView, depth_sampler: vk::Sampler) {
+        unsafe {
+            let descriptor_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: self.mip_count + 1,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: self.mip_count,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 3,
+                },
+            ];
+            self.descriptor_pool = self
+                .base
+                .device
+                .create_descriptor_pool(
+                    &vk::DescriptorPoolCreateInfo::builder()
+                        .pool_sizes(&descriptor_sizes)
+                        .max_sets(self.mip_count + 1)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create occlusion culling descriptor pool.");
+
+            let mut layouts = vec![self.build_set_layout; self.mip_count as usize];
+            layouts.push(self.cull_set_layout);
+            let mut descriptor_sets = self
+                .base
+                .device
+                .allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::builder()
+                        .descriptor_pool(self.descriptor_pool)
+                        .set_layouts(&layouts)
+                        .build(),
+                )
+                .expect("Failed to allocate occlusion culling descriptor sets.");
+            self.cull_set = descriptor_sets.pop().unwrap();
+            self.build_sets = descriptor_sets;
+
+            // Level 0 reads the depth target in the layout the pre-pass leaves it in, every
+            // other level reads the one below it
+            let source_infos: Vec<[vk::DescriptorImageInfo; 1]> = (0..self.mip_count as usize)
+                .map(|level| {
+                    [if level == 0 {
+                        vk::DescriptorImageInfo {
+                            sampler: depth_sampler,
+                            image_view: depth_view,
+                            image_layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                        }
+                    } else {
+                        vk::DescriptorImageInfo {
+                            sampler: self.depth_pyramid.sampler,
+                            image_view: self.mip_views[level - 1],
+                            image_layout: vk::ImageLayout::GENERAL,
+                        }
+                    }]
+                })
+                .collect();
+            let target_infos: Vec<[vk::DescriptorImageInfo; 1]> = self
+                .mip_views
+                .iter()
+                .map(|&image_view| {
+                    [vk::DescriptorImageInfo {
+                        image_view,
+                        image_layout: vk::ImageLayout::GENERAL,
+                        ..Default::default()
+                    }]
+                })
+                .collect();
+            let pyramid_info = [vk::DescriptorImageInfo {
+                sampler: self.depth_pyramid.sampler,
+                image_view: self.depth_pyramid.view,
+                image_layout: vk::ImageLayout::GENERAL,
+            }];
+
+            let mut descriptor_writes = Vec::new();
+            for (level, &build_set) in self.build_sets.iter().enumerate() {
+                descriptor_writes.push(
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(build_set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&source_infos[level])
+                        .build(),
+                );
+                descriptor_writes.push(
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(build_set)
+                        .dst_binding(1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&target_infos[level])
+                        .build(),
+                );
+            }
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.cull_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&pyramid_info)
+                    .build(),
+            );
+
+            self.base
+                .device
+                .update_descriptor_sets(&descriptor_writes, &[]);
+        }
+    }
+
+    // Grows the per-instance buffers, every instance starts out visible so the first frame
+    // draws everything in the early pass
+    pub(super) fn reserve_instances(&mut self, instance_count: u32) {
+        if instance_count <= self.instance_capacity {
+            return;
+        }
+        self.instance_capacity = instance_count.next_power_of_two();
+        let capacity = self.instance_capacity as usize;
+
+        self.bounds_buffer = Some(BufferResource::new(
+            (std::mem::size_of::<InstanceBounds>() * capacity) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        ));
+        let mut visibility_buffer = BufferResource::new(
+            (std::mem::size_of::<u32>() * capacity) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        visibility_buffer.store(&vec![1u32; capacity]);
+        self.visibility_buffer = Some(visibility_buffer);
+        self.draw_buffer = Some(BufferResource::new(
+            (std::mem::size_of::<vk::DrawIndexedIndirectCommand>() * capacity) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            self.base.clone(),
+        ));
+
+        let buffer_info = |buffer: &Option<BufferResource>| {
+            [vk::DescriptorBufferInfo {
+                buffer: buffer.as_ref().unwrap().buffer,
+                range: vk::WHOLE_SIZE,
+                ..Default::default()
+            }]
+        };
+        let bounds_info = buffer_info(&self.bounds_buffer);
+        let visibility_info = buffer_info(&self.visibility_buffer);
+        let draw_info = buffer_info(&self.draw_buffer);
+        let descriptor_writes =
+            [(1, &bounds_info), (2, &visibility_info), (3, &draw_info)].map(|(binding, info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.cull_set)
+                    .dst_binding(binding)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(info)
+                    .build()
+            });
+        unsafe {
+            self.base
+                .device
+                .update_descriptor_sets(&descriptor_writes, &[]);
+        }
+    }
+
+    pub(super) fn upload_bounds(&mut self, instance_bounds: &[Aabb]) {
+        let bounds: Vec<InstanceBounds> = instance_bounds
+            .iter()
+            .map(|bounds| InstanceBounds {
+                min: [bounds.min.x, bounds.min.y, bounds.min.z, 0.0],
+                max: [bounds.max.x, bounds.max.y, bounds.max.z, 0.0],
+            })
+            .collect();
+        self.bounds_buffer.as_mut().unwrap().store(&bounds);
+    }
+
+    // Writes the draws of one phase, 0 for the early and 1 for the late pass. The late phase
+    // expects the pyramid to be built.
+    pub(super) fn record_cull(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        phase: u32,
+        mut push_constants: OcclusionCullPushConstants,
+    ) {
+        push_constants.phase = phase;
+        push_constants.pyramid_size = [self.extent.width, self.extent.height];
+        push_constants.mip_count = self.mip_count;
+
+        // The previous pass must be done reading the draws and writing the visibility
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .build();
+        let draw_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ)
+            .build();
+
+        unsafe {
+            self.base.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+            self.base.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.cull_pipeline,
+            );
+            self.base.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.cull_pipeline_layout,
+                0,
+                &[self.cull_set],
+                &[],
+            );
+            self.base.device.cmd_push_constants(
+                command_buffer,
+                self.cull_pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const OcclusionCullPushConstants as *const u8,
+                    std::mem::size_of::<OcclusionCullPushConstants>(),
+                ),
+            );
+            self.base.device.cmd_dispatch(
+                command_buffer,
+                push_constants
+                    .instance_count
+                    .div_ceil(OCCLUSION_CULL_WORKGROUP_SIZE),
+                1,
+                1,
+            );
+            self.base.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::DRAW_INDIRECT,
+                vk::DependencyFlags::empty(),
+                &[draw_barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    // Expects the depth target to be written and in DEPTH_STENCIL_READ_ONLY_OPTIMAL
+    pub(super) fn record_pyramid(&self, command_buffer: vk::CommandBuffer) {
+        let pyramid_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_READ)
+            .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.depth_pyramid.image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: self.mip_count,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .build();
+        let level_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .build();
+
+        unsafe {
+            self.base.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[pyramid_barrier],
+            );
+            self.base.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.build_pipeline,
+            );
+
+            for level in 0..self.mip_count {
+                let src_size = if level == 0 {
+                    self.extent
+                } else {
+                    self.mip_extent(level - 1)
+                };
+                let dst_size = self.mip_extent(level);
+                let push_constants = HizBuildPushConstants {
+                    src_size: [src_size.width, src_size.height],
+                    dst_size: [dst_size.width, dst_size.height],
+                };
+
+                self.base.device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.build_pipeline_layout,
+                    0,
+                    &[self.build_sets[level as usize]],
+                    &[],
+                );
+                self.base.device.cmd_push_constants(
+                    command_buffer,
+                    self.build_pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    std::slice::from_raw_parts(
+                        &push_constants as *const HizBuildPushConstants as *const u8,
+                        std::mem::size_of::<HizBuildPushConstants>(),
+                    ),
+                );
+                self.base.device.cmd_dispatch(
+                    command_buffer,
+                    dst_size.width.div_ceil(DEPTH_PYRAMID_WORKGROUP_SIZE),
+                    dst_size.height.div_ceil(DEPTH_PYRAMID_WORKGROUP_SIZE),
+                    1,
+                );
+                self.base.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[level_barrier],
+                    &[],
+                    &[],
+                );
+            }
+        }
+    }
+
+    pub(super) fn draw_buffer(&self) -> vk::Buffer {
+        self.draw_buffer.as_ref().unwrap().buffer
+    }
+
+    pub(super) fn release(&mut self) {
+        unsafe {
+            let mut shader_modules = self.base.shader_modules.lock().unwrap();
+            shader_modules.destroy_pipeline(self.build_pipeline);
+            shader_modules.destroy_pipeline(self.cull_pipeline);
+            self.base
+                .device
+                .destroy_pipeline_layout(self.build_pipeline_layout, None);
+            self.base
+                .device
+                .destroy_pipeline_layout(self.cull_pipeline_layout, None);
+            self.base
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.base
+                .device
+                .destroy_descriptor_set_layout(self.build_set_layout, None);
+            self.base
+                .device
+                .destroy_descriptor_set_layout(self.cull_set_layout, None);
+            for view in self.mip_views.drain(..) {
+                self.base.device.destroy_image_view(view, None);
+            }
+        }
+        self.bounds_buffer = None;
+        self.visibility_buffer = None;
+        self.draw_buffer = None;
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    ffi::{CStr, CString},
+    path::Path,
+    ptr,
+};
+
+use super::base::Renderer;
+use crate::utility::{
+    constants::*,
+    push_constant_diagnostics::{PushConstantDiagnostics, PushConstantLayout},
+    structures::*,
+};
+
+use ash::{extensions::nv, vk};
+
+// Destroy the pipeline through the shader module cache, which holds its module.
+// `push_constants` are the structs pushed into `layout`, which debug builds check the shader
+// against before creating the pipeline
+pub(super) fn create_compute_pipeline(
+    base: &Renderer,
+    layout: vk::PipelineLayout,
+    shader_path: &Path,
+    push_constants: PushConstantLayout,
+) -> vk::Pipeline {
+    let mut shader_modules = base.shader_modules.lock().unwrap();
+    unsafe {
+        let shader_module = shader_modules.load(shader_path);
+        if cfg!(debug_assertions) {
+            let label = shader_path.display().to_string();
+            let mut diagnostics = PushConstantDiagnostics::new(&label, push_constants);
+            diagnostics.add_shader("comp", shader_modules.code(shader_module));
+            if !diagnostics.mismatches().is_empty() {
+                panic!(
+                    "{} does not match the host side push constants.\n{}",
+                    label, diagnostics
+                );
+            }
+        }
+
+        let main_name = CString::new("main").unwrap();
+        let pipeline_create_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::COMPUTE)
+                    .module(shader_module)
+                    .name(&main_name)
+                    .build(),
+            )
+            .layout(layout)
+            .build();
+
+        let pipeline = base
+            .device
+            .create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_create_info], None)
+            .expect("Failed to create compute pipeline.")[0];
+
+        shader_modules.attach(pipeline, &[shader_module]);
+
+        pipeline
+    }
+}
+
+// One stage of a pipeline: which module and which of its entry points
+#[derive(Clone, Copy)]
+pub(super) struct ShaderStageDesc {
+    stage: vk::ShaderStageFlags,
+    module: vk::ShaderModule,
+    entry_point: &'static CStr,
+}
+
+impl ShaderStageDesc {
+    pub(super) fn new(
+        stage: vk::ShaderStageFlags,
+        module: vk::ShaderModule,
+        entry_point: &'static CStr,
+    ) -> Self {
+        ShaderStageDesc {
+            stage,
+            module,
+            entry_point,
+        }
+    }
+
+    // `specialization_info` must outlive the pipeline creation
+    fn create_info(
+        &self,
+        specialization_info: Option<&vk::SpecializationInfo>,
+    ) -> vk::PipelineShaderStageCreateInfo {
+        vk::PipelineShaderStageCreateInfo {
+            stage: self.stage,
+            module: self.module,
+            p_name: self.entry_point.as_ptr(),
+            p_specialization_info: specialization_info.map_or(ptr::null(), |info| info),
+            ..Default::default()
+        }
+    }
+}
+
+// The ray tracing stages in shader group order
+#[derive(Clone, Copy)]
+pub(super) struct RayTracingShaderStages {
+    raygen: ShaderStageDesc,
+    closest_hit: ShaderStageDesc,
+    miss: ShaderStageDesc,
+    shadow_miss: ShaderStageDesc,
+    // Shared by every hit group, with stochastic transparency only
+    pub(super) any_hit: Option<ShaderStageDesc>,
+}
+
+impl RayTracingShaderStages {
+    // The HLSL library holds every stage under its own entry point
+    pub(super) fn from_library(module: vk::ShaderModule) -> Self {
+        RayTracingShaderStages {
+            raygen: ShaderStageDesc::new(vk::ShaderStageFlags::RAYGEN_NV, module, c"rgen_main"),
+            closest_hit: ShaderStageDesc::new(
+                vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                module,
+                c"rchit_main",
+            ),
+            miss: ShaderStageDesc::new(vk::ShaderStageFlags::MISS_NV, module, c"rmiss_main"),
+            shadow_miss: ShaderStageDesc::new(
+                vk::ShaderStageFlags::MISS_NV,
+                module,
+                c"rmiss_shadow_main",
+            ),
+            any_hit: None,
+        }
+    }
+
+    // One module per stage, each with a single "main"
+    pub(super) fn from_modules(
+        raygen: vk::ShaderModule,
+        closest_hit: vk::ShaderModule,
+        miss: vk::ShaderModule,
+        shadow_miss: vk::ShaderModule,
+    ) -> Self {
+        RayTracingShaderStages {
+            raygen: ShaderStageDesc::new(vk::ShaderStageFlags::RAYGEN_NV, raygen, c"main"),
+            closest_hit: ShaderStageDesc::new(
+                vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                closest_hit,
+                c"main",
+            ),
+            miss: ShaderStageDesc::new(vk::ShaderStageFlags::MISS_NV, miss, c"main"),
+            shadow_miss: ShaderStageDesc::new(vk::ShaderStageFlags::MISS_NV, shadow_miss, c"main"),
+            any_hit: None,
+        }
+    }
+
+    // Every stage in the order of the shader groups: raygen, the closest-hit stage once per
+    // HitGroup, miss, shadow miss, then the any-hit stage when there is one
+    fn in_group_order(&self) -> Vec<ShaderStageDesc> {
+        let mut stages = vec![self.raygen];
+        stages.extend(HitGroup::ALL.iter().map(|_| self.closest_hit));
+        stages.push(self.miss);
+        stages.push(self.shadow_miss);
+        stages.extend(self.any_hit);
+        stages
+    }
+}
+
+// Strided records of one kind in the shader binding table, the record of group
+// `first_group + i` at `offset + i * stride`
+pub(super) struct SbtRegion {
+    pub(super) name: &'static str,
+    pub(super) first_group: vk::DeviceSize,
+    pub(super) offset: vk::DeviceSize,
+    pub(super) stride: vk::DeviceSize,
+    pub(super) count: vk::DeviceSize,
+}
+
+// Plain handles only, so a pipeline build can be moved to a worker thread
+#[derive(Clone, Copy)]
+pub(super) struct RayTracingPipelineDesc {
+    pub(super) layout: vk::PipelineLayout,
+    pub(super) cache: vk::PipelineCache,
+    pub(super) stages: RayTracingShaderStages,
+    pub(super) max_recursion_depth: u32,
+    // Whether the closest hit shaders read the normals of the skinning pass
+    pub(super) skinned: bool,
+}
+
+// Groups and stages in the same order: [ raygen ], one [ chit ] per HitGroup, [ miss ],
+// [ shadow miss ]. The shader binding table copies the group handles in this order. The any-hit
+// stage, when there is one, comes last and belongs to every hit group.
+pub(super) fn ray_tracing_shader_groups(
+    any_hit: bool,
+) -> Vec<vk::RayTracingShaderGroupCreateInfoNV> {
+    let general_group = |shader| vk::RayTracingShaderGroupCreateInfoNV {
+        s_type: vk::StructureType::RAY_TRACING_SHADER_GROUP_CREATE_INFO_NV,
+        p_next: ptr::null(),
+        ty: vk::RayTracingShaderGroupTypeNV::GENERAL,
+        general_shader: shader,
+        closest_hit_shader: vk::SHADER_UNUSED_NV,
+        any_hit_shader: vk::SHADER_UNUSED_NV,
+        intersection_shader: vk::SHADER_UNUSED_NV,
+    };
+    let hit_group_count = HitGroup::ALL.len() as u32;
+    let mut shader_groups = vec![general_group(0)];
+    shader_groups.extend(
+        HitGroup::ALL
+            .iter()
+            .map(|group| vk::RayTracingShaderGroupCreateInfoNV {
+                s_type: vk::StructureType::RAY_TRACING_SHADER_GROUP_CREATE_INFO_NV,
+                p_next: ptr::null(),
+                ty: vk::RayTracingShaderGroupTypeNV::TRIANGLES_HIT_GROUP,
+                general_shader: vk::SHADER_UNUSED_NV,
+                closest_hit_shader: 1 + group.index(),
+                any_hit_shader: if any_hit {
+                    3 + hit_group_count
+                } else {
+                    vk::SHADER_UNUSED_NV
+                },
+                intersection_shader: vk::SHADER_UNUSED_NV,
+            }),
+    );
+    shader_groups.push(general_group(1 + hit_group_count));
+    shader_groups.push(general_group(2 + hit_group_count));
+    shader_groups
+}
+
+// Names of the stages in the order create_ray_tracing_pipeline passes them
+pub(super) fn ray_tracing_stage_names(any_hit: bool) -> Vec<String> {
+    let mut names = vec![String::from("rgen")];
+    names.extend(
+        HitGroup::ALL
+            .iter()
+            .map(|group| format!("rchit ({})", group.name())),
+    );
+    names.push(String::from("rmiss"));
+    names.push(String::from("shadow rmiss"));
+    if any_hit {
+        names.push(String::from("rahit"));
+    }
+    names
+}
+
+pub(super) fn create_ray_tracing_pipeline(
+    ray_tracing: &nv::RayTracing,
+    desc: &RayTracingPipelineDesc,
+    flags: vk::PipelineCreateFlags,
+) -> Result<vk::Pipeline, vk::Result> {
+    unsafe {
+        let stages = &desc.stages;
+        let shader_groups = ray_tracing_shader_groups(stages.any_hit.is_some());
+        // The any-hit shader only runs for rays without the opaque flag
+        let ray_flags = |flags: u32| {
+            if stages.any_hit.is_some() {
+                flags & !RAY_FLAG_OPAQUE
+            } else {
+                flags
+            }
+        };
+
+        // Shadow rays only need to know whether anything is hit, so the closest-hit
+        // shader reads its trace flags from a specialization constant. Another one picks the
+        // shading model of each hit group, and a third tells it the scene mesh is skinned.
+        let specialization_entries = [
+            vk::SpecializationMapEntry {
+                constant_id: SHADOW_RAY_FLAGS_CONSTANT_ID,
+                offset: 0,
+                size: std::mem::size_of::<u32>(),
+            },
+            vk::SpecializationMapEntry {
+                constant_id: SHADING_MODEL_CONSTANT_ID,
+                offset: std::mem::size_of::<u32>() as u32,
+                size: std::mem::size_of::<u32>(),
+            },
+            vk::SpecializationMapEntry {
+                constant_id: SKINNED_CONSTANT_ID,
+                offset: 2 * std::mem::size_of::<u32>() as u32,
+                size: std::mem::size_of::<u32>(),
+            },
+        ];
+        let specialization_data: Vec<[u8; 12]> = HitGroup::ALL
+            .iter()
+            .map(|group| {
+                let mut data = [0u8; 12];
+                data[..4].copy_from_slice(&ray_flags(SHADOW_RAY_FLAGS).to_ne_bytes());
+                data[4..8].copy_from_slice(&group.index().to_ne_bytes());
+                data[8..].copy_from_slice(&(desc.skinned as u32).to_ne_bytes());
+                data
+            })
+            .collect();
+        let chit_specialization_infos: Vec<vk::SpecializationInfo> = specialization_data
+            .iter()
+            .map(|data| {
+                vk::SpecializationInfo::builder()
+                    .map_entries(&specialization_entries)
+                    .data(data)
+                    .build()
+            })
+            .collect();
+
+        let rgen_specialization_entries = [vk::SpecializationMapEntry {
+            constant_id: PRIMARY_RAY_FLAGS_CONSTANT_ID,
+            offset: 0,
+            size: std::mem::size_of::<u32>(),
+        }];
+        let rgen_specialization_data = ray_flags(PRIMARY_RAY_FLAGS).to_ne_bytes();
+        let rgen_specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(&rgen_specialization_entries)
+            .data(&rgen_specialization_data)
+            .build();
+
+        let shader_stages: Vec<vk::PipelineShaderStageCreateInfo> = stages
+            .in_group_order()
+            .iter()
+            .enumerate()
+            .map(|(index, stage)| {
+                // The closest-hit stages follow raygen, one per HitGroup
+                let specialization_info = match index {
+                    0 => Some(&rgen_specialization_info),
+                    index => chit_specialization_infos.get(index - 1),
+                };
+                stage.create_info(specialization_info)
+            })
+            .collect();
+
+        let rt_pipeline_create_info = vk::RayTracingPipelineCreateInfoNV {
+            s_type: vk::StructureType::RAY_TRACING_PIPELINE_CREATE_INFO_NV,
+            p_next: ptr::null(),
+            stage_count: shader_stages.len() as u32,
+            p_stages: shader_stages.as_ptr(),
+            group_count: shader_groups.len() as u32,
+            p_groups: shader_groups.as_ptr(),
+            // Primary rays plus one level of shadow rays from the closest-hit shader
+            max_recursion_depth: desc.max_recursion_depth,
+            layout: desc.layout,
+            flags,
+            ..Default::default()
+        };
+
+        ray_tracing
+            .create_ray_tracing_pipelines(desc.cache, &[rt_pipeline_create_info], None)
+            .map(|pipelines| pipelines[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    fn module(raw: u64) -> vk::ShaderModule {
+        vk::ShaderModule::from_raw(raw)
+    }
+
+    // (stage, module, entry point) of every stage in group order
+    fn stage_list(stages: &RayTracingShaderStages) -> Vec<(vk::ShaderStageFlags, u64, &CStr)> {
+        stages
+            .in_group_order()
+            .iter()
+            .map(|stage| (stage.stage, stage.module.as_raw(), stage.entry_point))
+            .collect()
+    }
+
+    #[test]
+    fn library_stages_use_their_entry_points() {
+        let stages = RayTracingShaderStages::from_library(module(7));
+        let mut expected = vec![(vk::ShaderStageFlags::RAYGEN_NV, 7, c"rgen_main")];
+        for _ in HitGroup::ALL {
+            expected.push((vk::ShaderStageFlags::CLOSEST_HIT_NV, 7, c"rchit_main"));
+        }
+        expected.push((vk::ShaderStageFlags::MISS_NV, 7, c"rmiss_main"));
+        expected.push((vk::ShaderStageFlags::MISS_NV, 7, c"rmiss_shadow_main"));
+        assert_eq!(stage_list(&stages), expected);
+    }
+
+    #[test]
+    fn module_stages_keep_their_modules() {
+        let stages =
+            RayTracingShaderStages::from_modules(module(1), module(2), module(3), module(4));
+        let mut expected = vec![(vk::ShaderStageFlags::RAYGEN_NV, 1, c"main")];
+        for _ in HitGroup::ALL {
+            expected.push((vk::ShaderStageFlags::CLOSEST_HIT_NV, 2, c"main"));
+        }
+        expected.push((vk::ShaderStageFlags::MISS_NV, 3, c"main"));
+        expected.push((vk::ShaderStageFlags::MISS_NV, 4, c"main"));
+        assert_eq!(stage_list(&stages), expected);
+    }
+
+    #[test]
+    fn any_hit_stage_comes_last() {
+        for mut stages in [
+            RayTracingShaderStages::from_library(module(7)),
+            RayTracingShaderStages::from_modules(module(1), module(2), module(3), module(4)),
+        ] {
+            stages.any_hit = Some(ShaderStageDesc::new(
+                vk::ShaderStageFlags::ANY_HIT_NV,
+                module(5),
+                c"main",
+            ));
+            let list = stage_list(&stages);
+            assert_eq!(list.len(), 4 + HitGroup::ALL.len());
+            assert_eq!(
+                list.last(),
+                Some(&(vk::ShaderStageFlags::ANY_HIT_NV, 5, c"main"))
+            );
+        }
+    }
+
+    // Every group points at stages of the kind it expects, in the order the SBT copies them
+    #[test]
+    fn groups_index_the_stages() {
+        for any_hit in [false, true] {
+            let mut stages =
+                RayTracingShaderStages::from_modules(module(1), module(2), module(3), module(4));
+            if any_hit {
+                stages.any_hit = Some(ShaderStageDesc::new(
+                    vk::ShaderStageFlags::ANY_HIT_NV,
+                    module(5),
+                    c"main",
+                ));
+            }
+            let list = stage_list(&stages);
+            let groups = ray_tracing_shader_groups(any_hit);
+            assert_eq!(groups.len(), 3 + HitGroup::ALL.len());
+            assert_eq!(ray_tracing_stage_names(any_hit).len(), list.len());
+
+            let stage_of = |index: u32| list[index as usize];
+            assert_eq!(stage_of(groups[0].general_shader).1, 1);
+            for group in &groups[1..=HitGroup::ALL.len()] {
+                assert_eq!(
+                    group.ty,
+                    vk::RayTracingShaderGroupTypeNV::TRIANGLES_HIT_GROUP
+                );
+                assert_eq!(
+                    stage_of(group.closest_hit_shader).0,
+                    vk::ShaderStageFlags::CLOSEST_HIT_NV
+                );
+                if any_hit {
+                    assert_eq!(stage_of(group.any_hit_shader).1, 5);
+                } else {
+                    assert_eq!(group.any_hit_shader, vk::SHADER_UNUSED_NV);
+                }
+            }
+            let misses = &groups[1 + HitGroup::ALL.len()..];
+            assert_eq!(stage_of(misses[0].general_shader).1, 3);
+            assert_eq!(stage_of(misses[1].general_shader).1, 4);
+        }
+    }
+}
@@ -0,0 +1,528 @@
+use std::{path::Path, sync::Arc};
+
+use super::{
+    acceleration_structure::KhrAccelerationStructure, base::Renderer,
+    pipelines::create_compute_pipeline, resources::BufferResource, scene::GeometryInstance,
+};
+use crate::{
+    utility,
+    utility::{
+        camera::Camera, constants::*, descriptor_writes::WriteBatch, logging::TARGET_AS,
+        push_constant_diagnostics::PushConstantLayout, structures::*,
+    },
+};
+
+use ash::{extensions::khr, vk};
+
+// Meters the HDR target with a log-luminance histogram, adapts the exposure over time and
+// tonemaps into an LDR image in the surface format, through a tone curve and a color grading LUT
+// Compute shader backend tracing ray queries against KHR acceleration structures built from the
+// same mesh and instances as the NV ones. The shading matches the standard closest hit shader;
+// the AOVs keep their cleared values and only the start of the shutter interval is traced.
+pub(super) struct RayQueryPass {
+    base: Arc<Renderer>,
+    acceleration_structure: Arc<khr::AccelerationStructure>,
+    // Object space BLAS input, also read by the shader for the hit normals
+    position_buffer: Option<BufferResource>,
+    index_buffer: Option<BufferResource>,
+    bottom_as: Option<KhrAccelerationStructure>,
+    top_as: Option<KhrAccelerationStructure>,
+    instance_buffer: Option<BufferResource>,
+    instance_count: u32,
+    // Kept for rebuilding the TLAS after instances changed
+    top_as_scratch: Option<BufferResource>,
+    top_as_dirty: bool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl RayQueryPass {
+    pub(super) fn new(base: Arc<Renderer>) -> Self {
+        let acceleration_structure = Arc::new(khr::AccelerationStructure::new(
+            &base.instance,
+            &base.device,
+        ));
+
+        RayQueryPass {
+            base,
+            acceleration_structure,
+            position_buffer: None,
+            index_buffer: None,
+            bottom_as: None,
+            top_as: None,
+            instance_buffer: None,
+            instance_count: 0,
+            top_as_scratch: None,
+            top_as_dirty: false,
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        }
+    }
+
+    // The acceleration structures outlive release(), only the pipeline and descriptors depend
+    // on the render target
+    pub(super) fn initialize(
+        &mut self,
+        output_view: vk::ImageView,
+        instance_data_buffer: vk::Buffer,
+    ) {
+        self.create_pipeline();
+        self.create_descriptor_set(output_view, instance_data_buffer);
+    }
+
+    // `instances` are the NV instances of one time sample, KHR instances have the same layout
+    // with the BLAS device address in place of the handle
+    pub(super) fn build_scene(
+        &mut self,
+        positions: &[[f32; 3]],
+        indices: &[u32],
+        instances: &[GeometryInstance],
+    ) {
+        let _span = tracing::info_span!(target: TARGET_AS, "build_ray_query_scene").entered();
+
+        let geometry_usage = vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::STORAGE_BUFFER;
+        let mut position_buffer = BufferResource::new(
+            std::mem::size_of_val(positions) as vk::DeviceSize,
+            geometry_usage,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        position_buffer.store(positions);
+        let mut index_buffer = BufferResource::new(
+            std::mem::size_of_val(indices) as vk::DeviceSize,
+            geometry_usage,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        index_buffer.store(indices);
+
+        let geometries = [vk::AccelerationStructureGeometryKHR::builder()
+            .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+            .geometry(vk::AccelerationStructureGeometryDataKHR {
+                triangles: vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
+                    .vertex_format(vk::Format::R32G32B32_SFLOAT)
+                    .vertex_data(vk::DeviceOrHostAddressConstKHR {
+                        device_address: position_buffer.device_address(),
+                    })
+                    .vertex_stride(std::mem::size_of::<[f32; 3]>() as vk::DeviceSize)
+                    .max_vertex(positions.len() as u32 - 1)
+                    .index_type(vk::IndexType::UINT32)
+                    .index_data(vk::DeviceOrHostAddressConstKHR {
+                        device_address: index_buffer.device_address(),
+                    })
+                    .build(),
+            })
+            .flags(vk::GeometryFlagsKHR::OPAQUE)
+            .build()];
+        let primitive_count = (indices.len() / 3) as u32;
+
+        let mut bottom_build = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+            .geometries(&geometries)
+            .build();
+        let bottom_sizes = unsafe {
+            self.acceleration_structure
+                .get_acceleration_structure_build_sizes(
+                    vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                    &bottom_build,
+                    &[primitive_count],
+                )
+        };
+        let bottom_as = KhrAccelerationStructure::new(
+            self.base.clone(),
+            self.acceleration_structure.clone(),
+            vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL,
+            bottom_sizes.acceleration_structure_size,
+        );
+        let bottom_scratch = BufferResource::new(
+            bottom_sizes.build_scratch_size,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            self.base.clone(),
+        );
+        bottom_build.dst_acceleration_structure = bottom_as.handle;
+        bottom_build.scratch_data = vk::DeviceOrHostAddressKHR {
+            device_address: bottom_scratch.device_address(),
+        };
+
+        self.instance_count = instances.len() as u32;
+        self.instance_buffer = Some(BufferResource::new(
+            std::mem::size_of_val(instances) as vk::DeviceSize,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        ));
+        self.bottom_as = Some(bottom_as);
+        self.update_instances(instances);
+
+        let top_geometries = [self.top_level_geometry()];
+        let top_sizes = unsafe {
+            self.acceleration_structure
+                .get_acceleration_structure_build_sizes(
+                    vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                    &top_level_build_info(&top_geometries),
+                    &[self.instance_count],
+                )
+        };
+        self.top_as = Some(KhrAccelerationStructure::new(
+            self.base.clone(),
+            self.acceleration_structure.clone(),
+            vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+            top_sizes.acceleration_structure_size,
+        ));
+        self.top_as_scratch = Some(BufferResource::new(
+            top_sizes.build_scratch_size,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            self.base.clone(),
+        ));
+
+        let _queue = self.base.lock_queue();
+        let command_buffer =
+            utility::general::begin_single_time_command(&self.base.device, self.base.command_pool);
+        unsafe {
+            self.acceleration_structure
+                .cmd_build_acceleration_structures(
+                    command_buffer,
+                    &[bottom_build],
+                    &[&[vk::AccelerationStructureBuildRangeInfoKHR {
+                        primitive_count,
+                        ..Default::default()
+                    }]],
+                );
+        }
+
+        // The TLAS build reads the BLAS
+        let build_barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR)
+            .src_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR)
+            .dst_stage_mask(vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR)
+            .dst_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_KHR)
+            .build();
+        self.base
+            .synchronization
+            .pipeline_barrier(command_buffer, &[build_barrier], &[]);
+
+        self.record_top_level_build(command_buffer);
+        self.top_as_dirty = false;
+
+        // Waits for the builds, after which the BLAS scratch can go
+        utility::general::end_single_time_command(
+            &self.base.device,
+            self.base.command_pool,
+            self.base.graphics_queue,
+            command_buffer,
+        );
+        tracing::info!(target: TARGET_AS, "Built ray query acceleration structures");
+
+        self.position_buffer = Some(position_buffer);
+        self.index_buffer = Some(index_buffer);
+    }
+
+    // Rewrites every instance, the TLAS is rebuilt at the start of the next record()
+    pub(super) fn update_instances(&mut self, instances: &[GeometryInstance]) {
+        let bottom_as_address = self.bottom_as.as_ref().unwrap().address;
+        let instances: Vec<GeometryInstance> = instances
+            .iter()
+            .map(|instance| GeometryInstance {
+                acceleration_handle: bottom_as_address,
+                ..*instance
+            })
+            .collect();
+        self.instance_buffer.as_mut().unwrap().store(&instances);
+        self.top_as_dirty = true;
+    }
+
+    fn top_level_geometry(&self) -> vk::AccelerationStructureGeometryKHR {
+        vk::AccelerationStructureGeometryKHR::builder()
+            .geometry_type(vk::GeometryTypeKHR::INSTANCES)
+            .geometry(vk::AccelerationStructureGeometryDataKHR {
+                instances: vk::AccelerationStructureGeometryInstancesDataKHR::builder()
+                    .array_of_pointers(false)
+                    .data(vk::DeviceOrHostAddressConstKHR {
+                        device_address: self.instance_buffer.as_ref().unwrap().device_address(),
+                    })
+                    .build(),
+            })
+            .build()
+    }
+
+    // A full build rather than a refit, the TLAS only holds a handful of instances
+    fn record_top_level_build(&self, command_buffer: vk::CommandBuffer) {
+        let geometries = [self.top_level_geometry()];
+        let mut build_info = top_level_build_info(&geometries);
+        build_info.dst_acceleration_structure = self.top_as.as_ref().unwrap().handle;
+        build_info.scratch_data = vk::DeviceOrHostAddressKHR {
+            device_address: self.top_as_scratch.as_ref().unwrap().device_address(),
+        };
+
+        unsafe {
+            self.acceleration_structure
+                .cmd_build_acceleration_structures(
+                    command_buffer,
+                    &[build_info],
+                    &[&[vk::AccelerationStructureBuildRangeInfoKHR {
+                        primitive_count: self.instance_count,
+                        ..Default::default()
+                    }]],
+                );
+        }
+    }
+
+    fn descriptor_set_layout_bindings() -> [vk::DescriptorSetLayoutBinding; 5] {
+        let storage_buffer_binding = |binding| vk::DescriptorSetLayoutBinding {
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            binding,
+            ..Default::default()
+        };
+
+        [
+            vk::DescriptorSetLayoutBinding {
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                binding: 0,
+                ..Default::default()
+            },
+            vk::DescriptorSetLayoutBinding {
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                binding: 1,
+                ..Default::default()
+            },
+            // instance data
+            storage_buffer_binding(2),
+            // positions
+            storage_buffer_binding(3),
+            // indices
+            storage_buffer_binding(4),
+        ]
+    }
+
+    fn create_pipeline(&mut self) {
+        unsafe {
+            let descriptor_set_layout_bindings = Self::descriptor_set_layout_bindings();
+            let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&descriptor_set_layout_bindings)
+                .build();
+
+            self.descriptor_set_layout = self
+                .base
+                .device
+                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
+                .expect("Failed to create ray query descriptor set layout.");
+
+            let push_constant_ranges = [vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<RayQueryPushConstants>() as u32,
+            }];
+
+            let layouts = [self.descriptor_set_layout];
+            let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&layouts)
+                .push_constant_ranges(&push_constant_ranges)
+                .build();
+
+            self.pipeline_layout = self
+                .base
+                .device
+                .create_pipeline_layout(&layout_create_info, None)
+                .expect("Failed to create ray query pipeline layout.");
+        }
+
+        self.pipeline = create_compute_pipeline(
+            &self.base,
+            self.pipeline_layout,
+            Path::new("shaders/compiled/rayquery.glsl_trace_comp.spv"),
+            PushConstantLayout::of::<RayQueryPushConstants>(),
+        );
+    }
+
+    fn create_descriptor_set(
+        &mut self,
+        output_view: vk::ImageView,
+        instance_data_buffer: vk::Buffer,
+    ) {
+        unsafe {
+            let descriptor_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                    descriptor_count: 1,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: 1,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 3,
+                },
+            ];
+
+            let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+                .pool_sizes(&descriptor_sizes)
+                .max_sets(1)
+                .build();
+
+            self.descriptor_pool = self
+                .base
+                .device
+                .create_descriptor_pool(&descriptor_pool_info, None)
+                .expect("Failed to create ray query descriptor pool.");
+
+            let layouts = [self.descriptor_set_layout];
+            let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(&layouts)
+                .build();
+
+            self.descriptor_set = self
+                .base
+                .device
+                .allocate_descriptor_sets(&descriptor_set_allocate_info)
+                .expect("Failed to allocate ray query descriptor set.")[0];
+        }
+
+        WriteBatch::new(&Self::descriptor_set_layout_bindings())
+            .bind_khr_acceleration_structure(0, self.top_as.as_ref().unwrap().handle)
+            .bind_storage_image(1, output_view)
+            .bind_storage_buffer(2, instance_data_buffer)
+            .bind_storage_buffer(3, self.position_buffer.as_ref().unwrap().buffer)
+            .bind_storage_buffer(4, self.index_buffer.as_ref().unwrap().buffer)
+            .update(&self.base.device, self.descriptor_set);
+    }
+
+    // Traces every region `sample_count` times, accumulating into the output image
+    pub(super) fn record(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        regions: &[(Camera, [u32; 4])],
+        sample_count: u32,
+    ) {
+        if self.top_as_dirty {
+            self.record_top_level_build(command_buffer);
+            self.top_as_dirty = false;
+
+            let build_barrier = vk::MemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR)
+                .src_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR)
+                .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                .dst_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_KHR)
+                .build();
+            self.base
+                .synchronization
+                .pipeline_barrier(command_buffer, &[build_barrier], &[]);
+        }
+
+        unsafe {
+            self.base.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            self.base.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+
+            for sample in 0..sample_count {
+                // Viewports do not overlap, so their dispatches need no barriers in between
+                for (camera, [x, y, width, height]) in regions {
+                    let ray_masks = camera.ray_mask_constants();
+                    let push_constants = RayQueryPushConstants {
+                        camera: camera.ray_gen_constants(*width as f32 / *height as f32),
+                        viewport_offset: [*x, *y],
+                        viewport_size: [*width, *height],
+                        accumulation_weight: 1.0 / (sample + 1) as f32,
+                        sample_index: sample,
+                        primary_mask: ray_masks.primary,
+                        secondary_mask: ray_masks.secondary,
+                    };
+                    self.base.device.cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::COMPUTE,
+                        0,
+                        std::slice::from_raw_parts(
+                            &push_constants as *const RayQueryPushConstants as *const u8,
+                            std::mem::size_of::<RayQueryPushConstants>(),
+                        ),
+                    );
+
+                    self.base.device.cmd_dispatch(
+                        command_buffer,
+                        width.div_ceil(RAY_QUERY_WORKGROUP_SIZE),
+                        height.div_ceil(RAY_QUERY_WORKGROUP_SIZE),
+                        1,
+                    );
+                }
+
+                if sample + 1 < sample_count {
+                    // The next sample blends with what this one wrote
+                    let sample_barrier = vk::MemoryBarrier2::builder()
+                        .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                        .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+                        .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                        .dst_access_mask(
+                            vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE,
+                        )
+                        .build();
+                    self.base.synchronization.pipeline_barrier(
+                        command_buffer,
+                        &[sample_barrier],
+                        &[],
+                    );
+                }
+            }
+        }
+    }
+
+    pub(super) fn release(&mut self) {
+        self.base
+            .shader_modules
+            .lock()
+            .unwrap()
+            .destroy_pipeline(self.pipeline);
+        unsafe {
+            self.base
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.base
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.base
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
+
+fn top_level_build_info(
+    geometries: &[vk::AccelerationStructureGeometryKHR],
+) -> vk::AccelerationStructureBuildGeometryInfoKHR {
+    vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+        .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
+        .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+        .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+        .geometries(geometries)
+        .build()
+}
@@ -0,0 +1,349 @@
+use std::{mem::align_of, ptr, sync::Arc};
+
+use super::base::Renderer;
+use crate::utility;
+
+use ash::{util::Align, vk};
+
+#[derive(Clone)]
+pub(super) struct ImageResource {
+    pub(super) image: vk::Image,
+    pub(super) memory: vk::DeviceMemory,
+    pub(super) size: vk::DeviceSize,
+    pub(super) view: vk::ImageView,
+    pub(super) sampler: vk::Sampler,
+    base: Arc<Renderer>,
+}
+
+impl ImageResource {
+    pub(super) fn new(base: Arc<Renderer>) -> Self {
+        ImageResource {
+            image: vk::Image::null(),
+            memory: vk::DeviceMemory::null(),
+            size: 0,
+            view: vk::ImageView::null(),
+            sampler: vk::Sampler::null(),
+            base,
+        }
+    }
+
+    pub(super) fn create_image(
+        &mut self,
+        image_type: vk::ImageType,
+        format: vk::Format,
+        extent: vk::Extent3D,
+        tiling: vk::ImageTiling,
+        usage: vk::ImageUsageFlags,
+        memory_flags: vk::MemoryPropertyFlags,
+    ) {
+        let create_info = vk::ImageCreateInfo::builder()
+            .image_type(image_type)
+            .format(format)
+            .extent(extent)
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(tiling)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+        self.allocate_image(&create_info, memory_flags);
+    }
+
+    // Optimally tiled 2D image with `array_layers` layers
+    pub(super) fn create_image_array(
+        &mut self,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        array_layers: u32,
+        usage: vk::ImageUsageFlags,
+        memory_flags: vk::MemoryPropertyFlags,
+    ) {
+        let create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(array_layers)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+        self.allocate_image(&create_info, memory_flags);
+    }
+
+    pub(super) fn allocate_image(
+        &mut self,
+        create_info: &vk::ImageCreateInfo,
+        memory_flags: vk::MemoryPropertyFlags,
+    ) {
+        unsafe {
+            self.image = self.base.device.create_image(create_info, None).unwrap();
+
+            let requirements = self.base.device.get_image_memory_requirements(self.image);
+            let memory_index = utility::raytracing_aid::find_memorytype_index(
+                &requirements,
+                &self.base.memory_properties,
+                memory_flags,
+            )
+            .expect("Unable to find suitable memory index image.");
+
+            let allocate_info = vk::MemoryAllocateInfo {
+                allocation_size: requirements.size,
+                memory_type_index: memory_index,
+                ..Default::default()
+            };
+
+            self.memory = self
+                .base
+                .device
+                .allocate_memory(&allocate_info, None)
+                .unwrap();
+
+            self.base
+                .device
+                .bind_image_memory(self.image, self.memory, 0)
+                .expect("Unable to bind image memory");
+
+            self.size = requirements.size;
+        }
+    }
+
+    pub(super) fn create_view(
+        &mut self,
+        view_type: vk::ImageViewType,
+        format: vk::Format,
+        range: vk::ImageSubresourceRange,
+    ) {
+        let create_info = vk::ImageViewCreateInfo::builder()
+            .view_type(view_type)
+            .format(format)
+            .subresource_range(range)
+            .image(self.image)
+            .components(vk::ComponentMapping {
+                r: vk::ComponentSwizzle::R,
+                g: vk::ComponentSwizzle::G,
+                b: vk::ComponentSwizzle::B,
+                a: vk::ComponentSwizzle::A,
+            })
+            .build();
+        self.view = unsafe {
+            self.base
+                .device
+                .create_image_view(&create_info, None)
+                .unwrap()
+        };
+    }
+
+    // Names the image, its memory and its view after they were created
+    pub(super) fn set_name(&self, name: &str) {
+        self.base.set_debug_name(self.image, name);
+        self.base
+            .set_debug_name(self.memory, &format!("{} memory", name));
+        if self.view != vk::ImageView::null() {
+            self.base
+                .set_debug_name(self.view, &format!("{} view", name));
+        }
+    }
+}
+
+impl Drop for ImageResource {
+    fn drop(&mut self) {
+        unsafe {
+            self.base.device.destroy_image_view(self.view, None);
+            self.base.device.free_memory(self.memory, None);
+            self.base.device.destroy_image(self.image, None);
+            self.base.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct BufferResource {
+    pub(super) buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    pub(super) size: vk::DeviceSize,
+    base: Arc<Renderer>,
+}
+
+impl BufferResource {
+    pub(super) fn new(
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        memory_properties: vk::MemoryPropertyFlags,
+        base: Arc<Renderer>,
+    ) -> Self {
+        unsafe {
+            let buffer_info = vk::BufferCreateInfo::builder()
+                .size(size)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build();
+
+            let buffer = base.device.create_buffer(&buffer_info, None).unwrap();
+
+            let memory_req = base.device.get_buffer_memory_requirements(buffer);
+
+            let memory_index = utility::raytracing_aid::find_memorytype_index(
+                &memory_req,
+                &base.memory_properties,
+                memory_properties,
+            )
+            .unwrap();
+
+            // Buffers read through their device address need memory allocated for it
+            let mut allocate_flags_info = vk::MemoryAllocateFlagsInfo::builder()
+                .flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS)
+                .build();
+            let mut allocate_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(memory_req.size)
+                .memory_type_index(memory_index);
+            if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+                allocate_info = allocate_info.push_next(&mut allocate_flags_info);
+            }
+
+            let memory = base
+                .device
+                .allocate_memory(&allocate_info.build(), None)
+                .unwrap();
+
+            base.device.bind_buffer_memory(buffer, memory, 0).unwrap();
+
+            BufferResource {
+                buffer,
+                memory,
+                size,
+                base,
+            }
+        }
+    }
+
+    pub(super) fn store<T: Copy>(&mut self, data: &[T]) {
+        self.store_at(0, data);
+    }
+
+    // Only maps and writes the bytes starting at `offset`, the rest of the buffer is untouched
+    pub(super) fn store_at<T: Copy>(&mut self, offset: vk::DeviceSize, data: &[T]) {
+        unsafe {
+            let size = (std::mem::size_of::<T>() * data.len()) as u64;
+            let mapped_ptr = self.map_range(offset, size);
+            let mut mapped_slice = Align::new(mapped_ptr, align_of::<T>() as u64, size);
+            mapped_slice.copy_from_slice(&data);
+            self.unmap();
+        }
+    }
+
+    pub(super) fn map(&mut self, size: vk::DeviceSize) -> *mut std::ffi::c_void {
+        self.map_range(0, size)
+    }
+
+    fn map_range(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) -> *mut std::ffi::c_void {
+        unsafe {
+            let data: *mut std::ffi::c_void = self
+                .base
+                .device
+                .map_memory(self.memory, offset, size, vk::MemoryMapFlags::empty())
+                .unwrap();
+            data
+        }
+    }
+
+    pub(super) fn unmap(&mut self) {
+        unsafe {
+            self.base.device.unmap_memory(self.memory);
+        }
+    }
+
+    // Only for buffers created with SHADER_DEVICE_ADDRESS usage
+    pub(super) fn device_address(&self) -> vk::DeviceAddress {
+        unsafe {
+            self.base.device.get_buffer_device_address(
+                &vk::BufferDeviceAddressInfo::builder()
+                    .buffer(self.buffer)
+                    .build(),
+            )
+        }
+    }
+
+    // Shown in validation messages and graphics debuggers, along with its memory
+    pub(super) fn set_name(&self, name: &str) {
+        self.base.set_debug_name(self.buffer, name);
+        self.base
+            .set_debug_name(self.memory, &format!("{} memory", name));
+    }
+}
+
+impl Drop for BufferResource {
+    fn drop(&mut self) {
+        unsafe {
+            self.base.device.destroy_buffer(self.buffer, None);
+            self.base.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+// Host coherent array that stays mapped for its whole life, so single elements can be written
+// between frames without mapping the memory again
+pub(super) struct MappedBuffer<T: Copy> {
+    resource: BufferResource,
+    mapped: *mut T,
+    len: usize,
+}
+
+// The mapping is only written through &mut self
+unsafe impl<T: Copy> Send for MappedBuffer<T> {}
+unsafe impl<T: Copy> Sync for MappedBuffer<T> {}
+
+impl<T: Copy> MappedBuffer<T> {
+    pub(super) fn new(data: &[T], usage: vk::BufferUsageFlags, base: Arc<Renderer>) -> Self {
+        let mut resource = BufferResource::new(
+            std::mem::size_of_val(data) as vk::DeviceSize,
+            usage,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            base,
+        );
+        let mapped = resource.map(resource.size) as *mut T;
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), mapped, data.len());
+        }
+        MappedBuffer {
+            resource,
+            mapped,
+            len: data.len(),
+        }
+    }
+
+    pub(super) fn buffer(&self) -> vk::Buffer {
+        self.resource.buffer
+    }
+
+    pub(super) fn set_name(&self, name: &str) {
+        self.resource.set_name(name);
+    }
+
+    // The GPU must not be reading the buffer, frames are waited for after their submit
+    pub(super) fn write(&mut self, index: usize, value: T) {
+        assert!(index < self.len, "Mapped buffer index out of range.");
+        unsafe {
+            self.mapped.add(index).write(value);
+        }
+    }
+
+    pub(super) fn read(&self, index: usize) -> T {
+        assert!(index < self.len, "Mapped buffer index out of range.");
+        unsafe { self.mapped.add(index).read() }
+    }
+}
+
+impl<T: Copy> Drop for MappedBuffer<T> {
+    fn drop(&mut self) {
+        self.resource.unmap();
+    }
+}
@@ -0,0 +1,378 @@
+use std::{path::Path, sync::Arc};
+
+use super::{base::Renderer, pipelines::create_compute_pipeline, resources::BufferResource};
+use crate::utility::{constants::*, push_constant_diagnostics::PushConstantLayout, structures::*};
+use cgmath::Vector3;
+
+use ash::vk;
+
+#[derive(Clone)]
+pub(super) struct RestirPass {
+    base: Arc<Renderer>,
+    extent: vk::Extent2D,
+    pub(super) lights: Vec<PointLight>,
+    light_buffer: Option<BufferResource>,
+    pub(super) gbuffer: Option<BufferResource>,
+    // [0] = reservoirs of the current frame, [1] = spatially resampled reservoirs that are
+    // shaded and then reused temporally by the next frame
+    reservoir_buffers: Vec<BufferResource>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    initial_pipeline: vk::Pipeline,
+    temporal_pipeline: vk::Pipeline,
+    spatial_pipeline: vk::Pipeline,
+    shade_pipeline: vk::Pipeline,
+    frame_index: u32,
+}
+
+impl RestirPass {
+    pub(super) fn new(base: Arc<Renderer>, extent: vk::Extent2D, lights: Vec<PointLight>) -> Self {
+        RestirPass {
+            base,
+            extent,
+            lights,
+            light_buffer: None,
+            gbuffer: None,
+            reservoir_buffers: Vec::new(),
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            initial_pipeline: vk::Pipeline::null(),
+            temporal_pipeline: vk::Pipeline::null(),
+            spatial_pipeline: vk::Pipeline::null(),
+            shade_pipeline: vk::Pipeline::null(),
+            frame_index: 0,
+        }
+    }
+
+    pub(super) fn initialize(&mut self, output_view: vk::ImageView) {
+        self.create_buffers();
+        self.create_pipelines();
+        self.create_descriptor_set(output_view);
+    }
+
+    fn create_buffers(&mut self) {
+        let pixel_count = (self.extent.width * self.extent.height) as vk::DeviceSize;
+
+        let mut light_buffer = BufferResource::new(
+            (std::mem::size_of::<PointLight>() * self.lights.len()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        light_buffer.store(&self.lights);
+        self.light_buffer = Some(light_buffer);
+
+        self.gbuffer = Some(BufferResource::new(
+            std::mem::size_of::<GBufferSample>() as vk::DeviceSize * pixel_count,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            self.base.clone(),
+        ));
+
+        self.reservoir_buffers = (0..2)
+            .map(|_| {
+                BufferResource::new(
+                    std::mem::size_of::<Reservoir>() as vk::DeviceSize * pixel_count,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                    self.base.clone(),
+                )
+            })
+            .collect();
+    }
+
+    fn create_pipelines(&mut self) {
+        unsafe {
+            let storage_buffer_binding = |binding| vk::DescriptorSetLayoutBinding {
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                binding,
+                ..Default::default()
+            };
+
+            let descriptor_set_layout_bindings = [
+                // lights
+                storage_buffer_binding(0),
+                // G-buffer
+                storage_buffer_binding(1),
+                // current reservoirs
+                storage_buffer_binding(2),
+                // previous reservoirs
+                storage_buffer_binding(3),
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                    stage_flags: vk::ShaderStageFlags::COMPUTE,
+                    binding: 4,
+                    ..Default::default()
+                },
+            ];
+
+            let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&descriptor_set_layout_bindings)
+                .build();
+
+            self.descriptor_set_layout = self
+                .base
+                .device
+                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
+                .expect("Failed to create ReSTIR descriptor set layout.");
+
+            let push_constant_ranges = [vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<RestirPushConstants>() as u32,
+            }];
+
+            let layouts = [self.descriptor_set_layout];
+            let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&layouts)
+                .push_constant_ranges(&push_constant_ranges)
+                .build();
+
+            self.pipeline_layout = self
+                .base
+                .device
+                .create_pipeline_layout(&layout_create_info, None)
+                .expect("Failed to create ReSTIR pipeline layout.");
+        }
+
+        self.initial_pipeline = create_compute_pipeline(
+            &self.base,
+            self.pipeline_layout,
+            Path::new("shaders/compiled/restir.glsl_initial_comp.spv"),
+            PushConstantLayout::of::<RestirPushConstants>(),
+        );
+        self.temporal_pipeline = create_compute_pipeline(
+            &self.base,
+            self.pipeline_layout,
+            Path::new("shaders/compiled/restir.glsl_temporal_comp.spv"),
+            PushConstantLayout::of::<RestirPushConstants>(),
+        );
+        self.spatial_pipeline = create_compute_pipeline(
+            &self.base,
+            self.pipeline_layout,
+            Path::new("shaders/compiled/restir.glsl_spatial_comp.spv"),
+            PushConstantLayout::of::<RestirPushConstants>(),
+        );
+        self.shade_pipeline = create_compute_pipeline(
+            &self.base,
+            self.pipeline_layout,
+            Path::new("shaders/compiled/restir.glsl_shade_comp.spv"),
+            PushConstantLayout::of::<RestirPushConstants>(),
+        );
+    }
+
+    fn create_descriptor_set(&mut self, output_view: vk::ImageView) {
+        unsafe {
+            let descriptor_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 4,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: 1,
+                },
+            ];
+
+            let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+                .pool_sizes(&descriptor_sizes)
+                .max_sets(1)
+                .build();
+
+            self.descriptor_pool = self
+                .base
+                .device
+                .create_descriptor_pool(&descriptor_pool_info, None)
+                .expect("Failed to create ReSTIR descriptor pool.");
+
+            let layouts = [self.descriptor_set_layout];
+            let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(&layouts)
+                .build();
+
+            self.descriptor_set = self
+                .base
+                .device
+                .allocate_descriptor_sets(&descriptor_set_allocate_info)
+                .expect("Failed to allocate ReSTIR descriptor set.")[0];
+
+            let buffers = [
+                self.light_buffer.as_ref().unwrap().buffer,
+                self.gbuffer.as_ref().unwrap().buffer,
+                self.reservoir_buffers[0].buffer,
+                self.reservoir_buffers[1].buffer,
+            ];
+            let buffer_infos: Vec<[vk::DescriptorBufferInfo; 1]> = buffers
+                .iter()
+                .map(|&buffer| {
+                    [vk::DescriptorBufferInfo {
+                        buffer,
+                        range: vk::WHOLE_SIZE,
+                        ..Default::default()
+                    }]
+                })
+                .collect();
+
+            let mut descriptor_writes: Vec<vk::WriteDescriptorSet> = buffer_infos
+                .iter()
+                .enumerate()
+                .map(|(binding, buffer_info)| {
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(self.descriptor_set)
+                        .dst_binding(binding as u32)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .buffer_info(buffer_info)
+                        .build()
+                })
+                .collect();
+
+            let image_info = [vk::DescriptorImageInfo {
+                image_layout: vk::ImageLayout::GENERAL,
+                image_view: output_view,
+                ..Default::default()
+            }];
+
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(4)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(&image_info)
+                    .build(),
+            );
+
+            self.base
+                .device
+                .update_descriptor_sets(&descriptor_writes, &[]);
+        }
+    }
+
+    // Expects the G-buffer of this frame to be written by the ray generation shader
+    pub(super) fn record(&mut self, command_buffer: vk::CommandBuffer) {
+        let push_constants = RestirPushConstants {
+            frame_index: self.frame_index,
+            light_count: self.lights.len() as u32,
+            initial_candidates: RESTIR_INITIAL_CANDIDATES,
+            max_history: RESTIR_MAX_HISTORY,
+            spatial_neighbors: RESTIR_SPATIAL_NEIGHBORS,
+            spatial_radius: RESTIR_SPATIAL_RADIUS,
+            width: self.extent.width,
+            height: self.extent.height,
+        };
+
+        let group_count_x = self.extent.width.div_ceil(RESTIR_WORKGROUP_SIZE);
+        let group_count_y = self.extent.height.div_ceil(RESTIR_WORKGROUP_SIZE);
+
+        // Every pass reads what the previous one wrote
+        let memory_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .build();
+
+        unsafe {
+            let push_constant_bytes = std::slice::from_raw_parts(
+                &push_constants as *const RestirPushConstants as *const u8,
+                std::mem::size_of::<RestirPushConstants>(),
+            );
+
+            self.base.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            self.base.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                push_constant_bytes,
+            );
+
+            let passes = [
+                self.initial_pipeline,
+                self.temporal_pipeline,
+                self.spatial_pipeline,
+                self.shade_pipeline,
+            ];
+            for (i, &pipeline) in passes.iter().enumerate() {
+                if i > 0 {
+                    self.base.device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::COMPUTE_SHADER,
+                        vk::PipelineStageFlags::COMPUTE_SHADER,
+                        vk::DependencyFlags::empty(),
+                        &[memory_barrier],
+                        &[],
+                        &[],
+                    );
+                }
+
+                self.base.device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    pipeline,
+                );
+                self.base
+                    .device
+                    .cmd_dispatch(command_buffer, group_count_x, group_count_y, 1);
+            }
+        }
+
+        self.frame_index += 1;
+    }
+
+    // Keeps the lights in place when the render origin moves by `-shift`
+    pub(super) fn translate_lights(&mut self, shift: Vector3<f32>) {
+        for light in &mut self.lights {
+            light.position[0] += shift.x;
+            light.position[1] += shift.y;
+            light.position[2] += shift.z;
+        }
+        self.light_buffer.as_mut().unwrap().store(&self.lights);
+    }
+
+    pub(super) fn release(&mut self) {
+        unsafe {
+            for &pipeline in [
+                self.initial_pipeline,
+                self.temporal_pipeline,
+                self.spatial_pipeline,
+                self.shade_pipeline,
+            ]
+            .iter()
+            {
+                self.base
+                    .shader_modules
+                    .lock()
+                    .unwrap()
+                    .destroy_pipeline(pipeline);
+            }
+            self.base
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.base
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.base
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+
+        self.light_buffer = None;
+        self.gbuffer = None;
+        self.reservoir_buffers.clear();
+    }
+}
@@ -0,0 +1,813 @@
+use super::RtContext;
+use crate::renderer::{checkerboard::CheckerboardPass, scene::GeometryInstance};
+use crate::utility::{
+    bounds::Aabb,
+    camera::{Camera, Projection},
+    constants::*,
+    structures::*,
+};
+use cgmath::{EuclideanSpace, Matrix4, Point3, SquareMatrix, Vector3};
+use smallvec::SmallVec;
+
+use ash::vk;
+
+impl RtContext {
+    pub fn trace_frame(&mut self) {
+        let _frame_span = tracing::debug_span!("frame").entered();
+
+        self.defragment_geometry_pool();
+        self.follow_camera_with_origin();
+        let pipeline_ready = if self.deterministic {
+            self.wait_for_pipeline();
+            self.pipeline != vk::Pipeline::null()
+        } else {
+            self.poll_pipeline()
+        };
+
+        unsafe {
+            let record_span = tracing::debug_span!("record").entered();
+
+            // The command buffer, TLAS and timestamps of the slot were last used by the frame
+            // before the one in flight
+            let frame_slot = self.frame_slot;
+            self.frame_contexts[frame_slot].wait(&self.base.device);
+            if self.writes_frame_inputs(pipeline_ready) {
+                self.finish_traced_frames();
+            }
+
+            // Cloned so the guard does not borrow self while recording
+            let base = self.base.clone();
+            let _queue = base.lock_queue();
+            let command_buffer = self.frame_contexts[frame_slot].begin(&self.base.device);
+
+            let first_query = 2 * frame_slot as u32;
+            if self.timestamp_query_pool != vk::QueryPool::null() {
+                self.base.device.cmd_reset_query_pool(
+                    command_buffer,
+                    self.timestamp_query_pool,
+                    first_query,
+                    2,
+                );
+                self.base.device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    self.timestamp_query_pool,
+                    first_query,
+                );
+            }
+            self.profiler.begin_frame(command_buffer);
+
+            // Ahead of the barrier below, so it overlaps the frame before still tracing the
+            // other TLAS. Both TLASes reference the BLAS of a skinned scene mesh, so they are
+            // refitted after the barrier and the deform.
+            let refits_early = self.skinning.is_none();
+            if refits_early
+                && pipeline_ready
+                && self.traces_scene_tlas()
+                && self.upload_dirty_instances()
+            {
+                self.record_tlas_refit(command_buffer, 0, false);
+            }
+
+            // Everything after waits for the frame before, which may still be writing the
+            // targets or reading the buffers this frame is about to write. The checkerboard
+            // reconstruction reads the pixels it traced.
+            let keeps_history = self
+                .checkerboard
+                .as_ref()
+                .is_some_and(CheckerboardPass::has_history);
+            let frame_barrier = vk::MemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
+                .build();
+            let image_barrier = vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV
+                        | vk::PipelineStageFlags2::COMPUTE_SHADER
+                        | vk::PipelineStageFlags2::TRANSFER,
+                )
+                .dst_access_mask(vk::AccessFlags2::SHADER_WRITE | vk::AccessFlags2::TRANSFER_WRITE)
+                .old_layout(if keeps_history {
+                    vk::ImageLayout::GENERAL
+                } else {
+                    vk::ImageLayout::UNDEFINED
+                })
+                .new_layout(vk::ImageLayout::GENERAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(self.offscreen_target.image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .build();
+
+            self.base.synchronization.pipeline_barrier(
+                command_buffer,
+                &[frame_barrier],
+                &[image_barrier],
+            );
+
+            if !refits_early {
+                self.record_skinning(command_buffer);
+                if pipeline_ready && self.traces_scene_tlas() && self.upload_dirty_instances() {
+                    self.record_tlas_refit(command_buffer, 0, false);
+                }
+            }
+
+            self.record_aov_clear(command_buffer);
+
+            if pipeline_ready {
+                self.record_trace_commands(command_buffer);
+            } else {
+                self.base.device.cmd_clear_color_image(
+                    command_buffer,
+                    self.offscreen_target.image,
+                    vk::ImageLayout::GENERAL,
+                    &vk::ClearColorValue {
+                        float32: PLACEHOLDER_COLOR,
+                    },
+                    &[vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    }],
+                );
+            }
+
+            // Device 0 goes on alone with the whole frame
+            let command_buffer = match self.split_frame.as_ref() {
+                Some(split_frame) => {
+                    split_frame.gather(command_buffer, self.offscreen_target.image)
+                }
+                None => command_buffer,
+            };
+
+            self.record_debug_draw(command_buffer);
+
+            if let Some(exposure) = self.exposure.as_mut() {
+                let hdr_barrier = vk::MemoryBarrier2::builder()
+                    .src_stage_mask(
+                        vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV
+                            | vk::PipelineStageFlags2::COMPUTE_SHADER
+                            | vk::PipelineStageFlags2::TRANSFER,
+                    )
+                    .src_access_mask(
+                        vk::AccessFlags2::SHADER_WRITE | vk::AccessFlags2::TRANSFER_WRITE,
+                    )
+                    .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                    .dst_access_mask(vk::AccessFlags2::SHADER_READ)
+                    .build();
+
+                self.base
+                    .synchronization
+                    .pipeline_barrier(command_buffer, &[hdr_barrier], &[]);
+
+                self.profiler.begin_pass(command_buffer, "exposure");
+                exposure.record(command_buffer);
+                self.profiler.end_pass(command_buffer);
+            }
+            if pipeline_ready {
+                self.record_selection_outline(command_buffer);
+            }
+            if self.debug_pixel.is_some() {
+                // dump_debug_buffer reads the records once the frame is done
+                let debug_barrier = vk::MemoryBarrier2::builder()
+                    .src_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV)
+                    .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+                    .dst_access_mask(vk::AccessFlags2::HOST_READ)
+                    .build();
+                self.base
+                    .synchronization
+                    .pipeline_barrier(command_buffer, &[debug_barrier], &[]);
+            }
+            self.profiler.end_frame(command_buffer);
+
+            if self.timestamp_query_pool != vk::QueryPool::null() {
+                self.base.device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    self.timestamp_query_pool,
+                    first_query + 1,
+                );
+            }
+
+            self.base.device.end_command_buffer(command_buffer).unwrap();
+            record_span.exit();
+
+            let _submit_span = tracing::debug_span!("submit").entered();
+            let frame_context = &mut self.frame_contexts[frame_slot];
+            self.base
+                .device
+                .reset_fences(&[frame_context.fence])
+                .expect("Failed to reset the frame fence.");
+            self.base
+                .synchronization
+                .submit(
+                    self.base.graphics_queue,
+                    &[command_buffer],
+                    frame_context.fence,
+                )
+                .expect("queue submit failed.");
+            frame_context.submitted = true;
+
+            // What is read back on the host needs the frame done, everything else is left in
+            // flight
+            if self.debug_pixel.is_some() || self.profiler.has_backend() {
+                self.frame_contexts[frame_slot].wait(&self.base.device);
+                self.profiler.resolve();
+                self.dump_debug_buffer();
+            }
+        }
+
+        self.frame_slot = (self.frame_slot + 1) % self.frame_contexts.len();
+        self.update_dynamic_resolution();
+    }
+
+    // Whether the frame writes buffers on the host that the frame before may still read, in
+    // which case it has to finish first. Only the scene TLAS is double buffered.
+    fn writes_frame_inputs(&self, pipeline_ready: bool) -> bool {
+        self.depth_prepass.is_some()
+            || self.ray_query.is_some()
+            || self.debug_draw.is_some()
+            || (self.settings.shadow_map && self.shadow_map.is_some())
+            || (pipeline_ready && !self.selection.is_empty())
+    }
+
+    // Whether record_trace_commands traces the scene TLAS rather than a pass of its own
+    fn traces_scene_tlas(&self) -> bool {
+        self.ray_query.is_none() && !(self.settings.shadow_map && self.shadow_map.is_some())
+    }
+
+    // Refits the TLAS of this frame's slot to the instances of a motion blur time sample. After
+    // a trace of this frame it first waits for the trace to be done with the TLAS.
+    fn record_tlas_refit(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        time_sample: u32,
+        after_trace: bool,
+    ) {
+        let slot = &self.tlas_slots[self.frame_slot];
+        let instance_set_size = (std::mem::size_of::<GeometryInstance>() as u32
+            * self.instance_count) as vk::DeviceSize;
+        unsafe {
+            if after_trace {
+                let trace_barrier = vk::MemoryBarrier2::builder()
+                    .src_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV)
+                    .src_access_mask(vk::AccessFlags2::NONE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_NV)
+                    .dst_access_mask(vk::AccessFlags2::NONE)
+                    .build();
+                self.base
+                    .synchronization
+                    .pipeline_barrier(command_buffer, &[trace_barrier], &[]);
+            }
+
+            self.ray_tracing.cmd_build_acceleration_structure(
+                command_buffer,
+                &vk::AccelerationStructureInfoNV::builder()
+                    .ty(vk::AccelerationStructureTypeNV::TOP_LEVEL)
+                    .flags(vk::BuildAccelerationStructureFlagsNV::ALLOW_UPDATE)
+                    .instance_count(self.instance_count)
+                    .build(),
+                slot.instance_buffer.buffer,
+                time_sample as vk::DeviceSize * instance_set_size,
+                true,
+                slot.top_as.handle,
+                slot.top_as.handle,
+                slot.update_scratch.buffer,
+                0,
+            );
+
+            let refit_barrier = vk::MemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_NV)
+                .src_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_NV)
+                .dst_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV)
+                .dst_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_NV)
+                .build();
+            self.base
+                .synchronization
+                .pipeline_barrier(command_buffer, &[refit_barrier], &[]);
+        }
+    }
+
+    // Resets the AOVs to their background values, misses leave them untouched
+    fn record_aov_clear(&self, command_buffer: vk::CommandBuffer) {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let layout_barriers: SmallVec<[vk::ImageMemoryBarrier; 8]> = self
+            .aov_targets
+            .iter()
+            .map(|target| {
+                vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::empty())
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::GENERAL)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(target.image)
+                    .subresource_range(subresource_range)
+                    .build()
+            })
+            .collect();
+        let clear_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .build();
+
+        unsafe {
+            // The previous frame's readback may still be copying out of them
+            self.base.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::RAY_TRACING_SHADER_NV,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &layout_barriers,
+            );
+            for (aov, target) in Aov::ALL.iter().zip(&self.aov_targets) {
+                self.base.device.cmd_clear_color_image(
+                    command_buffer,
+                    target.image,
+                    vk::ImageLayout::GENERAL,
+                    &vk::ClearColorValue {
+                        float32: aov.background(),
+                    },
+                    &[subresource_range],
+                );
+            }
+            self.base.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_NV,
+                vk::DependencyFlags::empty(),
+                &[clear_barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    // Queues this frame's overlay: BLAS bounds in instance space, the TLAS instance boxes, the
+    // lights and the frozen camera frustum
+    fn record_debug_draw(&mut self, command_buffer: vk::CommandBuffer) {
+        let mut debug_draw = match self.debug_draw.take() {
+            Some(debug_draw) => debug_draw,
+            None => return,
+        };
+        debug_draw.clear();
+
+        let mesh_bounds = self.scene_mesh_bounds;
+        for transform in &self.scene_transforms {
+            let world_from_object = Matrix4::from(*transform);
+            debug_draw.transformed_box(
+                &world_from_object,
+                mesh_bounds.min,
+                mesh_bounds.max,
+                DEBUG_DRAW_BLAS_COLOR,
+            );
+
+            // The box the TLAS sees is axis aligned around the transformed BLAS bounds
+            let instance_bounds = mesh_bounds.transformed(&world_from_object);
+            debug_draw.aabb(
+                instance_bounds.min,
+                instance_bounds.max,
+                DEBUG_DRAW_INSTANCE_COLOR,
+            );
+            debug_draw.axes(&world_from_object, DEBUG_DRAW_LIGHT_SIZE);
+        }
+
+        // Emitters at their centroids and the ReSTIR point lights, in their normalized color
+        let light_color = |rgb: [f32; 3]| {
+            let peak = rgb[0].max(rgb[1]).max(rgb[2]).max(1e-6);
+            [rgb[0] / peak, rgb[1] / peak, rgb[2] / peak, 1.0]
+        };
+        for triangle in &self.emissive_triangles {
+            let centroid = Point3::new(
+                (triangle.v0[0] + triangle.v1[0] + triangle.v2[0]) / 3.0,
+                (triangle.v0[1] + triangle.v1[1] + triangle.v2[1]) / 3.0,
+                (triangle.v0[2] + triangle.v1[2] + triangle.v2[2]) / 3.0,
+            );
+            let [r, g, b, _] = triangle.emission;
+            debug_draw.point(centroid, DEBUG_DRAW_LIGHT_SIZE, light_color([r, g, b]));
+        }
+        if let Some(restir) = self.restir.as_ref() {
+            for light in &restir.lights {
+                let [x, y, z, _] = light.position;
+                let [r, g, b, _] = light.color;
+                debug_draw.point(
+                    Point3::new(x, y, z),
+                    DEBUG_DRAW_LIGHT_SIZE,
+                    light_color([r, g, b]),
+                );
+            }
+        }
+
+        debug_draw.frustum(&self.debug_frustum, DEBUG_DRAW_FRUSTUM_COLOR);
+        debug_draw.axes(&Matrix4::identity(), 1.0);
+
+        self.profiler.begin_pass(command_buffer, "debug_draw");
+        debug_draw.record(command_buffer, &self.clip_from_world());
+        self.profiler.end_pass(command_buffer);
+        self.debug_draw = Some(debug_draw);
+    }
+
+    pub(super) fn clip_from_world(&self) -> Matrix4<f32> {
+        let extent = self.render_extent();
+        let aspect_ratio = extent.width as f32 / extent.height as f32;
+        let depth_range = self.depth_range();
+        self.camera
+            .clip_from_world(aspect_ratio, depth_range.near, depth_range.far)
+    }
+
+    fn depth_range(&self) -> DepthRangePushConstants {
+        self.depth_range.resolve(&self.camera, &self.scene_bounds)
+    }
+
+    // Orthographic view along the light direction, framed on the scene like the camera and
+    // widened by SHADOW_MAP_COVERAGE
+    fn light_from_world(&self) -> Matrix4<f32> {
+        let radius = self.scene_bounds.radius();
+        let mut light = Camera {
+            position: Point3::from_vec(Vector3::from(self.sun.direction())),
+            target: Point3::origin(),
+            projection: Projection::Orthographic { height: 1.0 },
+            ..Camera::default()
+        };
+        light.frame_scene(&self.scene_bounds);
+        light.projection = Projection::Orthographic {
+            height: 2.0 * radius * SHADOW_MAP_COVERAGE,
+        };
+        // Framing puts the light twice the radius away from the center
+        light.clip_from_world(1.0, 0.0, 4.0 * radius)
+    }
+
+    // Raster transforms and world space boxes of every instance in instance data order, for the
+    // raster pre-passes and their occlusion culling. In the lists of the frame context, which
+    // take them back with give_back_raster_inputs.
+    pub(super) fn raster_inputs(&mut self) -> (Vec<Matrix4<f32>>, Vec<Aabb>) {
+        let clip_from_world = self.clip_from_world();
+        let mut clip_from_objects = self.frame_contexts[self.frame_slot].matrices.take();
+        let mut instance_bounds = self.frame_contexts[self.frame_slot].bounds.take();
+        for transform in &self.scene_transforms {
+            let world_from_object = Matrix4::from(*transform);
+            clip_from_objects.push(clip_from_world * world_from_object);
+            instance_bounds.push(self.scene_mesh_bounds.transformed(&world_from_object));
+        }
+        (clip_from_objects, instance_bounds)
+    }
+
+    pub(super) fn give_back_raster_inputs(
+        &mut self,
+        clip_from_objects: Vec<Matrix4<f32>>,
+        bounds: Vec<Aabb>,
+    ) {
+        self.frame_contexts[self.frame_slot]
+            .matrices
+            .give_back(clip_from_objects);
+        self.frame_contexts[self.frame_slot]
+            .bounds
+            .give_back(bounds);
+    }
+
+    fn record_trace_commands(&mut self, command_buffer: vk::CommandBuffer) {
+        if self.ray_query.is_some() {
+            self.record_ray_query_commands(command_buffer);
+            return;
+        }
+
+        let extent = self.render_extent();
+
+        if self.depth_prepass.is_some() {
+            let clip_from_world = self.clip_from_world();
+            let (clip_from_objects, instance_bounds) = self.raster_inputs();
+            self.profiler.begin_pass(command_buffer, "depth_prepass");
+            self.depth_prepass.as_mut().unwrap().record(
+                command_buffer,
+                self.geometry_pool.as_ref().unwrap(),
+                &self.scene_mesh,
+                clip_from_world,
+                &clip_from_objects,
+                &instance_bounds,
+            );
+            self.profiler.end_pass(command_buffer);
+            self.give_back_raster_inputs(clip_from_objects, instance_bounds);
+        }
+
+        if let Some(light_culler) = self.light_culler.as_ref() {
+            let camera = self
+                .camera
+                .ray_gen_constants(extent.width as f32 / extent.height as f32);
+            self.profiler.begin_pass(command_buffer, "light_cull");
+            light_culler.record(command_buffer, camera, self.depth_range());
+            self.profiler.end_pass(command_buffer);
+        }
+
+        if self.settings.shadow_map && self.shadow_map.is_some() {
+            self.record_shadow_map_commands(command_buffer);
+            return;
+        }
+
+        unsafe {
+            self.profiler.begin_pass(command_buffer, "trace_rays");
+            self.base.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::RAY_TRACING_NV,
+                self.pipeline,
+            );
+            self.base.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::RAY_TRACING_NV,
+                self.pipeline_layout,
+                SCENE_DESCRIPTOR_SET,
+                &[
+                    self.tlas_slots[self.frame_slot].scene_descriptor_set,
+                    self.frame_descriptor_set,
+                ],
+                &[],
+            );
+
+            // Shader binding table layout: [ raygen | chit | miss | shadow miss ]
+            let [raygen_region, miss_region, hit_region] = self.shader_binding_table_regions();
+            let sbt_buffer = self.shader_binding_table.as_ref().unwrap().buffer;
+
+            // Every pixel sample is split over the motion blur time samples; ReSTIR resolves
+            // its own samples and only traces the G-buffer once, and the hybrid mode's primary
+            // hits come from a single rasterized sample
+            let pixel_samples = if self.restir.is_some() || self.depth_prepass.is_some() {
+                1
+            } else {
+                self.settings.samples_per_pixel.max(1)
+            };
+            let regions = self.device_regions(self.viewport_regions(extent));
+            let checkerboard = self
+                .checkerboard
+                .as_ref()
+                .map_or(0, CheckerboardPass::push_constant);
+            let ray_bias = self.settings.ray_bias_constants();
+            let sample_count = pixel_samples * self.motion_time_samples;
+            for sample in 0..sample_count {
+                // trace_frame refitted to the first time sample, without motion blur only when
+                // instances moved
+                let time_sample = sample % self.motion_time_samples;
+                if self.motion_time_samples > 1 && sample > 0 {
+                    self.record_tlas_refit(command_buffer, time_sample, true);
+                }
+
+                // The ray generation shader keeps a running average of the samples
+                let accumulation = AccumulationPushConstants {
+                    weight: 1.0 / (sample + 1) as f32,
+                    sample_index: sample,
+                };
+                self.base.device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::RAYGEN_NV | vk::ShaderStageFlags::ANY_HIT_NV,
+                    std::mem::size_of::<CameraPushConstants>() as u32,
+                    std::slice::from_raw_parts(
+                        &accumulation as *const AccumulationPushConstants as *const u8,
+                        std::mem::size_of::<AccumulationPushConstants>(),
+                    ),
+                );
+
+                // Viewports do not overlap, so their dispatches need no barriers in between
+                for (index, (device_mask, camera, [x, y, width, height])) in
+                    regions.iter().enumerate()
+                {
+                    if self.deterministic && index > 0 {
+                        let dispatch_barrier = vk::MemoryBarrier2::builder()
+                            .src_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV)
+                            .dst_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV)
+                            .build();
+                        self.base.synchronization.pipeline_barrier(
+                            command_buffer,
+                            &[dispatch_barrier],
+                            &[],
+                        );
+                    }
+                    if let Some(device_mask) = device_mask {
+                        self.base
+                            .device
+                            .cmd_set_device_mask(command_buffer, *device_mask);
+                    }
+                    let camera_constants = camera.ray_gen_constants(*width as f32 / *height as f32);
+                    self.base.device.cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::RAYGEN_NV,
+                        0,
+                        std::slice::from_raw_parts(
+                            &camera_constants as *const CameraPushConstants as *const u8,
+                            std::mem::size_of::<CameraPushConstants>(),
+                        ),
+                    );
+
+                    // Primary rays start and end on the planes the raster passes use, the hybrid
+                    // mode reconstructs its hits with them
+                    let depth_range = self.depth_range.resolve(camera, &self.scene_bounds);
+                    self.base.device.cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::RAYGEN_NV,
+                        (std::mem::size_of::<CameraPushConstants>()
+                            + std::mem::size_of::<AccumulationPushConstants>())
+                            as u32,
+                        std::slice::from_raw_parts(
+                            &depth_range as *const DepthRangePushConstants as *const u8,
+                            std::mem::size_of::<DepthRangePushConstants>(),
+                        ),
+                    );
+
+                    let ray_masks = camera.ray_mask_constants();
+                    self.base.device.cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::RAYGEN_NV | vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                        RAY_MASK_PUSH_CONSTANT_OFFSET,
+                        std::slice::from_raw_parts(
+                            &ray_masks as *const RayMaskPushConstants as *const u8,
+                            std::mem::size_of::<RayMaskPushConstants>(),
+                        ),
+                    );
+
+                    let viewport = ViewportPushConstants {
+                        offset: [*x, *y],
+                        size: [*width, *height],
+                        checkerboard,
+                    };
+                    self.base.device.cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::RAYGEN_NV | vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                        VIEWPORT_PUSH_CONSTANT_OFFSET,
+                        std::slice::from_raw_parts(
+                            &viewport as *const ViewportPushConstants as *const u8,
+                            std::mem::size_of::<ViewportPushConstants>(),
+                        ),
+                    );
+
+                    self.base.device.cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::RAYGEN_NV | vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                        RAY_BIAS_PUSH_CONSTANT_OFFSET,
+                        std::slice::from_raw_parts(
+                            &ray_bias as *const RayBiasPushConstants as *const u8,
+                            std::mem::size_of::<RayBiasPushConstants>(),
+                        ),
+                    );
+
+                    self.ray_tracing.cmd_trace_rays(
+                        command_buffer,
+                        sbt_buffer,
+                        raygen_region.offset,
+                        sbt_buffer,
+                        miss_region.offset,
+                        miss_region.stride,
+                        sbt_buffer,
+                        hit_region.offset,
+                        hit_region.stride,
+                        vk::Buffer::null(),
+                        0,
+                        0,
+                        if checkerboard != 0 {
+                            width.div_ceil(2)
+                        } else {
+                            *width
+                        },
+                        *height,
+                        1,
+                    );
+                }
+                // Refits run on every device
+                if regions
+                    .iter()
+                    .any(|(device_mask, ..)| device_mask.is_some())
+                {
+                    self.base
+                        .device
+                        .cmd_set_device_mask(command_buffer, (1 << self.base.device_count) - 1);
+                }
+
+                if sample + 1 < sample_count {
+                    // The next refit must wait for these rays, and the next trace reads the
+                    // image they wrote
+                    let refit_wait_barrier = vk::MemoryBarrier2::builder()
+                        .src_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV)
+                        .src_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_NV)
+                        .dst_stage_mask(vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_NV)
+                        .dst_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_NV)
+                        .build();
+                    let sample_barrier = vk::MemoryBarrier2::builder()
+                        .src_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV)
+                        .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+                        .dst_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV)
+                        .dst_access_mask(
+                            vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE,
+                        )
+                        .build();
+
+                    self.base.synchronization.pipeline_barrier(
+                        command_buffer,
+                        &[refit_wait_barrier, sample_barrier],
+                        &[],
+                    );
+                }
+            }
+            self.profiler.end_pass(command_buffer);
+
+            if let Some(checkerboard) = self.checkerboard.as_mut() {
+                self.profiler.begin_pass(command_buffer, "checkerboard");
+                checkerboard.record(command_buffer);
+                self.profiler.end_pass(command_buffer);
+            }
+
+            if let Some(restir) = self.restir.as_mut() {
+                let gbuffer_barrier = vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .build();
+
+                self.base.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::RAY_TRACING_SHADER_NV,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[gbuffer_barrier],
+                    &[],
+                    &[],
+                );
+
+                self.profiler.begin_pass(command_buffer, "restir");
+                restir.record(command_buffer);
+                self.profiler.end_pass(command_buffer);
+            }
+        }
+    }
+
+    // Moved instances are refitted into the TLAS by the next traced frame
+    fn record_shadow_map_commands(&mut self, command_buffer: vk::CommandBuffer) {
+        let extent = self.render_extent();
+        let camera = self
+            .camera
+            .ray_gen_constants(extent.width as f32 / extent.height as f32);
+        let depth_range = self.depth_range();
+        let light_from_world = self.light_from_world();
+        let mut world_from_objects = self.frame_contexts[self.frame_slot].matrices.take();
+        world_from_objects.extend(
+            self.scene_transforms
+                .iter()
+                .map(|transform| Matrix4::from(*transform)),
+        );
+
+        if let Some(shadow_map) = self.shadow_map.as_mut() {
+            self.profiler.begin_pass(command_buffer, "shadow_map");
+            shadow_map.record(
+                command_buffer,
+                self.geometry_pool.as_ref().unwrap(),
+                &self.scene_mesh,
+                light_from_world,
+                &world_from_objects,
+                camera,
+                depth_range,
+            );
+            self.profiler.end_pass(command_buffer);
+        }
+        self.frame_contexts[self.frame_slot]
+            .matrices
+            .give_back(world_from_objects);
+    }
+
+    fn record_ray_query_commands(&mut self, command_buffer: vk::CommandBuffer) {
+        let extent = self.render_extent();
+        let instances_moved = self.upload_dirty_instances();
+        let regions = self.viewport_regions(extent);
+        let sample_count = self.settings.samples_per_pixel.max(1);
+
+        if let Some(ray_query) = self.ray_query.as_mut() {
+            if instances_moved {
+                ray_query.update_instances(&self.instances[..self.instance_count as usize]);
+            }
+            self.profiler.begin_pass(command_buffer, "ray_query");
+            ray_query.record(command_buffer, &regions, sample_count);
+            self.profiler.end_pass(command_buffer);
+        }
+    }
+}
@@ -0,0 +1,1497 @@
+use std::{path::PathBuf, sync::Arc, time::Instant};
+
+use super::{
+    acceleration_structure::{AccelerationStructure, AsBuilder},
+    base::Renderer,
+    checkerboard::CheckerboardPass,
+    debug_draw::DebugDraw,
+    depth_prepass::DepthPrepass,
+    environment_probes::EnvironmentProbes,
+    exposure::ExposurePass,
+    geometry_pool::{GeometryPool, MeshAllocation},
+    light_culling::LightCuller,
+    lights::{create_point_lights, create_restir_lights},
+    pipelines::RayTracingPipelineDesc,
+    ray_query::RayQueryPass,
+    resources::{BufferResource, ImageResource, MappedBuffer},
+    restir::RestirPass,
+    scene::{ground_plane_instance, motion_sample_transform, scene_triangle, GeometryInstance},
+    selection_outline::SelectionOutlinePass,
+    shadow_map::ShadowMapPass,
+    skinning::SkinningPass,
+    split_frame::SplitFrame,
+    texture_atlas::TextureAtlas,
+};
+use crate::{
+    utility,
+    utility::{
+        bounds::Aabb,
+        camera::{Camera, DepthRange, Viewport},
+        color_grading::CubeLut,
+        constants::*,
+        denoiser::DenoiserBackend,
+        descriptor_diagnostics::DescriptorDiagnostics,
+        dirty_ranges::DirtyRanges,
+        dynamic_resolution::DynamicResolution,
+        feature_chain::DeviceFeature,
+        frame_scratch::ScratchVec,
+        gpu_profiler::GpuProfiler,
+        hdr_format::{choose_hdr_format, HdrFormat},
+        logging::{TARGET_AS, TARGET_SHADER},
+        participating_media::fog_params,
+        push_constant_diagnostics::{PushConstantDiagnostics, PushConstantLayout},
+        render_origin::RenderOrigin,
+        skinning::Skin,
+        structures::*,
+        sun::SunController,
+        tessellation::{displace, tessellate, DisplacementMap},
+    },
+};
+use cgmath::{Deg, Matrix4, Point3, SquareMatrix, Vector3, Zero};
+use smallvec::SmallVec;
+
+use ash::{extensions::nv, vk};
+
+mod frame;
+mod offline;
+mod pipeline;
+mod scene;
+mod stress;
+
+// What every traced frame records with, kept from one frame to the next so a frame does not go
+// to the heap for it: the command buffer, reset from a pool of its own, and the per instance
+// lists of the raster passes. There is one per frame in flight, the fence tells when the GPU is
+// done with the last frame recorded into it.
+#[derive(Default)]
+struct FrameContext {
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    // Whether a frame was submitted with it, so its timestamps can be read
+    submitted: bool,
+    matrices: ScratchVec<Matrix4<f32>>,
+    bounds: ScratchVec<Aabb>,
+}
+
+impl FrameContext {
+    fn new(base: &Renderer) -> FrameContext {
+        let command_pool_create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(
+                vk::CommandPoolCreateFlags::TRANSIENT
+                    | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            )
+            .queue_family_index(base.queue_family.graphics_family.unwrap());
+        unsafe {
+            let command_pool = base
+                .device
+                .create_command_pool(&command_pool_create_info, None)
+                .expect("Failed to create the frame command pool.");
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(1)
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY);
+            let command_buffer = base
+                .device
+                .allocate_command_buffers(&allocate_info)
+                .expect("Failed to allocate the frame command buffer.")[0];
+            // Signaled, nothing was submitted to wait for
+            let fence = base
+                .device
+                .create_fence(
+                    &vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED),
+                    None,
+                )
+                .expect("Failed to create the frame fence.");
+            FrameContext {
+                command_pool,
+                command_buffer,
+                fence,
+                ..FrameContext::default()
+            }
+        }
+    }
+
+    fn wait(&self, device: &ash::Device) {
+        unsafe {
+            device
+                .wait_for_fences(&[self.fence], true, u64::MAX)
+                .expect("Failed to wait for a traced frame.");
+        }
+    }
+
+    // Reset and begun for one submit, wait has to be called first
+    fn begin(&self, device: &ash::Device) -> vk::CommandBuffer {
+        unsafe {
+            device
+                .reset_command_buffer(self.command_buffer, vk::CommandBufferResetFlags::empty())
+                .expect("Failed to reset the frame command buffer.");
+            device
+                .begin_command_buffer(
+                    self.command_buffer,
+                    &vk::CommandBufferBeginInfo::builder()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )
+                .expect("Failed to begin the frame command buffer.");
+        }
+        self.command_buffer
+    }
+
+    // Frees the command buffer with its pool
+    fn destroy(&mut self, device: &ash::Device) {
+        unsafe {
+            device.destroy_command_pool(self.command_pool, None);
+            device.destroy_fence(self.fence, None);
+        }
+        *self = FrameContext::default();
+    }
+}
+
+// One of the TLASes the frames in flight take turns with. A frame refits its own to the
+// instances, which leaves the other to the frame before it that may still be tracing.
+struct TlasSlot {
+    top_as: AccelerationStructure,
+    // One set of instances per motion blur time sample
+    instance_buffer: BufferResource,
+    update_scratch: BufferResource,
+    // Instances changed since this TLAS was last refitted
+    dirty_instances: DirtyRanges,
+    // Binds this TLAS, the other bindings are the same in every slot
+    scene_descriptor_pool: vk::DescriptorPool,
+    scene_descriptor_set: vk::DescriptorSet,
+}
+
+// Traced regions of a frame, a quad view or split frame fits without going to the heap
+type ViewportRegions = SmallVec<[(Camera, [u32; 4]); 4]>;
+type DeviceRegions = SmallVec<[(Option<u32>, Camera, [u32; 4]); 4]>;
+
+// Traces the scene with the Renderer's device. Released when dropped. It holds on to the
+// renderer, so the device outlives everything the context created on it. Built with
+// RtContext::builder().
+pub struct RtContext {
+    base: Arc<Renderer>,
+    ray_tracing: Arc<nv::RayTracing>,
+    properties: vk::PhysicalDeviceRayTracingPropertiesNV,
+    // One per frame in flight, indexed by frame_slot like frame_contexts
+    tlas_slots: Vec<TlasSlot>,
+    // Host copy of the instance buffers, only the dirty ranges are written back before a refit
+    instances: Vec<GeometryInstance>,
+    // Instances changed since the last traced frame, which every slot still has to pick up
+    dirty_instances: DirtyRanges,
+    instance_count: u32,
+    motion_time_samples: u32,
+    bottom_as: Option<AccelerationStructure>,
+    scene_set_layout: vk::DescriptorSetLayout,
+    frame_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    pipeline_cache: vk::PipelineCache,
+    pipeline_creation_cache_control: bool,
+    pending_pipeline: Option<std::thread::JoinHandle<Result<vk::Pipeline, vk::Result>>>,
+    shader_binding_table: Option<BufferResource>,
+    instance_data: Vec<InstanceShadingData>,
+    // Written through by set_instance_material
+    instance_data_buffer: Option<MappedBuffer<InstanceShadingData>>,
+    emissive_triangles: Vec<EmissiveTriangle>,
+    emissive_triangle_buffer: Option<BufferResource>,
+    emissive_alias_buffer: Option<BufferResource>,
+    // Written through by set_sun
+    sun: SunController,
+    sun_buffer: Option<MappedBuffer<SunParams>>,
+    // Written through by set_fog
+    fog_buffer: Option<MappedBuffer<FogParams>>,
+    fog_density_buffer: Option<BufferResource>,
+    // DebugBufferHeader and the records the closest hit shaders append for debug_pixel
+    debug_buffer: Option<BufferResource>,
+    debug_pixel: Option<[u32; 2]>,
+    // The scene sets of tlas_slots live as long as the scene, the frame set is recreated with the
+    // render size
+    frame_descriptor_pool: vk::DescriptorPool,
+    frame_descriptor_set: vk::DescriptorSet,
+    // Checked against the device in initialize, `--hdr_format` sets the one asked for
+    hdr_format: HdrFormat,
+    // The passes initialize creates besides the ray tracing pipeline, `--render_mode` sets it
+    render_mode: RenderMode,
+    offscreen_target: ImageResource,
+    // Follows the offscreen target, only on a device group
+    split_frame: Option<SplitFrame>,
+    // One per Aov, in Aov::ALL order
+    aov_targets: Vec<ImageResource>,
+    rgen_shader_module: vk::ShaderModule,
+    chit_shader_module: vk::ShaderModule,
+    miss_shader_module: vk::ShaderModule,
+    shadow_miss_shader_module: vk::ShaderModule,
+    lib_shader_module: vk::ShaderModule,
+    ahit_shader_module: vk::ShaderModule,
+    // `--shader_library` builds the pipeline from the HLSL library with every stage in one
+    // module, which not every driver supports
+    shader_library: bool,
+    // `--stochastic_transparency` adds the any-hit shader to every hit group and traces without
+    // the opaque flag, so rays pass through the instances whose material is transparent
+    stochastic_transparency: bool,
+    // `--tessellation=` and `--displacement=` split and displace the scene mesh as the BLAS is
+    // built from it
+    tessellation_level: u32,
+    displacement: Option<DisplacementMap>,
+    // The device limit, lowered when the driver fails to create a pipeline that deep. Caps
+    // settings.max_recursion_depth.
+    max_recursion_depth: u32,
+    restir: Option<RestirPass>,
+    // Traces the frame instead of the ray tracing pipeline in RenderMode::RayQuery
+    ray_query: Option<RayQueryPass>,
+    exposure: Option<ExposurePass>,
+    // Applied by the exposure pass after the tone curve, the identity unless --lut is given
+    grading_lut: CubeLut,
+    // Set while settings.checkerboard is, in the standard render mode only
+    checkerboard: Option<CheckerboardPass>,
+    depth_prepass: Option<DepthPrepass>,
+    // Bins point_lights into screen tiles, set along with depth_prepass
+    light_culler: Option<LightCuller>,
+    // Lighting the hybrid and shadow map modes, set with `--point_lights=N`
+    point_lights: Vec<ClusteredLight>,
+    // Shades the depth pre-pass instead of the rays while settings.shadow_map is on
+    shadow_map: Option<ShadowMapPass>,
+    // Reflected by the shadow map shading, set with `--probes=x,y,z[,x,y,z...]`
+    environment_probes: EnvironmentProbes,
+    // Raster pass rendered only for rectangle selection when the hybrid pre-pass is not in use
+    picking_prepass: Option<DepthPrepass>,
+    // Instances outlined over every traced frame, set with set_selection
+    selection: Vec<u32>,
+    // Created with the first frame that has a selection, on the pre-pass with the instance IDs
+    selection_outline: Option<SelectionOutlinePass>,
+    debug_draw: Option<DebugDraw>,
+    // Applied to the offline outputs when settings.denoise is on
+    denoiser: Option<DenoiserBackend>,
+    // Textures loaded with `ash_rt atlas`, not sampled by the shaders yet
+    texture_atlas: Option<TextureAtlas>,
+    // Where the atlas was packed from, packed again when the texture budget changes
+    texture_atlas_directory: Option<PathBuf>,
+    pub texture_budget: u64,
+    // Camera view when the debug draw was turned on, drawn as the camera moves away from it
+    debug_frustum: Matrix4<f32>,
+    geometry_pool: Option<GeometryPool>,
+    scene_mesh: MeshAllocation,
+    // Object space bounds of the BLAS mesh
+    scene_mesh_bounds: Aabb,
+    // Given to the builder, taken by the skinning pass once the scene mesh is built
+    skin: Option<Skin>,
+    // Set while the scene mesh has a skin, posed with set_skin_pose
+    skinning: Option<SkinningPass>,
+    // Bound in place of the skin buffers of the scene set when there is no skin
+    skin_placeholder_buffer: Option<BufferResource>,
+    // World space bounds of every instance when the scene was built, the camera is framed on it
+    scene_bounds: Aabb,
+    // World position render space is relative to, follows the camera
+    render_origin: RenderOrigin,
+    // Instance transforms at the start of the shutter interval in render space, in instance data
+    // order
+    scene_transforms: Vec<InstanceTransform>,
+    // World space translation of each instance, the render space one is rebased from it
+    scene_translations: Vec<Vector3<f64>>,
+    // Translation of each instance over the shutter interval
+    scene_motion: Vec<[f32; 3]>,
+    // Instance mask of each instance, built from its RenderLayers
+    scene_layers: Vec<u8>,
+    scene_stats: SceneStats,
+    // BLASes built per frame by scenes that queue them, 0 builds them all at once. Set with
+    // `--blas_builds_per_frame=N`.
+    pub blas_builds_per_frame: u32,
+    // `--deterministic` renders bit for bit the same frames on every run: the pipeline is
+    // waited for, exposure adapts at a fixed rate, dynamic resolution and split-frame rendering
+    // are off and the viewport dispatches run one after the other
+    deterministic: bool,
+    pub camera: Camera,
+    // Planes set with `--near=` and `--far=`, the others are fitted to scene_bounds
+    pub depth_range: DepthRange,
+    // Split screen regions with their own cameras, empty to trace the whole target with camera
+    viewports: Vec<Viewport>,
+    settings: RenderSettings,
+    // Set while settings.target_fps is, adjusts the settings after every traced frame
+    dynamic_resolution: Option<DynamicResolution>,
+    frame_contexts: Vec<FrameContext>,
+    // Of the next traced frame, into tlas_slots and frame_contexts
+    frame_slot: usize,
+    // Start and end of every frame in flight, two per slot
+    timestamp_query_pool: vk::QueryPool,
+    // Nanoseconds per timestamp tick, 0 when the graphics queue has no timestamps
+    timestamp_period: f32,
+    pipeline_desc: Option<RayTracingPipelineDesc>,
+    // Printed when the descriptor set or the pipeline fails validation
+    // One per descriptor set, indexed by SCENE_DESCRIPTOR_SET and FRAME_DESCRIPTOR_SET
+    descriptor_diagnostics: [DescriptorDiagnostics; 2],
+    // The push constant structs of every ray tracing stage against what the stages declare
+    push_constant_diagnostics: PushConstantDiagnostics,
+    validation_errors_seen: u32,
+    // Labels the passes of every traced frame, counters only after enable_gpu_counters
+    profiler: GpuProfiler,
+}
+// What an RtContext is initialized with, the defaults of the executable without options
+pub struct RtContextBuilder {
+    base: Arc<Renderer>,
+    hdr_format: HdrFormat,
+    render_mode: RenderMode,
+    deterministic: bool,
+    shader_library: bool,
+    stochastic_transparency: bool,
+    tessellation_level: u32,
+    displacement: Option<DisplacementMap>,
+    skin: Option<Skin>,
+}
+
+impl RtContextBuilder {
+    // Of the offscreen target, which falls back to one the device supports
+    pub fn hdr_format(mut self, hdr_format: HdrFormat) -> RtContextBuilder {
+        self.hdr_format = hdr_format;
+        self
+    }
+
+    // ReSTIR DI, the hybrid depth pre-pass or the ray query backend instead of the standard
+    // ray tracing pipeline alone
+    pub fn render_mode(mut self, render_mode: RenderMode) -> RtContextBuilder {
+        self.render_mode = render_mode;
+        self
+    }
+
+    // Bit for bit the same frames on every run, see RtContext::frame_hash
+    pub fn deterministic(mut self, deterministic: bool) -> RtContextBuilder {
+        self.deterministic = deterministic;
+        self
+    }
+
+    // The pipeline from the HLSL library with every stage in one module
+    pub fn shader_library(mut self, shader_library: bool) -> RtContextBuilder {
+        self.shader_library = shader_library;
+        self
+    }
+
+    // Rays pass through the instances whose material is transparent
+    pub fn stochastic_transparency(mut self, stochastic_transparency: bool) -> RtContextBuilder {
+        self.stochastic_transparency = stochastic_transparency;
+        self
+    }
+
+    // Splits every triangle of the scene mesh `level` times along each edge
+    pub fn tessellation_level(mut self, tessellation_level: u32) -> RtContextBuilder {
+        self.tessellation_level = tessellation_level;
+        self
+    }
+
+    // Pushes the tessellated scene mesh out along its normals
+    pub fn displacement(mut self, displacement: DisplacementMap) -> RtContextBuilder {
+        self.displacement = Some(displacement);
+        self
+    }
+
+    // Deforms the scene mesh with joints and morph targets, posed with RtContext::set_skin_pose.
+    // Ignored with a warning when it does not fit the mesh or the ray query backend is used.
+    pub fn skin(mut self, skin: Skin) -> RtContextBuilder {
+        self.skin = Some(skin);
+        self
+    }
+
+    // Builds the scene and the pipeline, ready to trace
+    pub fn build(self) -> RtContext {
+        let (properties, ray_tracing) = unsafe {
+            (
+                nv::RayTracing::get_properties(&self.base.instance, self.base.physical_device),
+                Arc::new(nv::RayTracing::new(&self.base.instance, &self.base.device)),
+            )
+        };
+        let mut context = RtContext::new(self.base, ray_tracing, properties);
+        context.hdr_format = self.hdr_format;
+        context.render_mode = self.render_mode;
+        context.deterministic = self.deterministic;
+        context.shader_library = self.shader_library;
+        context.stochastic_transparency = self.stochastic_transparency;
+        context.tessellation_level = self.tessellation_level;
+        context.displacement = self.displacement;
+        context.skin = self.skin;
+        context.initialize();
+        context
+    }
+}
+
+impl RtContext {
+    pub fn builder(base: Arc<Renderer>) -> RtContextBuilder {
+        RtContextBuilder {
+            base,
+            hdr_format: HDR_FORMAT,
+            render_mode: RENDER_MODE,
+            deterministic: false,
+            shader_library: false,
+            stochastic_transparency: false,
+            tessellation_level: DEFAULT_TESSELLATION_LEVEL,
+            displacement: None,
+            skin: None,
+        }
+    }
+
+    pub fn ray_tracing_properties(&self) -> vk::PhysicalDeviceRayTracingPropertiesNV {
+        self.properties
+    }
+
+    pub fn settings(&self) -> RenderSettings {
+        self.settings
+    }
+
+    pub fn sun(&self) -> SunController {
+        self.sun
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    // World space bounds of every instance when the scene was built
+    pub fn scene_bounds(&self) -> Aabb {
+        self.scene_bounds
+    }
+
+    // World space translation of instance `index`
+    pub fn instance_position(&self, index: usize) -> Vector3<f64> {
+        self.scene_translations[index]
+    }
+
+    fn new(
+        base: Arc<Renderer>,
+        ray_tracing: Arc<nv::RayTracing>,
+        properties: vk::PhysicalDeviceRayTracingPropertiesNV,
+    ) -> Self {
+        let pipeline_creation_cache_control = base
+            .features
+            .contains(DeviceFeature::PipelineCreationCacheControl);
+        let environment_probes = EnvironmentProbes::new(base.clone(), &[]);
+
+        RtContext {
+            base: base.clone(),
+            ray_tracing,
+            properties,
+            tlas_slots: Vec::new(),
+            instances: Vec::new(),
+            dirty_instances: DirtyRanges::new(),
+            instance_count: 0,
+            motion_time_samples: 1,
+            bottom_as: None,
+            scene_set_layout: vk::DescriptorSetLayout::null(),
+            frame_set_layout: vk::DescriptorSetLayout::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            pipeline_cache: vk::PipelineCache::null(),
+            pipeline_creation_cache_control,
+            pending_pipeline: None,
+            shader_binding_table: None,
+            instance_data: Vec::new(),
+            instance_data_buffer: None,
+            emissive_triangles: Vec::new(),
+            emissive_triangle_buffer: None,
+            emissive_alias_buffer: None,
+            sun: SunController::default(),
+            sun_buffer: None,
+            fog_buffer: None,
+            fog_density_buffer: None,
+            debug_buffer: None,
+            debug_pixel: None,
+            frame_descriptor_pool: vk::DescriptorPool::null(),
+            frame_descriptor_set: vk::DescriptorSet::null(),
+            profiler: GpuProfiler::new(base.debug_utils_loader.clone()),
+            hdr_format: HDR_FORMAT,
+            render_mode: RENDER_MODE,
+            offscreen_target: ImageResource::new(base),
+            split_frame: None,
+            aov_targets: Vec::new(),
+            rgen_shader_module: vk::ShaderModule::null(),
+            chit_shader_module: vk::ShaderModule::null(),
+            miss_shader_module: vk::ShaderModule::null(),
+            shadow_miss_shader_module: vk::ShaderModule::null(),
+            lib_shader_module: vk::ShaderModule::null(),
+            ahit_shader_module: vk::ShaderModule::null(),
+            shader_library: false,
+            stochastic_transparency: false,
+            tessellation_level: DEFAULT_TESSELLATION_LEVEL,
+            displacement: None,
+            max_recursion_depth: properties.max_recursion_depth,
+            restir: None,
+            ray_query: None,
+            exposure: None,
+            grading_lut: CubeLut::identity(),
+            checkerboard: None,
+            depth_prepass: None,
+            light_culler: None,
+            point_lights: Vec::new(),
+            shadow_map: None,
+            environment_probes,
+            picking_prepass: None,
+            selection: Vec::new(),
+            selection_outline: None,
+            debug_draw: None,
+            denoiser: utility::denoiser::default_denoiser(),
+            texture_atlas: None,
+            texture_atlas_directory: None,
+            texture_budget: TEXTURE_MEMORY_BUDGET,
+            debug_frustum: Matrix4::identity(),
+            geometry_pool: None,
+            scene_mesh: MeshAllocation::default(),
+            scene_mesh_bounds: Aabb::empty(),
+            skin: None,
+            skinning: None,
+            skin_placeholder_buffer: None,
+            scene_bounds: Aabb::empty(),
+            scene_transforms: Vec::new(),
+            render_origin: RenderOrigin::default(),
+            scene_translations: Vec::new(),
+            scene_motion: Vec::new(),
+            scene_layers: Vec::new(),
+            scene_stats: SceneStats::default(),
+            blas_builds_per_frame: BLAS_BUILDS_PER_FRAME,
+            deterministic: false,
+            depth_range: DepthRange::default(),
+            camera: Camera::default(),
+            viewports: Vec::new(),
+            settings: RENDER_SETTINGS,
+            dynamic_resolution: None,
+            frame_contexts: Vec::new(),
+            frame_slot: 0,
+            timestamp_query_pool: vk::QueryPool::null(),
+            timestamp_period: 0.0,
+            pipeline_desc: None,
+            descriptor_diagnostics: [
+                DescriptorDiagnostics::new("the ray tracing scene set", SCENE_DESCRIPTOR_SET),
+                DescriptorDiagnostics::new("the ray tracing frame set", FRAME_DESCRIPTOR_SET),
+            ],
+            push_constant_diagnostics: PushConstantDiagnostics::new(
+                "the ray tracing pipeline",
+                // At the *_PUSH_CONSTANT_OFFSET constants
+                PushConstantLayout::of::<CameraPushConstants>()
+                    .then::<AccumulationPushConstants>()
+                    .then::<DepthRangePushConstants>()
+                    .then::<RayMaskPushConstants>()
+                    .then::<ViewportPushConstants>()
+                    .then::<RayBiasPushConstants>(),
+            ),
+            validation_errors_seen: 0,
+        }
+    }
+
+    fn initialize(&mut self) {
+        self.hdr_format = choose_hdr_format(
+            &self.base.instance,
+            self.base.physical_device,
+            self.hdr_format,
+        );
+        self.create_offscreen_target();
+        self.create_aov_targets();
+        self.create_exposure_pass();
+        if self.render_mode == RenderMode::RestirDi {
+            self.create_restir_pass();
+        }
+        if self.render_mode == RenderMode::Hybrid {
+            self.create_depth_prepass();
+        }
+        if self.render_mode == RenderMode::RayQuery {
+            self.create_ray_query_pass();
+        }
+        self.create_acceleration_structures();
+        self.create_instance_data_buffer();
+        self.initialize_ray_query_pass();
+        self.create_shadow_map_pass();
+        self.create_emissive_light_buffers();
+        self.create_sun_buffer();
+        self.create_fog_buffers();
+        self.create_debug_buffer();
+        self.create_pipeline();
+        self.create_scene_descriptor_set();
+        self.create_frame_descriptor_set();
+        self.create_timestamp_queries();
+        self.frame_contexts = (0..TRACED_FRAMES_IN_FLIGHT)
+            .map(|_| FrameContext::new(&self.base))
+            .collect();
+        self.frame_slot = 0;
+    }
+
+    fn create_timestamp_queries(&mut self) {
+        let limits = unsafe {
+            self.base
+                .instance
+                .get_physical_device_properties(self.base.physical_device)
+        }
+        .limits;
+        if limits.timestamp_compute_and_graphics == vk::FALSE {
+            tracing::warn!("No GPU timestamps on this device, dynamic resolution is unavailable");
+            return;
+        }
+        self.timestamp_period = limits.timestamp_period;
+
+        let query_pool_create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(2 * TRACED_FRAMES_IN_FLIGHT as u32)
+            .build();
+        self.timestamp_query_pool = unsafe {
+            self.base
+                .device
+                .create_query_pool(&query_pool_create_info, None)
+                .expect("Failed to create timestamp query pool.")
+        };
+    }
+
+    // GPU time in milliseconds of the frame last traced with the slot, waits for it to finish
+    fn gpu_frame_time(&self, slot: usize) -> Option<f32> {
+        if self.timestamp_query_pool == vk::QueryPool::null()
+            || !self.frame_contexts[slot].submitted
+        {
+            return None;
+        }
+        let mut timestamps = [0u64; 2];
+        unsafe {
+            self.base
+                .device
+                .get_query_pool_results(
+                    self.timestamp_query_pool,
+                    2 * slot as u32,
+                    2,
+                    &mut timestamps,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                )
+                .ok()?;
+        }
+        let ticks = timestamps[1].wrapping_sub(timestamps[0]);
+        Some(ticks as f32 * self.timestamp_period / 1_000_000.0)
+    }
+
+    // Feeds the oldest frame in flight to the dynamic resolution controller and applies what it
+    // decides. That is the frame before the one just submitted, which leaves the GPU the newer
+    // one to work on while this waits.
+    fn update_dynamic_resolution(&mut self) {
+        if self.dynamic_resolution.is_none() {
+            return;
+        }
+        let gpu_frame_time = match self.gpu_frame_time(self.frame_slot) {
+            Some(gpu_frame_time) => gpu_frame_time,
+            None => return,
+        };
+        let settings = match self.dynamic_resolution.as_mut() {
+            Some(controller) => controller.update(gpu_frame_time, &self.settings),
+            None => return,
+        };
+        if let Some(settings) = settings {
+            tracing::debug!(
+                "Dynamic resolution: {:.2} ms, scale {} -> {}, spp {} -> {}",
+                gpu_frame_time,
+                self.settings.render_scale,
+                settings.render_scale,
+                self.settings.samples_per_pixel,
+                settings.samples_per_pixel
+            );
+            self.apply_settings(settings);
+        }
+    }
+
+    // Size of every ray traced target, the window size scaled by the render scale
+    pub fn render_extent(&self) -> vk::Extent2D {
+        let scale = |size: u32| ((size as f32 * self.settings.render_scale) as u32).max(1);
+        vk::Extent2D {
+            width: scale(self.base.swapchain_extent.width),
+            height: scale(self.base.swapchain_extent.height),
+        }
+    }
+
+    fn create_offscreen_target(&mut self) {
+        let extent = self.render_extent();
+        self.offscreen_target.create_image(
+            vk::ImageType::TYPE_2D,
+            self.hdr_format.vk_format(),
+            vk::Extent3D::builder()
+                .width(extent.width)
+                .height(extent.height)
+                .depth(1)
+                .build(),
+            vk::ImageTiling::OPTIMAL,
+            // Color attachment for the debug draw overlay
+            vk::ImageUsageFlags::STORAGE
+                | vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        self.offscreen_target.create_view(
+            vk::ImageViewType::TYPE_2D,
+            self.hdr_format.vk_format(),
+            vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+        );
+        self.offscreen_target.set_name("HDR target");
+
+        self.split_frame = None;
+        if self.base.device_count > 1 {
+            self.split_frame =
+                SplitFrame::new(self.base.clone(), extent, self.hdr_format.bytes_per_pixel());
+        }
+    }
+
+    fn create_aov_targets(&mut self) {
+        let extent = if AOV_OUTPUTS {
+            self.render_extent()
+        } else {
+            vk::Extent2D {
+                width: 1,
+                height: 1,
+            }
+        };
+
+        self.aov_targets = Aov::ALL
+            .iter()
+            .map(|aov| {
+                let mut target = ImageResource::new(self.base.clone());
+                target.create_image(
+                    vk::ImageType::TYPE_2D,
+                    AOV_FORMAT,
+                    vk::Extent3D::builder()
+                        .width(extent.width)
+                        .height(extent.height)
+                        .depth(1)
+                        .build(),
+                    vk::ImageTiling::OPTIMAL,
+                    // Cleared to the background every frame and read back for saving
+                    vk::ImageUsageFlags::STORAGE
+                        | vk::ImageUsageFlags::TRANSFER_SRC
+                        | vk::ImageUsageFlags::TRANSFER_DST,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                );
+                target.create_view(
+                    vk::ImageViewType::TYPE_2D,
+                    AOV_FORMAT,
+                    vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    },
+                );
+                target.set_name(&format!("{} AOV", aov.name()));
+                target
+            })
+            .collect();
+    }
+
+    fn create_exposure_pass(&mut self) {
+        let mut exposure = ExposurePass::new(self.base.clone(), self.render_extent());
+        exposure.set_adaptation_speed(self.settings.exposure_adaptation_speed);
+        exposure.set_ev_compensation(self.settings.ev_compensation);
+        exposure.set_tone_curve(self.settings.tone_curve);
+        exposure.fixed_delta_time = self.deterministic.then_some(DETERMINISTIC_FRAME_TIME);
+        exposure.initialize(self.offscreen_target.view, &self.grading_lut);
+        self.exposure = Some(exposure);
+    }
+
+    fn create_checkerboard_pass(&mut self) {
+        // The other modes trace their own fixed set of rays
+        if self.restir.is_some() || self.depth_prepass.is_some() || self.ray_query.is_some() {
+            tracing::warn!("Checkerboard rendering needs the standard render mode, ignoring it");
+            return;
+        }
+        let mut checkerboard = CheckerboardPass::new(self.base.clone(), self.render_extent());
+        checkerboard.initialize(self.offscreen_target.view);
+        self.checkerboard = Some(checkerboard);
+    }
+
+    fn create_debug_draw(&mut self) {
+        let mut debug_draw = DebugDraw::new(self.base.clone(), self.render_extent());
+        debug_draw.initialize(self.offscreen_target.view, self.hdr_format.vk_format());
+        self.debug_draw = Some(debug_draw);
+    }
+
+    fn create_restir_pass(&mut self) {
+        let mut restir = RestirPass::new(
+            self.base.clone(),
+            self.render_extent(),
+            create_restir_lights(RESTIR_LIGHT_COUNT),
+        );
+        restir.initialize(self.offscreen_target.view);
+        // The lights are placed in world space
+        restir.translate_lights(self.render_origin.to_render(Vector3::zero()));
+        self.restir = Some(restir);
+    }
+
+    // The pipeline is still created without ray query support, it takes over as the backend
+    fn create_ray_query_pass(&mut self) {
+        if !self.base.features.contains(DeviceFeature::RayQuery) {
+            tracing::warn!("No ray query support, tracing with the ray tracing pipeline instead");
+            return;
+        }
+        self.ray_query = Some(RayQueryPass::new(self.base.clone()));
+    }
+
+    fn initialize_ray_query_pass(&mut self) {
+        if let Some(ray_query) = self.ray_query.as_mut() {
+            ray_query.initialize(
+                self.offscreen_target.view,
+                self.instance_data_buffer.as_ref().unwrap().buffer(),
+            );
+        }
+    }
+
+    fn create_depth_prepass(&mut self) {
+        let mut depth_prepass = DepthPrepass::new(self.base.clone(), self.render_extent());
+        depth_prepass.initialize();
+
+        let mut light_culler = LightCuller::new(self.base.clone(), self.render_extent());
+        light_culler.initialize(
+            depth_prepass.depth_target.view,
+            depth_prepass.depth_target.sampler,
+        );
+        light_culler.set_lights(&self.point_lights);
+
+        self.depth_prepass = Some(depth_prepass);
+        self.light_culler = Some(light_culler);
+    }
+
+    pub fn set_grading_lut(&mut self, lut: CubeLut) {
+        if let Some(exposure) = self.exposure.as_mut() {
+            self.base.wait_device_idle();
+            exposure.set_lut(&lut);
+        }
+        self.grading_lut = lut;
+    }
+
+    // Replaces the point lights with a grid of `count` over the scene. Only the hybrid and shadow
+    // map modes are lit by them.
+    pub fn set_point_lights(&mut self, count: u32) {
+        if count > POINT_LIGHT_CAPACITY {
+            tracing::warn!(
+                "Only {} of {} point lights fit in the light buffer",
+                POINT_LIGHT_CAPACITY,
+                count
+            );
+        }
+        self.point_lights =
+            create_point_lights(count.min(POINT_LIGHT_CAPACITY), &self.scene_bounds);
+        if let Some(light_culler) = self.light_culler.as_mut() {
+            self.base.wait_device_idle();
+            light_culler.set_lights(&self.point_lights);
+        }
+    }
+
+    // Created along with the depth pre-pass it shades, so both kinds of shadows can be compared
+    // without restarting
+    fn create_shadow_map_pass(&mut self) {
+        if let Some(depth_prepass) = self.depth_prepass.as_ref() {
+            let mut shadow_map = ShadowMapPass::new(self.base.clone(), self.render_extent());
+            shadow_map.set_sun(self.sun.params());
+            shadow_map.initialize(
+                self.offscreen_target.view,
+                self.hdr_format.vk_format(),
+                self.instance_data_buffer.as_ref().unwrap().buffer(),
+                depth_prepass,
+                self.light_culler.as_ref().unwrap(),
+                &self.environment_probes,
+                self.instance_count,
+            );
+            self.shadow_map = Some(shadow_map);
+        }
+    }
+
+    fn create_acceleration_structures(&mut self) {
+        let _span =
+            tracing::info_span!(target: TARGET_AS, "build_acceleration_structures").entered();
+        // Create geometry
+
+        let vertices = scene_triangle();
+
+        let mut positions: Vec<[f32; 3]> = vertices.iter().map(|vertex| vertex.pos).collect();
+        // Over the square around the triangle, with v down like images
+        let tex_coords: Vec<[f32; 2]> = positions
+            .iter()
+            .map(|position| [position[0] + 0.5, 0.5 - position[1]])
+            .collect();
+        let mut indices = vec![0u16, 1, 2];
+
+        // The closest hit still shades with the flat normal of the source triangle, displacement
+        // shows in the silhouettes, shadows and occlusion
+        if self.tessellation_level > 1 || self.displacement.is_some() {
+            let level = self.tessellation_level.clamp(1, MAX_TESSELLATION_LEVEL);
+            if level != self.tessellation_level {
+                tracing::warn!(
+                    target: TARGET_AS,
+                    "Tessellation level {} clamped to {}",
+                    self.tessellation_level,
+                    level
+                );
+            }
+            let source_indices: Vec<u32> = indices.iter().map(|&index| index as u32).collect();
+            let mut mesh = tessellate(&positions, &tex_coords, &source_indices, level);
+            if let Some(displacement) = self.displacement.as_ref() {
+                displace(&mut mesh, displacement);
+            }
+            tracing::debug!(
+                target: TARGET_AS,
+                "Tessellated the scene mesh at level {}: {} vertices, {} triangles",
+                level,
+                mesh.positions.len(),
+                mesh.indices.len() / 3
+            );
+            positions = mesh.positions;
+            indices = mesh.indices.iter().map(|&index| index as u16).collect();
+        }
+
+        self.scene_mesh_bounds = Aabb::from_points(positions.iter().copied().map(Point3::from));
+
+        let skin = match self.skin.take() {
+            Some(_) if self.ray_query.is_some() => {
+                tracing::warn!(
+                    target: TARGET_AS,
+                    "The ray query backend traces the scene mesh at rest, ignoring its skin"
+                );
+                None
+            }
+            Some(skin) => match skin.validate(positions.len()) {
+                Ok(()) => Some(skin),
+                Err(err) => {
+                    tracing::warn!(target: TARGET_AS, "Ignoring the skin: {}", err);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let vertex_stride = BLAS_VERTEX_FORMAT.stride();
+        let vertex_data = BLAS_VERTEX_FORMAT.encode(&positions);
+        self.scene_stats.vertex_memory = vertex_data.len() as vk::DeviceSize;
+
+        let index_count = indices.len();
+
+        let mut geometry_pool = GeometryPool::new(self.base.clone(), vertex_stride);
+        let mesh = geometry_pool.add_mesh(&vertex_data, &indices);
+
+        let geometry = vec![geometry_pool.triangle_geometry(&mesh)];
+
+        tracing::debug!(target: TARGET_AS, "BLAS geometries: {}", geometry.len());
+
+        // BLASes are created up front, the instances need their references
+        let mut as_builder = AsBuilder::new(self.base.clone(), self.ray_tracing.clone());
+        let bottom_as = as_builder.add_bottom_level(
+            &geometry,
+            if skin.is_some() {
+                SKINNED_BLAS_FLAGS
+            } else {
+                vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE
+            },
+        );
+
+        self.scene_stats.geometry_count = geometry.len() as u32;
+        self.scene_stats.blas_size = bottom_as.size;
+
+        // Create instance buffer
+
+        let bottom_as_handle = bottom_as.reference;
+
+        let transform_0 =
+            InstanceTransform::from(Matrix4::from_translation(Vector3::new(-1.5, 1.1, 0.0)));
+
+        let transform_1 =
+            InstanceTransform::from(Matrix4::from_translation(Vector3::new(0.0, -1.1, 0.0)));
+
+        let transform_2 =
+            InstanceTransform::from(Matrix4::from_translation(Vector3::new(1.5, 1.1, 0.0)));
+
+        // Lamp lying flat above the other triangles, lighting them from the front
+        let transform_3 = InstanceTransform::from(
+            Matrix4::from_translation(Vector3::new(0.0, 1.3, -0.5))
+                * Matrix4::from_angle_x(Deg(90.0))
+                * Matrix4::from_scale(0.6),
+        );
+
+        // (transform, color, emission, translation over the shutter interval)
+        let mut scene_instances = vec![
+            (transform_0, [1.0, 0.0, 0.0, 1.0], [0.0; 4], [0.0; 3]),
+            (transform_1, [0.0, 1.0, 0.0, 1.0], [0.0; 4], [0.4, 0.0, 0.0]),
+            (transform_2, [0.0, 0.0, 1.0, 1.0], [0.0; 4], [0.0; 3]),
+            (
+                transform_3,
+                [1.0, 1.0, 1.0, 1.0],
+                [4.0, 3.6, 3.0, 0.0],
+                [0.0; 3],
+            ),
+        ];
+
+        // Framed without the ground plane, which is big enough to swallow everything else
+        self.scene_bounds =
+            scene_instances
+                .iter()
+                .fold(Aabb::empty(), |bounds, (transform, _, _, _)| {
+                    bounds.union(
+                        &self
+                            .scene_mesh_bounds
+                            .transformed(&Matrix4::from(*transform)),
+                    )
+                });
+        self.camera.frame_scene(&self.scene_bounds);
+        let mut scene_layers =
+            vec![RenderLayer::mask_of(SCENE_INSTANCE_LAYERS); scene_instances.len()];
+        if let Some((transform, color, emission)) =
+            ground_plane_instance(&self.scene_mesh_bounds, &self.scene_bounds)
+        {
+            scene_instances.push((transform, color, emission, [0.0; 3]));
+            scene_layers.push(RenderLayer::mask_of(GROUND_PLANE_LAYERS));
+        }
+
+        // The ReSTIR passes resolve lighting once per frame from a single G-buffer and the
+        // depth pre-pass is rasterized once, so they only see the instances at the start of
+        // the shutter interval, as does the ray query backend
+        self.motion_time_samples =
+            if self.restir.is_some() || self.depth_prepass.is_some() || self.ray_query.is_some() {
+                1
+            } else {
+                MOTION_BLUR_TIME_SAMPLES.max(1)
+            };
+        self.instance_count = scene_instances.len() as u32;
+
+        // The custom index of each instance selects its entry in the instance data buffer
+        let motion_time_samples = self.motion_time_samples;
+        let layers = &scene_layers;
+        let instances: Vec<GeometryInstance> = (0..self.motion_time_samples)
+            .flat_map(|sample| {
+                scene_instances
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, (transform, _, _, motion))| {
+                        GeometryInstance::new(
+                            motion_sample_transform(transform, motion, sample, motion_time_samples),
+                            index as u32,
+                            layers[index],
+                            0,
+                            vk::GeometryInstanceFlagsNV::TRIANGLE_CULL_DISABLE_NV,
+                            bottom_as_handle,
+                        )
+                    })
+            })
+            .collect();
+
+        self.scene_transforms = scene_instances
+            .iter()
+            .map(|(transform, _, _, _)| *transform)
+            .collect();
+        self.scene_translations = self
+            .scene_transforms
+            .iter()
+            .map(|transform| {
+                self.render_origin
+                    .to_world(Vector3::from(transform.translation()))
+            })
+            .collect();
+        self.scene_motion = scene_instances
+            .iter()
+            .map(|(_, _, _, motion)| *motion)
+            .collect();
+        self.scene_layers = scene_layers;
+
+        self.instance_data = scene_instances
+            .iter()
+            .map(|(_, color, emission, _)| InstanceShadingData {
+                color: *color,
+                emission: *emission,
+                surface: [
+                    DEFAULT_ROUGHNESS,
+                    DEFAULT_METALLIC,
+                    DEFAULT_ANISOTROPY,
+                    DEFAULT_OPACITY,
+                ],
+                subsurface: [0.0, 0.0, 0.0, DEFAULT_OPACITY_THRESHOLD],
+            })
+            .collect();
+
+        let emitters: Vec<(InstanceTransform, [f32; 4])> = scene_instances
+            .iter()
+            .map(|(transform, _, emission, _)| (*transform, *emission))
+            .collect();
+        // Emitters are sampled by triangle, not through the index buffer
+        let triangle_positions: Vec<[f32; 3]> = indices
+            .iter()
+            .map(|&index| positions[index as usize])
+            .collect();
+        self.emissive_triangles =
+            utility::light_sampling::gather_emissive_triangles(&triangle_positions, &emitters);
+
+        // Create the top-level acceleration structures, one per frame in flight with instances
+        // of its own
+
+        let instance_buffer_size = std::mem::size_of::<GeometryInstance>() * instances.len();
+        let mut tlas_slots = Vec::with_capacity(TRACED_FRAMES_IN_FLIGHT);
+        for _ in 0..TRACED_FRAMES_IN_FLIGHT {
+            let mut instance_buffer = BufferResource::new(
+                instance_buffer_size as u64,
+                vk::BufferUsageFlags::RAY_TRACING_NV,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                self.base.clone(),
+            );
+            instance_buffer.store(&instances);
+
+            // Refit for every motion blur time sample and after instances were moved
+            let top_as = as_builder.add_top_level(
+                self.instance_count,
+                vk::BuildAccelerationStructureFlagsNV::ALLOW_UPDATE,
+                instance_buffer.buffer,
+                0,
+            );
+            let requirements = top_as.memory_requirements(
+                vk::AccelerationStructureMemoryRequirementsTypeNV::UPDATE_SCRATCH,
+            );
+            let update_scratch = BufferResource::new(
+                requirements.size,
+                vk::BufferUsageFlags::RAY_TRACING_NV,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                self.base.clone(),
+            );
+            tlas_slots.push(TlasSlot {
+                top_as,
+                instance_buffer,
+                update_scratch,
+                dirty_instances: DirtyRanges::new(),
+                scene_descriptor_pool: vk::DescriptorPool::null(),
+                scene_descriptor_set: vk::DescriptorSet::null(),
+            });
+        }
+
+        self.scene_stats.instance_count = self.instance_count;
+        // Every instance references the same BLAS
+        self.scene_stats.triangle_count = (index_count as u32 / 3 * self.instance_count) as u64;
+        self.scene_stats.tlas_size = tlas_slots[0].top_as.size;
+
+        // Build accleration structures
+
+        let build_start = Instant::now();
+        self.scene_stats.scratch_peak = as_builder.build();
+        self.scene_stats.build_time = build_start.elapsed();
+        tracing::info!(target: TARGET_AS, "Built acceleration structures");
+
+        bottom_as.set_name("scene mesh BLAS");
+        for (index, slot) in tlas_slots.iter().enumerate() {
+            slot.top_as.set_name(&format!("scene TLAS {}", index));
+            slot.instance_buffer
+                .set_name(&format!("scene TLAS {} instances", index));
+            slot.update_scratch
+                .set_name(&format!("scene TLAS {} update scratch", index));
+        }
+        self.bottom_as = Some(bottom_as);
+        self.tlas_slots = tlas_slots;
+        self.instances = instances;
+        self.dirty_instances = DirtyRanges::new();
+
+        if let Some(ray_query) = self.ray_query.as_mut() {
+            let indices: Vec<u32> = indices.iter().map(|&index| index as u32).collect();
+            ray_query.build_scene(
+                &positions,
+                &indices,
+                &self.instances[..self.instance_count as usize],
+            );
+        }
+
+        // Kept for the raster depth pre-pass
+        self.geometry_pool = Some(geometry_pool);
+        self.scene_mesh = mesh;
+
+        if let Some(skin) = skin {
+            let mut skinning = SkinningPass::new(self.base.clone(), self.ray_tracing.clone(), skin);
+            skinning.initialize(&positions, &indices, self.bottom_as.as_ref().unwrap());
+            self.skinning = Some(skinning);
+        }
+    }
+
+    fn create_instance_data_buffer(&mut self) {
+        let instance_data_buffer = MappedBuffer::new(
+            &self.instance_data,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            self.base.clone(),
+        );
+        instance_data_buffer.set_name("instance shading data");
+        self.instance_data_buffer = Some(instance_data_buffer);
+    }
+
+    fn create_emissive_light_buffers(&mut self) {
+        let weights: Vec<f32> = self
+            .emissive_triangles
+            .iter()
+            .map(utility::light_sampling::emitted_power)
+            .collect();
+        let mut alias_table = utility::light_sampling::build_alias_table(&weights);
+
+        // Storage buffers cannot be empty, a black triangle keeps the hit shader branch-free
+        let mut emissive_triangles = self.emissive_triangles.clone();
+        if alias_table.is_empty() {
+            emissive_triangles = vec![EmissiveTriangle::default()];
+            alias_table = vec![AliasEntry {
+                threshold: 1.0,
+                pdf: 1.0,
+                ..Default::default()
+            }];
+        }
+
+        let mut emissive_triangle_buffer = BufferResource::new(
+            std::mem::size_of_val(emissive_triangles.as_slice()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        emissive_triangle_buffer.store(&emissive_triangles);
+        emissive_triangle_buffer.set_name("emissive triangles");
+        self.emissive_triangle_buffer = Some(emissive_triangle_buffer);
+
+        let mut emissive_alias_buffer = BufferResource::new(
+            std::mem::size_of_val(alias_table.as_slice()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        emissive_alias_buffer.store(&alias_table);
+        emissive_alias_buffer.set_name("emissive alias table");
+        self.emissive_alias_buffer = Some(emissive_alias_buffer);
+    }
+
+    fn create_sun_buffer(&mut self) {
+        self.sun_buffer = Some(MappedBuffer::new(
+            &[self.sun.params()],
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            self.base.clone(),
+        ));
+    }
+
+    fn create_fog_buffers(&mut self) {
+        self.fog_buffer = Some(MappedBuffer::new(
+            &[fog_params(DEFAULT_FOG_COLOR, DEFAULT_FOG_DENSITY, None)],
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            self.base.clone(),
+        ));
+        // Storage buffers cannot be empty, the grid is skipped while its scale is 0
+        self.fog_density_buffer = Some(self.create_fog_density_buffer(&[0.0]));
+    }
+
+    fn create_debug_buffer(&mut self) {
+        let size = std::mem::size_of::<DebugBufferHeader>()
+            + std::mem::size_of::<DebugRecord>() * DEBUG_BUFFER_CAPACITY as usize;
+        self.debug_buffer = Some(BufferResource::new(
+            size as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        ));
+        self.set_debug_pixel(self.debug_pixel);
+    }
+
+    // Picks the pixel whose closest hits append to the debug buffer, None to append nothing.
+    // Pixels count from the top left of the render target.
+    pub fn set_debug_pixel(&mut self, pixel: Option<[u32; 2]>) {
+        self.debug_pixel = pixel;
+        self.debug_buffer
+            .as_mut()
+            .unwrap()
+            .store(&[DebugBufferHeader {
+                count: 0,
+                capacity: DEBUG_BUFFER_CAPACITY,
+                pixel: pixel.unwrap_or([DEBUG_PIXEL_NONE; 2]),
+            }]);
+    }
+
+    // Logs the records appended during the last traced frame and empties the buffer for the next
+    fn dump_debug_buffer(&mut self) {
+        if self.debug_pixel.is_none() {
+            return;
+        }
+        let debug_buffer = self.debug_buffer.as_mut().unwrap();
+        let size = debug_buffer.size;
+        let (header, records) = unsafe {
+            let mapped = debug_buffer.map(size);
+            let header = (mapped as *const DebugBufferHeader).read();
+            let records = std::slice::from_raw_parts(
+                (mapped as *const DebugBufferHeader).add(1) as *const DebugRecord,
+                header.count.min(header.capacity) as usize,
+            )
+            .to_vec();
+            debug_buffer.unmap();
+            (header, records)
+        };
+
+        for record in &records {
+            tracing::info!(
+                target: TARGET_SHADER,
+                "Pixel {:?} tag {}: {:?}",
+                record.pixel,
+                record.tag,
+                record.value
+            );
+        }
+        if header.count > header.capacity {
+            tracing::warn!(
+                target: TARGET_SHADER,
+                "Dropped {} debug records past the capacity of {}",
+                header.count - header.capacity,
+                header.capacity
+            );
+        }
+        debug_buffer.store(&[DebugBufferHeader { count: 0, ..header }]);
+    }
+
+    fn create_fog_density_buffer(&self, densities: &[f32]) -> BufferResource {
+        let mut buffer = BufferResource::new(
+            std::mem::size_of_val(densities) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        buffer.store(densities);
+        buffer
+    }
+
+    // Destroys everything created on the device and nulls the handles, so it can run again
+    fn release(&mut self) {
+        // The worker may still be compiling against the layout and shader modules
+        self.wait_for_pipeline();
+
+        unsafe {
+            self.base.wait_device_idle();
+
+            for slot in self.tlas_slots.drain(..) {
+                self.base
+                    .device
+                    .destroy_descriptor_pool(slot.scene_descriptor_pool, None);
+            }
+            self.bottom_as = None;
+
+            self.base
+                .device
+                .destroy_descriptor_pool(self.frame_descriptor_pool, None);
+            self.base
+                .device
+                .destroy_query_pool(self.timestamp_query_pool, None);
+            self.frame_descriptor_pool = vk::DescriptorPool::null();
+            self.timestamp_query_pool = vk::QueryPool::null();
+            for frame_context in &mut self.frame_contexts {
+                frame_context.destroy(&self.base.device);
+            }
+            self.frame_contexts.clear();
+            self.frame_slot = 0;
+
+            self.shader_binding_table = None;
+
+            self.instance_data_buffer = None;
+            self.emissive_triangle_buffer = None;
+            self.emissive_alias_buffer = None;
+            self.fog_buffer = None;
+            self.fog_density_buffer = None;
+            self.debug_buffer = None;
+
+            if let Some(restir) = self.restir.as_mut() {
+                restir.release();
+            }
+            self.restir = None;
+
+            if let Some(ray_query) = self.ray_query.as_mut() {
+                ray_query.release();
+            }
+            self.ray_query = None;
+
+            if let Some(exposure) = self.exposure.as_mut() {
+                exposure.release();
+            }
+            self.exposure = None;
+
+            if let Some(checkerboard) = self.checkerboard.as_mut() {
+                checkerboard.release();
+            }
+            self.checkerboard = None;
+
+            if let Some(skinning) = self.skinning.as_mut() {
+                skinning.release();
+            }
+            self.skinning = None;
+            self.skin_placeholder_buffer = None;
+
+            if let Some(shadow_map) = self.shadow_map.as_mut() {
+                shadow_map.release();
+            }
+            self.shadow_map = None;
+
+            if let Some(depth_prepass) = self.depth_prepass.as_mut() {
+                depth_prepass.release();
+            }
+            if let Some(light_culler) = self.light_culler.as_mut() {
+                light_culler.release();
+            }
+            self.depth_prepass = None;
+            self.light_culler = None;
+
+            if let Some(picking_prepass) = self.picking_prepass.as_mut() {
+                picking_prepass.release();
+            }
+            self.picking_prepass = None;
+
+            if let Some(selection_outline) = self.selection_outline.as_mut() {
+                selection_outline.release();
+            }
+            self.selection_outline = None;
+
+            if let Some(debug_draw) = self.debug_draw.as_mut() {
+                debug_draw.release();
+            }
+            self.debug_draw = None;
+            self.geometry_pool = None;
+            self.texture_atlas = None;
+            // Releases the profiling lock
+            self.profiler.set_backend(None);
+
+            self.base.device.destroy_pipeline(self.pipeline, None);
+            self.base
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.base
+                .device
+                .destroy_descriptor_set_layout(self.scene_set_layout, None);
+            self.base
+                .device
+                .destroy_descriptor_set_layout(self.frame_set_layout, None);
+            self.pipeline = vk::Pipeline::null();
+            self.pipeline_layout = vk::PipelineLayout::null();
+            self.scene_set_layout = vk::DescriptorSetLayout::null();
+            self.frame_set_layout = vk::DescriptorSetLayout::null();
+            self.pipeline_desc = None;
+
+            if self.pipeline_cache != vk::PipelineCache::null() {
+                self.save_pipeline_cache();
+                self.base
+                    .device
+                    .destroy_pipeline_cache(self.pipeline_cache, None);
+                self.pipeline_cache = vk::PipelineCache::null();
+            }
+
+            // Only the modules of the path create_pipeline took are set
+            let mut shader_modules = self.base.shader_modules.lock().unwrap();
+            for module in [
+                &mut self.rgen_shader_module,
+                &mut self.chit_shader_module,
+                &mut self.miss_shader_module,
+                &mut self.shadow_miss_shader_module,
+                &mut self.lib_shader_module,
+                &mut self.ahit_shader_module,
+            ] {
+                if *module != vk::ShaderModule::null() {
+                    shader_modules.release(*module);
+                    *module = vk::ShaderModule::null();
+                }
+            }
+            shader_modules.trim();
+        }
+    }
+}
+
+impl Drop for RtContext {
+    fn drop(&mut self) {
+        // Waiting on the device could panic again while unwinding, the device is lost anyway
+        if std::thread::panicking() {
+            return;
+        }
+        self.release();
+    }
+}
@@ -0,0 +1,704 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use super::RtContext;
+use crate::renderer::{environment_probes::EnvironmentProbes, resources::BufferResource};
+use crate::{
+    utility,
+    utility::{
+        camera::{Camera, Projection},
+        constants::*,
+        denoiser::{DenoiserBackend, DenoiserImages, GpuDenoiserImages},
+        environment_probe::{CubeMap, PROBE_FACES},
+        exr_output::{write_exr, ExrLayer},
+        structures::*,
+    },
+};
+use cgmath::{Point3, Vector3};
+use image::RgbaImage;
+
+use ash::vk;
+
+impl RtContext {
+    // Copies the tonemapped image of the last traced frame out as tightly packed RGBA8
+    pub fn read_tonemapped_image(&self) -> Vec<u8> {
+        let tonemapped_image = self
+            .exposure
+            .as_ref()
+            .expect("No tonemapped image to read back.")
+            .tonemapped_target
+            .image;
+        let mut pixels = self.read_image(
+            tonemapped_image,
+            4,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+        );
+
+        // Swapchain formats are usually BGRA
+        if matches!(
+            self.base.surface_format.format,
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB
+        ) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        pixels
+    }
+
+    // Hash of the bits of the last traced HDR image, equal for bit-exact frames
+    pub fn frame_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for value in self.read_hdr_image() {
+            value.to_bits().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    // The HDR image of the last traced frame before tonemapping, as RGBA
+    fn read_hdr_image(&self) -> Vec<f32> {
+        let pixels = self.read_image(
+            self.offscreen_target.image,
+            self.hdr_format.bytes_per_pixel(),
+            vk::PipelineStageFlags::RAY_TRACING_SHADER_NV
+                | vk::PipelineStageFlags::COMPUTE_SHADER
+                | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::SHADER_WRITE
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::TRANSFER_WRITE,
+        );
+        self.hdr_format.decode(&pixels)
+    }
+
+    // One AOV of the last traced frame as RGBA, only meaningful with AOV_OUTPUTS
+    fn read_aov(&self, aov: Aov) -> Vec<f32> {
+        self.read_image(
+            self.aov_targets[aov as usize].image,
+            16,
+            vk::PipelineStageFlags::RAY_TRACING_SHADER_NV | vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+        )
+        .chunks_exact(4)
+        .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+    }
+
+    // Copies a render sized image in the GENERAL layout out tightly packed, once the writes in
+    // `src_stage` are done
+    fn read_image(
+        &self,
+        image: vk::Image,
+        bytes_per_pixel: u32,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+    ) -> Vec<u8> {
+        let extent = self.render_extent();
+        let mut pixels =
+            Vec::with_capacity((extent.width * extent.height * bytes_per_pixel) as usize);
+        self.read_image_bands(image, bytes_per_pixel, src_stage, src_access, |_, band| {
+            pixels.extend_from_slice(band)
+        });
+        pixels
+    }
+
+    // Copies the image out in horizontal bands of rows, handing each one to `visit_band` with its
+    // first row in top to bottom order. The bands go through a ring of READBACK_STAGING_SLOTS
+    // staging buffers of at most READBACK_STAGING_SIZE bytes, so the host visible memory needed
+    // does not grow with the image and the copy of a band overlaps reading the one before.
+    fn read_image_bands(
+        &self,
+        image: vk::Image,
+        bytes_per_pixel: u32,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        mut visit_band: impl FnMut(u32, &[u8]),
+    ) {
+        let extent = self.render_extent();
+        let row_size = (extent.width * bytes_per_pixel) as vk::DeviceSize;
+        // A single row larger than the staging size still has to be copied whole
+        let band_rows = (READBACK_STAGING_SIZE / row_size).clamp(1, extent.height as u64) as u32;
+        let band_count = extent.height.div_ceil(band_rows);
+        let slot_count = READBACK_STAGING_SLOTS.min(band_count as usize);
+
+        let mut staging_buffers: Vec<BufferResource> = (0..slot_count)
+            .map(|_| {
+                BufferResource::new(
+                    band_rows as vk::DeviceSize * row_size,
+                    vk::BufferUsageFlags::TRANSFER_DST,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    self.base.clone(),
+                )
+            })
+            .collect();
+
+        unsafe {
+            // Cloned so the guard does not borrow self while recording
+            let base = self.base.clone();
+            let _queue = base.lock_queue();
+            let device = &self.base.device;
+
+            let fences: Vec<vk::Fence> = (0..slot_count)
+                .map(|_| {
+                    device
+                        .create_fence(&vk::FenceCreateInfo::default(), None)
+                        .expect("Failed to create the readback fence.")
+                })
+                .collect();
+            let command_buffers = device
+                .allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo::builder()
+                        .command_buffer_count(band_count)
+                        .command_pool(self.base.command_pool)
+                        .level(vk::CommandBufferLevel::PRIMARY)
+                        .build(),
+                )
+                .unwrap();
+
+            let image_barrier = vk::ImageMemoryBarrier::builder()
+                .src_access_mask(src_access)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .old_layout(vk::ImageLayout::GENERAL)
+                .new_layout(vk::ImageLayout::GENERAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .build();
+
+            // Waits for the copy of a band and hands it out, its slot is free again afterwards
+            let mut finish_band = |band: u32, staging_buffer: &mut BufferResource, fence| {
+                device
+                    .wait_for_fences(&[fence], true, u64::MAX)
+                    .expect("Failed to wait for the image readback.");
+                device.reset_fences(&[fence]).unwrap();
+                let first_row = band * band_rows;
+                let rows = band_rows.min(extent.height - first_row);
+                let size = rows as vk::DeviceSize * row_size;
+                let mapped = staging_buffer.map(size) as *const u8;
+                visit_band(first_row, std::slice::from_raw_parts(mapped, size as usize));
+                staging_buffer.unmap();
+            };
+
+            for band in 0..band_count {
+                let slot = band as usize % slot_count;
+                if band as usize >= slot_count {
+                    finish_band(
+                        band - slot_count as u32,
+                        &mut staging_buffers[slot],
+                        fences[slot],
+                    );
+                }
+
+                let first_row = band * band_rows;
+                let rows = band_rows.min(extent.height - first_row);
+                let command_buffer = command_buffers[band as usize];
+                device
+                    .begin_command_buffer(
+                        command_buffer,
+                        &vk::CommandBufferBeginInfo::builder()
+                            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                            .build(),
+                    )
+                    .unwrap();
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    src_stage,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[image_barrier],
+                );
+                device.cmd_copy_image_to_buffer(
+                    command_buffer,
+                    image,
+                    vk::ImageLayout::GENERAL,
+                    staging_buffers[slot].buffer,
+                    &[vk::BufferImageCopy {
+                        buffer_offset: 0,
+                        buffer_row_length: 0,
+                        buffer_image_height: 0,
+                        image_subresource: vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: 0,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        },
+                        image_offset: vk::Offset3D {
+                            x: 0,
+                            y: first_row as i32,
+                            z: 0,
+                        },
+                        image_extent: vk::Extent3D {
+                            width: extent.width,
+                            height: rows,
+                            depth: 1,
+                        },
+                    }],
+                );
+                device.end_command_buffer(command_buffer).unwrap();
+
+                device
+                    .queue_submit(
+                        self.base.graphics_queue,
+                        &[vk::SubmitInfo::builder()
+                            .command_buffers(&[command_buffer])
+                            .build()],
+                        fences[slot],
+                    )
+                    .expect("queue submit failed.");
+            }
+
+            // The last bands are still in flight
+            for band in band_count - slot_count as u32..band_count {
+                let slot = band as usize % slot_count;
+                finish_band(band, &mut staging_buffers[slot], fences[slot]);
+            }
+
+            device.free_command_buffers(self.base.command_pool, &command_buffers);
+            for fence in fences {
+                device.destroy_fence(fence, None);
+            }
+        }
+    }
+
+    // The HDR image and, with AOV_OUTPUTS, every AOV of the last traced frame as EXR layers:
+    // (name, channel names, samples interleaved by channel)
+    fn read_exr_layers(&self) -> Vec<(&'static str, &'static [&'static str], Vec<f32>)> {
+        let mut layers = vec![("beauty", &["R", "G", "B", "A"][..], self.read_hdr_image())];
+        if AOV_OUTPUTS {
+            for aov in Aov::ALL {
+                let channel_names = aov.channel_names();
+                let samples = self
+                    .read_aov(aov)
+                    .chunks_exact(4)
+                    .flat_map(|pixel| pixel[..channel_names.len()].to_vec())
+                    .collect();
+                layers.push((aov.name(), channel_names, samples));
+            }
+        }
+        layers
+    }
+
+    fn save_exr(
+        &self,
+        output_path: &Path,
+        extent: vk::Extent2D,
+        layers: &[(&'static str, &'static [&'static str], Vec<f32>)],
+    ) {
+        let exr_layers: Vec<ExrLayer> = layers
+            .iter()
+            .enumerate()
+            .map(|(index, (name, channel_names, samples))| ExrLayer {
+                name,
+                channel_names,
+                samples,
+                precision: if index == 0 {
+                    EXR_BEAUTY_PRECISION
+                } else {
+                    EXR_AOV_PRECISION
+                },
+            })
+            .collect();
+        write_exr(output_path, extent.width, extent.height, &exr_layers)
+            .unwrap_or_else(|err| panic!("Failed to save {:?}: {}", output_path, err));
+        tracing::info!(
+            "Saved {}x{} EXR with {} layers to {:?}",
+            extent.width,
+            extent.height,
+            layers.len(),
+            output_path
+        );
+    }
+
+    // Frames that are saved rather than presented, denoised when settings.denoise is on
+    fn trace_offline_frame(&mut self) {
+        self.trace_frame();
+        if !self.settings.denoise {
+            return;
+        }
+        if self.denoiser.is_none() {
+            tracing::warn!("No denoiser available, saving the frame as traced");
+            return;
+        }
+        self.denoise_frame();
+        // The tonemapped image is made from the denoised one
+        self.record_exposure();
+    }
+
+    fn denoise_frame(&mut self) {
+        let _span = tracing::info_span!("denoise").entered();
+        match self.denoiser {
+            Some(DenoiserBackend::Cpu(_)) => self.denoise_on_host(),
+            Some(DenoiserBackend::Gpu(_)) => self.denoise_on_device(),
+            None => {}
+        }
+    }
+
+    fn denoise_on_host(&mut self) {
+        let extent = self.render_extent();
+        let color = self.read_hdr_image();
+        // Only written by the shaders with AOV_OUTPUTS
+        let guides = AOV_OUTPUTS.then(|| (self.read_aov(Aov::Albedo), self.read_aov(Aov::Normal)));
+        let images = DenoiserImages {
+            width: extent.width,
+            height: extent.height,
+            color: &color,
+            albedo: guides.as_ref().map(|(albedo, _)| albedo.as_slice()),
+            normal: guides.as_ref().map(|(_, normal)| normal.as_slice()),
+        };
+
+        let denoised = match self.denoiser.as_mut() {
+            Some(DenoiserBackend::Cpu(denoiser)) => denoiser
+                .denoise(&images)
+                .map_err(|message| format!("{} failed: {}", denoiser.name(), message)),
+            _ => return,
+        };
+        match denoised {
+            Ok(denoised) => self.write_hdr_image(&denoised),
+            Err(message) => tracing::error!("{}", message),
+        }
+    }
+
+    fn denoise_on_device(&mut self) {
+        let images = GpuDenoiserImages {
+            extent: self.render_extent(),
+            color: self.offscreen_target.image,
+            albedo: AOV_OUTPUTS.then(|| self.aov_targets[Aov::Albedo as usize].image),
+            normal: AOV_OUTPUTS.then(|| self.aov_targets[Aov::Normal as usize].image),
+        };
+
+        let _queue = self.base.lock_queue();
+        let command_buffer =
+            utility::general::begin_single_time_command(&self.base.device, self.base.command_pool);
+        // The trace was waited for, only its writes have to be made visible
+        let trace_barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
+            .build();
+        self.base
+            .synchronization
+            .pipeline_barrier(command_buffer, &[trace_barrier], &[]);
+        if let Some(DenoiserBackend::Gpu(denoiser)) = self.denoiser.as_mut() {
+            denoiser.record(command_buffer, &images);
+        }
+        utility::general::end_single_time_command(
+            &self.base.device,
+            self.base.command_pool,
+            self.base.graphics_queue,
+            command_buffer,
+        );
+    }
+
+    // Replaces the HDR image of the last traced frame, `pixels` is RGBA
+    fn write_hdr_image(&self, pixels: &[f32]) {
+        let extent = self.render_extent();
+        let texels = self.hdr_format.encode(pixels);
+
+        let mut staging_buffer = BufferResource::new(
+            std::mem::size_of_val(texels.as_slice()) as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        staging_buffer.store(&texels);
+
+        let _queue = self.base.lock_queue();
+        let command_buffer =
+            utility::general::begin_single_time_command(&self.base.device, self.base.command_pool);
+        unsafe {
+            // The image stays in GENERAL, only the readback before has to be done with it
+            let read_barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .build();
+            self.base.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[read_barrier],
+                &[],
+                &[],
+            );
+
+            self.base.device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_buffer.buffer,
+                self.offscreen_target.image,
+                vk::ImageLayout::GENERAL,
+                &[vk::BufferImageCopy {
+                    buffer_offset: 0,
+                    buffer_row_length: 0,
+                    buffer_image_height: 0,
+                    image_subresource: vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: 0,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    },
+                    image_offset: vk::Offset3D::default(),
+                    image_extent: vk::Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    },
+                }],
+            );
+        }
+        utility::general::end_single_time_command(
+            &self.base.device,
+            self.base.command_pool,
+            self.base.graphics_queue,
+            command_buffer,
+        );
+    }
+
+    // Tonemaps the HDR image again after it was changed outside of trace_frame
+    fn record_exposure(&mut self) {
+        let exposure = match self.exposure.as_mut() {
+            Some(exposure) => exposure,
+            None => return,
+        };
+
+        let _queue = self.base.lock_queue();
+        let command_buffer =
+            utility::general::begin_single_time_command(&self.base.device, self.base.command_pool);
+        let hdr_barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_READ)
+            .build();
+        self.base
+            .synchronization
+            .pipeline_barrier(command_buffer, &[hdr_barrier], &[]);
+        exposure.record(command_buffer);
+        utility::general::end_single_time_command(
+            &self.base.device,
+            self.base.command_pool,
+            self.base.graphics_queue,
+            command_buffer,
+        );
+    }
+
+    // Traces a frame and saves the HDR image with its AOVs
+    pub fn save_aovs(&mut self, output_path: &Path) {
+        if !AOV_OUTPUTS {
+            tracing::warn!("AOV_OUTPUTS is off, only the HDR image is saved");
+        }
+        self.wait_for_pipeline();
+        self.trace_offline_frame();
+        let layers = self.read_exr_layers();
+        self.save_exr(output_path, self.render_extent(), &layers);
+    }
+
+    // Renders the camera view as a `columns` x `rows` grid of window sized tiles and saves
+    // the stitched image, which may be larger than any image the device can create. An .exr
+    // path saves the HDR image and the AOVs instead of the tonemapped one.
+    pub fn render_tiled(&mut self, columns: u32, rows: u32, output_path: &Path) {
+        let extent = self.render_extent();
+        let aspect_ratio = extent.width as f32 / extent.height as f32;
+        let camera = self.camera;
+        // The tiles are stitched at the size measured here, the scale may not move in between
+        let dynamic_resolution = self.dynamic_resolution.take();
+
+        // Meter the full view once and hold that exposure so the tiles match up
+        self.wait_for_pipeline();
+        self.trace_frame();
+        if let Some(exposure) = self.exposure.as_mut() {
+            exposure.set_adaptation_speed(0.0);
+        }
+
+        let save_exr = output_path.extension() == Some("exr".as_ref());
+        let stitched_extent = vk::Extent2D {
+            width: extent.width * columns,
+            height: extent.height * rows,
+        };
+        let mut stitched_layers = Vec::new();
+
+        let mut stitched = image::RgbaImage::new(stitched_extent.width, stitched_extent.height);
+        for row in 0..rows {
+            for column in 0..columns {
+                self.camera = camera.tile(aspect_ratio, columns, rows, column, row);
+                self.trace_offline_frame();
+
+                if save_exr {
+                    let layers = self.read_exr_layers();
+                    if stitched_layers.is_empty() {
+                        stitched_layers = layers
+                            .iter()
+                            .map(|(name, channel_names, _)| {
+                                let size = (stitched_extent.width * stitched_extent.height)
+                                    as usize
+                                    * channel_names.len();
+                                (*name, *channel_names, vec![0.0; size])
+                            })
+                            .collect();
+                    }
+                    for ((_, channel_names, stitched), (_, _, tile)) in
+                        stitched_layers.iter_mut().zip(&layers)
+                    {
+                        let tile_row = extent.width as usize * channel_names.len();
+                        let stitched_row = stitched_extent.width as usize * channel_names.len();
+                        for (y, source) in tile.chunks_exact(tile_row).enumerate() {
+                            let start = (row * extent.height) as usize * stitched_row
+                                + y * stitched_row
+                                + column as usize * tile_row;
+                            stitched[start..start + tile_row].copy_from_slice(source);
+                        }
+                    }
+                    continue;
+                }
+
+                let tile = image::RgbaImage::from_raw(
+                    extent.width,
+                    extent.height,
+                    self.read_tonemapped_image(),
+                )
+                .expect("Tile readback has the wrong size.");
+                image::imageops::replace(
+                    &mut stitched,
+                    &tile,
+                    (column * extent.width) as i64,
+                    (row * extent.height) as i64,
+                );
+            }
+        }
+
+        self.camera = camera;
+        self.dynamic_resolution = dynamic_resolution;
+        if let Some(exposure) = self.exposure.as_mut() {
+            exposure.set_adaptation_speed(self.settings.exposure_adaptation_speed);
+        }
+
+        if save_exr {
+            self.save_exr(output_path, stitched_extent, &stitched_layers);
+            return;
+        }
+        stitched.save(output_path).unwrap_or_else(|err| {
+            panic!("Failed to save tiled render to {:?}: {}", output_path, err)
+        });
+        tracing::info!(
+            "Saved {}x{} tiled render to {:?}",
+            stitched.width(),
+            stitched.height(),
+            output_path
+        );
+    }
+
+    // Traces the frame with shadow rays on the left and shades it with the shadow map on the
+    // right, both at the exposure metered for the first
+    pub fn compare_shadows(&mut self, output_path: &Path) {
+        if self.shadow_map.is_none() {
+            tracing::warn!("Shadow maps are only rendered in RenderMode::Hybrid");
+            return;
+        }
+        let extent = self.render_extent();
+        let shadow_map = self.settings.shadow_map;
+        let dynamic_resolution = self.dynamic_resolution.take();
+
+        self.wait_for_pipeline();
+        self.settings.shadow_map = false;
+        self.trace_frame();
+        if let Some(exposure) = self.exposure.as_mut() {
+            exposure.set_adaptation_speed(0.0);
+        }
+
+        let mut compared = RgbaImage::new(2 * extent.width, extent.height);
+        for (column, use_shadow_map) in [false, true].into_iter().enumerate() {
+            self.settings.shadow_map = use_shadow_map;
+            self.trace_frame();
+            let frame =
+                RgbaImage::from_raw(extent.width, extent.height, self.read_tonemapped_image())
+                    .expect("Frame readback has the wrong size.");
+            image::imageops::replace(
+                &mut compared,
+                &frame,
+                (column as u32 * extent.width) as i64,
+                0,
+            );
+        }
+
+        self.settings.shadow_map = shadow_map;
+        self.dynamic_resolution = dynamic_resolution;
+        if let Some(exposure) = self.exposure.as_mut() {
+            exposure.set_adaptation_speed(self.settings.exposure_adaptation_speed);
+        }
+
+        compared.save(output_path).unwrap_or_else(|err| {
+            panic!(
+                "Failed to save shadow comparison to {:?}: {}",
+                output_path, err
+            )
+        });
+        tracing::info!("Saved shadow comparison to {:?}", output_path);
+    }
+
+    // Traces the six faces of a cube map at every position, prefilters them for roughness and
+    // hands them to the shadow map shading for its reflections, replacing the probes it had. The
+    // faces are traced in the current render mode, so with the shadow map shading on they
+    // already reflect the probes captured before.
+    pub fn capture_environment_probes(&mut self, positions: &[Point3<f32>]) {
+        let _span = tracing::info_span!("capture_environment_probes").entered();
+        let camera = self.camera;
+        let dynamic_resolution = self.dynamic_resolution.take();
+        self.wait_for_pipeline();
+
+        let extent = self.render_extent();
+        let mut probes = Vec::with_capacity(positions.len());
+        for &position in positions {
+            let mut faces = Vec::with_capacity(PROBE_FACES.len());
+            for (forward, up) in PROBE_FACES {
+                // A 90 degree window on both axes whatever the aspect ratio of the render size
+                self.camera = Camera {
+                    position,
+                    target: position + Vector3::from(forward),
+                    up: Vector3::from(up),
+                    projection: Projection::OffAxis {
+                        left: -1.0,
+                        right: 1.0,
+                        bottom: -1.0,
+                        top: 1.0,
+                    },
+                    ..camera
+                };
+                self.trace_offline_frame();
+                faces.push(self.read_hdr_image());
+            }
+            let cube_map =
+                CubeMap::from_faces(&faces, extent.width, extent.height, ENVIRONMENT_PROBE_SIZE);
+            probes.push((
+                position,
+                cube_map.prefilter_levels(ENVIRONMENT_PROBE_MIP_LEVELS),
+            ));
+        }
+
+        self.camera = camera;
+        self.dynamic_resolution = dynamic_resolution;
+        if self.shadow_map.is_none() {
+            tracing::warn!("Environment probes are only reflected by the shadow map shading of RenderMode::Hybrid");
+        }
+
+        self.base.wait_device_idle();
+        self.environment_probes = EnvironmentProbes::new(self.base.clone(), &probes);
+        if let Some(shadow_map) = self.shadow_map.as_mut() {
+            shadow_map.set_environment_probes(&self.environment_probes);
+        }
+        tracing::info!("Captured {} environment probes", probes.len());
+    }
+}
@@ -0,0 +1,1066 @@
+use std::{fmt::Write as _, path::Path, ptr};
+
+use super::RtContext;
+use crate::renderer::{
+    pipelines::{
+        create_ray_tracing_pipeline, ray_tracing_shader_groups, ray_tracing_stage_names,
+        RayTracingPipelineDesc, RayTracingShaderStages, SbtRegion, ShaderStageDesc,
+    },
+    resources::{BufferResource, ImageResource},
+};
+use crate::{
+    utility,
+    utility::{
+        constants::*, descriptor_writes::WriteBatch, dynamic_resolution::DynamicResolution,
+        logging::TARGET_SBT, structures::*,
+    },
+};
+
+use ash::vk;
+
+impl RtContext {
+    pub(super) fn create_pipeline(&mut self) {
+        unsafe {
+            // Set 0 holds what stays for the life of the scene, set 1 what is recreated with the
+            // render size, so a resize leaves the scene set alone
+            let scene_bindings = [
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::ACCELERATION_STRUCTURE_NV,
+                    stage_flags: vk::ShaderStageFlags::RAYGEN_NV
+                        | vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                    binding: 0,
+                    ..Default::default()
+                },
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    // The hybrid ray generation shader shades the rasterized hits itself, the
+                    // any-hit shader reads the opacity
+                    stage_flags: vk::ShaderStageFlags::RAYGEN_NV
+                        | vk::ShaderStageFlags::CLOSEST_HIT_NV
+                        | vk::ShaderStageFlags::ANY_HIT_NV,
+                    binding: 2,
+                    ..Default::default()
+                },
+                // emissive triangles
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    stage_flags: vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                    binding: 4,
+                    ..Default::default()
+                },
+                // emissive triangle alias table
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    stage_flags: vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                    binding: 5,
+                    ..Default::default()
+                },
+                // FogParams and the density grid, camera rays are faded where they end
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    stage_flags: vk::ShaderStageFlags::CLOSEST_HIT_NV
+                        | vk::ShaderStageFlags::MISS_NV,
+                    binding: 9,
+                    ..Default::default()
+                },
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    stage_flags: vk::ShaderStageFlags::CLOSEST_HIT_NV
+                        | vk::ShaderStageFlags::MISS_NV,
+                    binding: 10,
+                    ..Default::default()
+                },
+                // SunParams, the hybrid ray generation shader lights the rasterized hits with it
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    stage_flags: vk::ShaderStageFlags::RAYGEN_NV
+                        | vk::ShaderStageFlags::CLOSEST_HIT_NV
+                        | vk::ShaderStageFlags::MISS_NV,
+                    binding: 11,
+                    ..Default::default()
+                },
+                // The debug buffer
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    stage_flags: vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                    binding: 12,
+                    ..Default::default()
+                },
+                // The indices and deformed normals of a skinned scene mesh, placeholders without
+                // a skin
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    stage_flags: vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                    binding: 14,
+                    ..Default::default()
+                },
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    stage_flags: vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                    binding: 15,
+                    ..Default::default()
+                },
+            ];
+
+            let mut frame_bindings = vec![
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                    stage_flags: vk::ShaderStageFlags::RAYGEN_NV,
+                    binding: 1,
+                    ..Default::default()
+                },
+                // AOV images, written at the primary hit
+                vk::DescriptorSetLayoutBinding {
+                    descriptor_count: Aov::ALL.len() as u32,
+                    descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                    stage_flags: vk::ShaderStageFlags::RAYGEN_NV
+                        | vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                    binding: 8,
+                    ..Default::default()
+                },
+            ];
+
+            if self.restir.is_some() {
+                // G-buffer consumed by the ReSTIR compute passes
+                frame_bindings.push(vk::DescriptorSetLayoutBinding {
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    stage_flags: vk::ShaderStageFlags::RAYGEN_NV,
+                    binding: 3,
+                    ..Default::default()
+                });
+            }
+
+            if self.depth_prepass.is_some() {
+                // Depth and instance IDs from the raster pre-pass
+                for binding in [6, 7] {
+                    frame_bindings.push(vk::DescriptorSetLayoutBinding {
+                        descriptor_count: 1,
+                        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                        stage_flags: vk::ShaderStageFlags::RAYGEN_NV,
+                        binding,
+                        ..Default::default()
+                    });
+                }
+                // Point lights and their screen tiles from the light culler
+                for binding in [12, 13] {
+                    frame_bindings.push(vk::DescriptorSetLayoutBinding {
+                        descriptor_count: 1,
+                        descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                        stage_flags: vk::ShaderStageFlags::RAYGEN_NV,
+                        binding,
+                        ..Default::default()
+                    });
+                }
+            }
+
+            let create_layout = |bindings: &[vk::DescriptorSetLayoutBinding]| {
+                self.base
+                    .device
+                    .create_descriptor_set_layout(
+                        &vk::DescriptorSetLayoutCreateInfo::builder()
+                            .bindings(bindings)
+                            .build(),
+                        None,
+                    )
+                    .expect("Failed to create descriptor set layout.")
+            };
+            self.scene_set_layout = create_layout(&scene_bindings);
+            self.frame_set_layout = create_layout(&frame_bindings);
+
+            self.descriptor_diagnostics[SCENE_DESCRIPTOR_SET as usize]
+                .set_layout_bindings(&scene_bindings);
+            self.descriptor_diagnostics[FRAME_DESCRIPTOR_SET as usize]
+                .set_layout_bindings(&frame_bindings);
+            let stages = self.load_stage_modules();
+
+            // In SCENE_DESCRIPTOR_SET and FRAME_DESCRIPTOR_SET order
+            let layouts = [self.scene_set_layout, self.frame_set_layout];
+            let push_constant_ranges = [
+                vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::RAYGEN_NV,
+                    offset: 0,
+                    // Camera, the sample accumulation constants and the hybrid mode's depth range
+                    size: RAY_MASK_PUSH_CONSTANT_OFFSET,
+                },
+                // The any-hit shader rolls with the sample index
+                vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::ANY_HIT_NV,
+                    offset: std::mem::size_of::<CameraPushConstants>() as u32,
+                    size: std::mem::size_of::<AccumulationPushConstants>() as u32,
+                },
+                // Closest hit shaders cull their shadow rays with the secondary mask, start them
+                // with the ray bias and write the AOVs at the viewport offset
+                vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::RAYGEN_NV
+                        | vk::ShaderStageFlags::CLOSEST_HIT_NV,
+                    offset: RAY_MASK_PUSH_CONSTANT_OFFSET,
+                    size: (std::mem::size_of::<RayMaskPushConstants>()
+                        + std::mem::size_of::<ViewportPushConstants>()
+                        + std::mem::size_of::<RayBiasPushConstants>())
+                        as u32,
+                },
+            ];
+            self.push_constant_diagnostics
+                .set_ranges(&push_constant_ranges);
+            self.verify_shader_interface();
+
+            let layout_create_info = vk::PipelineLayoutCreateInfo {
+                s_type: vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
+                p_next: ptr::null(),
+                set_layout_count: layouts.len() as u32,
+                p_set_layouts: layouts.as_ptr(),
+                push_constant_range_count: push_constant_ranges.len() as u32,
+                p_push_constant_ranges: push_constant_ranges.as_ptr(),
+                ..Default::default()
+            };
+
+            self.pipeline_layout = self
+                .base
+                .device
+                .create_pipeline_layout(&layout_create_info, None)
+                .expect("Failed to create pipeline layout.");
+
+            self.pipeline_cache = self.create_pipeline_cache();
+
+            self.pipeline_desc = Some(RayTracingPipelineDesc {
+                layout: self.pipeline_layout,
+                cache: self.pipeline_cache,
+                stages,
+                max_recursion_depth: self.settings.max_recursion_depth,
+                skinned: self.skinning.is_some(),
+            });
+        }
+
+        self.compile_pipeline();
+    }
+
+    // The shader modules of the ray tracing stages, and their bindings for the descriptor
+    // diagnostics
+    fn load_stage_modules(&mut self) -> RayTracingShaderStages {
+        for diagnostics in &mut self.descriptor_diagnostics {
+            diagnostics.clear_shaders();
+        }
+        self.push_constant_diagnostics.clear_shaders();
+
+        let use_hlsl = true;
+        let use_bindless = true;
+        let mut shader_modules = self.base.shader_modules.lock().unwrap();
+        let mut stages = if self.shader_library && use_hlsl {
+            self.lib_shader_module =
+                shader_modules.load(Path::new("shaders/compiled/triangle.hlsl_lib.spv"));
+            for diagnostics in &mut self.descriptor_diagnostics {
+                diagnostics.add_shader("lib", shader_modules.code(self.lib_shader_module));
+            }
+            self.push_constant_diagnostics
+                .add_shader("lib", shader_modules.code(self.lib_shader_module));
+
+            RayTracingShaderStages::from_library(self.lib_shader_module)
+        } else {
+            let lang = if use_hlsl { "hlsl_" } else { "glsl_" };
+
+            let variant = if use_bindless { "bindless_" } else { "" };
+
+            // The ReSTIR shaders only write the G-buffer and are GLSL only
+            let (rgen_path, rchit_path, rmiss_path) = if self.restir.is_some() {
+                (
+                    String::from("shaders/compiled/triangle.glsl_restir_rgen.spv"),
+                    String::from("shaders/compiled/triangle.glsl_restir_rchit.spv"),
+                    String::from("shaders/compiled/triangle.glsl_restir_rmiss.spv"),
+                )
+            } else if self.depth_prepass.is_some() {
+                // Only shadow rays are traced, the hit and miss shaders stay the standard ones
+                (
+                    String::from("shaders/compiled/triangle.glsl_hybrid_rgen.spv"),
+                    format!("shaders/compiled/triangle.{}{}rchit.spv", lang, variant),
+                    format!("shaders/compiled/triangle.{}rmiss.spv", lang),
+                )
+            } else {
+                (
+                    format!("shaders/compiled/triangle.{}rgen.spv", lang),
+                    format!("shaders/compiled/triangle.{}{}rchit.spv", lang, variant),
+                    format!("shaders/compiled/triangle.{}rmiss.spv", lang),
+                )
+            };
+            self.rgen_shader_module = shader_modules.load(Path::new(&rgen_path));
+            self.chit_shader_module = shader_modules.load(Path::new(&rchit_path));
+            self.miss_shader_module = shader_modules.load(Path::new(&rmiss_path));
+            self.shadow_miss_shader_module = shader_modules.load(Path::new(&format!(
+                "shaders/compiled/triangle.{}shadow_rmiss.spv",
+                lang
+            )));
+            for (name, module) in [
+                ("rgen", self.rgen_shader_module),
+                ("rchit", self.chit_shader_module),
+                ("rmiss", self.miss_shader_module),
+                ("shadow rmiss", self.shadow_miss_shader_module),
+            ] {
+                for diagnostics in &mut self.descriptor_diagnostics {
+                    diagnostics.add_shader(name, shader_modules.code(module));
+                }
+                self.push_constant_diagnostics
+                    .add_shader(name, shader_modules.code(module));
+            }
+
+            RayTracingShaderStages::from_modules(
+                self.rgen_shader_module,
+                self.chit_shader_module,
+                self.miss_shader_module,
+                self.shadow_miss_shader_module,
+            )
+        };
+
+        // The GLSL any-hit shader goes with the HLSL library and the modules alike, a pipeline
+        // downgrade keeps it
+        if self.stochastic_transparency {
+            if self.ahit_shader_module == vk::ShaderModule::null() {
+                self.ahit_shader_module = shader_modules.load(Path::new(
+                    "shaders/compiled/triangle.glsl_transparent_rahit.spv",
+                ));
+            }
+            for diagnostics in &mut self.descriptor_diagnostics {
+                diagnostics.add_shader("rahit", shader_modules.code(self.ahit_shader_module));
+            }
+            self.push_constant_diagnostics
+                .add_shader("rahit", shader_modules.code(self.ahit_shader_module));
+            stages.any_hit = Some(ShaderStageDesc::new(
+                vk::ShaderStageFlags::ANY_HIT_NV,
+                self.ahit_shader_module,
+                c"main",
+            ));
+        }
+        stages
+    }
+
+    fn compile_pipeline(&mut self) {
+        let mut desc = self
+            .pipeline_desc
+            .expect("Pipeline layout and shaders must be created first.");
+        desc.max_recursion_depth = self
+            .settings
+            .max_recursion_depth
+            .min(self.max_recursion_depth);
+        if desc.max_recursion_depth < self.settings.max_recursion_depth {
+            tracing::warn!(
+                "Recursion depth {} lowered to {}",
+                self.settings.max_recursion_depth,
+                desc.max_recursion_depth
+            );
+        }
+        self.validation_errors_seen = utility::debug::validation_error_count();
+
+        // A pipeline found in the cache is created right away, anything else is compiled
+        // on a worker thread while the placeholder is shown
+        if self.pipeline_creation_cache_control {
+            match create_ray_tracing_pipeline(
+                &self.ray_tracing,
+                &desc,
+                vk::PipelineCreateFlags::FAIL_ON_PIPELINE_COMPILE_REQUIRED,
+            ) {
+                Ok(pipeline) => {
+                    self.pipeline = pipeline;
+                    self.check_validation_errors("pipeline creation");
+                    return;
+                }
+                Err(vk::Result::PIPELINE_COMPILE_REQUIRED) => {}
+                Err(err) => {
+                    self.downgrade_pipeline(err);
+                    self.compile_pipeline();
+                    return;
+                }
+            }
+        }
+
+        let ray_tracing = (*self.ray_tracing).clone();
+        self.pending_pipeline = Some(std::thread::spawn(move || {
+            create_ray_tracing_pipeline(&ray_tracing, &desc, vk::PipelineCreateFlags::empty())
+        }));
+    }
+
+    // Applies new settings, rebuilding only what the changed knobs require
+    pub fn apply_settings(&mut self, settings: RenderSettings) {
+        let changes = settings.changes_from(&self.settings);
+        // Turning it on or changing the target starts over from the settings given, which also
+        // bound what the controller may raise them to
+        if settings.target_fps != self.settings.target_fps {
+            self.dynamic_resolution = (settings.target_fps > 0.0 && !self.deterministic)
+                .then(|| DynamicResolution::new(settings.target_fps, &settings));
+        }
+        self.settings = settings;
+
+        if let Some(exposure) = self.exposure.as_mut() {
+            exposure.set_adaptation_speed(settings.exposure_adaptation_speed);
+            exposure.set_ev_compensation(settings.ev_compensation);
+            exposure.set_tone_curve(settings.tone_curve);
+        }
+
+        if settings.debug_draw && self.debug_draw.is_none() {
+            let extent = self.render_extent();
+            let aspect_ratio = extent.width as f32 / extent.height as f32;
+            self.debug_frustum = self.camera.clip_from_world(
+                aspect_ratio,
+                DEFAULT_NEAR_PLANE,
+                DEBUG_DRAW_FRUSTUM_FAR_PLANE,
+            );
+            self.create_debug_draw();
+        } else if !settings.debug_draw {
+            if let Some(debug_draw) = self.debug_draw.as_mut() {
+                self.base.wait_device_idle();
+                debug_draw.release();
+            }
+            self.debug_draw = None;
+        }
+
+        if settings.checkerboard && self.checkerboard.is_none() {
+            self.create_checkerboard_pass();
+        } else if !settings.checkerboard {
+            if let Some(checkerboard) = self.checkerboard.as_mut() {
+                self.base.wait_device_idle();
+                checkerboard.release();
+            }
+            self.checkerboard = None;
+        }
+
+        if changes.pipeline {
+            self.wait_for_pipeline();
+            self.base.wait_device_idle();
+            unsafe {
+                self.base.device.destroy_pipeline(self.pipeline, None);
+            }
+            self.pipeline = vk::Pipeline::null();
+            self.shader_binding_table = None;
+            self.compile_pipeline();
+        }
+
+        if changes.descriptors {
+            self.base.wait_device_idle();
+
+            if let Some(restir) = self.restir.as_mut() {
+                restir.release();
+            }
+            if let Some(ray_query) = self.ray_query.as_mut() {
+                ray_query.release();
+            }
+            if let Some(exposure) = self.exposure.as_mut() {
+                exposure.release();
+            }
+            if let Some(checkerboard) = self.checkerboard.as_mut() {
+                checkerboard.release();
+            }
+            if let Some(shadow_map) = self.shadow_map.as_mut() {
+                shadow_map.release();
+            }
+            self.shadow_map = None;
+            if let Some(depth_prepass) = self.depth_prepass.as_mut() {
+                depth_prepass.release();
+            }
+            if let Some(light_culler) = self.light_culler.as_mut() {
+                light_culler.release();
+            }
+            // Recreated at the new size on the next selection
+            if let Some(picking_prepass) = self.picking_prepass.as_mut() {
+                picking_prepass.release();
+            }
+            self.picking_prepass = None;
+            if let Some(selection_outline) = self.selection_outline.as_mut() {
+                selection_outline.release();
+            }
+            self.selection_outline = None;
+            if let Some(debug_draw) = self.debug_draw.as_mut() {
+                debug_draw.release();
+            }
+            // The scene set does not depend on the render size
+            unsafe {
+                self.base
+                    .device
+                    .destroy_descriptor_pool(self.frame_descriptor_pool, None);
+            }
+
+            self.offscreen_target = ImageResource::new(self.base.clone());
+            self.create_offscreen_target();
+            self.create_aov_targets();
+            self.create_exposure_pass();
+            // Starts over without history at the new size
+            if self.checkerboard.is_some() {
+                self.create_checkerboard_pass();
+            }
+            if self.restir.is_some() {
+                self.create_restir_pass();
+            }
+            self.initialize_ray_query_pass();
+            if self.depth_prepass.is_some() {
+                self.create_depth_prepass();
+            }
+            self.create_shadow_map_pass();
+            if self.debug_draw.is_some() {
+                self.create_debug_draw();
+            }
+            self.create_frame_descriptor_set();
+        }
+
+        // Modules of passes that were recreated above got reused, the rest can go
+        self.base.shader_modules.lock().unwrap().trim();
+    }
+
+    fn create_pipeline_cache(&self) -> vk::PipelineCache {
+        // Data from another driver or device is rejected by the driver, which then starts empty
+        let initial_data = std::fs::read(PIPELINE_CACHE_PATH).unwrap_or_default();
+        let pipeline_cache_create_info = vk::PipelineCacheCreateInfo::builder()
+            .initial_data(&initial_data)
+            .build();
+
+        unsafe {
+            self.base
+                .device
+                .create_pipeline_cache(&pipeline_cache_create_info, None)
+                .expect("Failed to create pipeline cache.")
+        }
+    }
+
+    pub(super) fn save_pipeline_cache(&self) {
+        let cache_data = unsafe {
+            self.base
+                .device
+                .get_pipeline_cache_data(self.pipeline_cache)
+                .expect("Failed to get pipeline cache data.")
+        };
+
+        if let Err(err) = std::fs::write(PIPELINE_CACHE_PATH, cache_data) {
+            tracing::warn!("Failed to save pipeline cache: {}", err);
+        }
+    }
+
+    // Returns true once the ray tracing pipeline and its shader binding table can be used
+    pub(super) fn poll_pipeline(&mut self) -> bool {
+        if let Some(pending_pipeline) = &self.pending_pipeline {
+            if !pending_pipeline.is_finished() {
+                return false;
+            }
+        }
+
+        self.wait_for_pipeline();
+        true
+    }
+
+    pub(super) fn wait_for_pipeline(&mut self) {
+        // A downgraded pipeline may be compiled on a worker again
+        while let Some(pending_pipeline) = self.pending_pipeline.take() {
+            let result = pending_pipeline
+                .join()
+                .expect("Ray tracing pipeline worker panicked.");
+            match result {
+                Ok(pipeline) => {
+                    self.pipeline = pipeline;
+                    self.check_validation_errors("pipeline creation");
+                }
+                Err(err) => {
+                    self.downgrade_pipeline(err);
+                    self.compile_pipeline();
+                }
+            }
+        }
+
+        if self.shader_binding_table.is_none() && self.pipeline != vk::Pipeline::null() {
+            self.create_shader_binding_table();
+        }
+    }
+
+    // Called when the driver rejected the pipeline, switches to the next simpler configuration
+    // for compile_pipeline to try: a module per stage instead of the shader library, then half
+    // the recursion depth down to the primary and shadow rays the shaders trace. Panics when
+    // there is nothing left to give up.
+    fn downgrade_pipeline(&mut self, err: vk::Result) {
+        let mut desc = self
+            .pipeline_desc
+            .expect("Pipeline layout and shaders must be created first.");
+        let recursion_depth = self
+            .settings
+            .max_recursion_depth
+            .min(self.max_recursion_depth);
+
+        if self.shader_library {
+            tracing::warn!(
+                "Failed to create the ray tracing pipeline from the shader library: {:?}, \
+                 retrying with a module per stage",
+                err
+            );
+            self.shader_library = false;
+            self.base
+                .shader_modules
+                .lock()
+                .unwrap()
+                .release(self.lib_shader_module);
+            self.lib_shader_module = vk::ShaderModule::null();
+            desc.stages = self.load_stage_modules();
+            self.pipeline_desc = Some(desc);
+        } else if recursion_depth > MIN_RECURSION_DEPTH {
+            self.max_recursion_depth = (recursion_depth / 2).max(MIN_RECURSION_DEPTH);
+            tracing::warn!(
+                "Failed to create the ray tracing pipeline with a recursion depth of {}: {:?}, \
+                 retrying with {}",
+                recursion_depth,
+                err,
+                self.max_recursion_depth
+            );
+        } else {
+            panic!(
+                "Failed to create ray tracing pipeline: {:?}\n{}",
+                err,
+                self.descriptor_diagnostics_report()
+            );
+        }
+    }
+
+    // Debug builds refuse to create a pipeline whose shaders declare the descriptors or push
+    // constants otherwise than the host lays them out, which would only show as garbage shading
+    fn verify_shader_interface(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let mut report = String::new();
+        for diagnostics in &self.descriptor_diagnostics {
+            if !diagnostics.shader_mismatches().is_empty() {
+                report += &diagnostics.to_string();
+            }
+        }
+        if !self.push_constant_diagnostics.mismatches().is_empty() {
+            report += &self.push_constant_diagnostics.to_string();
+        }
+        if !report.is_empty() {
+            panic!(
+                "The ray tracing shaders do not match the host side layout.\n{}",
+                report
+            );
+        }
+    }
+
+    // The descriptor tables of every set and the push constants of the ray tracing pipeline
+    fn descriptor_diagnostics_report(&self) -> String {
+        let mut report: String = self
+            .descriptor_diagnostics
+            .iter()
+            .map(|diagnostics| diagnostics.to_string())
+            .collect();
+        report += &self.push_constant_diagnostics.to_string();
+        report
+    }
+
+    // Dumps the descriptor table when the validation layers reported errors since the last check
+    pub(super) fn check_validation_errors(&mut self, operation: &str) {
+        let error_count = utility::debug::validation_error_count();
+        if error_count > self.validation_errors_seen {
+            tracing::error!(
+                "{} validation error(s) during {}",
+                error_count - self.validation_errors_seen,
+                operation
+            );
+            tracing::error!("{}", self.descriptor_diagnostics_report());
+        }
+        self.validation_errors_seen = error_count;
+    }
+
+    fn create_shader_binding_table(&mut self) {
+        // Ray generation, the hit groups and both miss groups, in pipeline order
+        let group_count = 3 + HitGroup::ALL.len() as u32;
+        let table_size = (self.properties.shader_group_handle_size * group_count) as u64;
+        let mut table_data: Vec<u8> = vec![0u8; table_size as usize];
+
+        unsafe {
+            self.ray_tracing
+                .get_ray_tracing_shader_group_handles(
+                    self.pipeline,
+                    0,
+                    group_count,
+                    &mut table_data,
+                )
+                .expect("Failed to get ray tracing shader group handles.");
+        }
+
+        let mut shader_binding_table = BufferResource::new(
+            table_size,
+            vk::BufferUsageFlags::RAY_TRACING_NV,
+            vk::MemoryPropertyFlags::HOST_VISIBLE,
+            self.base.clone(),
+        );
+        shader_binding_table.store(&table_data);
+        self.shader_binding_table = Some(shader_binding_table);
+
+        tracing::debug!(
+            target: TARGET_SBT,
+            "Created shader binding table: {} groups of {} bytes",
+            group_count,
+            self.properties.shader_group_handle_size
+        );
+    }
+
+    // The raygen, miss and hit regions cmd_trace_rays reads from the shader binding table
+    pub(super) fn shader_binding_table_regions(&self) -> [SbtRegion; 3] {
+        let handle_size = self.properties.shader_group_handle_size as vk::DeviceSize;
+        let hit_group_count = HitGroup::ALL.len() as vk::DeviceSize;
+        [
+            SbtRegion {
+                name: "raygen",
+                first_group: 0,
+                offset: 0,
+                stride: handle_size,
+                count: 1,
+            },
+            SbtRegion {
+                name: "miss",
+                first_group: 1 + hit_group_count,
+                offset: (1 + hit_group_count) * handle_size,
+                stride: handle_size,
+                count: 2,
+            },
+            SbtRegion {
+                name: "hit",
+                first_group: 1,
+                offset: handle_size,
+                stride: handle_size,
+                count: hit_group_count,
+            },
+        ]
+    }
+
+    // The shader groups and the stages they use, the shader binding table records with the group
+    // handles and the descriptor tables, waiting for the pipeline first. Records that
+    // break the alignment rules or run past the table are marked, the driver does not complain
+    // about them but traces garbage.
+    pub fn pipeline_info(&mut self) -> String {
+        self.wait_for_pipeline();
+        let mut text = String::new();
+        let stage_names = ray_tracing_stage_names(self.stochastic_transparency);
+        let stage_name = |index: u32| {
+            if index == vk::SHADER_UNUSED_NV {
+                String::from("-")
+            } else {
+                stage_names
+                    .get(index as usize)
+                    .map_or(format!("{} (out of range)", index), |name| {
+                        format!("{} {}", index, name)
+                    })
+            }
+        };
+
+        let shader_groups = ray_tracing_shader_groups(self.stochastic_transparency);
+        let _ = writeln!(text, "Shader groups:");
+        for (index, group) in shader_groups.iter().enumerate() {
+            let _ = writeln!(
+                text,
+                " {}: {:?} general: {}, closest hit: {}, any hit: {}, intersection: {}",
+                index,
+                group.ty,
+                stage_name(group.general_shader),
+                stage_name(group.closest_hit_shader),
+                stage_name(group.any_hit_shader),
+                stage_name(group.intersection_shader),
+            );
+        }
+
+        let _ = writeln!(text, "Stages:");
+        for (index, name) in stage_names.iter().enumerate() {
+            let groups: Vec<String> = shader_groups
+                .iter()
+                .enumerate()
+                .filter(|(_, group)| {
+                    [
+                        group.general_shader,
+                        group.closest_hit_shader,
+                        group.any_hit_shader,
+                        group.intersection_shader,
+                    ]
+                    .contains(&(index as u32))
+                })
+                .map(|(group, _)| group.to_string())
+                .collect();
+            let groups = if groups.is_empty() {
+                String::from("none")
+            } else {
+                groups.join(", ")
+            };
+            let _ = writeln!(text, " {}: {} in groups {}", index, name, groups);
+        }
+
+        let table = match (&self.shader_binding_table, self.pipeline) {
+            (Some(table), pipeline) if pipeline != vk::Pipeline::null() => table,
+            _ => {
+                tracing::warn!("No shader binding table, the pipeline is not created");
+                text += &self.descriptor_diagnostics_report();
+                return text;
+            }
+        };
+        let handle_size = self.properties.shader_group_handle_size as usize;
+        let mut handles = vec![0u8; handle_size * shader_groups.len()];
+        unsafe {
+            self.ray_tracing
+                .get_ray_tracing_shader_group_handles(
+                    self.pipeline,
+                    0,
+                    shader_groups.len() as u32,
+                    &mut handles,
+                )
+                .expect("Failed to get ray tracing shader group handles.");
+        }
+
+        let _ =
+            writeln!(text,
+            "Shader binding table: {} bytes, handles of {} bytes, base alignment {}, max stride {}",
+            table.size,
+            handle_size,
+            self.properties.shader_group_base_alignment,
+            self.properties.max_shader_group_stride
+        );
+        for region in self.shader_binding_table_regions() {
+            let mut problems = Vec::new();
+            if region.offset % self.properties.shader_group_base_alignment as vk::DeviceSize != 0 {
+                problems.push("offset not base aligned");
+            }
+            if region.stride < handle_size as vk::DeviceSize
+                || region.stride > self.properties.max_shader_group_stride as vk::DeviceSize
+            {
+                problems.push("stride out of range");
+            }
+            if region.offset + region.stride * region.count > table.size {
+                problems.push("past the end of the table");
+            }
+            let _ = writeln!(
+                text,
+                " {}: offset {:#x}, stride {:#x}, {} records{}",
+                region.name,
+                region.offset,
+                region.stride,
+                region.count,
+                if problems.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", problems.join(", "))
+                }
+            );
+            for record in 0..region.count {
+                let group = (region.first_group + record) as usize;
+                let handle = handles
+                    .get(group * handle_size..(group + 1) * handle_size)
+                    .map_or(String::from("-"), |handle| {
+                        handle.iter().map(|byte| format!("{:02x}", byte)).collect()
+                    });
+                let _ = writeln!(
+                    text,
+                    "  {:#06x}: group {} {}",
+                    region.offset + record * region.stride,
+                    group,
+                    handle
+                );
+            }
+        }
+
+        text += &self.descriptor_diagnostics_report();
+        text
+    }
+
+    // A pool sized for exactly one set of the layout, and that set
+    fn allocate_descriptor_set(
+        &self,
+        pool_sizes: &[vk::DescriptorPoolSize],
+        layout: vk::DescriptorSetLayout,
+    ) -> (vk::DescriptorPool, vk::DescriptorSet) {
+        unsafe {
+            let descriptor_pool_info = vk::DescriptorPoolCreateInfo {
+                s_type: vk::StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+                p_next: ptr::null(),
+                pool_size_count: pool_sizes.len() as u32,
+                p_pool_sizes: pool_sizes.as_ptr(),
+                max_sets: 1,
+                ..Default::default()
+            };
+
+            let descriptor_pool = self
+                .base
+                .device
+                .create_descriptor_pool(&descriptor_pool_info, None)
+                .expect("Failed to create descriptor pool.");
+
+            let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo {
+                s_type: vk::StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+                p_next: ptr::null(),
+                descriptor_pool,
+                p_set_layouts: [layout].as_ptr(),
+                ..Default::default()
+            };
+            let descriptor_sets = self
+                .base
+                .device
+                .allocate_descriptor_sets(&descriptor_set_allocate_info)
+                .expect("Failed to allocate descriptor sets.");
+
+            (descriptor_pool, descriptor_sets[0])
+        }
+    }
+
+    // The batch was checked against the set's layout as it was bound
+    fn write_batch(&self, set: u32) -> WriteBatch {
+        WriteBatch::new(&self.descriptor_diagnostics[set as usize].layout_bindings())
+    }
+
+    fn update_descriptor_set(
+        &mut self,
+        set: u32,
+        descriptor_set: vk::DescriptorSet,
+        pool_sizes: &[vk::DescriptorPoolSize],
+        batch: &WriteBatch,
+    ) {
+        batch.with_writes(descriptor_set, |descriptor_writes| {
+            let diagnostics = &mut self.descriptor_diagnostics[set as usize];
+            diagnostics.set_pool_sizes(pool_sizes);
+            diagnostics.set_writes(descriptor_writes);
+            if !diagnostics.mismatches().is_empty() {
+                tracing::warn!("{}", diagnostics);
+            }
+
+            self.validation_errors_seen = utility::debug::validation_error_count();
+            unsafe {
+                self.base
+                    .device
+                    .update_descriptor_sets(descriptor_writes, &[]);
+            }
+        });
+        self.check_validation_errors("update_descriptor_sets");
+    }
+
+    // SCENE_DESCRIPTOR_SET: the TLAS and the buffers describing the scene, one set per TLAS slot
+    pub(super) fn create_scene_descriptor_set(&mut self) {
+        let descriptor_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::ACCELERATION_STRUCTURE_NV,
+                descriptor_count: 1,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                // Instance data, emissive triangles and their alias table, fog and its grid, the
+                // sun, the debug buffer, the skin indices and normals
+                descriptor_count: 9,
+            },
+        ];
+        for index in 0..self.tlas_slots.len() {
+            let (descriptor_pool, descriptor_set) =
+                self.allocate_descriptor_set(&descriptor_sizes, self.scene_set_layout);
+            self.tlas_slots[index].scene_descriptor_pool = descriptor_pool;
+            self.tlas_slots[index].scene_descriptor_set = descriptor_set;
+
+            // Storage buffers cannot be empty, the hit shaders skip them unless the mesh is skinned
+            if self.skinning.is_none() && self.skin_placeholder_buffer.is_none() {
+                let mut placeholder = BufferResource::new(
+                    std::mem::size_of::<[f32; 4]>() as vk::DeviceSize,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    self.base.clone(),
+                );
+                placeholder.store(&[[0.0f32; 4]]);
+                placeholder.set_name("skin placeholder");
+                self.skin_placeholder_buffer = Some(placeholder);
+            }
+            let (skin_indices, skin_normals) = match self.skinning.as_ref() {
+                Some(skinning) => (skinning.indices_buffer(), skinning.normals_buffer()),
+                None => {
+                    let placeholder = self.skin_placeholder_buffer.as_ref().unwrap().buffer;
+                    (placeholder, placeholder)
+                }
+            };
+            let mut batch = self.write_batch(SCENE_DESCRIPTOR_SET);
+            batch
+                .bind_acceleration_structure(0, self.tlas_slots[index].top_as.handle)
+                .bind_storage_buffer(2, self.instance_data_buffer.as_ref().unwrap().buffer())
+                .bind_storage_buffer(4, self.emissive_triangle_buffer.as_ref().unwrap().buffer)
+                .bind_storage_buffer(5, self.emissive_alias_buffer.as_ref().unwrap().buffer)
+                .bind_storage_buffer(9, self.fog_buffer.as_ref().unwrap().buffer())
+                .bind_storage_buffer(10, self.fog_density_buffer.as_ref().unwrap().buffer)
+                .bind_storage_buffer(11, self.sun_buffer.as_ref().unwrap().buffer())
+                .bind_storage_buffer(12, self.debug_buffer.as_ref().unwrap().buffer)
+                .bind_storage_buffer(14, skin_indices)
+                .bind_storage_buffer(15, skin_normals);
+
+            self.update_descriptor_set(
+                SCENE_DESCRIPTOR_SET,
+                descriptor_set,
+                &descriptor_sizes,
+                &batch,
+            );
+        }
+    }
+
+    // FRAME_DESCRIPTOR_SET: the render sized images and buffers, recreated on resize
+    pub(super) fn create_frame_descriptor_set(&mut self) {
+        let mut descriptor_sizes = vec![vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_IMAGE,
+            // The offscreen target and the AOVs
+            descriptor_count: 1 + Aov::ALL.len() as u32,
+        }];
+        if self.restir.is_some() {
+            descriptor_sizes.push(vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+            });
+        }
+        if self.depth_prepass.is_some() {
+            descriptor_sizes.push(vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 2,
+            });
+            descriptor_sizes.push(vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 2,
+            });
+        }
+        let (descriptor_pool, descriptor_set) =
+            self.allocate_descriptor_set(&descriptor_sizes, self.frame_set_layout);
+        self.frame_descriptor_pool = descriptor_pool;
+        self.frame_descriptor_set = descriptor_set;
+
+        let aov_views: Vec<vk::ImageView> =
+            self.aov_targets.iter().map(|target| target.view).collect();
+        let mut batch = self.write_batch(FRAME_DESCRIPTOR_SET);
+        batch
+            .bind_storage_image(1, self.offscreen_target.view)
+            .bind_storage_images(8, &aov_views);
+        if let Some(restir) = &self.restir {
+            batch.bind_storage_buffer(3, restir.gbuffer.as_ref().unwrap().buffer);
+        }
+        if let Some(depth_prepass) = &self.depth_prepass {
+            batch
+                .bind_sampled_image(
+                    6,
+                    depth_prepass.depth_target.sampler,
+                    depth_prepass.depth_target.view,
+                    vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                )
+                .bind_sampled_image(
+                    7,
+                    depth_prepass.depth_target.sampler,
+                    depth_prepass.instance_target.view,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                );
+        }
+        if let Some(light_culler) = &self.light_culler {
+            batch
+                .bind_storage_buffer(12, light_culler.light_buffer())
+                .bind_storage_buffer(13, light_culler.tile_buffer());
+        }
+
+        self.update_descriptor_set(
+            FRAME_DESCRIPTOR_SET,
+            descriptor_set,
+            &descriptor_sizes,
+            &batch,
+        );
+    }
+}
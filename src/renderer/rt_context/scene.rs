@@ -0,0 +1,745 @@
+use std::path::Path;
+
+use super::{DeviceRegions, RtContext, ViewportRegions};
+use crate::renderer::{
+    depth_prepass::DepthPrepass,
+    scene::{motion_sample_transform, GeometryInstance},
+    selection_outline::SelectionOutlinePass,
+    texture_atlas::TextureAtlas,
+};
+use crate::{
+    utility,
+    utility::{
+        camera::Viewport,
+        constants::*,
+        logging::TARGET_AS,
+        material::{Material, TextureSlot},
+        participating_media::{fog_params, translate_fog_grid, DensityGrid},
+        sampler_cache::TextureQuality,
+        skinning::SkinPose,
+        structures::*,
+        sun::SunController,
+        texture_budget::{mip_extent, plan_mip_drops, BudgetTexture},
+        video_capture::{CaptureOutput, CaptureSettings, VideoCapture},
+    },
+};
+use cgmath::{EuclideanSpace, Matrix4, Vector3, Zero};
+use image::RgbaImage;
+use smallvec::SmallVec;
+
+use ash::vk;
+
+impl RtContext {
+    // Every traced frame accumulates its samples from scratch, so the next one is lit by the new
+    // sun without anything to reset
+    pub fn set_sun(&mut self, sun: SunController) {
+        self.sun = sun;
+        let params = sun.params();
+        self.finish_traced_frames();
+        self.sun_buffer.as_mut().unwrap().write(0, params);
+        if let Some(shadow_map) = self.shadow_map.as_mut() {
+            shadow_map.set_sun(params);
+        }
+    }
+
+    // Runs the day cycle from midnight and saves every frame as <prefix>_<frame>.png
+    pub fn render_day_cycle(&mut self, frame_count: u32, prefix: &str) {
+        let extent = self.render_extent();
+        let mut sun = self.sun;
+        sun.set_time_of_day(0.0);
+        let seconds_per_frame = 24.0 / SUN_CYCLE_HOURS_PER_SECOND / frame_count as f32;
+        for frame in 0..frame_count {
+            self.set_sun(sun);
+            self.trace_frame();
+            let path = format!("{}_{:03}.png", prefix, frame);
+            RgbaImage::from_raw(extent.width, extent.height, self.read_tonemapped_image())
+                .expect("Frame readback has the wrong size.")
+                .save(&path)
+                .unwrap_or_else(|err| panic!("Failed to save {}: {}", path, err));
+            sun.advance(seconds_per_frame);
+        }
+        tracing::info!(
+            "Saved {} frames of the day cycle to {}_*.png",
+            frame_count,
+            prefix
+        );
+    }
+
+    // Poses the skinned scene mesh over a cycle of Skin::sway and saves every frame as
+    // <prefix>_<frame>.png
+    pub fn render_skin_cycle(&mut self, frame_count: u32, prefix: &str) {
+        let extent = self.render_extent();
+        for frame in 0..frame_count {
+            let pose = utility::skinning::sway_pose(frame as f32 / frame_count as f32);
+            if let Err(err) = self.set_skin_pose(pose) {
+                tracing::error!("Cannot pose the scene mesh: {}", err);
+                return;
+            }
+            self.trace_frame();
+            let path = format!("{}_{:03}.png", prefix, frame);
+            RgbaImage::from_raw(extent.width, extent.height, self.read_tonemapped_image())
+                .expect("Frame readback has the wrong size.")
+                .save(&path)
+                .unwrap_or_else(|err| panic!("Failed to save {}: {}", path, err));
+        }
+        tracing::info!(
+            "Saved {} frames of the skinning cycle to {}_*.png",
+            frame_count,
+            prefix
+        );
+    }
+
+    // Deforms the scene mesh into the pose with the next frame, the same for the rays and the
+    // raster passes. Fails without a skin or when the pose does not fit it.
+    pub fn set_skin_pose(&mut self, pose: SkinPose) -> Result<(), String> {
+        if self.ray_query.is_some() {
+            return Err("The ray query backend traces the scene mesh at rest".to_string());
+        }
+        let skinning = self
+            .skinning
+            .as_mut()
+            .ok_or_else(|| "The scene mesh has no skin".to_string())?;
+        // Culls the raster passes and outlines the BLAS in the debug draw
+        self.scene_mesh_bounds = skinning.set_pose(pose)?;
+        Ok(())
+    }
+
+    // Traces `settings.frame_count()` frames and writes them to `output` on a worker thread. The
+    // sun runs the day cycle from `start_hour` when given and stays put otherwise.
+    pub fn capture(
+        &mut self,
+        output: CaptureOutput,
+        settings: CaptureSettings,
+        start_hour: Option<f32>,
+    ) {
+        let extent = self.render_extent();
+        let mut sun = self.sun;
+        if let Some(hours) = start_hour {
+            sun.set_time_of_day(hours);
+        }
+        let capture = VideoCapture::start(
+            output.clone(),
+            extent.width,
+            extent.height,
+            settings.frame_rate,
+        );
+        for _ in 0..settings.frame_count() {
+            self.set_sun(sun);
+            self.trace_frame();
+            capture.submit(self.read_tonemapped_image());
+            sun.advance(settings.frame_seconds());
+        }
+        match capture.finish() {
+            Ok(frame_count) => tracing::info!("Captured {} frames to {:?}", frame_count, output),
+            Err(message) => tracing::error!("Capture to {:?} failed: {}", output, message),
+        }
+    }
+
+    // Uniform fog over the whole scene plus, optionally, a density grid scaled by `scale`. Camera
+    // rays fade towards `color` with the transmittance up to their hit, shadow rays ignore it.
+    pub fn set_fog(&mut self, color: [f32; 3], density: f32, grid: Option<(&DensityGrid, f32)>) {
+        if let Some((grid, _)) = grid {
+            // The old grid may still be bound to a frame in flight
+            self.base.wait_device_idle();
+            self.fog_density_buffer = Some(self.create_fog_density_buffer(&grid.densities));
+            let density_info = [vk::DescriptorBufferInfo {
+                buffer: self.fog_density_buffer.as_ref().unwrap().buffer,
+                range: vk::WHOLE_SIZE,
+                ..Default::default()
+            }];
+            let density_writes: SmallVec<[vk::WriteDescriptorSet; TRACED_FRAMES_IN_FLIGHT]> = self
+                .tlas_slots
+                .iter()
+                .map(|slot| {
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(slot.scene_descriptor_set)
+                        .dst_binding(10)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .buffer_info(&density_info)
+                        .build()
+                })
+                .collect();
+            unsafe {
+                self.base
+                    .device
+                    .update_descriptor_sets(&density_writes, &[]);
+            }
+        } else {
+            // The fog parameters may still be read by a frame in flight
+            self.finish_traced_frames();
+        }
+        // The grid bounds are in world space
+        let mut params = fog_params(color, density, grid);
+        translate_fog_grid(&mut params, self.render_origin.to_render(Vector3::zero()));
+        self.fog_buffer.as_mut().unwrap().write(0, params);
+    }
+
+    // Packs every image in the directory into the texture atlas, sorted by file name so the
+    // region of a texture does not depend on the directory order
+    pub fn load_texture_atlas(&mut self, directory: &Path) {
+        let mut paths: Vec<_> = std::fs::read_dir(directory)
+            .unwrap_or_else(|_| panic!("Failed to read texture directory {:?}.", directory))
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+
+        let mut textures = Vec::with_capacity(paths.len());
+        let mut budget_textures = Vec::with_capacity(paths.len());
+        for path in &paths {
+            match image::open(path) {
+                Ok(texture) => {
+                    let texture = texture.flipv().to_rgba8();
+                    budget_textures.push(BudgetTexture {
+                        width: texture.width(),
+                        height: texture.height(),
+                        bytes_per_texel: 4,
+                        // The pages have no mips, dropping the top one halves the texture
+                        mip_levels: 1,
+                        priority: TextureSlot::from_file_name(path).priority(),
+                    });
+                    textures.push(texture);
+                }
+                Err(err) => tracing::warn!("Skipping texture {:?}: {}", path, err),
+            }
+        }
+
+        // The lowest priority textures are shrunk to the budget first, the atlas then shrinks all
+        // of them alike if they still do not fit on its pages
+        let plan = plan_mip_drops(
+            &budget_textures,
+            self.texture_budget,
+            TEXTURE_BUDGET_MIN_SIZE,
+        );
+        if plan.shrunk_count() > 0 {
+            tracing::info!(
+                "Dropped {} mips of {} textures to fit the texture budget of {} MiB, {} MiB left",
+                plan.dropped_levels(),
+                plan.shrunk_count(),
+                self.texture_budget >> 20,
+                plan.size >> 20
+            );
+        }
+        if !plan.fits {
+            tracing::warn!(
+                "Textures of {:?} take {} MiB shrunk down to {} texels, over the budget of {} MiB",
+                directory,
+                plan.size >> 20,
+                TEXTURE_BUDGET_MIN_SIZE,
+                self.texture_budget >> 20
+            );
+        }
+        for (texture, &dropped) in textures.iter_mut().zip(&plan.dropped) {
+            if dropped > 0 {
+                let (width, height) = mip_extent(texture.width(), texture.height(), dropped);
+                *texture = image::imageops::resize(
+                    texture,
+                    width,
+                    height,
+                    image::imageops::FilterType::Triangle,
+                );
+            }
+        }
+
+        self.texture_atlas_directory = Some(directory.to_path_buf());
+        self.texture_atlas = None;
+        self.texture_atlas = TextureAtlas::new(self.base.clone(), &textures);
+        match &self.texture_atlas {
+            Some(texture_atlas) => {
+                texture_atlas
+                    .image
+                    .set_name(&utility::debug::asset_debug_name(directory, "atlas"));
+                let layout = &texture_atlas.layout;
+                tracing::info!(
+                    "Packed {} textures onto {} atlas pages of {}x{}, shrunk by {}, {:.1}% occupied",
+                    textures.len(),
+                    layout.page_count,
+                    layout.page_size,
+                    layout.page_size,
+                    layout.downscale,
+                    layout.occupancy() * 100.0
+                );
+            }
+            None => tracing::warn!("No textures packed from {:?}", directory),
+        }
+    }
+
+    // Packs the atlas again for the new budget, from the files so textures shrunk for a lower
+    // budget get their mips back
+    pub fn set_texture_budget(&mut self, budget: u64) {
+        self.texture_budget = budget;
+        if let Some(directory) = self.texture_atlas_directory.clone() {
+            self.load_texture_atlas(&directory);
+        }
+    }
+
+    // Rebuilds the texture samplers with the new filtering, see
+    // Renderer::set_texture_quality
+    pub fn set_texture_quality(&mut self, quality: TextureQuality) {
+        if !self.base.set_texture_quality(quality) {
+            return;
+        }
+        if let Some(texture_atlas) = self.texture_atlas.as_mut() {
+            texture_atlas.sampler = self
+                .base
+                .sampler_cache
+                .lock()
+                .unwrap()
+                .get(TextureAtlas::SAMPLER_KEY);
+        }
+    }
+
+    pub fn texture_quality(&self) -> TextureQuality {
+        self.base.sampler_cache.lock().unwrap().quality()
+    }
+
+    pub fn stats(&self) -> SceneStats {
+        let mut stats = self.scene_stats;
+        stats.texture_memory = self.offscreen_target.size
+            + self
+                .aov_targets
+                .iter()
+                .map(|target| target.size)
+                .sum::<vk::DeviceSize>()
+            + self.exposure.as_ref().map_or(0, |exposure| {
+                exposure.tonemapped_target.size + exposure.lut.size
+            })
+            + self.depth_prepass.as_ref().map_or(0, |depth_prepass| {
+                depth_prepass.depth_target.size + depth_prepass.instance_target.size
+            })
+            + self
+                .shadow_map
+                .as_ref()
+                .map_or(0, |shadow_map| shadow_map.shadow_target.size)
+            + self
+                .texture_atlas
+                .as_ref()
+                .map_or(0, |texture_atlas| texture_atlas.image.size);
+        if let Some(geometry_pool) = self.geometry_pool.as_ref() {
+            stats.geometry_pool = geometry_pool.stats();
+        }
+        stats
+    }
+
+    // Moves an instance to a double precision world position, keeping its rotation and scale.
+    // The TLAS is refitted the next time a frame is traced. Light sampling keeps the emitters
+    // where they were when the scene was built.
+    pub fn set_instance_position(&mut self, index: usize, position: Vector3<f64>) {
+        self.scene_translations[index] = position;
+        let translation = self.render_origin.to_render(position);
+        let transform = self.scene_transforms[index].with_translation(translation.into());
+        self.write_instance_transform(index, transform);
+    }
+
+    fn write_instance_transform(&mut self, index: usize, transform: InstanceTransform) {
+        self.scene_transforms[index] = transform;
+        for sample in 0..self.motion_time_samples {
+            let slot = sample as usize * self.instance_count as usize + index;
+            self.instances[slot].transform = motion_sample_transform(
+                &transform,
+                &self.scene_motion[index],
+                sample,
+                self.motion_time_samples,
+            );
+            self.dirty_instances.mark(slot);
+        }
+    }
+
+    fn camera_world_position(&self) -> Vector3<f64> {
+        self.render_origin.to_world(self.camera.position.to_vec())
+    }
+
+    // Moves the render origin to the cell around the camera, along with everything kept in render
+    // space, so the GPU only sees positions close to the camera however far it travels. Instances
+    // are rebased from their world translations and refitted with the next frame.
+    pub(super) fn follow_camera_with_origin(&mut self) {
+        let shift = match self.render_origin.follow(self.camera_world_position()) {
+            Some(shift) => shift,
+            None => return,
+        };
+        tracing::debug!(
+            "Moved the render origin to {:?}",
+            self.render_origin.world()
+        );
+        // The light buffers may still be read by a frame in flight
+        self.base.wait_device_idle();
+
+        let cameras = std::iter::once(&mut self.camera).chain(
+            self.viewports
+                .iter_mut()
+                .map(|viewport| &mut viewport.camera),
+        );
+        for camera in cameras {
+            camera.position += shift;
+            camera.target += shift;
+        }
+        self.debug_frustum = self.debug_frustum * Matrix4::from_translation(-shift);
+        if !self.scene_bounds.is_empty() {
+            self.scene_bounds = self
+                .scene_bounds
+                .transformed(&Matrix4::from_translation(shift));
+        }
+
+        for index in 0..self.scene_translations.len() {
+            let translation = self.render_origin.to_render(self.scene_translations[index]);
+            let transform = self.scene_transforms[index].with_translation(translation.into());
+            self.write_instance_transform(index, transform);
+        }
+
+        for triangle in &mut self.emissive_triangles {
+            for vertex in [&mut triangle.v0, &mut triangle.v1, &mut triangle.v2] {
+                vertex[0] += shift.x;
+                vertex[1] += shift.y;
+                vertex[2] += shift.z;
+            }
+        }
+        // The placeholder of a scene without emitters stays where it is
+        if !self.emissive_triangles.is_empty() {
+            self.emissive_triangle_buffer
+                .as_mut()
+                .unwrap()
+                .store(&self.emissive_triangles);
+        }
+
+        if let Some(restir) = self.restir.as_mut() {
+            restir.translate_lights(shift);
+        }
+        for light in &mut self.point_lights {
+            light.position[0] += shift.x;
+            light.position[1] += shift.y;
+            light.position[2] += shift.z;
+        }
+        if let Some(light_culler) = self.light_culler.as_mut() {
+            light_culler.set_lights(&self.point_lights);
+        }
+
+        let fog_buffer = self.fog_buffer.as_mut().unwrap();
+        let mut fog = fog_buffer.read(0);
+        translate_fog_grid(&mut fog, shift);
+        fog_buffer.write(0, fog);
+    }
+
+    // Splits the render target between the viewports' cameras, an empty list traces the whole
+    // target with the app's camera. Only the standard mode has viewports, the ReSTIR G-buffer
+    // and the hybrid depth pre-pass cover the whole target with one camera.
+    pub fn set_viewports(&mut self, viewports: Vec<Viewport>) {
+        if !viewports.is_empty() && (self.restir.is_some() || self.depth_prepass.is_some()) {
+            tracing::warn!("Viewports are ignored outside the standard render mode");
+        }
+        self.viewports = viewports;
+    }
+
+    // Camera and pixel rect (x, y, width, height) of every region traced this frame
+    pub(super) fn viewport_regions(&self, extent: vk::Extent2D) -> ViewportRegions {
+        if self.viewports.is_empty() || self.restir.is_some() || self.depth_prepass.is_some() {
+            return std::iter::once((self.camera, [0, 0, extent.width, extent.height])).collect();
+        }
+
+        self.viewports
+            .iter()
+            .map(|viewport| {
+                (
+                    viewport.camera,
+                    viewport.pixel_rect(extent.width, extent.height),
+                )
+            })
+            .filter(|(_, [_, _, width, height])| *width > 0 && *height > 0)
+            .collect()
+    }
+
+    // With split-frame rendering every region is traced in bands of rows, each by the device of
+    // the mask. The passes that read neighbouring pixels need whole regions on every device.
+    pub(super) fn device_regions(&self, regions: ViewportRegions) -> DeviceRegions {
+        let device_count = match self.split_frame.as_ref() {
+            Some(split_frame)
+                if !self.deterministic
+                    && self.restir.is_none()
+                    && self.depth_prepass.is_none()
+                    && self.checkerboard.is_none() =>
+            {
+                split_frame.device_count()
+            }
+            _ => {
+                return regions
+                    .into_iter()
+                    .map(|(camera, rect)| (None, camera, rect))
+                    .collect()
+            }
+        };
+
+        regions
+            .into_iter()
+            .flat_map(|(camera, rect)| {
+                let [_, y, width, height] = rect;
+                utility::device_group::split_rows(rect, device_count)
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, [_, _, _, band_height])| *band_height > 0)
+                    .map(move |(device, band)| {
+                        let top = (band[1] - y) as f32 / height as f32;
+                        let bottom = (band[1] - y + band[3]) as f32 / height as f32;
+                        (
+                            Some(1 << device),
+                            camera.sub_view(width as f32 / height as f32, [0.0, top, 1.0, bottom]),
+                            band,
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    // Moves the instance into exactly the given layers, cameras only trace it with rays whose
+    // mask shares one of them
+    pub fn set_instance_layers(&mut self, index: usize, layers: &[RenderLayer]) {
+        let mask = RenderLayer::mask_of(layers);
+        self.scene_layers[index] = mask;
+        for sample in 0..self.motion_time_samples {
+            let slot = sample as usize * self.instance_count as usize + index;
+            self.instances[slot].set_mask(mask);
+            self.dirty_instances.mark(slot);
+        }
+    }
+
+    pub fn instance_material(&self, index: usize) -> Material {
+        Material::of(&self.instance_data[index])
+    }
+
+    // Writes straight into the mapped instance data, the next frame shades with it without
+    // rebuilding anything. The light sampling tables keep the emission the scene was built with.
+    // Under stochastic transparency the instance is only handed to the any-hit shader while it
+    // is transparent, which reaches the TLAS with the refit of the next frame.
+    pub fn set_instance_material(&mut self, index: usize, material: &Material) {
+        self.finish_traced_frames();
+        material.apply_to(&mut self.instance_data[index]);
+        self.instance_data_buffer
+            .as_mut()
+            .unwrap()
+            .write(index, self.instance_data[index]);
+
+        if self.stochastic_transparency {
+            let mut flags = vk::GeometryInstanceFlagsNV::TRIANGLE_CULL_DISABLE_NV;
+            if Material::of(&self.instance_data[index]).is_transparent() {
+                flags |= vk::GeometryInstanceFlagsNV::FORCE_NO_OPAQUE_NV;
+            }
+            for sample in 0..self.motion_time_samples {
+                let slot = sample as usize * self.instance_count as usize + index;
+                self.instances[slot].set_flags(flags);
+                self.dirty_instances.mark(slot);
+            }
+        }
+    }
+
+    // Points the instance at another hit group, e.g. to preview it with another shading model.
+    // Like the other instance edits it reaches the TLAS with the refit of the next frame.
+    pub fn set_instance_hit_group(&mut self, index: usize, group: HitGroup) {
+        for sample in 0..self.motion_time_samples {
+            let slot = sample as usize * self.instance_count as usize + index;
+            self.instances[slot].set_offset(group.index());
+            self.dirty_instances.mark(slot);
+        }
+    }
+
+    // Deforms the scene mesh with a pose set since the last frame. Recorded after the frame
+    // barrier, which keeps the BLAS update from the frame before still tracing it. Every
+    // instance is refitted in the TLAS over the updated BLAS.
+    pub(super) fn record_skinning(&mut self, command_buffer: vk::CommandBuffer) {
+        let skinning = match self.skinning.as_mut() {
+            Some(skinning) if skinning.has_pending_pose() => skinning,
+            _ => return,
+        };
+        // The joint matrices and morph weights are written from the host, where the frame before
+        // may still be deforming with them
+        for frame_context in &self.frame_contexts {
+            frame_context.wait(&self.base.device);
+        }
+        self.profiler.begin_pass(command_buffer, "skinning");
+        skinning.record(
+            command_buffer,
+            self.geometry_pool.as_ref().unwrap(),
+            &self.scene_mesh,
+            self.bottom_as.as_ref().unwrap(),
+        );
+        self.profiler.end_pass(command_buffer);
+        self.dirty_instances.mark_range(0..self.instances.len());
+    }
+
+    // Writes the instances the TLAS of this frame's slot missed to its instance buffer, returns
+    // whether anything was written and the TLAS has to be refitted. The other slots pick the
+    // same instances up with their next frame.
+    pub(super) fn upload_dirty_instances(&mut self) -> bool {
+        let changed = self.dirty_instances.take();
+        for slot in &mut self.tlas_slots {
+            for range in &changed {
+                slot.dirty_instances.mark_range(range.clone());
+            }
+        }
+        let slot = &mut self.tlas_slots[self.frame_slot];
+        if slot.dirty_instances.is_empty() {
+            return false;
+        }
+
+        let instance_size = std::mem::size_of::<GeometryInstance>();
+        tracing::debug!(
+            target: TARGET_AS,
+            "Uploading {} of {} instances to TLAS {}",
+            slot.dirty_instances.len(),
+            self.instances.len(),
+            self.frame_slot
+        );
+        for range in slot.dirty_instances.take() {
+            slot.instance_buffer.store_at(
+                (range.start * instance_size) as vk::DeviceSize,
+                &self.instances[range],
+            );
+        }
+        true
+    }
+
+    // Waits until the GPU is done with every traced frame, for host writes to what they read
+    pub(super) fn finish_traced_frames(&self) {
+        for frame_context in &self.frame_contexts {
+            frame_context.wait(&self.base.device);
+        }
+    }
+
+    // Compacts the geometry pool once enough of it is taken up by holes
+    pub(super) fn defragment_geometry_pool(&mut self) {
+        let geometry_pool = match self.geometry_pool.as_mut() {
+            Some(geometry_pool) => geometry_pool,
+            None => return,
+        };
+        let before = geometry_pool.stats();
+        if before.fragmentation() < GEOMETRY_POOL_DEFRAGMENT_THRESHOLD {
+            return;
+        }
+
+        self.base.wait_device_idle();
+        geometry_pool.defragment(&mut [&mut self.scene_mesh]);
+
+        let after = geometry_pool.stats();
+        tracing::info!(
+            "Defragmented geometry pool: {} -> {} blocks, {} -> {} bytes",
+            before.block_count,
+            after.block_count,
+            before.capacity,
+            after.capacity
+        );
+    }
+
+    // Instance IDs covered by a screen rectangle in render target pixels, for editor selection.
+    // The hybrid pre-pass of the last traced frame is read directly, other modes rasterize the
+    // instance IDs on demand.
+    pub fn select_instances(&mut self, region: vk::Rect2D) -> Vec<u32> {
+        if let Some(depth_prepass) = self.depth_prepass.as_ref() {
+            return depth_prepass.read_instance_ids(region);
+        }
+
+        if self.picking_prepass.is_none() {
+            self.create_picking_prepass();
+        }
+        let clip_from_world = self.clip_from_world();
+        let (clip_from_objects, instance_bounds) = self.raster_inputs();
+        let picking_prepass = self.picking_prepass.as_mut().unwrap();
+
+        let queue = self.base.lock_queue();
+        let command_buffer =
+            utility::general::begin_single_time_command(&self.base.device, self.base.command_pool);
+        picking_prepass.record(
+            command_buffer,
+            self.geometry_pool.as_ref().unwrap(),
+            &self.scene_mesh,
+            clip_from_world,
+            &clip_from_objects,
+            &instance_bounds,
+        );
+        utility::general::end_single_time_command(
+            &self.base.device,
+            self.base.command_pool,
+            self.base.graphics_queue,
+            command_buffer,
+        );
+        let instance_ids = picking_prepass.read_instance_ids(region);
+        drop(queue);
+
+        self.give_back_raster_inputs(clip_from_objects, instance_bounds);
+        instance_ids
+    }
+
+    fn create_picking_prepass(&mut self) {
+        let mut picking_prepass = DepthPrepass::new(self.base.clone(), self.render_extent());
+        picking_prepass.initialize();
+        self.picking_prepass = Some(picking_prepass);
+    }
+
+    // Outlines these instances over every traced frame, an empty selection turns it off
+    pub fn set_selection(&mut self, instance_ids: &[u32]) {
+        self.selection = instance_ids.to_vec();
+        if let Some(selection_outline) = self.selection_outline.as_mut() {
+            self.base.wait_device_idle();
+            if selection_outline.instance_count == self.instance_count {
+                selection_outline.set_selection(&self.selection);
+            } else {
+                // Rebuilt with room for every instance by the next frame
+                selection_outline.release();
+                self.selection_outline = None;
+            }
+        }
+    }
+
+    // Draws the selection outline over the tonemapped image. Outside the hybrid mode the
+    // instance IDs come from the picking pre-pass, rendered for it every frame.
+    pub(super) fn record_selection_outline(&mut self, command_buffer: vk::CommandBuffer) {
+        if self.selection.is_empty() {
+            return;
+        }
+
+        if self.depth_prepass.is_none() {
+            if self.picking_prepass.is_none() {
+                self.create_picking_prepass();
+            }
+            let clip_from_world = self.clip_from_world();
+            let (clip_from_objects, instance_bounds) = self.raster_inputs();
+            let picking_prepass = self.picking_prepass.as_mut().unwrap();
+            self.profiler.begin_pass(command_buffer, "picking_prepass");
+            picking_prepass.record(
+                command_buffer,
+                self.geometry_pool.as_ref().unwrap(),
+                &self.scene_mesh,
+                clip_from_world,
+                &clip_from_objects,
+                &instance_bounds,
+            );
+            self.profiler.end_pass(command_buffer);
+            self.give_back_raster_inputs(clip_from_objects, instance_bounds);
+        }
+
+        if self.selection_outline.is_none() {
+            let instance_prepass = self
+                .depth_prepass
+                .as_ref()
+                .or(self.picking_prepass.as_ref())
+                .unwrap();
+            let mut selection_outline = SelectionOutlinePass::new(
+                self.base.clone(),
+                self.render_extent(),
+                self.instance_count,
+            );
+            selection_outline.initialize(
+                instance_prepass.instance_target.view,
+                instance_prepass.depth_target.sampler,
+                self.exposure.as_ref().unwrap().tonemapped_target.view,
+            );
+            selection_outline.set_selection(&self.selection);
+            self.selection_outline = Some(selection_outline);
+        }
+
+        self.profiler
+            .begin_pass(command_buffer, "selection_outline");
+        self.selection_outline
+            .as_ref()
+            .unwrap()
+            .record(command_buffer);
+        self.profiler.end_pass(command_buffer);
+    }
+}
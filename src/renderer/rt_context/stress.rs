@@ -0,0 +1,603 @@
+use std::time::{Duration, Instant};
+
+use super::RtContext;
+use crate::renderer::{
+    acceleration_structure::{AccelerationStructure, AsBuilder, BlasBuildQueue},
+    geometry_pool::{GeometryPool, MeshAllocation},
+    resources::BufferResource,
+    scene::GeometryInstance,
+};
+use crate::{
+    utility,
+    utility::{
+        bounds::Aabb,
+        chunk_streaming::{ChunkGrid, ChunkKey, ChunkStreamer, StreamParams, StreamReport},
+        constants::*,
+        feature_chain::DeviceFeature,
+        gpu_profiler::{KhrPerformanceCounters, PassCounters},
+        stress_scene::{
+            random_instance_transform, random_mesh, stress_limits, StressCosts, StressLimit,
+            StressParams, StressReport, StressRng,
+        },
+        structures::*,
+    },
+};
+use cgmath::{EuclideanSpace, Matrix4, Point3};
+
+use ash::vk;
+
+impl RtContext {
+    // Builds a random scene with its SBT and descriptor set next to the regular one, reports where
+    // it runs into device limits and what it cost, then drops it again
+    pub fn stress_test(&mut self, params: StressParams) -> StressReport {
+        let limits = unsafe {
+            self.base
+                .instance
+                .get_physical_device_properties(self.base.physical_device)
+        }
+        .limits;
+        let stress_limits = stress_limits(&params, &self.properties, &limits);
+        if stress_limits.iter().any(StressLimit::is_exceeded) {
+            return StressReport {
+                params,
+                limits: stress_limits,
+                costs: None,
+            };
+        }
+
+        let mut rng = StressRng::new(params.seed);
+        let mut geometry_pool = GeometryPool::new(self.base.clone(), BLAS_VERTEX_FORMAT.stride());
+        let mut as_builder = AsBuilder::new(self.base.clone(), self.ray_tracing.clone());
+        // Bindless hit shading would index the vertex and index buffers of every geometry
+        let mut geometry_buffers = Vec::new();
+        let mut mesh_geometries = Vec::new();
+        let mut mesh_bounds = Vec::new();
+        for _ in 0..params.mesh_count {
+            let mut geometries = Vec::new();
+            let mut bounds = Aabb::empty();
+            for geometry in random_mesh(&mut rng, &params) {
+                bounds = bounds.union(&Aabb::from_points(
+                    geometry.positions.iter().copied().map(Point3::from),
+                ));
+                let mesh = geometry_pool.add_mesh(
+                    &BLAS_VERTEX_FORMAT.encode(&geometry.positions),
+                    &geometry.indices,
+                );
+                // Whole blocks, mesh offsets are not aligned to storage buffer offsets
+                for buffer in [
+                    geometry_pool.vertex_buffer(&mesh),
+                    geometry_pool.index_buffer(&mesh),
+                ] {
+                    geometry_buffers.push(vk::DescriptorBufferInfo {
+                        buffer,
+                        offset: 0,
+                        range: vk::WHOLE_SIZE,
+                    });
+                }
+                geometries.push(geometry_pool.triangle_geometry(&mesh));
+            }
+            mesh_geometries.push(geometries);
+            mesh_bounds.push(bounds);
+        }
+
+        // Every instance picks a mesh and the hit records of its geometries
+        let instance_placements: Vec<(usize, Matrix4<f32>)> = (0..params.instance_count)
+            .map(|_| {
+                let mesh = (rng.next_u64() % params.mesh_count as u64) as usize;
+                (mesh, random_instance_transform(&mut rng, STRESS_SCENE_SIZE))
+            })
+            .collect();
+
+        // Time-sliced builds go by how much of the screen the instances of each mesh cover. Until
+        // their mesh is built, instances reference a placeholder BLAS and are masked out.
+        let time_sliced = self.blas_builds_per_frame > 0;
+        let mut blas_queue = BlasBuildQueue::new(self.base.clone(), self.ray_tracing.clone());
+        let mut mesh_priorities = vec![0.0f32; params.mesh_count as usize];
+        if time_sliced {
+            let clip_from_world = self.clip_from_world();
+            for (mesh, world_from_object) in &instance_placements {
+                let bounds = mesh_bounds[*mesh].transformed(world_from_object);
+                let center = clip_from_world * bounds.center().to_homogeneous();
+                let radius = bounds.radius();
+                if center.w > 0.0
+                    && center.x.abs() <= center.w + radius
+                    && center.y.abs() <= center.w + radius
+                {
+                    mesh_priorities[*mesh] += radius / center.w;
+                }
+            }
+        }
+        let bottom_as: Vec<AccelerationStructure> = mesh_geometries
+            .iter()
+            .zip(&mesh_priorities)
+            .map(|(geometries, &priority)| {
+                let flags = vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE;
+                if time_sliced {
+                    blas_queue.add(geometries, flags, priority)
+                } else {
+                    as_builder.add_bottom_level(geometries, flags)
+                }
+            })
+            .collect();
+        let placeholder_as = time_sliced.then(|| {
+            let placeholder =
+                geometry_pool.add_mesh(&BLAS_VERTEX_FORMAT.encode(&[[0.0; 3]; 3]), &[0, 1, 2]);
+            as_builder.add_bottom_level(
+                &[geometry_pool.triangle_geometry(&placeholder)],
+                vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE,
+            )
+        });
+
+        let mut instances: Vec<GeometryInstance> = instance_placements
+            .iter()
+            .enumerate()
+            .map(|(index, (mesh, world_from_object))| {
+                let (mask, blas) = match placeholder_as.as_ref() {
+                    Some(placeholder_as) => (0, placeholder_as),
+                    None => (0xff, &bottom_as[*mesh]),
+                };
+                GeometryInstance::new(
+                    InstanceTransform::from(*world_from_object),
+                    index as u32,
+                    mask,
+                    *mesh as u32 * params.geometries_per_mesh,
+                    vk::GeometryInstanceFlagsNV::TRIANGLE_CULL_DISABLE_NV,
+                    blas.reference,
+                )
+            })
+            .collect();
+        let mut instance_buffer = BufferResource::new(
+            std::mem::size_of_val(instances.as_slice()) as vk::DeviceSize,
+            vk::BufferUsageFlags::RAY_TRACING_NV,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        );
+        instance_buffer.store(&instances);
+        let top_as = as_builder.add_top_level(
+            params.instance_count,
+            vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE,
+            instance_buffer.buffer,
+            0,
+        );
+
+        for (mesh, blas) in bottom_as.iter().enumerate() {
+            blas.set_name(&format!("stress mesh {} BLAS", mesh));
+        }
+        top_as.set_name("stress TLAS");
+        instance_buffer.set_name("stress TLAS instances");
+
+        let build_start = Instant::now();
+        let mut scratch_size = as_builder.build();
+        let mut build_frames = 1;
+        let mut longest_build_frame = build_start.elapsed();
+        if time_sliced {
+            let top_scratch_size = top_as
+                .memory_requirements(
+                    vk::AccelerationStructureMemoryRequirementsTypeNV::BUILD_SCRATCH,
+                )
+                .size;
+            let top_scratch = BufferResource::new(
+                top_scratch_size,
+                vk::BufferUsageFlags::RAY_TRACING_NV,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                self.base.clone(),
+            );
+            while !blas_queue.is_empty() {
+                let frame_start = Instant::now();
+                let _queue = self.base.lock_queue();
+                let command_buffer = utility::general::begin_single_time_command(
+                    &self.base.device,
+                    self.base.command_pool,
+                );
+                let built = blas_queue.record(command_buffer, self.blas_builds_per_frame);
+
+                // The instances of the meshes built this frame swap the placeholder for them
+                for (instance, (mesh, _)) in instances.iter_mut().zip(&instance_placements) {
+                    if built.contains(&bottom_as[*mesh].handle) {
+                        instance.acceleration_handle = bottom_as[*mesh].reference;
+                        instance.set_mask(0xff);
+                    }
+                }
+                instance_buffer.store(&instances);
+                unsafe {
+                    self.ray_tracing.cmd_build_acceleration_structure(
+                        command_buffer,
+                        &vk::AccelerationStructureInfoNV::builder()
+                            .ty(vk::AccelerationStructureTypeNV::TOP_LEVEL)
+                            .flags(vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE)
+                            .instance_count(params.instance_count)
+                            .build(),
+                        instance_buffer.buffer,
+                        0,
+                        false,
+                        top_as.handle,
+                        vk::AccelerationStructureNV::null(),
+                        top_scratch.buffer,
+                        0,
+                    );
+                }
+                utility::general::end_single_time_command(
+                    &self.base.device,
+                    self.base.command_pool,
+                    self.base.graphics_queue,
+                    command_buffer,
+                );
+                build_frames += 1;
+                longest_build_frame = longest_build_frame.max(frame_start.elapsed());
+            }
+            scratch_size = scratch_size.max(
+                blas_queue
+                    .scratch
+                    .as_ref()
+                    .map_or(0, |scratch| scratch.size)
+                    + top_scratch_size,
+            );
+        }
+        let build_time = build_start.elapsed();
+        self.check_validation_errors("the stress scene build");
+
+        // Ray generation, the lit hit group once per geometry, then both miss groups
+        self.wait_for_pipeline();
+        let handle_size = self.properties.shader_group_handle_size as usize;
+        let group_count = 3 + HitGroup::ALL.len();
+        let mut group_handles = vec![0u8; handle_size * group_count];
+        unsafe {
+            self.ray_tracing
+                .get_ray_tracing_shader_group_handles(
+                    self.pipeline,
+                    0,
+                    group_count as u32,
+                    &mut group_handles,
+                )
+                .expect("Failed to get ray tracing shader group handles.");
+        }
+        let handle = |group: usize| &group_handles[group * handle_size..(group + 1) * handle_size];
+        let mut table_data = handle(0).to_vec();
+        for _ in 0..params.hit_record_count() {
+            table_data.extend_from_slice(handle(1 + HitGroup::Lit.index() as usize));
+        }
+        table_data.extend_from_slice(handle(group_count - 2));
+        table_data.extend_from_slice(handle(group_count - 1));
+        let mut shader_binding_table = BufferResource::new(
+            table_data.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::RAY_TRACING_NV,
+            vk::MemoryPropertyFlags::HOST_VISIBLE,
+            self.base.clone(),
+        );
+        shader_binding_table.store(&table_data);
+
+        let descriptor_count = geometry_buffers.len() as u32;
+        unsafe {
+            let device = &self.base.device;
+            let bindings = [vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(descriptor_count)
+                .stage_flags(vk::ShaderStageFlags::CLOSEST_HIT_NV)
+                .build()];
+            let descriptor_set_layout = device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings),
+                    None,
+                )
+                .expect("Failed to create the stress descriptor set layout.");
+            let pool_sizes = [vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count,
+            }];
+            let descriptor_pool = device
+                .create_descriptor_pool(
+                    &vk::DescriptorPoolCreateInfo::builder()
+                        .pool_sizes(&pool_sizes)
+                        .max_sets(1),
+                    None,
+                )
+                .expect("Failed to create the stress descriptor pool.");
+            let descriptor_set = device
+                .allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::builder()
+                        .descriptor_pool(descriptor_pool)
+                        .set_layouts(&[descriptor_set_layout]),
+                )
+                .expect("Failed to allocate the stress descriptor set.")[0];
+            device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&geometry_buffers)
+                    .build()],
+                &[],
+            );
+            device.destroy_descriptor_pool(descriptor_pool, None);
+            device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+        }
+        self.check_validation_errors("the stress descriptor set");
+
+        StressReport {
+            params,
+            limits: stress_limits,
+            costs: Some(StressCosts {
+                blas_size: bottom_as.iter().map(|blas| blas.size).sum(),
+                blas_count: bottom_as.len(),
+                tlas_size: top_as.size,
+                scratch_peak: scratch_size,
+                build_time,
+                build_frames,
+                longest_build_frame,
+                sbt_size: shader_binding_table.size,
+                descriptor_count,
+                geometry_pool: geometry_pool.stats(),
+            }),
+        }
+    }
+
+    // Flies a camera across a city of random meshes in chunks, streaming the geometry and BLAS
+    // of each chunk in and out around it within the memory budget and rebuilding the TLAS from
+    // the resident ones every frame. Reports what it cost, then drops the city again.
+    pub fn stream_test(&mut self, params: StreamParams) -> StreamReport {
+        let grid = ChunkGrid {
+            cell_size: STREAM_CELL_SIZE,
+        };
+        let half_side = params.chunks_per_side as i32 / 2;
+        let city = -half_side..params.chunks_per_side as i32 - half_side;
+        let exists = |key: ChunkKey| city.contains(&key.x) && city.contains(&key.z);
+        let mesh_params = StressParams {
+            instance_count: params.instances_per_chunk,
+            mesh_count: 1,
+            triangles_per_mesh: params.triangles_per_chunk,
+            geometries_per_mesh: 1,
+            seed: params.seed,
+        };
+        tracing::info!(
+            "Streaming {0}x{0} chunks of {1} instances of {2} triangles over {3} frames, {4} \
+             bytes resident at most",
+            params.chunks_per_side,
+            params.instances_per_chunk,
+            mesh_params.triangles_per_geometry(),
+            params.frames,
+            params.budget
+        );
+
+        struct ResidentChunk {
+            key: ChunkKey,
+            mesh: MeshAllocation,
+            blas: AccelerationStructure,
+            built: bool,
+            instances: Vec<Matrix4<f32>>,
+        }
+        let mut streamer = ChunkStreamer::new(
+            grid,
+            STREAM_LOAD_RADIUS,
+            STREAM_UNLOAD_RADIUS,
+            params.budget,
+        );
+        let mut geometry_pool = GeometryPool::new(self.base.clone(), BLAS_VERTEX_FORMAT.stride());
+        let mut blas_queue = BlasBuildQueue::new(self.base.clone(), self.ray_tracing.clone());
+        let mut chunks: Vec<ResidentChunk> = Vec::new();
+        // Every chunk has the same triangle count, so the first one measured stands in for the
+        // chunks not loaded yet
+        let mut chunk_size_estimate = None;
+
+        // Both grown as the resident instances outgrow them
+        let mut instance_buffer: Option<BufferResource> = None;
+        let mut top_as: Option<(AccelerationStructure, BufferResource, u32)> = None;
+
+        let (mut loads, mut unloads) = (0, 0);
+        let mut peak_resident_size = 0;
+        let mut peak_instance_count = 0;
+        let mut longest_frame = Duration::ZERO;
+        let stream_start = Instant::now();
+        let max_builds = match self.blas_builds_per_frame {
+            0 => u32::MAX,
+            builds => builds,
+        };
+        // Straight across the city through its center, starting and ending outside of it
+        let extent = (half_side as f32 + 1.0) * STREAM_CELL_SIZE + STREAM_LOAD_RADIUS;
+        for frame in 0..params.frames {
+            let frame_start = Instant::now();
+            let progress = frame as f32 / (params.frames - 1).max(1) as f32;
+            let position =
+                Point3::new(-extent + 2.0 * extent * progress, STREAM_CAMERA_HEIGHT, 0.0);
+
+            let estimate = chunk_size_estimate.unwrap_or(0);
+            let plan = streamer.update(position, STREAM_LOADS_PER_FRAME, |key| {
+                exists(key).then_some(estimate)
+            });
+
+            // The frame before waited for its commands, nothing in flight uses these any more
+            for key in &plan.unload {
+                if let Some(index) = chunks.iter().position(|chunk| chunk.key == *key) {
+                    let chunk = chunks.swap_remove(index);
+                    blas_queue.remove(chunk.blas.handle);
+                    geometry_pool.remove_mesh(&chunk.mesh);
+                    unloads += 1;
+                }
+            }
+            for key in &plan.load {
+                let mut rng = StressRng::new(
+                    params.seed ^ (((key.x as u32 as u64) << 32) | key.z as u32 as u64),
+                );
+                let geometry = random_mesh(&mut rng, &mesh_params).remove(0);
+                let mesh = geometry_pool.add_mesh(
+                    &BLAS_VERTEX_FORMAT.encode(&geometry.positions),
+                    &geometry.indices,
+                );
+                // Nearer chunks are built first when the builds are spread over frames
+                let priority = 1.0 / (1.0 + grid.distance(*key, position));
+                let blas = blas_queue.add(
+                    &[geometry_pool.triangle_geometry(&mesh)],
+                    vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE,
+                    priority,
+                );
+                blas.set_name(&format!("chunk {},{} BLAS", key.x, key.z));
+
+                let size = mesh.vertex_count as u64 * BLAS_VERTEX_FORMAT.stride() as u64
+                    + mesh.index_count as u64 * std::mem::size_of::<u16>() as u64
+                    + blas.size;
+                streamer.set_size(*key, size);
+                chunk_size_estimate.get_or_insert(size);
+
+                let center = Matrix4::from_translation(grid.center(*key).to_vec());
+                let instances = (0..params.instances_per_chunk)
+                    .map(|_| center * random_instance_transform(&mut rng, STREAM_CELL_SIZE))
+                    .collect();
+                chunks.push(ResidentChunk {
+                    key: *key,
+                    mesh,
+                    blas,
+                    built: false,
+                    instances,
+                });
+                loads += 1;
+            }
+
+            // Chunks are only traced once their BLAS is built
+            let _queue = self.base.lock_queue();
+            let command_buffer = utility::general::begin_single_time_command(
+                &self.base.device,
+                self.base.command_pool,
+            );
+            if !blas_queue.is_empty() {
+                let built = blas_queue.record(command_buffer, max_builds);
+                for chunk in chunks.iter_mut() {
+                    chunk.built |= built.contains(&chunk.blas.handle);
+                }
+            }
+            let instances: Vec<GeometryInstance> = chunks
+                .iter()
+                .filter(|chunk| chunk.built)
+                .flat_map(|chunk| {
+                    chunk.instances.iter().map(move |world_from_object| {
+                        GeometryInstance::new(
+                            InstanceTransform::from(*world_from_object),
+                            0,
+                            0xff,
+                            0,
+                            vk::GeometryInstanceFlagsNV::TRIANGLE_CULL_DISABLE_NV,
+                            chunk.blas.reference,
+                        )
+                    })
+                })
+                .collect();
+            let instance_count = instances.len() as u32;
+
+            if instance_count > 0 {
+                let capacity = top_as.as_ref().map_or(0, |(_, _, capacity)| *capacity);
+                if instance_count > capacity {
+                    let capacity = instance_count.next_power_of_two();
+                    let top_level = AccelerationStructure::new(
+                        self.base.clone(),
+                        self.ray_tracing.clone(),
+                        vk::AccelerationStructureInfoNV::builder()
+                            .ty(vk::AccelerationStructureTypeNV::TOP_LEVEL)
+                            .flags(vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE)
+                            .instance_count(capacity)
+                            .build(),
+                    );
+                    top_level.set_name("stream TLAS");
+                    let scratch = BufferResource::new(
+                        top_level
+                            .memory_requirements(
+                                vk::AccelerationStructureMemoryRequirementsTypeNV::BUILD_SCRATCH,
+                            )
+                            .size,
+                        vk::BufferUsageFlags::RAY_TRACING_NV,
+                        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                        self.base.clone(),
+                    );
+                    let buffer = BufferResource::new(
+                        (std::mem::size_of::<GeometryInstance>() * capacity as usize)
+                            as vk::DeviceSize,
+                        vk::BufferUsageFlags::RAY_TRACING_NV,
+                        vk::MemoryPropertyFlags::HOST_VISIBLE
+                            | vk::MemoryPropertyFlags::HOST_COHERENT,
+                        self.base.clone(),
+                    );
+                    buffer.set_name("stream TLAS instances");
+                    instance_buffer = Some(buffer);
+                    top_as = Some((top_level, scratch, capacity));
+                }
+                let instance_buffer = instance_buffer.as_mut().unwrap();
+                instance_buffer.store(&instances);
+                let (top_level, scratch, _) = top_as.as_ref().unwrap();
+                unsafe {
+                    self.ray_tracing.cmd_build_acceleration_structure(
+                        command_buffer,
+                        &vk::AccelerationStructureInfoNV::builder()
+                            .ty(vk::AccelerationStructureTypeNV::TOP_LEVEL)
+                            .flags(vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE)
+                            .instance_count(instance_count)
+                            .build(),
+                        instance_buffer.buffer,
+                        0,
+                        false,
+                        top_level.handle,
+                        vk::AccelerationStructureNV::null(),
+                        scratch.buffer,
+                        0,
+                    );
+                }
+            }
+            utility::general::end_single_time_command(
+                &self.base.device,
+                self.base.command_pool,
+                self.base.graphics_queue,
+                command_buffer,
+            );
+
+            peak_resident_size = peak_resident_size.max(streamer.resident_size());
+            peak_instance_count = peak_instance_count.max(instance_count);
+            longest_frame = longest_frame.max(frame_start.elapsed());
+        }
+        let stream_time = stream_start.elapsed();
+        self.check_validation_errors("the streamed city");
+
+        StreamReport {
+            loads,
+            unloads,
+            resident_chunks: streamer.resident().count(),
+            resident_size: streamer.resident_size(),
+            peak_resident_size,
+            peak_instance_count,
+            time: stream_time,
+            longest_frame,
+            geometry_pool: geometry_pool.stats(),
+        }
+    }
+
+    // Collects VK_KHR_performance_query counters for the marked passes of the following frames
+    fn enable_gpu_counters(&mut self) -> bool {
+        if !self.base.features.contains(DeviceFeature::PerformanceQuery) {
+            tracing::warn!("No VK_KHR_performance_query support, the passes are only labeled");
+            return false;
+        }
+        let counters = KhrPerformanceCounters::new(
+            &self.base.entry,
+            &self.base.instance,
+            self.base.physical_device,
+            &self.base.device,
+            self.base.queue_family.graphics_family.unwrap(),
+        );
+        match counters {
+            Ok(counters) => {
+                self.profiler.set_backend(Some(Box::new(counters)));
+                true
+            }
+            Err(message) => {
+                tracing::warn!("GPU counters are unavailable: {}", message);
+                false
+            }
+        }
+    }
+
+    // Traces a frame with the GPU counters on and returns them per pass
+    pub fn profile_frame(&mut self) -> Vec<PassCounters> {
+        self.wait_for_pipeline();
+        if !self.profiler.has_backend() {
+            self.enable_gpu_counters();
+        }
+        self.trace_frame();
+        self.profiler.results().to_vec()
+    }
+}
@@ -0,0 +1,114 @@
+use crate::utility::{bounds::Aabb, constants::*, structures::*};
+use cgmath::{Deg, EuclideanSpace, Matrix4, Vector3};
+
+use ash::vk;
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub(super) struct GeometryInstance {
+    pub(super) transform: InstanceTransform,
+    pub(super) instance_id_and_mask: u32,
+    pub(super) instance_offset_and_flags: u32,
+    pub(super) acceleration_handle: u64,
+}
+
+impl GeometryInstance {
+    pub(super) fn new(
+        transform: InstanceTransform,
+        id: u32,
+        mask: u8,
+        offset: u32,
+        flags: vk::GeometryInstanceFlagsNV,
+        acceleration_handle: u64,
+    ) -> Self {
+        let mut instance = GeometryInstance {
+            transform,
+            instance_id_and_mask: 0,
+            instance_offset_and_flags: 0,
+            acceleration_handle,
+        };
+        instance.set_id(id);
+        instance.set_mask(mask);
+        instance.set_offset(offset);
+        instance.set_flags(flags);
+        instance
+    }
+
+    fn set_id(&mut self, id: u32) {
+        let id = id & 0x00ffffff;
+        self.instance_id_and_mask |= id;
+    }
+
+    pub(super) fn set_mask(&mut self, mask: u8) {
+        let mask = mask as u32;
+        self.instance_id_and_mask = (self.instance_id_and_mask & 0x00ffffff) | mask << 24;
+    }
+
+    pub(super) fn set_offset(&mut self, offset: u32) {
+        let offset = offset & 0x00ffffff;
+        self.instance_offset_and_flags = (self.instance_offset_and_flags & 0xff000000) | offset;
+    }
+
+    pub(super) fn set_flags(&mut self, flags: vk::GeometryInstanceFlagsNV) {
+        let flags = flags.as_raw() as u32;
+        self.instance_offset_and_flags =
+            (self.instance_offset_and_flags & 0x00ffffff) | flags << 24;
+    }
+}
+
+// Instance transform at the middle of a motion blur time sample, `motion` is the translation
+// over the whole shutter interval
+pub(super) fn motion_sample_transform(
+    transform: &InstanceTransform,
+    motion: &[f32; 3],
+    sample: u32,
+    sample_count: u32,
+) -> InstanceTransform {
+    if sample_count <= 1 {
+        return *transform;
+    }
+    let time = (sample as f32 + 0.5) / sample_count as f32;
+    let end_transform = InstanceTransform::from(
+        Matrix4::from_translation(Vector3::from(*motion)) * Matrix4::from(*transform),
+    );
+    transform.lerp(&end_transform, time)
+}
+
+// Transform, color and emission of the GROUND_PLANE instance: the mesh laid flat, scaled up and
+// centered under `scene_bounds`
+pub(super) fn ground_plane_instance(
+    mesh_bounds: &Aabb,
+    scene_bounds: &Aabb,
+) -> Option<(InstanceTransform, [f32; 4], [f32; 4])> {
+    let (color, emission) = match GROUND_PLANE {
+        GroundPlane::None => return None,
+        GroundPlane::Checker { color, size } => (
+            [color[0], color[1], color[2], size],
+            [0.0, 0.0, 0.0, INSTANCE_MATERIAL_CHECKER],
+        ),
+        GroundPlane::ShadowCatcher => ([1.0; 4], [0.0, 0.0, 0.0, INSTANCE_MATERIAL_SHADOW_CATCHER]),
+    };
+
+    // The mesh lies in its z = 0 plane, turned so z points up the world y axis
+    let center = scene_bounds.center();
+    let transform = Matrix4::from_translation(Vector3::new(center.x, scene_bounds.min.y, center.z))
+        * Matrix4::from_angle_x(Deg(-90.0))
+        * Matrix4::from_scale(GROUND_PLANE_SCALE)
+        * Matrix4::from_translation(-mesh_bounds.center().to_vec());
+    Some((InstanceTransform::from(transform), color, emission))
+}
+
+// The mesh every scene instance references, before tessellation
+pub fn scene_triangle() -> [VertexRt; 3] {
+    [
+        VertexRt {
+            pos: [-0.5, -0.5, 0.0],
+        },
+        VertexRt {
+            pos: [0.0, 0.5, 0.0],
+        },
+        VertexRt {
+            pos: [0.5, -0.5, 0.0],
+        },
+    ]
+}
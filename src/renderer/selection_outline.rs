@@ -0,0 +1,288 @@
+use std::{path::Path, sync::Arc};
+
+use super::{base::Renderer, pipelines::create_compute_pipeline, resources::BufferResource};
+use crate::utility::{constants::*, push_constant_diagnostics::PushConstantLayout, structures::*};
+
+use ash::vk;
+
+// Outlines the selected instances over the tonemapped image. The instance ID target of a raster
+// pre-pass is the selection mask, dilated by SELECTION_OUTLINE_RADIUS in one compute pass.
+pub(super) struct SelectionOutlinePass {
+    base: Arc<Renderer>,
+    extent: vk::Extent2D,
+    // One bit per instance, instance_count of them
+    selection_buffer: Option<BufferResource>,
+    pub(super) instance_count: u32,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl SelectionOutlinePass {
+    pub(super) fn new(base: Arc<Renderer>, extent: vk::Extent2D, instance_count: u32) -> Self {
+        SelectionOutlinePass {
+            base,
+            extent,
+            selection_buffer: None,
+            instance_count,
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        }
+    }
+
+    pub(super) fn initialize(
+        &mut self,
+        instance_view: vk::ImageView,
+        instance_sampler: vk::Sampler,
+        ldr_view: vk::ImageView,
+    ) {
+        self.selection_buffer = Some(BufferResource::new(
+            (std::mem::size_of::<u32>() as u32 * self.instance_count.div_ceil(32).max(1))
+                as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        ));
+        self.set_selection(&[]);
+        self.create_pipeline();
+        self.create_descriptor_set(instance_view, instance_sampler, ldr_view);
+    }
+
+    // IDs past the instance count are ignored. The GPU must be done with the last frame.
+    pub(super) fn set_selection(&mut self, instance_ids: &[u32]) {
+        let mut bits = vec![0u32; self.instance_count.div_ceil(32).max(1) as usize];
+        for &instance_id in instance_ids {
+            if instance_id < self.instance_count {
+                bits[(instance_id / 32) as usize] |= 1 << (instance_id % 32);
+            }
+        }
+        self.selection_buffer.as_mut().unwrap().store(&bits);
+    }
+
+    fn create_pipeline(&mut self) {
+        let binding = |binding, descriptor_type| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            ..Default::default()
+        };
+        let bindings = [
+            // instance IDs of the pre-pass
+            binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            // tonemapped image
+            binding(1, vk::DescriptorType::STORAGE_IMAGE),
+            // selection bits
+            binding(2, vk::DescriptorType::STORAGE_BUFFER),
+        ];
+        unsafe {
+            self.descriptor_set_layout = self
+                .base
+                .device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::builder()
+                        .bindings(&bindings)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create selection outline descriptor set layout.");
+
+            let push_constant_ranges = [vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<SelectionOutlinePushConstants>() as u32,
+            }];
+            let layouts = [self.descriptor_set_layout];
+            self.pipeline_layout = self
+                .base
+                .device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder()
+                        .set_layouts(&layouts)
+                        .push_constant_ranges(&push_constant_ranges)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create selection outline pipeline layout.");
+        }
+
+        self.pipeline = create_compute_pipeline(
+            &self.base,
+            self.pipeline_layout,
+            Path::new("shaders/compiled/selection.glsl_outline_comp.spv"),
+            PushConstantLayout::of::<SelectionOutlinePushConstants>(),
+        );
+    }
+
+    fn create_descriptor_set(
+        &mut self,
+        instance_view: vk::ImageView,
+        instance_sampler: vk::Sampler,
+        ldr_view: vk::ImageView,
+    ) {
+        unsafe {
+            let descriptor_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 1,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: 1,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 1,
+                },
+            ];
+            self.descriptor_pool = self
+                .base
+                .device
+                .create_descriptor_pool(
+                    &vk::DescriptorPoolCreateInfo::builder()
+                        .pool_sizes(&descriptor_sizes)
+                        .max_sets(1)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create selection outline descriptor pool.");
+
+            let layouts = [self.descriptor_set_layout];
+            self.descriptor_set = self
+                .base
+                .device
+                .allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::builder()
+                        .descriptor_pool(self.descriptor_pool)
+                        .set_layouts(&layouts)
+                        .build(),
+                )
+                .expect("Failed to allocate selection outline descriptor set.")[0];
+
+            let instance_info = [vk::DescriptorImageInfo {
+                sampler: instance_sampler,
+                image_view: instance_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }];
+            let ldr_info = [vk::DescriptorImageInfo {
+                image_layout: vk::ImageLayout::GENERAL,
+                image_view: ldr_view,
+                ..Default::default()
+            }];
+            let selection_info = [vk::DescriptorBufferInfo {
+                buffer: self.selection_buffer.as_ref().unwrap().buffer,
+                range: vk::WHOLE_SIZE,
+                ..Default::default()
+            }];
+            let descriptor_writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&instance_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(&ldr_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(2)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&selection_info)
+                    .build(),
+            ];
+            self.base
+                .device
+                .update_descriptor_sets(&descriptor_writes, &[]);
+        }
+    }
+
+    // After the tonemapping, the pre-pass render pass already made its instance IDs visible to
+    // compute shaders
+    pub(super) fn record(&self, command_buffer: vk::CommandBuffer) {
+        let push_constants = SelectionOutlinePushConstants {
+            color: SELECTION_OUTLINE_COLOR,
+            width: self.extent.width,
+            height: self.extent.height,
+            instance_count: self.instance_count,
+            radius: SELECTION_OUTLINE_RADIUS,
+        };
+
+        let tonemap_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .build();
+
+        unsafe {
+            let device = &self.base.device;
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[tonemap_barrier],
+                &[],
+                &[],
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const SelectionOutlinePushConstants as *const u8,
+                    std::mem::size_of::<SelectionOutlinePushConstants>(),
+                ),
+            );
+            device.cmd_dispatch(
+                command_buffer,
+                self.extent.width.div_ceil(SELECTION_OUTLINE_WORKGROUP_SIZE),
+                self.extent
+                    .height
+                    .div_ceil(SELECTION_OUTLINE_WORKGROUP_SIZE),
+                1,
+            );
+        }
+    }
+
+    pub(super) fn release(&mut self) {
+        unsafe {
+            self.base
+                .shader_modules
+                .lock()
+                .unwrap()
+                .destroy_pipeline(self.pipeline);
+            self.base
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.base
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.base
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        self.selection_buffer = None;
+    }
+}
@@ -0,0 +1,944 @@
+use std::{ffi::CString, path::Path, sync::Arc};
+
+use super::{
+    base::Renderer,
+    depth_prepass::DepthPrepass,
+    environment_probes::EnvironmentProbes,
+    geometry_pool::{GeometryPool, MeshAllocation},
+    light_culling::LightCuller,
+    resources::{BufferResource, ImageResource},
+};
+use crate::utility::{constants::*, frame_scratch::ScratchVec, structures::*, sun::SunController};
+use cgmath::Matrix4;
+
+use ash::vk;
+
+// Raster alternative to the hybrid mode's shadow rays: the scene is rendered depth-only from
+// the light, then the depth pre-pass is shaded in a fullscreen pass with percentage closer
+// filtered lookups into that shadow map
+pub(super) struct ShadowMapPass {
+    base: Arc<Renderer>,
+    extent: vk::Extent2D,
+    pub(super) shadow_target: ImageResource,
+    depth_render_pass: vk::RenderPass,
+    depth_framebuffer: vk::Framebuffer,
+    // Light clip_from_object of every instance, read by the depth pre-pass vertex shader
+    transform_buffer: Option<BufferResource>,
+    uniform_buffer: Option<BufferResource>,
+    // Draws into the HDR target in place of the ray generation shader
+    shade_render_pass: vk::RenderPass,
+    shade_framebuffer: vk::Framebuffer,
+    descriptor_pool: vk::DescriptorPool,
+    depth_descriptor_set_layout: vk::DescriptorSetLayout,
+    depth_descriptor_set: vk::DescriptorSet,
+    depth_pipeline_layout: vk::PipelineLayout,
+    depth_pipeline: vk::Pipeline,
+    shade_descriptor_set_layout: vk::DescriptorSetLayout,
+    shade_descriptor_set: vk::DescriptorSet,
+    shade_pipeline_layout: vk::PipelineLayout,
+    shade_pipeline: vk::Pipeline,
+    // Written to the uniforms with every record
+    sun: SunParams,
+    light_from_objects: ScratchVec<Matrix4<f32>>,
+}
+
+impl ShadowMapPass {
+    pub(super) fn new(base: Arc<Renderer>, extent: vk::Extent2D) -> Self {
+        ShadowMapPass {
+            base: base.clone(),
+            extent,
+            shadow_target: ImageResource::new(base),
+            depth_render_pass: vk::RenderPass::null(),
+            depth_framebuffer: vk::Framebuffer::null(),
+            transform_buffer: None,
+            uniform_buffer: None,
+            shade_render_pass: vk::RenderPass::null(),
+            shade_framebuffer: vk::Framebuffer::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            depth_descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            depth_descriptor_set: vk::DescriptorSet::null(),
+            depth_pipeline_layout: vk::PipelineLayout::null(),
+            depth_pipeline: vk::Pipeline::null(),
+            shade_descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            shade_descriptor_set: vk::DescriptorSet::null(),
+            shade_pipeline_layout: vk::PipelineLayout::null(),
+            shade_pipeline: vk::Pipeline::null(),
+            sun: SunController::default().params(),
+            light_from_objects: ScratchVec::default(),
+        }
+    }
+
+    pub(super) fn set_sun(&mut self, sun: SunParams) {
+        self.sun = sun;
+    }
+
+    pub(super) fn initialize(
+        &mut self,
+        output_view: vk::ImageView,
+        output_format: vk::Format,
+        instance_data_buffer: vk::Buffer,
+        depth_prepass: &DepthPrepass,
+        light_culler: &LightCuller,
+        environment_probes: &EnvironmentProbes,
+        instance_count: u32,
+    ) {
+        self.create_shadow_target();
+        self.create_render_passes(output_view, output_format);
+        self.create_buffers(instance_count);
+        self.create_descriptor_sets(
+            instance_data_buffer,
+            depth_prepass,
+            light_culler,
+            environment_probes,
+        );
+        self.create_depth_pipeline();
+        self.create_shade_pipeline();
+    }
+
+    fn create_shadow_target(&mut self) {
+        let extent = vk::Extent3D::builder()
+            .width(SHADOW_MAP_SIZE)
+            .height(SHADOW_MAP_SIZE)
+            .depth(1)
+            .build();
+        self.shadow_target.create_image(
+            vk::ImageType::TYPE_2D,
+            DEPTH_PREPASS_FORMAT,
+            extent,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+        self.shadow_target.create_view(
+            vk::ImageViewType::TYPE_2D,
+            DEPTH_PREPASS_FORMAT,
+            vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+        );
+
+        // Bilinear depth comparisons, the edge texels stretch out over the clamped lookups
+        let sampler_create_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .compare_enable(true)
+            .compare_op(vk::CompareOp::LESS_OR_EQUAL)
+            .build();
+        self.shadow_target.sampler = unsafe {
+            self.base
+                .device
+                .create_sampler(&sampler_create_info, None)
+                .expect("Failed to create shadow map sampler.")
+        };
+    }
+
+    fn create_render_passes(&mut self, output_view: vk::ImageView, output_format: vk::Format) {
+        let depth_attachments = [vk::AttachmentDescription::builder()
+            .format(DEPTH_PREPASS_FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .build()];
+        let depth_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+        let depth_subpasses = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .build()];
+
+        let depth_stages = vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let depth_dependencies = [
+            // The previous frame's shading must be done with the map before it is cleared
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_stage_mask(depth_stages)
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .build(),
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(depth_stages)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build(),
+        ];
+
+        // Every pixel is written, and the HDR target stays in GENERAL for the storage image
+        // writes of the other modes
+        let shade_attachments = [vk::AttachmentDescription::builder()
+            .format(output_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::GENERAL)
+            .final_layout(vk::ImageLayout::GENERAL)
+            .build()];
+        let color_attachment_refs = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::GENERAL,
+        }];
+        let shade_subpasses = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .build()];
+
+        let shade_dependencies = [
+            // Waits for the layout transition at the start of the frame
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::RAY_TRACING_SHADER_NV
+                        | vk::PipelineStageFlags::COMPUTE_SHADER
+                        | vk::PipelineStageFlags::TRANSFER,
+                )
+                .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .build(),
+            // Drawn over by the debug overlay, metered and tonemapped by the exposure pass
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::COMPUTE_SHADER
+                        | vk::PipelineStageFlags::TRANSFER,
+                )
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_READ
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::SHADER_READ
+                        | vk::AccessFlags::TRANSFER_READ,
+                )
+                .build(),
+        ];
+
+        unsafe {
+            self.depth_render_pass = self
+                .base
+                .device
+                .create_render_pass(
+                    &vk::RenderPassCreateInfo::builder()
+                        .attachments(&depth_attachments)
+                        .subpasses(&depth_subpasses)
+                        .dependencies(&depth_dependencies)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create shadow map render pass.");
+            self.shade_render_pass = self
+                .base
+                .device
+                .create_render_pass(
+                    &vk::RenderPassCreateInfo::builder()
+                        .attachments(&shade_attachments)
+                        .subpasses(&shade_subpasses)
+                        .dependencies(&shade_dependencies)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create shadow map shading render pass.");
+
+            let depth_framebuffer_attachments = [self.shadow_target.view];
+            self.depth_framebuffer = self
+                .base
+                .device
+                .create_framebuffer(
+                    &vk::FramebufferCreateInfo::builder()
+                        .render_pass(self.depth_render_pass)
+                        .attachments(&depth_framebuffer_attachments)
+                        .width(SHADOW_MAP_SIZE)
+                        .height(SHADOW_MAP_SIZE)
+                        .layers(1)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create shadow map framebuffer.");
+
+            let shade_framebuffer_attachments = [output_view];
+            self.shade_framebuffer = self
+                .base
+                .device
+                .create_framebuffer(
+                    &vk::FramebufferCreateInfo::builder()
+                        .render_pass(self.shade_render_pass)
+                        .attachments(&shade_framebuffer_attachments)
+                        .width(self.extent.width)
+                        .height(self.extent.height)
+                        .layers(1)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create shadow map shading framebuffer.");
+        }
+    }
+
+    // Both are rewritten every frame, which waits for the previous one to finish
+    fn create_buffers(&mut self, instance_count: u32) {
+        self.transform_buffer = Some(BufferResource::new(
+            (std::mem::size_of::<Matrix4<f32>>() * instance_count.max(1) as usize)
+                as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        ));
+        self.uniform_buffer = Some(BufferResource::new(
+            std::mem::size_of::<ShadowMapUniforms>() as vk::DeviceSize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.base.clone(),
+        ));
+    }
+
+    fn create_descriptor_sets(
+        &mut self,
+        instance_data_buffer: vk::Buffer,
+        depth_prepass: &DepthPrepass,
+        light_culler: &LightCuller,
+        environment_probes: &EnvironmentProbes,
+    ) {
+        let binding = |binding: u32, descriptor_type, stage_flags| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+        };
+        let depth_bindings = [binding(
+            0,
+            vk::DescriptorType::STORAGE_BUFFER,
+            vk::ShaderStageFlags::VERTEX,
+        )];
+        // Bindings of shadowmap_shade.frag
+        let shade_bindings = [
+            binding(
+                0,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                1,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                2,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                3,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                4,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                5,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                6,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                7,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+            binding(
+                8,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+        ];
+
+        let descriptor_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 5,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 4,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+            },
+        ];
+
+        unsafe {
+            let device = &self.base.device;
+            self.depth_descriptor_set_layout = device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::builder()
+                        .bindings(&depth_bindings)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create shadow map descriptor set layout.");
+            self.shade_descriptor_set_layout = device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::builder()
+                        .bindings(&shade_bindings)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create shadow map shading descriptor set layout.");
+
+            self.descriptor_pool = device
+                .create_descriptor_pool(
+                    &vk::DescriptorPoolCreateInfo::builder()
+                        .pool_sizes(&descriptor_sizes)
+                        .max_sets(2)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create shadow map descriptor pool.");
+
+            let set_layouts = [
+                self.depth_descriptor_set_layout,
+                self.shade_descriptor_set_layout,
+            ];
+            let descriptor_sets = device
+                .allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::builder()
+                        .descriptor_pool(self.descriptor_pool)
+                        .set_layouts(&set_layouts)
+                        .build(),
+                )
+                .expect("Failed to allocate shadow map descriptor sets.");
+            self.depth_descriptor_set = descriptor_sets[0];
+            self.shade_descriptor_set = descriptor_sets[1];
+
+            let buffer_info = |buffer: vk::Buffer| {
+                [vk::DescriptorBufferInfo {
+                    buffer,
+                    range: vk::WHOLE_SIZE,
+                    ..Default::default()
+                }]
+            };
+            let transform_info = buffer_info(self.transform_buffer.as_ref().unwrap().buffer);
+            let instance_info = buffer_info(instance_data_buffer);
+            let uniform_info = buffer_info(self.uniform_buffer.as_ref().unwrap().buffer);
+            let light_info = buffer_info(light_culler.light_buffer());
+            let tile_info = buffer_info(light_culler.tile_buffer());
+            let depth_info = [vk::DescriptorImageInfo {
+                sampler: depth_prepass.depth_target.sampler,
+                image_view: depth_prepass.depth_target.view,
+                image_layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            }];
+            let instance_id_info = [vk::DescriptorImageInfo {
+                sampler: depth_prepass.depth_target.sampler,
+                image_view: depth_prepass.instance_target.view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }];
+            let shadow_info = [vk::DescriptorImageInfo {
+                sampler: self.shadow_target.sampler,
+                image_view: self.shadow_target.view,
+                image_layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            }];
+
+            let buffer_write =
+                |set, binding, descriptor_type, info: &[vk::DescriptorBufferInfo]| {
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(set)
+                        .dst_binding(binding)
+                        .descriptor_type(descriptor_type)
+                        .buffer_info(info)
+                        .build()
+                };
+            let image_write = |binding, info: &[vk::DescriptorImageInfo]| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.shade_descriptor_set)
+                    .dst_binding(binding)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(info)
+                    .build()
+            };
+            let descriptor_writes = [
+                buffer_write(
+                    self.depth_descriptor_set,
+                    0,
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    &transform_info,
+                ),
+                buffer_write(
+                    self.shade_descriptor_set,
+                    0,
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    &instance_info,
+                ),
+                image_write(1, &depth_info),
+                image_write(2, &instance_id_info),
+                image_write(3, &shadow_info),
+                buffer_write(
+                    self.shade_descriptor_set,
+                    4,
+                    vk::DescriptorType::UNIFORM_BUFFER,
+                    &uniform_info,
+                ),
+                buffer_write(
+                    self.shade_descriptor_set,
+                    5,
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    &light_info,
+                ),
+                buffer_write(
+                    self.shade_descriptor_set,
+                    6,
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    &tile_info,
+                ),
+            ];
+            device.update_descriptor_sets(&descriptor_writes, &[]);
+        }
+        self.set_environment_probes(environment_probes);
+    }
+
+    // Points the shading at other probes, the old ones must not be in use anymore
+    pub(super) fn set_environment_probes(&mut self, environment_probes: &EnvironmentProbes) {
+        let image_info = [vk::DescriptorImageInfo {
+            sampler: environment_probes.image.sampler,
+            image_view: environment_probes.image.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let buffer_info = [vk::DescriptorBufferInfo {
+            buffer: environment_probes.buffer.buffer,
+            range: vk::WHOLE_SIZE,
+            ..Default::default()
+        }];
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(self.shade_descriptor_set)
+                .dst_binding(7)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(self.shade_descriptor_set)
+                .dst_binding(8)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&buffer_info)
+                .build(),
+        ];
+        unsafe {
+            self.base
+                .device
+                .update_descriptor_sets(&descriptor_writes, &[]);
+        }
+    }
+
+    fn create_depth_pipeline(&mut self) {
+        unsafe {
+            let set_layouts = [self.depth_descriptor_set_layout];
+            self.depth_pipeline_layout = self
+                .base
+                .device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder()
+                        .set_layouts(&set_layouts)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create shadow map pipeline layout.");
+
+            // The depth pre-pass vertex shader with light transforms, no fragment shader
+            let mut shader_modules = self.base.shader_modules.lock().unwrap();
+            let vert_module =
+                shader_modules.load(Path::new("shaders/compiled/depth.glsl_prepass_vert.spv"));
+
+            let main_name = CString::new("main").unwrap();
+            let shader_stages = [vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vert_module)
+                .name(&main_name)
+                .build()];
+
+            // Same vertex layout as the BLAS geometry
+            let binding_descriptions = [vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: BLAS_VERTEX_FORMAT.stride() as u32,
+                input_rate: vk::VertexInputRate::VERTEX,
+            }];
+            let attribute_descriptions = [vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: BLAS_VERTEX_FORMAT.vk_format(),
+                offset: 0,
+            }];
+            let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+                .vertex_binding_descriptions(&binding_descriptions)
+                .vertex_attribute_descriptions(&attribute_descriptions)
+                .build();
+            let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+                .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                .build();
+
+            let viewports = [vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: SHADOW_MAP_SIZE as f32,
+                height: SHADOW_MAP_SIZE as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }];
+            let scissors = [vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D {
+                    width: SHADOW_MAP_SIZE,
+                    height: SHADOW_MAP_SIZE,
+                },
+            }];
+            let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+                .viewports(&viewports)
+                .scissors(&scissors)
+                .build();
+
+            // Two-sided like the shadow rays, which see every instance with culling disabled
+            let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+                .polygon_mode(vk::PolygonMode::FILL)
+                .cull_mode(vk::CullModeFlags::NONE)
+                .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                .depth_bias_enable(true)
+                .depth_bias_constant_factor(SHADOW_MAP_DEPTH_BIAS_CONSTANT)
+                .depth_bias_slope_factor(SHADOW_MAP_DEPTH_BIAS_SLOPE)
+                .line_width(1.0)
+                .build();
+            let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+                .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                .build();
+            let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+                .depth_test_enable(true)
+                .depth_write_enable(true)
+                .depth_compare_op(vk::CompareOp::LESS)
+                .build();
+            let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder().build();
+
+            let pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
+                .stages(&shader_stages)
+                .vertex_input_state(&vertex_input_state)
+                .input_assembly_state(&input_assembly_state)
+                .viewport_state(&viewport_state)
+                .rasterization_state(&rasterization_state)
+                .multisample_state(&multisample_state)
+                .depth_stencil_state(&depth_stencil_state)
+                .color_blend_state(&color_blend_state)
+                .layout(self.depth_pipeline_layout)
+                .render_pass(self.depth_render_pass)
+                .subpass(0)
+                .build();
+
+            self.depth_pipeline = self
+                .base
+                .device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_create_info], None)
+                .expect("Failed to create shadow map pipeline.")[0];
+
+            shader_modules.attach(self.depth_pipeline, &[vert_module]);
+        }
+    }
+
+    fn create_shade_pipeline(&mut self) {
+        unsafe {
+            let push_constant_ranges = [vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: (std::mem::size_of::<CameraPushConstants>()
+                    + std::mem::size_of::<DepthRangePushConstants>()) as u32,
+            }];
+            let set_layouts = [self.shade_descriptor_set_layout];
+            self.shade_pipeline_layout = self
+                .base
+                .device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder()
+                        .set_layouts(&set_layouts)
+                        .push_constant_ranges(&push_constant_ranges)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create shadow map shading pipeline layout.");
+
+            let mut shader_modules = self.base.shader_modules.lock().unwrap();
+            let mut load_module = |path: &Path| shader_modules.load(path);
+            let vert_module =
+                load_module(Path::new("shaders/compiled/shadowmap.glsl_shade_vert.spv"));
+            let frag_module =
+                load_module(Path::new("shaders/compiled/shadowmap.glsl_shade_frag.spv"));
+
+            let main_name = CString::new("main").unwrap();
+            let shader_stages = [
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::VERTEX)
+                    .module(vert_module)
+                    .name(&main_name)
+                    .build(),
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::FRAGMENT)
+                    .module(frag_module)
+                    .name(&main_name)
+                    .build(),
+            ];
+
+            // The fullscreen triangle comes from gl_VertexIndex
+            let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder().build();
+            let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+                .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                .build();
+
+            let viewports = [vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: self.extent.width as f32,
+                height: self.extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }];
+            let scissors = [vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            }];
+            let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+                .viewports(&viewports)
+                .scissors(&scissors)
+                .build();
+
+            let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+                .polygon_mode(vk::PolygonMode::FILL)
+                .cull_mode(vk::CullModeFlags::NONE)
+                .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                .line_width(1.0)
+                .build();
+            let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+                .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                .build();
+            let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder().build();
+            let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(false)
+                .color_write_mask(vk::ColorComponentFlags::RGBA)
+                .build()];
+            let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+                .attachments(&color_blend_attachments)
+                .build();
+
+            let pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
+                .stages(&shader_stages)
+                .vertex_input_state(&vertex_input_state)
+                .input_assembly_state(&input_assembly_state)
+                .viewport_state(&viewport_state)
+                .rasterization_state(&rasterization_state)
+                .multisample_state(&multisample_state)
+                .depth_stencil_state(&depth_stencil_state)
+                .color_blend_state(&color_blend_state)
+                .layout(self.shade_pipeline_layout)
+                .render_pass(self.shade_render_pass)
+                .subpass(0)
+                .build();
+
+            self.shade_pipeline = self
+                .base
+                .device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_create_info], None)
+                .expect("Failed to create shadow map shading pipeline.")[0];
+
+            shader_modules.attach(self.shade_pipeline, &[vert_module, frag_module]);
+        }
+    }
+
+    // Renders the shadow map from `light_from_world` and shades the depth pre-pass recorded
+    // before it, `world_from_objects` holds one transform per instance in instance data order
+    pub(super) fn record(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        geometry_pool: &GeometryPool,
+        mesh: &MeshAllocation,
+        light_from_world: Matrix4<f32>,
+        world_from_objects: &[Matrix4<f32>],
+        camera: CameraPushConstants,
+        depth_range: DepthRangePushConstants,
+    ) {
+        let mut light_from_objects = self.light_from_objects.take();
+        light_from_objects.extend(
+            world_from_objects
+                .iter()
+                .map(|world_from_object| light_from_world * world_from_object),
+        );
+        self.transform_buffer
+            .as_mut()
+            .unwrap()
+            .store(&light_from_objects);
+        self.light_from_objects.give_back(light_from_objects);
+        self.uniform_buffer
+            .as_mut()
+            .unwrap()
+            .store(&[ShadowMapUniforms {
+                light_from_world,
+                texel_size: 1.0 / SHADOW_MAP_SIZE as f32,
+                padding: [0.0; 3],
+                sun: self.sun,
+            }]);
+
+        let clear_values = [vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        }];
+        let depth_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.depth_render_pass)
+            .framebuffer(self.depth_framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D {
+                    width: SHADOW_MAP_SIZE,
+                    height: SHADOW_MAP_SIZE,
+                },
+            })
+            .clear_values(&clear_values)
+            .build();
+        let shade_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.shade_render_pass)
+            .framebuffer(self.shade_framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .build();
+
+        unsafe {
+            let device = &self.base.device;
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &depth_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.depth_pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.depth_pipeline_layout,
+                0,
+                &[self.depth_descriptor_set],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[geometry_pool.vertex_buffer(mesh)],
+                &[0],
+            );
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                geometry_pool.index_buffer(mesh),
+                0,
+                vk::IndexType::UINT16,
+            );
+            // Every instance casts shadows, there is no culling against the light view
+            device.cmd_draw_indexed(
+                command_buffer,
+                mesh.index_count,
+                world_from_objects.len() as u32,
+                mesh.first_index(),
+                mesh.base_vertex(geometry_pool.vertex_stride),
+                0,
+            );
+            device.cmd_end_render_pass(command_buffer);
+
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &shade_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.shade_pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.shade_pipeline_layout,
+                0,
+                &[self.shade_descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.shade_pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                std::slice::from_raw_parts(
+                    &camera as *const CameraPushConstants as *const u8,
+                    std::mem::size_of::<CameraPushConstants>(),
+                ),
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.shade_pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                std::mem::size_of::<CameraPushConstants>() as u32,
+                std::slice::from_raw_parts(
+                    &depth_range as *const DepthRangePushConstants as *const u8,
+                    std::mem::size_of::<DepthRangePushConstants>(),
+                ),
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    pub(super) fn release(&mut self) {
+        let mut shader_modules = self.base.shader_modules.lock().unwrap();
+        shader_modules.destroy_pipeline(self.depth_pipeline);
+        shader_modules.destroy_pipeline(self.shade_pipeline);
+        unsafe {
+            let device = &self.base.device;
+            device.destroy_pipeline_layout(self.depth_pipeline_layout, None);
+            device.destroy_pipeline_layout(self.shade_pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.depth_descriptor_set_layout, None);
+            device.destroy_descriptor_set_layout(self.shade_descriptor_set_layout, None);
+            device.destroy_framebuffer(self.depth_framebuffer, None);
+            device.destroy_framebuffer(self.shade_framebuffer, None);
+            device.destroy_render_pass(self.depth_render_pass, None);
+            device.destroy_render_pass(self.shade_render_pass, None);
+        }
+        self.transform_buffer = None;
+        self.uniform_buffer = None;
+    }
+}
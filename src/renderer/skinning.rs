@@ -0,0 +1,465 @@
+use std::{path::Path, sync::Arc};
+
+use super::{
+    acceleration_structure::AccelerationStructure,
+    base::Renderer,
+    geometry_pool::{GeometryPool, MeshAllocation},
+    pipelines::create_compute_pipeline,
+    resources::{BufferResource, MappedBuffer},
+};
+use crate::utility::{
+    bounds::Aabb,
+    constants::*,
+    push_constant_diagnostics::PushConstantLayout,
+    skinning::{Skin, SkinPose},
+    structures::*,
+    vertex_format::VertexPositionFormat,
+};
+use cgmath::{Matrix4, Point3};
+
+use ash::{extensions::nv, vk};
+
+// Deforms the scene mesh in its geometry pool block with the pose of a Skin, then updates the
+// BLAS over the same vertices. The raster passes draw from the pool too, so every render mode
+// shows the mesh in the same pose. The deformed normals are written for the closest hit shaders,
+// which interpolate them over the triangle of each hit.
+pub(super) struct SkinningPass {
+    base: Arc<Renderer>,
+    ray_tracing: Arc<nv::RayTracing>,
+    skin: Skin,
+    // What the mesh was uploaded with, deformed on the host too for the bounds of each pose
+    positions: Vec<[f32; 3]>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    rest_positions: Option<BufferResource>,
+    influences: Option<BufferResource>,
+    morph_deltas: Option<BufferResource>,
+    joint_matrices: Option<MappedBuffer<Matrix4<f32>>>,
+    morph_weights: Option<MappedBuffer<f32>>,
+    // One object space normal per vertex, and the mesh indices as uints, bound to the scene set
+    normals: Option<BufferResource>,
+    indices: Option<BufferResource>,
+    blas_update_scratch: Option<BufferResource>,
+    // The pool block written by the descriptor set, bound again after a defragmentation
+    bound_vertex_buffer: vk::Buffer,
+    // Deformed by the next frame
+    pending_pose: Option<SkinPose>,
+}
+
+impl SkinningPass {
+    pub(super) fn new(base: Arc<Renderer>, ray_tracing: Arc<nv::RayTracing>, skin: Skin) -> Self {
+        let pending_pose = Some(skin.rest_pose());
+        SkinningPass {
+            base,
+            ray_tracing,
+            skin,
+            positions: Vec::new(),
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            rest_positions: None,
+            influences: None,
+            morph_deltas: None,
+            joint_matrices: None,
+            morph_weights: None,
+            normals: None,
+            indices: None,
+            blas_update_scratch: None,
+            bound_vertex_buffer: vk::Buffer::null(),
+            pending_pose,
+        }
+    }
+
+    // The positions and indices the mesh was uploaded with and its BLAS, built with
+    // SKINNED_BLAS_FLAGS
+    pub(super) fn initialize(
+        &mut self,
+        positions: &[[f32; 3]],
+        indices: &[u16],
+        bottom_as: &AccelerationStructure,
+    ) {
+        let storage_buffer = |data_size: usize, name: &str| {
+            let buffer = BufferResource::new(
+                data_size as vk::DeviceSize,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                self.base.clone(),
+            );
+            buffer.set_name(name);
+            buffer
+        };
+        let rest_positions: Vec<[f32; 4]> = positions
+            .iter()
+            .map(|position| [position[0], position[1], position[2], 1.0])
+            .collect();
+        let influences = self.skin.vertex_influences(positions.len());
+        let morph_deltas = self.skin.packed_morph_deltas();
+        let indices: Vec<u32> = indices.iter().map(|&index| index as u32).collect();
+
+        let mut rest_positions_buffer = storage_buffer(
+            std::mem::size_of_val(rest_positions.as_slice()),
+            "skin rest positions",
+        );
+        rest_positions_buffer.store(&rest_positions);
+        let mut influences_buffer = storage_buffer(
+            std::mem::size_of_val(influences.as_slice()),
+            "skin influences",
+        );
+        influences_buffer.store(&influences);
+        let mut morph_deltas_buffer = storage_buffer(
+            std::mem::size_of_val(morph_deltas.as_slice()),
+            "skin morph deltas",
+        );
+        morph_deltas_buffer.store(&morph_deltas);
+        let mut indices_buffer =
+            storage_buffer(std::mem::size_of_val(indices.as_slice()), "skin indices");
+        indices_buffer.store(&indices);
+        // Written by the first pose, before anything traces the mesh
+        let normals_buffer = BufferResource::new(
+            (positions.len() * std::mem::size_of::<[f32; 4]>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            self.base.clone(),
+        );
+        normals_buffer.set_name("skin normals");
+
+        // Written by record with each pose. Storage buffers cannot be empty, so a skin without
+        // morph targets gets one unused weight.
+        let mut rest_pose = self.skin.rest_pose();
+        if rest_pose.morph_weights.is_empty() {
+            rest_pose.morph_weights.push(0.0);
+        }
+        let joint_matrices = MappedBuffer::new(
+            &rest_pose.joint_matrices,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            self.base.clone(),
+        );
+        joint_matrices.set_name("skin joint matrices");
+        let morph_weights = MappedBuffer::new(
+            &rest_pose.morph_weights,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            self.base.clone(),
+        );
+        morph_weights.set_name("skin morph weights");
+
+        let requirements = bottom_as
+            .memory_requirements(vk::AccelerationStructureMemoryRequirementsTypeNV::UPDATE_SCRATCH);
+        let blas_update_scratch = BufferResource::new(
+            requirements.size,
+            vk::BufferUsageFlags::RAY_TRACING_NV,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            self.base.clone(),
+        );
+        blas_update_scratch.set_name("scene mesh BLAS update scratch");
+
+        unsafe {
+            let descriptor_set_layout_bindings: Vec<vk::DescriptorSetLayoutBinding> = (0..7)
+                .map(|binding| vk::DescriptorSetLayoutBinding {
+                    binding,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::COMPUTE,
+                    ..Default::default()
+                })
+                .collect();
+            self.descriptor_set_layout = self
+                .base
+                .device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::builder()
+                        .bindings(&descriptor_set_layout_bindings)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create skinning descriptor set layout.");
+
+            let push_constant_ranges = [vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<SkinningPushConstants>() as u32,
+            }];
+            let layouts = [self.descriptor_set_layout];
+            self.pipeline_layout = self
+                .base
+                .device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder()
+                        .set_layouts(&layouts)
+                        .push_constant_ranges(&push_constant_ranges)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create skinning pipeline layout.");
+
+            let descriptor_sizes = [vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 7,
+            }];
+            self.descriptor_pool = self
+                .base
+                .device
+                .create_descriptor_pool(
+                    &vk::DescriptorPoolCreateInfo::builder()
+                        .pool_sizes(&descriptor_sizes)
+                        .max_sets(1)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create skinning descriptor pool.");
+
+            self.descriptor_set = self
+                .base
+                .device
+                .allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::builder()
+                        .descriptor_pool(self.descriptor_pool)
+                        .set_layouts(&layouts)
+                        .build(),
+                )
+                .expect("Failed to allocate skinning descriptor set.")[0];
+
+            // The vertices at binding 5 are written with the first pose, once the pool block is
+            // known
+            let buffer_infos: Vec<(u32, [vk::DescriptorBufferInfo; 1])> = [
+                (0, rest_positions_buffer.buffer),
+                (1, influences_buffer.buffer),
+                (2, morph_deltas_buffer.buffer),
+                (3, joint_matrices.buffer()),
+                (4, morph_weights.buffer()),
+                (6, normals_buffer.buffer),
+            ]
+            .iter()
+            .map(|&(binding, buffer)| {
+                (
+                    binding,
+                    [vk::DescriptorBufferInfo {
+                        buffer,
+                        offset: 0,
+                        range: vk::WHOLE_SIZE,
+                    }],
+                )
+            })
+            .collect();
+            let descriptor_writes: Vec<vk::WriteDescriptorSet> = buffer_infos
+                .iter()
+                .map(|(binding, buffer_info)| {
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(self.descriptor_set)
+                        .dst_binding(*binding)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .buffer_info(buffer_info)
+                        .build()
+                })
+                .collect();
+            self.base
+                .device
+                .update_descriptor_sets(&descriptor_writes, &[]);
+        }
+
+        self.pipeline = create_compute_pipeline(
+            &self.base,
+            self.pipeline_layout,
+            Path::new("shaders/compiled/skinning.glsl_deform_comp.spv"),
+            PushConstantLayout::of::<SkinningPushConstants>(),
+        );
+
+        self.positions = positions.to_vec();
+        self.rest_positions = Some(rest_positions_buffer);
+        self.influences = Some(influences_buffer);
+        self.morph_deltas = Some(morph_deltas_buffer);
+        self.joint_matrices = Some(joint_matrices);
+        self.morph_weights = Some(morph_weights);
+        self.normals = Some(normals_buffer);
+        self.indices = Some(indices_buffer);
+        self.blas_update_scratch = Some(blas_update_scratch);
+    }
+
+    pub(super) fn normals_buffer(&self) -> vk::Buffer {
+        self.normals.as_ref().unwrap().buffer
+    }
+
+    pub(super) fn indices_buffer(&self) -> vk::Buffer {
+        self.indices.as_ref().unwrap().buffer
+    }
+
+    // The object space bounds of the mesh in the pose
+    pub(super) fn set_pose(&mut self, pose: SkinPose) -> Result<Aabb, String> {
+        self.skin.validate_pose(&pose)?;
+        let positions = self.skin.deform(&self.positions, &pose);
+        self.pending_pose = Some(pose);
+        Ok(Aabb::from_points(positions.into_iter().map(Point3::from)))
+    }
+
+    // Whether the next frame deforms the mesh, which writes the pose buffers on the host and
+    // the vertices and BLAS the frame before reads
+    pub(super) fn has_pending_pose(&self) -> bool {
+        self.pending_pose.is_some()
+    }
+
+    // Deforms the mesh into the pool and updates the BLAS, which is left ready to be built into
+    // a TLAS or traced. Only records anything after set_pose.
+    pub(super) fn record(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        geometry_pool: &GeometryPool,
+        mesh: &MeshAllocation,
+        bottom_as: &AccelerationStructure,
+    ) {
+        let pose = match self.pending_pose.take() {
+            Some(pose) => pose,
+            None => return,
+        };
+        let joint_matrices = self.joint_matrices.as_mut().unwrap();
+        for (index, matrix) in pose.joint_matrices.iter().enumerate() {
+            joint_matrices.write(index, *matrix);
+        }
+        let morph_weights = self.morph_weights.as_mut().unwrap();
+        for (index, weight) in pose.morph_weights.iter().enumerate() {
+            morph_weights.write(index, *weight);
+        }
+
+        let vertex_buffer = geometry_pool.vertex_buffer(mesh);
+        if vertex_buffer != self.bound_vertex_buffer {
+            let buffer_info = [vk::DescriptorBufferInfo {
+                buffer: vertex_buffer,
+                offset: 0,
+                range: vk::WHOLE_SIZE,
+            }];
+            let descriptor_writes = [vk::WriteDescriptorSet::builder()
+                .dst_set(self.descriptor_set)
+                .dst_binding(5)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&buffer_info)
+                .build()];
+            unsafe {
+                self.base
+                    .device
+                    .update_descriptor_sets(&descriptor_writes, &[]);
+            }
+            self.bound_vertex_buffer = vertex_buffer;
+        }
+
+        let push_constants = SkinningPushConstants {
+            vertex_count: mesh.vertex_count,
+            morph_target_count: self.skin.morph_targets.len() as u32,
+            first_word: (mesh.vertex_offset / std::mem::size_of::<u32>() as vk::DeviceSize) as u32,
+            half_floats: (BLAS_VERTEX_FORMAT == VertexPositionFormat::Float16) as u32,
+        };
+
+        // The raster passes draw the vertices and the closest hit shaders read the normals later
+        // in the same frame
+        let deform_barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_NV
+                    | vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV
+                    | vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+            )
+            .dst_access_mask(
+                vk::AccessFlags2::SHADER_READ
+                    | vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_NV
+                    | vk::AccessFlags2::VERTEX_ATTRIBUTE_READ,
+            )
+            .build();
+        let update_barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_NV)
+            .src_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_NV)
+            .dst_stage_mask(
+                vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_NV
+                    | vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV,
+            )
+            .dst_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_NV)
+            .build();
+        let geometry = [geometry_pool.triangle_geometry(mesh)];
+
+        unsafe {
+            self.base.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            self.base.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            self.base.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const SkinningPushConstants as *const u8,
+                    std::mem::size_of::<SkinningPushConstants>(),
+                ),
+            );
+            self.base.device.cmd_dispatch(
+                command_buffer,
+                mesh.vertex_count.div_ceil(SKINNING_WORKGROUP_SIZE),
+                1,
+                1,
+            );
+
+            self.base
+                .synchronization
+                .pipeline_barrier(command_buffer, &[deform_barrier], &[]);
+            self.ray_tracing.cmd_build_acceleration_structure(
+                command_buffer,
+                &vk::AccelerationStructureInfoNV::builder()
+                    .ty(vk::AccelerationStructureTypeNV::BOTTOM_LEVEL)
+                    .flags(SKINNED_BLAS_FLAGS)
+                    .geometries(&geometry)
+                    .build(),
+                vk::Buffer::null(),
+                0,
+                true,
+                bottom_as.handle,
+                bottom_as.handle,
+                self.blas_update_scratch.as_ref().unwrap().buffer,
+                0,
+            );
+            self.base
+                .synchronization
+                .pipeline_barrier(command_buffer, &[update_barrier], &[]);
+        }
+    }
+
+    pub(super) fn release(&mut self) {
+        unsafe {
+            self.base
+                .shader_modules
+                .lock()
+                .unwrap()
+                .destroy_pipeline(self.pipeline);
+            self.base
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.base
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.base
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+
+        self.rest_positions = None;
+        self.influences = None;
+        self.morph_deltas = None;
+        self.joint_matrices = None;
+        self.morph_weights = None;
+        self.normals = None;
+        self.indices = None;
+        self.blas_update_scratch = None;
+    }
+}
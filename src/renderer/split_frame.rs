@@ -0,0 +1,250 @@
+use std::sync::Arc;
+
+use super::base::Renderer;
+use crate::utility;
+
+use ash::vk;
+
+// Split-frame rendering over the device group of `--split_frame`. Every device traces its band
+// of rows into its own instance of the HDR target, then the other devices copy their bands into a
+// buffer they all bind to device 0's memory, and the rest of the frame runs on device 0 alone.
+pub(super) struct SplitFrame {
+    base: Arc<Renderer>,
+    extent: vk::Extent2D,
+    bytes_per_pixel: u32,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+}
+
+impl SplitFrame {
+    // None when a device of the group cannot copy into the memory of device 0
+    pub(super) fn new(
+        base: Arc<Renderer>,
+        extent: vk::Extent2D,
+        bytes_per_pixel: u32,
+    ) -> Option<Self> {
+        unsafe {
+            let buffer = base
+                .device
+                .create_buffer(
+                    &vk::BufferCreateInfo::builder()
+                        .size((extent.width * extent.height * bytes_per_pixel) as vk::DeviceSize)
+                        .usage(
+                            vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST,
+                        )
+                        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                        .build(),
+                    None,
+                )
+                .unwrap();
+            let memory_req = base.device.get_buffer_memory_requirements(buffer);
+            let memory_index = utility::raytracing_aid::find_memorytype_index(
+                &memory_req,
+                &base.memory_properties,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .unwrap();
+            let heap_index = base.memory_properties.memory_types[memory_index as usize].heap_index;
+            if (1..base.device_count).any(|device| {
+                !utility::device_group::can_copy_to(&base.device, heap_index, device, 0)
+            }) {
+                tracing::warn!(
+                    "The devices cannot copy into each other's memory, rendering on one device"
+                );
+                base.device.destroy_buffer(buffer, None);
+                return None;
+            }
+
+            let memory = base
+                .device
+                .allocate_memory(
+                    &vk::MemoryAllocateInfo::builder()
+                        .allocation_size(memory_req.size)
+                        .memory_type_index(memory_index)
+                        .build(),
+                    None,
+                )
+                .unwrap();
+            // Every device's instance of the buffer is device 0's instance of the memory
+            let device_indices = vec![0; base.device_count as usize];
+            let mut device_group_info =
+                vk::BindBufferMemoryDeviceGroupInfo::builder().device_indices(&device_indices);
+            base.device
+                .bind_buffer_memory2(&[vk::BindBufferMemoryInfo::builder()
+                    .buffer(buffer)
+                    .memory(memory)
+                    .push_next(&mut device_group_info)
+                    .build()])
+                .unwrap();
+            tracing::info!("Split-frame rendering on {} devices", base.device_count);
+
+            Some(SplitFrame {
+                base,
+                extent,
+                bytes_per_pixel,
+                buffer,
+                memory,
+            })
+        }
+    }
+
+    pub(super) fn device_count(&self) -> u32 {
+        self.base.device_count
+    }
+
+    // The rows of the target device `device` traces
+    fn band(&self, device: u32) -> [u32; 4] {
+        utility::device_group::split_rows(
+            [0, 0, self.extent.width, self.extent.height],
+            self.device_count(),
+        )[device as usize]
+    }
+
+    fn band_copy(&self, device: u32) -> vk::BufferImageCopy {
+        let [x, y, width, height] = self.band(device);
+        vk::BufferImageCopy {
+            buffer_offset: ((y * self.extent.width + x) * self.bytes_per_pixel) as vk::DeviceSize,
+            buffer_row_length: self.extent.width,
+            buffer_image_height: self.extent.height,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D {
+                x: x as i32,
+                y: y as i32,
+                z: 0,
+            },
+            image_extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+        }
+    }
+
+    // Ends and submits `command_buffer` once every device traced its band into `target`, and
+    // returns the command buffer the frame goes on with, on device 0 with all of `target` traced.
+    // The caller holds the queue lock.
+    pub(super) fn gather(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        target: vk::Image,
+    ) -> vk::CommandBuffer {
+        let all_devices = (1 << self.device_count()) - 1;
+        unsafe {
+            let trace_barrier = vk::MemoryBarrier2::builder()
+                .src_stage_mask(
+                    vk::PipelineStageFlags2::RAY_TRACING_SHADER_NV
+                        | vk::PipelineStageFlags2::COMPUTE_SHADER
+                        | vk::PipelineStageFlags2::TRANSFER,
+                )
+                .src_access_mask(vk::AccessFlags2::SHADER_WRITE | vk::AccessFlags2::TRANSFER_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+                .build();
+            self.base
+                .synchronization
+                .pipeline_barrier(command_buffer, &[trace_barrier], &[]);
+            for device in 1..self.device_count() {
+                self.base
+                    .device
+                    .cmd_set_device_mask(command_buffer, 1 << device);
+                self.base.device.cmd_copy_image_to_buffer(
+                    command_buffer,
+                    target,
+                    vk::ImageLayout::GENERAL,
+                    self.buffer,
+                    &[self.band_copy(device)],
+                );
+            }
+            self.base
+                .device
+                .cmd_set_device_mask(command_buffer, all_devices);
+            self.base.device.end_command_buffer(command_buffer).unwrap();
+
+            // Barriers do not reach across devices, the submission boundary orders the copies
+            // with device 0 reading them
+            self.base
+                .synchronization
+                .submit(
+                    self.base.graphics_queue,
+                    &[command_buffer],
+                    vk::Fence::null(),
+                )
+                .expect("queue submit failed.");
+            self.base
+                .device
+                .queue_wait_idle(self.base.graphics_queue)
+                .expect("Failed to wait for the traced bands.");
+            self.base
+                .device
+                .free_command_buffers(self.base.command_pool, &[command_buffer]);
+
+            let command_buffer = self
+                .base
+                .device
+                .allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo::builder()
+                        .command_buffer_count(1)
+                        .command_pool(self.base.command_pool)
+                        .level(vk::CommandBufferLevel::PRIMARY)
+                        .build(),
+                )
+                .unwrap()[0];
+            let mut device_group_info =
+                vk::DeviceGroupCommandBufferBeginInfo::builder().device_mask(1);
+            self.base
+                .device
+                .begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo::builder()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                        .push_next(&mut device_group_info)
+                        .build(),
+                )
+                .unwrap();
+
+            let copies: Vec<vk::BufferImageCopy> = (1..self.device_count())
+                .map(|device| self.band_copy(device))
+                .collect();
+            self.base.device.cmd_copy_buffer_to_image(
+                command_buffer,
+                self.buffer,
+                target,
+                vk::ImageLayout::GENERAL,
+                &copies,
+            );
+            // For whatever reads the HDR target next
+            let gather_barrier = vk::MemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags2::COMPUTE_SHADER
+                        | vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags2::TRANSFER,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags2::SHADER_READ
+                        | vk::AccessFlags2::COLOR_ATTACHMENT_READ
+                        | vk::AccessFlags2::TRANSFER_READ,
+                )
+                .build();
+            self.base
+                .synchronization
+                .pipeline_barrier(command_buffer, &[gather_barrier], &[]);
+            command_buffer
+        }
+    }
+}
+
+impl Drop for SplitFrame {
+    fn drop(&mut self) {
+        unsafe {
+            self.base.device.destroy_buffer(self.buffer, None);
+            self.base.device.free_memory(self.memory, None);
+        }
+    }
+}
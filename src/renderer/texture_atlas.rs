@@ -0,0 +1,218 @@
+use std::sync::Arc;
+
+use super::{
+    base::Renderer,
+    resources::{BufferResource, ImageResource},
+};
+use crate::{
+    utility,
+    utility::{
+        constants::*,
+        sampler_cache::SamplerKey,
+        texture_atlas::{pack_atlas, AtlasLayout},
+    },
+};
+use image::RgbaImage;
+
+use ash::vk;
+
+// Pages of packed textures as the layers of one 2D array image. A texture is sampled on the
+// layer of its page, with AtlasRegion::uv_transform applied to its UVs. There are no mips, they
+// would filter across the regions.
+pub(super) struct TextureAtlas {
+    pub(super) image: ImageResource,
+    pub(super) layout: AtlasLayout,
+    // Owned by the sampler cache, fetched again when the texture quality changes
+    pub(super) sampler: vk::Sampler,
+}
+
+impl TextureAtlas {
+    // The pages have a single mip level
+    pub(super) const SAMPLER_KEY: SamplerKey = SamplerKey {
+        address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        mip_levels: 1,
+    };
+
+    // None without textures or when not even shrunk ones fit ATLAS_MEMORY_BUDGET
+    pub(super) fn new(base: Arc<Renderer>, textures: &[RgbaImage]) -> Option<Self> {
+        let limits = unsafe {
+            base.instance
+                .get_physical_device_properties(base.physical_device)
+        }
+        .limits;
+        let page_size = ATLAS_PAGE_SIZE.min(limits.max_image_dimension2_d);
+        let page_bytes = page_size as u64 * page_size as u64 * 4;
+        let max_pages =
+            ((ATLAS_MEMORY_BUDGET / page_bytes) as u32).min(limits.max_image_array_layers);
+
+        let sizes: Vec<(u32, u32)> = textures.iter().map(RgbaImage::dimensions).collect();
+        let layout = pack_atlas(&sizes, page_size, ATLAS_PADDING, max_pages)?;
+        if layout.page_count == 0 {
+            return None;
+        }
+
+        // Every texture with its padding, one after the other
+        let padded: Vec<RgbaImage> = textures
+            .iter()
+            .zip(&layout.regions)
+            .map(|(texture, region)| layout.padded_texture(texture, region))
+            .collect();
+        let staging_size = padded
+            .iter()
+            .map(|texture| texture.as_raw().len() as vk::DeviceSize)
+            .sum();
+        let mut staging_buffer = BufferResource::new(
+            staging_size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            base.clone(),
+        );
+        let mut copies = Vec::with_capacity(padded.len());
+        let mut offset = 0;
+        for (texture, region) in padded.iter().zip(&layout.regions) {
+            staging_buffer.store_at(offset, texture.as_raw());
+            copies.push(vk::BufferImageCopy {
+                buffer_offset: offset,
+                buffer_row_length: 0,
+                buffer_image_height: 0,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: region.page,
+                    layer_count: 1,
+                },
+                image_offset: vk::Offset3D {
+                    x: (region.x - layout.padding) as i32,
+                    y: (region.y - layout.padding) as i32,
+                    z: 0,
+                },
+                image_extent: vk::Extent3D {
+                    width: texture.width(),
+                    height: texture.height(),
+                    depth: 1,
+                },
+            });
+            offset += texture.as_raw().len() as vk::DeviceSize;
+        }
+
+        let mut image = ImageResource::new(base.clone());
+        image.create_image_array(
+            vk::Format::R8G8B8A8_SRGB,
+            vk::Extent2D {
+                width: page_size,
+                height: page_size,
+            },
+            layout.page_count,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: layout.page_count,
+        };
+        image.create_view(
+            vk::ImageViewType::TYPE_2D_ARRAY,
+            vk::Format::R8G8B8A8_SRGB,
+            range,
+        );
+        let sampler = base
+            .sampler_cache
+            .lock()
+            .unwrap()
+            .get(TextureAtlas::SAMPLER_KEY);
+
+        let barrier = |old_layout,
+                       new_layout,
+                       src_stage_mask,
+                       src_access_mask,
+                       dst_stage_mask,
+                       dst_access_mask| {
+            vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(src_stage_mask)
+                .src_access_mask(src_access_mask)
+                .dst_stage_mask(dst_stage_mask)
+                .dst_access_mask(dst_access_mask)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image.image)
+                .subresource_range(range)
+                .build()
+        };
+
+        let _queue = base.lock_queue();
+        let command_buffer =
+            utility::general::begin_single_time_command(&base.device, base.command_pool);
+        unsafe {
+            base.synchronization.pipeline_barrier(
+                command_buffer,
+                &[],
+                &[barrier(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::PipelineStageFlags2::NONE,
+                    vk::AccessFlags2::NONE,
+                    vk::PipelineStageFlags2::TRANSFER,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                )],
+            );
+            // Whatever no region covers stays transparent black
+            base.device.cmd_clear_color_image(
+                command_buffer,
+                image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue::default(),
+                &[range],
+            );
+            base.synchronization.pipeline_barrier(
+                command_buffer,
+                &[],
+                &[barrier(
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::PipelineStageFlags2::TRANSFER,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                    vk::PipelineStageFlags2::TRANSFER,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                )],
+            );
+            base.device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_buffer.buffer,
+                image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &copies,
+            );
+            base.synchronization.pipeline_barrier(
+                command_buffer,
+                &[],
+                &[barrier(
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::PipelineStageFlags2::TRANSFER,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                    vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR
+                        | vk::PipelineStageFlags2::COMPUTE_SHADER
+                        | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::SHADER_READ,
+                )],
+            );
+        }
+        utility::general::end_single_time_command(
+            &base.device,
+            base.command_pool,
+            base.graphics_queue,
+            command_buffer,
+        );
+
+        Some(TextureAtlas {
+            image,
+            layout,
+            sampler,
+        })
+    }
+}
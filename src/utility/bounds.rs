@@ -0,0 +1,103 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Transform};
+
+// Axis aligned bounding box, empty boxes have min above max so any point extends them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Aabb::empty()
+    }
+}
+
+impl Aabb {
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Point3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Point3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Aabb {
+        points.into_iter().fold(Aabb::empty(), |bounds, point| {
+            bounds.union(&Aabb {
+                min: point,
+                max: point,
+            })
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    // Radius of the bounding sphere around the center
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).magnitude() / 2.0
+    }
+
+    // Bit 0, 1 and 2 of `index` pick max over min for x, y and z
+    pub fn corner(&self, index: usize) -> Point3<f32> {
+        Point3::new(
+            if index & 1 == 0 {
+                self.min.x
+            } else {
+                self.max.x
+            },
+            if index & 2 == 0 {
+                self.min.y
+            } else {
+                self.max.y
+            },
+            if index & 4 == 0 {
+                self.min.z
+            } else {
+                self.max.z
+            },
+        )
+    }
+
+    // Axis aligned box around this box after `transform`, how a TLAS instance bounds its BLAS
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        Aabb::from_points((0..8).map(|index| transform.transform_point(self.corner(index))))
+    }
+}
+
+// Bounds of every mesh in a model and of the whole model, in model space
+#[derive(Clone, Debug, Default)]
+pub struct ModelBounds {
+    pub meshes: Vec<Aabb>,
+    pub scene: Aabb,
+}
+
+impl ModelBounds {
+    pub fn add_mesh(&mut self, bounds: Aabb) {
+        self.scene = self.scene.union(&bounds);
+        self.meshes.push(bounds);
+    }
+}
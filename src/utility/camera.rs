@@ -0,0 +1,392 @@
+use cgmath::{
+    Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, Quaternion, Rotation, Rotation3, Vector3,
+};
+
+use super::{
+    bounds::Aabb,
+    constants::{
+        CAMERA_LAYER_MASKS, DEFAULT_FAR_PLANE, DEFAULT_NEAR_PLANE, DEPTH_RANGE_MARGIN,
+        DEPTH_RANGE_MIN_NEAR_RATIO,
+    },
+    structures::{
+        CameraLayerMasks, CameraPushConstants, DepthRangePushConstants, RayMaskPushConstants,
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective {
+        fov_y: Deg<f32>,
+    },
+    // Height of the view volume in world units, the width follows the aspect ratio
+    Orthographic {
+        height: f32,
+    },
+    // Asymmetric frustum given by the view window at unit distance from the camera
+    OffAxis {
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: Point3<f32>,
+    pub target: Point3<f32>,
+    pub up: Vector3<f32>,
+    pub projection: Projection,
+    pub layers: CameraLayerMasks,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            position: Point3::new(0.0, 0.0, -2.0),
+            target: Point3::new(0.0, 0.0, 0.0),
+            up: Vector3::new(0.0, 1.0, 0.0),
+            projection: Projection::Perspective { fov_y: Deg(90.0) },
+            layers: CAMERA_LAYER_MASKS,
+        }
+    }
+}
+
+impl Camera {
+    /// View window as [left, right, bottom, top]
+    pub fn window(&self, aspect_ratio: f32) -> [f32; 4] {
+        match self.projection {
+            Projection::Perspective { fov_y } => {
+                let half_height = (fov_y / 2.0).0.to_radians().tan();
+                let half_width = half_height * aspect_ratio;
+                [-half_width, half_width, -half_height, half_height]
+            }
+            Projection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * aspect_ratio;
+                [-half_width, half_width, -half_height, half_height]
+            }
+            Projection::OffAxis {
+                left,
+                right,
+                bottom,
+                top,
+            } => [left, right, bottom, top],
+        }
+    }
+
+    /// Orthonormal (forward, right, up) view basis
+    pub fn basis(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let forward = (self.target - self.position).normalize();
+        let right = self.up.cross(forward).normalize();
+        let up = forward.cross(right);
+        (forward, right, up)
+    }
+
+    /// Looks at the center of `bounds` from far enough back along the current view direction
+    /// for its bounding sphere to fit the narrowest side of the view
+    pub fn frame_scene(&mut self, bounds: &Aabb) {
+        if bounds.is_empty() {
+            return;
+        }
+
+        let (forward, _, _) = self.basis();
+        let center = bounds.center();
+        let radius = bounds.radius().max(f32::EPSILON);
+        let distance = match self.projection {
+            // Any distance works, far enough to keep the whole scene in front of the camera
+            Projection::Orthographic { .. } => {
+                self.projection = Projection::Orthographic {
+                    height: 2.0 * radius,
+                };
+                2.0 * radius
+            }
+            // The window lies at unit distance, so its half extent is the tangent of the
+            // half angle the sphere has to fit in
+            _ => {
+                let [left, right, bottom, top] = self.window(1.0);
+                let half_extent = (-left).min(right).min(-bottom).min(top);
+                if half_extent <= 0.0 {
+                    return;
+                }
+                radius / half_extent.atan().sin()
+            }
+        };
+
+        self.target = center;
+        self.position = center - forward * distance;
+    }
+
+    /// Moves by `offset` along the (right, up, forward) view basis, then turns `yaw` to the right
+    /// and `pitch` up. The target moves along at the same distance, pitch stops short of `up`.
+    pub fn fly(&mut self, offset: Vector3<f32>, yaw: Deg<f32>, pitch: Deg<f32>) {
+        let (forward, right, up) = self.basis();
+        let distance = (self.target - self.position).magnitude();
+        self.position += right * offset.x + up * offset.y + forward * offset.z;
+
+        let turned = Quaternion::from_axis_angle(self.up.normalize(), yaw).rotate_vector(forward);
+        let pitched = Quaternion::from_axis_angle(-right, pitch).rotate_vector(turned);
+        let forward = if pitched.dot(self.up.normalize()).abs() < 0.99 {
+            pitched
+        } else {
+            turned
+        };
+        self.target = self.position + forward * distance;
+    }
+
+    /// Camera that renders one tile of a `columns` x `rows` grid over this camera's view,
+    /// counted from the top left. Each tile is expected to keep the full image's pixel aspect.
+    pub fn tile(
+        &self,
+        aspect_ratio: f32,
+        columns: u32,
+        rows: u32,
+        column: u32,
+        row: u32,
+    ) -> Camera {
+        self.sub_view(
+            aspect_ratio,
+            [
+                column as f32 / columns as f32,
+                row as f32 / rows as f32,
+                (column + 1) as f32 / columns as f32,
+                (row + 1) as f32 / rows as f32,
+            ],
+        )
+    }
+
+    /// Camera that renders the [left, top, right, bottom] part of this camera's view, given as
+    /// fractions of it counted from the top left
+    pub fn sub_view(
+        &self,
+        aspect_ratio: f32,
+        [left_x, top_y, right_x, bottom_y]: [f32; 4],
+    ) -> Camera {
+        let [left, right, bottom, top] = self.window(aspect_ratio);
+        let width = right - left;
+        let height = top - bottom;
+        let sub_left = left + width * left_x;
+        let sub_top = top - height * top_y;
+        let sub_width = width * (right_x - left_x);
+        let sub_height = height * (bottom_y - top_y);
+
+        match self.projection {
+            // The orthographic window is always centered, so the camera itself moves instead
+            Projection::Orthographic { .. } => {
+                let (_, right_axis, up_axis) = self.basis();
+                let shift = right_axis * (sub_left + sub_width / 2.0)
+                    + up_axis * (sub_top - sub_height / 2.0);
+                Camera {
+                    position: self.position + shift,
+                    target: self.target + shift,
+                    up: self.up,
+                    projection: Projection::Orthographic { height: sub_height },
+                    layers: self.layers,
+                }
+            }
+            _ => Camera {
+                projection: Projection::OffAxis {
+                    left: sub_left,
+                    right: sub_left + sub_width,
+                    bottom: sub_top - sub_height,
+                    top: sub_top,
+                },
+                ..*self
+            },
+        }
+    }
+
+    /// Raster transform covering the same view as the rays from `ray_gen_constants`, with
+    /// depth mapped to [0, 1] between the near and far planes and y pointing down
+    pub fn clip_from_world(&self, aspect_ratio: f32, near: f32, far: f32) -> Matrix4<f32> {
+        let (forward, right, up) = self.basis();
+        let origin = self.position.to_vec();
+        let view_from_world = Matrix4::new(
+            right.x,
+            up.x,
+            forward.x,
+            0.0,
+            right.y,
+            up.y,
+            forward.y,
+            0.0,
+            right.z,
+            up.z,
+            forward.z,
+            0.0,
+            -right.dot(origin),
+            -up.dot(origin),
+            -forward.dot(origin),
+            1.0,
+        );
+
+        // Image rows go down while the window's up axis goes up
+        let [left, right, bottom, top] = self.window(aspect_ratio);
+        let clip_from_view = match self.projection {
+            Projection::Orthographic { .. } => Matrix4::new(
+                2.0 / (right - left),
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                -2.0 / (top - bottom),
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                1.0 / (far - near),
+                0.0,
+                -(right + left) / (right - left),
+                (top + bottom) / (top - bottom),
+                -near / (far - near),
+                1.0,
+            ),
+            // The window lies at unit distance, so x and y are divided by the forward distance
+            _ => Matrix4::new(
+                2.0 / (right - left),
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                -2.0 / (top - bottom),
+                0.0,
+                0.0,
+                -(right + left) / (right - left),
+                (top + bottom) / (top - bottom),
+                far / (far - near),
+                1.0,
+                0.0,
+                0.0,
+                -far * near / (far - near),
+                0.0,
+            ),
+        };
+
+        clip_from_view * view_from_world
+    }
+
+    pub fn ray_gen_constants(&self, aspect_ratio: f32) -> CameraPushConstants {
+        let (forward, right, up) = self.basis();
+        let orthographic = match self.projection {
+            Projection::Orthographic { .. } => 1.0,
+            _ => 0.0,
+        };
+
+        CameraPushConstants {
+            origin: [
+                self.position.x,
+                self.position.y,
+                self.position.z,
+                orthographic,
+            ],
+            right: [right.x, right.y, right.z, 0.0],
+            up: [up.x, up.y, up.z, 0.0],
+            forward: [forward.x, forward.y, forward.z, 0.0],
+            window: self.window(aspect_ratio),
+        }
+    }
+
+    pub fn ray_mask_constants(&self) -> RayMaskPushConstants {
+        RayMaskPushConstants {
+            primary: self.layers.primary as u32,
+            secondary: self.layers.secondary as u32,
+        }
+    }
+}
+
+/// Near and far planes along the camera's forward axis, for the raster projections and the
+/// t_min and t_max of primary rays. Planes left unset are fitted to the scene.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DepthRange {
+    pub near: Option<f32>,
+    pub far: Option<f32>,
+}
+
+impl DepthRange {
+    /// The fitted planes enclose the bounding sphere of `bounds` with DEPTH_RANGE_MARGIN to
+    /// spare. Inside the sphere the near plane is kept DEPTH_RANGE_MIN_NEAR_RATIO of the far
+    /// plane away, for the depth precision.
+    pub fn resolve(&self, camera: &Camera, bounds: &Aabb) -> DepthRangePushConstants {
+        let (fitted_near, fitted_far) = if bounds.is_empty() {
+            (DEFAULT_NEAR_PLANE, DEFAULT_FAR_PLANE)
+        } else {
+            let (forward, _, _) = camera.basis();
+            let distance = (bounds.center() - camera.position).dot(forward);
+            let radius = bounds.radius() * (1.0 + DEPTH_RANGE_MARGIN);
+            let far = (distance + radius).max(DEFAULT_NEAR_PLANE);
+            (
+                (distance - radius).max(far * DEPTH_RANGE_MIN_NEAR_RATIO),
+                far,
+            )
+        };
+        let near = self.near.unwrap_or(fitted_near);
+        DepthRangePushConstants {
+            near,
+            // An override may leave the fitted far plane in front of the near one
+            far: self.far.unwrap_or(fitted_far.max(near * 2.0)),
+        }
+    }
+}
+
+/// Part of the render target traced with its own camera
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+    /// x, y, width, height as fractions of the render target, from the top left
+    pub rect: [f32; 4],
+    pub camera: Camera,
+}
+
+impl Viewport {
+    /// The rect in pixels of a `width` x `height` target, as x, y, width, height. Neighbouring
+    /// viewports share their edges, so the target is covered without gaps.
+    pub fn pixel_rect(&self, width: u32, height: u32) -> [u32; 4] {
+        let [x, y, rect_width, rect_height] = self.rect;
+        let to_pixel = |fraction: f32, size: u32| (fraction * size as f32).round() as u32;
+        let left = to_pixel(x, width).min(width);
+        let top = to_pixel(y, height).min(height);
+        let right = to_pixel(x + rect_width, width).min(width);
+        let bottom = to_pixel(y + rect_height, height).min(height);
+        [
+            left,
+            top,
+            right.saturating_sub(left),
+            bottom.saturating_sub(top),
+        ]
+    }
+}
+
+/// Editor style quad view: `camera` at the top left and orthographic top, front and side views
+/// of `bounds` in the other quarters
+pub fn quad_view(camera: &Camera, bounds: &Aabb) -> Vec<Viewport> {
+    let center = bounds.center();
+    let orthographic = |direction: Vector3<f32>, up: Vector3<f32>| {
+        let mut view = Camera {
+            position: center - direction,
+            target: center,
+            up,
+            projection: Projection::Orthographic { height: 1.0 },
+            layers: camera.layers,
+        };
+        view.frame_scene(bounds);
+        view
+    };
+
+    vec![
+        Viewport {
+            rect: [0.0, 0.0, 0.5, 0.5],
+            camera: *camera,
+        },
+        Viewport {
+            rect: [0.5, 0.0, 0.5, 0.5],
+            camera: orthographic(Vector3::new(0.0, -1.0, 0.0), Vector3::new(0.0, 0.0, 1.0)),
+        },
+        Viewport {
+            rect: [0.0, 0.5, 0.5, 0.5],
+            camera: orthographic(Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 1.0, 0.0)),
+        },
+        Viewport {
+            rect: [0.5, 0.5, 0.5, 0.5],
+            camera: orthographic(Vector3::new(-1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)),
+        },
+    ]
+}
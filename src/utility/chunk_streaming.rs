@@ -0,0 +1,219 @@
+use crate::utility::structures::GeometryPoolStats;
+
+use cgmath::Point3;
+
+use std::fmt;
+use std::time::Duration;
+
+// What `ash_rt stream` generates and flies through
+#[derive(Clone, Copy, Debug)]
+pub struct StreamParams {
+    // The city is a square of chunks centered on the origin
+    pub chunks_per_side: u32,
+    pub frames: u32,
+    pub instances_per_chunk: u32,
+    pub triangles_per_chunk: u32,
+    // Bytes of geometry and BLASes resident at once
+    pub budget: u64,
+    pub seed: u64,
+}
+
+// What `ash_rt stream` reports once the camera left the city
+#[derive(Clone, Debug)]
+pub struct StreamReport {
+    pub loads: u32,
+    pub unloads: u32,
+    pub resident_chunks: usize,
+    pub resident_size: u64,
+    pub peak_resident_size: u64,
+    pub peak_instance_count: u32,
+    pub time: Duration,
+    pub longest_frame: Duration,
+    pub geometry_pool: GeometryPoolStats,
+}
+
+impl fmt::Display for StreamReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Streamed city:")?;
+        writeln!(
+            f,
+            " chunks loaded: {}, unloaded: {}",
+            self.loads, self.unloads
+        )?;
+        writeln!(
+            f,
+            " resident at the end: {} chunks, {} bytes",
+            self.resident_chunks, self.resident_size
+        )?;
+        writeln!(f, " resident peak: {} bytes", self.peak_resident_size)?;
+        writeln!(f, " TLAS instances peak: {}", self.peak_instance_count)?;
+        writeln!(
+            f,
+            " time: {:?}, the longest frame took {:?}",
+            self.time, self.longest_frame
+        )?;
+        writeln!(
+            f,
+            " geometry pool: {} blocks, {} bytes capacity, {} bytes live",
+            self.geometry_pool.block_count, self.geometry_pool.capacity, self.geometry_pool.live
+        )
+    }
+}
+
+// Cell of the world space grid on the ground plane, everything whose origin lies in it is
+// loaded and unloaded together
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChunkKey {
+    pub x: i32,
+    pub z: i32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkGrid {
+    pub cell_size: f32,
+}
+
+impl ChunkGrid {
+    pub fn key_of(&self, position: Point3<f32>) -> ChunkKey {
+        ChunkKey {
+            x: (position.x / self.cell_size).floor() as i32,
+            z: (position.z / self.cell_size).floor() as i32,
+        }
+    }
+
+    // On the ground plane
+    pub fn center(&self, key: ChunkKey) -> Point3<f32> {
+        Point3::new(
+            (key.x as f32 + 0.5) * self.cell_size,
+            0.0,
+            (key.z as f32 + 0.5) * self.cell_size,
+        )
+    }
+
+    // Horizontal distance from the position to the nearest point of the cell, 0 inside it
+    pub fn distance(&self, key: ChunkKey, position: Point3<f32>) -> f32 {
+        let center = self.center(key);
+        let half_size = 0.5 * self.cell_size;
+        let dx = ((position.x - center.x).abs() - half_size).max(0.0);
+        let dz = ((position.z - center.z).abs() - half_size).max(0.0);
+        (dx * dx + dz * dz).sqrt()
+    }
+
+    pub fn keys_within(&self, position: Point3<f32>, radius: f32) -> Vec<ChunkKey> {
+        let min = self.key_of(Point3::new(position.x - radius, 0.0, position.z - radius));
+        let max = self.key_of(Point3::new(position.x + radius, 0.0, position.z + radius));
+        (min.z..=max.z)
+            .flat_map(|z| (min.x..=max.x).map(move |x| ChunkKey { x, z }))
+            .filter(|key| self.distance(*key, position) <= radius)
+            .collect()
+    }
+}
+
+// What the caller has to load and unload this frame, loads nearest first
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamingPlan {
+    pub load: Vec<ChunkKey>,
+    pub unload: Vec<ChunkKey>,
+}
+
+// Decides which chunks are resident around a position. Chunks within the load radius are loaded
+// nearest first and those beyond the unload radius unloaded, in between they stay as they are so
+// a camera moving along a cell border does not keep swapping them. Over the budget the farthest
+// chunks are evicted, but only for nearer ones.
+pub struct ChunkStreamer {
+    pub grid: ChunkGrid,
+    pub load_radius: f32,
+    pub unload_radius: f32,
+    pub budget: u64,
+    // With their sizes in bytes
+    resident: Vec<(ChunkKey, u64)>,
+}
+
+impl ChunkStreamer {
+    pub fn new(grid: ChunkGrid, load_radius: f32, unload_radius: f32, budget: u64) -> Self {
+        ChunkStreamer {
+            grid,
+            load_radius,
+            unload_radius: unload_radius.max(load_radius),
+            budget,
+            resident: Vec::new(),
+        }
+    }
+
+    pub fn resident(&self) -> impl Iterator<Item = ChunkKey> + '_ {
+        self.resident.iter().map(|(key, _)| *key)
+    }
+
+    pub fn resident_size(&self) -> u64 {
+        self.resident.iter().map(|(_, size)| size).sum()
+    }
+
+    // Replaces the estimate the chunk was loaded with once its size is known. A chunk that turns
+    // out larger can push the resident ones over the budget until the next update.
+    pub fn set_size(&mut self, key: ChunkKey, size: u64) {
+        if let Some((_, resident_size)) = self.resident.iter_mut().find(|(k, _)| *k == key) {
+            *resident_size = size;
+        }
+    }
+
+    // `size` is what a chunk costs once loaded, None where there is nothing to load. At most
+    // `max_loads` chunks are loaded per update, the ones in the plan count as resident right away.
+    pub fn update(
+        &mut self,
+        position: Point3<f32>,
+        max_loads: usize,
+        size: impl Fn(ChunkKey) -> Option<u64>,
+    ) -> StreamingPlan {
+        let grid = self.grid;
+        let mut plan = StreamingPlan::default();
+
+        let unload_radius = self.unload_radius;
+        self.resident.retain(|(key, _)| {
+            let keep = grid.distance(*key, position) <= unload_radius;
+            if !keep {
+                plan.unload.push(*key);
+            }
+            keep
+        });
+        // Whatever a larger than estimated chunk pushed over the budget
+        while self.resident_size() > self.budget {
+            match self.evict_farther_than(position, 0.0) {
+                Some(key) => plan.unload.push(key),
+                None => break,
+            }
+        }
+
+        let mut candidates: Vec<(ChunkKey, f32, u64)> = grid
+            .keys_within(position, self.load_radius)
+            .into_iter()
+            .filter(|key| !self.resident.iter().any(|(resident, _)| resident == key))
+            .filter_map(|key| size(key).map(|size| (key, grid.distance(key, position), size)))
+            .collect();
+        candidates.sort_by(|(_, a, _), (_, b, _)| a.total_cmp(b));
+
+        'candidates: for (key, distance, size) in candidates.into_iter().take(max_loads) {
+            while self.resident_size() + size > self.budget {
+                match self.evict_farther_than(position, distance) {
+                    Some(evicted) => plan.unload.push(evicted),
+                    // The rest are farther still
+                    None => break 'candidates,
+                }
+            }
+            self.resident.push((key, size));
+            plan.load.push(key);
+        }
+        plan
+    }
+
+    fn evict_farther_than(&mut self, position: Point3<f32>, distance: f32) -> Option<ChunkKey> {
+        let grid = self.grid;
+        let (index, _) = self
+            .resident
+            .iter()
+            .enumerate()
+            .map(|(index, (key, _))| (index, grid.distance(*key, position)))
+            .filter(|(_, resident_distance)| *resident_distance > distance)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        Some(self.resident.remove(index).0)
+    }
+}
@@ -0,0 +1,128 @@
+use exr::prelude::f16;
+use std::path::Path;
+
+// 3D color lookup table of the .cube format Resolve and most grading tools export, applied by
+// the tonemap pass to the tonemapped color as it is written out
+#[derive(Clone, Debug, PartialEq)]
+pub struct CubeLut {
+    pub title: Option<String>,
+    // Entries along each axis
+    pub size: u32,
+    // Inputs that map to the first and the last entry, per channel
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    // Red changing fastest and blue slowest, the order of the file and of a 3D image's texels
+    pub entries: Vec<[f32; 3]>,
+}
+
+impl CubeLut {
+    // Maps every color to itself, two entries per axis are exact under trilinear filtering
+    pub fn identity() -> CubeLut {
+        let entries = (0..8)
+            .map(|index| {
+                [
+                    (index & 1) as f32,
+                    ((index >> 1) & 1) as f32,
+                    ((index >> 2) & 1) as f32,
+                ]
+            })
+            .collect();
+        CubeLut {
+            title: None,
+            size: 2,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            entries,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<CubeLut, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {:?}: {}", path, err))?;
+        CubeLut::parse(&text).map_err(|message| format!("{:?}: {}", path, message))
+    }
+
+    pub fn parse(text: &str) -> Result<CubeLut, String> {
+        let mut lut = CubeLut {
+            size: 0,
+            entries: Vec::new(),
+            ..CubeLut::identity()
+        };
+        let parse_floats = |values: &[&str], line: usize| -> Result<Vec<f32>, String> {
+            values
+                .iter()
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid number {} on line {}", value, line))
+                })
+                .collect()
+        };
+        let parse_triple = |values: &[&str], line: usize| -> Result<[f32; 3], String> {
+            match parse_floats(values, line)?[..] {
+                [r, g, b] => Ok([r, g, b]),
+                _ => Err(format!("Expected three numbers on line {}", line)),
+            }
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.split('#').next().unwrap().trim();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                [] => {}
+                ["TITLE", ..] => {
+                    lut.title = Some(line["TITLE".len()..].trim().trim_matches('"').to_string())
+                }
+                ["LUT_3D_SIZE", size] => {
+                    lut.size = size
+                        .parse()
+                        .ok()
+                        .filter(|size| (2..=256).contains(size))
+                        .ok_or_else(|| format!("Invalid LUT_3D_SIZE {}", size))?
+                }
+                ["LUT_1D_SIZE", ..] => return Err("1D LUTs are not supported".to_string()),
+                ["DOMAIN_MIN", ..] => lut.domain_min = parse_triple(&words[1..], line_number)?,
+                ["DOMAIN_MAX", ..] => lut.domain_max = parse_triple(&words[1..], line_number)?,
+                // The older form of the domain, the same for every channel
+                ["LUT_3D_INPUT_RANGE", ..] => match parse_floats(&words[1..], line_number)?[..] {
+                    [min, max] => {
+                        lut.domain_min = [min; 3];
+                        lut.domain_max = [max; 3];
+                    }
+                    _ => return Err(format!("Expected two numbers on line {}", line_number)),
+                },
+                [keyword, ..] if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    tracing::warn!("Ignoring {} on line {} of the LUT", keyword, line_number)
+                }
+                _ => lut.entries.push(parse_triple(&words, line_number)?),
+            }
+        }
+
+        if lut.size == 0 {
+            return Err("No LUT_3D_SIZE".to_string());
+        }
+        let expected = (lut.size as usize).pow(3);
+        if lut.entries.len() != expected {
+            return Err(format!(
+                "Expected {} entries for LUT_3D_SIZE {}, found {}",
+                expected,
+                lut.size,
+                lut.entries.len()
+            ));
+        }
+        if (0..3).any(|channel| lut.domain_max[channel] <= lut.domain_min[channel]) {
+            return Err("DOMAIN_MAX has to be above DOMAIN_MIN".to_string());
+        }
+        Ok(lut)
+    }
+
+    // RGBA half floats in the order of the entries, alpha 1, for a R16G16B16A16_SFLOAT image
+    pub fn to_rgba_f16_bytes(&self) -> Vec<u8> {
+        self.entries
+            .iter()
+            .flat_map(|&[r, g, b]| [r, g, b, 1.0])
+            .flat_map(|value| f16::from_f32(value).to_ne_bytes())
+            .collect()
+    }
+}
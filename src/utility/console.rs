@@ -0,0 +1,133 @@
+// Runs a console command on the app, returning what to print
+pub type ConsoleHandler<A> = fn(&mut A, &[&str]) -> Result<String, String>;
+
+struct ConsoleCommand<A> {
+    name: &'static str,
+    usage: &'static str,
+    handler: ConsoleHandler<A>,
+}
+
+// Commands the console of a window loop can run, by name. `help` is always there and lists them.
+pub struct CommandRegistry<A> {
+    commands: Vec<ConsoleCommand<A>>,
+}
+
+impl<A> Default for CommandRegistry<A> {
+    fn default() -> Self {
+        CommandRegistry {
+            commands: Vec::new(),
+        }
+    }
+}
+
+impl<A> CommandRegistry<A> {
+    // `usage` shows the arguments after the name, e.g. `<path>` or `[name]`
+    pub fn register(
+        mut self,
+        name: &'static str,
+        usage: &'static str,
+        handler: ConsoleHandler<A>,
+    ) -> Self {
+        assert!(
+            name != "help" && self.commands.iter().all(|command| command.name != name),
+            "Console command {} is registered twice.",
+            name
+        );
+        self.commands.push(ConsoleCommand {
+            name,
+            usage,
+            handler,
+        });
+        self
+    }
+
+    pub fn help(&self) -> String {
+        let mut help = String::from("help");
+        for command in &self.commands {
+            help.push_str(&format!("\n{} {}", command.name, command.usage));
+        }
+        help
+    }
+
+    // Splits the line at whitespace, the first word picks the command
+    pub fn execute(&self, app: &mut A, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (*name, args),
+            None => return Ok(String::new()),
+        };
+        if name == "help" {
+            return Ok(self.help());
+        }
+        let command = self
+            .commands
+            .iter()
+            .find(|command| command.name == name)
+            .ok_or_else(|| format!("Unknown command {}, see help", name))?;
+        (command.handler)(app, args)
+            .map_err(|message| format!("{}\nUsage: {} {}", message, command.name, command.usage))
+    }
+}
+
+// The line being typed into the drop-down console and the lines run before it. Without a text
+// overlay the window title shows the line, output goes to the log.
+#[derive(Default)]
+pub struct Console {
+    pub is_open: bool,
+    pub input: String,
+    history: Vec<String>,
+    // Position in history while stepping through it, None at the line being typed
+    history_position: Option<usize>,
+}
+
+impl Console {
+    pub fn toggle(&mut self) {
+        self.is_open = !self.is_open;
+    }
+
+    // Control characters and the toggle key are not typed
+    pub fn type_char(&mut self, character: char) {
+        if !character.is_control() && character != '`' {
+            self.input.push(character);
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        self.input.pop();
+    }
+
+    // Takes the typed line to run it, empty lines are skipped
+    pub fn submit(&mut self) -> Option<String> {
+        self.history_position = None;
+        let line = std::mem::take(&mut self.input);
+        if line.trim().is_empty() {
+            return None;
+        }
+        if self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        Some(line)
+    }
+
+    // Steps back through the history for `older`, forward otherwise
+    pub fn recall(&mut self, older: bool) {
+        let position = match (self.history_position, older) {
+            (None, true) => self.history.len().checked_sub(1),
+            (None, false) => None,
+            (Some(position), true) => Some(position.saturating_sub(1)),
+            (Some(position), false) => {
+                Some(position + 1).filter(|&position| position < self.history.len())
+            }
+        };
+        self.history_position = position;
+        self.input = position.map_or_else(String::new, |position| self.history[position].clone());
+    }
+
+    pub fn title(&self, window_title: &str) -> String {
+        if self.is_open {
+            format!("{} > {}_", window_title, self.input)
+        } else {
+            window_title.to_string()
+        }
+    }
+}
@@ -1,9 +1,21 @@
+use std::ops::RangeInclusive;
 use std::os::raw::c_char;
+use std::time::Duration;
 
 use crate::utility::debug::ValidationInfo;
+use crate::utility::exr_output::ExrPrecision;
+use crate::utility::hdr_format::HdrFormat;
+#[cfg(feature = "winit")]
+use crate::utility::input::Action;
+#[cfg(feature = "gamepad")]
+use crate::utility::input::Binding;
+use crate::utility::sampler_cache::TextureQuality;
 use crate::utility::structures::*;
+use crate::utility::vertex_format::VertexPositionFormat;
 
 use ash::vk;
+use cgmath::Deg;
+#[cfg(feature = "winit")]
 use winit::event::VirtualKeyCode;
 
 // Constants
@@ -11,12 +23,92 @@ pub const WINDOW_TITLE: &'static str = "测试窗口";
 // pub const TEXTURE_PATH: &'static str = "textures/texture.jpg";
 pub const MODEL_PATH: &'static str = "assets/viking_room.obj";
 pub const TEXTURE_PATH: &'static str = "assets/viking_room.png";
+// The model is framed from its (1, 1, 1) diagonal, at whatever distance its bounds need
+pub const MODEL_CAMERA_FOV_Y: Deg<f32> = Deg(45.0);
 pub const WINDOW_WIDTH: u32 = 800;
 pub const WINDOW_HEIGHT: u32 = 600;
-pub const WINDOW_KEYCODE_EXIT: VirtualKeyCode = VirtualKeyCode::Escape;
+// Bindings of the input map when the input file does not set them. The arrow keys look around
+// unless the drop-down console is open, then they recall its history.
+#[cfg(feature = "winit")]
+pub const DEFAULT_KEY_BINDINGS: [(Action, VirtualKeyCode); 12] = [
+    (Action::Exit, VirtualKeyCode::Escape),
+    (Action::ToggleConsole, VirtualKeyCode::Grave),
+    (Action::MoveForward, VirtualKeyCode::W),
+    (Action::MoveBackward, VirtualKeyCode::S),
+    (Action::MoveLeft, VirtualKeyCode::A),
+    (Action::MoveRight, VirtualKeyCode::D),
+    (Action::MoveUp, VirtualKeyCode::E),
+    (Action::MoveDown, VirtualKeyCode::Q),
+    (Action::LookLeft, VirtualKeyCode::Left),
+    (Action::LookRight, VirtualKeyCode::Right),
+    (Action::LookUp, VirtualKeyCode::Up),
+    (Action::LookDown, VirtualKeyCode::Down),
+];
+#[cfg(feature = "gamepad")]
+pub const DEFAULT_GAMEPAD_BINDINGS: [(Action, Binding); 11] = [
+    (
+        Action::ToggleConsole,
+        Binding::Button(gilrs::Button::Select),
+    ),
+    (
+        Action::MoveForward,
+        Binding::Axis(gilrs::Axis::LeftStickY, true),
+    ),
+    (
+        Action::MoveBackward,
+        Binding::Axis(gilrs::Axis::LeftStickY, false),
+    ),
+    (
+        Action::MoveLeft,
+        Binding::Axis(gilrs::Axis::LeftStickX, false),
+    ),
+    (
+        Action::MoveRight,
+        Binding::Axis(gilrs::Axis::LeftStickX, true),
+    ),
+    (
+        Action::MoveUp,
+        Binding::Button(gilrs::Button::RightTrigger2),
+    ),
+    (
+        Action::MoveDown,
+        Binding::Button(gilrs::Button::LeftTrigger2),
+    ),
+    (
+        Action::LookLeft,
+        Binding::Axis(gilrs::Axis::RightStickX, false),
+    ),
+    (
+        Action::LookRight,
+        Binding::Axis(gilrs::Axis::RightStickX, true),
+    ),
+    (
+        Action::LookUp,
+        Binding::Axis(gilrs::Axis::RightStickY, true),
+    ),
+    (
+        Action::LookDown,
+        Binding::Axis(gilrs::Axis::RightStickY, false),
+    ),
+];
+// Stick deflection below which a stick counts as centered
+pub const GAMEPAD_DEAD_ZONE: f32 = 0.15;
+// Next to USER_SETTINGS_FILE
+pub const INPUT_MAP_FILE: &str = "input.txt";
+// Speed of the window's camera in scene radii per second at full deflection
+pub const CAMERA_MOVE_SPEED: f32 = 0.5;
+pub const CAMERA_LOOK_SPEED: Deg<f32> = Deg(90.0);
+// Simulation updates of the window loop run at this fixed rate whatever the frame rate, frames
+// interpolate between the last two updates
+pub const SIMULATION_TIMESTEP: Duration = Duration::from_micros(1_000_000 / 60);
+pub const MAX_SIMULATION_STEPS_PER_FRAME: u32 = 8;
+// Turntable speed of the rasterized model
+pub const MODEL_ROTATION_SPEED: Deg<f32> = Deg(90.0);
 
+// Defaults to on in debug builds only, see ValidationInfo::resolve
 pub const VALIDATION: ValidationInfo = ValidationInfo {
-    is_enable: true,
+    is_enable: cfg!(debug_assertions),
+    debug_printf: false,
     required_validation_layers: ["VK_LAYER_KHRONOS_validation"],
 };
 pub const APPLICATION_VERSION: u32 = vk::make_api_version(0, 1, 0, 0);
@@ -39,3 +131,378 @@ impl DeviceExtension {
 }
 
 pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+// Ray flags, matching the bit values of the SPIR-V RayFlags operand of traceNV
+pub const RAY_FLAG_OPAQUE: u32 = 0x01;
+pub const RAY_FLAG_TERMINATE_ON_FIRST_HIT: u32 = 0x04;
+pub const RAY_FLAG_SKIP_CLOSEST_HIT_SHADER: u32 = 0x08;
+
+// Shadow rays only test visibility: the first hit is enough and no shading is required
+pub const SHADOW_RAY_FLAGS: u32 =
+    RAY_FLAG_OPAQUE | RAY_FLAG_TERMINATE_ON_FIRST_HIT | RAY_FLAG_SKIP_CLOSEST_HIT_SHADER;
+pub const SHADOW_RAY_FLAGS_CONSTANT_ID: u32 = 0;
+// Set to HitGroup::index for every hit group
+pub const SHADING_MODEL_CONSTANT_ID: u32 = 1;
+// 1 while the scene mesh has a skin, the hit shaders then read its deformed normals
+pub const SKINNED_CONSTANT_ID: u32 = 2;
+// Camera rays of the ray generation shaders
+pub const PRIMARY_RAY_FLAGS: u32 = RAY_FLAG_OPAQUE;
+pub const PRIMARY_RAY_FLAGS_CONSTANT_ID: u32 = 3;
+
+// Descriptor sets of the ray tracing pipeline. The scene set changes with the scene, the frame
+// set holds the render sized targets and is the only one recreated on resize.
+pub const SCENE_DESCRIPTOR_SET: u32 = 0;
+pub const FRAME_DESCRIPTOR_SET: u32 = 1;
+
+// Picks the passes created with the app, `--render_mode` overrides it
+pub const RENDER_MODE: RenderMode = RenderMode::Standard;
+
+pub const GROUND_PLANE: GroundPlane = GroundPlane::Checker {
+    color: [0.8, 0.8, 0.8],
+    size: 0.5,
+};
+// The plane is one triangle of the scene mesh scaled up far enough to pass for infinite
+pub const GROUND_PLANE_SCALE: f32 = 4000.0;
+// The render origin follows the camera in cells this big, a multiple of every checker size
+pub const RENDER_ORIGIN_CELL_SIZE: f64 = 1024.0;
+// InstanceShadingData::emission a, mirrored in the closest hit and hybrid ray generation shaders
+// Layers of the scene's own instances and of the ground plane
+pub const SCENE_INSTANCE_LAYERS: &[RenderLayer] = &[RenderLayer::Scene];
+pub const GROUND_PLANE_LAYERS: &[RenderLayer] = &[RenderLayer::Ground];
+// Cameras see every layer and cast secondary rays against all but the first person and debug ones
+pub const CAMERA_LAYER_MASKS: CameraLayerMasks = CameraLayerMasks {
+    primary: 0xff,
+    secondary: !(RenderLayer::FirstPerson.mask() | RenderLayer::Debug.mask()),
+};
+// The ray masks follow the camera, accumulation and depth range push constants
+pub const RAY_MASK_PUSH_CONSTANT_OFFSET: u32 = (std::mem::size_of::<CameraPushConstants>()
+    + std::mem::size_of::<AccumulationPushConstants>()
+    + std::mem::size_of::<DepthRangePushConstants>())
+    as u32;
+pub const VIEWPORT_PUSH_CONSTANT_OFFSET: u32 =
+    RAY_MASK_PUSH_CONSTANT_OFFSET + std::mem::size_of::<RayMaskPushConstants>() as u32;
+pub const RAY_BIAS_PUSH_CONSTANT_OFFSET: u32 =
+    VIEWPORT_PUSH_CONSTANT_OFFSET + std::mem::size_of::<ViewportPushConstants>() as u32;
+pub const INSTANCE_MATERIAL_DEFAULT: f32 = 0.0;
+pub const INSTANCE_MATERIAL_CHECKER: f32 = 1.0;
+pub const INSTANCE_MATERIAL_SHADOW_CATCHER: f32 = 2.0;
+// Surface of the built-in instances, until the scene describes its own
+pub const DEFAULT_ROUGHNESS: f32 = 0.5;
+pub const DEFAULT_METALLIC: f32 = 0.0;
+pub const DEFAULT_ANISOTROPY: f32 = 0.0;
+// Opaque, the any-hit shader of stochastic transparency only runs below the threshold
+pub const DEFAULT_OPACITY: f32 = 1.0;
+pub const DEFAULT_OPACITY_THRESHOLD: f32 = 1.0;
+// What the GGX shading in the shaders is defined for, material edits outside are rejected
+pub const ALBEDO_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+pub const ROUGHNESS_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+pub const METALLIC_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+// Negative values stretch the highlight along the bitangent instead of the tangent
+pub const ANISOTROPY_RANGE: RangeInclusive<f32> = -1.0..=1.0;
+pub const EMISSIVE_RANGE: RangeInclusive<f32> = 0.0..=f32::MAX;
+// World units, zero keeps the light where it entered
+pub const MEAN_FREE_PATH_RANGE: RangeInclusive<f32> = 0.0..=f32::MAX;
+pub const OPACITY_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+
+// ReSTIR DI tuning
+pub const RESTIR_LIGHT_COUNT: u32 = 256;
+pub const RESTIR_INITIAL_CANDIDATES: u32 = 32;
+// Temporal history is clamped to this many times the initial candidate count
+pub const RESTIR_MAX_HISTORY: u32 = 20;
+pub const RESTIR_SPATIAL_NEIGHBORS: u32 = 5;
+pub const RESTIR_SPATIAL_RADIUS: f32 = 30.0;
+pub const RESTIR_WORKGROUP_SIZE: u32 = 8;
+
+// Matches local_size_x and local_size_y of rayquery_trace.comp
+pub const RAY_QUERY_WORKGROUP_SIZE: u32 = 8;
+
+// Ray tracing pipeline cache, reloaded on startup so later runs can skip compilation
+pub const PIPELINE_CACHE_PATH: &str = "pipeline_cache.bin";
+// Written to the offscreen target while the ray tracing pipeline is still compiling
+pub const PLACEHOLDER_COLOR: [f32; 4] = [0.2, 0.2, 0.2, 1.0];
+
+// The ray traced image is kept in HDR and tonemapped to the surface format by the exposure pass.
+// `--hdr_format` overrides it, unsupported formats fall back along HdrFormat::ALL.
+pub const HDR_FORMAT: HdrFormat = HdrFormat::Rgba16f;
+// Albedo, normals, depth, light and shadow catcher alpha at the render resolution, see Aov.
+// Without them the AOV images are 1x1 and the shaders' writes fall outside of them.
+pub const AOV_OUTPUTS: bool = false;
+pub const AOV_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+// Auto-exposure: the histogram covers 2^min .. 2^(min + range) in luminance
+pub const EXPOSURE_HISTOGRAM_BINS: u32 = 256;
+pub const EXPOSURE_MIN_LOG_LUMINANCE: f32 = -10.0;
+pub const EXPOSURE_LOG_LUMINANCE_RANGE: f32 = 12.0;
+// Rate at which the adapted luminance follows the metered one, per second
+pub const EXPOSURE_ADAPTATION_SPEED: f32 = 1.5;
+// Applied on top of the metered exposure, in stops
+pub const EXPOSURE_EV_COMPENSATION: f32 = 0.0;
+pub const EXPOSURE_HISTOGRAM_WORKGROUP_SIZE: u32 = 16;
+pub const EXPOSURE_TONEMAP_WORKGROUP_SIZE: u32 = 8;
+pub const CHECKERBOARD_WORKGROUP_SIZE: u32 = 8;
+pub const SKINNING_WORKGROUP_SIZE: u32 = 64;
+
+// Tiled offline rendering (`ash_rt tiled [columns] [rows] [path]`), every tile is rendered at the
+// window size. A .exr path saves the HDR image and the AOVs.
+pub const TILED_RENDER_COLUMNS: u32 = 4;
+pub const TILED_RENDER_ROWS: u32 = 4;
+pub const TILED_RENDER_OUTPUT_PATH: &str = "tiled_render.png";
+// Images are read back in bands of rows through this many staging buffers of at most this
+// size, whatever the size of the image
+pub const READBACK_STAGING_SIZE: u64 = 32 << 20;
+pub const READBACK_STAGING_SLOTS: usize = 2;
+// `ash_rt aovs` writes the HDR image and every AOV here as layers of one EXR file
+pub const AOV_OUTPUT_PATH: &str = "aovs.exr";
+// `ash_rt shadows` writes the ray traced and the shadow mapped frame here, side by side
+pub const SHADOW_COMPARISON_OUTPUT_PATH: &str = "shadow_comparison.png";
+// `ash_rt stats [columns rows]` reports on the scene without rendering it, see
+// utility::scene_report. Meshes above this many triangles are warned about.
+pub const SCENE_REPORT_HUGE_MESH_TRIANGLES: u32 = 1 << 20;
+// Cells of the triangle heatmap over the scene's x and z extent
+pub const SCENE_REPORT_HEATMAP_COLUMNS: u32 = 48;
+pub const SCENE_REPORT_HEATMAP_ROWS: u32 = 16;
+// `ash_rt diff a b`, see utility::image_diff. The tolerance is in linear color, about one 8 bit
+// step at mid gray.
+pub const IMAGE_DIFF_TOLERANCE: f32 = 0.005;
+pub const IMAGE_DIFF_SSIM_WINDOW: u32 = 8;
+// Mismatched pixels this many tolerances past theirs are yellow in the heatmap, less are redder
+pub const IMAGE_DIFF_HEATMAP_RANGE: f32 = 10.0;
+// Brightness of the matching pixels in the heatmap
+pub const IMAGE_DIFF_HEATMAP_SHADE: f32 = 0.3;
+pub const IMAGE_DIFF_OUTPUT_PATH: &str = "diff.png";
+// `screenshot` in the console of `ash_rt window` saves the next frame here without a path
+pub const SCREENSHOT_OUTPUT_PATH: &str = "screenshot.png";
+pub const EXR_BEAUTY_PRECISION: ExrPrecision = ExrPrecision::Half;
+// Depth and normals lose too much in half precision
+pub const EXR_AOV_PRECISION: ExrPrecision = ExrPrecision::Float;
+
+// Texture atlas (`ash_rt atlas directory`): textures are packed onto square pages until the
+// budget is used up, after which all of them are shrunk to fit
+pub const ATLAS_PAGE_SIZE: u32 = 4096;
+pub const ATLAS_PADDING: u32 = 4;
+pub const ATLAS_MEMORY_BUDGET: u64 = 512 << 20;
+// Texels of the textures before they are packed, changed with `--texture_budget=` in MiB or
+// `ash_rt texture_budget mib directory`. Over it the top mips of the lowest priority textures are
+// dropped first, see TexturePriority. Below the atlas budget, which also pays for the padding and
+// the unused page space.
+pub const TEXTURE_MEMORY_BUDGET: u64 = 384 << 20;
+// Textures are not shrunk below this on their larger side to meet the budget
+pub const TEXTURE_BUDGET_MIN_SIZE: u32 = 64;
+
+// Filtering of the model texture and the texture atlas, changed with `--max_anisotropy=`,
+// `--lod_bias=` and `--trilinear=` or `ash_rt texture_quality name=value...`. Clamped to what the
+// device supports.
+pub const TEXTURE_QUALITY: TextureQuality = TextureQuality {
+    max_anisotropy: 16.0,
+    lod_bias: 0.0,
+    trilinear: true,
+};
+
+// Fog (`ash_rt fog density r,g,b [grid.vol] [scale]`), off until a density is set
+pub const DEFAULT_FOG_DENSITY: f32 = 0.0;
+pub const DEFAULT_FOG_COLOR: [f32; 3] = [0.5, 0.6, 0.7];
+
+// AS stress test (`ash_rt stress [instances] [meshes] [triangles] [geometries]`): random triangle
+// soups instanced all over a cube, with a hit record and two storage buffers per geometry
+pub const STRESS_INSTANCE_COUNT: u32 = 100_000;
+pub const STRESS_MESH_COUNT: u32 = 256;
+pub const STRESS_TRIANGLES_PER_MESH: u32 = 1024;
+pub const STRESS_GEOMETRIES_PER_MESH: u32 = 4;
+pub const STRESS_SEED: u64 = 0x5eed;
+pub const STRESS_SCENE_SIZE: f32 = 200.0;
+// Chunk streaming test (`ash_rt stream [chunks_per_side] [frames] [budget_mb]`): a city of
+// random meshes, one per chunk instanced all over it, flown through at the camera height
+pub const STREAM_CHUNKS_PER_SIDE: u32 = 32;
+pub const STREAM_FRAMES: u32 = 600;
+pub const STREAM_CELL_SIZE: f32 = 50.0;
+pub const STREAM_INSTANCES_PER_CHUNK: u32 = 64;
+pub const STREAM_TRIANGLES_PER_CHUNK: u32 = 4096;
+pub const STREAM_CAMERA_HEIGHT: f32 = 10.0;
+// Chunks within the load radius are streamed in, those beyond the unload radius out
+pub const STREAM_LOAD_RADIUS: f32 = 200.0;
+pub const STREAM_UNLOAD_RADIUS: f32 = 250.0;
+pub const STREAM_LOADS_PER_FRAME: usize = 8;
+pub const STREAM_MEMORY_BUDGET: u64 = 64 << 20;
+// BLASes built per frame when `--blas_builds_per_frame` is not given, 0 builds all of them in
+// one go. Only the stress and streaming scenes queue their builds so far.
+pub const BLAS_BUILDS_PER_FRAME: u32 = 0;
+
+// Motion blur: the shutter interval is split into this many time samples, each traced against
+// the TLAS refit with the instance transforms at that time. 1 disables motion blur.
+pub const MOTION_BLUR_TIME_SAMPLES: u32 = 4;
+
+// Encoding of the BLAS vertex positions, also used by the raster pre-pass
+pub const BLAS_VERTEX_FORMAT: VertexPositionFormat = VertexPositionFormat::Float16;
+// The BLAS of a skinned scene mesh is updated in place with every pose
+pub const SKINNED_BLAS_FLAGS: vk::BuildAccelerationStructureFlagsNV =
+    vk::BuildAccelerationStructureFlagsNV::from_raw(
+        vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE.as_raw()
+            | vk::BuildAccelerationStructureFlagsNV::ALLOW_UPDATE.as_raw(),
+    );
+
+// Bytes of transient uniform data each frame can allocate from the uniform ring
+pub const UNIFORM_RING_FRAME_SIZE: u64 = 64 * 1024;
+
+// Size of the shared vertex and index buffers of the geometry pool
+pub const GEOMETRY_POOL_VERTEX_BLOCK_SIZE: u64 = 16 * 1024 * 1024;
+pub const GEOMETRY_POOL_INDEX_BLOCK_SIZE: u64 = 8 * 1024 * 1024;
+// Share of the pool lost to holes from removed meshes above which it is compacted
+pub const GEOMETRY_POOL_DEFRAGMENT_THRESHOLD: f32 = 0.25;
+
+// Hybrid mode: depth and instance ID targets of the raster pre-pass, read by the ray generation
+// shader to reconstruct primary hits
+pub const DEPTH_PREPASS_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+pub const INSTANCE_ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+// Instance ID target value of pixels not covered by any instance
+pub const NO_INSTANCE_ID: u32 = u32::MAX;
+// Two-pass occlusion culling of the pre-pass draws against a pyramid of the farthest depths
+pub const DEPTH_PYRAMID_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+pub const DEPTH_PYRAMID_WORKGROUP_SIZE: u32 = 8;
+pub const OCCLUSION_CULL_WORKGROUP_SIZE: u32 = 64;
+// Tiled light culling of the depth pre-pass (`--point_lights=N`). Mirrored in
+// shaders/include/tiled_lights.glsl, a tile is also one workgroup of light_cull.comp.
+pub const LIGHT_TILE_SIZE: u32 = 16;
+// Lights past this many in one tile are dropped
+pub const LIGHT_TILE_MAX_LIGHTS: u32 = 63;
+pub const POINT_LIGHT_CAPACITY: u32 = 4096;
+// The lights stand in a grid over the scene, each gives this irradiance one grid step away and
+// is cut off where its irradiance falls to POINT_LIGHT_CUTOFF
+pub const POINT_LIGHT_IRRADIANCE: f32 = 0.5;
+pub const POINT_LIGHT_CUTOFF: f32 = 0.05;
+// Values the closest hit shaders append for the pixel picked with `--debug_pixel=x,y`, dumped to
+// the log after every traced frame
+pub const DEBUG_BUFFER_CAPACITY: u32 = 256;
+pub const DEBUG_PIXEL_NONE: u32 = u32::MAX;
+
+// Where the sun starts out, pointing towards it. SunController moves it from there.
+pub const LIGHT_DIRECTION: [f32; 3] = [0.5, 1.0, -1.0];
+// A drag over the full height of the view turns the sun by this much
+pub const SUN_DRAG_RADIANS_PER_VIEW: f32 = std::f32::consts::PI;
+// Day cycle (`ash_rt day`): the sun rises at 6, peaks at noon and sets at 18
+pub const SUN_NOON_ELEVATION: Deg<f32> = Deg(60.0);
+pub const SUN_CYCLE_HOURS_PER_SECOND: f32 = 1.0;
+pub const SUN_CYCLE_FRAMES: u32 = 24;
+// Frames are saved as <prefix>_<frame>.png
+pub const SUN_CYCLE_OUTPUT_PREFIX: &str = "day";
+// Background of rays that leave the scene, blended by sun elevation
+pub const SKY_DAY_COLOR: [f32; 3] = [0.0, 0.1, 0.3];
+pub const SKY_TWILIGHT_COLOR: [f32; 3] = [0.35, 0.15, 0.1];
+pub const SKY_NIGHT_COLOR: [f32; 3] = [0.002, 0.004, 0.012];
+
+// Skinning cycle (`ash_rt skin`): the scene mesh sways on Skin::sway over this many frames
+pub const SKIN_CYCLE_FRAMES: u32 = 24;
+// Frames are saved as <prefix>_<frame>.png
+pub const SKIN_CYCLE_OUTPUT_PREFIX: &str = "skin";
+
+// Shadow map shading of the hybrid mode (`--shadow_map=true`), lit by the same sun
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+// Width of the light view in scene bounding sphere diameters, above 1 to also take in the
+// shadows cast onto the ground around the scene
+pub const SHADOW_MAP_COVERAGE: f32 = 2.0;
+// Rasterization depth bias against shadow acne
+pub const SHADOW_MAP_DEPTH_BIAS_CONSTANT: f32 = 1.25;
+pub const SHADOW_MAP_DEPTH_BIAS_SLOPE: f32 = 1.75;
+// Environment probes reflected by the shadow map shading (`--probes=x,y,z[,x,y,z...]`), see
+// utility::environment_probe. Each face is traced at the render size and filtered down to this.
+pub const ENVIRONMENT_PROBE_SIZE: u32 = 64;
+// Mip levels of the probe faces, prefiltered for roughness 0 to 1. At most log2 of the size + 1.
+pub const ENVIRONMENT_PROBE_MIP_LEVELS: u32 = 5;
+
+// Debug draw overlay (`--debug_draw=true`)
+pub const DEBUG_DRAW_BLAS_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+pub const DEBUG_DRAW_INSTANCE_COLOR: [f32; 4] = [1.0, 0.8, 0.1, 1.0];
+pub const DEBUG_DRAW_FRUSTUM_COLOR: [f32; 4] = [1.0, 0.3, 1.0, 1.0];
+pub const DEBUG_DRAW_LIGHT_SIZE: f32 = 0.1;
+// The frozen camera frustum is drawn up to this distance instead of the real far plane
+pub const DEBUG_DRAW_FRUSTUM_FAR_PLANE: f32 = 5.0;
+
+// Outline of the instances `ash_rt select` picked, drawn over the tonemapped image
+pub const SELECTION_OUTLINE_COLOR: [f32; 4] = [1.0, 0.55, 0.0, 1.0];
+// Pixels around the selected instances the outline covers
+pub const SELECTION_OUTLINE_RADIUS: u32 = 2;
+pub const SELECTION_OUTLINE_WORKGROUP_SIZE: u32 = 8;
+// `ash_rt select` saves the frame with the selection outlined here
+pub const SELECTION_OUTPUT_PATH: &str = "selection.png";
+
+// Default log filter in EnvFilter syntax, LOG_FILTER_ENV overrides it
+pub const LOG_FILTER: &str = "info";
+pub const LOG_FILTER_ENV: &str = "ASH_RT_LOG";
+// "1"/"0" turns the validation layers on or off regardless of the build
+pub const VALIDATION_ENV: &str = "ASH_RT_VALIDATION";
+
+// GPU counters of `ash_rt profile`, collected for the performance counters whose names contain
+// one of the keywords, matched without case
+pub const PERFORMANCE_COUNTER_KEYWORDS: &[&str] = &["occupancy", "throughput", "bandwidth"];
+// Marked passes beyond this many per frame only get labels
+pub const MAX_PROFILED_PASSES: u32 = 16;
+
+// Per-user settings restored on startup, see UserSettings::path
+pub const USER_SETTINGS_DIR: &str = "ash_rt";
+pub const USER_SETTINGS_FILE: &str = "settings.txt";
+
+// Primary rays plus the shadow rays cast from the closest hit shader, the pipeline is not
+// downgraded below it
+pub const MIN_RECURSION_DEPTH: u32 = 2;
+
+pub const RENDER_SETTINGS: RenderSettings = RenderSettings {
+    max_recursion_depth: MIN_RECURSION_DEPTH,
+    samples_per_pixel: 1,
+    // Only the offline outputs are denoised, with utility::denoiser::default_denoiser
+    denoise: false,
+    render_scale: 1.0,
+    exposure_adaptation_speed: EXPOSURE_ADAPTATION_SPEED,
+    ev_compensation: EXPOSURE_EV_COMPENSATION,
+    tone_curve: ToneCurve::Aces,
+    debug_draw: false,
+    target_fps: 0.0,
+    shadow_map: false,
+    checkerboard: false,
+    ray_t_min: 0.001,
+    // Relative to the position's magnitude, about a hundred float ulps
+    ray_offset: RayOffset::Relative,
+    ray_offset_scale: 1e-5,
+};
+
+// Dynamic resolution (`--target_fps=60`), see DynamicResolution
+// Fraction of the target frame time the GPU time may stray from it without a change
+pub const DYNAMIC_RESOLUTION_HYSTERESIS: f32 = 0.1;
+// Weight of the newest frame in the smoothed frame time
+pub const DYNAMIC_RESOLUTION_SMOOTHING: f32 = 0.2;
+// Frames measured after a change before the next one
+pub const DYNAMIC_RESOLUTION_SETTLE_FRAMES: u32 = 8;
+pub const DYNAMIC_RESOLUTION_MIN_SCALE: f32 = 0.25;
+// The scale moves in steps so the targets are not reallocated for every small drift
+pub const DYNAMIC_RESOLUTION_SCALE_STEP: f32 = 0.0625;
+// Video capture (`ash_rt capture`)
+pub const CAPTURE_FRAME_RATE: u32 = 30;
+pub const CAPTURE_DURATION_SECONDS: f32 = 4.0;
+// Frames read back ahead of the worker writing them
+pub const CAPTURE_QUEUE_FRAMES: usize = 4;
+// Encodes .mp4, .mkv and .mov captures, looked up on PATH
+pub const FFMPEG_PATH: &str = "ffmpeg";
+// Devices of a device group `--split_frame` renders on
+pub const SPLIT_FRAME_MAX_DEVICES: usize = 2;
+// Time exposure adapts by every frame with `--deterministic`
+pub const DETERMINISTIC_FRAME_TIME: f32 = 1.0 / 60.0;
+// Depth range of the raster passes and primary rays, see DepthRange. `--near=` and `--far=` set
+// the planes, otherwise they are fitted to the scene bounds.
+// Planes of an empty scene
+pub const DEFAULT_NEAR_PLANE: f32 = 0.1;
+pub const DEFAULT_FAR_PLANE: f32 = 1000.0;
+// Added to the scene's bounding radius
+pub const DEPTH_RANGE_MARGIN: f32 = 0.01;
+pub const DEPTH_RANGE_MIN_NEAR_RATIO: f32 = 1.0e-4;
+// Tessellation and displacement of the ray traced geometry (`--tessellation=4`,
+// `--displacement=height.png`), see utility::tessellation
+pub const DEFAULT_TESSELLATION_LEVEL: u32 = 1;
+// The BLAS has 16 bit indices, the vertices of one triangle split 360 times still fit
+pub const MAX_TESSELLATION_LEVEL: u32 = 360;
+pub const DEFAULT_DISPLACEMENT_SCALE: f32 = 0.1;
+pub const DEFAULT_DISPLACEMENT_MIDLEVEL: f32 = 0.5;
+// Allocation count (`ash_rt allocations [frames]`), built with the `alloc-count` feature
+// Frames traced before counting, which size the scratch lists of the frame context
+pub const ALLOCATION_WARMUP_FRAMES: u32 = 4;
+pub const ALLOCATION_COUNT_FRAMES: u32 = 100;
+// Traced frames the GPU may be working on at once, each with its own TLAS, scene descriptor set,
+// command buffer and timestamps. A frame refits its TLAS while the one before it still traces.
+pub const TRACED_FRAMES_IN_FLIGHT: usize = 2;
@@ -1,10 +1,17 @@
 use crate::utility;
+use crate::utility::constants::VALIDATION_ENV;
+use crate::utility::logging::{TARGET_SHADER, TARGET_VALIDATION};
 
 use ash::vk;
 
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::os::raw::c_void;
+use std::path::Path;
 use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// Bumped for every error the validation layers report, see validation_error_count
+static VALIDATION_ERROR_COUNT: AtomicU32 = AtomicU32::new(0);
 
 unsafe extern "system" fn vulkan_debug_utils_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
@@ -12,30 +19,123 @@ unsafe extern "system" fn vulkan_debug_utils_callback(
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
     _p_user_data: *mut c_void,
 ) -> vk::Bool32 {
-    let severity = match message_severity {
-        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => "[Verbose]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => "[Warning]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => "[Error]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => "[Info]",
-        _ => "[Unknown]",
-    };
     let types = match message_type {
         vk::DebugUtilsMessageTypeFlagsEXT::GENERAL => "[General]",
         vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE => "[Performance]",
         vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION => "[Validation]",
         _ => "[Unknown]",
     };
-    let message = CStr::from_ptr((*p_callback_data).p_message);
-    println!("[Debug]{}{}{:?}", severity, types, message);
+    let message = CStr::from_ptr((*p_callback_data).p_message).to_string_lossy();
+    // Shader printf output comes through the validation layer as info messages
+    let message_id = (*p_callback_data).p_message_id_name;
+    if !message_id.is_null()
+        && CStr::from_ptr(message_id)
+            .to_bytes()
+            .ends_with(b"DEBUG-PRINTF")
+    {
+        tracing::info!(target: TARGET_SHADER, "{}", message);
+        return vk::FALSE;
+    }
+    match message_severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => {
+            VALIDATION_ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+            tracing::error!(target: TARGET_VALIDATION, "{}{}", types, message);
+        }
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => {
+            tracing::warn!(target: TARGET_VALIDATION, "{}{}", types, message)
+        }
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => {
+            tracing::debug!(target: TARGET_VALIDATION, "{}{}", types, message)
+        }
+        _ => tracing::trace!(target: TARGET_VALIDATION, "{}{}", types, message),
+    }
 
     vk::FALSE
 }
 
+/// Errors reported by the validation layers so far, compared before and after a call to tell
+/// whether it failed validation
+pub fn validation_error_count() -> u32 {
+    VALIDATION_ERROR_COUNT.load(Ordering::Relaxed)
+}
+
+/// Names `handle` in validation messages and graphics debuggers. Does nothing without
+/// VK_EXT_debug_utils, a name is cut at its first NUL.
+pub fn set_object_name<T: vk::Handle>(
+    debug_utils: Option<&ash::extensions::ext::DebugUtils>,
+    device: vk::Device,
+    handle: T,
+    name: &str,
+) {
+    let debug_utils = match debug_utils {
+        Some(debug_utils) => debug_utils,
+        None => return,
+    };
+    let name = CString::new(name.split('\0').next().unwrap_or_default()).unwrap();
+    let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+        .object_type(T::TYPE)
+        .object_handle(handle.as_raw())
+        .object_name(&name)
+        .build();
+    if let Err(err) = unsafe { debug_utils.set_debug_utils_object_name(device, &name_info) } {
+        tracing::warn!("Failed to name {:?} {:?}: {}", T::TYPE, name, err);
+    }
+}
+
+/// Debug name of something made from the asset at `path`: the file name followed by what was
+/// made of it, e.g. `viking_room.obj vertices`
+pub fn asset_debug_name(path: &Path, resource: &str) -> String {
+    let file_name = path
+        .file_name()
+        .map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy());
+    format!("{} {}", file_name, resource)
+}
+
+#[derive(Clone, Copy)]
 pub struct ValidationInfo {
     pub is_enable: bool,
+    // Runs the shaders' printf through the validation layer, which it turns on
+    pub debug_printf: bool,
     pub required_validation_layers: [&'static str; 1],
 }
 
+impl ValidationInfo {
+    /// The build default, overridden by the VALIDATION_ENV environment variable and then by a
+    /// `--validation=bool` option. `--debug_printf=bool` sets debug_printf.
+    pub fn resolve(&self, options: &[String]) -> ValidationInfo {
+        let parse = |value: &str| match value {
+            "1" | "true" | "on" => Some(true),
+            "0" | "false" | "off" => Some(false),
+            _ => None,
+        };
+
+        let mut is_enable = self.is_enable;
+        if let Ok(value) = std::env::var(VALIDATION_ENV) {
+            is_enable = parse(&value)
+                .unwrap_or_else(|| panic!("Invalid {} value {:?}.", VALIDATION_ENV, value));
+        }
+        if let Some(value) = options
+            .iter()
+            .find_map(|option| option.strip_prefix("--validation="))
+        {
+            is_enable = parse(value).expect("Invalid validation.");
+        }
+        let mut debug_printf = self.debug_printf;
+        if let Some(value) = options
+            .iter()
+            .find_map(|option| option.strip_prefix("--debug_printf="))
+        {
+            debug_printf = parse(value).expect("Invalid debug_printf.");
+        }
+
+        ValidationInfo {
+            is_enable: is_enable || debug_printf,
+            debug_printf,
+            ..*self
+        }
+    }
+}
+
 pub fn check_validation_layer_support(
     entry: &ash::Entry,
     required_validation_layers: &Vec<&str>,
@@ -47,7 +147,7 @@ pub fn check_validation_layer_support(
         .expect("Failed to enumerate Instance Layers Properties!");
 
     if layer_properties.len() <= 0 {
-        eprintln!("No available layers.");
+        tracing::warn!(target: TARGET_VALIDATION, "No available layers.");
         return false;
     }
     // else {
@@ -77,17 +177,36 @@ pub fn check_validation_layer_support(
     true
 }
 
+/// Whether the instance can enable VK_EXT_debug_utils, which the validation layers provide too
+pub fn debug_utils_supported(entry: &ash::Entry) -> bool {
+    let name = ash::extensions::ext::DebugUtils::name().to_str().unwrap();
+    entry
+        .enumerate_instance_extension_properties(None)
+        .is_ok_and(|extensions| {
+            extensions
+                .iter()
+                .any(|extension| utility::tools::vk_to_string(&extension.extension_name) == name)
+        })
+}
+
+// The loader is created whenever the instance has VK_EXT_debug_utils, the messenger only when
+// validation is on
 pub fn setup_debug_utils(
     is_enable_debug: bool,
+    debug_printf: bool,
     entry: &ash::Entry,
     instance: &ash::Instance,
-) -> (ash::extensions::ext::DebugUtils, vk::DebugUtilsMessengerEXT) {
-    let debug_utils_loader = ash::extensions::ext::DebugUtils::new(entry, instance);
-
+) -> (
+    Option<ash::extensions::ext::DebugUtils>,
+    vk::DebugUtilsMessengerEXT,
+) {
     if is_enable_debug == false {
+        let debug_utils_loader = debug_utils_supported(entry)
+            .then(|| ash::extensions::ext::DebugUtils::new(entry, instance));
         (debug_utils_loader, vk::DebugUtilsMessengerEXT::null())
     } else {
-        let messenger_ci = populate_debug_messenger_create_info();
+        let debug_utils_loader = ash::extensions::ext::DebugUtils::new(entry, instance);
+        let messenger_ci = populate_debug_messenger_create_info(debug_printf);
 
         let utils_messenger = unsafe {
             debug_utils_loader
@@ -95,19 +214,25 @@ pub fn setup_debug_utils(
                 .expect("Debug Utils Callback")
         };
 
-        (debug_utils_loader, utils_messenger)
+        (Some(debug_utils_loader), utils_messenger)
     }
 }
 
-pub fn populate_debug_messenger_create_info() -> vk::DebugUtilsMessengerCreateInfoEXT {
+// Info messages are only asked for along with debug printf, which reports through them
+pub fn populate_debug_messenger_create_info(
+    debug_printf: bool,
+) -> vk::DebugUtilsMessengerCreateInfoEXT {
+    let mut message_severity = vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+        // | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
+        | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
+    if debug_printf {
+        message_severity |= vk::DebugUtilsMessageSeverityFlagsEXT::INFO;
+    }
     vk::DebugUtilsMessengerCreateInfoEXT {
         s_type: vk::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
         p_next: ptr::null(),
         flags: vk::DebugUtilsMessengerCreateFlagsEXT::empty(),
-        message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-            // | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-            // | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-            | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+        message_severity,
         message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
             | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
             | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
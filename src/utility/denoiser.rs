@@ -0,0 +1,192 @@
+use ash::vk;
+
+// Images of one traced frame as RGBA f32, rows from the top. Normals are only used together with
+// the albedo.
+pub struct DenoiserImages<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub color: &'a [f32],
+    pub albedo: Option<&'a [f32]>,
+    pub normal: Option<&'a [f32]>,
+}
+
+// Denoises on the host, the frame is read back before and uploaded again after
+pub trait Denoiser {
+    fn name(&self) -> &str;
+    // Returns the denoised color as RGBA, with the alpha of the input
+    fn denoise(&mut self, images: &DenoiserImages) -> Result<Vec<f32>, String>;
+}
+
+// Render targets of a frame in the GENERAL layout, already written by the trace
+pub struct GpuDenoiserImages {
+    pub extent: vk::Extent2D,
+    pub color: vk::Image,
+    pub albedo: Option<vk::Image>,
+    pub normal: Option<vk::Image>,
+}
+
+// Denoises the frame in place on the device, e.g. OptiX through external memory. Recorded into
+// a command buffer after the trace, without a readback.
+pub trait GpuDenoiser {
+    fn name(&self) -> &str;
+    fn record(&mut self, command_buffer: vk::CommandBuffer, images: &GpuDenoiserImages);
+}
+
+pub enum DenoiserBackend {
+    Cpu(Box<dyn Denoiser>),
+    Gpu(Box<dyn GpuDenoiser>),
+}
+
+impl DenoiserBackend {
+    pub fn name(&self) -> &str {
+        match self {
+            DenoiserBackend::Cpu(denoiser) => denoiser.name(),
+            DenoiserBackend::Gpu(denoiser) => denoiser.name(),
+        }
+    }
+}
+
+// The built-in denoiser, Open Image Denoise when built with the `oidn` feature
+pub fn default_denoiser() -> Option<DenoiserBackend> {
+    #[cfg(feature = "oidn")]
+    {
+        match OidnDenoiser::new() {
+            Ok(denoiser) => return Some(DenoiserBackend::Cpu(Box::new(denoiser))),
+            Err(message) => tracing::warn!("Open Image Denoise is unavailable: {}", message),
+        }
+    }
+    None
+}
+
+#[cfg(feature = "oidn")]
+pub use oidn::OidnDenoiser;
+
+// Binds the C API of Open Image Denoise, the library has to be installed where the linker finds
+// it. Only the calls shared by versions 1 and 2 are used.
+#[cfg(feature = "oidn")]
+mod oidn {
+    use super::{Denoiser, DenoiserImages};
+    use std::ffi::{c_char, c_void, CStr};
+    use std::ptr;
+
+    type OidnDevice = *mut c_void;
+    type OidnFilter = *mut c_void;
+
+    const OIDN_DEVICE_TYPE_CPU: i32 = 1;
+    const OIDN_FORMAT_FLOAT3: i32 = 3;
+    const OIDN_ERROR_NONE: i32 = 0;
+
+    #[link(name = "OpenImageDenoise")]
+    extern "C" {
+        fn oidnNewDevice(device_type: i32) -> OidnDevice;
+        fn oidnCommitDevice(device: OidnDevice);
+        fn oidnGetDeviceError(device: OidnDevice, out_message: *mut *const c_char) -> i32;
+        fn oidnReleaseDevice(device: OidnDevice);
+        fn oidnNewFilter(device: OidnDevice, filter_type: *const c_char) -> OidnFilter;
+        fn oidnSetSharedFilterImage(
+            filter: OidnFilter,
+            name: *const c_char,
+            data: *mut c_void,
+            format: i32,
+            width: usize,
+            height: usize,
+            byte_offset: usize,
+            byte_pixel_stride: usize,
+            byte_row_stride: usize,
+        );
+        fn oidnSetFilter1b(filter: OidnFilter, name: *const c_char, value: bool);
+        fn oidnCommitFilter(filter: OidnFilter);
+        fn oidnExecuteFilter(filter: OidnFilter);
+        fn oidnReleaseFilter(filter: OidnFilter);
+    }
+
+    // The generic ray tracing filter on the CPU device
+    pub struct OidnDenoiser {
+        device: OidnDevice,
+    }
+
+    impl OidnDenoiser {
+        pub fn new() -> Result<OidnDenoiser, String> {
+            unsafe {
+                let device = oidnNewDevice(OIDN_DEVICE_TYPE_CPU);
+                if device.is_null() {
+                    return Err(String::from("Failed to create the CPU device."));
+                }
+                oidnCommitDevice(device);
+                let denoiser = OidnDenoiser { device };
+                denoiser.check_error()?;
+                Ok(denoiser)
+            }
+        }
+
+        fn check_error(&self) -> Result<(), String> {
+            let mut message: *const c_char = ptr::null();
+            let error = unsafe { oidnGetDeviceError(self.device, &mut message) };
+            if error == OIDN_ERROR_NONE {
+                return Ok(());
+            }
+            if message.is_null() {
+                return Err(format!("Error {}", error));
+            }
+            Err(unsafe { CStr::from_ptr(message) }
+                .to_string_lossy()
+                .into_owned())
+        }
+    }
+
+    impl Denoiser for OidnDenoiser {
+        fn name(&self) -> &str {
+            "Open Image Denoise"
+        }
+
+        fn denoise(&mut self, images: &DenoiserImages) -> Result<Vec<f32>, String> {
+            let width = images.width as usize;
+            let height = images.height as usize;
+            let pixel_stride = 4 * std::mem::size_of::<f32>();
+            // The alpha channel is skipped by the filter and copied from the input
+            let mut output = images.color.to_vec();
+
+            unsafe {
+                let filter = oidnNewFilter(self.device, c"RT".as_ptr());
+                let set_image = |name: &CStr, data: *mut c_void| {
+                    oidnSetSharedFilterImage(
+                        filter,
+                        name.as_ptr(),
+                        data,
+                        OIDN_FORMAT_FLOAT3,
+                        width,
+                        height,
+                        0,
+                        pixel_stride,
+                        pixel_stride * width,
+                    )
+                };
+                // Inputs are only read, the API just has no const variant
+                set_image(c"color", images.color.as_ptr() as *mut c_void);
+                if let Some(albedo) = images.albedo {
+                    set_image(c"albedo", albedo.as_ptr() as *mut c_void);
+                    if let Some(normal) = images.normal {
+                        set_image(c"normal", normal.as_ptr() as *mut c_void);
+                    }
+                }
+                set_image(c"output", output.as_mut_ptr() as *mut c_void);
+                oidnSetFilter1b(filter, c"hdr".as_ptr(), true);
+
+                oidnCommitFilter(filter);
+                oidnExecuteFilter(filter);
+                oidnReleaseFilter(filter);
+            }
+            self.check_error()?;
+
+            Ok(output)
+        }
+    }
+
+    impl Drop for OidnDenoiser {
+        fn drop(&mut self) {
+            unsafe {
+                oidnReleaseDevice(self.device);
+            }
+        }
+    }
+}
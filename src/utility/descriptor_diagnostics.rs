@@ -0,0 +1,411 @@
+use ash::vk;
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+// Descriptor binding declared by a shader, read back from its SPIR-V
+#[derive(Clone, Copy, Debug)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    // None for resource types the reflection does not know
+    pub descriptor_type: Option<vk::DescriptorType>,
+    // 0 for runtime sized arrays
+    pub count: u32,
+    pub stage: vk::ShaderStageFlags,
+}
+
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+
+pub(crate) fn execution_model_stage(execution_model: u32) -> vk::ShaderStageFlags {
+    match execution_model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        5313 => vk::ShaderStageFlags::RAYGEN_NV,
+        5314 => vk::ShaderStageFlags::INTERSECTION_NV,
+        5315 => vk::ShaderStageFlags::ANY_HIT_NV,
+        5316 => vk::ShaderStageFlags::CLOSEST_HIT_NV,
+        5317 => vk::ShaderStageFlags::MISS_NV,
+        5318 => vk::ShaderStageFlags::CALLABLE_NV,
+        _ => vk::ShaderStageFlags::empty(),
+    }
+}
+
+/// Descriptor bindings of every resource variable in a SPIR-V module. A library with several
+/// entry points reports the union of their stages for each binding.
+pub fn reflect_bindings(code: &[u32]) -> Vec<ReflectedBinding> {
+    let mut stage = vk::ShaderStageFlags::empty();
+    let mut decorations: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut types: HashMap<u32, (u32, Vec<u32>)> = HashMap::new();
+    let mut constants: HashMap<u32, u32> = HashMap::new();
+    let mut variables = Vec::new();
+
+    // Skip the 5 word header
+    let mut position = 5;
+    while position < code.len() {
+        let word_count = (code[position] >> 16) as usize;
+        let opcode = code[position] & 0xffff;
+        if word_count == 0 || position + word_count > code.len() {
+            break;
+        }
+        let operands = &code[position + 1..position + word_count];
+
+        match opcode {
+            OP_ENTRY_POINT => stage |= execution_model_stage(operands[0]),
+            OP_DECORATE => {
+                let value = operands.get(2).copied().unwrap_or(0);
+                decorations.insert((operands[0], operands[1]), value);
+            }
+            OP_CONSTANT => {
+                constants.insert(operands[1], operands[2]);
+            }
+            OP_VARIABLE => variables.push((operands[0], operands[1], operands[2])),
+            OP_TYPE_IMAGE
+            | OP_TYPE_SAMPLER
+            | OP_TYPE_SAMPLED_IMAGE
+            | OP_TYPE_ARRAY
+            | OP_TYPE_RUNTIME_ARRAY
+            | OP_TYPE_STRUCT
+            | OP_TYPE_POINTER
+            | OP_TYPE_ACCELERATION_STRUCTURE => {
+                types.insert(operands[0], (opcode, operands[1..].to_vec()));
+            }
+            _ => {}
+        }
+
+        position += word_count;
+    }
+
+    let mut bindings = Vec::new();
+    for (pointer_type, id, storage_class) in variables {
+        let (set, binding) = match (
+            decorations.get(&(id, DECORATION_DESCRIPTOR_SET)),
+            decorations.get(&(id, DECORATION_BINDING)),
+        ) {
+            (Some(&set), Some(&binding)) => (set, binding),
+            _ => continue,
+        };
+
+        let mut type_id = match types.get(&pointer_type) {
+            Some((OP_TYPE_POINTER, operands)) => operands[1],
+            _ => continue,
+        };
+
+        // Arrays of resources are one binding with several descriptors
+        let mut count = 1;
+        match types.get(&type_id) {
+            Some((OP_TYPE_ARRAY, operands)) => {
+                count = constants.get(&operands[1]).copied().unwrap_or(1);
+                type_id = operands[0];
+            }
+            Some((OP_TYPE_RUNTIME_ARRAY, operands)) => {
+                count = 0;
+                type_id = operands[0];
+            }
+            _ => {}
+        }
+
+        let descriptor_type = match (storage_class, types.get(&type_id)) {
+            (STORAGE_CLASS_STORAGE_BUFFER, _) => Some(vk::DescriptorType::STORAGE_BUFFER),
+            (STORAGE_CLASS_UNIFORM, Some((OP_TYPE_STRUCT, _))) => {
+                if decorations.contains_key(&(type_id, DECORATION_BUFFER_BLOCK)) {
+                    Some(vk::DescriptorType::STORAGE_BUFFER)
+                } else if decorations.contains_key(&(type_id, DECORATION_BLOCK)) {
+                    Some(vk::DescriptorType::UNIFORM_BUFFER)
+                } else {
+                    None
+                }
+            }
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Some((opcode, operands))) => match *opcode {
+                OP_TYPE_SAMPLER => Some(vk::DescriptorType::SAMPLER),
+                OP_TYPE_SAMPLED_IMAGE => Some(vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+                OP_TYPE_ACCELERATION_STRUCTURE => {
+                    Some(vk::DescriptorType::ACCELERATION_STRUCTURE_NV)
+                }
+                // operands: sampled type, dim, depth, arrayed, ms, sampled, format
+                OP_TYPE_IMAGE => Some(match (operands[1] == DIM_BUFFER, operands[5] == 2) {
+                    (true, true) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                    (true, false) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                    (false, true) => vk::DescriptorType::STORAGE_IMAGE,
+                    (false, false) => vk::DescriptorType::SAMPLED_IMAGE,
+                }),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        bindings.push(ReflectedBinding {
+            set,
+            binding,
+            descriptor_type,
+            count,
+            stage,
+        });
+    }
+
+    bindings.sort_by_key(|binding| (binding.set, binding.binding));
+    bindings
+}
+
+// What was handed to Vulkan for one descriptor set, next to what its shaders declare, so a
+// validation error can be traced back to the binding that does not line up
+pub struct DescriptorDiagnostics {
+    label: String,
+    set: u32,
+    layout_bindings: Vec<(u32, vk::DescriptorType, u32, vk::ShaderStageFlags)>,
+    pool_sizes: Vec<(vk::DescriptorType, u32)>,
+    writes: Vec<(u32, vk::DescriptorType, u32)>,
+    shaders: Vec<(String, Vec<ReflectedBinding>)>,
+}
+
+impl DescriptorDiagnostics {
+    pub fn new(label: &str, set: u32) -> DescriptorDiagnostics {
+        DescriptorDiagnostics {
+            label: label.to_string(),
+            set,
+            layout_bindings: Vec::new(),
+            pool_sizes: Vec::new(),
+            writes: Vec::new(),
+            shaders: Vec::new(),
+        }
+    }
+
+    pub fn set_layout_bindings(&mut self, bindings: &[vk::DescriptorSetLayoutBinding]) {
+        self.layout_bindings = bindings
+            .iter()
+            .map(|binding| {
+                (
+                    binding.binding,
+                    binding.descriptor_type,
+                    binding.descriptor_count,
+                    binding.stage_flags,
+                )
+            })
+            .collect();
+    }
+
+    pub fn layout_bindings(&self) -> Vec<vk::DescriptorSetLayoutBinding> {
+        self.layout_bindings
+            .iter()
+            .map(
+                |&(binding, descriptor_type, descriptor_count, stage_flags)| {
+                    vk::DescriptorSetLayoutBinding {
+                        binding,
+                        descriptor_type,
+                        descriptor_count,
+                        stage_flags,
+                        ..Default::default()
+                    }
+                },
+            )
+            .collect()
+    }
+
+    pub fn set_pool_sizes(&mut self, pool_sizes: &[vk::DescriptorPoolSize]) {
+        self.pool_sizes = pool_sizes
+            .iter()
+            .map(|size| (size.ty, size.descriptor_count))
+            .collect();
+    }
+
+    pub fn set_writes(&mut self, writes: &[vk::WriteDescriptorSet]) {
+        self.writes = writes
+            .iter()
+            .map(|write| {
+                (
+                    write.dst_binding,
+                    write.descriptor_type,
+                    write.descriptor_count,
+                )
+            })
+            .collect();
+    }
+
+    // Only the bindings of the set the layout describes are compared
+    pub fn add_shader(&mut self, name: &str, code: &[u32]) {
+        let set = self.set;
+        let bindings = reflect_bindings(code)
+            .into_iter()
+            .filter(|binding| binding.set == set)
+            .collect();
+        self.shaders.push((name.to_string(), bindings));
+    }
+
+    pub fn clear_shaders(&mut self) {
+        self.shaders.clear();
+    }
+
+    /// Shader bindings the layout does not have the way they are declared, known as soon as the
+    /// layout and the shaders are, before anything is written
+    pub fn shader_mismatches(&self) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        for (name, bindings) in &self.shaders {
+            for reflected in bindings {
+                let layout = self
+                    .layout_bindings
+                    .iter()
+                    .find(|layout| layout.0 == reflected.binding);
+                match layout {
+                    None => mismatches.push(format!(
+                        "{} uses binding {} which is missing from the layout",
+                        name, reflected.binding
+                    )),
+                    Some(&(_, descriptor_type, _, stage_flags)) => {
+                        if let Some(reflected_type) = reflected.descriptor_type {
+                            if reflected_type != descriptor_type {
+                                mismatches.push(format!(
+                                    "{} declares binding {} as {:?} but it is laid out as {:?}",
+                                    name, reflected.binding, reflected_type, descriptor_type
+                                ));
+                            }
+                        }
+                        // A library with several entry points only needs it in one of their stages
+                        if !stage_flags.intersects(reflected.stage) {
+                            mismatches.push(format!(
+                                "{} ({:?}) uses binding {} whose stages are {:?}",
+                                name, reflected.stage, reflected.binding, stage_flags
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        mismatches
+    }
+
+    /// Bindings that will fail validation or read garbage, one line each
+    pub fn mismatches(&self) -> Vec<String> {
+        let mut mismatches = Vec::new();
+
+        for &(binding, descriptor_type, count, _) in &self.layout_bindings {
+            match self.writes.iter().find(|write| write.0 == binding) {
+                None => mismatches.push(format!("binding {} is never written", binding)),
+                Some(&(_, write_type, write_count)) => {
+                    if write_type != descriptor_type {
+                        mismatches.push(format!(
+                            "binding {} is written as {:?} but laid out as {:?}",
+                            binding, write_type, descriptor_type
+                        ));
+                    }
+                    if write_count > count {
+                        mismatches.push(format!(
+                            "binding {} is written with {} descriptors but holds {}",
+                            binding, write_count, count
+                        ));
+                    }
+                }
+            }
+        }
+
+        mismatches.extend(self.shader_mismatches());
+
+        // Every layout binding has to fit into the pool
+        let mut required: Vec<(vk::DescriptorType, u32)> = Vec::new();
+        for &(_, descriptor_type, count, _) in &self.layout_bindings {
+            match required.iter_mut().find(|entry| entry.0 == descriptor_type) {
+                Some(entry) => entry.1 += count,
+                None => required.push((descriptor_type, count)),
+            }
+        }
+        for (descriptor_type, count) in required {
+            let available: u32 = self
+                .pool_sizes
+                .iter()
+                .filter(|size| size.0 == descriptor_type)
+                .map(|size| size.1)
+                .sum();
+            if available < count {
+                mismatches.push(format!(
+                    "the pool holds {} {:?} descriptors but the layout needs {}",
+                    available, descriptor_type, count
+                ));
+            }
+        }
+
+        mismatches
+    }
+}
+
+impl fmt::Display for DescriptorDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Descriptor table of {}:", self.label)?;
+
+        let mut bindings: BTreeSet<u32> =
+            self.layout_bindings.iter().map(|layout| layout.0).collect();
+        bindings.extend(self.writes.iter().map(|write| write.0));
+        for (_, reflected) in &self.shaders {
+            bindings.extend(reflected.iter().map(|binding| binding.binding));
+        }
+
+        for binding in bindings {
+            writeln!(f, " binding {}:", binding)?;
+            match self
+                .layout_bindings
+                .iter()
+                .find(|layout| layout.0 == binding)
+            {
+                Some((_, descriptor_type, count, stage_flags)) => writeln!(
+                    f,
+                    "  layout: {:?} x{} [{:?}]",
+                    descriptor_type, count, stage_flags
+                )?,
+                None => writeln!(f, "  layout: -")?,
+            }
+            match self.writes.iter().find(|write| write.0 == binding) {
+                Some((_, descriptor_type, count)) => {
+                    writeln!(f, "  write:  {:?} x{}", descriptor_type, count)?
+                }
+                None => writeln!(f, "  write:  -")?,
+            }
+            for (name, reflected) in &self.shaders {
+                if let Some(reflected) = reflected.iter().find(|b| b.binding == binding) {
+                    let descriptor_type = reflected
+                        .descriptor_type
+                        .map_or(String::from("unknown"), |ty| format!("{:?}", ty));
+                    writeln!(
+                        f,
+                        "  {}: {} x{} [{:?}]",
+                        name, descriptor_type, reflected.count, reflected.stage
+                    )?;
+                }
+            }
+        }
+
+        writeln!(f, " pool sizes:")?;
+        for (descriptor_type, count) in &self.pool_sizes {
+            writeln!(f, "  {:?} x{}", descriptor_type, count)?;
+        }
+
+        let mismatches = self.mismatches();
+        if !mismatches.is_empty() {
+            writeln!(f, " mismatches:")?;
+            for mismatch in mismatches {
+                writeln!(f, "  {}", mismatch)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use ash::vk;
+
+enum WriteInfo {
+    NvAccelerationStructures(Vec<vk::AccelerationStructureNV>),
+    KhrAccelerationStructures(Vec<vk::AccelerationStructureKHR>),
+    Images(Vec<vk::DescriptorImageInfo>),
+    Buffers(Vec<vk::DescriptorBufferInfo>),
+}
+
+impl WriteInfo {
+    fn descriptor_count(&self) -> u32 {
+        (match self {
+            WriteInfo::NvAccelerationStructures(handles) => handles.len(),
+            WriteInfo::KhrAccelerationStructures(handles) => handles.len(),
+            WriteInfo::Images(infos) => infos.len(),
+            WriteInfo::Buffers(infos) => infos.len(),
+        }) as u32
+    }
+}
+
+// The writes of one descriptor set, each from element 0 of its binding. The descriptor type and
+// count of a write follow from what is bound, and are checked against the set's layout as it is
+// bound: a binding missing from the layout, of another type or with fewer descriptors panics.
+pub struct WriteBatch {
+    // Binding, descriptor type and count
+    layout: Vec<(u32, vk::DescriptorType, u32)>,
+    writes: Vec<(u32, vk::DescriptorType, WriteInfo)>,
+}
+
+impl WriteBatch {
+    pub fn new(layout_bindings: &[vk::DescriptorSetLayoutBinding]) -> WriteBatch {
+        WriteBatch {
+            layout: layout_bindings
+                .iter()
+                .map(|binding| {
+                    (
+                        binding.binding,
+                        binding.descriptor_type,
+                        binding.descriptor_count,
+                    )
+                })
+                .collect(),
+            writes: Vec::new(),
+        }
+    }
+
+    pub fn bind_acceleration_structure(
+        &mut self,
+        binding: u32,
+        acceleration_structure: vk::AccelerationStructureNV,
+    ) -> &mut WriteBatch {
+        self.push(
+            binding,
+            vk::DescriptorType::ACCELERATION_STRUCTURE_NV,
+            WriteInfo::NvAccelerationStructures(vec![acceleration_structure]),
+        )
+    }
+
+    pub fn bind_khr_acceleration_structure(
+        &mut self,
+        binding: u32,
+        acceleration_structure: vk::AccelerationStructureKHR,
+    ) -> &mut WriteBatch {
+        self.push(
+            binding,
+            vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            WriteInfo::KhrAccelerationStructures(vec![acceleration_structure]),
+        )
+    }
+
+    // In the GENERAL layout
+    pub fn bind_storage_image(&mut self, binding: u32, view: vk::ImageView) -> &mut WriteBatch {
+        self.bind_storage_images(binding, &[view])
+    }
+
+    pub fn bind_storage_images(
+        &mut self,
+        binding: u32,
+        views: &[vk::ImageView],
+    ) -> &mut WriteBatch {
+        let infos = views
+            .iter()
+            .map(|&image_view| vk::DescriptorImageInfo {
+                image_layout: vk::ImageLayout::GENERAL,
+                image_view,
+                ..Default::default()
+            })
+            .collect();
+        self.push(
+            binding,
+            vk::DescriptorType::STORAGE_IMAGE,
+            WriteInfo::Images(infos),
+        )
+    }
+
+    pub fn bind_sampled_image(
+        &mut self,
+        binding: u32,
+        sampler: vk::Sampler,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+    ) -> &mut WriteBatch {
+        self.push(
+            binding,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            WriteInfo::Images(vec![vk::DescriptorImageInfo {
+                sampler,
+                image_view: view,
+                image_layout: layout,
+            }]),
+        )
+    }
+
+    // The whole buffer
+    pub fn bind_storage_buffer(&mut self, binding: u32, buffer: vk::Buffer) -> &mut WriteBatch {
+        self.bind_buffers(binding, vk::DescriptorType::STORAGE_BUFFER, &[buffer])
+    }
+
+    pub fn bind_storage_array(&mut self, binding: u32, buffers: &[vk::Buffer]) -> &mut WriteBatch {
+        self.bind_buffers(binding, vk::DescriptorType::STORAGE_BUFFER, buffers)
+    }
+
+    pub fn bind_uniform_buffer(&mut self, binding: u32, buffer: vk::Buffer) -> &mut WriteBatch {
+        self.bind_buffers(binding, vk::DescriptorType::UNIFORM_BUFFER, &[buffer])
+    }
+
+    pub fn bind_uniform_array(&mut self, binding: u32, buffers: &[vk::Buffer]) -> &mut WriteBatch {
+        self.bind_buffers(binding, vk::DescriptorType::UNIFORM_BUFFER, buffers)
+    }
+
+    fn bind_buffers(
+        &mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        buffers: &[vk::Buffer],
+    ) -> &mut WriteBatch {
+        let infos = buffers
+            .iter()
+            .map(|&buffer| vk::DescriptorBufferInfo {
+                buffer,
+                offset: 0,
+                range: vk::WHOLE_SIZE,
+            })
+            .collect();
+        self.push(binding, descriptor_type, WriteInfo::Buffers(infos))
+    }
+
+    fn push(
+        &mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        info: WriteInfo,
+    ) -> &mut WriteBatch {
+        let count = info.descriptor_count();
+        match self.layout.iter().find(|layout| layout.0 == binding) {
+            None => panic!("Descriptor binding {} is missing from the layout.", binding),
+            Some(&(_, layout_type, layout_count)) => {
+                if layout_type != descriptor_type || count > layout_count {
+                    panic!(
+                        "Descriptor binding {} is laid out as {:?} x{} but written as {:?} x{}.",
+                        binding, layout_type, layout_count, descriptor_type, count
+                    );
+                }
+            }
+        }
+        self.writes.retain(|write| write.0 != binding);
+        self.writes.push((binding, descriptor_type, info));
+        self
+    }
+
+    // Hands the writes into `set` to `f`, they point into the batch and the locals of this call
+    pub fn with_writes<R>(
+        &self,
+        set: vk::DescriptorSet,
+        f: impl FnOnce(&[vk::WriteDescriptorSet]) -> R,
+    ) -> R {
+        let mut nv_infos: Vec<vk::WriteDescriptorSetAccelerationStructureNV> = self
+            .writes
+            .iter()
+            .filter_map(|(_, _, info)| match info {
+                WriteInfo::NvAccelerationStructures(handles) => Some(
+                    vk::WriteDescriptorSetAccelerationStructureNV::builder()
+                        .acceleration_structures(handles)
+                        .build(),
+                ),
+                _ => None,
+            })
+            .collect();
+        let mut khr_infos: Vec<vk::WriteDescriptorSetAccelerationStructureKHR> = self
+            .writes
+            .iter()
+            .filter_map(|(_, _, info)| match info {
+                WriteInfo::KhrAccelerationStructures(handles) => Some(
+                    vk::WriteDescriptorSetAccelerationStructureKHR::builder()
+                        .acceleration_structures(handles)
+                        .build(),
+                ),
+                _ => None,
+            })
+            .collect();
+
+        let mut nv_infos = nv_infos.iter_mut();
+        let mut khr_infos = khr_infos.iter_mut();
+        let writes: Vec<vk::WriteDescriptorSet> = self
+            .writes
+            .iter()
+            .map(|(binding, descriptor_type, info)| {
+                let builder = vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(*binding)
+                    .dst_array_element(0)
+                    .descriptor_type(*descriptor_type);
+                let mut write = match info {
+                    WriteInfo::NvAccelerationStructures(_) => {
+                        builder.push_next(nv_infos.next().unwrap()).build()
+                    }
+                    WriteInfo::KhrAccelerationStructures(_) => {
+                        builder.push_next(khr_infos.next().unwrap()).build()
+                    }
+                    WriteInfo::Images(infos) => builder.image_info(infos).build(),
+                    WriteInfo::Buffers(infos) => builder.buffer_info(infos).build(),
+                };
+                // Only the info arrays set it, the acceleration structures are in p_next
+                write.descriptor_count = info.descriptor_count();
+                write
+            })
+            .collect();
+        f(&writes)
+    }
+
+    pub fn update(&self, device: &ash::Device, set: vk::DescriptorSet) {
+        self.with_writes(set, |writes| unsafe {
+            device.update_descriptor_sets(writes, &[]);
+        });
+    }
+}
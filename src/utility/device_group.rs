@@ -0,0 +1,53 @@
+use ash::vk;
+
+// The physical devices of the first device group with `physical_device` and at least one other
+// device, at most `max_devices` of them with `physical_device` first. Empty when there is none.
+pub fn find_device_group(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    max_devices: usize,
+) -> Vec<vk::PhysicalDevice> {
+    let groups = unsafe {
+        let count = instance.enumerate_physical_device_groups_len().unwrap_or(0);
+        let mut groups = vec![vk::PhysicalDeviceGroupProperties::default(); count];
+        if instance
+            .enumerate_physical_device_groups(&mut groups)
+            .is_err()
+        {
+            return Vec::new();
+        }
+        groups
+    };
+
+    groups
+        .iter()
+        .map(|group| &group.physical_devices[..group.physical_device_count as usize])
+        .find(|devices| devices.len() > 1 && devices.contains(&physical_device))
+        .map(|devices| {
+            let mut devices: Vec<vk::PhysicalDevice> = devices.to_vec();
+            devices.retain(|device| *device != physical_device);
+            devices.insert(0, physical_device);
+            devices.truncate(max_devices);
+            devices
+        })
+        .unwrap_or_default()
+}
+
+// Whether device `from` of the group can copy into the instance of `heap_index` memory that
+// lives on device `to`
+pub fn can_copy_to(device: &ash::Device, heap_index: u32, from: u32, to: u32) -> bool {
+    let features = unsafe { device.get_device_group_peer_memory_features(heap_index, from, to) };
+    features.contains(vk::PeerMemoryFeatureFlags::COPY_DST)
+}
+
+// Splits the x, y, width, height pixel rect into `count` bands of rows from the top, as even as
+// the height allows
+pub fn split_rows([x, y, width, height]: [u32; 4], count: u32) -> Vec<[u32; 4]> {
+    (0..count)
+        .map(|band| {
+            let top = height * band / count;
+            let bottom = height * (band + 1) / count;
+            [x, y + top, width, bottom - top]
+        })
+        .collect()
+}
@@ -0,0 +1,89 @@
+use std::ops::Range;
+
+// Element ranges of a host copy that changed since the last upload. Overlapping and adjacent
+// ranges are merged when they are marked, so taking them yields the fewest separate writes.
+#[derive(Clone, Debug, Default)]
+pub struct DirtyRanges {
+    // Sorted by start and never touching each other
+    ranges: Vec<Range<usize>>,
+}
+
+impl DirtyRanges {
+    pub fn new() -> DirtyRanges {
+        DirtyRanges { ranges: Vec::new() }
+    }
+
+    pub fn mark(&mut self, index: usize) {
+        self.mark_range(index..index + 1);
+    }
+
+    pub fn mark_range(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+
+        // First range that ends at or after the new start, everything before it stays separate
+        let first = self.ranges.partition_point(|dirty| dirty.end < range.start);
+        let mut merged = range;
+        let mut last = first;
+        while last < self.ranges.len() && self.ranges[last].start <= merged.end {
+            merged.start = merged.start.min(self.ranges[last].start);
+            merged.end = merged.end.max(self.ranges[last].end);
+            last += 1;
+        }
+        self.ranges.splice(first..last, std::iter::once(merged));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    // Number of elements that would be uploaded
+    pub fn len(&self) -> usize {
+        self.ranges.iter().map(|range| range.len()).sum()
+    }
+
+    pub fn take(&mut self) -> Vec<Range<usize>> {
+        std::mem::take(&mut self.ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_and_adjacent_ranges_merge() {
+        let mut dirty = DirtyRanges::new();
+        dirty.mark_range(4..8);
+        dirty.mark_range(6..10);
+        dirty.mark(10);
+        dirty.mark_range(2..4);
+        assert_eq!(dirty.len(), 9);
+        let ranges = dirty.take();
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0], 2..11);
+        assert!(dirty.is_empty());
+    }
+
+    #[test]
+    fn separate_ranges_stay_sorted_and_apart() {
+        let mut dirty = DirtyRanges::new();
+        dirty.mark(20);
+        dirty.mark_range(0..2);
+        dirty.mark_range(10..12);
+        dirty.mark_range(5..5);
+        assert_eq!(dirty.len(), 5);
+        assert_eq!(dirty.take(), [0..2, 10..12, 20..21]);
+    }
+
+    #[test]
+    fn a_range_spanning_the_gaps_joins_them() {
+        let mut dirty = DirtyRanges::new();
+        for index in [1, 3, 5, 9] {
+            dirty.mark(index);
+        }
+        dirty.mark_range(2..6);
+        assert_eq!(dirty.take(), [1..6, 9..10]);
+    }
+}
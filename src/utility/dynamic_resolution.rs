@@ -0,0 +1,82 @@
+use super::constants::*;
+use super::structures::RenderSettings;
+
+// Feedback controller holding a target frame rate by trading render scale and samples per pixel
+// for GPU frame time. Frame times are smoothed, nothing changes while they stay within the
+// hysteresis band around the target, and every change is given a few frames to settle before
+// the next one. Over budget the samples go first, then the scale; under budget the scale comes
+// back first. Neither ever exceeds what was asked for when the controller was created.
+pub struct DynamicResolution {
+    // Milliseconds
+    target_frame_time: f32,
+    max_render_scale: f32,
+    max_samples_per_pixel: u32,
+    smoothed_frame_time: Option<f32>,
+    frames_since_change: u32,
+}
+
+impl DynamicResolution {
+    pub fn new(target_fps: f32, settings: &RenderSettings) -> DynamicResolution {
+        DynamicResolution {
+            target_frame_time: 1000.0 / target_fps,
+            max_render_scale: settings.render_scale,
+            max_samples_per_pixel: settings.samples_per_pixel.max(1),
+            smoothed_frame_time: None,
+            frames_since_change: 0,
+        }
+    }
+
+    pub fn smoothed_frame_time(&self) -> Option<f32> {
+        self.smoothed_frame_time
+    }
+
+    // Feeds the GPU time of one frame in milliseconds, returns the settings to apply when a
+    // change is due
+    pub fn update(
+        &mut self,
+        gpu_frame_time: f32,
+        settings: &RenderSettings,
+    ) -> Option<RenderSettings> {
+        let smoothed = match self.smoothed_frame_time {
+            Some(smoothed) => smoothed + (gpu_frame_time - smoothed) * DYNAMIC_RESOLUTION_SMOOTHING,
+            None => gpu_frame_time,
+        };
+        self.smoothed_frame_time = Some(smoothed);
+        self.frames_since_change += 1;
+        if self.frames_since_change < DYNAMIC_RESOLUTION_SETTLE_FRAMES {
+            return None;
+        }
+
+        // Cost is taken as proportional to the pixel count, which goes with the scale squared
+        let scale_for = |frame_time: f32| {
+            let scale = settings.render_scale * (self.target_frame_time / frame_time).sqrt();
+            let scale =
+                (scale / DYNAMIC_RESOLUTION_SCALE_STEP).round() * DYNAMIC_RESOLUTION_SCALE_STEP;
+            scale.clamp(DYNAMIC_RESOLUTION_MIN_SCALE, self.max_render_scale)
+        };
+
+        let mut next = *settings;
+        if smoothed > self.target_frame_time * (1.0 + DYNAMIC_RESOLUTION_HYSTERESIS) {
+            if settings.samples_per_pixel > 1 {
+                next.samples_per_pixel = settings.samples_per_pixel / 2;
+            } else {
+                next.render_scale = scale_for(smoothed).min(settings.render_scale);
+            }
+        } else if smoothed < self.target_frame_time * (1.0 - DYNAMIC_RESOLUTION_HYSTERESIS) {
+            if settings.render_scale < self.max_render_scale {
+                next.render_scale = scale_for(smoothed).max(settings.render_scale);
+            } else if settings.samples_per_pixel < self.max_samples_per_pixel {
+                next.samples_per_pixel =
+                    (settings.samples_per_pixel * 2).min(self.max_samples_per_pixel);
+            }
+        }
+
+        if next == *settings {
+            return None;
+        }
+        // The old measurements describe the old settings
+        self.smoothed_frame_time = None;
+        self.frames_since_change = 0;
+        Some(next)
+    }
+}
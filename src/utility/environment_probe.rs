@@ -0,0 +1,188 @@
+use cgmath::{InnerSpace, Vector3};
+
+// Forward and up of the camera capturing each cube face, in the order +x, -x, +y, -y, +z, -z.
+// Mirrors PROBE_FACE_FORWARD and PROBE_FACE_UP in shaders/src/shadowmap_shade.frag.
+pub const PROBE_FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+];
+
+// Keeps the lobe of a perfectly smooth surface finite, like MIN_GGX_ALPHA in brdf.glsl
+const MIN_GGX_ALPHA: f32 = 0.002;
+
+// (forward, right, up) of a face camera, with right = up x forward like Camera::basis
+fn face_basis(face: usize) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+    let (forward, up) = PROBE_FACES[face];
+    let (forward, up) = (Vector3::from(forward), Vector3::from(up));
+    (forward, up.cross(forward), up)
+}
+
+// Radiance around a point, `size` x `size` texels on each face in PROBE_FACES order. The rows
+// go down the face like those of a traced frame.
+#[derive(Clone, Debug)]
+pub struct CubeMap {
+    pub size: u32,
+    pub texels: Vec<Vector3<f32>>,
+}
+
+impl CubeMap {
+    // From one traced RGBA frame of `width` x `height` per face, taken with a 90 degree window
+    // on each axis. Every texel averages the pixels whose centers fall on it, or takes the
+    // nearest one when the frames are smaller than the faces.
+    pub fn from_faces(faces: &[Vec<f32>], width: u32, height: u32, size: u32) -> CubeMap {
+        assert_eq!(faces.len(), 6, "A cube map is captured from six faces");
+        let span = |texel: u32, pixels: u32| {
+            let start = texel * pixels / size;
+            let end = ((texel + 1) * pixels / size).max(start + 1);
+            start..end
+        };
+
+        let mut texels = Vec::with_capacity(6 * (size * size) as usize);
+        for pixels in faces {
+            for y in 0..size {
+                for x in 0..size {
+                    let mut sum = Vector3::new(0.0, 0.0, 0.0);
+                    let mut count = 0;
+                    for row in span(y, height) {
+                        for column in span(x, width) {
+                            let index = 4 * (row * width + column) as usize;
+                            sum +=
+                                Vector3::new(pixels[index], pixels[index + 1], pixels[index + 2]);
+                            count += 1;
+                        }
+                    }
+                    texels.push(sum / count as f32);
+                }
+            }
+        }
+        CubeMap { size, texels }
+    }
+
+    fn texel(&self, face: usize, x: u32, y: u32) -> Vector3<f32> {
+        self.texels[face * (self.size * self.size) as usize + (y * self.size + x) as usize]
+    }
+
+    // Window coordinates in [-1, 1] of the texel center, v going up
+    fn texel_window(&self, x: u32, y: u32) -> (f32, f32) {
+        let size = self.size as f32;
+        (
+            2.0 * (x as f32 + 0.5) / size - 1.0,
+            1.0 - 2.0 * (y as f32 + 0.5) / size,
+        )
+    }
+
+    // Unit direction through the center of a texel
+    fn texel_direction(&self, face: usize, x: u32, y: u32) -> Vector3<f32> {
+        let (forward, right, up) = face_basis(face);
+        let (u, v) = self.texel_window(x, y);
+        (forward + right * u + up * v).normalize()
+    }
+
+    // Solid angle a texel covers, from the area of its window projected onto the unit sphere
+    fn texel_solid_angle(&self, x: u32, y: u32) -> f32 {
+        let area = |u: f32, v: f32| (u * v).atan2((u * u + v * v + 1.0).sqrt());
+        let (u, v) = self.texel_window(x, y);
+        let half = 1.0 / self.size as f32;
+        area(u - half, v - half) - area(u - half, v + half) - area(u + half, v - half)
+            + area(u + half, v + half)
+    }
+
+    // Half the size, every texel the average of the four it covers
+    pub fn downsample(&self) -> CubeMap {
+        let size = (self.size / 2).max(1);
+        let scale = self.size / size;
+        let mut texels = Vec::with_capacity(6 * (size * size) as usize);
+        for face in 0..6 {
+            for y in 0..size {
+                for x in 0..size {
+                    let mut sum = Vector3::new(0.0, 0.0, 0.0);
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            sum += self.texel(face, x * scale + dx, y * scale + dy);
+                        }
+                    }
+                    texels.push(sum / (scale * scale) as f32);
+                }
+            }
+        }
+        CubeMap { size, texels }
+    }
+
+    // Radiance reflected by GGX microfacets of `roughness`, with the view along the normal like
+    // the split sum approximation assumes. Integrates over every texel, which is only cheap on
+    // the small maps the rough levels are made from.
+    pub fn prefilter(&self, roughness: f32) -> CubeMap {
+        let alpha = (roughness * roughness).max(MIN_GGX_ALPHA);
+        let alpha2 = alpha * alpha;
+        let mut sources = Vec::with_capacity(self.texels.len());
+        for face in 0..6 {
+            for y in 0..self.size {
+                for x in 0..self.size {
+                    sources.push((
+                        self.texel_direction(face, x, y),
+                        self.texel_solid_angle(x, y),
+                        self.texel(face, x, y),
+                    ));
+                }
+            }
+        }
+
+        let mut texels = Vec::with_capacity(self.texels.len());
+        for face in 0..6 {
+            for y in 0..self.size {
+                for x in 0..self.size {
+                    let normal = self.texel_direction(face, x, y);
+                    let mut sum = Vector3::new(0.0, 0.0, 0.0);
+                    let mut weight_sum = 0.0;
+                    for &(light, solid_angle, radiance) in &sources {
+                        let n_dot_l = normal.dot(light);
+                        if n_dot_l <= 0.0 {
+                            continue;
+                        }
+                        // With the view along the normal the half vector pdf is D / 4 over the
+                        // light directions, the 1 / (4 pi) cancels in the normalization
+                        let n_dot_h = normal.dot((normal + light).normalize());
+                        let denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+                        let distribution = alpha2 / (denominator * denominator);
+                        let weight = distribution * n_dot_l * solid_angle;
+                        sum += radiance * weight;
+                        weight_sum += weight;
+                    }
+                    texels.push(if weight_sum > 0.0 {
+                        sum / weight_sum
+                    } else {
+                        self.texel(face, x, y)
+                    });
+                }
+            }
+        }
+        CubeMap {
+            size: self.size,
+            texels,
+        }
+    }
+
+    // `levels` maps halving in size, level n prefiltered for roughness n / (levels - 1) from this
+    // map box filtered down to its size. Level 0 is this map as is.
+    pub fn prefilter_levels(&self, levels: u32) -> Vec<CubeMap> {
+        let mut maps = vec![self.clone()];
+        let mut source = self.clone();
+        for level in 1..levels {
+            source = source.downsample();
+            maps.push(source.prefilter(level as f32 / (levels - 1) as f32));
+        }
+        maps
+    }
+
+    // RGBA texels with an alpha of 1, face after face
+    pub fn to_rgba(&self) -> Vec<f32> {
+        self.texels
+            .iter()
+            .flat_map(|texel| [texel.x, texel.y, texel.z, 1.0])
+            .collect()
+    }
+}
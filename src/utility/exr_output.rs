@@ -0,0 +1,69 @@
+use std::path::Path;
+
+use exr::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+// One layer of an EXR file. `samples` holds every pixel's channels interleaved in the order of
+// `channel_names`, rows from top to bottom.
+pub struct ExrLayer<'a> {
+    pub name: &'a str,
+    pub channel_names: &'a [&'a str],
+    pub samples: &'a [f32],
+    pub precision: ExrPrecision,
+}
+
+// Writes the layers as the parts of one multi-layer EXR file, all of them `width` x `height`
+pub fn write_exr(path: &Path, width: u32, height: u32, layers: &[ExrLayer]) -> Result<()> {
+    let size = Vec2(width as usize, height as usize);
+
+    let layers: Vec<Layer<AnyChannels<FlatSamples>>> = layers
+        .iter()
+        .map(|layer| {
+            let channel_count = layer.channel_names.len();
+            assert_eq!(
+                layer.samples.len(),
+                size.area() * channel_count,
+                "EXR layer {} does not match the image size.",
+                layer.name
+            );
+
+            let channels = layer
+                .channel_names
+                .iter()
+                .enumerate()
+                .map(|(channel, name)| {
+                    let values = layer
+                        .samples
+                        .iter()
+                        .skip(channel)
+                        .step_by(channel_count)
+                        .copied();
+                    let samples = match layer.precision {
+                        ExrPrecision::Half => FlatSamples::F16(values.map(f16::from_f32).collect()),
+                        ExrPrecision::Float => FlatSamples::F32(values.collect()),
+                    };
+                    AnyChannel::new(*name, samples)
+                })
+                .collect();
+
+            Layer::new(
+                size,
+                LayerAttributes::named(layer.name),
+                Encoding::FAST_LOSSLESS,
+                AnyChannels::sort(channels),
+            )
+        })
+        .collect();
+
+    Image::from_layers(
+        ImageAttributes::new(IntegerBounds::from_dimensions(size)),
+        layers,
+    )
+    .write()
+    .to_file(path)
+}
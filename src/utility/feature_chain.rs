@@ -0,0 +1,425 @@
+use crate::utility::low_latency::LowLatency;
+use crate::utility::tools::vk_to_string;
+
+use ash::extensions::khr;
+use ash::vk;
+
+use std::ffi::{c_char, CStr};
+
+// Device features enabled through the pNext chain of VkDeviceCreateInfo
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceFeature {
+    // Variable count descriptor arrays of the textures
+    DescriptorIndexing,
+    ScalarBlockLayout,
+    PipelineCreationCacheControl,
+    Synchronization2,
+    BufferDeviceAddress,
+    AccelerationStructure,
+    RayQuery,
+    RayTracingPipeline,
+    // Hardware counter queries of the GPU profiler
+    PerformanceQuery,
+    // Debug printf instructions in shaders, no feature struct
+    ShaderNonSemanticInfo,
+    // Actual present times of the swapchain images, no feature struct
+    DisplayTiming,
+    PresentId,
+    // Waiting on the host for a present id to reach the display
+    PresentWait,
+    TimelineSemaphore,
+    // VK_NV_low_latency2 sleeps and markers of the low latency mode, no feature struct
+    LowLatency,
+}
+
+impl DeviceFeature {
+    pub const ALL: [DeviceFeature; 15] = [
+        DeviceFeature::DescriptorIndexing,
+        DeviceFeature::ScalarBlockLayout,
+        DeviceFeature::PipelineCreationCacheControl,
+        DeviceFeature::Synchronization2,
+        DeviceFeature::BufferDeviceAddress,
+        DeviceFeature::AccelerationStructure,
+        DeviceFeature::RayQuery,
+        DeviceFeature::RayTracingPipeline,
+        DeviceFeature::PerformanceQuery,
+        DeviceFeature::ShaderNonSemanticInfo,
+        DeviceFeature::DisplayTiming,
+        DeviceFeature::PresentId,
+        DeviceFeature::PresentWait,
+        DeviceFeature::TimelineSemaphore,
+        DeviceFeature::LowLatency,
+    ];
+
+    // Extensions enabled along with the feature, on top of DEVICE_EXTENSIONS. The others are core
+    // in Vulkan 1.3.
+    pub fn extensions(self) -> Vec<&'static CStr> {
+        match self {
+            DeviceFeature::AccelerationStructure => vec![
+                khr::AccelerationStructure::name(),
+                khr::DeferredHostOperations::name(),
+            ],
+            DeviceFeature::RayQuery => vec![vk::KhrRayQueryFn::name()],
+            DeviceFeature::RayTracingPipeline => vec![khr::RayTracingPipeline::name()],
+            DeviceFeature::PerformanceQuery => vec![vk::KhrPerformanceQueryFn::name()],
+            DeviceFeature::ShaderNonSemanticInfo => vec![vk::KhrShaderNonSemanticInfoFn::name()],
+            DeviceFeature::DisplayTiming => vec![vk::GoogleDisplayTimingFn::name()],
+            DeviceFeature::PresentId => vec![vk::KhrPresentIdFn::name()],
+            DeviceFeature::PresentWait => vec![khr::PresentWait::name()],
+            DeviceFeature::LowLatency => vec![LowLatency::name()],
+            _ => Vec::new(),
+        }
+    }
+
+    // Features that have to be enabled for this one to be usable
+    pub fn dependencies(self) -> &'static [DeviceFeature] {
+        match self {
+            DeviceFeature::AccelerationStructure => &[
+                DeviceFeature::BufferDeviceAddress,
+                DeviceFeature::DescriptorIndexing,
+            ],
+            DeviceFeature::RayQuery | DeviceFeature::RayTracingPipeline => {
+                &[DeviceFeature::AccelerationStructure]
+            }
+            DeviceFeature::PresentWait => &[DeviceFeature::PresentId],
+            DeviceFeature::LowLatency => {
+                &[DeviceFeature::PresentId, DeviceFeature::TimelineSemaphore]
+            }
+            _ => &[],
+        }
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceFeatures(u32);
+
+impl DeviceFeatures {
+    pub fn contains(self, feature: DeviceFeature) -> bool {
+        self.0 & feature.bit() != 0
+    }
+
+    pub fn insert(&mut self, feature: DeviceFeature) {
+        self.0 |= feature.bit();
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = DeviceFeature> {
+        DeviceFeature::ALL
+            .into_iter()
+            .filter(move |&feature| self.contains(feature))
+    }
+}
+
+// Collects the feature structs for device creation. Requests are checked against what the
+// physical device reports when they are made: required features panic when they are missing,
+// optional ones are left out together with everything depending on them. Dependencies are
+// enabled along with a feature, and the feature structs of extensions are only chained when the
+// extension is enabled.
+pub struct FeatureChain {
+    supported: DeviceFeatures,
+    enabled: DeviceFeatures,
+
+    descriptor_indexing: vk::PhysicalDeviceDescriptorIndexingFeatures,
+    scalar_block_layout: vk::PhysicalDeviceScalarBlockLayoutFeatures,
+    pipeline_creation_cache_control: vk::PhysicalDevicePipelineCreationCacheControlFeatures,
+    synchronization2: vk::PhysicalDeviceSynchronization2Features,
+    buffer_device_address: vk::PhysicalDeviceBufferDeviceAddressFeatures,
+    acceleration_structure: vk::PhysicalDeviceAccelerationStructureFeaturesKHR,
+    ray_query: vk::PhysicalDeviceRayQueryFeaturesKHR,
+    ray_tracing_pipeline: vk::PhysicalDeviceRayTracingPipelineFeaturesKHR,
+    performance_query: vk::PhysicalDevicePerformanceQueryFeaturesKHR,
+    present_id: vk::PhysicalDevicePresentIdFeaturesKHR,
+    present_wait: vk::PhysicalDevicePresentWaitFeaturesKHR,
+    timeline_semaphore: vk::PhysicalDeviceTimelineSemaphoreFeatures,
+}
+
+impl FeatureChain {
+    pub fn new(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> FeatureChain {
+        FeatureChain {
+            supported: query_supported(instance, physical_device),
+            enabled: DeviceFeatures::default(),
+
+            descriptor_indexing: Default::default(),
+            scalar_block_layout: Default::default(),
+            pipeline_creation_cache_control: Default::default(),
+            synchronization2: Default::default(),
+            buffer_device_address: Default::default(),
+            acceleration_structure: Default::default(),
+            ray_query: Default::default(),
+            ray_tracing_pipeline: Default::default(),
+            performance_query: Default::default(),
+            present_id: Default::default(),
+            present_wait: Default::default(),
+            timeline_semaphore: Default::default(),
+        }
+    }
+
+    pub fn require(mut self, feature: DeviceFeature) -> FeatureChain {
+        if let Some(missing) = self.missing(feature) {
+            panic!(
+                "{:?} is required but the device does not support {:?}.",
+                feature, missing
+            );
+        }
+        self.enable(feature);
+        self
+    }
+
+    // Enabled when the device supports the feature and its dependencies
+    pub fn request(mut self, feature: DeviceFeature) -> FeatureChain {
+        match self.missing(feature) {
+            Some(missing) => {
+                tracing::debug!("Leaving out {:?}, {:?} is unsupported", feature, missing)
+            }
+            None => self.enable(feature),
+        }
+        self
+    }
+
+    pub fn supported(&self) -> DeviceFeatures {
+        self.supported
+    }
+
+    pub fn enabled(&self) -> DeviceFeatures {
+        self.enabled
+    }
+
+    pub fn extension_names(&self) -> Vec<*const c_char> {
+        self.enabled
+            .iter()
+            .flat_map(DeviceFeature::extensions)
+            .map(CStr::as_ptr)
+            .collect()
+    }
+
+    // Chains the structs of the enabled features into the create info
+    pub fn push_next<'a>(
+        &'a mut self,
+        mut create_info: vk::DeviceCreateInfoBuilder<'a>,
+    ) -> vk::DeviceCreateInfoBuilder<'a> {
+        let enabled = self.enabled;
+        if enabled.contains(DeviceFeature::DescriptorIndexing) {
+            create_info = create_info.push_next(&mut self.descriptor_indexing);
+        }
+        if enabled.contains(DeviceFeature::ScalarBlockLayout) {
+            create_info = create_info.push_next(&mut self.scalar_block_layout);
+        }
+        if enabled.contains(DeviceFeature::PipelineCreationCacheControl) {
+            create_info = create_info.push_next(&mut self.pipeline_creation_cache_control);
+        }
+        if enabled.contains(DeviceFeature::Synchronization2) {
+            create_info = create_info.push_next(&mut self.synchronization2);
+        }
+        if enabled.contains(DeviceFeature::BufferDeviceAddress) {
+            create_info = create_info.push_next(&mut self.buffer_device_address);
+        }
+        if enabled.contains(DeviceFeature::AccelerationStructure) {
+            create_info = create_info.push_next(&mut self.acceleration_structure);
+        }
+        if enabled.contains(DeviceFeature::RayQuery) {
+            create_info = create_info.push_next(&mut self.ray_query);
+        }
+        if enabled.contains(DeviceFeature::RayTracingPipeline) {
+            create_info = create_info.push_next(&mut self.ray_tracing_pipeline);
+        }
+        if enabled.contains(DeviceFeature::PerformanceQuery) {
+            create_info = create_info.push_next(&mut self.performance_query);
+        }
+        if enabled.contains(DeviceFeature::PresentId) {
+            create_info = create_info.push_next(&mut self.present_id);
+        }
+        if enabled.contains(DeviceFeature::PresentWait) {
+            create_info = create_info.push_next(&mut self.present_wait);
+        }
+        if enabled.contains(DeviceFeature::TimelineSemaphore) {
+            create_info = create_info.push_next(&mut self.timeline_semaphore);
+        }
+        create_info
+    }
+
+    // The first unsupported feature among the feature and its dependencies
+    fn missing(&self, feature: DeviceFeature) -> Option<DeviceFeature> {
+        if !self.supported.contains(feature) {
+            return Some(feature);
+        }
+        feature
+            .dependencies()
+            .iter()
+            .find_map(|&dependency| self.missing(dependency))
+    }
+
+    fn enable(&mut self, feature: DeviceFeature) {
+        for &dependency in feature.dependencies() {
+            self.enable(dependency);
+        }
+        self.enabled.insert(feature);
+
+        match feature {
+            DeviceFeature::DescriptorIndexing => {
+                self.descriptor_indexing
+                    .descriptor_binding_variable_descriptor_count = vk::TRUE;
+                self.descriptor_indexing.runtime_descriptor_array = vk::TRUE;
+            }
+            DeviceFeature::ScalarBlockLayout => {
+                self.scalar_block_layout.scalar_block_layout = vk::TRUE
+            }
+            DeviceFeature::PipelineCreationCacheControl => {
+                self.pipeline_creation_cache_control
+                    .pipeline_creation_cache_control = vk::TRUE
+            }
+            DeviceFeature::Synchronization2 => self.synchronization2.synchronization2 = vk::TRUE,
+            DeviceFeature::BufferDeviceAddress => {
+                self.buffer_device_address.buffer_device_address = vk::TRUE
+            }
+            DeviceFeature::AccelerationStructure => {
+                self.acceleration_structure.acceleration_structure = vk::TRUE
+            }
+            DeviceFeature::RayQuery => self.ray_query.ray_query = vk::TRUE,
+            DeviceFeature::RayTracingPipeline => {
+                self.ray_tracing_pipeline.ray_tracing_pipeline = vk::TRUE
+            }
+            DeviceFeature::PerformanceQuery => {
+                self.performance_query.performance_counter_query_pools = vk::TRUE
+            }
+            DeviceFeature::PresentId => self.present_id.present_id = vk::TRUE,
+            DeviceFeature::PresentWait => self.present_wait.present_wait = vk::TRUE,
+            DeviceFeature::TimelineSemaphore => {
+                self.timeline_semaphore.timeline_semaphore = vk::TRUE
+            }
+            DeviceFeature::ShaderNonSemanticInfo
+            | DeviceFeature::DisplayTiming
+            | DeviceFeature::LowLatency => {}
+        }
+    }
+}
+
+fn query_supported(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> DeviceFeatures {
+    let available_extensions = unsafe {
+        instance
+            .enumerate_device_extension_properties(physical_device)
+            .expect("Failed to get device extension properties.")
+    };
+    let has_extensions = |feature: DeviceFeature| {
+        feature.extensions().iter().all(|required| {
+            available_extensions.iter().any(|extension| {
+                vk_to_string(&extension.extension_name) == required.to_str().unwrap()
+            })
+        })
+    };
+
+    let mut descriptor_indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+    let mut scalar_block_layout = vk::PhysicalDeviceScalarBlockLayoutFeatures::default();
+    let mut pipeline_creation_cache_control =
+        vk::PhysicalDevicePipelineCreationCacheControlFeatures::default();
+    let mut synchronization2 = vk::PhysicalDeviceSynchronization2Features::default();
+    let mut buffer_device_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+    let mut acceleration_structure = vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+    let mut ray_query = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
+    let mut ray_tracing_pipeline = vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::default();
+    let mut performance_query = vk::PhysicalDevicePerformanceQueryFeaturesKHR::default();
+    let mut present_id = vk::PhysicalDevicePresentIdFeaturesKHR::default();
+    let mut present_wait = vk::PhysicalDevicePresentWaitFeaturesKHR::default();
+    let mut timeline_semaphore = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+
+    // Structs of extensions the device lacks may not be passed to the query either
+    let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut descriptor_indexing)
+        .push_next(&mut scalar_block_layout)
+        .push_next(&mut pipeline_creation_cache_control)
+        .push_next(&mut synchronization2)
+        .push_next(&mut buffer_device_address)
+        .push_next(&mut timeline_semaphore);
+    if has_extensions(DeviceFeature::AccelerationStructure) {
+        features2 = features2.push_next(&mut acceleration_structure);
+    }
+    if has_extensions(DeviceFeature::RayQuery) {
+        features2 = features2.push_next(&mut ray_query);
+    }
+    if has_extensions(DeviceFeature::RayTracingPipeline) {
+        features2 = features2.push_next(&mut ray_tracing_pipeline);
+    }
+    if has_extensions(DeviceFeature::PerformanceQuery) {
+        features2 = features2.push_next(&mut performance_query);
+    }
+    if has_extensions(DeviceFeature::PresentId) {
+        features2 = features2.push_next(&mut present_id);
+    }
+    if has_extensions(DeviceFeature::PresentWait) {
+        features2 = features2.push_next(&mut present_wait);
+    }
+    unsafe {
+        instance.get_physical_device_features2(physical_device, &mut features2);
+    }
+
+    let support = [
+        (
+            DeviceFeature::DescriptorIndexing,
+            descriptor_indexing.descriptor_binding_variable_descriptor_count == vk::TRUE
+                && descriptor_indexing.runtime_descriptor_array == vk::TRUE,
+        ),
+        (
+            DeviceFeature::ScalarBlockLayout,
+            scalar_block_layout.scalar_block_layout == vk::TRUE,
+        ),
+        (
+            DeviceFeature::PipelineCreationCacheControl,
+            pipeline_creation_cache_control.pipeline_creation_cache_control == vk::TRUE,
+        ),
+        (
+            DeviceFeature::Synchronization2,
+            synchronization2.synchronization2 == vk::TRUE,
+        ),
+        (
+            DeviceFeature::BufferDeviceAddress,
+            buffer_device_address.buffer_device_address == vk::TRUE,
+        ),
+        (
+            DeviceFeature::AccelerationStructure,
+            acceleration_structure.acceleration_structure == vk::TRUE,
+        ),
+        (DeviceFeature::RayQuery, ray_query.ray_query == vk::TRUE),
+        (
+            DeviceFeature::RayTracingPipeline,
+            ray_tracing_pipeline.ray_tracing_pipeline == vk::TRUE,
+        ),
+        (
+            DeviceFeature::PerformanceQuery,
+            performance_query.performance_counter_query_pools == vk::TRUE,
+        ),
+        (
+            DeviceFeature::ShaderNonSemanticInfo,
+            has_extensions(DeviceFeature::ShaderNonSemanticInfo),
+        ),
+        (
+            DeviceFeature::DisplayTiming,
+            has_extensions(DeviceFeature::DisplayTiming),
+        ),
+        (DeviceFeature::PresentId, present_id.present_id == vk::TRUE),
+        (
+            DeviceFeature::PresentWait,
+            present_wait.present_wait == vk::TRUE,
+        ),
+        (
+            DeviceFeature::TimelineSemaphore,
+            timeline_semaphore.timeline_semaphore == vk::TRUE,
+        ),
+        (
+            DeviceFeature::LowLatency,
+            has_extensions(DeviceFeature::LowLatency),
+        ),
+    ];
+
+    let mut supported = DeviceFeatures::default();
+    for (feature, is_supported) in support {
+        if is_supported {
+            supported.insert(feature);
+        }
+    }
+    supported
+}
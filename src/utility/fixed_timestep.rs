@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+// Splits the variable frame time into fixed simulation steps. What is left over is carried to
+// the next frame and returned as the fraction of a step the frame lies past the last update.
+pub struct FixedTimestep {
+    step: Duration,
+    accumulator: Duration,
+    // Frames longer than this many steps drop the rest instead of catching up forever
+    max_steps: u32,
+}
+
+impl FixedTimestep {
+    pub fn new(step: Duration, max_steps: u32) -> FixedTimestep {
+        FixedTimestep {
+            step,
+            accumulator: Duration::ZERO,
+            max_steps,
+        }
+    }
+
+    pub fn step_seconds(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+
+    // Adds the frame time, returns the number of updates to run and the interpolation factor
+    // between the last two simulation states
+    pub fn advance(&mut self, frame_time: Duration) -> (u32, f32) {
+        self.accumulator += frame_time;
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        if steps > self.max_steps {
+            tracing::debug!(
+                "Dropping {} simulation steps of a long frame",
+                steps - self.max_steps
+            );
+            steps = self.max_steps;
+        }
+        (
+            steps,
+            self.accumulator.as_secs_f32() / self.step.as_secs_f32(),
+        )
+    }
+}
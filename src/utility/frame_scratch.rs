@@ -0,0 +1,28 @@
+// A list filled anew every frame that keeps its capacity from one frame to the next, so once
+// the first frames sized it the frame does not go back to the heap for it
+pub struct ScratchVec<T> {
+    vec: Vec<T>,
+}
+
+impl<T> Default for ScratchVec<T> {
+    fn default() -> Self {
+        ScratchVec { vec: Vec::new() }
+    }
+}
+
+impl<T> ScratchVec<T> {
+    // Empty, with the capacity of the list given back last. Taken out of the owner so it does
+    // not borrow the owner while it is filled and read.
+    pub fn take(&mut self) -> Vec<T> {
+        let mut vec = std::mem::take(&mut self.vec);
+        vec.clear();
+        vec
+    }
+
+    // The larger of the two lists is kept
+    pub fn give_back(&mut self, vec: Vec<T>) {
+        if vec.capacity() >= self.vec.capacity() {
+            self.vec = vec;
+        }
+    }
+}
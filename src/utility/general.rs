@@ -1,5 +1,12 @@
 use crate::{
-    utility, utility::constants::*, utility::debug::ValidationInfo, utility::platforms,
+    utility,
+    utility::constants::*,
+    utility::debug::ValidationInfo,
+    utility::feature_chain::{DeviceFeature, DeviceFeatures, FeatureChain},
+    utility::logging::TARGET_SWAPCHAIN,
+    utility::low_latency::SwapchainLatencyCreateInfoNV,
+    utility::platforms,
+    utility::shader_cache::ShaderModuleCache,
     utility::structures::*,
 };
 
@@ -13,12 +20,16 @@ use std::{
 
 use ash::vk;
 use image::EncodableLayout;
+use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
 
 pub fn create_instance(
     entry: &ash::Entry,
     window_title: &str,
     is_enable_debug: bool,
+    debug_printf: bool,
     required_validation_layers: &Vec<&str>,
+    // What surfaces of the window need, platforms::required_extension_names for winit windows
+    surface_extension_names: Vec<*const c_char>,
 ) -> ash::Instance {
     if is_enable_debug
         && utility::debug::check_validation_layer_support(entry, required_validation_layers)
@@ -39,9 +50,26 @@ pub fn create_instance(
         api_version: API_VERSION,
     };
 
-    let debug_utils_create_info = utility::debug::populate_debug_messenger_create_info();
+    // The validation layer runs the shaders' printf once it is enabled as a validation feature
+    let enabled_validation_features = [vk::ValidationFeatureEnableEXT::DEBUG_PRINTF];
+    let validation_features = vk::ValidationFeaturesEXT::builder()
+        .enabled_validation_features(&enabled_validation_features)
+        .build();
+    let mut debug_utils_create_info =
+        utility::debug::populate_debug_messenger_create_info(debug_printf);
+    if debug_printf {
+        debug_utils_create_info.p_next =
+            &validation_features as *const vk::ValidationFeaturesEXT as *const c_void;
+    }
 
-    let extension_names = utility::platforms::required_extension_names();
+    let mut extension_names = surface_extension_names;
+    // Also enabled without validation for the GPU profiler's labels
+    if is_enable_debug || utility::debug::debug_utils_supported(entry) {
+        extension_names.push(ash::extensions::ext::DebugUtils::name().as_ptr());
+    }
+    if debug_printf {
+        extension_names.push(vk::ExtValidationFeaturesFn::name().as_ptr());
+    }
 
     let required_validation_layer_raw_names: Vec<CString> = required_validation_layers
         .iter()
@@ -84,6 +112,7 @@ pub fn create_instance(
     instance
 }
 
+#[cfg(feature = "winit")]
 pub fn create_surface(
     entry: &ash::Entry,
     instance: &ash::Instance,
@@ -104,6 +133,27 @@ pub fn create_surface(
     }
 }
 
+// For windows of other toolkits, fails on platforms without a surface extension for them
+pub fn create_surface_from_raw_handles(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    display: RawDisplayHandle,
+    window: RawWindowHandle,
+    screen_width: u32,
+    screen_height: u32,
+) -> Result<SurfaceStuff, vk::Result> {
+    let surface =
+        unsafe { platforms::create_surface_from_raw_handles(entry, instance, display, window)? };
+    let surface_loader = ash::extensions::khr::Surface::new(entry, instance);
+
+    Ok(SurfaceStuff {
+        surface_loader,
+        surface,
+        screen_width,
+        screen_height,
+    })
+}
+
 pub fn create_surface_format(
     physical_device: vk::PhysicalDevice,
     surface_stuff: &SurfaceStuff,
@@ -163,7 +213,7 @@ fn is_physical_device_suitable(
     required_device_extensions: &DeviceExtension,
 ) -> bool {
     let device_properties = unsafe { instance.get_physical_device_properties(physical_device) };
-    println!(
+    tracing::info!(
         "Current Device: {}",
         utility::tools::vk_to_string(&device_properties.device_name)
     );
@@ -190,13 +240,16 @@ fn is_physical_device_suitable(
         && is_support_sampler_anisotropy;
 }
 
+// With more than one device in `device_group` the logical device spans all of them, the first
+// one being `physical_device`
 pub fn create_logical_device(
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
+    device_group: &[vk::PhysicalDevice],
     validation: &ValidationInfo,
     device_extension: &DeviceExtension,
     surface_stuff: &SurfaceStuff,
-) -> (ash::Device, QueueFamilyIndices) {
+) -> (ash::Device, QueueFamilyIndices, DeviceFeatures) {
     let indices = find_queue_family(instance, physical_device, surface_stuff);
 
     let mut unique_queue_families = HashSet::new();
@@ -223,13 +276,23 @@ pub fn create_logical_device(
     // };
     unsafe {
         let features2 = instance.get_physical_device_features(physical_device);
-        let mut descriptor_indexing = vk::PhysicalDeviceDescriptorIndexingFeaturesEXT::builder()
-            .descriptor_binding_variable_descriptor_count(true)
-            .runtime_descriptor_array(true)
-            .build();
-        let mut scalar_block = vk::PhysicalDeviceScalarBlockLayoutFeaturesEXT::builder()
-            .scalar_block_layout(true)
-            .build();
+        // The NV ray tracing pipeline only needs DEVICE_EXTENSIONS, the ray query backend is
+        // optional and RenderMode::RayQuery falls back to the pipeline without it
+        let mut feature_chain = FeatureChain::new(instance, physical_device)
+            .require(DeviceFeature::DescriptorIndexing)
+            .require(DeviceFeature::ScalarBlockLayout)
+            .request(DeviceFeature::PipelineCreationCacheControl)
+            .request(DeviceFeature::Synchronization2)
+            .request(DeviceFeature::RayQuery)
+            .request(DeviceFeature::PerformanceQuery)
+            .request(DeviceFeature::DisplayTiming)
+            .request(DeviceFeature::PresentWait)
+            .request(DeviceFeature::LowLatency);
+        // Core in Vulkan 1.3, enabled by name for drivers that still expect the extension
+        if validation.debug_printf {
+            feature_chain = feature_chain.request(DeviceFeature::ShaderNonSemanticInfo);
+        }
+        let enabled_features = feature_chain.enabled();
 
         let required_validation_layer_raw_names: Vec<CString> = validation
             .required_validation_layers
@@ -241,7 +304,8 @@ pub fn create_logical_device(
             .map(|layer_name| layer_name.as_ptr())
             .collect();
 
-        let enable_extension_names = device_extension.get_extensions_raw_names();
+        let mut enable_extension_names = device_extension.get_extensions_raw_names().to_vec();
+        enable_extension_names.extend(feature_chain.extension_names());
 
         // let device_create_info = vk::DeviceCreateInfo {
         //     s_type: vk::StructureType::DEVICE_CREATE_INFO,
@@ -263,13 +327,16 @@ pub fn create_logical_device(
         //     pp_enabled_extension_names: enable_extension_names.as_ptr(),
         //     p_enabled_features: &features2,
         // };
-        let device_create_info = vk::DeviceCreateInfo::builder()
+        let mut device_group_info =
+            vk::DeviceGroupDeviceCreateInfo::builder().physical_devices(device_group);
+        let mut device_create_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(&queue_create_infos)
             .enabled_extension_names(&enable_extension_names)
-            .enabled_features(&features2)
-            .push_next(&mut scalar_block)
-            .push_next(&mut descriptor_indexing)
-            .build();
+            .enabled_features(&features2);
+        if device_group.len() > 1 {
+            device_create_info = device_create_info.push_next(&mut device_group_info);
+        }
+        let device_create_info = feature_chain.push_next(device_create_info).build();
 
         let device: ash::Device = unsafe {
             instance
@@ -277,7 +344,7 @@ pub fn create_logical_device(
                 .expect("Failed to create logical Device!")
         };
 
-        (device, indices)
+        (device, indices, enabled_features)
     }
 }
 
@@ -291,30 +358,31 @@ fn find_queue_family(
 
     let mut queue_family_indices = QueueFamilyIndices::new();
 
-    let mut index: u32 = 0;
-    for queue_family in queue_families.iter() {
-        if queue_family.queue_count > 0
-            && queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-        {
-            queue_family_indices.graphics_family = Some(index);
-        }
-
+    // A graphics family that presents too is preferred, a present family of its own costs an
+    // ownership transfer every frame
+    for (index, queue_family) in queue_families.iter().enumerate() {
+        let index = index as u32;
+        let is_graphics = queue_family.queue_count > 0
+            && queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS);
         let is_present_support = unsafe {
             surface_stuff
                 .surface_loader
                 .get_physical_device_surface_support(physical_device, index, surface_stuff.surface)
                 .unwrap()
         };
+        let is_present = queue_family.queue_count > 0 && is_present_support;
 
-        if queue_family.queue_count > 0 && is_present_support {
+        if is_graphics && is_present {
+            queue_family_indices.graphics_family = Some(index);
             queue_family_indices.present_family = Some(index);
-        }
-
-        if queue_family_indices.is_complete() {
             break;
         }
-
-        index += 1;
+        if is_graphics && queue_family_indices.graphics_family.is_none() {
+            queue_family_indices.graphics_family = Some(index);
+        }
+        if is_present && queue_family_indices.present_family.is_none() {
+            queue_family_indices.present_family = Some(index);
+        }
     }
 
     queue_family_indices
@@ -380,15 +448,20 @@ pub fn create_swapchain(
     instance: &ash::Instance,
     device: &ash::Device,
     physical_device: vk::PhysicalDevice,
-    window: &winit::window::Window,
+    // Inner size of the window, used where the surface leaves the extent to the swapchain
+    window_size: vk::Extent2D,
     surface_stuff: &SurfaceStuff,
-    queue_family: &QueueFamilyIndices,
+    requested_usage: vk::ImageUsageFlags,
+    low_latency: bool,
+    // Whether the device has VK_NV_low_latency2, whose low latency mode the swapchain is then
+    // created for
+    nv_low_latency: bool,
 ) -> SwapChainStuff {
     let swapchain_support = query_swapchain_support(physical_device, surface_stuff);
 
     let surface_format = choose_swapchain_format(&swapchain_support.formats);
-    let present_mode = choose_swapchain_present_mode(&swapchain_support.present_modes);
-    let extent = choose_swapchain_extent(&swapchain_support.capabilities, window);
+    let present_mode = choose_swapchain_present_mode(&swapchain_support.present_modes, low_latency);
+    let extent = choose_swapchain_extent(&swapchain_support.capabilities, window_size);
 
     let image_count = swapchain_support.capabilities.min_image_count + 1;
     let image_count = if swapchain_support.capabilities.max_image_count > 0 {
@@ -397,33 +470,47 @@ pub fn create_swapchain(
         image_count
     };
 
-    let (image_sharing_mode, queue_family_index_count, queue_family_indices) =
-        if queue_family.graphics_family != queue_family.present_family {
-            (
-                vk::SharingMode::CONCURRENT,
-                2,
-                vec![
-                    queue_family.graphics_family.unwrap(),
-                    queue_family.present_family.unwrap(),
-                ],
-            )
-        } else {
-            (vk::SharingMode::EXCLUSIVE, 0, vec![])
-        };
+    // Storage use also needs a format shaders can store to, which the sRGB ones usually are not
+    let mut supported_usage = swapchain_support.capabilities.supported_usage_flags;
+    let format_features = unsafe {
+        instance.get_physical_device_format_properties(physical_device, surface_format.format)
+    }
+    .optimal_tiling_features;
+    if !format_features.contains(vk::FormatFeatureFlags::STORAGE_IMAGE) {
+        supported_usage &= !vk::ImageUsageFlags::STORAGE;
+    }
+    if !supported_usage.contains(requested_usage) {
+        tracing::warn!(
+            target: TARGET_SWAPCHAIN,
+            "Swapchain usage {:?} is not supported with {:?}",
+            requested_usage & !supported_usage,
+            surface_format.format
+        );
+    }
+    // Copies out of the swapchain images take screenshots where the surface allows them
+    let image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+        | (supported_usage & (vk::ImageUsageFlags::TRANSFER_SRC | requested_usage));
 
+    let latency_create_info = SwapchainLatencyCreateInfoNV::default();
     let swapchain_create_info = vk::SwapchainCreateInfoKHR {
         s_type: vk::StructureType::SWAPCHAIN_CREATE_INFO_KHR,
-        p_next: ptr::null(),
+        p_next: if nv_low_latency {
+            &latency_create_info as *const SwapchainLatencyCreateInfoNV as *const c_void
+        } else {
+            ptr::null()
+        },
         flags: vk::SwapchainCreateFlagsKHR::empty(),
         surface: surface_stuff.surface,
         min_image_count: image_count,
         image_color_space: surface_format.color_space,
         image_format: surface_format.format,
         image_extent: extent,
-        image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
-        image_sharing_mode,
-        p_queue_family_indices: queue_family_indices.as_ptr(),
-        queue_family_index_count,
+        image_usage,
+        // A separate present family gets the images by explicit ownership transfers, see
+        // PresentTransfer, which keeps them compressible where concurrent sharing might not
+        image_sharing_mode: vk::SharingMode::EXCLUSIVE,
+        p_queue_family_indices: ptr::null(),
+        queue_family_index_count: 0,
         pre_transform: swapchain_support.capabilities.current_transform,
         composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
         present_mode,
@@ -445,12 +532,24 @@ pub fn create_swapchain(
             .expect("Failed to get Swapcahin Images.")
     };
 
+    tracing::info!(
+        target: TARGET_SWAPCHAIN,
+        "Created swapchain: {}x{} {:?} {:?}, {} images, {:?}",
+        extent.width,
+        extent.height,
+        surface_format.format,
+        present_mode,
+        swapchain_images.len(),
+        image_usage
+    );
+
     SwapChainStuff {
         swapchain_loader,
         swapchain,
         swapchain_images,
         swapchain_format: surface_format.format,
         swapchain_extent: extent,
+        swapchain_usage: image_usage,
     }
 }
 
@@ -466,36 +565,40 @@ fn choose_swapchain_format(available_formats: &Vec<vk::SurfaceFormatKHR>) -> vk:
     return available_formats.first().unwrap().clone();
 }
 
+// Low latency takes tearing over waiting for the vertical blank when there is no mailbox
 fn choose_swapchain_present_mode(
     available_present_modes: &Vec<vk::PresentModeKHR>,
+    low_latency: bool,
 ) -> vk::PresentModeKHR {
     for &available_present_mode in available_present_modes.iter() {
         if available_present_mode == vk::PresentModeKHR::MAILBOX {
             return available_present_mode;
         }
     }
+    if low_latency && available_present_modes.contains(&vk::PresentModeKHR::IMMEDIATE) {
+        return vk::PresentModeKHR::IMMEDIATE;
+    }
 
     vk::PresentModeKHR::FIFO
 }
 
 fn choose_swapchain_extent(
     capabilities: &vk::SurfaceCapabilitiesKHR,
-    window: &winit::window::Window,
+    window_size: vk::Extent2D,
 ) -> vk::Extent2D {
     if capabilities.current_extent.width != u32::MAX {
         capabilities.current_extent
     } else {
         use num::clamp;
 
-        let window_size = window.inner_size();
         vk::Extent2D {
             width: clamp(
-                window_size.width as u32,
+                window_size.width,
                 capabilities.min_image_extent.width,
                 capabilities.max_image_extent.width,
             ),
             height: clamp(
-                window_size.height as u32,
+                window_size.height,
                 capabilities.min_image_extent.height,
                 capabilities.max_image_extent.height,
             ),
@@ -574,47 +677,16 @@ pub fn create_image_view(
     }
 }
 
-pub fn create_texture_sampler(device: &ash::Device, mip_levels: u32) -> vk::Sampler {
-    let sampler_create_info = vk::SamplerCreateInfo {
-        s_type: vk::StructureType::SAMPLER_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::SamplerCreateFlags::empty(),
-        mag_filter: vk::Filter::LINEAR,
-        min_filter: vk::Filter::LINEAR,
-        address_mode_u: vk::SamplerAddressMode::REPEAT,
-        address_mode_v: vk::SamplerAddressMode::REPEAT,
-        address_mode_w: vk::SamplerAddressMode::REPEAT,
-        anisotropy_enable: vk::TRUE,
-        max_anisotropy: 16.0,
-        compare_enable: vk::FALSE,
-        compare_op: vk::CompareOp::ALWAYS,
-        mipmap_mode: vk::SamplerMipmapMode::LINEAR,
-        min_lod: 0.0,
-        max_lod: mip_levels as f32,
-        mip_lod_bias: 0.0,
-        border_color: vk::BorderColor::INT_OPAQUE_BLACK,
-        unnormalized_coordinates: vk::FALSE,
-    };
-
-    unsafe {
-        device
-            .create_sampler(&sampler_create_info, None)
-            .expect("Failed to create Sampler!")
-    }
-}
-
 pub fn create_graphics_pipeline(
     device: &ash::Device,
+    shader_modules: &mut ShaderModuleCache,
     render_pass: vk::RenderPass,
     swapchain_extent: vk::Extent2D,
     ubo_set_layout: vk::DescriptorSetLayout,
     msaa_samples: vk::SampleCountFlags,
 ) -> (vk::Pipeline, vk::PipelineLayout) {
-    let vert_shader_code = utility::tools::read_shader_code(Path::new("shaders/spv/vert.spv"));
-    let frag_shader_code = utility::tools::read_shader_code(Path::new("shaders/spv/frag.spv"));
-
-    let vert_shader_module = create_shader_module(device, vert_shader_code);
-    let frag_shader_module = create_shader_module(device, frag_shader_code);
+    let vert_shader_module = shader_modules.load(Path::new("shaders/spv/vert.spv"));
+    let frag_shader_module = shader_modules.load(Path::new("shaders/spv/frag.spv"));
 
     let main_function_name = CString::new("main").unwrap();
 
@@ -807,30 +879,14 @@ pub fn create_graphics_pipeline(
             .expect("Failed to create Graphics Pipeline!")
     };
 
-    unsafe {
-        device.destroy_shader_module(vert_shader_module, None);
-        device.destroy_shader_module(frag_shader_module, None);
-    }
+    shader_modules.attach(
+        graphics_pipelines[0],
+        &[vert_shader_module, frag_shader_module],
+    );
 
     (graphics_pipelines[0], pipeline_layout)
 }
 
-fn create_shader_module(device: &ash::Device, code: Vec<u8>) -> vk::ShaderModule {
-    let shader_module_create_info = vk::ShaderModuleCreateInfo {
-        s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::ShaderModuleCreateFlags::empty(),
-        code_size: code.len(),
-        p_code: code.as_ptr() as *const u32,
-    };
-
-    unsafe {
-        device
-            .create_shader_module(&shader_module_create_info, None)
-            .expect("Failed to create Shader Module!")
-    }
-}
-
 pub fn create_render_pass(
     instance: &ash::Instance,
     device: &ash::Device,
@@ -936,31 +992,6 @@ pub fn create_render_pass(
     }
 }
 
-pub fn create_uniform_buffers(
-    device: &ash::Device,
-    device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
-    swapchain_image_count: usize,
-) -> (Vec<vk::Buffer>, Vec<vk::DeviceMemory>) {
-    let buffer_size = std::mem::size_of::<UniformBufferObject>();
-
-    let mut uniform_buffers = vec![];
-    let mut uniform_buffers_memory = vec![];
-
-    for _ in 0..swapchain_image_count {
-        let (uniform_buffer, uniform_buffer_memory) = create_buffer(
-            device,
-            buffer_size as u64,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            device_memory_properties,
-        );
-        uniform_buffers.push(uniform_buffer);
-        uniform_buffers_memory.push(uniform_buffer_memory)
-    }
-
-    (uniform_buffers, uniform_buffers_memory)
-}
-
 pub fn create_framebuffers(
     device: &ash::Device,
     render_pass: vk::RenderPass,
@@ -1280,8 +1311,11 @@ pub fn create_command_buffers(
     vertex_buffer: vk::Buffer,
     index_buffer: vk::Buffer,
     pipeline_layout: vk::PipelineLayout,
-    descriptor_sets: &Vec<vk::DescriptorSet>,
+    descriptor_set: vk::DescriptorSet,
+    uniform_offsets: &[u32],
     index_count: u32,
+    // Called with each command buffer and its framebuffer index after the render pass
+    mut end_frame: impl FnMut(vk::CommandBuffer, usize),
 ) -> Vec<vk::CommandBuffer> {
     let command_buffer_allocate_info = vk::CommandBufferAllocateInfo {
         s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
@@ -1352,7 +1386,9 @@ pub fn create_command_buffers(
 
             let vertex_buffers = [vertex_buffer];
             let offsets = [0_u64];
-            let descriptor_sets_to_bind = [descriptor_sets[i]];
+            let descriptor_sets_to_bind = [descriptor_set];
+            // Each image's uniforms start its frame slice of the uniform ring
+            let dynamic_offsets = [uniform_offsets[i]];
 
             device.cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffers, &offsets);
             device.cmd_bind_index_buffer(command_buffer, index_buffer, 0, vk::IndexType::UINT32);
@@ -1362,12 +1398,13 @@ pub fn create_command_buffers(
                 pipeline_layout,
                 0,
                 &descriptor_sets_to_bind,
-                &[],
+                &dynamic_offsets,
             );
 
             device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
 
             device.cmd_end_render_pass(command_buffer);
+            end_frame(command_buffer, i);
 
             device
                 .end_command_buffer(command_buffer)
@@ -1382,7 +1419,7 @@ pub fn create_descriptor_set_layout(device: &ash::Device) -> vk::DescriptorSetLa
     let ubo_layout_bindings = [
         vk::DescriptorSetLayoutBinding {
             binding: 0,
-            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
             descriptor_count: 1,
             stage_flags: vk::ShaderStageFlags::VERTEX,
             p_immutable_samplers: ptr::null(),
@@ -1411,18 +1448,15 @@ pub fn create_descriptor_set_layout(device: &ash::Device) -> vk::DescriptorSetLa
     }
 }
 
-pub fn create_descriptor_pool(
-    device: &ash::Device,
-    swapchain_images_size: usize,
-) -> vk::DescriptorPool {
+pub fn create_descriptor_pool(device: &ash::Device) -> vk::DescriptorPool {
     let pool_sizes = [
         vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: swapchain_images_size as u32,
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: 1,
         },
         vk::DescriptorPoolSize {
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: swapchain_images_size as u32,
+            descriptor_count: 1,
         },
     ];
 
@@ -1430,7 +1464,7 @@ pub fn create_descriptor_pool(
         s_type: vk::StructureType::DESCRIPTOR_POOL_CREATE_INFO,
         p_next: ptr::null(),
         flags: vk::DescriptorPoolCreateFlags::empty(),
-        max_sets: swapchain_images_size as u32,
+        max_sets: 1,
         pool_size_count: pool_sizes.len() as u32,
         p_pool_sizes: pool_sizes.as_ptr(),
     };
@@ -1442,80 +1476,75 @@ pub fn create_descriptor_pool(
     }
 }
 
-pub fn create_descriptor_sets(
+// A single set serves every frame, the uniform binding is dynamic and moved by its offset
+pub fn create_descriptor_set(
     device: &ash::Device,
     descriptor_pool: vk::DescriptorPool,
     descriptor_set_layout: vk::DescriptorSetLayout,
-    uniform_buffers: &Vec<vk::Buffer>,
+    uniform_buffer: vk::Buffer,
     texture_image_view: vk::ImageView,
     texture_sampler: vk::Sampler,
-    swapchain_images_size: usize,
-) -> Vec<vk::DescriptorSet> {
-    let mut layouts: Vec<vk::DescriptorSetLayout> = vec![];
-    for _ in 0..swapchain_images_size {
-        layouts.push(descriptor_set_layout);
-    }
+) -> vk::DescriptorSet {
+    let layouts = [descriptor_set_layout];
 
     let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo {
         s_type: vk::StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
         p_next: ptr::null(),
         descriptor_pool,
-        descriptor_set_count: swapchain_images_size as u32,
+        descriptor_set_count: layouts.len() as u32,
         p_set_layouts: layouts.as_ptr(),
     };
 
-    let descriptor_sets = unsafe {
+    let descriptor_set = unsafe {
         device
             .allocate_descriptor_sets(&descriptor_set_allocate_info)
             .expect("Failed to allocate descriptor sets!")
-    };
+    }[0];
 
-    for (i, &descriptor_set) in descriptor_sets.iter().enumerate() {
-        let descriptor_buffer_infos = [vk::DescriptorBufferInfo {
-            buffer: uniform_buffers[i],
-            offset: 0,
-            range: std::mem::size_of::<UniformBufferObject>() as u64,
-        }];
+    let descriptor_buffer_infos = [vk::DescriptorBufferInfo {
+        buffer: uniform_buffer,
+        offset: 0,
+        range: std::mem::size_of::<UniformBufferObject>() as u64,
+    }];
 
-        let descriptor_image_infos = [vk::DescriptorImageInfo {
-            sampler: texture_sampler,
-            image_view: texture_image_view,
-            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-        }];
+    let descriptor_image_infos = [vk::DescriptorImageInfo {
+        sampler: texture_sampler,
+        image_view: texture_image_view,
+        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    }];
 
-        let descriptor_write_sets = [
-            vk::WriteDescriptorSet {
-                s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
-                p_next: ptr::null(),
-                dst_set: descriptor_set,
-                dst_binding: 0,
-                dst_array_element: 0,
-                descriptor_count: 1,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
-                p_image_info: ptr::null(),
-                p_buffer_info: descriptor_buffer_infos.as_ptr(),
-                p_texel_buffer_view: ptr::null(),
-            },
-            vk::WriteDescriptorSet {
-                s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
-                p_next: ptr::null(),
-                dst_set: descriptor_set,
-                dst_binding: 1,
-                dst_array_element: 0,
-                descriptor_count: 1,
-                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                p_image_info: descriptor_image_infos.as_ptr(),
-                p_buffer_info: ptr::null(),
-                p_texel_buffer_view: ptr::null(),
-            },
-        ];
+    let descriptor_write_sets = [
+        vk::WriteDescriptorSet {
+            s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+            p_next: ptr::null(),
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            p_image_info: ptr::null(),
+            p_buffer_info: descriptor_buffer_infos.as_ptr(),
+            p_texel_buffer_view: ptr::null(),
+        },
+        vk::WriteDescriptorSet {
+            s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+            p_next: ptr::null(),
+            dst_set: descriptor_set,
+            dst_binding: 1,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            p_image_info: descriptor_image_infos.as_ptr(),
+            p_buffer_info: ptr::null(),
+            p_texel_buffer_view: ptr::null(),
+        },
+    ];
 
-        unsafe {
-            device.update_descriptor_sets(&descriptor_write_sets, &[]);
-        }
+    unsafe {
+        device.update_descriptor_sets(&descriptor_write_sets, &[]);
     }
 
-    descriptor_sets
+    descriptor_set
 }
 
 pub fn get_max_usable_sample_count(
@@ -2061,7 +2090,7 @@ fn transition_image_layout(
     end_single_time_command(device, command_pool, submit_queue, command_buffer);
 }
 
-fn begin_single_time_command(
+pub fn begin_single_time_command(
     device: &ash::Device,
     command_pool: vk::CommandPool,
 ) -> vk::CommandBuffer {
@@ -2095,7 +2124,7 @@ fn begin_single_time_command(
     command_buffer
 }
 
-fn end_single_time_command(
+pub fn end_single_time_command(
     device: &ash::Device,
     command_pool: vk::CommandPool,
     submit_queue: vk::Queue,
@@ -0,0 +1,389 @@
+use crate::utility::constants::*;
+use crate::utility::tools::vk_to_string;
+
+use ash::extensions::ext::DebugUtils;
+use ash::vk;
+
+use std::ffi::CString;
+
+#[derive(Clone, Debug)]
+pub struct CounterValue {
+    pub name: String,
+    pub unit: vk::PerformanceCounterUnitKHR,
+    pub value: f64,
+}
+
+// Counters one marked pass of the last resolved frame recorded
+#[derive(Clone, Debug)]
+pub struct PassCounters {
+    pub pass: &'static str,
+    pub counters: Vec<CounterValue>,
+}
+
+// Collects hardware counters around the marked passes: VK_KHR_performance_query through
+// KhrPerformanceCounters, or a vendor library such as the Nsight Perf SDK wrapped the same way.
+// Passes never nest and are numbered in recording order.
+pub trait CounterBackend: Send {
+    fn name(&self) -> &str;
+    // Recorded first into the frame's command buffer
+    fn begin_frame(&mut self, command_buffer: vk::CommandBuffer);
+    fn begin_pass(&mut self, command_buffer: vk::CommandBuffer, pass_index: u32);
+    fn end_pass(&mut self, command_buffer: vk::CommandBuffer, pass_index: u32);
+    // Once the frame finished executing, one entry per pass that has results
+    fn read_results(&mut self, passes: &[&'static str]) -> Vec<PassCounters>;
+}
+
+// Brackets the frame and its passes with debug utils labels, which Nsight Graphics and the
+// Radeon GPU Profiler show as workload regions, and with the hooks of the counter backend
+pub struct GpuProfiler {
+    // None when the instance lacks VK_EXT_debug_utils
+    debug_utils: Option<DebugUtils>,
+    backend: Option<Box<dyn CounterBackend>>,
+    // Marked in the frame being recorded
+    passes: Vec<&'static str>,
+    pass_open: bool,
+    results: Vec<PassCounters>,
+}
+
+impl GpuProfiler {
+    pub fn new(debug_utils: Option<DebugUtils>) -> GpuProfiler {
+        GpuProfiler {
+            debug_utils,
+            backend: None,
+            passes: Vec::new(),
+            pass_open: false,
+            results: Vec::new(),
+        }
+    }
+
+    // Replaces the counter backend, None stops collecting
+    pub fn set_backend(&mut self, backend: Option<Box<dyn CounterBackend>>) {
+        if let Some(backend) = backend.as_ref() {
+            tracing::info!("Collecting GPU counters with {}", backend.name());
+        }
+        self.backend = backend;
+        self.results.clear();
+    }
+
+    pub fn has_backend(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    pub fn begin_frame(&mut self, command_buffer: vk::CommandBuffer) {
+        self.passes.clear();
+        if let Some(backend) = self.backend.as_mut() {
+            backend.begin_frame(command_buffer);
+        }
+        self.begin_label(command_buffer, "frame");
+    }
+
+    pub fn end_frame(&mut self, command_buffer: vk::CommandBuffer) {
+        assert!(!self.pass_open, "The frame ended inside a profiled pass.");
+        self.end_label(command_buffer);
+    }
+
+    pub fn begin_pass(&mut self, command_buffer: vk::CommandBuffer, name: &'static str) {
+        assert!(!self.pass_open, "Profiled passes may not nest.");
+        self.pass_open = true;
+        self.begin_label(command_buffer, name);
+        if let Some(backend) = self.backend.as_mut() {
+            backend.begin_pass(command_buffer, self.passes.len() as u32);
+        }
+        self.passes.push(name);
+    }
+
+    pub fn end_pass(&mut self, command_buffer: vk::CommandBuffer) {
+        assert!(self.pass_open, "No profiled pass to end.");
+        self.pass_open = false;
+        if let Some(backend) = self.backend.as_mut() {
+            backend.end_pass(command_buffer, self.passes.len() as u32 - 1);
+        }
+        self.end_label(command_buffer);
+    }
+
+    // Reads the counters back, the frame's command buffer must have finished executing
+    pub fn resolve(&mut self) {
+        if let Some(backend) = self.backend.as_mut() {
+            self.results = backend.read_results(&self.passes);
+        }
+    }
+
+    // Counters of the last resolved frame, in pass order
+    pub fn results(&self) -> &[PassCounters] {
+        &self.results
+    }
+
+    fn begin_label(&self, command_buffer: vk::CommandBuffer, name: &str) {
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            let name = CString::new(name).unwrap();
+            let label = vk::DebugUtilsLabelEXT::builder().label_name(&name).build();
+            unsafe {
+                debug_utils.cmd_begin_debug_utils_label(command_buffer, &label);
+            }
+        }
+    }
+
+    fn end_label(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            unsafe {
+                debug_utils.cmd_end_debug_utils_label(command_buffer);
+            }
+        }
+    }
+}
+
+// VK_KHR_performance_query counters whose names contain one of PERFORMANCE_COUNTER_KEYWORDS,
+// e.g. shader occupancy and memory throughput. Only counters that fit into a single counter
+// pass and that can be scoped to a span of commands are collected. Holds the profiling lock
+// while it exists.
+pub struct KhrPerformanceCounters {
+    device: ash::Device,
+    performance_query: vk::KhrPerformanceQueryFn,
+    query_pool: vk::QueryPool,
+    // Name, unit and storage of every sampled counter, the counter structs hold a raw p_next
+    counters: Vec<(
+        String,
+        vk::PerformanceCounterUnitKHR,
+        vk::PerformanceCounterStorageKHR,
+    )>,
+}
+
+impl KhrPerformanceCounters {
+    pub fn new(
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        queue_family_index: u32,
+    ) -> Result<KhrPerformanceCounters, String> {
+        let performance_query = vk::KhrPerformanceQueryFn::load(|name| unsafe {
+            std::mem::transmute(entry.get_instance_proc_addr(instance.handle(), name.as_ptr()))
+        });
+
+        let available = unsafe {
+            let mut count = 0;
+            (performance_query
+                .enumerate_physical_device_queue_family_performance_query_counters_khr)(
+                physical_device,
+                queue_family_index,
+                &mut count,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+            .result()
+            .map_err(|err| format!("Failed to count the performance counters: {}", err))?;
+            let mut counters = vec![vk::PerformanceCounterKHR::default(); count as usize];
+            let mut descriptions =
+                vec![vk::PerformanceCounterDescriptionKHR::default(); count as usize];
+            (performance_query
+                .enumerate_physical_device_queue_family_performance_query_counters_khr)(
+                physical_device,
+                queue_family_index,
+                &mut count,
+                counters.as_mut_ptr(),
+                descriptions.as_mut_ptr(),
+            )
+            .result()
+            .map_err(|err| format!("Failed to enumerate the performance counters: {}", err))?;
+            counters.truncate(count as usize);
+            descriptions.truncate(count as usize);
+            counters.into_iter().zip(descriptions)
+        };
+
+        // Command buffer scoped counters would have to start the command buffer
+        let mut selected: Vec<(u32, String, vk::PerformanceCounterKHR)> = available
+            .enumerate()
+            .filter(|(_, (counter, _))| {
+                counter.scope != vk::PerformanceCounterScopeKHR::COMMAND_BUFFER
+            })
+            .map(|(index, (counter, description))| {
+                (index as u32, vk_to_string(&description.name), counter)
+            })
+            .filter(|(_, name, _)| {
+                let name = name.to_lowercase();
+                PERFORMANCE_COUNTER_KEYWORDS
+                    .iter()
+                    .any(|keyword| name.contains(keyword))
+            })
+            .collect();
+
+        // Trailing counters are dropped until the rest can be sampled in a single pass
+        let counter_passes = |selected: &[(u32, String, vk::PerformanceCounterKHR)]| {
+            let counter_indices: Vec<u32> = selected.iter().map(|(index, _, _)| *index).collect();
+            let create_info = vk::QueryPoolPerformanceCreateInfoKHR::builder()
+                .queue_family_index(queue_family_index)
+                .counter_indices(&counter_indices)
+                .build();
+            let mut passes = 0;
+            unsafe {
+                (performance_query.get_physical_device_queue_family_performance_query_passes_khr)(
+                    physical_device,
+                    &create_info,
+                    &mut passes,
+                );
+            }
+            passes
+        };
+        while selected.len() > 1 && counter_passes(&selected) > 1 {
+            let (_, name, _) = selected.pop().unwrap();
+            tracing::debug!("Leaving out counter {:?}, it needs another pass", name);
+        }
+        if selected.is_empty() {
+            return Err(String::from(
+                "No matching single pass performance counters.",
+            ));
+        }
+
+        let counter_indices: Vec<u32> = selected.iter().map(|(index, _, _)| *index).collect();
+        let mut performance_create_info = vk::QueryPoolPerformanceCreateInfoKHR::builder()
+            .queue_family_index(queue_family_index)
+            .counter_indices(&counter_indices)
+            .build();
+        let query_pool_create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::PERFORMANCE_QUERY_KHR)
+            .query_count(MAX_PROFILED_PASSES)
+            .push_next(&mut performance_create_info)
+            .build();
+
+        unsafe {
+            // Has to be held before recording any command buffer with performance queries
+            let lock_info = vk::AcquireProfilingLockInfoKHR::builder()
+                .timeout(u64::MAX)
+                .build();
+            (performance_query.acquire_profiling_lock_khr)(device.handle(), &lock_info)
+                .result()
+                .map_err(|err| format!("Failed to acquire the profiling lock: {}", err))?;
+
+            let query_pool = match device.create_query_pool(&query_pool_create_info, None) {
+                Ok(query_pool) => query_pool,
+                Err(err) => {
+                    (performance_query.release_profiling_lock_khr)(device.handle());
+                    return Err(format!("Failed to create the query pool: {}", err));
+                }
+            };
+
+            Ok(KhrPerformanceCounters {
+                device: device.clone(),
+                performance_query,
+                query_pool,
+                counters: selected
+                    .into_iter()
+                    .map(|(_, name, counter)| (name, counter.unit, counter.storage))
+                    .collect(),
+            })
+        }
+    }
+}
+
+impl CounterBackend for KhrPerformanceCounters {
+    fn name(&self) -> &str {
+        "VK_KHR_performance_query"
+    }
+
+    fn begin_frame(&mut self, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            self.device.cmd_reset_query_pool(
+                command_buffer,
+                self.query_pool,
+                0,
+                MAX_PROFILED_PASSES,
+            );
+        }
+    }
+
+    fn begin_pass(&mut self, command_buffer: vk::CommandBuffer, pass_index: u32) {
+        if pass_index < MAX_PROFILED_PASSES {
+            unsafe {
+                self.device.cmd_begin_query(
+                    command_buffer,
+                    self.query_pool,
+                    pass_index,
+                    vk::QueryControlFlags::empty(),
+                );
+            }
+        }
+    }
+
+    fn end_pass(&mut self, command_buffer: vk::CommandBuffer, pass_index: u32) {
+        if pass_index < MAX_PROFILED_PASSES {
+            unsafe {
+                self.device
+                    .cmd_end_query(command_buffer, self.query_pool, pass_index);
+            }
+        }
+    }
+
+    fn read_results(&mut self, passes: &[&'static str]) -> Vec<PassCounters> {
+        let query_count = (passes.len() as u32).min(MAX_PROFILED_PASSES);
+        if query_count == 0 {
+            return Vec::new();
+        }
+
+        // One result per counter for every query
+        let stride = self.counters.len() * std::mem::size_of::<vk::PerformanceCounterResultKHR>();
+        let mut results = vec![
+            vk::PerformanceCounterResultKHR::default();
+            self.counters.len() * query_count as usize
+        ];
+        let read = unsafe {
+            (self.device.fp_v1_0().get_query_pool_results)(
+                self.device.handle(),
+                self.query_pool,
+                0,
+                query_count,
+                stride * query_count as usize,
+                results.as_mut_ptr() as *mut std::ffi::c_void,
+                stride as vk::DeviceSize,
+                vk::QueryResultFlags::WAIT,
+            )
+            .result()
+        };
+        if let Err(err) = read {
+            tracing::warn!("Failed to read the performance counters: {}", err);
+            return Vec::new();
+        }
+
+        passes
+            .iter()
+            .zip(results.chunks_exact(self.counters.len()))
+            .map(|(pass, values)| PassCounters {
+                pass,
+                counters: self
+                    .counters
+                    .iter()
+                    .zip(values)
+                    .map(|((name, unit, storage), value)| CounterValue {
+                        name: name.clone(),
+                        unit: *unit,
+                        value: counter_value(*storage, value),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+impl Drop for KhrPerformanceCounters {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_query_pool(self.query_pool, None);
+            (self.performance_query.release_profiling_lock_khr)(self.device.handle());
+        }
+    }
+}
+
+fn counter_value(
+    storage: vk::PerformanceCounterStorageKHR,
+    value: &vk::PerformanceCounterResultKHR,
+) -> f64 {
+    unsafe {
+        match storage {
+            vk::PerformanceCounterStorageKHR::INT32 => value.int32 as f64,
+            vk::PerformanceCounterStorageKHR::INT64 => value.int64 as f64,
+            vk::PerformanceCounterStorageKHR::UINT32 => value.uint32 as f64,
+            vk::PerformanceCounterStorageKHR::UINT64 => value.uint64 as f64,
+            vk::PerformanceCounterStorageKHR::FLOAT32 => value.float32 as f64,
+            _ => value.float64,
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use ash::vk;
+use exr::prelude::f16;
+
+// Formats the ray traced HDR image can be kept in, picked with `--hdr_format=<name>`. The
+// shaders load and store it without a format qualifier, so any of them can be bound.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HdrFormat {
+    Rgba16f,
+    Rgba32f,
+    // Half the memory of Rgba16f, alpha reads back as 1. Storage support is optional.
+    R11g11b10f,
+}
+
+// Every use of the offscreen target: traced into, loaded by the exposure pass, drawn over by the
+// debug overlay and the shadow map pass, cleared, and copied out
+pub const HDR_TARGET_FEATURES: vk::FormatFeatureFlags = vk::FormatFeatureFlags::from_raw(
+    vk::FormatFeatureFlags::STORAGE_IMAGE.as_raw()
+        | vk::FormatFeatureFlags::COLOR_ATTACHMENT.as_raw()
+        | vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND.as_raw()
+        | vk::FormatFeatureFlags::TRANSFER_SRC.as_raw()
+        | vk::FormatFeatureFlags::TRANSFER_DST.as_raw(),
+);
+
+impl HdrFormat {
+    // In the order they are tried when the requested format is unsupported
+    pub const ALL: [HdrFormat; 3] = [
+        HdrFormat::Rgba16f,
+        HdrFormat::Rgba32f,
+        HdrFormat::R11g11b10f,
+    ];
+
+    pub fn from_name(name: &str) -> Option<HdrFormat> {
+        HdrFormat::ALL
+            .into_iter()
+            .find(|format| format.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HdrFormat::Rgba16f => "rgba16f",
+            HdrFormat::Rgba32f => "rgba32f",
+            HdrFormat::R11g11b10f => "r11g11b10f",
+        }
+    }
+
+    pub fn vk_format(self) -> vk::Format {
+        match self {
+            HdrFormat::Rgba16f => vk::Format::R16G16B16A16_SFLOAT,
+            HdrFormat::Rgba32f => vk::Format::R32G32B32A32_SFLOAT,
+            HdrFormat::R11g11b10f => vk::Format::B10G11R11_UFLOAT_PACK32,
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> u32 {
+        match self {
+            HdrFormat::Rgba16f => 8,
+            HdrFormat::Rgba32f => 16,
+            HdrFormat::R11g11b10f => 4,
+        }
+    }
+
+    pub fn is_supported(
+        self,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> bool {
+        let properties = unsafe {
+            instance.get_physical_device_format_properties(physical_device, self.vk_format())
+        };
+        properties
+            .optimal_tiling_features
+            .contains(HDR_TARGET_FEATURES)
+    }
+
+    /// Tightly packed pixels of an image in this format as RGBA
+    pub fn decode(self, bytes: &[u8]) -> Vec<f32> {
+        match self {
+            HdrFormat::Rgba16f => bytes
+                .chunks_exact(2)
+                .map(|half| f16::from_ne_bytes([half[0], half[1]]).to_f32())
+                .collect(),
+            HdrFormat::Rgba32f => bytes
+                .chunks_exact(4)
+                .map(|float| f32::from_ne_bytes([float[0], float[1], float[2], float[3]]))
+                .collect(),
+            HdrFormat::R11g11b10f => bytes
+                .chunks_exact(4)
+                .flat_map(|pixel| {
+                    let packed = u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                    [
+                        unsigned_float_to_f32(packed & 0x7ff, 6),
+                        unsigned_float_to_f32((packed >> 11) & 0x7ff, 6),
+                        unsigned_float_to_f32(packed >> 22, 5),
+                        1.0,
+                    ]
+                })
+                .collect(),
+        }
+    }
+
+    /// RGBA pixels as an upload of an image in this format
+    pub fn encode(self, pixels: &[f32]) -> Vec<u8> {
+        match self {
+            HdrFormat::Rgba16f => pixels
+                .iter()
+                .flat_map(|&value| f16::from_f32(value).to_ne_bytes())
+                .collect(),
+            HdrFormat::Rgba32f => pixels
+                .iter()
+                .flat_map(|&value| value.to_ne_bytes())
+                .collect(),
+            HdrFormat::R11g11b10f => pixels
+                .chunks_exact(4)
+                .flat_map(|pixel| {
+                    let packed = f32_to_unsigned_float(pixel[0], 6)
+                        | (f32_to_unsigned_float(pixel[1], 6) << 11)
+                        | (f32_to_unsigned_float(pixel[2], 5) << 22);
+                    packed.to_ne_bytes()
+                })
+                .collect(),
+        }
+    }
+}
+
+// Negative values and NaN become 0, anything too large the largest finite value
+fn f32_to_unsigned_float(value: f32, mantissa_bits: u32) -> u32 {
+    let largest = (0x1e << mantissa_bits) | ((1 << mantissa_bits) - 1);
+    if value.is_nan() || value <= 0.0 {
+        return 0;
+    }
+    let bits = value.to_bits();
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let packed = if exponent <= 0 {
+        // Subnormal, rounding up to the smallest normal carries into the exponent
+        (value * 2f32.powi(14) * (1 << mantissa_bits) as f32).round() as u32
+    } else {
+        let shift = 23 - mantissa_bits;
+        let mantissa = (bits & 0x7f_ffff) + (1 << (shift - 1));
+        ((exponent as u32) << mantissa_bits) + (mantissa >> shift)
+    };
+    packed.min(largest)
+}
+
+// The 11 and 10 bit floats of B10G11R11_UFLOAT_PACK32: a 5 bit exponent biased by 15 above the
+// mantissa, no sign
+fn unsigned_float_to_f32(bits: u32, mantissa_bits: u32) -> f32 {
+    let exponent = (bits >> mantissa_bits) as i32;
+    let mantissa = (bits & ((1 << mantissa_bits) - 1)) as f32 / (1 << mantissa_bits) as f32;
+    match exponent {
+        0 => mantissa * 2f32.powi(-14),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa) * 2f32.powi(exponent - 15),
+    }
+}
+
+// The requested format if the device supports it, the first supported one of HdrFormat::ALL
+// otherwise
+pub fn choose_hdr_format(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    requested: HdrFormat,
+) -> HdrFormat {
+    let features = unsafe { instance.get_physical_device_features(physical_device) };
+    if features.shader_storage_image_read_without_format != vk::TRUE
+        || features.shader_storage_image_write_without_format != vk::TRUE
+    {
+        panic!("The device cannot access storage images without a format qualifier.");
+    }
+
+    if requested.is_supported(instance, physical_device) {
+        return requested;
+    }
+    let fallback = HdrFormat::ALL
+        .into_iter()
+        .find(|format| format.is_supported(instance, physical_device))
+        .expect("No HDR format supports storage, color attachment and transfer use.");
+    tracing::warn!(
+        "{} cannot be used for the offscreen target, using {} instead",
+        requested.name(),
+        fallback.name()
+    );
+    fallback
+}
@@ -0,0 +1,305 @@
+use crate::utility::constants::*;
+
+use image::{ColorType, Rgb, Rgb32FImage, RgbImage};
+use std::path::Path;
+
+// What counts as a match. The tolerances are per channel in linear color, and are widened
+// towards the corners by `radial` times the squared distance from the center (1 at the corners),
+// where vignetting and lens effects make renders noisier.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiffSettings {
+    pub tolerance: [f32; 3],
+    pub radial: f32,
+}
+
+impl Default for DiffSettings {
+    fn default() -> Self {
+        DiffSettings {
+            tolerance: [IMAGE_DIFF_TOLERANCE; 3],
+            radial: 0.0,
+        }
+    }
+}
+
+impl DiffSettings {
+    // `0.01` for every channel or `0.01,0.02,0.01` per channel
+    pub fn parse_tolerance(&mut self, value: &str) -> Result<(), String> {
+        let values: Vec<f32> = value
+            .split(',')
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| format!("Invalid tolerance {}", value))
+            })
+            .collect::<Result<_, _>>()?;
+        self.tolerance = match values[..] {
+            [all] => [all; 3],
+            [r, g, b] => [r, g, b],
+            _ => return Err("Expected one tolerance or one per channel".to_string()),
+        };
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiffReport {
+    pub width: u32,
+    pub height: u32,
+    // Pixels with a channel past its tolerance
+    pub mismatched_pixels: u64,
+    pub max_difference: [f32; 3],
+    // Of the sRGB encoded images clamped to [0, 1], infinite when they are the same
+    pub psnr: f64,
+    // Mean SSIM of the sRGB encoded luma over IMAGE_DIFF_SSIM_WINDOW sized windows, 1 when the
+    // images are the same
+    pub ssim: f64,
+}
+
+impl DiffReport {
+    pub fn matches(&self) -> bool {
+        self.mismatched_pixels == 0
+    }
+}
+
+pub struct ImageDiff {
+    pub report: DiffReport,
+    // The first image darkened, with the mismatched pixels from red to yellow as they get
+    // further past their tolerance
+    pub heatmap: RgbImage,
+}
+
+// Any format the image crate reads, in linear color. Integer images are taken as sRGB encoded,
+// float ones (EXR, HDR) as linear already. Alpha is dropped.
+pub fn load_linear(path: &Path) -> Result<Rgb32FImage, String> {
+    let image = image::open(path).map_err(|err| format!("Failed to open {:?}: {}", path, err))?;
+    let is_float = matches!(image.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+    let mut image = image.into_rgb32f();
+    if !is_float {
+        for channel in image.iter_mut() {
+            *channel = srgb_to_linear(*channel);
+        }
+    }
+    Ok(image)
+}
+
+pub fn diff_files(a: &Path, b: &Path, settings: &DiffSettings) -> Result<ImageDiff, String> {
+    diff(&load_linear(a)?, &load_linear(b)?, settings)
+}
+
+pub fn diff(
+    a: &Rgb32FImage,
+    b: &Rgb32FImage,
+    settings: &DiffSettings,
+) -> Result<ImageDiff, String> {
+    if a.dimensions() != b.dimensions() {
+        return Err(format!(
+            "The images differ in size, {:?} and {:?}",
+            a.dimensions(),
+            b.dimensions()
+        ));
+    }
+    let (width, height) = a.dimensions();
+    let center = [width as f32 * 0.5, height as f32 * 0.5];
+    let corner_distance_squared = (center[0] * center[0] + center[1] * center[1]).max(1.0);
+
+    let mut report = DiffReport {
+        width,
+        height,
+        mismatched_pixels: 0,
+        max_difference: [0.0; 3],
+        psnr: f64::INFINITY,
+        ssim: 1.0,
+    };
+    let mut heatmap = RgbImage::new(width, height);
+    let mut squared_error_sum = 0.0f64;
+    for (x, y, pixel_a) in a.enumerate_pixels() {
+        let pixel_b = b.get_pixel(x, y);
+        let dx = x as f32 + 0.5 - center[0];
+        let dy = y as f32 + 0.5 - center[1];
+        let widening = 1.0 + settings.radial * (dx * dx + dy * dy) / corner_distance_squared;
+
+        // How far past its tolerance the worst channel is, in multiples of the tolerance
+        let mut excess = 0.0f32;
+        for channel in 0..3 {
+            let difference = (pixel_a[channel] - pixel_b[channel]).abs();
+            report.max_difference[channel] = report.max_difference[channel].max(difference);
+            let tolerance = settings.tolerance[channel] * widening;
+            if difference > tolerance {
+                excess = excess.max((difference - tolerance) / tolerance.max(f32::EPSILON));
+            }
+
+            let error = linear_to_srgb(pixel_a[channel]) - linear_to_srgb(pixel_b[channel]);
+            squared_error_sum += (error * error) as f64;
+        }
+
+        let color = if excess > 0.0 {
+            report.mismatched_pixels += 1;
+            let heat = (excess / IMAGE_DIFF_HEATMAP_RANGE).min(1.0);
+            Rgb([255, (heat * 255.0) as u8, 0])
+        } else {
+            let shade =
+                (linear_to_srgb(luminance(pixel_a)) * IMAGE_DIFF_HEATMAP_SHADE * 255.0) as u8;
+            Rgb([shade, shade, shade])
+        };
+        heatmap.put_pixel(x, y, color);
+    }
+
+    let mean_squared_error = squared_error_sum / (3 * width as u64 * height as u64).max(1) as f64;
+    if mean_squared_error > 0.0 {
+        report.psnr = -10.0 * mean_squared_error.log10();
+    }
+    report.ssim = mean_ssim(a, b);
+    Ok(ImageDiff { report, heatmap })
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Clamped to [0, 1], the range of the displayed image
+fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn luminance(pixel: &Rgb<f32>) -> f32 {
+    0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2]
+}
+
+// Wang et al.'s SSIM with uniform windows, stepping half a window at a time. Images smaller than
+// a window are one window.
+fn mean_ssim(a: &Rgb32FImage, b: &Rgb32FImage) -> f64 {
+    // Of the [0, 1] dynamic range
+    const C1: f64 = 0.01 * 0.01;
+    const C2: f64 = 0.03 * 0.03;
+
+    let (width, height) = a.dimensions();
+    if width == 0 || height == 0 {
+        return 1.0;
+    }
+    let luma = |image: &Rgb32FImage, x: u32, y: u32| {
+        linear_to_srgb(luminance(image.get_pixel(x, y))) as f64
+    };
+    let window_width = IMAGE_DIFF_SSIM_WINDOW.min(width);
+    let window_height = IMAGE_DIFF_SSIM_WINDOW.min(height);
+    let step_x = (window_width / 2).max(1);
+    let step_y = (window_height / 2).max(1);
+
+    let mut ssim_sum = 0.0;
+    let mut window_count = 0;
+    for top in (0..=height - window_height).step_by(step_y as usize) {
+        for left in (0..=width - window_width).step_by(step_x as usize) {
+            let (mut sum_a, mut sum_b) = (0.0, 0.0);
+            let (mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0);
+            for y in top..top + window_height {
+                for x in left..left + window_width {
+                    let (value_a, value_b) = (luma(a, x, y), luma(b, x, y));
+                    sum_a += value_a;
+                    sum_b += value_b;
+                    sum_aa += value_a * value_a;
+                    sum_bb += value_b * value_b;
+                    sum_ab += value_a * value_b;
+                }
+            }
+            let count = (window_width * window_height) as f64;
+            let (mean_a, mean_b) = (sum_a / count, sum_b / count);
+            let variance_a = sum_aa / count - mean_a * mean_a;
+            let variance_b = sum_bb / count - mean_b * mean_b;
+            let covariance = sum_ab / count - mean_a * mean_b;
+
+            ssim_sum += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2));
+            window_count += 1;
+        }
+    }
+    ssim_sum / window_count as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 16;
+
+    fn gradient() -> Rgb32FImage {
+        Rgb32FImage::from_fn(SIZE, SIZE, |x, y| {
+            Rgb([x as f32 / SIZE as f32, y as f32 / SIZE as f32, 0.25])
+        })
+    }
+
+    fn is_mismatch(pixel: &Rgb<u8>) -> bool {
+        pixel[0] == 255 && pixel[2] == 0
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = gradient();
+        let diff = diff(&image, &image, &DiffSettings::default()).unwrap();
+        assert!(diff.report.matches());
+        assert_eq!(diff.report.max_difference, [0.0; 3]);
+        assert_eq!(diff.report.psnr, f64::INFINITY);
+        assert_eq!(diff.report.ssim, 1.0);
+        assert!(!diff.heatmap.pixels().any(is_mismatch));
+    }
+
+    #[test]
+    fn changed_pixel_shows_in_the_heatmap() {
+        let a = gradient();
+        let mut b = a.clone();
+        b.get_pixel_mut(5, 7)[1] += 0.5;
+        let diff = diff(&a, &b, &DiffSettings::default()).unwrap();
+        assert_eq!(diff.report.mismatched_pixels, 1);
+        assert_eq!(diff.report.max_difference[1], 0.5);
+        assert!(diff.report.psnr.is_finite());
+        assert!(diff.report.ssim < 1.0);
+        for (x, y, pixel) in diff.heatmap.enumerate_pixels() {
+            assert_eq!(is_mismatch(pixel), (x, y) == (5, 7), "at {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn differences_within_the_tolerance_match() {
+        let a = gradient();
+        let mut b = a.clone();
+        b.get_pixel_mut(8, 8)[1] += 0.004;
+        let mut settings = DiffSettings::default();
+        assert!(diff(&a, &b, &settings).unwrap().report.matches());
+
+        settings.parse_tolerance("0.01,0.001,0.01").unwrap();
+        assert_eq!(settings.tolerance, [0.01, 0.001, 0.01]);
+        assert_eq!(diff(&a, &b, &settings).unwrap().report.mismatched_pixels, 1);
+
+        assert!(settings.parse_tolerance("0.01,0.01").is_err());
+        assert!(settings.parse_tolerance("loose").is_err());
+    }
+
+    #[test]
+    fn radial_tolerance_widens_towards_the_corners() {
+        let a = gradient();
+        let mut b = a.clone();
+        b.get_pixel_mut(0, 0)[2] += 0.008;
+        let mut settings = DiffSettings::default();
+        assert!(!diff(&a, &b, &settings).unwrap().report.matches());
+
+        settings.radial = 1.0;
+        assert!(diff(&a, &b, &settings).unwrap().report.matches());
+        // Not at the center
+        b = a.clone();
+        b.get_pixel_mut(SIZE / 2, SIZE / 2)[2] += 0.008;
+        assert!(!diff(&a, &b, &settings).unwrap().report.matches());
+    }
+
+    #[test]
+    fn sizes_must_agree() {
+        let small = Rgb32FImage::new(SIZE / 2, SIZE);
+        assert!(diff(&gradient(), &small, &DiffSettings::default()).is_err());
+    }
+}
@@ -0,0 +1,412 @@
+use crate::utility::constants::*;
+use crate::utility::user_settings::UserSettings;
+
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use winit::event::VirtualKeyCode;
+
+// What the window loop does with its input. Exit and ToggleConsole fire once per press, the
+// others are held and read as a level in [0, 1] every simulation step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Exit,
+    ToggleConsole,
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    LookLeft,
+    LookRight,
+    LookUp,
+    LookDown,
+}
+
+const ACTIONS: [(Action, &str); 12] = [
+    (Action::Exit, "exit"),
+    (Action::ToggleConsole, "toggle_console"),
+    (Action::MoveForward, "move_forward"),
+    (Action::MoveBackward, "move_backward"),
+    (Action::MoveLeft, "move_left"),
+    (Action::MoveRight, "move_right"),
+    (Action::MoveUp, "move_up"),
+    (Action::MoveDown, "move_down"),
+    (Action::LookLeft, "look_left"),
+    (Action::LookRight, "look_right"),
+    (Action::LookUp, "look_up"),
+    (Action::LookDown, "look_down"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    #[cfg(feature = "gamepad")]
+    Button(gilrs::Button),
+    // One half of a stick axis, true for the positive half
+    #[cfg(feature = "gamepad")]
+    Axis(gilrs::Axis, bool),
+}
+
+// Keys that can be bound, by their VirtualKeyCode names
+const BINDABLE_KEYS: [VirtualKeyCode; 64] = {
+    use VirtualKeyCode::*;
+    [
+        Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, A, B, C, D, E, F, G, H, I, J,
+        K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9,
+        F10, F11, F12, Left, Up, Right, Down, Back, Return, Space, Tab, Grave, LShift, RShift,
+        LControl, RControl, PageUp, PageDown,
+    ]
+};
+
+#[cfg(feature = "gamepad")]
+const BINDABLE_BUTTONS: [gilrs::Button; 19] = {
+    use gilrs::Button::*;
+    [
+        South,
+        East,
+        North,
+        West,
+        C,
+        Z,
+        LeftTrigger,
+        LeftTrigger2,
+        RightTrigger,
+        RightTrigger2,
+        Select,
+        Start,
+        Mode,
+        LeftThumb,
+        RightThumb,
+        DPadUp,
+        DPadDown,
+        DPadLeft,
+        DPadRight,
+    ]
+};
+
+#[cfg(feature = "gamepad")]
+const BINDABLE_AXES: [gilrs::Axis; 6] = {
+    use gilrs::Axis::*;
+    [
+        LeftStickX,
+        LeftStickY,
+        LeftZ,
+        RightStickX,
+        RightStickY,
+        RightZ,
+    ]
+};
+
+impl Binding {
+    // Keys by name, gamepad buttons as `pad.South` and stick halves as `pad.LeftStickX+`
+    pub fn parse(name: &str) -> Result<Binding, String> {
+        match name.strip_prefix("pad.") {
+            Some(name) => Binding::parse_gamepad(name),
+            None => BINDABLE_KEYS
+                .iter()
+                .find(|key| format!("{:?}", key) == name)
+                .map(|key| Binding::Key(*key))
+                .ok_or_else(|| format!("Unknown key {}", name)),
+        }
+    }
+
+    #[cfg(feature = "gamepad")]
+    fn parse_gamepad(name: &str) -> Result<Binding, String> {
+        let axis = |name: &str| {
+            BINDABLE_AXES
+                .iter()
+                .find(|axis| format!("{:?}", axis) == name)
+                .copied()
+        };
+        let binding = if let Some(name) = name.strip_suffix('+') {
+            axis(name).map(|axis| Binding::Axis(axis, true))
+        } else if let Some(name) = name.strip_suffix('-') {
+            axis(name).map(|axis| Binding::Axis(axis, false))
+        } else {
+            BINDABLE_BUTTONS
+                .iter()
+                .find(|button| format!("{:?}", button) == name)
+                .map(|button| Binding::Button(*button))
+        };
+        binding.ok_or_else(|| format!("Unknown gamepad input {}", name))
+    }
+
+    #[cfg(not(feature = "gamepad"))]
+    fn parse_gamepad(name: &str) -> Result<Binding, String> {
+        Err(format!(
+            "Built without the gamepad feature, pad.{} is unused",
+            name
+        ))
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Binding::Key(key) => format!("{:?}", key),
+            #[cfg(feature = "gamepad")]
+            Binding::Button(button) => format!("pad.{:?}", button),
+            #[cfg(feature = "gamepad")]
+            Binding::Axis(axis, positive) => {
+                format!("pad.{:?}{}", axis, if *positive { '+' } else { '-' })
+            }
+        }
+    }
+}
+
+// Action to key/button/axis bindings, read from a `action = binding, binding` file next to the
+// user settings. An action may have any number of bindings and a binding any number of actions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputMap {
+    bindings: Vec<(Action, Binding)>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let keys = DEFAULT_KEY_BINDINGS
+            .iter()
+            .map(|(action, key)| (*action, Binding::Key(*key)));
+        #[cfg(feature = "gamepad")]
+        let keys = keys.chain(DEFAULT_GAMEPAD_BINDINGS.iter().copied());
+        InputMap {
+            bindings: keys.collect(),
+        }
+    }
+}
+
+impl InputMap {
+    pub fn path() -> Option<PathBuf> {
+        UserSettings::path().map(|path| path.with_file_name(INPUT_MAP_FILE))
+    }
+
+    // The defaults are written out when there is no file yet, to have something to edit
+    pub fn load() -> InputMap {
+        let path = match InputMap::path() {
+            Some(path) => path,
+            None => return InputMap::default(),
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                tracing::debug!("Loading input bindings from {:?}", path);
+                InputMap::parse(&text)
+            }
+            Err(_) => {
+                let input_map = InputMap::default();
+                let result = path
+                    .parent()
+                    .map_or(Ok(()), std::fs::create_dir_all)
+                    .and_then(|_| std::fs::write(&path, input_map.to_text()));
+                if let Err(err) = result {
+                    tracing::warn!("Failed to write input bindings to {:?}: {}", path, err);
+                }
+                input_map
+            }
+        }
+    }
+
+    // Actions missing from the text keep their default bindings, an empty value unbinds one
+    pub fn parse(text: &str) -> InputMap {
+        let mut input_map = InputMap::default();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => {
+                    tracing::warn!("Ignoring input bindings line {:?}", line);
+                    continue;
+                }
+            };
+            let action = match ACTIONS.iter().find(|(_, action_name)| *action_name == name) {
+                Some((action, _)) => *action,
+                None => {
+                    tracing::warn!("Ignoring unknown action {}", name);
+                    continue;
+                }
+            };
+            input_map.bindings.retain(|(bound, _)| *bound != action);
+            for binding in value.split(',').map(str::trim).filter(|b| !b.is_empty()) {
+                match Binding::parse(binding) {
+                    Ok(binding) => input_map.bindings.push((action, binding)),
+                    Err(message) => tracing::warn!("{} for {}", message, name),
+                }
+            }
+        }
+        input_map
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (action, name) in ACTIONS.iter() {
+            let bindings: Vec<String> = self.bindings(*action).map(|b| b.name()).collect();
+            let _ = writeln!(text, "{} = {}", name, bindings.join(", "));
+        }
+        text
+    }
+
+    pub fn bindings(&self, action: Action) -> impl Iterator<Item = &Binding> {
+        self.bindings
+            .iter()
+            .filter(move |(bound, _)| *bound == action)
+            .map(|(_, binding)| binding)
+    }
+
+    pub fn actions(&self, binding: Binding) -> impl Iterator<Item = Action> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, bound)| *bound == binding)
+            .map(|(action, _)| *action)
+    }
+}
+
+// The bindings and how far each of them is held down
+#[derive(Clone, Debug, Default)]
+pub struct Input {
+    pub map: InputMap,
+    levels: Vec<(Binding, f32)>,
+}
+
+impl Input {
+    pub fn new(map: InputMap) -> Input {
+        Input {
+            map,
+            levels: Vec::new(),
+        }
+    }
+
+    pub fn set_level(&mut self, binding: Binding, level: f32) {
+        self.levels.retain(|(held, _)| *held != binding);
+        if level > 0.0 {
+            self.levels.push((binding, level.min(1.0)));
+        }
+    }
+
+    // Splits a stick axis in [-1, 1] into its halves, inside the dead zone both are released
+    #[cfg(feature = "gamepad")]
+    pub fn set_axis(&mut self, axis: gilrs::Axis, value: f32) {
+        let value = if value.abs() < GAMEPAD_DEAD_ZONE {
+            0.0
+        } else {
+            value
+        };
+        self.set_level(Binding::Axis(axis, true), value);
+        self.set_level(Binding::Axis(axis, false), -value);
+    }
+
+    // Keys stay held while the window is out of focus or the console takes the keyboard
+    pub fn release_keys(&mut self) {
+        self.levels
+            .retain(|(binding, _)| !matches!(binding, Binding::Key(_)));
+    }
+
+    #[cfg(feature = "gamepad")]
+    pub fn release_gamepad(&mut self) {
+        self.levels
+            .retain(|(binding, _)| matches!(binding, Binding::Key(_)));
+    }
+
+    // The strongest of the action's bindings
+    pub fn level(&self, action: Action) -> f32 {
+        self.map
+            .bindings(action)
+            .filter_map(|binding| {
+                self.levels
+                    .iter()
+                    .find(|(held, _)| held == binding)
+                    .map(|(_, level)| *level)
+            })
+            .fold(0.0, f32::max)
+    }
+
+    // In [-1, 1], from `negative` to `positive`
+    pub fn axis(&self, positive: Action, negative: Action) -> f32 {
+        self.level(positive) - self.level(negative)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn same_bindings(a: &InputMap, b: &InputMap) -> bool {
+        ACTIONS
+            .iter()
+            .all(|(action, _)| a.bindings(*action).eq(b.bindings(*action)))
+    }
+
+    #[test]
+    fn binding_names_round_trip() {
+        for key in BINDABLE_KEYS {
+            let binding = Binding::Key(key);
+            assert_eq!(Binding::parse(&binding.name()), Ok(binding));
+        }
+        assert!(Binding::parse("NotAKey").is_err());
+        assert!(Binding::parse("pad.NotAButton").is_err());
+    }
+
+    #[cfg(feature = "gamepad")]
+    #[test]
+    fn gamepad_binding_names_round_trip() {
+        let buttons = BINDABLE_BUTTONS.map(Binding::Button);
+        let axes = BINDABLE_AXES
+            .iter()
+            .flat_map(|&axis| [Binding::Axis(axis, true), Binding::Axis(axis, false)]);
+        for binding in buttons.into_iter().chain(axes) {
+            assert_eq!(Binding::parse(&binding.name()), Ok(binding));
+        }
+    }
+
+    #[test]
+    fn input_maps_round_trip_through_text() {
+        let defaults = InputMap::default();
+        assert!(same_bindings(
+            &InputMap::parse(&defaults.to_text()),
+            &defaults
+        ));
+
+        let edited = InputMap::parse("exit = Q, F10\nmove_up = Space\nlook_left =\n");
+        let reparsed = InputMap::parse(&edited.to_text());
+        assert!(same_bindings(&reparsed, &edited));
+        assert_eq!(reparsed.to_text(), edited.to_text());
+    }
+
+    #[test]
+    fn parsing_keeps_defaults_and_skips_what_it_does_not_know() {
+        let input_map = InputMap::parse(
+            "# comment\nexit = Q, F10\nlook_left =\nmove_down = NotAKey, Z\nfly = F\nnonsense\n",
+        );
+        let keys = |action| {
+            input_map
+                .bindings(action)
+                .filter(|binding| matches!(binding, Binding::Key(_)))
+                .copied()
+                .collect::<Vec<Binding>>()
+        };
+        use VirtualKeyCode::*;
+        assert_eq!(keys(Action::Exit), [Binding::Key(Q), Binding::Key(F10)]);
+        assert_eq!(keys(Action::LookLeft), []);
+        assert_eq!(keys(Action::MoveDown), [Binding::Key(Z)]);
+        assert_eq!(keys(Action::MoveForward), [Binding::Key(W)]);
+        // A binding may trigger several actions
+        let actions: Vec<Action> = input_map.actions(Binding::Key(Q)).collect();
+        assert_eq!(actions, [Action::Exit]);
+        let input_map = InputMap::parse("move_up = Q");
+        let actions: Vec<Action> = input_map.actions(Binding::Key(Q)).collect();
+        assert_eq!(actions, [Action::MoveDown, Action::MoveUp]);
+    }
+
+    #[test]
+    fn levels_take_the_strongest_binding() {
+        let mut input = Input::new(InputMap::parse("move_forward = W, Up"));
+        input.set_level(Binding::Key(VirtualKeyCode::W), 1.0);
+        input.set_level(Binding::Key(VirtualKeyCode::Up), 2.0);
+        assert_eq!(input.level(Action::MoveForward), 1.0);
+        assert_eq!(input.axis(Action::MoveForward, Action::MoveBackward), 1.0);
+
+        input.set_level(Binding::Key(VirtualKeyCode::S), 1.0);
+        assert_eq!(input.axis(Action::MoveForward, Action::MoveBackward), 0.0);
+        input.release_keys();
+        assert_eq!(input.level(Action::MoveForward), 0.0);
+    }
+}
@@ -0,0 +1,106 @@
+use super::structures::{AliasEntry, EmissiveTriangle, InstanceTransform};
+
+fn luminance(color: [f32; 3]) -> f32 {
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
+
+fn transform_point(transform: &InstanceTransform, point: [f32; 3]) -> [f32; 4] {
+    let [x, y, z] = transform.transform_point(point);
+    [x, y, z, 1.0]
+}
+
+fn triangle_area(v0: [f32; 4], v1: [f32; 4], v2: [f32; 4]) -> f32 {
+    let e1 = [v1[0] - v0[0], v1[1] - v0[1], v1[2] - v0[2]];
+    let e2 = [v2[0] - v0[0], v2[1] - v0[1], v2[2] - v0[2]];
+    let cross = [
+        e1[1] * e2[2] - e1[2] * e2[1],
+        e1[2] * e2[0] - e1[0] * e2[2],
+        e1[0] * e2[1] - e1[1] * e2[0],
+    ];
+    0.5 * (cross[0] * cross[0] + cross[1] * cross[1] + cross[2] * cross[2]).sqrt()
+}
+
+/// World space triangles of every (transform, emission) instance that emits light
+pub fn gather_emissive_triangles(
+    positions: &[[f32; 3]],
+    instances: &[(InstanceTransform, [f32; 4])],
+) -> Vec<EmissiveTriangle> {
+    let mut triangles = Vec::new();
+
+    for (transform, emission) in instances.iter() {
+        if luminance([emission[0], emission[1], emission[2]]) <= 0.0 {
+            continue;
+        }
+
+        for triangle in positions.chunks_exact(3) {
+            let v0 = transform_point(transform, triangle[0]);
+            let v1 = transform_point(transform, triangle[1]);
+            let v2 = transform_point(transform, triangle[2]);
+            let area = triangle_area(v0, v1, v2);
+            if area <= 0.0 {
+                continue;
+            }
+
+            triangles.push(EmissiveTriangle {
+                v0,
+                v1,
+                v2,
+                emission: [emission[0], emission[1], emission[2], area],
+            });
+        }
+    }
+
+    triangles
+}
+
+/// Sampling weight of an emissive triangle, proportional to the power it emits
+pub fn emitted_power(triangle: &EmissiveTriangle) -> f32 {
+    let emission = triangle.emission;
+    luminance([emission[0], emission[1], emission[2]]) * emission[3]
+}
+
+/// Vose alias table: picks an index proportionally to `weights` with a single lookup
+pub fn build_alias_table(weights: &[f32]) -> Vec<AliasEntry> {
+    let total: f32 = weights.iter().sum();
+    if weights.is_empty() || total <= 0.0 {
+        return Vec::new();
+    }
+
+    let count = weights.len();
+    let mut scaled: Vec<f32> = weights
+        .iter()
+        .map(|weight| weight * count as f32 / total)
+        .collect();
+    let mut entries: Vec<AliasEntry> = weights
+        .iter()
+        .enumerate()
+        .map(|(index, weight)| AliasEntry {
+            threshold: 1.0,
+            alias: index as u32,
+            pdf: weight / total,
+            padding: 0,
+        })
+        .collect();
+
+    let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+        (0..count).partition(|&index| scaled[index] < 1.0);
+
+    while let (Some(&less), Some(&more)) = (small.last(), large.last()) {
+        small.pop();
+        entries[less].threshold = scaled[less];
+        entries[less].alias = more as u32;
+
+        scaled[more] -= 1.0 - scaled[less];
+        if scaled[more] < 1.0 {
+            large.pop();
+            small.push(more);
+        }
+    }
+
+    // Whatever is left only misses 1.0 through rounding
+    for index in small.into_iter().chain(large) {
+        entries[index].threshold = 1.0;
+    }
+
+    entries
+}
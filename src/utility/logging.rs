@@ -0,0 +1,24 @@
+use crate::utility::constants::*;
+
+use tracing_subscriber::EnvFilter;
+
+// Per-subsystem targets, filtered separately e.g. `ASH_RT_LOG=info,ash_rt::as=debug`
+pub const TARGET_AS: &str = "ash_rt::as";
+pub const TARGET_SWAPCHAIN: &str = "ash_rt::swapchain";
+pub const TARGET_SBT: &str = "ash_rt::sbt";
+pub const TARGET_VALIDATION: &str = "ash_rt::validation";
+// Shader printf output and the records of the GPU debug buffer
+pub const TARGET_SHADER: &str = "ash_rt::shader";
+
+/// Installs the global subscriber. The filter comes from the ASH_RT_LOG environment variable and
+/// falls back to LOG_FILTER.
+pub fn init_logging() {
+    let filter =
+        EnvFilter::try_from_env(LOG_FILTER_ENV).unwrap_or_else(|_| EnvFilter::new(LOG_FILTER));
+
+    // Only fails when a subscriber was already installed, which is fine to keep
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(true)
+        .try_init();
+}
@@ -0,0 +1,202 @@
+use ash::prelude::VkResult;
+use ash::vk;
+
+use std::ffi::{c_void, CStr};
+use std::ptr;
+
+// VK_NV_low_latency2 is newer than the headers ash was generated from, the parts of it used here
+// are declared as the registry has them
+const LATENCY_SLEEP_MODE_INFO_NV: vk::StructureType = vk::StructureType::from_raw(1000505000);
+const LATENCY_SLEEP_INFO_NV: vk::StructureType = vk::StructureType::from_raw(1000505001);
+const SET_LATENCY_MARKER_INFO_NV: vk::StructureType = vk::StructureType::from_raw(1000505002);
+const LATENCY_SUBMISSION_PRESENT_ID_NV: vk::StructureType = vk::StructureType::from_raw(1000505005);
+const SWAPCHAIN_LATENCY_CREATE_INFO_NV: vk::StructureType = vk::StructureType::from_raw(1000505007);
+
+#[repr(C)]
+struct LatencySleepModeInfoNV {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    low_latency_mode: vk::Bool32,
+    low_latency_boost: vk::Bool32,
+    minimum_interval_us: u32,
+}
+
+#[repr(C)]
+struct LatencySleepInfoNV {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    signal_semaphore: vk::Semaphore,
+    value: u64,
+}
+
+#[repr(C)]
+struct SetLatencyMarkerInfoNV {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    present_id: u64,
+    marker: LatencyMarker,
+}
+
+// Chained into the VkSubmitInfo of the frame `present_id` is presented with
+#[repr(C)]
+pub struct LatencySubmissionPresentIdNV {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    present_id: u64,
+}
+
+impl LatencySubmissionPresentIdNV {
+    pub fn new(present_id: u64) -> LatencySubmissionPresentIdNV {
+        LatencySubmissionPresentIdNV {
+            s_type: LATENCY_SUBMISSION_PRESENT_ID_NV,
+            p_next: ptr::null(),
+            present_id,
+        }
+    }
+}
+
+// Chained into the VkSwapchainCreateInfoKHR of swapchains the low latency mode is used with
+#[repr(C)]
+pub struct SwapchainLatencyCreateInfoNV {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    latency_mode_enable: vk::Bool32,
+}
+
+impl Default for SwapchainLatencyCreateInfoNV {
+    fn default() -> Self {
+        SwapchainLatencyCreateInfoNV {
+            s_type: SWAPCHAIN_LATENCY_CREATE_INFO_NV,
+            p_next: ptr::null(),
+            latency_mode_enable: vk::TRUE,
+        }
+    }
+}
+
+// Where in its frame the renderer is, for the driver to time the next one from
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LatencyMarker {
+    SimulationStart = 0,
+    SimulationEnd = 1,
+    RenderSubmitStart = 2,
+    RenderSubmitEnd = 3,
+    PresentStart = 4,
+    PresentEnd = 5,
+}
+
+// What get_device_proc_addr loads, before it is cast to one of the functions below
+type VoidFunction = unsafe extern "system" fn();
+type SetLatencySleepModeNV = unsafe extern "system" fn(
+    vk::Device,
+    vk::SwapchainKHR,
+    *const LatencySleepModeInfoNV,
+) -> vk::Result;
+type LatencySleepNV = unsafe extern "system" fn(
+    vk::Device,
+    vk::SwapchainKHR,
+    *const LatencySleepInfoNV,
+) -> vk::Result;
+type SetLatencyMarkerNV =
+    unsafe extern "system" fn(vk::Device, vk::SwapchainKHR, *const SetLatencyMarkerInfoNV);
+
+// The low latency mode of VK_NV_low_latency2. The driver paces the frames of a swapchain in the
+// mode by holding the renderer in `sleep` until the frame should start, which it times from the
+// markers of the previous frames. The device needs DeviceFeature::LowLatency.
+pub struct LowLatency {
+    device: ash::Device,
+    set_latency_sleep_mode: SetLatencySleepModeNV,
+    latency_sleep: LatencySleepNV,
+    set_latency_marker: SetLatencyMarkerNV,
+    // Timeline the driver signals when a sleep ends
+    sleep_semaphore: vk::Semaphore,
+    sleep_value: u64,
+}
+
+impl LowLatency {
+    pub fn name() -> &'static CStr {
+        c"VK_NV_low_latency2"
+    }
+
+    pub fn new(instance: &ash::Instance, device: &ash::Device) -> LowLatency {
+        let load = |name: &CStr| unsafe {
+            instance
+                .get_device_proc_addr(device.handle(), name.as_ptr())
+                .unwrap_or_else(|| panic!("Failed to load {:?}.", name))
+        };
+        let mut semaphore_type = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let semaphore_create_info =
+            vk::SemaphoreCreateInfo::builder().push_next(&mut semaphore_type);
+        unsafe {
+            LowLatency {
+                device: device.clone(),
+                set_latency_sleep_mode: std::mem::transmute::<VoidFunction, SetLatencySleepModeNV>(
+                    load(c"vkSetLatencySleepModeNV"),
+                ),
+                latency_sleep: std::mem::transmute::<VoidFunction, LatencySleepNV>(load(
+                    c"vkLatencySleepNV",
+                )),
+                set_latency_marker: std::mem::transmute::<VoidFunction, SetLatencyMarkerNV>(load(
+                    c"vkSetLatencyMarkerNV",
+                )),
+                sleep_semaphore: device
+                    .create_semaphore(&semaphore_create_info, None)
+                    .expect("Failed to create the low latency semaphore."),
+                sleep_value: 0,
+            }
+        }
+    }
+
+    // Every swapchain starts out of the mode, it has to be created with a
+    // SwapchainLatencyCreateInfoNV to be put in it
+    pub fn set_mode(&self, swapchain: vk::SwapchainKHR, enabled: bool) -> VkResult<()> {
+        let info = LatencySleepModeInfoNV {
+            s_type: LATENCY_SLEEP_MODE_INFO_NV,
+            p_next: ptr::null(),
+            low_latency_mode: enabled.into(),
+            low_latency_boost: enabled.into(),
+            // No frame rate cap
+            minimum_interval_us: 0,
+        };
+        unsafe { (self.set_latency_sleep_mode)(self.device.handle(), swapchain, &info).result() }
+    }
+
+    // Blocks until the driver wants the next frame started, right away outside the mode
+    pub fn sleep(&mut self, swapchain: vk::SwapchainKHR) -> VkResult<()> {
+        self.sleep_value += 1;
+        let info = LatencySleepInfoNV {
+            s_type: LATENCY_SLEEP_INFO_NV,
+            p_next: ptr::null(),
+            signal_semaphore: self.sleep_semaphore,
+            value: self.sleep_value,
+        };
+        let semaphores = [self.sleep_semaphore];
+        let values = [self.sleep_value];
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values);
+        unsafe {
+            (self.latency_sleep)(self.device.handle(), swapchain, &info).result()?;
+            self.device.wait_semaphores(&wait_info, u64::MAX)
+        }
+    }
+
+    // `present_id` is the one the frame is presented with, see PresentTiming::next_present_id
+    pub fn set_marker(&self, swapchain: vk::SwapchainKHR, present_id: u64, marker: LatencyMarker) {
+        let info = SetLatencyMarkerInfoNV {
+            s_type: SET_LATENCY_MARKER_INFO_NV,
+            p_next: ptr::null(),
+            present_id,
+            marker,
+        };
+        unsafe { (self.set_latency_marker)(self.device.handle(), swapchain, &info) }
+    }
+
+    pub fn destroy(&self) {
+        unsafe {
+            self.device.destroy_semaphore(self.sleep_semaphore, None);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use super::constants::*;
+use super::structures::InstanceShadingData;
+
+// What the material inspector edits of an instance. The material kind and the checker size in
+// the rest of its InstanceShadingData are left alone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    pub albedo: [f32; 3],
+    pub roughness: f32,
+    pub metallic: f32,
+    // Along the object space x axis for positive values, see ANISOTROPY_RANGE
+    pub anisotropy: f32,
+    pub emissive: [f32; 3],
+    // Per channel, in world units. Shaded by HitGroup::Subsurface only.
+    pub mean_free_path: [f32; 3],
+    // Chance of a ray to stop at the surface. Only below the threshold, and only with
+    // `--stochastic_transparency`, do rays pass through.
+    pub opacity: f32,
+    pub opacity_threshold: f32,
+}
+
+fn check_range(name: &str, value: f32, range: &RangeInclusive<f32>) -> Result<(), String> {
+    // NaN is never contained
+    if range.contains(&value) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} {} is outside of {}..={}",
+            name,
+            value,
+            range.start(),
+            range.end()
+        ))
+    }
+}
+
+impl Material {
+    pub fn of(data: &InstanceShadingData) -> Material {
+        Material {
+            albedo: [data.color[0], data.color[1], data.color[2]],
+            roughness: data.surface[0],
+            metallic: data.surface[1],
+            anisotropy: data.surface[2],
+            emissive: [data.emission[0], data.emission[1], data.emission[2]],
+            mean_free_path: [data.subsurface[0], data.subsurface[1], data.subsurface[2]],
+            opacity: data.surface[3],
+            opacity_threshold: data.subsurface[3],
+        }
+    }
+
+    // Every parameter against the range the shaders are written for
+    pub fn validate(&self) -> Result<(), String> {
+        for channel in 0..3 {
+            check_range("albedo", self.albedo[channel], &ALBEDO_RANGE)?;
+            check_range("emissive", self.emissive[channel], &EMISSIVE_RANGE)?;
+            check_range(
+                "mean_free_path",
+                self.mean_free_path[channel],
+                &MEAN_FREE_PATH_RANGE,
+            )?;
+        }
+        check_range("roughness", self.roughness, &ROUGHNESS_RANGE)?;
+        check_range("metallic", self.metallic, &METALLIC_RANGE)?;
+        check_range("anisotropy", self.anisotropy, &ANISOTROPY_RANGE)?;
+        check_range("opacity", self.opacity, &OPACITY_RANGE)?;
+        check_range("opacity_threshold", self.opacity_threshold, &OPACITY_RANGE)
+    }
+
+    // Whether rays may pass through the surface under stochastic transparency
+    pub fn is_transparent(&self) -> bool {
+        self.opacity < self.opacity_threshold
+    }
+
+    // Clamped to what the shaders can take, for materials that skipped validate
+    pub fn apply_to(&self, data: &mut InstanceShadingData) {
+        let clamp =
+            |value: f32, range: &RangeInclusive<f32>| value.clamp(*range.start(), *range.end());
+        for channel in 0..3 {
+            data.color[channel] = clamp(self.albedo[channel], &ALBEDO_RANGE);
+            data.emission[channel] = clamp(self.emissive[channel], &EMISSIVE_RANGE);
+            data.subsurface[channel] = clamp(self.mean_free_path[channel], &MEAN_FREE_PATH_RANGE);
+        }
+        data.surface[0] = clamp(self.roughness, &ROUGHNESS_RANGE);
+        data.surface[1] = clamp(self.metallic, &METALLIC_RANGE);
+        data.surface[2] = clamp(self.anisotropy, &ANISOTROPY_RANGE);
+        data.surface[3] = clamp(self.opacity, &OPACITY_RANGE);
+        data.subsurface[3] = clamp(self.opacity_threshold, &OPACITY_RANGE);
+    }
+
+    // Applies one `name=value` edit, colors are given as `r,g,b`. Edits that leave the material
+    // invalid are rejected and change nothing.
+    pub fn edit(&mut self, edit: &str) -> Result<(), String> {
+        let (name, value) = edit
+            .split_once('=')
+            .ok_or_else(|| format!("Expected name=value, got {:?}", edit))?;
+        let parse_scalar = |value: &str| {
+            value
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid {} value {:?}", name, value))
+        };
+        let parse_color = |value: &str| -> Result<[f32; 3], String> {
+            let channels = value
+                .split(',')
+                .map(parse_scalar)
+                .collect::<Result<Vec<f32>, String>>()?;
+            channels
+                .try_into()
+                .map_err(|_| format!("{} takes three comma separated channels", name))
+        };
+        let mut edited = *self;
+        match name {
+            "albedo" => edited.albedo = parse_color(value)?,
+            "roughness" => edited.roughness = parse_scalar(value)?,
+            "metallic" => edited.metallic = parse_scalar(value)?,
+            "anisotropy" => edited.anisotropy = parse_scalar(value)?,
+            "emissive" => edited.emissive = parse_color(value)?,
+            "mean_free_path" => edited.mean_free_path = parse_color(value)?,
+            "opacity" => edited.opacity = parse_scalar(value)?,
+            "opacity_threshold" => edited.opacity_threshold = parse_scalar(value)?,
+            _ => return Err(format!("Unknown material property {}", name)),
+        }
+        edited.validate()?;
+        *self = edited;
+        Ok(())
+    }
+}
+
+// Which textures keep their resolution when the scene's textures do not fit
+// TEXTURE_MEMORY_BUDGET, the lowest go first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TexturePriority {
+    Low,
+    Normal,
+    High,
+}
+
+// The material input a texture is read for, going by the last word of its file name, as in
+// `brick_albedo.png` or `brick_roughness.png`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureSlot {
+    BaseColor,
+    Normal,
+    Emissive,
+    Roughness,
+    Metallic,
+    Occlusion,
+    Other,
+}
+
+impl TextureSlot {
+    pub fn from_file_name(path: &Path) -> TextureSlot {
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        let suffix = stem.rsplit(['_', '-', '.']).next();
+        match suffix.unwrap_or("") {
+            "albedo" | "basecolor" | "diffuse" | "color" => TextureSlot::BaseColor,
+            "normal" | "nrm" => TextureSlot::Normal,
+            "emissive" | "emission" => TextureSlot::Emissive,
+            "roughness" | "rough" => TextureSlot::Roughness,
+            "metallic" | "metalness" => TextureSlot::Metallic,
+            "ao" | "occlusion" => TextureSlot::Occlusion,
+            _ => TextureSlot::Other,
+        }
+    }
+
+    // Detail in the base color is what is missed first, roughness, metalness and occlusion are
+    // smooth enough to lose theirs
+    pub fn priority(self) -> TexturePriority {
+        match self {
+            TextureSlot::BaseColor => TexturePriority::High,
+            TextureSlot::Normal | TextureSlot::Emissive | TextureSlot::Other => {
+                TexturePriority::Normal
+            }
+            TextureSlot::Roughness | TextureSlot::Metallic | TextureSlot::Occlusion => {
+                TexturePriority::Low
+            }
+        }
+    }
+}
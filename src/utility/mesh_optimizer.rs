@@ -0,0 +1,385 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+// Vertices the cache optimization models, with room for the three of the next triangle
+const VERTEX_CACHE_SIZE: usize = 32;
+// FIFO post-transform cache of typical GPUs, used to measure and to cut overdraw clusters
+const FIFO_CACHE_SIZE: usize = 16;
+
+// Merges vertices with the same key and rewrites the indices to the merged ones, keeping the
+// order of first appearance
+pub fn deduplicate_vertices<V: Copy, K: Hash + Eq>(
+    vertices: &[V],
+    indices: &[u32],
+    key: impl Fn(&V) -> K,
+) -> (Vec<V>, Vec<u32>) {
+    let mut unique_vertices = Vec::new();
+    let mut unique_indices: HashMap<K, u32> = HashMap::with_capacity(vertices.len());
+    let remap: Vec<u32> = vertices
+        .iter()
+        .map(|vertex| {
+            *unique_indices.entry(key(vertex)).or_insert_with(|| {
+                unique_vertices.push(*vertex);
+                unique_vertices.len() as u32 - 1
+            })
+        })
+        .collect();
+    let indices = indices.iter().map(|&index| remap[index as usize]).collect();
+    (unique_vertices, indices)
+}
+
+// Vertex shader invocations per triangle with a FIFO cache of the given size, between 0.5 for
+// a regular grid and 3 when no vertex is ever reused
+pub fn average_cache_miss_ratio(indices: &[u32], cache_size: usize) -> f32 {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return 0.0;
+    }
+    let mut cache = VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for &index in indices {
+        if !cache.contains(&index) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(index);
+        }
+    }
+    misses as f32 / triangle_count as f32
+}
+
+// Forsyth's score: recently used vertices and vertices with few triangles left come first
+fn vertex_score(cache_position: Option<usize>, live_triangles: u32) -> f32 {
+    if live_triangles == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        // The vertices of the last triangle, so that strips do not flip back and forth
+        Some(position) if position < 3 => 0.75,
+        Some(position) => (1.0 - (position - 3) as f32 / (VERTEX_CACHE_SIZE - 3) as f32).powf(1.5),
+        None => 0.0,
+    };
+    cache_score + 2.0 * (live_triangles as f32).powf(-0.5)
+}
+
+// Reorders the triangles for the post-transform vertex cache with Forsyth's linear-speed
+// algorithm, the vertices stay where they are
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+
+    // Triangles around every vertex, the ones not emitted yet first
+    let mut live_triangles = vec![0u32; vertex_count];
+    for &index in indices {
+        live_triangles[index as usize] += 1;
+    }
+    let mut adjacency_offsets = Vec::with_capacity(vertex_count);
+    let mut offset = 0;
+    for &count in &live_triangles {
+        adjacency_offsets.push(offset);
+        offset += count as usize;
+    }
+    let mut adjacency = vec![0u32; indices.len()];
+    let mut fill = adjacency_offsets.clone();
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &vertex in corners {
+            adjacency[fill[vertex as usize]] = triangle as u32;
+            fill[vertex as usize] += 1;
+        }
+    }
+
+    let mut cache_positions: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f32> = live_triangles
+        .iter()
+        .map(|&count| vertex_score(None, count))
+        .collect();
+    let mut emitted = vec![false; triangle_count];
+    let mut cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(triangle_count * 3);
+    let mut next_unemitted = 0;
+    let mut best_triangle = None;
+
+    for _ in 0..triangle_count {
+        // Only the triangles around the cache are scored, anything else starts over
+        let triangle = match best_triangle {
+            Some(triangle) => triangle,
+            None => {
+                while emitted[next_unemitted] {
+                    next_unemitted += 1;
+                }
+                next_unemitted
+            }
+        };
+        emitted[triangle] = true;
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        output.extend_from_slice(corners);
+
+        for &vertex in corners {
+            let vertex = vertex as usize;
+            let start = adjacency_offsets[vertex];
+            let live = live_triangles[vertex] as usize;
+            let position = adjacency[start..start + live]
+                .iter()
+                .position(|&adjacent| adjacent as usize == triangle)
+                .expect("The triangle is missing from the adjacency of its vertex.");
+            adjacency.swap(start + position, start + live - 1);
+            live_triangles[vertex] -= 1;
+        }
+
+        // Most recently used first, what falls off the end loses its cache score
+        let mut new_cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+        for &vertex in corners.iter().chain(cache.iter()) {
+            if !new_cache.contains(&vertex) {
+                new_cache.push(vertex);
+            }
+        }
+        for (position, &vertex) in new_cache.iter().enumerate() {
+            let vertex = vertex as usize;
+            cache_positions[vertex] = (position < VERTEX_CACHE_SIZE).then_some(position);
+            vertex_scores[vertex] = vertex_score(cache_positions[vertex], live_triangles[vertex]);
+        }
+
+        best_triangle = None;
+        let mut best_score = f32::MIN;
+        for &vertex in &new_cache {
+            let start = adjacency_offsets[vertex as usize];
+            let live = live_triangles[vertex as usize] as usize;
+            for &adjacent in &adjacency[start..start + live] {
+                let adjacent = adjacent as usize;
+                let score: f32 = indices[adjacent * 3..adjacent * 3 + 3]
+                    .iter()
+                    .map(|&corner| vertex_scores[corner as usize])
+                    .sum();
+                if score > best_score {
+                    best_score = score;
+                    best_triangle = Some(adjacent);
+                }
+            }
+        }
+
+        new_cache.truncate(VERTEX_CACHE_SIZE);
+        cache = new_cache;
+    }
+
+    output
+}
+
+// Sorts clusters of cache optimized triangles so the ones facing out of the mesh come first and
+// hide what is drawn after them. Clusters start where a FIFO cache misses all three vertices, so
+// the reordering keeps most of the cache efficiency.
+pub fn optimize_overdraw(indices: &[u32], positions: &[[f32; 3]]) -> Vec<u32> {
+    let mut cluster_starts = Vec::new();
+    let mut cache = VecDeque::with_capacity(FIFO_CACHE_SIZE);
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        let mut misses = 0;
+        for &index in corners {
+            if !cache.contains(&index) {
+                misses += 1;
+                if cache.len() == FIFO_CACHE_SIZE {
+                    cache.pop_front();
+                }
+                cache.push_back(index);
+            }
+        }
+        if misses == 3 || triangle == 0 {
+            cluster_starts.push(triangle * 3);
+        }
+    }
+    cluster_starts.push(indices.len() - indices.len() % 3);
+
+    let sub = |a: [f32; 3], b: [f32; 3]| [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+    let cross = |a: [f32; 3], b: [f32; 3]| {
+        [
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0],
+        ]
+    };
+
+    // Area weighted centroid and normal of every cluster
+    let mut mesh_centroid = [0.0f32; 3];
+    let mut mesh_area = 0.0;
+    let clusters: Vec<(&[u32], [f32; 3], [f32; 3])> = cluster_starts
+        .windows(2)
+        .map(|range| {
+            let cluster = &indices[range[0]..range[1]];
+            let mut centroid = [0.0f32; 3];
+            let mut normal = [0.0f32; 3];
+            let mut area = 0.0;
+            for corners in cluster.chunks_exact(3) {
+                let [p0, p1, p2] = [0, 1, 2].map(|corner| positions[corners[corner] as usize]);
+                let triangle_normal = cross(sub(p1, p0), sub(p2, p0));
+                let triangle_area = 0.5
+                    * (triangle_normal[0] * triangle_normal[0]
+                        + triangle_normal[1] * triangle_normal[1]
+                        + triangle_normal[2] * triangle_normal[2])
+                        .sqrt();
+                for axis in 0..3 {
+                    centroid[axis] += triangle_area * (p0[axis] + p1[axis] + p2[axis]) / 3.0;
+                    normal[axis] += triangle_normal[axis];
+                }
+                area += triangle_area;
+            }
+            for axis in 0..3 {
+                mesh_centroid[axis] += centroid[axis];
+                if area > 0.0 {
+                    centroid[axis] /= area;
+                }
+            }
+            mesh_area += area;
+            (cluster, centroid, normal)
+        })
+        .collect();
+    if mesh_area > 0.0 {
+        mesh_centroid = mesh_centroid.map(|coordinate| coordinate / mesh_area);
+    }
+
+    let mut sorted: Vec<(f32, &[u32])> = clusters
+        .into_iter()
+        .map(|(cluster, centroid, normal)| {
+            let length =
+                (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+            let offset = sub(centroid, mesh_centroid);
+            let facing = if length > 0.0 {
+                (offset[0] * normal[0] + offset[1] * normal[1] + offset[2] * normal[2]) / length
+            } else {
+                0.0
+            };
+            (facing, cluster)
+        })
+        .collect();
+    sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+    sorted
+        .into_iter()
+        .flat_map(|(_, cluster)| cluster.iter().copied())
+        .collect()
+}
+
+// Renumbers the vertices in the order the indices first use them so vertex fetches stay close
+// together, vertices no index uses are dropped
+pub fn optimize_vertex_fetch<V: Copy>(vertices: &[V], indices: &mut [u32]) -> Vec<V> {
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut ordered = Vec::with_capacity(vertices.len());
+    for index in indices.iter_mut() {
+        let old_index = *index as usize;
+        if remap[old_index] == u32::MAX {
+            remap[old_index] = ordered.len() as u32;
+            ordered.push(vertices[old_index]);
+        }
+        *index = remap[old_index];
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRID_SIZE: u32 = 16;
+
+    // Two triangles per cell of a GRID_SIZE x GRID_SIZE grid in the z = 0 plane, in a scrambled
+    // order no cache likes
+    fn scrambled_grid() -> (Vec<[f32; 3]>, Vec<u32>) {
+        let row = GRID_SIZE + 1;
+        let positions = (0..row * row)
+            .map(|vertex| [(vertex % row) as f32, (vertex / row) as f32, 0.0])
+            .collect();
+        let mut triangles = Vec::new();
+        for y in 0..GRID_SIZE {
+            for x in 0..GRID_SIZE {
+                let corner = y * row + x;
+                triangles.push([corner, corner + 1, corner + row]);
+                triangles.push([corner + 1, corner + row + 1, corner + row]);
+            }
+        }
+        // 7919 is prime, so this visits every triangle once
+        let count = triangles.len();
+        let indices = (0..count)
+            .flat_map(|triangle| triangles[triangle * 7919 % count])
+            .collect();
+        (positions, indices)
+    }
+
+    fn sorted_triangles(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut triangles: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|corners| [corners[0], corners[1], corners[2]])
+            .collect();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn cache_optimization_reorders_whole_triangles() {
+        let (positions, indices) = scrambled_grid();
+        let optimized = optimize_vertex_cache(&indices, positions.len());
+        assert_eq!(sorted_triangles(&optimized), sorted_triangles(&indices));
+
+        let before = average_cache_miss_ratio(&indices, FIFO_CACHE_SIZE);
+        let after = average_cache_miss_ratio(&optimized, FIFO_CACHE_SIZE);
+        assert!(after < before, "ACMR went from {} to {}", before, after);
+        assert!(after < 1.0, "ACMR {} of a regular grid", after);
+    }
+
+    #[test]
+    fn overdraw_optimization_keeps_the_triangles_and_most_of_the_cache() {
+        let (positions, indices) = scrambled_grid();
+        let cache_optimized = optimize_vertex_cache(&indices, positions.len());
+        let optimized = optimize_overdraw(&cache_optimized, &positions);
+        assert_eq!(sorted_triangles(&optimized), sorted_triangles(&indices));
+        assert!(
+            average_cache_miss_ratio(&optimized, FIFO_CACHE_SIZE)
+                < average_cache_miss_ratio(&indices, FIFO_CACHE_SIZE)
+        );
+    }
+
+    #[test]
+    fn vertex_fetch_follows_the_indices() {
+        let (positions, indices) = scrambled_grid();
+        let mut remapped = indices.clone();
+        let ordered = optimize_vertex_fetch(&positions, &mut remapped);
+        assert_eq!(ordered.len(), positions.len());
+        for (&old, &new) in indices.iter().zip(&remapped) {
+            assert_eq!(positions[old as usize], ordered[new as usize]);
+        }
+        // Every vertex is numbered at its first use
+        let mut next = 0;
+        for &index in &remapped {
+            assert!(index <= next);
+            if index == next {
+                next += 1;
+            }
+        }
+
+        // Vertices no index uses are dropped
+        let mut indices = vec![2, 0, 2];
+        assert_eq!(
+            optimize_vertex_fetch(&['a', 'b', 'c'], &mut indices),
+            ['c', 'a']
+        );
+        assert_eq!(indices, [0, 1, 0]);
+    }
+
+    #[test]
+    fn duplicates_merge_in_order_of_appearance() {
+        let vertices = [10, 20, 10, 30, 20];
+        let (unique, indices) = deduplicate_vertices(&vertices, &[0, 1, 2, 3, 4, 2], |&v| v);
+        assert_eq!(unique, [10, 20, 30]);
+        assert_eq!(indices, [0, 1, 0, 2, 1, 0]);
+    }
+
+    #[test]
+    fn cache_miss_ratio_counts_vertex_shader_invocations() {
+        assert_eq!(average_cache_miss_ratio(&[], FIFO_CACHE_SIZE), 0.0);
+        assert_eq!(average_cache_miss_ratio(&[0, 1, 2], FIFO_CACHE_SIZE), 3.0);
+        assert_eq!(
+            average_cache_miss_ratio(&[0, 1, 2, 2, 1, 3], FIFO_CACHE_SIZE),
+            2.0
+        );
+        // Evicted after a cache's worth of other vertices
+        assert_eq!(
+            average_cache_miss_ratio(&[0, 1, 2, 3, 4, 5, 0, 1, 2], 3),
+            3.0
+        );
+    }
+}
@@ -1,9 +1,62 @@
+pub mod alloc_count;
+pub mod bounds;
+pub mod camera;
+pub mod chunk_streaming;
+pub mod color_grading;
+pub mod console;
 pub mod constants;
 pub mod debug;
+pub mod denoiser;
+pub mod descriptor_diagnostics;
+pub mod descriptor_writes;
+pub mod device_group;
+pub mod dirty_ranges;
+pub mod dynamic_resolution;
+pub mod environment_probe;
+pub mod exr_output;
+pub mod feature_chain;
+pub mod fixed_timestep;
 pub mod fps_limiter;
+pub mod frame_scratch;
 pub mod general;
+pub mod gpu_profiler;
+pub mod hdr_format;
+pub mod image_diff;
+#[cfg(feature = "winit")]
+pub mod input;
+pub mod light_sampling;
+pub mod logging;
+pub mod low_latency;
+pub mod material;
+pub mod mesh_optimizer;
+pub mod participating_media;
 pub mod platforms;
+pub mod present_timing;
+pub mod present_transfer;
+pub mod push_constant_diagnostics;
+pub mod queue_ownership;
 pub mod raytracing_aid;
+#[cfg(feature = "remote-control")]
+pub mod remote_control;
+pub mod render_origin;
+pub mod resource_registry;
+pub mod sampler_cache;
+pub mod scene_report;
+pub mod shader_cache;
+pub mod skinning;
+pub mod stress_scene;
 pub mod structures;
+pub mod sun;
+pub mod synchronization;
+pub mod tessellation;
+pub mod texture_atlas;
+pub mod texture_budget;
 pub mod tools;
+pub mod uniform_ring;
+pub mod user_settings;
+pub mod vertex_format;
+pub mod video_capture;
+#[cfg(feature = "remote-control")]
+pub mod websocket;
+#[cfg(feature = "winit")]
 pub mod window;
@@ -0,0 +1,99 @@
+use cgmath::Vector3;
+
+use std::fs;
+use std::path::Path;
+
+use super::structures::FogParams;
+
+// Voxel densities over an axis aligned box, x varies fastest, then y, then z
+#[derive(Clone, Debug)]
+pub struct DensityGrid {
+    pub size: [u32; 3],
+    pub bounds_min: [f32; 3],
+    pub bounds_max: [f32; 3],
+    pub densities: Vec<f32>,
+}
+
+impl DensityGrid {
+    // Mitsuba's binary grid volume format: "VOL", version 3, the encoding, the resolution, the
+    // channel count and the bounding box, then little endian float32 voxels. Only the first
+    // channel of multi-channel grids is kept.
+    pub fn load_vol(path: &Path) -> Result<DensityGrid, String> {
+        let bytes = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        if bytes.len() < 48 || &bytes[0..3] != b"VOL" || bytes[3] != 3 {
+            return Err(format!("{} is not a version 3 VOL file", path.display()));
+        }
+        let word = |offset: usize| {
+            [
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ]
+        };
+        let int = |offset: usize| i32::from_le_bytes(word(offset));
+        let float = |offset: usize| f32::from_le_bytes(word(offset));
+
+        // 1 = float32, the 8 bit and half encodings are not supported
+        if int(4) != 1 {
+            return Err(format!(
+                "{} uses encoding {}, only float32 grids are supported",
+                path.display(),
+                int(4)
+            ));
+        }
+        let size = [int(8), int(12), int(16)];
+        let channels = int(20);
+        if size.iter().any(|&voxels| voxels <= 0) || channels <= 0 {
+            return Err(format!("{} has an empty grid", path.display()));
+        }
+        let size = size.map(|voxels| voxels as u32);
+        let channels = channels as usize;
+        let voxel_count = size
+            .iter()
+            .map(|&voxels| voxels as usize)
+            .product::<usize>();
+        if bytes.len() < 48 + voxel_count * channels * 4 {
+            return Err(format!("{} is truncated", path.display()));
+        }
+
+        let densities = (0..voxel_count)
+            .map(|voxel| float(48 + voxel * channels * 4).max(0.0))
+            .collect();
+        Ok(DensityGrid {
+            size,
+            bounds_min: [float(24), float(28), float(32)],
+            bounds_max: [float(36), float(40), float(44)],
+            densities,
+        })
+    }
+
+    pub fn max_density(&self) -> f32 {
+        self.densities.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+// The shader side description of uniform fog and an optional grid with its densities scaled
+pub fn fog_params(color: [f32; 3], density: f32, grid: Option<(&DensityGrid, f32)>) -> FogParams {
+    let mut params = FogParams {
+        color: [color[0], color[1], color[2], density.max(0.0)],
+        ..Default::default()
+    };
+    if let Some((grid, scale)) = grid {
+        let [min_x, min_y, min_z] = grid.bounds_min;
+        let [max_x, max_y, max_z] = grid.bounds_max;
+        let [size_x, size_y, size_z] = grid.size;
+        params.grid_min = [min_x, min_y, min_z, scale];
+        params.grid_max = [max_x, max_y, max_z, grid.max_density() * scale];
+        params.grid_size = [size_x, size_y, size_z, 0];
+    }
+    params
+}
+
+// Moves the grid by `offset`, uniform fog has no position
+pub fn translate_fog_grid(params: &mut FogParams, offset: Vector3<f32>) {
+    for (axis, offset) in [offset.x, offset.y, offset.z].into_iter().enumerate() {
+        params.grid_min[axis] += offset;
+        params.grid_max[axis] += offset;
+    }
+}
@@ -1,21 +1,28 @@
 #[cfg(target_os = "windows")]
 use ash::extensions::khr::Win32Surface;
 
-use ash::extensions::ext::DebugUtils;
+use ash::extensions::khr;
 use ash::extensions::khr::Surface;
 use ash::vk;
+use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
+use std::os::raw::c_char;
 
 #[cfg(all(windows))]
 pub fn required_extension_names() -> Vec<*const i8> {
     vec![
         Surface::name().as_ptr(),
         Win32Surface::name().as_ptr(),
-        DebugUtils::name().as_ptr(),
         vk::KhrGetPhysicalDeviceProperties2Fn::name().as_ptr(),
     ]
 }
 
-#[cfg(target_os = "windows")]
+/// Creates a surface for a winit window.
+///
+/// # Safety
+///
+/// The instance must have been created with the required_extension_names. The window must
+/// outlive the surface, which the caller destroys before the instance.
+#[cfg(all(target_os = "windows", feature = "winit"))]
 pub unsafe fn create_surface(
     entry: &ash::Entry,
     instance: &ash::Instance,
@@ -40,3 +47,64 @@ pub unsafe fn create_surface(
     let win32_surface_loader = Win32Surface::new(entry, instance);
     win32_surface_loader.create_win32_surface(&win32_create_info, None)
 }
+
+// Instance extensions a surface for a window of the display needs, for windows that do not come
+// from winit
+pub fn surface_extension_names(
+    display: RawDisplayHandle,
+) -> Result<Vec<*const c_char>, vk::Result> {
+    let platform_surface = match display {
+        RawDisplayHandle::Windows(_) => khr::Win32Surface::name(),
+        RawDisplayHandle::Xlib(_) => khr::XlibSurface::name(),
+        RawDisplayHandle::Xcb(_) => khr::XcbSurface::name(),
+        RawDisplayHandle::Wayland(_) => khr::WaylandSurface::name(),
+        _ => return Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT),
+    };
+    Ok(vec![
+        Surface::name().as_ptr(),
+        platform_surface.as_ptr(),
+        vk::KhrGetPhysicalDeviceProperties2Fn::name().as_ptr(),
+    ])
+}
+
+/// Creates a surface for a window of another toolkit.
+///
+/// # Safety
+///
+/// The instance must have been created with the surface_extension_names of `display`. Both
+/// handles must be valid and the window must outlive the surface, which the caller destroys
+/// before the instance.
+pub unsafe fn create_surface_from_raw_handles(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    display: RawDisplayHandle,
+    window: RawWindowHandle,
+) -> Result<vk::SurfaceKHR, vk::Result> {
+    match (display, window) {
+        (RawDisplayHandle::Windows(_), RawWindowHandle::Win32(window)) => {
+            let create_info = vk::Win32SurfaceCreateInfoKHR::builder()
+                .hinstance(window.hinstance)
+                .hwnd(window.hwnd);
+            khr::Win32Surface::new(entry, instance).create_win32_surface(&create_info, None)
+        }
+        (RawDisplayHandle::Xlib(display), RawWindowHandle::Xlib(window)) => {
+            let create_info = vk::XlibSurfaceCreateInfoKHR::builder()
+                .dpy(display.display as *mut _)
+                .window(window.window);
+            khr::XlibSurface::new(entry, instance).create_xlib_surface(&create_info, None)
+        }
+        (RawDisplayHandle::Xcb(display), RawWindowHandle::Xcb(window)) => {
+            let create_info = vk::XcbSurfaceCreateInfoKHR::builder()
+                .connection(display.connection)
+                .window(window.window);
+            khr::XcbSurface::new(entry, instance).create_xcb_surface(&create_info, None)
+        }
+        (RawDisplayHandle::Wayland(display), RawWindowHandle::Wayland(window)) => {
+            let create_info = vk::WaylandSurfaceCreateInfoKHR::builder()
+                .display(display.display)
+                .surface(window.surface);
+            khr::WaylandSurface::new(entry, instance).create_wayland_surface(&create_info, None)
+        }
+        _ => Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT),
+    }
+}
@@ -0,0 +1,240 @@
+use crate::utility::feature_chain::{DeviceFeature, DeviceFeatures};
+use crate::utility::logging::TARGET_SWAPCHAIN;
+
+use ash::extensions::khr;
+use ash::prelude::VkResult;
+use ash::vk;
+
+use std::collections::VecDeque;
+use std::ptr;
+use std::time::{Duration, Instant};
+
+// Totals over every swapchain the renderer had
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PresentStats {
+    // Frames whose present was seen to complete
+    pub presented: u64,
+    // Refresh cycles that showed the previous frame again, from the gaps between the actual
+    // present times of consecutive frames. Frames held back by a frame rate limit count too.
+    pub missed_vsyncs: u64,
+    pub refresh_duration: Option<Duration>,
+    // Of the last frame, how long before its refresh it could have been presented
+    pub present_margin: Option<Duration>,
+    // From queue_present until wait_for_present saw the frame presented, of the last frame and
+    // averaged. The present is only polled once a frame, which rounds these up to it.
+    pub last_latency: Option<Duration>,
+    pub mean_latency: Option<Duration>,
+}
+
+// Present feedback of VK_GOOGLE_display_timing and VK_KHR_present_wait, each used when it is
+// enabled. Display timing reports when frames actually reached the display, present wait when
+// their present completed as seen from the host.
+pub struct PresentTiming {
+    device: vk::Device,
+    display_timing: Option<vk::GoogleDisplayTimingFn>,
+    present_wait: Option<khr::PresentWait>,
+    // Whether presents carry a VK_KHR_present_id, which present wait and the low latency
+    // markers go by
+    has_present_id: bool,
+
+    // Ids are never 0, which stands for no id in VK_KHR_present_id
+    next_present_id: u64,
+    // Present ids not yet seen presented, with when they were queued
+    pending: VecDeque<(u64, Instant)>,
+    // Present id, truncated to 32 bits like display timing reports it, and actual present time
+    // of the last frame display timing reported
+    last_present: Option<(u32, u64)>,
+    latency_sum: Duration,
+    latency_count: u32,
+    stats: PresentStats,
+    // Reused by every update
+    timings: Vec<vk::PastPresentationTimingGOOGLE>,
+}
+
+impl PresentTiming {
+    pub fn new(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        features: DeviceFeatures,
+    ) -> PresentTiming {
+        let display_timing = features.contains(DeviceFeature::DisplayTiming).then(|| {
+            vk::GoogleDisplayTimingFn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            })
+        });
+        let present_wait = features
+            .contains(DeviceFeature::PresentWait)
+            .then(|| khr::PresentWait::new(instance, device));
+
+        PresentTiming {
+            device: device.handle(),
+            display_timing,
+            present_wait,
+            has_present_id: features.contains(DeviceFeature::PresentId),
+
+            next_present_id: 1,
+            pending: VecDeque::new(),
+            last_present: None,
+            latency_sum: Duration::ZERO,
+            latency_count: 0,
+            stats: PresentStats::default(),
+            timings: Vec::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.display_timing.is_some() || self.present_wait.is_some()
+    }
+
+    pub fn stats(&self) -> PresentStats {
+        self.stats
+    }
+
+    // What the next queue_present presents with
+    pub fn next_present_id(&self) -> u64 {
+        self.next_present_id
+    }
+
+    // Presents `image_index` with the next present id chained in for the enabled extensions
+    pub fn queue_present(
+        &mut self,
+        swapchain_loader: &khr::Swapchain,
+        queue: vk::Queue,
+        swapchain: vk::SwapchainKHR,
+        image_index: u32,
+        wait_semaphores: &[vk::Semaphore],
+    ) -> VkResult<bool> {
+        let present_id = self.next_present_id;
+        self.next_present_id += 1;
+
+        let swapchains = [swapchain];
+        let image_indices = [image_index];
+        let present_ids = [present_id];
+        // As soon as possible, only the id is of interest
+        let present_times = [vk::PresentTimeGOOGLE {
+            present_id: present_id as u32,
+            desired_present_time: 0,
+        }];
+        let mut present_id_info = vk::PresentIdKHR::builder().present_ids(&present_ids);
+        let mut present_times_info = vk::PresentTimesInfoGOOGLE::builder().times(&present_times);
+
+        let mut present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(wait_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&image_indices);
+        if self.has_present_id {
+            present_info = present_info.push_next(&mut present_id_info);
+        }
+        if self.display_timing.is_some() {
+            present_info = present_info.push_next(&mut present_times_info);
+        }
+
+        let result = unsafe { swapchain_loader.queue_present(queue, &present_info) };
+        if self.present_wait.is_some() && result.is_ok() {
+            self.pending.push_back((present_id, Instant::now()));
+        }
+        result
+    }
+
+    // Picks up the presents that completed since the last call, once a frame
+    pub fn update(&mut self, swapchain: vk::SwapchainKHR) {
+        if self.display_timing.is_some() {
+            self.update_display_timing(swapchain);
+        }
+        if let Some(present_wait) = &self.present_wait {
+            while let Some(&(present_id, queued)) = self.pending.front() {
+                match unsafe { present_wait.wait_for_present(swapchain, present_id, 0) } {
+                    Ok(()) => {
+                        let latency = queued.elapsed();
+                        self.latency_sum += latency;
+                        self.latency_count += 1;
+                        self.stats.last_latency = Some(latency);
+                        self.stats.mean_latency = Some(self.latency_sum / self.latency_count);
+                        if self.display_timing.is_none() {
+                            self.stats.presented += 1;
+                        }
+                        self.pending.pop_front();
+                    }
+                    Err(vk::Result::TIMEOUT) => break,
+                    Err(err) => {
+                        tracing::debug!(target: TARGET_SWAPCHAIN, "Waiting for a present failed: {}", err);
+                        self.pending.clear();
+                    }
+                }
+            }
+        }
+    }
+
+    // Present ids of the old swapchain mean nothing to the new one, the totals are kept
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.last_present = None;
+        self.stats.refresh_duration = None;
+    }
+
+    fn update_display_timing(&mut self, swapchain: vk::SwapchainKHR) {
+        let display_timing = self.display_timing.as_ref().unwrap();
+
+        if self.stats.refresh_duration.is_none() {
+            let mut refresh_cycle = vk::RefreshCycleDurationGOOGLE::default();
+            let result = unsafe {
+                (display_timing.get_refresh_cycle_duration_google)(
+                    self.device,
+                    swapchain,
+                    &mut refresh_cycle,
+                )
+            };
+            if result == vk::Result::SUCCESS && refresh_cycle.refresh_duration > 0 {
+                self.stats.refresh_duration =
+                    Some(Duration::from_nanos(refresh_cycle.refresh_duration));
+            }
+        }
+
+        let mut timings = std::mem::take(&mut self.timings);
+        unsafe {
+            let mut count = 0;
+            let result = (display_timing.get_past_presentation_timing_google)(
+                self.device,
+                swapchain,
+                &mut count,
+                ptr::null_mut(),
+            );
+            timings.clear();
+            timings.resize(count as usize, vk::PastPresentationTimingGOOGLE::default());
+            let result = result.result().and_then(|()| {
+                (display_timing.get_past_presentation_timing_google)(
+                    self.device,
+                    swapchain,
+                    &mut count,
+                    timings.as_mut_ptr(),
+                )
+                .result()
+            });
+            if let Err(err) = result {
+                tracing::debug!(target: TARGET_SWAPCHAIN, "Getting present times failed: {}", err);
+                self.timings = timings;
+                return;
+            }
+            timings.truncate(count as usize);
+        }
+
+        for &timing in &timings {
+            self.stats.presented += 1;
+            self.stats.present_margin = Some(Duration::from_nanos(timing.present_margin));
+            let present_id = timing.present_id;
+            if let (Some((last_id, last_time)), Some(refresh_duration)) =
+                (self.last_present, self.stats.refresh_duration)
+            {
+                // Frames display timing did not report, like those dropped from its history,
+                // each took a refresh cycle too
+                let frames = present_id.wrapping_sub(last_id) as u64;
+                let gap = timing.actual_present_time.saturating_sub(last_time);
+                let refresh_duration = refresh_duration.as_nanos() as u64;
+                let cycles = (gap + refresh_duration / 2) / refresh_duration;
+                self.stats.missed_vsyncs += cycles.saturating_sub(frames);
+            }
+            self.last_present = Some((present_id, timing.actual_present_time));
+        }
+        self.timings = timings;
+    }
+}
@@ -0,0 +1,211 @@
+use super::queue_ownership::{QueueOwnership, QueueUse};
+use super::structures::QueueFamilyIndices;
+
+use ash::vk;
+
+use std::ptr;
+
+// Hands the exclusive swapchain images from the graphics queue family over to a present queue
+// family of their own, e.g. a compute family on devices whose graphics family cannot present.
+// Every frame command buffer ends with the release and a command buffer on the present queue
+// acquires the image before it is presented. Rendering starts from UNDEFINED, so images coming
+// back from the presentation engine need no transfer.
+pub struct PresentTransfer {
+    device: ash::Device,
+    graphics_family: u32,
+    present_family: u32,
+    // Whether the present family supports copies, which screenshots are taken with
+    can_copy: bool,
+    command_pool: vk::CommandPool,
+    // One per swapchain image, recorded with the frame command buffers
+    acquire_command_buffers: Vec<vk::CommandBuffer>,
+    // One per frame in flight, signaled by the acquire and waited on by the present
+    acquired_semaphores: Vec<vk::Semaphore>,
+    ownership: QueueOwnership,
+    images: Vec<vk::Image>,
+}
+
+impl PresentTransfer {
+    // None when the graphics family presents itself
+    pub fn new(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        queue_family: &QueueFamilyIndices,
+        frames_in_flight: usize,
+    ) -> Option<PresentTransfer> {
+        let graphics_family = queue_family.graphics_family.unwrap();
+        let present_family = queue_family.present_family.unwrap();
+        if graphics_family == present_family {
+            return None;
+        }
+
+        let queue_flags =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) }
+                [present_family as usize]
+                .queue_flags;
+        let can_copy = queue_flags.intersects(
+            vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER,
+        );
+
+        unsafe {
+            let command_pool = device
+                .create_command_pool(
+                    &vk::CommandPoolCreateInfo::builder()
+                        .queue_family_index(present_family)
+                        .build(),
+                    None,
+                )
+                .expect("Failed to create present Command Pool!");
+            let acquired_semaphores = (0..frames_in_flight)
+                .map(|_| {
+                    device
+                        .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                        .expect("Failed to create Semaphore Object!")
+                })
+                .collect();
+
+            Some(PresentTransfer {
+                device: device.clone(),
+                graphics_family,
+                present_family,
+                can_copy,
+                command_pool,
+                acquire_command_buffers: Vec::new(),
+                acquired_semaphores,
+                ownership: QueueOwnership::new(device.clone()),
+                images: Vec::new(),
+            })
+        }
+    }
+
+    pub fn can_copy(&self) -> bool {
+        self.can_copy
+    }
+
+    // For one time commands on the present queue
+    pub fn command_pool(&self) -> vk::CommandPool {
+        self.command_pool
+    }
+
+    // Ends the frame command buffer of `image`, after the render pass left it in PRESENT_SRC
+    pub fn record_release(&mut self, command_buffer: vk::CommandBuffer, image: vk::Image) {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        self.ownership
+            .track_image(image, subresource_range, self.graphics_family);
+        self.ownership.release_image(
+            command_buffer,
+            image,
+            self.present_family,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            QueueUse {
+                stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            },
+        );
+    }
+
+    // Records the acquires matching the releases of the swapchain images, in image order, after
+    // free_command_buffers freed those of the last recording
+    pub fn record_acquires(&mut self, images: &[vk::Image]) {
+        unsafe {
+            self.acquire_command_buffers = self
+                .device
+                .allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo::builder()
+                        .command_pool(self.command_pool)
+                        .level(vk::CommandBufferLevel::PRIMARY)
+                        .command_buffer_count(images.len() as u32)
+                        .build(),
+                )
+                .expect("Failed to allocate Command Buffers!");
+
+            for (&command_buffer, &image) in self.acquire_command_buffers.iter().zip(images) {
+                self.device
+                    .begin_command_buffer(
+                        command_buffer,
+                        &vk::CommandBufferBeginInfo::builder()
+                            .flags(vk::CommandBufferUsageFlags::SIMULTANEOUS_USE)
+                            .build(),
+                    )
+                    .expect("Failed to begin recording Command Buffer at beginning!");
+                // All commands, so a screenshot copy submitted after it is ordered too
+                self.ownership.acquire_image(
+                    command_buffer,
+                    image,
+                    QueueUse {
+                        stage: vk::PipelineStageFlags::ALL_COMMANDS,
+                        access: vk::AccessFlags::empty(),
+                    },
+                );
+                self.device
+                    .end_command_buffer(command_buffer)
+                    .expect("Failed to record Command Buffer at Ending!");
+            }
+        }
+        self.images = images.to_vec();
+    }
+
+    // Acquires the swapchain image after `render_finished`, returns the semaphore the present
+    // waits on instead
+    pub fn submit(
+        &self,
+        present_queue: vk::Queue,
+        frame: usize,
+        image_index: u32,
+        render_finished: vk::Semaphore,
+    ) -> vk::Semaphore {
+        let wait_semaphores = [render_finished];
+        let wait_stages = [vk::PipelineStageFlags::ALL_COMMANDS];
+        let command_buffers = [self.acquire_command_buffers[image_index as usize]];
+        let signal_semaphores = [self.acquired_semaphores[frame]];
+        let submit_info = vk::SubmitInfo {
+            s_type: vk::StructureType::SUBMIT_INFO,
+            p_next: ptr::null(),
+            wait_semaphore_count: wait_semaphores.len() as u32,
+            p_wait_semaphores: wait_semaphores.as_ptr(),
+            p_wait_dst_stage_mask: wait_stages.as_ptr(),
+            command_buffer_count: command_buffers.len() as u32,
+            p_command_buffers: command_buffers.as_ptr(),
+            signal_semaphore_count: signal_semaphores.len() as u32,
+            p_signal_semaphores: signal_semaphores.as_ptr(),
+        };
+        unsafe {
+            self.device
+                .queue_submit(present_queue, &[submit_info], vk::Fence::null())
+                .expect("Failed to execute queue submit.");
+        }
+        signal_semaphores[0]
+    }
+
+    // With the frame command buffers, before the swapchain is destroyed
+    pub fn free_command_buffers(&mut self) {
+        if !self.acquire_command_buffers.is_empty() {
+            unsafe {
+                self.device
+                    .free_command_buffers(self.command_pool, &self.acquire_command_buffers);
+            }
+            self.acquire_command_buffers.clear();
+        }
+        for image in self.images.drain(..) {
+            self.ownership.forget_image(image);
+        }
+    }
+
+    pub fn destroy(&mut self) {
+        self.free_command_buffers();
+        unsafe {
+            for &semaphore in &self.acquired_semaphores {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+}
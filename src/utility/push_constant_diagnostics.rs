@@ -0,0 +1,527 @@
+use super::descriptor_diagnostics::execution_model_stage;
+use super::structures::*;
+
+use ash::vk;
+use memoffset::span_of;
+
+use std::collections::HashMap;
+use std::fmt;
+
+// Push constant block member a shader declares, nested structs flattened into their members
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReflectedMember {
+    // Dotted through the nested structs, or the member index when the module has no names
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+}
+
+// The push constant block of a SPIR-V module
+#[derive(Clone, Debug)]
+pub struct ReflectedPushConstants {
+    // The union of the stages of every entry point of the module
+    pub stage: vk::ShaderStageFlags,
+    // By offset
+    pub members: Vec<ReflectedMember>,
+}
+
+const OP_MEMBER_NAME: u32 = 6;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+
+// Nul terminated UTF-8 packed into words, little end first
+fn literal_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+struct Module {
+    types: HashMap<u32, (u32, Vec<u32>)>,
+    constants: HashMap<u32, u32>,
+    array_strides: HashMap<u32, u32>,
+    // By struct type and member index
+    member_offsets: HashMap<(u32, u32), u32>,
+    matrix_strides: HashMap<(u32, u32), u32>,
+    member_names: HashMap<(u32, u32), String>,
+}
+
+impl Module {
+    // In bytes as laid out in the block, None for types a push constant block cannot hold
+    fn size_of(&self, type_id: u32, matrix_stride: Option<u32>) -> Option<u32> {
+        let (opcode, operands) = self.types.get(&type_id)?;
+        match *opcode {
+            OP_TYPE_BOOL => Some(4),
+            OP_TYPE_INT | OP_TYPE_FLOAT => Some(operands[0] / 8),
+            OP_TYPE_VECTOR => Some(operands[1] * self.size_of(operands[0], None)?),
+            OP_TYPE_MATRIX => {
+                let column_stride = match matrix_stride {
+                    Some(stride) => stride,
+                    None => self.size_of(operands[0], None)?,
+                };
+                Some(operands[1] * column_stride)
+            }
+            OP_TYPE_ARRAY => {
+                let length = *self.constants.get(&operands[1])?;
+                let stride = match self.array_strides.get(&type_id) {
+                    Some(&stride) => stride,
+                    None => self.size_of(operands[0], None)?,
+                };
+                Some(length * stride)
+            }
+            OP_TYPE_STRUCT => (0..operands.len() as u32)
+                .map(|member| {
+                    let offset = self.member_offsets.get(&(type_id, member)).copied()?;
+                    let stride = self.matrix_strides.get(&(type_id, member)).copied();
+                    Some(offset + self.size_of(operands[member as usize], stride)?)
+                })
+                .try_fold(0, |size, end| Some(size.max(end?))),
+            _ => None,
+        }
+    }
+
+    fn flatten(&self, type_id: u32, offset: u32, prefix: &str, members: &mut Vec<ReflectedMember>) {
+        let member_types = match self.types.get(&type_id) {
+            Some((OP_TYPE_STRUCT, operands)) => operands,
+            _ => return,
+        };
+        for (member, &member_type) in member_types.iter().enumerate() {
+            let member = member as u32;
+            let name = match self.member_names.get(&(type_id, member)) {
+                Some(name) if !name.is_empty() => name.clone(),
+                _ => member.to_string(),
+            };
+            let name = if prefix.is_empty() {
+                name
+            } else {
+                format!("{}.{}", prefix, name)
+            };
+            let member_offset = offset
+                + self
+                    .member_offsets
+                    .get(&(type_id, member))
+                    .copied()
+                    .unwrap_or(0);
+            if matches!(self.types.get(&member_type), Some((OP_TYPE_STRUCT, _))) {
+                self.flatten(member_type, member_offset, &name, members);
+                continue;
+            }
+            let stride = self.matrix_strides.get(&(type_id, member)).copied();
+            members.push(ReflectedMember {
+                name,
+                offset: member_offset,
+                size: self.size_of(member_type, stride).unwrap_or(0),
+            });
+        }
+    }
+}
+
+/// The push constant block of a SPIR-V module, None when it declares none
+pub fn reflect_push_constants(code: &[u32]) -> Option<ReflectedPushConstants> {
+    let mut stage = vk::ShaderStageFlags::empty();
+    let mut module = Module {
+        types: HashMap::new(),
+        constants: HashMap::new(),
+        array_strides: HashMap::new(),
+        member_offsets: HashMap::new(),
+        matrix_strides: HashMap::new(),
+        member_names: HashMap::new(),
+    };
+    let mut block_pointer = None;
+
+    // Skip the 5 word header
+    let mut position = 5;
+    while position < code.len() {
+        let word_count = (code[position] >> 16) as usize;
+        let opcode = code[position] & 0xffff;
+        if word_count == 0 || position + word_count > code.len() {
+            break;
+        }
+        let operands = &code[position + 1..position + word_count];
+
+        match opcode {
+            OP_ENTRY_POINT => stage |= execution_model_stage(operands[0]),
+            OP_MEMBER_NAME => {
+                module
+                    .member_names
+                    .insert((operands[0], operands[1]), literal_string(&operands[2..]));
+            }
+            OP_DECORATE if operands.get(1) == Some(&DECORATION_ARRAY_STRIDE) => {
+                module.array_strides.insert(operands[0], operands[2]);
+            }
+            OP_MEMBER_DECORATE => match operands[2] {
+                DECORATION_OFFSET => {
+                    module
+                        .member_offsets
+                        .insert((operands[0], operands[1]), operands[3]);
+                }
+                DECORATION_MATRIX_STRIDE => {
+                    module
+                        .matrix_strides
+                        .insert((operands[0], operands[1]), operands[3]);
+                }
+                _ => {}
+            },
+            OP_CONSTANT => {
+                module.constants.insert(operands[1], operands[2]);
+            }
+            OP_VARIABLE if operands[2] == STORAGE_CLASS_PUSH_CONSTANT => {
+                block_pointer = Some(operands[0]);
+            }
+            OP_TYPE_BOOL | OP_TYPE_INT | OP_TYPE_FLOAT | OP_TYPE_VECTOR | OP_TYPE_MATRIX
+            | OP_TYPE_ARRAY | OP_TYPE_STRUCT | OP_TYPE_POINTER => {
+                module
+                    .types
+                    .insert(operands[0], (opcode, operands[1..].to_vec()));
+            }
+            _ => {}
+        }
+
+        position += word_count;
+    }
+
+    let block_type = match module.types.get(&block_pointer?) {
+        Some((OP_TYPE_POINTER, operands)) => operands[1],
+        _ => return None,
+    };
+    let mut members = Vec::new();
+    module.flatten(block_type, 0, "", &mut members);
+    members.sort_by_key(|member| member.offset);
+    Some(ReflectedPushConstants { stage, members })
+}
+
+// A field of a Rust push constant struct, at its offset in the whole push constant range
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PushConstantField {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+}
+
+// Push constant structs whose fields can be compared against what the shaders declare, see
+// push_constant_fields!
+pub trait PushConstantFields: Sized {
+    // Relative to the start of the struct
+    fn fields() -> Vec<PushConstantField>;
+}
+
+// Implements PushConstantFields for a #[repr(C)] struct from the list of its fields
+macro_rules! push_constant_fields {
+    ($($struct:ident { $($field:ident),* $(,)? })*) => {
+        $(
+            impl PushConstantFields for $struct {
+                fn fields() -> Vec<PushConstantField> {
+                    vec![$({
+                        let span = span_of!($struct, $field);
+                        PushConstantField {
+                            name: format!("{}::{}", stringify!($struct), stringify!($field)),
+                            offset: span.start as u32,
+                            size: (span.end - span.start) as u32,
+                        }
+                    }),*]
+                }
+            }
+        )*
+    };
+}
+
+push_constant_fields! {
+    CameraPushConstants { origin, right, up, forward, window }
+    AccumulationPushConstants { weight, sample_index }
+    DepthRangePushConstants { near, far }
+    RayMaskPushConstants { primary, secondary }
+    ViewportPushConstants { offset, size, checkerboard }
+    RayBiasPushConstants { t_min, offset_scale, offset }
+    RestirPushConstants {
+        frame_index, light_count, initial_candidates, max_history, spatial_neighbors,
+        spatial_radius, width, height,
+    }
+    CheckerboardPushConstants { width, height, parity, has_history }
+    ExposurePushConstants {
+        min_log_luminance, log_luminance_range, delta_time, adaptation_speed, ev_compensation,
+        width, height, tone_curve, lut_domain_min, lut_domain_max,
+    }
+    RayQueryPushConstants {
+        camera, viewport_offset, viewport_size, accumulation_weight, sample_index, primary_mask,
+        secondary_mask,
+    }
+    HizBuildPushConstants { src_size, dst_size }
+    LightCullPushConstants { camera, near, far, light_count }
+    SelectionOutlinePushConstants { color, width, height, instance_count, radius }
+    SkinningPushConstants { vertex_count, morph_target_count, first_word, half_floats }
+    OcclusionCullPushConstants {
+        clip_from_world, pyramid_size, mip_count, instance_count, phase, index_count,
+        first_index, vertex_offset,
+    }
+}
+
+// The Rust structs pushed into one pipeline layout, in the order of their offsets
+#[derive(Clone, Debug, Default)]
+pub struct PushConstantLayout {
+    fields: Vec<PushConstantField>,
+    size: u32,
+}
+
+impl PushConstantLayout {
+    // For pipelines without push constants
+    pub fn empty() -> PushConstantLayout {
+        PushConstantLayout::default()
+    }
+
+    pub fn of<T: PushConstantFields>() -> PushConstantLayout {
+        PushConstantLayout::empty().then::<T>()
+    }
+
+    // Pushed right after everything before it, like the ray tracing constants are
+    pub fn then<T: PushConstantFields>(mut self) -> PushConstantLayout {
+        let offset = self.size;
+        self.fields
+            .extend(T::fields().into_iter().map(|field| PushConstantField {
+                offset: offset + field.offset,
+                ..field
+            }));
+        self.size = offset + std::mem::size_of::<T>() as u32;
+        self
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    // Fields overlapping [offset, end)
+    fn overlapping(&self, offset: u32, end: u32) -> Vec<&PushConstantField> {
+        self.fields
+            .iter()
+            .filter(|field| field.offset < end && offset < field.offset + field.size)
+            .collect()
+    }
+
+    // Whether the shader member reads what the Rust side writes there: either it lies within a
+    // single field, like the members of a nested struct, or it starts and ends with the fields
+    // it covers and they leave no padding in between
+    fn matches(&self, member: &ReflectedMember) -> bool {
+        let end = member.offset + member.size;
+        let fields = self.overlapping(member.offset, end);
+        let (first, last) = match (fields.first(), fields.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return false,
+        };
+        if fields.len() == 1 && first.offset <= member.offset && end <= first.offset + first.size {
+            return true;
+        }
+        first.offset == member.offset
+            && last.offset + last.size == end
+            && fields
+                .windows(2)
+                .all(|pair| pair[0].offset + pair[0].size == pair[1].offset)
+    }
+}
+
+// The Rust push constant structs of a pipeline layout next to the push constant blocks its
+// shaders declare, so a member read at another offset than it is written fails before it shades
+// anything with garbage
+pub struct PushConstantDiagnostics {
+    label: String,
+    layout: PushConstantLayout,
+    // None until set_ranges, the ranges are not checked before
+    ranges: Option<Vec<vk::PushConstantRange>>,
+    shaders: Vec<(String, ReflectedPushConstants)>,
+}
+
+impl PushConstantDiagnostics {
+    pub fn new(label: &str, layout: PushConstantLayout) -> PushConstantDiagnostics {
+        PushConstantDiagnostics {
+            label: label.to_string(),
+            layout,
+            ranges: None,
+            shaders: Vec::new(),
+        }
+    }
+
+    pub fn set_ranges(&mut self, ranges: &[vk::PushConstantRange]) {
+        self.ranges = Some(ranges.to_vec());
+    }
+
+    // Shaders without push constants are left out
+    pub fn add_shader(&mut self, name: &str, code: &[u32]) {
+        if let Some(push_constants) = reflect_push_constants(code) {
+            self.shaders.push((name.to_string(), push_constants));
+        }
+    }
+
+    pub fn clear_shaders(&mut self) {
+        self.shaders.clear();
+    }
+
+    /// Members that do not line up with the Rust fields or the ranges, one line each
+    pub fn mismatches(&self) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        for (name, push_constants) in &self.shaders {
+            for member in &push_constants.members {
+                let end = member.offset + member.size;
+                if member.size == 0 {
+                    mismatches.push(format!(
+                        "{} declares {} with a type of unknown size",
+                        name, member.name
+                    ));
+                } else if end > self.layout.size {
+                    mismatches.push(format!(
+                        "{} reads {} at {}..{}, past the {} bytes pushed",
+                        name, member.name, member.offset, end, self.layout.size
+                    ));
+                } else if !self.layout.matches(member) {
+                    let fields: Vec<String> = self
+                        .layout
+                        .overlapping(member.offset, end)
+                        .iter()
+                        .map(|field| {
+                            format!(
+                                "{} at {}..{}",
+                                field.name,
+                                field.offset,
+                                field.offset + field.size
+                            )
+                        })
+                        .collect();
+                    mismatches.push(format!(
+                        "{} reads {} at {}..{} where {} is written",
+                        name,
+                        member.name,
+                        member.offset,
+                        end,
+                        if fields.is_empty() {
+                            String::from("only padding")
+                        } else {
+                            fields.join(" and ")
+                        }
+                    ));
+                }
+
+                // A library with several entry points only needs the member in a range of one
+                if let Some(ranges) = self.ranges.as_ref() {
+                    let covered = ranges
+                        .iter()
+                        .filter(|range| range.stage_flags.intersects(push_constants.stage))
+                        .any(|range| {
+                            range.offset <= member.offset && end <= range.offset + range.size
+                        });
+                    if !covered {
+                        mismatches.push(format!(
+                            "{} ({:?}) reads {} at {}..{} outside of its push constant ranges",
+                            name, push_constants.stage, member.name, member.offset, end
+                        ));
+                    }
+                }
+            }
+        }
+        mismatches
+    }
+}
+
+// Side by side by offset, with the rows that do not line up marked
+impl fmt::Display for PushConstantDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Push constants of {}:", self.label)?;
+        if let Some(ranges) = self.ranges.as_ref() {
+            for range in ranges {
+                writeln!(
+                    f,
+                    " range {}..{} [{:?}]",
+                    range.offset,
+                    range.offset + range.size,
+                    range.stage_flags
+                )?;
+            }
+        }
+        for (name, push_constants) in &self.shaders {
+            writeln!(f, " {} [{:?}]:", name, push_constants.stage)?;
+            let mut rows: Vec<(u32, String, String, bool)> = push_constants
+                .members
+                .iter()
+                .map(|member| {
+                    let fields: Vec<String> = self
+                        .layout
+                        .overlapping(member.offset, member.offset + member.size)
+                        .iter()
+                        .map(|field| format!("{} ({})", field.name, field.size))
+                        .collect();
+                    (
+                        member.offset,
+                        format!("{} ({})", member.name, member.size),
+                        if fields.is_empty() {
+                            String::from("-")
+                        } else {
+                            fields.join(", ")
+                        },
+                        member.size == 0 || !self.layout.matches(member),
+                    )
+                })
+                .collect();
+            // Rust fields between its members the shader has nothing at, which is fine for
+            // explicitly placed members but a likely cause of the ones that do not line up
+            let (first, last) = match (
+                push_constants.members.first(),
+                push_constants.members.last(),
+            ) {
+                (Some(first), Some(last)) => (first.offset, last.offset + last.size),
+                _ => continue,
+            };
+            for field in &self.layout.fields {
+                let unread = field.offset >= first
+                    && field.offset + field.size <= last
+                    && !push_constants.members.iter().any(|member| {
+                        member.offset < field.offset + field.size
+                            && field.offset < member.offset + member.size
+                    });
+                if unread {
+                    rows.push((
+                        field.offset,
+                        String::from("-"),
+                        format!("{} ({})", field.name, field.size),
+                        false,
+                    ));
+                }
+            }
+            rows.sort_by_key(|row| row.0);
+            for (offset, member, fields, mismatched) in rows {
+                writeln!(
+                    f,
+                    " {} {:>4}  {:<32} {}",
+                    if mismatched { '!' } else { ' ' },
+                    offset,
+                    member,
+                    fields
+                )?;
+            }
+        }
+
+        let mismatches = self.mismatches();
+        if !mismatches.is_empty() {
+            writeln!(f, " mismatches:")?;
+            for mismatch in mismatches {
+                writeln!(f, "  {}", mismatch)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,300 @@
+use ash::vk;
+use ash::vk::Handle;
+
+use std::collections::HashMap;
+
+// Pipeline stages and accesses of one side of a transfer
+#[derive(Clone, Copy, Debug)]
+pub struct QueueUse {
+    pub stage: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+}
+
+// Second half of a transfer, recorded by the release and consumed by the acquire
+#[derive(Clone, Copy, Debug)]
+struct PendingTransfer {
+    src_queue_family: u32,
+    dst_queue_family: u32,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    // Last use on the old queue, only needed by the acquire when no family changes hands
+    last_use: QueueUse,
+}
+
+// Queue family ownership of exclusive images and buffers used from more than one queue. A
+// transfer is a release barrier recorded on the old queue and a matching acquire barrier on
+// the new one, with a semaphore between the two submits; the tracker keeps both barriers
+// identical and knows which family owns each resource.
+pub struct QueueOwnership {
+    device: ash::Device,
+    owners: HashMap<u64, u32>,
+    // Transfers always cover the whole subresource range an image was tracked with
+    image_ranges: HashMap<u64, vk::ImageSubresourceRange>,
+    pending: HashMap<u64, PendingTransfer>,
+}
+
+impl QueueOwnership {
+    pub fn new(device: ash::Device) -> QueueOwnership {
+        QueueOwnership {
+            device,
+            owners: HashMap::new(),
+            image_ranges: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    // Exclusive resources belong to the first queue family that uses them
+    pub fn track_image(
+        &mut self,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        queue_family: u32,
+    ) {
+        self.owners.insert(image.as_raw(), queue_family);
+        self.image_ranges.insert(image.as_raw(), subresource_range);
+    }
+
+    pub fn track_buffer(&mut self, buffer: vk::Buffer, queue_family: u32) {
+        self.owners.insert(buffer.as_raw(), queue_family);
+    }
+
+    pub fn forget_image(&mut self, image: vk::Image) {
+        self.owners.remove(&image.as_raw());
+        self.image_ranges.remove(&image.as_raw());
+        self.pending.remove(&image.as_raw());
+    }
+
+    pub fn forget_buffer(&mut self, buffer: vk::Buffer) {
+        self.owners.remove(&buffer.as_raw());
+        self.pending.remove(&buffer.as_raw());
+    }
+
+    pub fn image_owner(&self, image: vk::Image) -> Option<u32> {
+        self.owners.get(&image.as_raw()).copied()
+    }
+
+    pub fn buffer_owner(&self, buffer: vk::Buffer) -> Option<u32> {
+        self.owners.get(&buffer.as_raw()).copied()
+    }
+
+    fn begin_transfer(
+        &mut self,
+        handle: u64,
+        dst_queue_family: u32,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        last_use: QueueUse,
+    ) -> PendingTransfer {
+        let src_queue_family = *self
+            .owners
+            .get(&handle)
+            .expect("Released resource has no owning queue family.");
+        assert!(
+            !self.pending.contains_key(&handle),
+            "Resource is already being transferred."
+        );
+
+        let transfer = PendingTransfer {
+            src_queue_family,
+            dst_queue_family,
+            old_layout,
+            new_layout,
+            last_use,
+        };
+        self.pending.insert(handle, transfer);
+        transfer
+    }
+
+    fn finish_transfer(&mut self, handle: u64) -> PendingTransfer {
+        let transfer = self
+            .pending
+            .remove(&handle)
+            .expect("Acquired resource was never released.");
+        self.owners.insert(handle, transfer.dst_queue_family);
+        transfer
+    }
+
+    // Recorded on the owning queue, last_use covers the accesses made there. The layout change
+    // happens once, as part of the transfer.
+    pub fn release_image(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        dst_queue_family: u32,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        last_use: QueueUse,
+    ) {
+        let transfer = self.begin_transfer(
+            image.as_raw(),
+            dst_queue_family,
+            old_layout,
+            new_layout,
+            last_use,
+        );
+
+        // Within one family the acquire barrier alone is enough
+        if transfer.src_queue_family == transfer.dst_queue_family {
+            return;
+        }
+
+        let release_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(last_use.access)
+            .dst_access_mask(vk::AccessFlags::empty())
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(transfer.src_queue_family)
+            .dst_queue_family_index(transfer.dst_queue_family)
+            .image(image)
+            .subresource_range(self.image_range(image))
+            .build();
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                last_use.stage,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[release_barrier],
+            );
+        }
+    }
+
+    // Recorded on the new queue, after waiting on the semaphore signaled by the release submit
+    pub fn acquire_image(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        first_use: QueueUse,
+    ) {
+        let transfer = self.finish_transfer(image.as_raw());
+        let (src_queue_family, dst_queue_family, src_use) = acquire_source(&transfer);
+
+        let acquire_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(src_use.access)
+            .dst_access_mask(first_use.access)
+            .old_layout(transfer.old_layout)
+            .new_layout(transfer.new_layout)
+            .src_queue_family_index(src_queue_family)
+            .dst_queue_family_index(dst_queue_family)
+            .image(image)
+            .subresource_range(self.image_range(image))
+            .build();
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                src_use.stage,
+                first_use.stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[acquire_barrier],
+            );
+        }
+    }
+
+    pub fn release_buffer(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        dst_queue_family: u32,
+        last_use: QueueUse,
+    ) {
+        let transfer = self.begin_transfer(
+            buffer.as_raw(),
+            dst_queue_family,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::UNDEFINED,
+            last_use,
+        );
+
+        if transfer.src_queue_family == transfer.dst_queue_family {
+            return;
+        }
+
+        let release_barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(last_use.access)
+            .dst_access_mask(vk::AccessFlags::empty())
+            .src_queue_family_index(transfer.src_queue_family)
+            .dst_queue_family_index(transfer.dst_queue_family)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                last_use.stage,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[release_barrier],
+                &[],
+            );
+        }
+    }
+
+    pub fn acquire_buffer(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        first_use: QueueUse,
+    ) {
+        let transfer = self.finish_transfer(buffer.as_raw());
+        let (src_queue_family, dst_queue_family, src_use) = acquire_source(&transfer);
+
+        let acquire_barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(src_use.access)
+            .dst_access_mask(first_use.access)
+            .src_queue_family_index(src_queue_family)
+            .dst_queue_family_index(dst_queue_family)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                src_use.stage,
+                first_use.stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[acquire_barrier],
+                &[],
+            );
+        }
+    }
+
+    fn image_range(&self, image: vk::Image) -> vk::ImageSubresourceRange {
+        *self
+            .image_ranges
+            .get(&image.as_raw())
+            .expect("Image is not tracked.")
+    }
+}
+
+// Queue families and source scope of an acquire barrier. Without a family change it is a plain
+// barrier that has to cover the last use itself, since no release was recorded.
+fn acquire_source(transfer: &PendingTransfer) -> (u32, u32, QueueUse) {
+    if transfer.src_queue_family == transfer.dst_queue_family {
+        (
+            vk::QUEUE_FAMILY_IGNORED,
+            vk::QUEUE_FAMILY_IGNORED,
+            transfer.last_use,
+        )
+    } else {
+        (
+            transfer.src_queue_family,
+            transfer.dst_queue_family,
+            QueueUse {
+                stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                access: vk::AccessFlags::empty(),
+            },
+        )
+    }
+}
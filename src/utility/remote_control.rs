@@ -0,0 +1,252 @@
+use serde_json::{json, Value};
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::utility::websocket::{self, *};
+
+// Longest request line or message a client may send, longer ones drop the client
+const MAX_REQUEST_LENGTH: usize = 64 << 10;
+// Replies a client may leave unread, past that it is dropped rather than buffered further
+const MAX_UNSENT_LENGTH: usize = 1 << 20;
+// Where a bare port listens, other machines have to be allowed with an explicit host
+const DEFAULT_HOST: &str = "127.0.0.1";
+
+enum Protocol {
+    // Until the first byte arrived: a WebSocket handshake starts with `GET`, a request line
+    // with the JSON object. Anything else, like a form a web page posts here, is refused.
+    Undecided,
+    Lines,
+    // The text of the message whose continuation frames are still arriving
+    WebSocket { message: Vec<u8> },
+}
+
+struct Client {
+    stream: TcpStream,
+    // Received bytes up to the end of the last complete line or frame
+    pending: Vec<u8>,
+    // Replies the socket did not take yet, written as it drains
+    unsent: Vec<u8>,
+    protocol: Protocol,
+}
+
+// Accepts JSON requests over TCP, one object per line, e.g. `{"id": 1, "command": "stats"}`.
+// The command runs like a line typed into the console and the reply is one line too:
+// `{"id": 1, "ok": true, "output": "..."}`, or `"ok": false` with the error as the output.
+// Browsers connect to the same port over WebSocket, one request and reply per text message.
+// Nonblocking, polled once per frame from the window loop so commands run between frames.
+pub struct RemoteControlServer {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    // Origins of the web pages that may connect over WebSocket, see websocket::handshake
+    allowed_origins: Vec<String>,
+}
+
+impl RemoteControlServer {
+    // `address` is `host:port`, or a port alone to listen on the loopback interface only
+    pub fn bind(address: &str, allowed_origins: Vec<String>) -> io::Result<RemoteControlServer> {
+        let listener = match address.parse::<u16>() {
+            Ok(port) => TcpListener::bind((DEFAULT_HOST, port))?,
+            Err(_) => TcpListener::bind(address)?,
+        };
+        listener.set_nonblocking(true)?;
+        tracing::info!("Remote control listening on {}", listener.local_addr()?);
+        Ok(RemoteControlServer {
+            listener,
+            clients: Vec::new(),
+            allowed_origins,
+        })
+    }
+
+    // Accepts new clients and runs every complete request line through `execute`
+    pub fn poll(&mut self, mut execute: impl FnMut(&str) -> Result<String, String>) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    if let Err(err) = stream.set_nonblocking(true) {
+                        tracing::warn!("Dropping remote control client {}: {}", address, err);
+                        continue;
+                    }
+                    tracing::info!("Remote control client {} connected", address);
+                    self.clients.push(Client {
+                        stream,
+                        pending: Vec::new(),
+                        unsent: Vec::new(),
+                        protocol: Protocol::Undecided,
+                    });
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    tracing::warn!("Remote control accept failed: {}", err);
+                    break;
+                }
+            }
+        }
+
+        let allowed_origins = &self.allowed_origins;
+        self.clients
+            .retain_mut(|client| match client.serve(&mut execute, allowed_origins) {
+                Ok(is_open) => is_open,
+                Err(err) => {
+                    tracing::warn!("Dropping remote control client: {}", err);
+                    false
+                }
+            });
+    }
+}
+
+impl Client {
+    // False once the client disconnected
+    fn serve(
+        &mut self,
+        execute: &mut impl FnMut(&str) -> Result<String, String>,
+        allowed_origins: &[String],
+    ) -> io::Result<bool> {
+        self.flush()?;
+        let mut buffer = [0u8; 4096];
+        let is_open = loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => break false,
+                Ok(read) => self.pending.extend_from_slice(&buffer[..read]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break true,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        };
+
+        if let Protocol::Undecided = self.protocol {
+            match self.pending.first() {
+                Some(b'G') => match websocket::handshake(&self.pending, allowed_origins)? {
+                    Some((length, response)) => {
+                        self.pending.drain(..length);
+                        self.send(&response)?;
+                        self.protocol = Protocol::WebSocket {
+                            message: Vec::new(),
+                        };
+                    }
+                    None => return self.check_pending(is_open),
+                },
+                Some(b'{') => self.protocol = Protocol::Lines,
+                Some(_) => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "not a JSON request or WebSocket upgrade",
+                    ))
+                }
+                None => return Ok(is_open),
+            }
+        }
+        let is_open = match self.protocol {
+            Protocol::WebSocket { .. } => self.serve_frames(execute)? && is_open,
+            _ => {
+                self.serve_lines(execute)?;
+                is_open
+            }
+        };
+        self.check_pending(is_open)
+    }
+
+    fn serve_lines(
+        &mut self,
+        execute: &mut impl FnMut(&str) -> Result<String, String>,
+    ) -> io::Result<()> {
+        while let Some(end) = self.pending.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            let reply = respond(line.trim(), execute);
+            self.send(format!("{}\n", reply).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    // False once the client closed the connection
+    fn serve_frames(
+        &mut self,
+        execute: &mut impl FnMut(&str) -> Result<String, String>,
+    ) -> io::Result<bool> {
+        while let Some((length, frame)) = websocket::parse_frame(&self.pending)? {
+            self.pending.drain(..length);
+            match frame.opcode {
+                OPCODE_TEXT | OPCODE_BINARY | OPCODE_CONTINUATION => {
+                    let message = match &mut self.protocol {
+                        Protocol::WebSocket { message } => message,
+                        _ => unreachable!(),
+                    };
+                    message.extend_from_slice(&frame.payload);
+                    if message.len() > MAX_REQUEST_LENGTH {
+                        return Err(io::Error::new(ErrorKind::InvalidData, "message too long"));
+                    }
+                    if !frame.is_final {
+                        continue;
+                    }
+                    let message = std::mem::take(message);
+                    let reply = respond(String::from_utf8_lossy(&message).trim(), execute);
+                    self.send(&encode_frame(OPCODE_TEXT, reply.to_string().as_bytes()))?;
+                }
+                OPCODE_PING => self.send(&encode_frame(OPCODE_PONG, &frame.payload))?,
+                OPCODE_CLOSE => {
+                    self.send(&encode_frame(OPCODE_CLOSE, &frame.payload))?;
+                    return Ok(false);
+                }
+                _ => {}
+            }
+        }
+        Ok(true)
+    }
+
+    // Queues the bytes behind the unsent ones and writes what the socket takes without blocking
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.unsent.extend_from_slice(bytes);
+        self.flush()?;
+        if self.unsent.len() > MAX_UNSENT_LENGTH {
+            return Err(io::Error::new(
+                ErrorKind::WouldBlock,
+                "client stopped reading its replies",
+            ));
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.unsent.is_empty() {
+            match self.stream.write(&self.unsent) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.unsent.drain(..written);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn check_pending(&self, is_open: bool) -> io::Result<bool> {
+        if self.pending.len() > MAX_REQUEST_LENGTH {
+            return Err(io::Error::new(ErrorKind::InvalidData, "request too long"));
+        }
+        Ok(is_open)
+    }
+}
+
+fn respond(line: &str, execute: &mut impl FnMut(&str) -> Result<String, String>) -> Value {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => {
+            return json!({ "id": Value::Null, "ok": false, "output": format!("Invalid JSON: {}", err) })
+        }
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let result = match request.get("command").and_then(Value::as_str) {
+        Some(command) => execute(command),
+        None => Err("Expected a \"command\" string".to_string()),
+    };
+    match result {
+        Ok(output) => json!({ "id": id, "ok": true, "output": output }),
+        Err(output) => json!({ "id": id, "ok": false, "output": output }),
+    }
+}
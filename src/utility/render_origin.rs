@@ -0,0 +1,51 @@
+use cgmath::Vector3;
+
+use super::constants::RENDER_ORIGIN_CELL_SIZE;
+
+// World position the GPU's render space is centered on. World positions are kept in f64 on the
+// CPU and only their offset from the origin reaches the GPU as f32, so precision near the camera
+// does not depend on how far from the world origin the scene lies.
+//
+// The origin follows the camera in whole RENDER_ORIGIN_CELL_SIZE cells. Data that is not written
+// every frame, like light and emitter positions, only moves when the camera crosses into another
+// cell, and world space patterns such as the checker material line up across the move.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderOrigin {
+    world: Vector3<f64>,
+}
+
+impl Default for RenderOrigin {
+    fn default() -> Self {
+        RenderOrigin {
+            world: Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl RenderOrigin {
+    pub fn world(&self) -> Vector3<f64> {
+        self.world
+    }
+
+    pub fn to_render(&self, world: Vector3<f64>) -> Vector3<f32> {
+        (world - self.world).cast().unwrap()
+    }
+
+    pub fn to_world(&self, render: Vector3<f32>) -> Vector3<f64> {
+        self.world + render.cast().unwrap()
+    }
+
+    // Moves to the cell around `camera`, returns how far render space positions have to move to
+    // keep their world position when it did
+    pub fn follow(&mut self, camera: Vector3<f64>) -> Option<Vector3<f32>> {
+        let snap = |value: f64| (value / RENDER_ORIGIN_CELL_SIZE).round() * RENDER_ORIGIN_CELL_SIZE;
+        let world = Vector3::new(snap(camera.x), snap(camera.y), snap(camera.z));
+        if world == self.world {
+            return None;
+        }
+        // Whole cells, exact in f32 as long as the camera does not jump across 2^24 of them
+        let shift = (self.world - world).cast().unwrap();
+        self.world = world;
+        Some(shift)
+    }
+}
@@ -0,0 +1,239 @@
+use ash::vk;
+
+use super::general::create_buffer;
+
+// Generational index: a slot is reused after its resource is destroyed, but with a new
+// generation, so ids of the old resource stop resolving instead of aliasing the new one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct SlotId {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferId(SlotId);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageId(SlotId);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineId(SlotId);
+
+struct Slots<T> {
+    entries: Vec<(u32, Option<T>)>,
+    free: Vec<u32>,
+}
+
+impl<T> Slots<T> {
+    fn new() -> Slots<T> {
+        Slots {
+            entries: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, value: T) -> SlotId {
+        match self.free.pop() {
+            Some(index) => {
+                let entry = &mut self.entries[index as usize];
+                entry.0 += 1;
+                entry.1 = Some(value);
+                SlotId {
+                    index,
+                    generation: entry.0,
+                }
+            }
+            None => {
+                self.entries.push((0, Some(value)));
+                SlotId {
+                    index: self.entries.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    fn get(&self, id: SlotId) -> Option<&T> {
+        match self.entries.get(id.index as usize) {
+            Some((generation, value)) if *generation == id.generation => value.as_ref(),
+            _ => None,
+        }
+    }
+
+    fn remove(&mut self, id: SlotId) -> Option<T> {
+        match self.entries.get_mut(id.index as usize) {
+            Some((generation, value)) if *generation == id.generation => {
+                let removed = value.take();
+                if removed.is_some() {
+                    self.free.push(id.index);
+                }
+                removed
+            }
+            _ => None,
+        }
+    }
+
+    fn drain(&mut self) -> Vec<T> {
+        self.free.clear();
+        self.entries
+            .iter_mut()
+            .filter_map(|(_, value)| value.take())
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BufferEntry {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    pub size: vk::DeviceSize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImageEntry {
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    // Null when the image has no view
+    pub view: vk::ImageView,
+}
+
+// Owns device objects behind typed ids. Destroying through the registry invalidates every copy
+// of the id, so user code holding a stale id gets None instead of a dangling vk handle. The raw
+// handles stay reachable through the accessors for code that needs them, as long as it does not
+// keep them past the destruction of the id. Everything left is destroyed when the registry is
+// dropped, which has to happen before the device is destroyed.
+pub struct ResourceRegistry {
+    device: ash::Device,
+    buffers: Slots<BufferEntry>,
+    images: Slots<ImageEntry>,
+    pipelines: Slots<vk::Pipeline>,
+}
+
+impl ResourceRegistry {
+    pub fn new(device: ash::Device) -> ResourceRegistry {
+        ResourceRegistry {
+            device,
+            buffers: Slots::new(),
+            images: Slots::new(),
+            pipelines: Slots::new(),
+        }
+    }
+
+    pub fn create_buffer(
+        &mut self,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        required_memory_properties: vk::MemoryPropertyFlags,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> BufferId {
+        let (buffer, memory) = create_buffer(
+            &self.device,
+            size,
+            usage,
+            required_memory_properties,
+            device_memory_properties,
+        );
+        self.insert_buffer(buffer, memory, size)
+    }
+
+    // Takes ownership of objects created elsewhere
+    pub fn insert_buffer(
+        &mut self,
+        buffer: vk::Buffer,
+        memory: vk::DeviceMemory,
+        size: vk::DeviceSize,
+    ) -> BufferId {
+        BufferId(self.buffers.insert(BufferEntry {
+            buffer,
+            memory,
+            size,
+        }))
+    }
+
+    pub fn insert_image(
+        &mut self,
+        image: vk::Image,
+        memory: vk::DeviceMemory,
+        view: vk::ImageView,
+    ) -> ImageId {
+        ImageId(self.images.insert(ImageEntry {
+            image,
+            memory,
+            view,
+        }))
+    }
+
+    pub fn insert_pipeline(&mut self, pipeline: vk::Pipeline) -> PipelineId {
+        PipelineId(self.pipelines.insert(pipeline))
+    }
+
+    pub fn buffer(&self, id: BufferId) -> Option<&BufferEntry> {
+        self.buffers.get(id.0)
+    }
+
+    pub fn image(&self, id: ImageId) -> Option<&ImageEntry> {
+        self.images.get(id.0)
+    }
+
+    pub fn pipeline(&self, id: PipelineId) -> Option<vk::Pipeline> {
+        self.pipelines.get(id.0).copied()
+    }
+
+    // The caller makes sure the GPU is done with the object. Returns false for stale ids.
+    pub fn destroy_buffer(&mut self, id: BufferId) -> bool {
+        match self.buffers.remove(id.0) {
+            Some(entry) => {
+                unsafe { destroy_buffer_entry(&self.device, &entry) };
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn destroy_image(&mut self, id: ImageId) -> bool {
+        match self.images.remove(id.0) {
+            Some(entry) => {
+                unsafe { destroy_image_entry(&self.device, &entry) };
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn destroy_pipeline(&mut self, id: PipelineId) -> bool {
+        match self.pipelines.remove(id.0) {
+            Some(pipeline) => {
+                unsafe { self.device.destroy_pipeline(pipeline, None) };
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+unsafe fn destroy_buffer_entry(device: &ash::Device, entry: &BufferEntry) {
+    device.destroy_buffer(entry.buffer, None);
+    device.free_memory(entry.memory, None);
+}
+
+unsafe fn destroy_image_entry(device: &ash::Device, entry: &ImageEntry) {
+    device.destroy_image_view(entry.view, None);
+    device.destroy_image(entry.image, None);
+    device.free_memory(entry.memory, None);
+}
+
+impl Drop for ResourceRegistry {
+    fn drop(&mut self) {
+        unsafe {
+            for pipeline in self.pipelines.drain() {
+                self.device.destroy_pipeline(pipeline, None);
+            }
+            for entry in self.images.drain() {
+                destroy_image_entry(&self.device, &entry);
+            }
+            for entry in self.buffers.drain() {
+                destroy_buffer_entry(&self.device, &entry);
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use ash::vk;
+
+use std::collections::HashMap;
+
+// Filtering shared by every texture sampler, changed at runtime for quality and performance
+// comparisons
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureQuality {
+    // 1 turns anisotropic filtering off
+    pub max_anisotropy: f32,
+    // Added to the LOD the hardware picks, negative values sharpen
+    pub lod_bias: f32,
+    // Blends between mip levels, nearest mip level otherwise
+    pub trilinear: bool,
+}
+
+impl TextureQuality {
+    // Applies one `name=value` edit, as given to the options and the `texture_quality` command
+    pub fn edit(&mut self, edit: &str) -> Result<(), String> {
+        let (name, value) = edit
+            .split_once('=')
+            .ok_or_else(|| format!("Expected name=value, got {:?}", edit))?;
+        let invalid = || format!("Invalid {} value {:?}", name, value);
+        match name {
+            "max_anisotropy" => {
+                let max_anisotropy = value.parse::<f32>().map_err(|_| invalid())?;
+                if max_anisotropy.is_nan() || max_anisotropy < 1.0 {
+                    return Err(format!("max_anisotropy {} is below 1", max_anisotropy));
+                }
+                self.max_anisotropy = max_anisotropy;
+            }
+            "lod_bias" => {
+                let lod_bias = value.parse::<f32>().map_err(|_| invalid())?;
+                if !lod_bias.is_finite() {
+                    return Err(invalid());
+                }
+                self.lod_bias = lod_bias;
+            }
+            "trilinear" => self.trilinear = value.parse::<bool>().map_err(|_| invalid())?,
+            _ => return Err(format!("Unknown texture quality setting {}", name)),
+        }
+        Ok(())
+    }
+}
+
+// What a texture sampler differs in apart from the TextureQuality
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerKey {
+    pub address_mode: vk::SamplerAddressMode,
+    pub mip_levels: u32,
+}
+
+// Texture samplers keyed by SamplerKey, all created with the current TextureQuality. Changing the
+// quality destroys every sampler, the users fetch new ones and rewrite their descriptors.
+pub struct SamplerCache {
+    device: ash::Device,
+    quality: TextureQuality,
+    // 1 when the device does not filter anisotropically
+    max_supported_anisotropy: f32,
+    max_supported_lod_bias: f32,
+    samplers: HashMap<SamplerKey, vk::Sampler>,
+}
+
+impl SamplerCache {
+    pub fn new(
+        device: ash::Device,
+        features: &vk::PhysicalDeviceFeatures,
+        limits: &vk::PhysicalDeviceLimits,
+        quality: TextureQuality,
+    ) -> SamplerCache {
+        let mut cache = SamplerCache {
+            device,
+            quality,
+            max_supported_anisotropy: if features.sampler_anisotropy == vk::TRUE {
+                limits.max_sampler_anisotropy
+            } else {
+                1.0
+            },
+            max_supported_lod_bias: limits.max_sampler_lod_bias,
+            samplers: HashMap::new(),
+        };
+        cache.quality = cache.clamp(quality);
+        cache
+    }
+
+    // As the samplers are created, after clamping to the device limits
+    pub fn quality(&self) -> TextureQuality {
+        self.quality
+    }
+
+    pub fn get(&mut self, key: SamplerKey) -> vk::Sampler {
+        let device = &self.device;
+        let quality = self.quality;
+        *self.samplers.entry(key).or_insert_with(|| {
+            let sampler_create_info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(if quality.trilinear {
+                    vk::SamplerMipmapMode::LINEAR
+                } else {
+                    vk::SamplerMipmapMode::NEAREST
+                })
+                .address_mode_u(key.address_mode)
+                .address_mode_v(key.address_mode)
+                .address_mode_w(key.address_mode)
+                .anisotropy_enable(quality.max_anisotropy > 1.0)
+                .max_anisotropy(quality.max_anisotropy)
+                .mip_lod_bias(quality.lod_bias)
+                .min_lod(0.0)
+                .max_lod(key.mip_levels as f32)
+                .border_color(vk::BorderColor::INT_OPAQUE_BLACK);
+            unsafe {
+                device
+                    .create_sampler(&sampler_create_info, None)
+                    .expect("Failed to create texture sampler.")
+            }
+        })
+    }
+
+    // Destroys every sampler when the clamped quality differs, which the device must be done
+    // with. Returns whether it did.
+    pub fn set_quality(&mut self, quality: TextureQuality) -> bool {
+        let quality = self.clamp(quality);
+        if quality == self.quality {
+            return false;
+        }
+        self.quality = quality;
+        self.destroy();
+        true
+    }
+
+    pub fn destroy(&mut self) {
+        for (_, sampler) in self.samplers.drain() {
+            unsafe {
+                self.device.destroy_sampler(sampler, None);
+            }
+        }
+    }
+
+    fn clamp(&self, quality: TextureQuality) -> TextureQuality {
+        let clamped = TextureQuality {
+            max_anisotropy: quality
+                .max_anisotropy
+                .clamp(1.0, self.max_supported_anisotropy),
+            lod_bias: quality
+                .lod_bias
+                .clamp(-self.max_supported_lod_bias, self.max_supported_lod_bias),
+            trilinear: quality.trilinear,
+        };
+        if clamped != quality {
+            tracing::warn!(
+                "Texture quality {:?} clamped to the device limits: {:?}",
+                quality,
+                clamped
+            );
+        }
+        clamped
+    }
+}
@@ -0,0 +1,258 @@
+use std::path::{Path, PathBuf};
+
+use cgmath::{InnerSpace, Point3};
+
+use super::{
+    bounds::Aabb, constants::SCENE_REPORT_HUGE_MESH_TRIANGLES, material::TexturePriority,
+    texture_budget::BudgetTexture,
+};
+
+// Empty to busiest, a heatmap cell is shaded by its share of the busiest one
+const HEATMAP_SHADES: &[u8] = b" .:-=+*#%@";
+
+// One model of an OBJ file as loaded, before load_model merges and optimizes them
+#[derive(Clone, Debug)]
+pub struct MeshStats {
+    pub name: String,
+    pub vertex_count: u32,
+    pub triangle_count: u32,
+    // Triangles with a repeated index, or whose edges are parallel within float precision
+    pub degenerate_count: u32,
+    pub bounds: Aabb,
+    // load_model panics on meshes without them
+    pub has_tex_coords: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct TextureStats {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    // RGBA8 with the full mip chain, the way create_texture_image uploads textures
+    pub size: u64,
+}
+
+// Triangle centroids counted in a grid over the x and z extent of the scene, seen from above
+#[derive(Clone, Debug)]
+pub struct TriangleHeatmap {
+    pub columns: u32,
+    pub rows: u32,
+    pub counts: Vec<u32>,
+}
+
+impl TriangleHeatmap {
+    fn new(columns: u32, rows: u32) -> TriangleHeatmap {
+        TriangleHeatmap {
+            columns,
+            rows,
+            counts: vec![0; (columns * rows) as usize],
+        }
+    }
+
+    fn add(&mut self, bounds: &Aabb, point: Point3<f32>) {
+        let cell = |value: f32, min: f32, max: f32, cells: u32| {
+            let extent = (max - min).max(f32::EPSILON);
+            (((value - min) / extent * cells as f32) as u32).min(cells - 1)
+        };
+        let column = cell(point.x, bounds.min.x, bounds.max.x, self.columns);
+        let row = cell(point.z, bounds.min.z, bounds.max.z, self.rows);
+        self.counts[(row * self.columns + column) as usize] += 1;
+    }
+
+    // One line per row, every cell shaded by its count relative to the busiest one
+    pub fn lines(&self) -> Vec<String> {
+        let max = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        self.counts
+            .chunks_exact(self.columns as usize)
+            .map(|row| {
+                row.iter()
+                    .map(|&count| {
+                        let shade = if count == 0 {
+                            0
+                        } else {
+                            1 + (count as u64 * (HEATMAP_SHADES.len() - 2) as u64 / max as u64)
+                                as usize
+                        };
+                        HEATMAP_SHADES[shade] as char
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SceneReport {
+    pub meshes: Vec<MeshStats>,
+    pub textures: Vec<TextureStats>,
+    pub heatmap: TriangleHeatmap,
+    pub warnings: Vec<String>,
+}
+
+impl SceneReport {
+    pub fn triangle_count(&self) -> u64 {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.triangle_count as u64)
+            .sum()
+    }
+
+    pub fn vertex_count(&self) -> u64 {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.vertex_count as u64)
+            .sum()
+    }
+
+    pub fn texture_memory(&self) -> u64 {
+        self.textures.iter().map(|texture| texture.size).sum()
+    }
+}
+
+// Loads the OBJ without optimizing it and measures every model in it. `model_texture` is the
+// texture the renderer samples whatever the materials say, the textures the materials reference
+// are counted after it. Only a missing or unreadable OBJ is an error, everything else that is
+// off ends up in the warnings.
+pub fn analyze_scene(
+    model_path: &Path,
+    model_texture: &Path,
+    heatmap_size: (u32, u32),
+) -> Result<SceneReport, String> {
+    // The same as load_model
+    let load_options = tobj::LoadOptions {
+        single_index: true,
+        ..Default::default()
+    };
+    let (models, materials) = tobj::load_obj(model_path, &load_options)
+        .map_err(|err| format!("Failed to load {:?}: {}", model_path, err))?;
+    let mut warnings = Vec::new();
+
+    let point = |positions: &[f32], index: u32| {
+        let index = index as usize * 3;
+        Point3::new(positions[index], positions[index + 1], positions[index + 2])
+    };
+    let mut meshes = Vec::with_capacity(models.len());
+    for model in &models {
+        let mesh = &model.mesh;
+        let mut degenerate_count = 0;
+        for corners in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|corner| point(&mesh.positions, corners[corner]));
+            let (e1, e2) = (b - a, c - a);
+            let repeated =
+                corners[0] == corners[1] || corners[1] == corners[2] || corners[0] == corners[2];
+            if repeated
+                || e1.cross(e2).magnitude() <= f32::EPSILON * e1.magnitude() * e2.magnitude()
+            {
+                degenerate_count += 1;
+            }
+        }
+        let stats = MeshStats {
+            name: model.name.clone(),
+            vertex_count: (mesh.positions.len() / 3) as u32,
+            triangle_count: (mesh.indices.len() / 3) as u32,
+            degenerate_count,
+            bounds: Aabb::from_points(
+                mesh.positions
+                    .chunks_exact(3)
+                    .map(|position| Point3::new(position[0], position[1], position[2])),
+            ),
+            has_tex_coords: !mesh.texcoords.is_empty(),
+        };
+        if stats.degenerate_count > 0 {
+            warnings.push(format!(
+                "Mesh {:?} has {} degenerate triangles of {}",
+                stats.name, stats.degenerate_count, stats.triangle_count
+            ));
+        }
+        if stats.triangle_count > SCENE_REPORT_HUGE_MESH_TRIANGLES {
+            warnings.push(format!(
+                "Mesh {:?} has {} triangles, over {}",
+                stats.name, stats.triangle_count, SCENE_REPORT_HUGE_MESH_TRIANGLES
+            ));
+        }
+        if !stats.has_tex_coords {
+            warnings.push(format!(
+                "Mesh {:?} has no texture coordinates, load_model refuses it",
+                stats.name
+            ));
+        }
+        meshes.push(stats);
+    }
+
+    let scene_bounds = meshes
+        .iter()
+        .fold(Aabb::empty(), |bounds, mesh| bounds.union(&mesh.bounds));
+    let mut heatmap = TriangleHeatmap::new(heatmap_size.0.max(1), heatmap_size.1.max(1));
+    for model in &models {
+        let positions = &model.mesh.positions;
+        for corners in model.mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|corner| point(positions, corners[corner]));
+            heatmap.add(
+                &scene_bounds,
+                Point3::new(
+                    (a.x + b.x + c.x) / 3.0,
+                    (a.y + b.y + c.y) / 3.0,
+                    (a.z + b.z + c.z) / 3.0,
+                ),
+            );
+        }
+    }
+
+    let mut texture_paths = vec![model_texture.to_path_buf()];
+    match materials {
+        Ok(materials) => {
+            let directory = model_path.parent().unwrap_or(Path::new(""));
+            for material in &materials {
+                for name in [
+                    &material.diffuse_texture,
+                    &material.normal_texture,
+                    &material.specular_texture,
+                    &material.ambient_texture,
+                    &material.shininess_texture,
+                    &material.dissolve_texture,
+                ] {
+                    let path = directory.join(name);
+                    if !name.is_empty() && !texture_paths.contains(&path) {
+                        texture_paths.push(path);
+                    }
+                }
+            }
+        }
+        Err(err) => warnings.push(format!(
+            "Failed to load the materials of {:?}: {}",
+            model_path, err
+        )),
+    }
+
+    let mut textures = Vec::with_capacity(texture_paths.len());
+    for path in texture_paths {
+        // Only the header is read
+        match image::image_dimensions(&path) {
+            Ok((width, height)) => {
+                let mip_levels = 32 - width.max(height).max(1).leading_zeros();
+                let size = BudgetTexture {
+                    width,
+                    height,
+                    bytes_per_texel: 4,
+                    mip_levels,
+                    priority: TexturePriority::Normal,
+                }
+                .size(0);
+                textures.push(TextureStats {
+                    path,
+                    width,
+                    height,
+                    size,
+                });
+            }
+            Err(err) => warnings.push(format!("Failed to read texture {:?}: {}", path, err)),
+        }
+    }
+
+    Ok(SceneReport {
+        meshes,
+        textures,
+        heatmap,
+        warnings,
+    })
+}
@@ -0,0 +1,149 @@
+use ash::util::read_spv;
+use ash::vk;
+use ash::vk::Handle;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+// Where the shaders are loaded from unless the build compiled them
+#[cfg(feature = "shader-compile")]
+const CHECKED_IN_SHADER_DIR: &str = "shaders/compiled";
+
+// A shader under shaders/compiled as the build script compiled it with the `shader-compile`
+// feature, or the checked-in one when it was not compiled, like the HLSL stages without DXC
+pub fn compiled_shader_path(path: &Path) -> PathBuf {
+    #[cfg(feature = "shader-compile")]
+    if let Ok(name) = path.strip_prefix(CHECKED_IN_SHADER_DIR) {
+        let compiled = Path::new(env!("ASH_RT_COMPILED_SHADER_DIR")).join(name);
+        if compiled.exists() {
+            return compiled;
+        }
+    }
+    path.to_path_buf()
+}
+
+struct CachedModule {
+    module: vk::ShaderModule,
+    code: Vec<u32>,
+    references: u32,
+}
+
+// Shader modules keyed by a hash of their SPIR-V, so rebuilt pipelines and pipelines loading the
+// same file share one module. Files are still read on every load, an edited shader hashes
+// differently and gets a new module. Unreferenced modules stay cached until trim(), which lets a
+// pass be released and recreated without compiling its shaders again.
+pub struct ShaderModuleCache {
+    device: ash::Device,
+    modules: HashMap<u64, CachedModule>,
+    // Module keys whose references belong to a pipeline, released by destroy_pipeline
+    pipelines: HashMap<u64, Vec<u64>>,
+}
+
+impl ShaderModuleCache {
+    pub fn new(device: ash::Device) -> ShaderModuleCache {
+        ShaderModuleCache {
+            device,
+            modules: HashMap::new(),
+            pipelines: HashMap::new(),
+        }
+    }
+
+    // Adds a reference to the module, which release() or destroy_pipeline() drops again
+    pub fn load(&mut self, path: &Path) -> vk::ShaderModule {
+        let path = &compiled_shader_path(path);
+        let mut file =
+            File::open(path).unwrap_or_else(|_| panic!("Failed to open shader file: {:?}", path));
+        let code = read_spv(&mut file)
+            .unwrap_or_else(|_| panic!("Failed to load shader file: {:?}", path));
+
+        let mut hasher = DefaultHasher::new();
+        code.hash(&mut hasher);
+        let key = hasher.finish();
+
+        let device = &self.device;
+        let cached = self.modules.entry(key).or_insert_with(|| {
+            let shader_info = vk::ShaderModuleCreateInfo::builder().code(&code);
+            let module = unsafe {
+                device
+                    .create_shader_module(&shader_info, None)
+                    .unwrap_or_else(|_| panic!("Failed to create shader module: {:?}", path))
+            };
+            tracing::debug!("Created shader module for {:?}", path);
+
+            CachedModule {
+                module,
+                code,
+                references: 0,
+            }
+        });
+        cached.references += 1;
+        cached.module
+    }
+
+    pub fn code(&self, module: vk::ShaderModule) -> &[u32] {
+        &self.modules[&self.key(module)].code
+    }
+
+    pub fn release(&mut self, module: vk::ShaderModule) {
+        let key = self.key(module);
+        let cached = self.modules.get_mut(&key).unwrap();
+        assert!(cached.references > 0, "Shader module released too often.");
+        cached.references -= 1;
+    }
+
+    // Hands the references taken for `modules` over to the pipeline created from them
+    pub fn attach(&mut self, pipeline: vk::Pipeline, modules: &[vk::ShaderModule]) {
+        let keys = modules.iter().map(|&module| self.key(module)).collect();
+        self.pipelines.insert(pipeline.as_raw(), keys);
+    }
+
+    // Destroys the pipeline and releases the modules attached to it
+    pub fn destroy_pipeline(&mut self, pipeline: vk::Pipeline) {
+        unsafe {
+            self.device.destroy_pipeline(pipeline, None);
+        }
+
+        for key in self
+            .pipelines
+            .remove(&pipeline.as_raw())
+            .unwrap_or_default()
+        {
+            self.modules.get_mut(&key).unwrap().references -= 1;
+        }
+    }
+
+    // Destroys the modules nothing refers to anymore
+    pub fn trim(&mut self) {
+        let device = &self.device;
+        self.modules.retain(|_, cached| {
+            if cached.references == 0 {
+                unsafe {
+                    device.destroy_shader_module(cached.module, None);
+                }
+            }
+            cached.references > 0
+        });
+    }
+
+    // Every module, referenced or not, for when the device is destroyed
+    pub fn destroy(&mut self) {
+        for (_, cached) in self.modules.drain() {
+            unsafe {
+                self.device.destroy_shader_module(cached.module, None);
+            }
+        }
+        self.pipelines.clear();
+    }
+
+    fn key(&self, module: vk::ShaderModule) -> u64 {
+        *self
+            .modules
+            .iter()
+            .find(|(_, cached)| cached.module == module)
+            .expect("Shader module is not cached.")
+            .0
+    }
+}
@@ -0,0 +1,341 @@
+use cgmath::{Deg, Matrix4, SquareMatrix, Vector3, Zero};
+
+// Up to four joints a vertex follows, with weights summing to 1. Unused slots have weight 0.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SkinInfluence {
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+impl Default for SkinInfluence {
+    // Follows joint 0 alone
+    fn default() -> Self {
+        SkinInfluence {
+            joints: [0; 4],
+            weights: [1.0, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+// Offsets from the rest pose, one per vertex
+#[derive(Clone, Debug, Default)]
+pub struct MorphTarget {
+    pub positions: Vec<[f32; 3]>,
+    // Empty for a target that leaves the normals to the joints
+    pub normals: Vec<[f32; 3]>,
+}
+
+// One vertex of one morph target as the skinning shader reads it, padded to vec4
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MorphDelta {
+    pub position: [f32; 4],
+    pub normal: [f32; 4],
+}
+
+// Joints and morph targets deforming the scene mesh, per vertex in the order of its positions
+#[derive(Clone, Debug, Default)]
+pub struct Skin {
+    pub joint_count: u32,
+    // Empty for a mesh with morph targets only, which then follows joint 0
+    pub influences: Vec<SkinInfluence>,
+    pub morph_targets: Vec<MorphTarget>,
+}
+
+// Where the joints are and how much of each morph target is applied. The joint matrices take
+// the morphed rest positions to the mesh's object space, so they include the inverse bind
+// matrices.
+#[derive(Clone, Debug, Default)]
+pub struct SkinPose {
+    pub joint_matrices: Vec<Matrix4<f32>>,
+    pub morph_weights: Vec<f32>,
+}
+
+impl Skin {
+    // Why the skin cannot deform a mesh of `vertex_count` vertices. The skinning shader indexes
+    // the joints without bounds checks.
+    pub fn validate(&self, vertex_count: usize) -> Result<(), String> {
+        if !self.influences.is_empty() && self.influences.len() != vertex_count {
+            return Err(format!(
+                "{} skin influences for {} vertices",
+                self.influences.len(),
+                vertex_count
+            ));
+        }
+        let joint_count = self.joint_count.max(1);
+        if let Some((vertex, _)) = self
+            .influences
+            .iter()
+            .enumerate()
+            .find(|(_, influence)| influence.joints.iter().any(|&joint| joint >= joint_count))
+        {
+            return Err(format!(
+                "Vertex {} follows a joint past the {} of the skin",
+                vertex, joint_count
+            ));
+        }
+        for (index, target) in self.morph_targets.iter().enumerate() {
+            if target.positions.len() != vertex_count {
+                return Err(format!(
+                    "Morph target {} has {} position offsets for {} vertices",
+                    index,
+                    target.positions.len(),
+                    vertex_count
+                ));
+            }
+            if !target.normals.is_empty() && target.normals.len() != vertex_count {
+                return Err(format!(
+                    "Morph target {} has {} normal offsets for {} vertices",
+                    index,
+                    target.normals.len(),
+                    vertex_count
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate_pose(&self, pose: &SkinPose) -> Result<(), String> {
+        if pose.joint_matrices.len() != self.joint_count.max(1) as usize {
+            return Err(format!(
+                "{} joint matrices for {} joints",
+                pose.joint_matrices.len(),
+                self.joint_count.max(1)
+            ));
+        }
+        if pose.morph_weights.len() != self.morph_targets.len() {
+            return Err(format!(
+                "{} morph weights for {} morph targets",
+                pose.morph_weights.len(),
+                self.morph_targets.len()
+            ));
+        }
+        Ok(())
+    }
+
+    // Leaves the mesh at its rest positions
+    pub fn rest_pose(&self) -> SkinPose {
+        SkinPose {
+            joint_matrices: vec![Matrix4::identity(); self.joint_count.max(1) as usize],
+            morph_weights: vec![0.0; self.morph_targets.len()],
+        }
+    }
+
+    // Influences of every vertex, with the default one for a skin without joints
+    pub fn vertex_influences(&self, vertex_count: usize) -> Vec<SkinInfluence> {
+        if self.influences.is_empty() {
+            vec![SkinInfluence::default(); vertex_count]
+        } else {
+            self.influences.clone()
+        }
+    }
+
+    // The positions the skinning shader writes for `pose`, on the host for the bounds of the
+    // posed mesh
+    pub fn deform(&self, rest_positions: &[[f32; 3]], pose: &SkinPose) -> Vec<[f32; 3]> {
+        let influences = self.vertex_influences(rest_positions.len());
+        rest_positions
+            .iter()
+            .zip(&influences)
+            .enumerate()
+            .map(|(vertex, (rest_position, influence))| {
+                let mut position = Vector3::from(*rest_position);
+                for (target, &weight) in self.morph_targets.iter().zip(&pose.morph_weights) {
+                    position += Vector3::from(target.positions[vertex]) * weight;
+                }
+                let skinning = influence
+                    .joints
+                    .iter()
+                    .zip(influence.weights)
+                    .fold(Matrix4::zero(), |sum, (&joint, weight)| {
+                        sum + pose.joint_matrices[joint as usize] * weight
+                    });
+                (skinning * position.extend(1.0)).truncate().into()
+            })
+            .collect()
+    }
+
+    // All targets in one array, the offsets of vertex v in target t at t * vertex_count + v.
+    // Never empty, as storage buffers cannot be.
+    pub fn packed_morph_deltas(&self) -> Vec<MorphDelta> {
+        let pad = |offset: [f32; 3]| [offset[0], offset[1], offset[2], 0.0];
+        let deltas: Vec<MorphDelta> = self
+            .morph_targets
+            .iter()
+            .flat_map(|target| {
+                target
+                    .positions
+                    .iter()
+                    .enumerate()
+                    .map(|(vertex, &position)| MorphDelta {
+                        position: pad(position),
+                        normal: target.normals.get(vertex).copied().map_or([0.0; 4], pad),
+                    })
+            })
+            .collect();
+        if deltas.is_empty() {
+            vec![MorphDelta::default()]
+        } else {
+            deltas
+        }
+    }
+
+    // The skin of `ash_rt skin`: vertices follow a joint at the origin by their height above the
+    // lowest one, and a morph target bulges the top out of the z = 0 plane
+    pub fn sway(rest_positions: &[[f32; 3]]) -> Skin {
+        let (low, high) = rest_positions
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(low, high), position| {
+                (low.min(position[1]), high.max(position[1]))
+            });
+        let height = (high - low).max(f32::EPSILON);
+        let follow = |position: &[f32; 3]| (position[1] - low) / height;
+        Skin {
+            joint_count: 2,
+            influences: rest_positions
+                .iter()
+                .map(|position| SkinInfluence {
+                    joints: [0, 1, 0, 0],
+                    weights: [1.0 - follow(position), follow(position), 0.0, 0.0],
+                })
+                .collect(),
+            morph_targets: vec![MorphTarget {
+                positions: rest_positions
+                    .iter()
+                    .map(|position| [0.0, 0.0, 0.5 * height * follow(position)])
+                    .collect(),
+                normals: rest_positions
+                    .iter()
+                    .map(|position| [0.0, -follow(position), 0.0])
+                    .collect(),
+            }],
+        }
+    }
+}
+
+// A pose of Skin::sway at `phase` in [0, 1) of the cycle
+pub fn sway_pose(phase: f32) -> SkinPose {
+    let swing = (phase * std::f32::consts::TAU).sin();
+    SkinPose {
+        joint_matrices: vec![
+            Matrix4::identity(),
+            Matrix4::from_angle_z(Deg(30.0 * swing)),
+        ],
+        morph_weights: vec![swing.abs()],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skin() -> Skin {
+        Skin {
+            joint_count: 2,
+            influences: vec![
+                SkinInfluence::default(),
+                SkinInfluence {
+                    joints: [0, 1, 0, 0],
+                    weights: [0.5, 0.5, 0.0, 0.0],
+                },
+            ],
+            morph_targets: vec![MorphTarget {
+                positions: vec![[0.0, 1.0, 0.0], [1.0, 0.0, 0.0]],
+                normals: Vec::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn validates_against_the_mesh() {
+        let mut skin = skin();
+        assert!(skin.validate(2).is_ok());
+        assert!(skin.validate(3).is_err());
+
+        skin.morph_targets[0].normals = vec![[0.0; 3]];
+        assert!(skin.validate(2).is_err());
+        skin.morph_targets[0].normals.clear();
+
+        skin.influences[1].joints[1] = 2;
+        assert!(skin.validate(2).is_err());
+
+        let morph_only = Skin {
+            morph_targets: vec![MorphTarget {
+                positions: vec![[0.0; 3]; 3],
+                normals: vec![[0.0; 3]; 3],
+            }],
+            ..Default::default()
+        };
+        assert!(morph_only.validate(3).is_ok());
+        assert_eq!(
+            morph_only.vertex_influences(3),
+            vec![SkinInfluence::default(); 3]
+        );
+    }
+
+    #[test]
+    fn rest_pose_fits_the_skin() {
+        let skin = skin();
+        let pose = skin.rest_pose();
+        assert!(skin.validate_pose(&pose).is_ok());
+        assert!(pose.morph_weights.iter().all(|&weight| weight == 0.0));
+        assert!(skin.validate_pose(&SkinPose::default()).is_err());
+    }
+
+    #[test]
+    fn deforms_with_morph_targets_then_joints() {
+        let skin = skin();
+        let rest_positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]];
+        assert_eq!(
+            skin.deform(&rest_positions, &skin.rest_pose()),
+            rest_positions
+        );
+
+        let pose = SkinPose {
+            joint_matrices: vec![
+                Matrix4::identity(),
+                Matrix4::from_translation(Vector3::new(0.0, 2.0, 0.0)),
+            ],
+            morph_weights: vec![0.5],
+        };
+        assert_eq!(
+            skin.deform(&rest_positions, &pose),
+            [[0.0, 0.5, 0.0], [1.5, 1.0, 0.0]]
+        );
+    }
+
+    #[test]
+    fn morph_deltas_are_target_major() {
+        let mut skin = skin();
+        skin.morph_targets.push(MorphTarget {
+            positions: vec![[2.0, 0.0, 0.0], [0.0, 0.0, 3.0]],
+            normals: vec![[0.0, 1.0, 0.0], [1.0, 0.0, 0.0]],
+        });
+        let deltas = skin.packed_morph_deltas();
+        assert_eq!(deltas.len(), 4);
+        assert_eq!(deltas[1].position, [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(deltas[1].normal, [0.0; 4]);
+        assert_eq!(deltas[3].position, [0.0, 0.0, 3.0, 0.0]);
+        assert_eq!(deltas[3].normal, [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(
+            Skin::default().packed_morph_deltas(),
+            vec![MorphDelta::default()]
+        );
+    }
+
+    #[test]
+    fn sway_fits_its_mesh_and_poses() {
+        let positions = [[-0.5, -0.5, 0.0], [0.0, 0.5, 0.0], [0.5, -0.5, 0.0]];
+        let skin = Skin::sway(&positions);
+        assert!(skin.validate(positions.len()).is_ok());
+        for phase in [0.0, 0.25, 0.5] {
+            assert!(skin.validate_pose(&sway_pose(phase)).is_ok());
+        }
+        // The base stays put whatever the pose
+        let posed = skin.deform(&positions, &sway_pose(0.25));
+        assert_eq!(posed[0], positions[0]);
+        assert_eq!(posed[2], positions[2]);
+        assert_ne!(posed[1], positions[1]);
+    }
+}
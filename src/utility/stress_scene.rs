@@ -0,0 +1,265 @@
+use crate::utility::structures::GeometryPoolStats;
+
+use ash::vk;
+use cgmath::{InnerSpace, Matrix4, Rad, Vector3};
+
+use std::fmt;
+use std::time::Duration;
+
+// Largest triangle soup a geometry can hold with 16 bit indices
+const MAX_GEOMETRY_TRIANGLES: u32 = (u16::MAX as u32 + 1) / 3;
+
+// What `ash_rt stress` generates
+#[derive(Clone, Copy, Debug)]
+pub struct StressParams {
+    pub instance_count: u32,
+    pub mesh_count: u32,
+    // Split evenly over the geometries of a mesh
+    pub triangles_per_mesh: u32,
+    pub geometries_per_mesh: u32,
+    pub seed: u64,
+}
+
+impl StressParams {
+    pub fn triangles_per_geometry(&self) -> u32 {
+        (self.triangles_per_mesh / self.geometries_per_mesh.max(1)).clamp(1, MAX_GEOMETRY_TRIANGLES)
+    }
+
+    // One hit record per geometry of every mesh
+    pub fn hit_record_count(&self) -> u64 {
+        self.mesh_count as u64 * self.geometries_per_mesh as u64
+    }
+
+    // A vertex and an index buffer per geometry, as bindless hit shading would bind them
+    pub fn storage_buffer_count(&self) -> u64 {
+        2 * self.hit_record_count()
+    }
+}
+
+// xorshift64*, the stress scenes only have to be reproducible
+pub struct StressRng(u64);
+
+impl StressRng {
+    pub fn new(seed: u64) -> StressRng {
+        // The state must never be zero
+        StressRng(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Uniform in [min, max)
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        let unit = (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+        min + (max - min) * unit
+    }
+
+    fn point(&mut self, min: f32, max: f32) -> [f32; 3] {
+        [
+            self.range(min, max),
+            self.range(min, max),
+            self.range(min, max),
+        ]
+    }
+}
+
+// Triangle soup of one BLAS geometry
+pub struct StressGeometry {
+    pub positions: Vec<[f32; 3]>,
+    pub indices: Vec<u16>,
+}
+
+// Small random triangles inside the unit cube around the origin
+pub fn random_mesh(rng: &mut StressRng, params: &StressParams) -> Vec<StressGeometry> {
+    let triangle_count = params.triangles_per_geometry();
+    (0..params.geometries_per_mesh.max(1))
+        .map(|_| {
+            let positions: Vec<[f32; 3]> = (0..triangle_count)
+                .flat_map(|_| {
+                    let center = rng.point(-0.4, 0.4);
+                    let corners: Vec<[f32; 3]> = (0..3)
+                        .map(|_| {
+                            let offset = rng.point(-0.1, 0.1);
+                            [
+                                center[0] + offset[0],
+                                center[1] + offset[1],
+                                center[2] + offset[2],
+                            ]
+                        })
+                        .collect();
+                    corners
+                })
+                .collect();
+            let indices = (0..positions.len() as u16).collect();
+            StressGeometry { positions, indices }
+        })
+        .collect()
+}
+
+// Randomly rotated and scaled, anywhere inside a cube of the given edge length
+pub fn random_instance_transform(rng: &mut StressRng, scene_size: f32) -> Matrix4<f32> {
+    let half_size = 0.5 * scene_size;
+    let translation = Vector3::from(rng.point(-half_size, half_size));
+    let axis = Vector3::from(rng.point(-1.0, 1.0));
+    let axis = if axis.magnitude2() > 1e-6 {
+        axis.normalize()
+    } else {
+        Vector3::unit_y()
+    };
+    Matrix4::from_translation(translation)
+        * Matrix4::from_axis_angle(axis, Rad(rng.range(0.0, std::f32::consts::TAU)))
+        * Matrix4::from_scale(rng.range(0.5, 2.0))
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StressLimit {
+    pub name: &'static str,
+    pub required: u64,
+    pub limit: u64,
+}
+
+impl StressLimit {
+    pub fn is_exceeded(&self) -> bool {
+        self.required > self.limit
+    }
+}
+
+// What the stress scene cost to build
+#[derive(Clone, Debug)]
+pub struct StressCosts {
+    pub blas_size: vk::DeviceSize,
+    pub blas_count: usize,
+    pub tlas_size: vk::DeviceSize,
+    pub scratch_peak: vk::DeviceSize,
+    pub build_time: Duration,
+    pub build_frames: u32,
+    pub longest_build_frame: Duration,
+    pub sbt_size: vk::DeviceSize,
+    pub descriptor_count: u32,
+    pub geometry_pool: GeometryPoolStats,
+}
+
+// What `ash_rt stress` reports, costs is None when the scene exceeds the device limits and was
+// not built
+#[derive(Clone, Debug)]
+pub struct StressReport {
+    pub params: StressParams,
+    pub limits: Vec<StressLimit>,
+    pub costs: Option<StressCosts>,
+}
+
+impl fmt::Display for StressReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Stress test: {} instances of {} meshes with {} geometries of {} triangles",
+            self.params.instance_count,
+            self.params.mesh_count,
+            self.params.geometries_per_mesh,
+            self.params.triangles_per_geometry()
+        )?;
+        for limit in &self.limits {
+            writeln!(
+                f,
+                " {}: {} / {}{}",
+                limit.name,
+                limit.required,
+                limit.limit,
+                if limit.is_exceeded() { " EXCEEDED" } else { "" }
+            )?;
+        }
+        let costs = match &self.costs {
+            Some(costs) => costs,
+            None => return writeln!(f, "The stress scene exceeds the device limits, not built"),
+        };
+        writeln!(f, "Stress scene:")?;
+        writeln!(
+            f,
+            " BLAS size: {} bytes in {} BLASes",
+            costs.blas_size, costs.blas_count
+        )?;
+        writeln!(f, " TLAS size: {} bytes", costs.tlas_size)?;
+        writeln!(f, " scratch peak: {} bytes", costs.scratch_peak)?;
+        writeln!(f, " AS build time: {:?}", costs.build_time)?;
+        writeln!(
+            f,
+            " AS build frames: {}, the longest took {:?}",
+            costs.build_frames, costs.longest_build_frame
+        )?;
+        writeln!(
+            f,
+            " SBT: {} hit records, {} bytes",
+            self.params.hit_record_count(),
+            costs.sbt_size
+        )?;
+        writeln!(
+            f,
+            " descriptor set: {} storage buffers",
+            costs.descriptor_count
+        )?;
+        writeln!(
+            f,
+            " geometry pool: {} blocks, {} bytes used",
+            costs.geometry_pool.block_count, costs.geometry_pool.used
+        )
+    }
+}
+
+// Every device limit the stress scene counts against. Triangles are counted per instance, the
+// way SceneStats counts them.
+pub fn stress_limits(
+    params: &StressParams,
+    properties: &vk::PhysicalDeviceRayTracingPropertiesNV,
+    limits: &vk::PhysicalDeviceLimits,
+) -> Vec<StressLimit> {
+    let triangles_per_mesh =
+        params.triangles_per_geometry() as u64 * params.geometries_per_mesh as u64;
+    vec![
+        StressLimit {
+            name: "max_instance_count",
+            required: params.instance_count as u64,
+            limit: properties.max_instance_count,
+        },
+        StressLimit {
+            name: "max_geometry_count",
+            required: params.geometries_per_mesh as u64,
+            limit: properties.max_geometry_count,
+        },
+        StressLimit {
+            name: "max_triangle_count",
+            required: triangles_per_mesh * params.instance_count as u64,
+            limit: properties.max_triangle_count,
+        },
+        // Instance custom indices and SBT record offsets are 24 bit fields
+        StressLimit {
+            name: "instance custom index range",
+            required: params.instance_count as u64,
+            limit: 1 << 24,
+        },
+        StressLimit {
+            name: "instance SBT record offset range",
+            required: params.hit_record_count(),
+            limit: 1 << 24,
+        },
+        StressLimit {
+            name: "max_per_stage_descriptor_storage_buffers",
+            required: params.storage_buffer_count(),
+            limit: limits.max_per_stage_descriptor_storage_buffers as u64,
+        },
+        StressLimit {
+            name: "max_descriptor_set_storage_buffers",
+            required: params.storage_buffer_count(),
+            limit: limits.max_descriptor_set_storage_buffers as u64,
+        },
+        // Every BLAS and the TLAS are bound to memory of their own
+        StressLimit {
+            name: "max_memory_allocation_count",
+            required: params.mesh_count as u64 + 1,
+            limit: limits.max_memory_allocation_count as u64,
+        },
+    ]
+}
@@ -20,6 +20,7 @@ pub struct SwapChainStuff {
     pub swapchain_images: Vec<vk::Image>,
     pub swapchain_format: vk::Format,
     pub swapchain_extent: vk::Extent2D,
+    pub swapchain_usage: vk::ImageUsageFlags,
 }
 
 pub struct SwapChainSupportDetail {
@@ -106,3 +107,883 @@ pub struct UniformBufferObject {
 pub struct VertexRt {
     pub pos: [f32; 3],
 }
+
+// Row-major 3x4 matrix as TLAS instances store it, the constant bottom row of an affine 4x4
+// matrix is dropped. Convert from Matrix4 rather than writing the rows by hand, cgmath and glam
+// matrices are column-major.
+#[repr(C)]
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct InstanceTransform(pub [f32; 12]);
+
+impl InstanceTransform {
+    // Element-wise blend, exact for the pure translations used for instance motion
+    pub fn lerp(&self, other: &InstanceTransform, time: f32) -> InstanceTransform {
+        let mut transform = [0.0; 12];
+        for (i, value) in transform.iter_mut().enumerate() {
+            *value = self.0[i] + (other.0[i] - self.0[i]) * time;
+        }
+        InstanceTransform(transform)
+    }
+
+    pub fn transform_point(&self, point: [f32; 3]) -> [f32; 3] {
+        let row = |r: usize| {
+            self.0[r * 4] * point[0]
+                + self.0[r * 4 + 1] * point[1]
+                + self.0[r * 4 + 2] * point[2]
+                + self.0[r * 4 + 3]
+        };
+        [row(0), row(1), row(2)]
+    }
+
+    pub fn translation(&self) -> [f32; 3] {
+        [self.0[3], self.0[7], self.0[11]]
+    }
+
+    pub fn with_translation(&self, translation: [f32; 3]) -> InstanceTransform {
+        let mut transform = self.0;
+        transform[3] = translation[0];
+        transform[7] = translation[1];
+        transform[11] = translation[2];
+        InstanceTransform(transform)
+    }
+}
+
+impl From<Matrix4<f32>> for InstanceTransform {
+    fn from(matrix: Matrix4<f32>) -> Self {
+        // cgmath indexes columns first
+        InstanceTransform([
+            matrix.x.x, matrix.y.x, matrix.z.x, matrix.w.x, matrix.x.y, matrix.y.y, matrix.z.y,
+            matrix.w.y, matrix.x.z, matrix.y.z, matrix.z.z, matrix.w.z,
+        ])
+    }
+}
+
+impl From<InstanceTransform> for Matrix4<f32> {
+    fn from(transform: InstanceTransform) -> Self {
+        let t = transform.0;
+        Matrix4::new(
+            t[0], t[4], t[8], 0.0, t[1], t[5], t[9], 0.0, t[2], t[6], t[10], 0.0, t[3], t[7],
+            t[11], 1.0,
+        )
+    }
+}
+
+#[cfg(feature = "glam")]
+impl From<glam::Mat4> for InstanceTransform {
+    fn from(matrix: glam::Mat4) -> Self {
+        // The first three rows of the matrix are the first three columns of its transpose
+        let rows = matrix.transpose().to_cols_array();
+        let mut transform = [0.0; 12];
+        transform.copy_from_slice(&rows[..12]);
+        InstanceTransform(transform)
+    }
+}
+
+#[cfg(feature = "glam")]
+impl From<InstanceTransform> for glam::Mat4 {
+    fn from(transform: InstanceTransform) -> Self {
+        let mut rows = [0.0; 16];
+        rows[..12].copy_from_slice(&transform.0);
+        rows[15] = 1.0;
+        glam::Mat4::from_cols_array(&rows).transpose()
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct InstanceShadingData {
+    // a = checker square size in world units for INSTANCE_MATERIAL_CHECKER
+    pub color: [f32; 4],
+    // rgb = emitted radiance, zero for non-emissive instances, a = one of INSTANCE_MATERIAL_*
+    pub emission: [f32; 4],
+    // x = roughness, y = metallic, z = anisotropy, w = opacity
+    pub surface: [f32; 4],
+    // rgb = mean free path in world units, only the Subsurface hit group reads it, a = opacity
+    // threshold, the opacity from which on the instance is opaque to stochastic transparency
+    pub subsurface: [f32; 4],
+}
+
+// The sun and the sky it lights, see SunController
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Default, PartialEq)]
+pub struct SunParams {
+    // xyz = normalized direction towards the sun, w = scale of SUN_IRRADIANCE, 0 at night
+    pub direction: [f32; 4],
+    // rgb = background of rays that leave the scene, a unused
+    pub sky_color: [f32; 4],
+}
+
+// Participating media of the scene, read by the closest hit and miss shaders
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Default)]
+pub struct FogParams {
+    // rgb = in-scattered radiance the fog fades to, a = density of the uniform fog per world unit
+    pub color: [f32; 4],
+    // xyz = bounds of the density grid, w = scale of the grid densities, 0 without a grid
+    pub grid_min: [f32; 4],
+    // xyz = bounds of the density grid, w = majorant, the largest scaled grid density
+    pub grid_max: [f32; 4],
+    // xyz = voxels, w unused
+    pub grid_size: [u32; 4],
+}
+
+// Start of the GPU debug buffer, DEBUG_BUFFER_CAPACITY DebugRecords follow
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Default)]
+pub struct DebugBufferHeader {
+    // Records the shaders tried to append, including those past the capacity
+    pub count: u32,
+    pub capacity: u32,
+    // Only rays of this pixel append, DEBUG_PIXEL_NONE in both to append nothing
+    pub pixel: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Default)]
+pub struct DebugRecord {
+    pub pixel: [u32; 2],
+    // Picked by the shader to tell its values apart
+    pub tag: u32,
+    pub unused: u32,
+    pub value: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Default)]
+pub struct EmissiveTriangle {
+    // World space vertices, w unused
+    pub v0: [f32; 4],
+    pub v1: [f32; 4],
+    pub v2: [f32; 4],
+    // rgb = emitted radiance, a = triangle area
+    pub emission: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Default)]
+pub struct AliasEntry {
+    pub threshold: f32,
+    pub alias: u32,
+    // Probability of this entry being picked by the whole table
+    pub pdf: f32,
+    pub padding: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+    // Closest-hit shading with a single directional light and shadow rays
+    Standard,
+    // Direct illumination from many point lights through reservoir resampling (ReSTIR DI)
+    RestirDi,
+    // Primary visibility from a raster depth pre-pass, only the shadow rays are traced
+    Hybrid,
+    // The standard shading in a compute shader tracing ray queries against KHR acceleration
+    // structures, for devices without a ray tracing pipeline and for comparing the two
+    RayQuery,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 4] = [
+        RenderMode::Standard,
+        RenderMode::RestirDi,
+        RenderMode::Hybrid,
+        RenderMode::RayQuery,
+    ];
+
+    pub fn from_name(name: &str) -> Option<RenderMode> {
+        RenderMode::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RenderMode::Standard => "standard",
+            RenderMode::RestirDi => "restir_di",
+            RenderMode::Hybrid => "hybrid",
+            RenderMode::RayQuery => "ray_query",
+        }
+    }
+}
+
+// Arbitrary output images written next to the beauty image for compositing, the discriminant is
+// the element of the AOV image array the shaders write
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aov {
+    Albedo,
+    // World space, facing the camera
+    Normal,
+    // Distance along the primary ray
+    Depth,
+    DirectLight,
+    IndirectLight,
+    // Opacity of the shadows on a shadow catcher, 1 on any other geometry
+    ShadowCatcherAlpha,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::DirectLight,
+        Aov::IndirectLight,
+        Aov::ShadowCatcherAlpha,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::DirectLight => "direct",
+            Aov::IndirectLight => "indirect",
+            Aov::ShadowCatcherAlpha => "shadow_catcher_alpha",
+        }
+    }
+
+    // Leading components of the RGBA image that are saved, under these EXR channel names
+    pub fn channel_names(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::ShadowCatcherAlpha => &["A"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    // Left where no primary ray hits anything
+    pub fn background(self) -> [f32; 4] {
+        match self {
+            Aov::Depth => [f32::INFINITY; 4],
+            _ => [0.0; 4],
+        }
+    }
+}
+
+// Plane added under the scene, lying on the lowest point of its bounds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GroundPlane {
+    None,
+    // Squares of `size` world units alternating between `color` and a darker shade of it
+    Checker { color: [f32; 3], size: f32 },
+    // Invisible except for the shadows cast onto it, which darken the background behind it
+    ShadowCatcher,
+}
+
+// Named bits of the 8 bit TLAS instance mask. A ray only hits the instances that share a layer
+// with its cull mask.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderLayer {
+    Scene,
+    Ground,
+    // Seen by the camera but kept out of its shadows and reflections, e.g. a held weapon
+    FirstPerson,
+    Debug,
+}
+
+impl RenderLayer {
+    pub const ALL: [RenderLayer; 4] = [
+        RenderLayer::Scene,
+        RenderLayer::Ground,
+        RenderLayer::FirstPerson,
+        RenderLayer::Debug,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RenderLayer::Scene => "scene",
+            RenderLayer::Ground => "ground",
+            RenderLayer::FirstPerson => "first_person",
+            RenderLayer::Debug => "debug",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<RenderLayer> {
+        RenderLayer::ALL
+            .iter()
+            .copied()
+            .find(|layer| layer.name() == name)
+    }
+
+    pub const fn mask(self) -> u8 {
+        1 << self as u8
+    }
+
+    pub fn mask_of(layers: &[RenderLayer]) -> u8 {
+        layers.iter().fold(0, |mask, layer| mask | layer.mask())
+    }
+}
+
+// Hit groups of the ray tracing pipeline, all with the same closest hit shader specialized for
+// another shading model. The TLAS instance SBT offset selects one, in this order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HitGroup {
+    Lit,
+    // Albedo only, without shadow rays
+    Unlit,
+    Normals,
+    // Lit, with the diffuse light of the sun spread below the surface by the mean free path of
+    // the material
+    Subsurface,
+}
+
+impl HitGroup {
+    pub const ALL: [HitGroup; 4] = [
+        HitGroup::Lit,
+        HitGroup::Unlit,
+        HitGroup::Normals,
+        HitGroup::Subsurface,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HitGroup::Lit => "lit",
+            HitGroup::Unlit => "unlit",
+            HitGroup::Normals => "normals",
+            HitGroup::Subsurface => "subsurface",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HitGroup> {
+        HitGroup::ALL
+            .iter()
+            .copied()
+            .find(|group| group.name() == name)
+    }
+
+    // Instance SBT offset, and the SHADING_MODEL of the closest hit shader
+    pub const fn index(self) -> u32 {
+        self as u32
+    }
+}
+
+// Cull masks of the rays traced for a camera
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CameraLayerMasks {
+    pub primary: u8,
+    // Every ray started from a hit: shadow rays, and reflections once there are any
+    pub secondary: u8,
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct PointLight {
+    pub position: [f32; 4],
+    // rgb = color, a = intensity
+    pub color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Default)]
+pub struct GBufferSample {
+    // w = 1.0 when the primary ray hit geometry
+    pub position: [f32; 4],
+    pub normal: [f32; 4],
+    pub albedo: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Default)]
+pub struct Reservoir {
+    pub light_index: u32,
+    pub sample_count: u32,
+    pub weight_sum: f32,
+    pub contribution_weight: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct RestirPushConstants {
+    pub frame_index: u32,
+    pub light_count: u32,
+    pub initial_candidates: u32,
+    pub max_history: u32,
+    pub spatial_neighbors: u32,
+    pub spatial_radius: f32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct CheckerboardPushConstants {
+    pub width: u32,
+    pub height: u32,
+    // (x + y) % 2 of the pixels traced this frame
+    pub parity: u32,
+    // 1 when the other pixels still hold the previous frame, traced with the other parity
+    pub has_history: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct SkinningPushConstants {
+    pub vertex_count: u32,
+    pub morph_target_count: u32,
+    // Index of the mesh's first vertex word in its geometry pool block
+    pub first_word: u32,
+    // 1 for a Float16 BLAS_VERTEX_FORMAT, 0 for Float32
+    pub half_floats: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct ExposurePushConstants {
+    pub min_log_luminance: f32,
+    pub log_luminance_range: f32,
+    pub delta_time: f32,
+    pub adaptation_speed: f32,
+    pub ev_compensation: f32,
+    pub width: u32,
+    pub height: u32,
+    // ToneCurve::index
+    pub tone_curve: u32,
+    // CubeLut::domain_min and domain_max, w unused
+    pub lut_domain_min: [f32; 4],
+    pub lut_domain_max: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Default)]
+pub struct ExposureState {
+    // 0.0 until the first frame has been metered
+    pub adapted_luminance: f32,
+    pub exposure: f32,
+}
+
+#[derive(Clone, Debug, Copy, Default)]
+pub struct GeometryPoolStats {
+    pub block_count: u32,
+    pub capacity: vk::DeviceSize,
+    // Up to the end of the last allocation in each block, holes included
+    pub used: vk::DeviceSize,
+    pub live: vk::DeviceSize,
+}
+
+impl GeometryPoolStats {
+    // Share of the used space lost to holes left by removed meshes
+    pub fn fragmentation(&self) -> f32 {
+        if self.used == 0 {
+            return 0.0;
+        }
+        (self.used - self.live) as f32 / self.used as f32
+    }
+}
+
+#[derive(Clone, Debug, Copy, Default)]
+pub struct SceneStats {
+    // Counted per instance, the way they count against max_triangle_count
+    pub triangle_count: u64,
+    pub instance_count: u32,
+    pub geometry_count: u32,
+    pub blas_size: vk::DeviceSize,
+    // Of one TLAS, every frame in flight has its own
+    pub tlas_size: vk::DeviceSize,
+    pub scratch_peak: vk::DeviceSize,
+    pub vertex_memory: vk::DeviceSize,
+    pub geometry_pool: GeometryPoolStats,
+    pub texture_memory: vk::DeviceSize,
+    pub build_time: std::time::Duration,
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct CameraPushConstants {
+    // w = 1.0 for orthographic projections, where rays are parallel to forward
+    pub origin: [f32; 4],
+    pub right: [f32; 4],
+    pub up: [f32; 4],
+    pub forward: [f32; 4],
+    // left, right, bottom, top of the view window along right and up
+    pub window: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct AccumulationPushConstants {
+    // 1 / (sample_index + 1)
+    pub weight: f32,
+    pub sample_index: u32,
+}
+
+// Pushed after DepthRangePushConstants, also visible to the closest hit shaders
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct RayMaskPushConstants {
+    pub primary: u32,
+    pub secondary: u32,
+}
+
+// Pushed after RayMaskPushConstants, where the traced region starts in the render target
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct ViewportPushConstants {
+    pub offset: [u32; 2],
+    // Of the whole region, the dispatch covers half its columns while checkerboarding
+    pub size: [u32; 2],
+    // 0 traces every pixel, otherwise 1 + the CheckerboardPushConstants::parity of this frame
+    pub checkerboard: u32,
+}
+
+// Pushed after ViewportPushConstants, how the closest hit shaders start their secondary rays
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct RayBiasPushConstants {
+    pub t_min: f32,
+    pub offset_scale: f32,
+    // RayOffset::index
+    pub offset: u32,
+}
+
+// How secondary rays are lifted off the surface they start from, along its geometric normal and
+// to the side they leave on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RayOffset {
+    // Only the t_min keeps the rays from hitting their own surface
+    None,
+    // By the offset scale in world units
+    Fixed,
+    // By the offset scale times the largest coordinate of the hit position, which follows the
+    // float precision of the position at any scene scale
+    Relative,
+}
+
+impl RayOffset {
+    pub const ALL: [RayOffset; 3] = [RayOffset::None, RayOffset::Fixed, RayOffset::Relative];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RayOffset::None => "none",
+            RayOffset::Fixed => "fixed",
+            RayOffset::Relative => "relative",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<RayOffset> {
+        RayOffset::ALL
+            .iter()
+            .copied()
+            .find(|offset| offset.name() == name)
+    }
+
+    // RAY_OFFSET_* in the closest hit shader
+    pub const fn index(self) -> u32 {
+        self as u32
+    }
+}
+
+// How the exposed HDR color is brought into [0, 1] before the color grading LUT
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneCurve {
+    // Narkowicz's fit of the ACES filmic curve
+    Aces,
+    Reinhard,
+    // Hable's Uncharted 2 curve, with its white point at 11.2
+    Hable,
+    // Only clamped, for LUTs that bring their own curve
+    Linear,
+}
+
+impl ToneCurve {
+    pub const ALL: [ToneCurve; 4] = [
+        ToneCurve::Aces,
+        ToneCurve::Reinhard,
+        ToneCurve::Hable,
+        ToneCurve::Linear,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneCurve::Aces => "aces",
+            ToneCurve::Reinhard => "reinhard",
+            ToneCurve::Hable => "hable",
+            ToneCurve::Linear => "linear",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ToneCurve> {
+        ToneCurve::ALL
+            .iter()
+            .copied()
+            .find(|curve| curve.name() == name)
+    }
+
+    // TONE_CURVE_* in the tonemap shader
+    pub const fn index(self) -> u32 {
+        self as u32
+    }
+}
+
+// Everything the ray query compute shader is given for one viewport, ordered so the uvec2s need
+// no padding in GLSL
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct RayQueryPushConstants {
+    pub camera: CameraPushConstants,
+    pub viewport_offset: [u32; 2],
+    pub viewport_size: [u32; 2],
+    // 1 / (sample_index + 1)
+    pub accumulation_weight: f32,
+    pub sample_index: u32,
+    pub primary_mask: u32,
+    pub secondary_mask: u32,
+}
+
+// std140 uniform block of the shadow map shading pass
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct ShadowMapUniforms {
+    // Light clip space, with [0, 1] depth like the shadow map
+    pub light_from_world: Matrix4<f32>,
+    // 1 / SHADOW_MAP_SIZE, the PCF tap spacing
+    pub texel_size: f32,
+    pub padding: [f32; 3],
+    pub sun: SunParams,
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct DepthRangePushConstants {
+    // Planes the depth pre-pass was rendered with, needed to reconstruct positions from depth
+    pub near: f32,
+    pub far: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct HizBuildPushConstants {
+    pub src_size: [u32; 2],
+    pub dst_size: [u32; 2],
+}
+
+// World space box of one instance, w unused
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Default)]
+pub struct InstanceBounds {
+    pub min: [f32; 4],
+    pub max: [f32; 4],
+}
+
+// Point light of the hybrid and shadow map modes, binned into screen tiles by light_cull.comp
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct ClusteredLight {
+    // w = radius past which the light is cut off
+    pub position: [f32; 4],
+    // rgb = color, a = intensity
+    pub color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct LightCullPushConstants {
+    pub camera: CameraPushConstants,
+    // Planes the depth pre-pass was rendered with
+    pub near: f32,
+    pub far: f32,
+    pub light_count: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct SelectionOutlinePushConstants {
+    pub color: [f32; 4],
+    pub width: u32,
+    pub height: u32,
+    // Instance IDs past the selection bits are never selected
+    pub instance_count: u32,
+    pub radius: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct OcclusionCullPushConstants {
+    pub clip_from_world: Matrix4<f32>,
+    pub pyramid_size: [u32; 2],
+    pub mip_count: u32,
+    pub instance_count: u32,
+    // 0 draws last frame's visible instances, 1 tests against the depth pyramid
+    pub phase: u32,
+    // Draw parameters of the single BLAS mesh
+    pub index_count: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct DebugDrawPushConstants {
+    pub clip_from_world: Matrix4<f32>,
+}
+
+// Knobs that can be changed while running, see RenderSettings::changes_from
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct RenderSettings {
+    pub max_recursion_depth: u32,
+    pub samples_per_pixel: u32,
+    pub denoise: bool,
+    // Fraction of the window resolution that is ray traced
+    pub render_scale: f32,
+    pub exposure_adaptation_speed: f32,
+    pub ev_compensation: f32,
+    pub tone_curve: ToneCurve,
+    // Overlays BLAS bounds, instance boxes, lights and the camera frustum
+    pub debug_draw: bool,
+    // GPU frame rate the render scale and samples per pixel are adjusted to hold, 0 keeps them
+    // as set
+    pub target_fps: f32,
+    // Hybrid mode: shades the pre-pass with shadows from a shadow map instead of shadow rays, to
+    // compare the two
+    pub shadow_map: bool,
+    // Traces half the pixels each frame in a checkerboard and fills in the other half from the
+    // previous frame and the traced neighbors. Standard render mode only.
+    pub checkerboard: bool,
+    // Where secondary rays start along their direction, against shadow acne. In world units.
+    pub ray_t_min: f32,
+    pub ray_offset: RayOffset,
+    // World units or a fraction of the position's magnitude, see RayOffset
+    pub ray_offset_scale: f32,
+}
+
+// What has to be rebuilt for a settings change to take effect, anything else is read per frame
+#[derive(Clone, Debug, Copy, PartialEq, Default)]
+pub struct SettingsChanges {
+    pub pipeline: bool,
+    // Render targets are resized and every descriptor referencing them rewritten
+    pub descriptors: bool,
+}
+
+impl RenderSettings {
+    pub fn ray_bias_constants(&self) -> RayBiasPushConstants {
+        RayBiasPushConstants {
+            t_min: self.ray_t_min,
+            offset_scale: self.ray_offset_scale,
+            offset: self.ray_offset.index(),
+        }
+    }
+
+    pub fn changes_from(&self, previous: &RenderSettings) -> SettingsChanges {
+        SettingsChanges {
+            pipeline: self.max_recursion_depth != previous.max_recursion_depth,
+            descriptors: self.render_scale != previous.render_scale,
+        }
+    }
+
+    // Applies one `name=value` edit, as given to the options and the C ABI
+    pub fn edit(&mut self, edit: &str) -> Result<(), String> {
+        let (name, value) = edit
+            .split_once('=')
+            .ok_or_else(|| format!("Expected name=value, got {:?}", edit))?;
+        let invalid = || format!("Invalid {} value {:?}", name, value);
+        match name {
+            "depth" => self.max_recursion_depth = value.parse().map_err(|_| invalid())?,
+            "spp" => self.samples_per_pixel = value.parse().map_err(|_| invalid())?,
+            "denoise" => self.denoise = value.parse().map_err(|_| invalid())?,
+            "scale" => self.render_scale = value.parse().map_err(|_| invalid())?,
+            "ev" => self.ev_compensation = value.parse().map_err(|_| invalid())?,
+            "debug_draw" => self.debug_draw = value.parse().map_err(|_| invalid())?,
+            "target_fps" => self.target_fps = value.parse().map_err(|_| invalid())?,
+            "shadow_map" => self.shadow_map = value.parse().map_err(|_| invalid())?,
+            "checkerboard" => self.checkerboard = value.parse().map_err(|_| invalid())?,
+            "ray_t_min" => self.ray_t_min = value.parse().map_err(|_| invalid())?,
+            "ray_offset" => self.ray_offset = RayOffset::from_name(value).ok_or_else(invalid)?,
+            "ray_offset_scale" => self.ray_offset_scale = value.parse().map_err(|_| invalid())?,
+            "tone_curve" => self.tone_curve = ToneCurve::from_name(value).ok_or_else(invalid)?,
+            _ => return Err(format!("Unknown render setting {}", name)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InstanceTransform;
+    use cgmath::{Deg, InnerSpace, Matrix4, Point3, Transform, Vector3, Vector4};
+
+    // Translated, rotated about a tilted axis and scaled differently along every axis
+    fn affine() -> Matrix4<f32> {
+        Matrix4::from_translation(Vector3::new(1.5, -2.0, 3.25))
+            * Matrix4::from_axis_angle(Vector3::new(1.0, 2.0, -0.5).normalize(), Deg(37.0))
+            * Matrix4::from_nonuniform_scale(2.0, 0.5, 3.0)
+    }
+
+    #[test]
+    fn translation_round_trip() {
+        let matrix = Matrix4::from_translation(Vector3::new(4.0, -5.0, 6.5));
+        let transform = InstanceTransform::from(matrix);
+        assert_eq!(transform.translation(), [4.0, -5.0, 6.5]);
+        assert_eq!(Matrix4::from(transform), matrix);
+    }
+
+    #[test]
+    fn with_translation_keeps_rotation_and_scale() {
+        let transform = InstanceTransform::from(affine()).with_translation([7.0, 8.0, 9.0]);
+        let mut expected = affine();
+        expected.w = Vector4::new(7.0, 8.0, 9.0, 1.0);
+        assert_eq!(Matrix4::from(transform), expected);
+    }
+
+    #[test]
+    fn rows_are_stored_first() {
+        let transform = InstanceTransform::from(affine());
+        let matrix = affine();
+        for row in 0..3 {
+            for column in 0..4 {
+                assert_eq!(transform.0[row * 4 + column], matrix[column][row]);
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_and_non_uniform_scale_round_trip() {
+        for matrix in [
+            Matrix4::from_angle_y(Deg(90.0)),
+            Matrix4::from_nonuniform_scale(2.0, 0.5, 3.0),
+            affine(),
+        ] {
+            assert_eq!(Matrix4::from(InstanceTransform::from(matrix)), matrix);
+        }
+    }
+
+    #[test]
+    fn transforms_points_like_the_matrix() {
+        let matrix = affine();
+        let transform = InstanceTransform::from(matrix);
+        for point in [[0.0, 0.0, 0.0], [1.0, 2.0, 3.0], [-4.0, 0.5, 7.0]] {
+            let expected = matrix.transform_point(Point3::from(point));
+            let actual = transform.transform_point(point);
+            for axis in 0..3 {
+                assert!((actual[axis] - expected[axis]).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[cfg(feature = "glam")]
+    #[test]
+    fn glam_round_trip() {
+        let matrix = glam::Mat4::from_scale_rotation_translation(
+            glam::Vec3::new(2.0, 0.5, 3.0),
+            glam::Quat::from_axis_angle(glam::Vec3::new(1.0, 2.0, -0.5).normalize(), 0.6),
+            glam::Vec3::new(1.5, -2.0, 3.25),
+        );
+        let transform = InstanceTransform::from(matrix);
+        assert_eq!(transform.translation(), [1.5, -2.0, 3.25]);
+        assert_eq!(glam::Mat4::from(transform), matrix);
+    }
+
+    #[cfg(feature = "glam")]
+    #[test]
+    fn glam_and_cgmath_agree() {
+        let cgmath_matrix = affine();
+        let glam_matrix = glam::Mat4::from_cols_array_2d(&cgmath_matrix.into());
+        assert_eq!(
+            InstanceTransform::from(glam_matrix),
+            InstanceTransform::from(cgmath_matrix)
+        );
+    }
+}
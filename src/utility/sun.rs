@@ -0,0 +1,122 @@
+use std::f32::consts::{FRAC_PI_2, TAU};
+
+use cgmath::{Deg, Rad};
+
+use super::constants::*;
+use super::structures::SunParams;
+
+// Sun elevations the sky color changes at, in degrees: full daylight above the first, twilight
+// at the horizon and night below the last
+const DAYLIGHT_ELEVATION: f32 = 10.0;
+const NIGHT_ELEVATION: f32 = -6.0;
+// The sun fades in over these elevations instead of switching on at the horizon
+const SUNRISE_START_ELEVATION: f32 = -1.0;
+const SUNRISE_END_ELEVATION: f32 = 5.0;
+
+// Where the sun is, either set directly or driven by the time of day. Dragging takes it off the
+// day cycle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SunController {
+    // Around the y axis, 0 towards +z and a quarter turn towards +x
+    pub azimuth: Rad<f32>,
+    // Above the horizon
+    pub elevation: Rad<f32>,
+    // Hours since midnight while the day cycle runs
+    pub time_of_day: Option<f32>,
+}
+
+impl Default for SunController {
+    fn default() -> Self {
+        SunController::towards(LIGHT_DIRECTION)
+    }
+}
+
+impl SunController {
+    pub fn towards(direction: [f32; 3]) -> SunController {
+        let [x, y, z] = direction;
+        let horizontal = (x * x + z * z).sqrt();
+        SunController {
+            azimuth: Rad(x.atan2(z)),
+            elevation: Rad(y.atan2(horizontal)),
+            time_of_day: None,
+        }
+    }
+
+    // Normalized, towards the sun
+    pub fn direction(&self) -> [f32; 3] {
+        let (sin_azimuth, cos_azimuth) = self.azimuth.0.sin_cos();
+        let (sin_elevation, cos_elevation) = self.elevation.0.sin_cos();
+        [
+            cos_elevation * sin_azimuth,
+            sin_elevation,
+            cos_elevation * cos_azimuth,
+        ]
+    }
+
+    // A mouse drag of `dx`, `dy` pixels over a view `view_height` pixels tall: sideways turns
+    // the sun around, upwards raises it
+    pub fn drag(&mut self, dx: f32, dy: f32, view_height: f32) {
+        let radians_per_pixel = SUN_DRAG_RADIANS_PER_VIEW / view_height.max(1.0);
+        self.azimuth = Rad((self.azimuth.0 + dx * radians_per_pixel).rem_euclid(TAU));
+        self.elevation =
+            Rad((self.elevation.0 - dy * radians_per_pixel).clamp(-FRAC_PI_2, FRAC_PI_2));
+        self.time_of_day = None;
+    }
+
+    // Sunrise at 6, sunset at 18. The sun crosses the sky through the azimuth it had before and
+    // peaks at SUN_NOON_ELEVATION.
+    pub fn set_time_of_day(&mut self, hours: f32) {
+        let noon_azimuth = match self.time_of_day {
+            Some(previous) => self.azimuth.0 - hour_angle(previous),
+            None => self.azimuth.0,
+        };
+        let hours = hours.rem_euclid(24.0);
+        let hour_angle = hour_angle(hours);
+        let noon_elevation = Rad::from(SUN_NOON_ELEVATION).0;
+        self.azimuth = Rad((noon_azimuth + hour_angle).rem_euclid(TAU));
+        self.elevation = Rad((noon_elevation.sin() * hour_angle.cos()).asin());
+        self.time_of_day = Some(hours);
+    }
+
+    // Moves the day cycle on by `seconds` of animation, SUN_CYCLE_HOURS_PER_SECOND
+    pub fn advance(&mut self, seconds: f32) {
+        if let Some(hours) = self.time_of_day {
+            self.set_time_of_day(hours + seconds * SUN_CYCLE_HOURS_PER_SECOND);
+        }
+    }
+
+    pub fn params(&self) -> SunParams {
+        let elevation = Deg::from(self.elevation).0;
+        let [x, y, z] = self.direction();
+        let irradiance = ((elevation - SUNRISE_START_ELEVATION)
+            / (SUNRISE_END_ELEVATION - SUNRISE_START_ELEVATION))
+            .clamp(0.0, 1.0);
+        let sky = if elevation >= 0.0 {
+            mix(
+                SKY_TWILIGHT_COLOR,
+                SKY_DAY_COLOR,
+                elevation / DAYLIGHT_ELEVATION,
+            )
+        } else {
+            mix(
+                SKY_NIGHT_COLOR,
+                SKY_TWILIGHT_COLOR,
+                1.0 - elevation / NIGHT_ELEVATION,
+            )
+        };
+        SunParams {
+            direction: [x, y, z, irradiance],
+            sky_color: [sky[0], sky[1], sky[2], 0.0],
+        }
+    }
+}
+
+// 0 at noon, a quarter turn at 18
+fn hour_angle(hours: f32) -> f32 {
+    (hours - 12.0) / 24.0 * TAU
+}
+
+fn mix(from: [f32; 3], to: [f32; 3], weight: f32) -> [f32; 3] {
+    let weight = weight.clamp(0.0, 1.0);
+    [0, 1, 2].map(|channel| from[channel] + (to[channel] - from[channel]) * weight)
+}
@@ -0,0 +1,157 @@
+use ash::vk;
+use smallvec::SmallVec;
+
+// Barriers and command buffers of one call that are converted without a heap allocation, more
+// spill onto it
+const INLINE_BARRIERS: usize = 8;
+const INLINE_COMMAND_BUFFERS: usize = 4;
+
+// Barriers and submits written against VK_KHR_synchronization2, where every barrier carries its
+// own stage masks. Devices without the feature get the legacy calls, with the stages of all
+// barriers merged into one dependency; only the stage and access bits that exist in the 32 bit
+// flags may be used.
+pub struct Synchronization {
+    device: ash::Device,
+    enabled: bool,
+}
+
+impl Synchronization {
+    pub fn new(device: ash::Device, enabled: bool) -> Synchronization {
+        Synchronization { device, enabled }
+    }
+
+    pub fn pipeline_barrier(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        memory_barriers: &[vk::MemoryBarrier2],
+        image_barriers: &[vk::ImageMemoryBarrier2],
+    ) {
+        if self.enabled {
+            let dependency_info = vk::DependencyInfo::builder()
+                .memory_barriers(memory_barriers)
+                .image_memory_barriers(image_barriers)
+                .build();
+
+            unsafe {
+                self.device
+                    .cmd_pipeline_barrier2(command_buffer, &dependency_info);
+            }
+            return;
+        }
+
+        let mut src_stage = vk::PipelineStageFlags2::NONE;
+        let mut dst_stage = vk::PipelineStageFlags2::NONE;
+
+        let legacy_memory_barriers: SmallVec<[vk::MemoryBarrier; INLINE_BARRIERS]> =
+            memory_barriers
+                .iter()
+                .map(|barrier| {
+                    src_stage |= barrier.src_stage_mask;
+                    dst_stage |= barrier.dst_stage_mask;
+                    vk::MemoryBarrier::builder()
+                        .src_access_mask(legacy_access(barrier.src_access_mask))
+                        .dst_access_mask(legacy_access(barrier.dst_access_mask))
+                        .build()
+                })
+                .collect();
+        let legacy_image_barriers: SmallVec<[vk::ImageMemoryBarrier; INLINE_BARRIERS]> =
+            image_barriers
+                .iter()
+                .map(|barrier| {
+                    src_stage |= barrier.src_stage_mask;
+                    dst_stage |= barrier.dst_stage_mask;
+                    vk::ImageMemoryBarrier::builder()
+                        .src_access_mask(legacy_access(barrier.src_access_mask))
+                        .dst_access_mask(legacy_access(barrier.dst_access_mask))
+                        .old_layout(barrier.old_layout)
+                        .new_layout(barrier.new_layout)
+                        .src_queue_family_index(barrier.src_queue_family_index)
+                        .dst_queue_family_index(barrier.dst_queue_family_index)
+                        .image(barrier.image)
+                        .subresource_range(barrier.subresource_range)
+                        .build()
+                })
+                .collect();
+
+        // NONE has no legacy equivalent, the ends of the pipe wait on nothing
+        let src_stage = if src_stage == vk::PipelineStageFlags2::NONE {
+            vk::PipelineStageFlags::TOP_OF_PIPE
+        } else {
+            legacy_stage(src_stage)
+        };
+        let dst_stage = if dst_stage == vk::PipelineStageFlags2::NONE {
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE
+        } else {
+            legacy_stage(dst_stage)
+        };
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &legacy_memory_barriers,
+                &[],
+                &legacy_image_barriers,
+            );
+        }
+    }
+
+    // Submits the command buffers as one batch without semaphores
+    pub fn submit(
+        &self,
+        queue: vk::Queue,
+        command_buffers: &[vk::CommandBuffer],
+        fence: vk::Fence,
+    ) -> Result<(), vk::Result> {
+        unsafe {
+            if self.enabled {
+                let command_buffer_infos: SmallVec<
+                    [vk::CommandBufferSubmitInfo; INLINE_COMMAND_BUFFERS],
+                > = command_buffers
+                    .iter()
+                    .map(|&command_buffer| {
+                        vk::CommandBufferSubmitInfo::builder()
+                            .command_buffer(command_buffer)
+                            .build()
+                    })
+                    .collect();
+
+                self.device.queue_submit2(
+                    queue,
+                    &[vk::SubmitInfo2::builder()
+                        .command_buffer_infos(&command_buffer_infos)
+                        .build()],
+                    fence,
+                )
+            } else {
+                self.device.queue_submit(
+                    queue,
+                    &[vk::SubmitInfo::builder()
+                        .command_buffers(command_buffers)
+                        .build()],
+                    fence,
+                )
+            }
+        }
+    }
+}
+
+fn legacy_stage(stage: vk::PipelineStageFlags2) -> vk::PipelineStageFlags {
+    debug_assert!(
+        stage.as_raw() <= u32::MAX as u64,
+        "{:?} has no legacy bit",
+        stage
+    );
+    vk::PipelineStageFlags::from_raw(stage.as_raw() as u32)
+}
+
+fn legacy_access(access: vk::AccessFlags2) -> vk::AccessFlags {
+    debug_assert!(
+        access.as_raw() <= u32::MAX as u64,
+        "{:?} has no legacy bit",
+        access
+    );
+    vk::AccessFlags::from_raw(access.as_raw() as u32)
+}
@@ -0,0 +1,163 @@
+use image::{ImageBuffer, Luma};
+use std::collections::HashMap;
+use std::path::Path;
+
+// Height map the tessellated vertices are pushed out along their normals by, sampled at their
+// texture coordinates
+pub struct DisplacementMap {
+    image: ImageBuffer<Luma<u16>, Vec<u16>>,
+    // Object space distance of a full height step
+    pub scale: f32,
+    // Height that leaves the surface where it is, 0.5 for maps that push in and out
+    pub midlevel: f32,
+}
+
+impl DisplacementMap {
+    pub fn load(path: &Path, scale: f32, midlevel: f32) -> Result<DisplacementMap, String> {
+        let image = image::open(path)
+            .map_err(|err| format!("Failed to open {:?}: {}", path, err))?
+            .to_luma16();
+        Ok(DisplacementMap {
+            image,
+            scale,
+            midlevel,
+        })
+    }
+
+    // Bilinear height in [0, 1], the map repeats outside of [0, 1]
+    pub fn sample(&self, tex_coord: [f32; 2]) -> f32 {
+        let (width, height) = self.image.dimensions();
+        let x = tex_coord[0].rem_euclid(1.0) * width as f32 - 0.5;
+        let y = tex_coord[1].rem_euclid(1.0) * height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let texel = |x: f32, y: f32| {
+            let x = (x as i64).rem_euclid(width as i64) as u32;
+            let y = (y as i64).rem_euclid(height as i64) as u32;
+            self.image.get_pixel(x, y)[0] as f32 / u16::MAX as f32
+        };
+        let top = texel(x0, y0) * (1.0 - fx) + texel(x0 + 1.0, y0) * fx;
+        let bottom = texel(x0, y0 + 1.0) * (1.0 - fx) + texel(x0 + 1.0, y0 + 1.0) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    pub fn offset(&self, tex_coord: [f32; 2]) -> f32 {
+        (self.sample(tex_coord) - self.midlevel) * self.scale
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TessellatedMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+// Vertices of one triangle split `level` times along each edge
+pub fn vertex_count(level: u32) -> usize {
+    ((level + 1) * (level + 2) / 2) as usize
+}
+
+// Splits every triangle into level * level triangles of the same winding. Vertices on the edges
+// and corners are shared between the triangles of the source mesh that share them.
+pub fn tessellate(
+    positions: &[[f32; 3]],
+    tex_coords: &[[f32; 2]],
+    indices: &[u32],
+    level: u32,
+) -> TessellatedMesh {
+    let level = level.max(1);
+    let mut mesh = TessellatedMesh::default();
+    // Source vertices and their weights, sorted by vertex. Points on a shared edge get the same
+    // key from both triangles, and are summed in the same order so they also match bit for bit.
+    let mut vertex_indices: HashMap<Vec<(u32, u32)>, u32> = HashMap::new();
+
+    for corners in indices.chunks_exact(3) {
+        let mut vertex = |i: u32, j: u32| {
+            let mut weights: Vec<(u32, u32)> = [
+                (corners[0], level - i - j),
+                (corners[1], i),
+                (corners[2], j),
+            ]
+            .into_iter()
+            .filter(|&(_, weight)| weight > 0)
+            .collect();
+            weights.sort_unstable();
+            *vertex_indices.entry(weights).or_insert_with_key(|weights| {
+                let mut position = [0.0; 3];
+                let mut tex_coord = [0.0; 2];
+                for &(source, weight) in weights {
+                    let weight = weight as f32 / level as f32;
+                    for axis in 0..3 {
+                        position[axis] += positions[source as usize][axis] * weight;
+                    }
+                    for axis in 0..2 {
+                        tex_coord[axis] += tex_coords[source as usize][axis] * weight;
+                    }
+                }
+                mesh.positions.push(position);
+                mesh.tex_coords.push(tex_coord);
+                mesh.positions.len() as u32 - 1
+            })
+        };
+
+        // Rows of j, each with an upright triangle per step of i and an upside down one between
+        for j in 0..level {
+            for i in 0..level - j {
+                let a = vertex(i, j);
+                let b = vertex(i + 1, j);
+                let c = vertex(i, j + 1);
+                mesh.indices.extend_from_slice(&[a, b, c]);
+                if i + j + 1 < level {
+                    let d = vertex(i + 1, j + 1);
+                    mesh.indices.extend_from_slice(&[b, d, c]);
+                }
+            }
+        }
+    }
+    mesh
+}
+
+// Area weighted average of the normals of the triangles around every vertex
+pub fn vertex_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let sub = |a: [f32; 3], b: [f32; 3]| [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+    let mut normals = vec![[0.0f32; 3]; positions.len()];
+    for corners in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|corner| positions[corners[corner] as usize]);
+        let (e1, e2) = (sub(b, a), sub(c, a));
+        // Twice the area long
+        let normal = [
+            e1[1] * e2[2] - e1[2] * e2[1],
+            e1[2] * e2[0] - e1[0] * e2[2],
+            e1[0] * e2[1] - e1[1] * e2[0],
+        ];
+        for &corner in corners {
+            for axis in 0..3 {
+                normals[corner as usize][axis] += normal[axis];
+            }
+        }
+    }
+    for normal in &mut normals {
+        let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+        if length > 0.0 {
+            *normal = normal.map(|value| value / length);
+        }
+    }
+    normals
+}
+
+// Moves every vertex along the normal of the undisplaced surface
+pub fn displace(mesh: &mut TessellatedMesh, map: &DisplacementMap) {
+    let normals = vertex_normals(&mesh.positions, &mesh.indices);
+    for ((position, normal), &tex_coord) in mesh
+        .positions
+        .iter_mut()
+        .zip(&normals)
+        .zip(&mesh.tex_coords)
+    {
+        let offset = map.offset(tex_coord);
+        for axis in 0..3 {
+            position[axis] += normal[axis] * offset;
+        }
+    }
+}
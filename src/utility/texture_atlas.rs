@@ -0,0 +1,276 @@
+use image::{imageops, RgbaImage};
+
+// Where a texture landed in the atlas, in texels of its page. The padding around it repeats the
+// edge texels so bilinear filtering never reads a neighbour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AtlasRegion {
+    pub page: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AtlasRegion {
+    // Offset and scale taking the texture's UVs to the UVs of its page
+    pub fn uv_transform(&self, page_size: u32) -> [f32; 4] {
+        let page_size = page_size as f32;
+        [
+            self.x as f32 / page_size,
+            self.y as f32 / page_size,
+            self.width as f32 / page_size,
+            self.height as f32 / page_size,
+        ]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AtlasLayout {
+    pub page_size: u32,
+    pub padding: u32,
+    pub page_count: u32,
+    // Every texture was shrunk by this factor to fit the page budget
+    pub downscale: u32,
+    // In the order of the packed sizes
+    pub regions: Vec<AtlasRegion>,
+}
+
+impl AtlasLayout {
+    // Fraction of the page texels covered by textures, padding excluded
+    pub fn occupancy(&self) -> f32 {
+        if self.page_count == 0 {
+            return 0.0;
+        }
+        let used: u64 = self
+            .regions
+            .iter()
+            .map(|region| region.width as u64 * region.height as u64)
+            .sum();
+        used as f32
+            / (self.page_count as u64 * self.page_size as u64 * self.page_size as u64) as f32
+    }
+
+    // The texture shrunk to its region, with the edge texels repeated into the padding
+    pub fn padded_texture(&self, texture: &RgbaImage, region: &AtlasRegion) -> RgbaImage {
+        let texture = if texture.dimensions() == (region.width, region.height) {
+            texture.clone()
+        } else {
+            imageops::resize(
+                texture,
+                region.width,
+                region.height,
+                imageops::FilterType::Triangle,
+            )
+        };
+
+        let padding = self.padding;
+        RgbaImage::from_fn(
+            region.width + 2 * padding,
+            region.height + 2 * padding,
+            |x, y| {
+                let x = x.saturating_sub(padding).min(region.width - 1);
+                let y = y.saturating_sub(padding).min(region.height - 1);
+                *texture.get_pixel(x, y)
+            },
+        )
+    }
+}
+
+struct Shelf {
+    y: u32,
+    height: u32,
+    width: u32,
+}
+
+// Packs textures of the given sizes onto at most `max_pages` square pages. Textures that do not
+// fit are halved, all of them together, until they do; None when not even 1x1 textures fit.
+pub fn pack_atlas(
+    sizes: &[(u32, u32)],
+    page_size: u32,
+    padding: u32,
+    max_pages: u32,
+) -> Option<AtlasLayout> {
+    let mut downscale = 1;
+    loop {
+        let scaled: Vec<(u32, u32)> = sizes
+            .iter()
+            .map(|&(width, height)| {
+                (
+                    width.div_ceil(downscale).max(1),
+                    height.div_ceil(downscale).max(1),
+                )
+            })
+            .collect();
+        if let Some((regions, page_count)) = pack_shelves(&scaled, page_size, padding, max_pages) {
+            return Some(AtlasLayout {
+                page_size,
+                padding,
+                page_count,
+                downscale,
+                regions,
+            });
+        }
+        if scaled
+            .iter()
+            .all(|&(width, height)| width == 1 && height == 1)
+        {
+            return None;
+        }
+        downscale *= 2;
+    }
+}
+
+// Shelf packing, tallest first so every shelf is as high as its first texture
+fn pack_shelves(
+    sizes: &[(u32, u32)],
+    page_size: u32,
+    padding: u32,
+    max_pages: u32,
+) -> Option<(Vec<AtlasRegion>, u32)> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(sizes[index].1));
+
+    let mut pages: Vec<Vec<Shelf>> = Vec::new();
+    let mut regions = vec![
+        AtlasRegion {
+            page: 0,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+        };
+        sizes.len()
+    ];
+
+    for index in order {
+        let (width, height) = sizes[index];
+        let padded_width = width + 2 * padding;
+        let padded_height = height + 2 * padding;
+        if padded_width > page_size || padded_height > page_size {
+            return None;
+        }
+
+        let existing_shelf = pages.iter().enumerate().find_map(|(page, shelves)| {
+            shelves
+                .iter()
+                .position(|shelf| {
+                    shelf.height >= padded_height && page_size - shelf.width >= padded_width
+                })
+                .map(|shelf| (page, shelf))
+        });
+        let (page, shelf) = match existing_shelf {
+            Some(found) => found,
+            None => {
+                let page = pages.iter().position(|shelves| {
+                    let top = shelves.last().map_or(0, |shelf| shelf.y + shelf.height);
+                    page_size - top >= padded_height
+                });
+                let page = match page {
+                    Some(page) => page,
+                    None if (pages.len() as u32) < max_pages => {
+                        pages.push(Vec::new());
+                        pages.len() - 1
+                    }
+                    None => return None,
+                };
+                let shelves = &mut pages[page];
+                let y = shelves.last().map_or(0, |shelf| shelf.y + shelf.height);
+                shelves.push(Shelf {
+                    y,
+                    height: padded_height,
+                    width: 0,
+                });
+                (page, shelves.len() - 1)
+            }
+        };
+
+        let shelf = &mut pages[page][shelf];
+        regions[index] = AtlasRegion {
+            page: page as u32,
+            x: shelf.width + padding,
+            y: shelf.y + padding,
+            width,
+            height,
+        };
+        shelf.width += padded_width;
+    }
+
+    Some((regions, pages.len() as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    // The region grown by its padding, as [left, top, right, bottom)
+    fn padded_rect(region: &AtlasRegion, padding: u32) -> [u32; 4] {
+        [
+            region.x - padding,
+            region.y - padding,
+            region.x + region.width + padding,
+            region.y + region.height + padding,
+        ]
+    }
+
+    #[test]
+    fn padded_regions_do_not_overlap() {
+        let sizes: Vec<(u32, u32)> = (0..40)
+            .map(|index| (8 + index * 37 % 120, 8 + index * 53 % 90))
+            .collect();
+        let (page_size, padding) = (256, 2);
+        let layout = pack_atlas(&sizes, page_size, padding, 8).unwrap();
+        assert_eq!(layout.downscale, 1);
+        assert_eq!(layout.regions.len(), sizes.len());
+
+        for (region, &size) in layout.regions.iter().zip(&sizes) {
+            assert_eq!((region.width, region.height), size);
+            assert!(region.page < layout.page_count);
+            let [_, _, right, bottom] = padded_rect(region, padding);
+            assert!(right <= page_size && bottom <= page_size);
+        }
+        for (index, a) in layout.regions.iter().enumerate() {
+            for b in &layout.regions[index + 1..] {
+                let [a_left, a_top, a_right, a_bottom] = padded_rect(a, padding);
+                let [b_left, b_top, b_right, b_bottom] = padded_rect(b, padding);
+                let overlap = a.page == b.page
+                    && a_left < b_right
+                    && b_left < a_right
+                    && a_top < b_bottom
+                    && b_top < a_bottom;
+                assert!(!overlap, "{:?} overlaps {:?}", a, b);
+            }
+        }
+        assert!(layout.occupancy() > 0.0 && layout.occupancy() <= 1.0);
+    }
+
+    #[test]
+    fn textures_shrink_to_the_page_budget() {
+        let layout = pack_atlas(&[(256, 256); 4], 256, 0, 1).unwrap();
+        assert_eq!(layout.downscale, 2);
+        assert_eq!(layout.page_count, 1);
+        assert_eq!(layout.occupancy(), 1.0);
+
+        // Not even a texel fits next to its padding
+        assert!(pack_atlas(&[(1, 1)], 4, 2, 1).is_none());
+    }
+
+    #[test]
+    fn padding_repeats_the_edge_texels() {
+        let layout = pack_atlas(&[(2, 2)], 16, 1, 1).unwrap();
+        let region = layout.regions[0];
+        assert_eq!((region.x, region.y), (1, 1));
+        assert_eq!(
+            region.uv_transform(16),
+            [1.0 / 16.0, 1.0 / 16.0, 0.125, 0.125]
+        );
+
+        let texture = RgbaImage::from_fn(2, 2, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        let padded = layout.padded_texture(&texture, &region);
+        assert_eq!(padded.dimensions(), (4, 4));
+        assert_eq!(padded.get_pixel(0, 0), texture.get_pixel(0, 0));
+        assert_eq!(padded.get_pixel(3, 0), texture.get_pixel(1, 0));
+        assert_eq!(padded.get_pixel(3, 3), texture.get_pixel(1, 1));
+        assert_eq!(padded.get_pixel(1, 2), texture.get_pixel(0, 1));
+    }
+}
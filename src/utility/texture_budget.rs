@@ -0,0 +1,89 @@
+use super::material::TexturePriority;
+
+// A texture as loaded, before any of its mips are dropped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BudgetTexture {
+    pub width: u32,
+    pub height: u32,
+    pub bytes_per_texel: u32,
+    // 1 for textures without mips, which shrink the same way
+    pub mip_levels: u32,
+    pub priority: TexturePriority,
+}
+
+impl BudgetTexture {
+    // Of the mips left after dropping the top `dropped` ones
+    pub fn size(&self, dropped: u32) -> u64 {
+        let levels = self.mip_levels.saturating_sub(dropped).max(1);
+        (dropped..dropped + levels)
+            .map(|level| {
+                let (width, height) = mip_extent(self.width, self.height, level);
+                width as u64 * height as u64 * self.bytes_per_texel as u64
+            })
+            .sum()
+    }
+}
+
+pub fn mip_extent(width: u32, height: u32, level: u32) -> (u32, u32) {
+    (
+        width.checked_shr(level).unwrap_or(0).max(1),
+        height.checked_shr(level).unwrap_or(0).max(1),
+    )
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BudgetPlan {
+    // Top mips dropped of every texture, in the order they were passed
+    pub dropped: Vec<u32>,
+    pub size: u64,
+    // Whether the budget was met before every texture was down to `min_size`
+    pub fits: bool,
+}
+
+impl BudgetPlan {
+    pub fn dropped_levels(&self) -> u32 {
+        self.dropped.iter().sum()
+    }
+
+    pub fn shrunk_count(&self) -> usize {
+        self.dropped.iter().filter(|&&dropped| dropped > 0).count()
+    }
+}
+
+// Drops top mips one at a time until the textures fit `budget`, always from the largest texture
+// of the lowest priority that can still lose one. A texture whose larger side is down to
+// `min_size` keeps what it has, so the higher priorities are only shrunk once all of the lower
+// ones are that small.
+pub fn plan_mip_drops(textures: &[BudgetTexture], budget: u64, min_size: u32) -> BudgetPlan {
+    let min_size = min_size.max(1);
+    let mut dropped = vec![0; textures.len()];
+    let mut size: u64 = textures.iter().map(|texture| texture.size(0)).sum();
+    while size > budget {
+        let next = textures
+            .iter()
+            .enumerate()
+            .filter(|&(index, texture)| {
+                let (width, height) = mip_extent(texture.width, texture.height, dropped[index]);
+                width.max(height) / 2 >= min_size
+            })
+            .min_by_key(|&(index, texture)| {
+                (
+                    texture.priority,
+                    std::cmp::Reverse(texture.size(dropped[index])),
+                )
+            })
+            .map(|(index, _)| index);
+        let index = match next {
+            Some(index) => index,
+            None => break,
+        };
+        let texture = &textures[index];
+        size -= texture.size(dropped[index]) - texture.size(dropped[index] + 1);
+        dropped[index] += 1;
+    }
+    BudgetPlan {
+        dropped,
+        size,
+        fits: size <= budget,
+    }
+}
@@ -1,6 +1,12 @@
 use std::{ffi::CStr, os::raw::c_char, path::Path};
 
-use super::structures::Vertex;
+use cgmath::Point3;
+
+use super::{
+    bounds::{Aabb, ModelBounds},
+    mesh_optimizer,
+    structures::Vertex,
+};
 
 pub fn vk_to_string(raw_string_array: &[c_char]) -> String {
     let raw_string = unsafe {
@@ -25,7 +31,7 @@ pub fn read_shader_code(shader_path: &Path) -> Vec<u8> {
     bytes_code
 }
 
-pub fn load_model(model_path: &Path) -> (Vec<Vertex>, Vec<u32>) {
+pub fn load_model(model_path: &Path) -> (Vec<Vertex>, Vec<u32>, ModelBounds) {
     let load_options = tobj::LoadOptions {
         single_index: true,
         ..Default::default()
@@ -35,6 +41,7 @@ pub fn load_model(model_path: &Path) -> (Vec<Vertex>, Vec<u32>) {
 
     let mut vertices = vec![];
     let mut indices = vec![];
+    let mut bounds = ModelBounds::default();
 
     let (models, _) = model_obj;
     for m in models.iter() {
@@ -44,7 +51,13 @@ pub fn load_model(model_path: &Path) -> (Vec<Vertex>, Vec<u32>) {
             panic!("Missing texture coordinate for the model.")
         }
 
+        let base_vertex = vertices.len() as u32;
         let total_vertices_count = mesh.positions.len() / 3;
+        bounds.add_mesh(Aabb::from_points(
+            mesh.positions
+                .chunks_exact(3)
+                .map(|position| Point3::new(position[0], position[1], position[2])),
+        ));
         for i in 0..total_vertices_count {
             let vertex = Vertex {
                 pos: [
@@ -59,8 +72,47 @@ pub fn load_model(model_path: &Path) -> (Vec<Vertex>, Vec<u32>) {
             vertices.push(vertex);
         }
 
-        indices = mesh.indices.clone();
+        indices.extend(mesh.indices.iter().map(|&index| index + base_vertex));
     }
-    println!("Num of vertices: {}", vertices.len());
-    (vertices, indices)
+
+    let (vertices, indices) = optimize_mesh(&vertices, &indices);
+    tracing::debug!("{} vertices in {:?}", vertices.len(), model_path);
+    (vertices, indices, bounds)
+}
+
+// Merges identical vertices, then orders the triangles for the vertex cache and against
+// overdraw, and the vertices for fetch locality
+fn optimize_mesh(vertices: &[Vertex], indices: &[u32]) -> (Vec<Vertex>, Vec<u32>) {
+    let acmr_before = mesh_optimizer::average_cache_miss_ratio(indices, 16);
+    let (unique_vertices, indices) =
+        mesh_optimizer::deduplicate_vertices(vertices, indices, |vertex| {
+            let mut key = [0u32; 10];
+            for (bits, value) in key.iter_mut().zip(
+                vertex
+                    .pos
+                    .iter()
+                    .chain(&vertex.color)
+                    .chain(&vertex.tex_coord),
+            ) {
+                *bits = value.to_bits();
+            }
+            key
+        });
+
+    let indices = mesh_optimizer::optimize_vertex_cache(&indices, unique_vertices.len());
+    let positions: Vec<[f32; 3]> = unique_vertices
+        .iter()
+        .map(|vertex| [vertex.pos[0], vertex.pos[1], vertex.pos[2]])
+        .collect();
+    let mut indices = mesh_optimizer::optimize_overdraw(&indices, &positions);
+    let optimized_vertices = mesh_optimizer::optimize_vertex_fetch(&unique_vertices, &mut indices);
+
+    tracing::debug!(
+        "Optimized the model: {} -> {} vertices, ACMR {:.2} -> {:.2}",
+        vertices.len(),
+        optimized_vertices.len(),
+        acmr_before,
+        mesh_optimizer::average_cache_miss_ratio(&indices, 16)
+    );
+    (optimized_vertices, indices)
 }
@@ -0,0 +1,196 @@
+use ash::vk;
+
+use super::general::create_buffer;
+
+// Persistently mapped ring of per-frame slices for transient uniform data. Every frame writes
+// its slice from the start again, so a slice may only be reused once the frame that last used
+// it has finished on the GPU. Allocations are bound with dynamic offsets.
+pub struct UniformRing {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut u8,
+    alignment: vk::DeviceSize,
+    frame_size: vk::DeviceSize,
+    frame_count: usize,
+    frame: usize,
+    head: vk::DeviceSize,
+}
+
+// The mapping is only written through &mut self
+unsafe impl Send for UniformRing {}
+unsafe impl Sync for UniformRing {}
+
+impl UniformRing {
+    pub fn new(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        frame_size: vk::DeviceSize,
+        frame_count: usize,
+    ) -> UniformRing {
+        let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+        let alignment = limits.min_uniform_buffer_offset_alignment.max(1);
+        let frame_size = align_up(frame_size, alignment);
+
+        let (buffer, memory) = create_buffer(
+            device,
+            frame_size * frame_count as vk::DeviceSize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            device_memory_properties,
+        );
+
+        let mapped = unsafe {
+            device
+                .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                .expect("Failed to map uniform ring memory.") as *mut u8
+        };
+
+        UniformRing {
+            buffer,
+            memory,
+            mapped,
+            alignment,
+            frame_size,
+            frame_count,
+            frame: 0,
+            head: 0,
+        }
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    /// Dynamic offset of the first allocation made in `frame`
+    pub fn frame_offset(&self, frame: usize) -> u32 {
+        assert!(
+            frame < self.frame_count,
+            "Uniform ring has no slice for frame {}.",
+            frame
+        );
+        (self.frame_size * frame as vk::DeviceSize) as u32
+    }
+
+    /// Starts writing `frame`'s slice, discarding what was allocated in it before
+    pub fn begin_frame(&mut self, frame: usize) {
+        assert!(
+            frame < self.frame_count,
+            "Uniform ring has no slice for frame {}.",
+            frame
+        );
+        self.frame = frame;
+        self.head = 0;
+    }
+
+    /// Copies `value` into the current frame's slice and returns its dynamic offset
+    pub fn push<T: Copy>(&mut self, value: &T) -> u32 {
+        let size = std::mem::size_of::<T>() as vk::DeviceSize;
+        let offset = align_up(self.head, self.alignment);
+        assert!(
+            offset + size <= self.frame_size,
+            "Uniform ring frame slice of {} bytes is full.",
+            self.frame_size
+        );
+        self.head = offset + size;
+
+        let dynamic_offset = self.frame_offset(self.frame) as vk::DeviceSize + offset;
+        unsafe {
+            let destination = self.mapped.add(dynamic_offset as usize) as *mut T;
+            destination.write_unaligned(*value);
+        }
+        dynamic_offset as u32
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.unmap_memory(self.memory);
+            device.destroy_buffer(self.buffer, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    value.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALIGNMENT: vk::DeviceSize = 256;
+    const FRAME_COUNT: usize = 3;
+
+    // A ring over host memory, never destroyed
+    fn host_ring(memory: &mut [u8], frame_size: vk::DeviceSize) -> UniformRing {
+        assert_eq!(
+            memory.len() as vk::DeviceSize,
+            frame_size * FRAME_COUNT as u64
+        );
+        UniformRing {
+            buffer: vk::Buffer::null(),
+            memory: vk::DeviceMemory::null(),
+            mapped: memory.as_mut_ptr(),
+            alignment: ALIGNMENT,
+            frame_size,
+            frame_count: FRAME_COUNT,
+            frame: 0,
+            head: 0,
+        }
+    }
+
+    fn read(memory: &[u8], offset: u32) -> u32 {
+        let offset = offset as usize;
+        u32::from_ne_bytes(memory[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn pushes_are_aligned_within_the_frame_slice() {
+        let mut memory = vec![0; 2 * ALIGNMENT as usize * FRAME_COUNT];
+        let mut ring = host_ring(&mut memory, 2 * ALIGNMENT);
+        ring.begin_frame(1);
+        let first = ring.push(&7u32);
+        let second = ring.push(&[1.0f32; 3]);
+        assert_eq!(first, ring.frame_offset(1));
+        assert_eq!(
+            second as vk::DeviceSize,
+            first as vk::DeviceSize + ALIGNMENT
+        );
+        assert_eq!(read(&memory, first), 7);
+    }
+
+    #[test]
+    fn frames_wrap_around_to_their_own_slices() {
+        let mut memory = vec![0; ALIGNMENT as usize * FRAME_COUNT];
+        let mut ring = host_ring(&mut memory, ALIGNMENT);
+        let offsets: Vec<u32> = (0..2 * FRAME_COUNT as u32)
+            .map(|frame| {
+                ring.begin_frame(frame as usize % FRAME_COUNT);
+                ring.push(&frame)
+            })
+            .collect();
+        assert_eq!(offsets, [0, 256, 512, 0, 256, 512]);
+        // The second lap overwrote the first one
+        for (frame, &offset) in offsets[FRAME_COUNT..].iter().enumerate() {
+            assert_eq!(read(&memory, offset), (FRAME_COUNT + frame) as u32);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "is full")]
+    fn a_full_frame_slice_panics() {
+        let mut memory = vec![0; ALIGNMENT as usize * FRAME_COUNT];
+        let mut ring = host_ring(&mut memory, ALIGNMENT);
+        ring.push(&0u32);
+        ring.push(&0u32);
+    }
+
+    #[test]
+    #[should_panic(expected = "no slice for frame 3")]
+    fn frames_past_the_ring_panic() {
+        let mut memory = vec![0; ALIGNMENT as usize * FRAME_COUNT];
+        host_ring(&mut memory, ALIGNMENT).begin_frame(FRAME_COUNT);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::utility::constants::*;
+use crate::utility::structures::{RayOffset, RenderSettings, ToneCurve};
+
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+// Outer position and inner size in physical pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Restored on startup from a per-user `key = value` file and saved on exit. Missing or broken
+// entries keep their defaults, `--reset` ignores the file altogether.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserSettings {
+    pub window: Option<WindowGeometry>,
+    pub render: RenderSettings,
+    // OBJ file of the rasterized model
+    pub scene: String,
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        UserSettings {
+            window: None,
+            render: RENDER_SETTINGS,
+            scene: MODEL_PATH.to_string(),
+        }
+    }
+}
+
+impl UserSettings {
+    // %APPDATA% on Windows, the XDG config directory elsewhere
+    pub fn path() -> Option<PathBuf> {
+        let config_dir = std::env::var_os("APPDATA")
+            .or_else(|| std::env::var_os("XDG_CONFIG_HOME"))
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join(USER_SETTINGS_DIR).join(USER_SETTINGS_FILE))
+    }
+
+    pub fn load() -> UserSettings {
+        let path = match UserSettings::path() {
+            Some(path) => path,
+            None => return UserSettings::default(),
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                tracing::debug!("Restoring settings from {:?}", path);
+                UserSettings::parse(&text)
+            }
+            // Nothing saved yet
+            Err(_) => UserSettings::default(),
+        }
+    }
+
+    pub fn save(&self) {
+        let path = match UserSettings::path() {
+            Some(path) => path,
+            None => {
+                tracing::warn!("No settings directory, the settings are not saved");
+                return;
+            }
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, self.to_text()));
+        match result {
+            Ok(()) => tracing::debug!("Saved settings to {:?}", path),
+            Err(err) => tracing::warn!("Failed to save settings to {:?}: {}", path, err),
+        }
+    }
+
+    pub fn parse(text: &str) -> UserSettings {
+        let mut settings = UserSettings::default();
+        let render = &mut settings.render;
+        let mut window: [Option<i64>; 4] = [None; 4];
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => {
+                    tracing::warn!("Ignoring settings line {:?}", line);
+                    continue;
+                }
+            };
+            match key {
+                "window_x" => window[0] = value.parse().ok(),
+                "window_y" => window[1] = value.parse().ok(),
+                "window_width" => window[2] = value.parse().ok(),
+                "window_height" => window[3] = value.parse().ok(),
+                "max_recursion_depth" => parse_into(key, value, &mut render.max_recursion_depth),
+                "samples_per_pixel" => parse_into(key, value, &mut render.samples_per_pixel),
+                "denoise" => parse_into(key, value, &mut render.denoise),
+                "render_scale" => parse_into(key, value, &mut render.render_scale),
+                "exposure_adaptation_speed" => {
+                    parse_into(key, value, &mut render.exposure_adaptation_speed)
+                }
+                "ev_compensation" => parse_into(key, value, &mut render.ev_compensation),
+                "tone_curve" => match ToneCurve::from_name(value) {
+                    Some(curve) => render.tone_curve = curve,
+                    None => tracing::warn!("Ignoring invalid {} = {}", key, value),
+                },
+                "debug_draw" => parse_into(key, value, &mut render.debug_draw),
+                "target_fps" => parse_into(key, value, &mut render.target_fps),
+                "shadow_map" => parse_into(key, value, &mut render.shadow_map),
+                "checkerboard" => parse_into(key, value, &mut render.checkerboard),
+                "ray_t_min" => parse_into(key, value, &mut render.ray_t_min),
+                "ray_offset" => match RayOffset::from_name(value) {
+                    Some(offset) => render.ray_offset = offset,
+                    None => tracing::warn!("Ignoring invalid {} = {}", key, value),
+                },
+                "ray_offset_scale" => parse_into(key, value, &mut render.ray_offset_scale),
+                "scene" => settings.scene = value.to_string(),
+                _ => tracing::warn!("Ignoring unknown setting {}", key),
+            }
+        }
+
+        // The window is only moved when the whole geometry was saved
+        if let [Some(x), Some(y), Some(width), Some(height)] = window {
+            settings.window = Some(WindowGeometry {
+                x: x as i32,
+                y: y as i32,
+                width: width as u32,
+                height: height as u32,
+            });
+        }
+        settings
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        if let Some(window) = self.window {
+            let _ = writeln!(text, "window_x = {}", window.x);
+            let _ = writeln!(text, "window_y = {}", window.y);
+            let _ = writeln!(text, "window_width = {}", window.width);
+            let _ = writeln!(text, "window_height = {}", window.height);
+        }
+        let render = &self.render;
+        let _ = writeln!(text, "max_recursion_depth = {}", render.max_recursion_depth);
+        let _ = writeln!(text, "samples_per_pixel = {}", render.samples_per_pixel);
+        let _ = writeln!(text, "denoise = {}", render.denoise);
+        let _ = writeln!(text, "render_scale = {}", render.render_scale);
+        let _ = writeln!(
+            text,
+            "exposure_adaptation_speed = {}",
+            render.exposure_adaptation_speed
+        );
+        let _ = writeln!(text, "ev_compensation = {}", render.ev_compensation);
+        let _ = writeln!(text, "tone_curve = {}", render.tone_curve.name());
+        let _ = writeln!(text, "debug_draw = {}", render.debug_draw);
+        let _ = writeln!(text, "target_fps = {}", render.target_fps);
+        let _ = writeln!(text, "shadow_map = {}", render.shadow_map);
+        let _ = writeln!(text, "checkerboard = {}", render.checkerboard);
+        let _ = writeln!(text, "ray_t_min = {}", render.ray_t_min);
+        let _ = writeln!(text, "ray_offset = {}", render.ray_offset.name());
+        let _ = writeln!(text, "ray_offset_scale = {}", render.ray_offset_scale);
+        let _ = writeln!(text, "scene = {}", self.scene);
+        text
+    }
+}
+
+fn parse_into<T: FromStr>(key: &str, value: &str, target: &mut T) {
+    match value.parse() {
+        Ok(parsed) => *target = parsed,
+        Err(_) => tracing::warn!("Ignoring invalid {} = {}", key, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every setting off its default
+    fn edited() -> UserSettings {
+        UserSettings {
+            window: Some(WindowGeometry {
+                x: -1920,
+                y: 24,
+                width: 1280,
+                height: 720,
+            }),
+            render: RenderSettings {
+                max_recursion_depth: 7,
+                samples_per_pixel: 16,
+                denoise: true,
+                render_scale: 0.75,
+                exposure_adaptation_speed: 0.1,
+                ev_compensation: -1.5,
+                tone_curve: ToneCurve::Reinhard,
+                debug_draw: true,
+                target_fps: 60.0,
+                shadow_map: true,
+                checkerboard: true,
+                ray_t_min: 1e-4,
+                ray_offset: RayOffset::Fixed,
+                ray_offset_scale: 0.002,
+            },
+            scene: "assets/other scene.obj".to_string(),
+        }
+    }
+
+    #[test]
+    fn settings_round_trip_through_text() {
+        let defaults = UserSettings::default();
+        assert_eq!(UserSettings::parse(&defaults.to_text()), defaults);
+
+        let edited = edited();
+        assert_ne!(edited.render, defaults.render);
+        assert_eq!(UserSettings::parse(&edited.to_text()), edited);
+    }
+
+    #[test]
+    fn broken_entries_keep_their_defaults() {
+        let settings = UserSettings::parse(
+            "# comment\nsamples_per_pixel = many\ntone_curve = sepia\nnonsense\n\
+             window_x = 10\nwindow_y = 10\nwindow_width = 800\ndenoise = true\n",
+        );
+        let mut expected = UserSettings::default();
+        expected.render.denoise = true;
+        // Without a height the window is left where the system puts it
+        assert_eq!(settings, expected);
+    }
+}
@@ -0,0 +1,80 @@
+use ash::vk;
+
+// Vertex position encodings accepted both as BLAS geometry and as raster vertex input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VertexPositionFormat {
+    Float32,
+    // Half the memory of Float32 with 11 bits of precision, w is padding
+    Float16,
+}
+
+impl VertexPositionFormat {
+    pub fn vk_format(self) -> vk::Format {
+        match self {
+            VertexPositionFormat::Float32 => vk::Format::R32G32B32_SFLOAT,
+            VertexPositionFormat::Float16 => vk::Format::R16G16B16A16_SFLOAT,
+        }
+    }
+
+    pub fn stride(self) -> usize {
+        match self {
+            VertexPositionFormat::Float32 => 3 * std::mem::size_of::<f32>(),
+            VertexPositionFormat::Float16 => 4 * std::mem::size_of::<u16>(),
+        }
+    }
+
+    /// Tightly packed vertex buffer contents, `stride()` bytes per position
+    pub fn encode(self, positions: &[[f32; 3]]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(positions.len() * self.stride());
+        for position in positions {
+            match self {
+                VertexPositionFormat::Float32 => {
+                    for value in position {
+                        data.extend_from_slice(&value.to_ne_bytes());
+                    }
+                }
+                VertexPositionFormat::Float16 => {
+                    for &value in position.iter().chain(&[1.0]) {
+                        data.extend_from_slice(&f32_to_f16(value).to_ne_bytes());
+                    }
+                }
+            }
+        }
+        data
+    }
+}
+
+// IEEE 754 binary16 bits, rounded to nearest even
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinity, NaN stays a quiet NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if half_exponent <= 0 {
+        // Too small for a normal half, flushed to zero below the smallest subnormal
+        if half_exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let round_bit = 1 << (shift - 1);
+        let round_up = mantissa & round_bit != 0 && mantissa & (3 * round_bit - 1) != 0;
+        return sign | ((mantissa >> shift) + round_up as u32) as u16;
+    }
+
+    // A rounding carry out of the mantissa correctly bumps the exponent
+    let half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+    let round_up = mantissa & 0x1000 != 0 && mantissa & 0x2fff != 0;
+    sign | (half + round_up as u32) as u16
+}
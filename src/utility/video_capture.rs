@@ -0,0 +1,195 @@
+use crate::utility::constants::*;
+
+use image::RgbaImage;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::JoinHandle;
+
+// Where captured frames go, picked by the extension of the output path
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CaptureOutput {
+    // `{prefix}_00000.png`, `{prefix}_00001.png`, ...
+    ImageSequence { prefix: String },
+    // Raw frames piped to FFMPEG_PATH, H.264 for .mp4 and .mkv, ProRes for .mov
+    Ffmpeg { path: PathBuf },
+}
+
+impl CaptureOutput {
+    pub fn from_path(path: &str) -> CaptureOutput {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("mp4" | "mkv" | "mov") => CaptureOutput::Ffmpeg {
+                path: PathBuf::from(path),
+            },
+            _ => CaptureOutput::ImageSequence {
+                prefix: path.to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CaptureSettings {
+    pub frame_rate: u32,
+    // Seconds of animation
+    pub duration: f32,
+}
+
+impl CaptureSettings {
+    pub fn frame_count(&self) -> u32 {
+        (self.duration * self.frame_rate as f32).round().max(1.0) as u32
+    }
+
+    pub fn frame_seconds(&self) -> f32 {
+        1.0 / self.frame_rate.max(1) as f32
+    }
+}
+
+// Encodes RGBA8 frames on a worker thread, so reading back the next frame overlaps writing the
+// last one. At most CAPTURE_QUEUE_FRAMES wait for the worker, submit blocks beyond that.
+pub struct VideoCapture {
+    sender: Option<SyncSender<Vec<u8>>>,
+    worker: Option<JoinHandle<Result<u32, String>>>,
+}
+
+impl VideoCapture {
+    pub fn start(output: CaptureOutput, width: u32, height: u32, frame_rate: u32) -> VideoCapture {
+        let (sender, receiver) = sync_channel::<Vec<u8>>(CAPTURE_QUEUE_FRAMES);
+        let worker = std::thread::spawn(move || {
+            let mut encoder = FrameEncoder::new(&output, width, height, frame_rate)?;
+            let mut frame_count = 0;
+            for pixels in receiver {
+                encoder.write(frame_count, pixels)?;
+                frame_count += 1;
+            }
+            encoder.finish()?;
+            Ok(frame_count)
+        });
+        VideoCapture {
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    // Tightly packed RGBA8 rows, top to bottom. Dropped once the worker has failed, finish
+    // reports why.
+    pub fn submit(&self, pixels: Vec<u8>) {
+        if let Some(sender) = &self.sender {
+            // A send only fails when the worker is gone
+            let _ = sender.send(pixels);
+        }
+    }
+
+    // Waits for the worker to write every submitted frame, returns how many it wrote
+    pub fn finish(mut self) -> Result<u32, String> {
+        self.sender = None;
+        self.worker
+            .take()
+            .unwrap()
+            .join()
+            .map_err(|_| "The capture worker panicked".to_string())?
+    }
+}
+
+enum FrameEncoder {
+    ImageSequence {
+        prefix: String,
+        width: u32,
+        height: u32,
+    },
+    Ffmpeg {
+        process: Child,
+    },
+}
+
+impl FrameEncoder {
+    fn new(
+        output: &CaptureOutput,
+        width: u32,
+        height: u32,
+        frame_rate: u32,
+    ) -> Result<FrameEncoder, String> {
+        match output {
+            CaptureOutput::ImageSequence { prefix } => Ok(FrameEncoder::ImageSequence {
+                prefix: prefix.clone(),
+                width,
+                height,
+            }),
+            CaptureOutput::Ffmpeg { path } => {
+                let is_prores = path
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("mov"));
+                let codec: &[&str] = if is_prores {
+                    &[
+                        "-c:v",
+                        "prores_ks",
+                        "-profile:v",
+                        "3",
+                        "-pix_fmt",
+                        "yuv422p10le",
+                    ]
+                } else {
+                    &["-c:v", "libx264", "-crf", "18", "-pix_fmt", "yuv420p"]
+                };
+                let process = Command::new(FFMPEG_PATH)
+                    .args(["-y", "-loglevel", "error", "-f", "rawvideo"])
+                    .args(["-pix_fmt", "rgba"])
+                    .args(["-s", &format!("{}x{}", width, height)])
+                    .args(["-framerate", &frame_rate.to_string()])
+                    .args(["-i", "-"])
+                    .args(codec)
+                    .arg(path)
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .map_err(|err| format!("Failed to run {}: {}", FFMPEG_PATH, err))?;
+                Ok(FrameEncoder::Ffmpeg { process })
+            }
+        }
+    }
+
+    fn write(&mut self, index: u32, pixels: Vec<u8>) -> Result<(), String> {
+        match self {
+            FrameEncoder::ImageSequence {
+                prefix,
+                width,
+                height,
+            } => {
+                let path = format!("{}_{:05}.png", prefix, index);
+                RgbaImage::from_raw(*width, *height, pixels)
+                    .ok_or_else(|| format!("Frame {} has the wrong size", index))?
+                    .save(&path)
+                    .map_err(|err| format!("Failed to save {}: {}", path, err))
+            }
+            FrameEncoder::Ffmpeg { process } => process
+                .stdin
+                .as_mut()
+                .unwrap()
+                .write_all(&pixels)
+                .map_err(|err| format!("{} stopped taking frames: {}", FFMPEG_PATH, err)),
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self {
+            FrameEncoder::ImageSequence { .. } => Ok(()),
+            FrameEncoder::Ffmpeg { mut process } => {
+                // Closing stdin ends the input
+                drop(process.stdin.take());
+                let status = process
+                    .wait()
+                    .map_err(|err| format!("Failed to wait for {}: {}", FFMPEG_PATH, err))?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(format!("{} failed: {}", FFMPEG_PATH, status))
+                }
+            }
+        }
+    }
+}
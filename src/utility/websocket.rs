@@ -0,0 +1,263 @@
+use std::io::{self, ErrorKind};
+
+// Appended to the client's key before hashing it into the accept key (RFC 6455, 1.3)
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub const OPCODE_CONTINUATION: u8 = 0x0;
+pub const OPCODE_TEXT: u8 = 0x1;
+pub const OPCODE_BINARY: u8 = 0x2;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xA;
+
+// A frame a client sent, unmasked. Messages longer than one frame continue in frames with
+// OPCODE_CONTINUATION until one is final.
+pub struct Frame {
+    pub is_final: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+// The length of the HTTP upgrade request at the start of `bytes` and the response accepting it,
+// None until the whole request arrived. Extensions and subprotocols are not negotiated.
+// Browsers send the page's Origin with every upgrade, any page the user visits could otherwise
+// connect, so requests with an Origin outside `allowed_origins` are refused. Tools send none.
+pub fn handshake(bytes: &[u8], allowed_origins: &[String]) -> io::Result<Option<(usize, Vec<u8>)>> {
+    let end = match bytes.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(position) => position + 4,
+        None => return Ok(None),
+    };
+    let request = String::from_utf8_lossy(&bytes[..end]);
+    if let Some(origin) = header(&request, "origin") {
+        if !allowed_origins.iter().any(|allowed| allowed == origin) {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("WebSocket origin {} not allowed", origin),
+            ));
+        }
+    }
+    let key = header(&request, "sec-websocket-key")
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "not a WebSocket upgrade"))?;
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    Ok(Some((end, response.into_bytes())))
+}
+
+// The value of the first header called `name`, in any case
+fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request.lines().skip(1).find_map(|line| {
+        let (header, value) = line.split_once(':')?;
+        header
+            .trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim())
+    })
+}
+
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()))
+}
+
+// The frame at the start of `bytes` and its length, None until all of it arrived
+pub fn parse_frame(bytes: &[u8]) -> io::Result<Option<(usize, Frame)>> {
+    if bytes.len() < 2 {
+        return Ok(None);
+    }
+    // Clients mask every frame they send
+    if bytes[1] & 0x80 == 0 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "unmasked client frame",
+        ));
+    }
+    let (length, mask_start) = match bytes[1] & 0x7f {
+        126 if bytes.len() < 4 => return Ok(None),
+        126 => (u16::from_be_bytes([bytes[2], bytes[3]]) as u64, 4),
+        127 if bytes.len() < 10 => return Ok(None),
+        127 => (u64::from_be_bytes(bytes[2..10].try_into().unwrap()), 10),
+        length => (length as u64, 2),
+    };
+    let payload_start = mask_start + 4;
+    let end = usize::try_from(length)
+        .ok()
+        .and_then(|length| length.checked_add(payload_start))
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "frame too long"))?;
+    if bytes.len() < end {
+        return Ok(None);
+    }
+    let mask = &bytes[mask_start..payload_start];
+    let payload = bytes[payload_start..end]
+        .iter()
+        .zip(mask.iter().cycle())
+        .map(|(byte, mask)| byte ^ mask)
+        .collect();
+    Ok(Some((
+        end,
+        Frame {
+            is_final: bytes[0] & 0x80 != 0,
+            opcode: bytes[0] & 0x0f,
+            payload,
+        },
+    )))
+}
+
+// A final, unmasked frame as servers send them
+pub fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        length if length < 126 => frame.push(length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// Only hashes the handshake key, SHA-1 is what the protocol asks for there
+fn sha1(message: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(message.len() as u64 * 8).to_be_bytes());
+
+    for block in padded.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, new_value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(new_value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, &byte)| {
+            bits | (byte as u32) << (16 - 8 * index)
+        });
+        // A chunk of n bytes fills n + 1 characters, the rest is padding
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(ALPHABET[(bits >> (18 - 6 * index) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example of RFC 6455, 1.3
+    #[test]
+    fn accept_key_matches_the_rfc() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn handshake_waits_for_the_whole_request() {
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\
+                        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n{";
+        assert!(handshake(&request[..20], &[]).unwrap().is_none());
+        let (length, response) = handshake(request, &[]).unwrap().unwrap();
+        assert_eq!(length, request.len() - 1);
+        assert!(String::from_utf8(response)
+            .unwrap()
+            .contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(handshake(b"GET / HTTP/1.1\r\n\r\n", &[]).is_err());
+    }
+
+    #[test]
+    fn handshake_checks_the_origin() {
+        let request = b"GET / HTTP/1.1\r\nOrigin: http://localhost:8000\r\n\
+                        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        let error = handshake(request, &[]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+        let other = ["https://example.com".to_string()];
+        assert!(handshake(request, &other).is_err());
+        let allowed = ["http://localhost:8000".to_string()];
+        assert!(handshake(request, &allowed).unwrap().is_some());
+    }
+
+    // The masked "Hello" of RFC 6455, 5.7
+    #[test]
+    fn parses_a_masked_frame() {
+        let bytes = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert!(parse_frame(&bytes[..8]).unwrap().is_none());
+        let (length, frame) = parse_frame(&bytes).unwrap().unwrap();
+        assert_eq!(length, bytes.len());
+        assert!(frame.is_final);
+        assert_eq!(frame.opcode, OPCODE_TEXT);
+        assert_eq!(frame.payload, b"Hello");
+        assert!(parse_frame(&[0x81, 0x05, b'H']).is_err());
+    }
+
+    #[test]
+    fn encodes_the_payload_length() {
+        assert_eq!(encode_frame(OPCODE_TEXT, b"Hello")[..2], [0x81, 5]);
+        assert_eq!(
+            encode_frame(OPCODE_TEXT, &[0; 300])[..4],
+            [0x81, 126, 1, 44]
+        );
+        let frame = encode_frame(OPCODE_BINARY, &[0; 70000]);
+        assert_eq!(frame[..2], [0x82, 127]);
+        assert_eq!(frame.len(), 10 + 70000);
+    }
+}
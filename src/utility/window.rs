@@ -1,13 +1,24 @@
-use crate::utility::{constants::*, fps_limiter};
+#[cfg(feature = "remote-control")]
+use crate::utility::remote_control::RemoteControlServer;
+use crate::utility::{
+    console::{CommandRegistry, Console},
+    constants::*,
+    fixed_timestep::FixedTimestep,
+    fps_limiter,
+    input::{Action, Binding, Input, InputMap},
+    user_settings::WindowGeometry,
+};
+use std::time::Instant;
 use winit::{
-    event::{ElementState, Event, KeyboardInput, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
 };
 
 const IS_PAINT_FPS_COUNTER: bool = true;
 
+// Also takes the EventLoop itself, which derefs to its window target
 pub fn init_window(
-    event_loop: &winit::event_loop::EventLoop<()>,
+    event_loop: &EventLoopWindowTarget<()>,
     title: &str,
     width: u32,
     height: u32,
@@ -19,13 +30,38 @@ pub fn init_window(
         .expect("Failed to create window.")
 }
 
+// Moves and resizes the window to where it was when the geometry was saved
+pub fn restore_geometry(window: &winit::window::Window, geometry: &WindowGeometry) {
+    window.set_inner_size(winit::dpi::PhysicalSize::new(
+        geometry.width,
+        geometry.height,
+    ));
+    window.set_outer_position(winit::dpi::PhysicalPosition::new(geometry.x, geometry.y));
+}
+
+// None on platforms that cannot tell where the window is
+pub fn window_geometry(window: &winit::window::Window) -> Option<WindowGeometry> {
+    let position = window.outer_position().ok()?;
+    let size = window.inner_size();
+    Some(WindowGeometry {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+    })
+}
+
 pub trait VulkanApp {
-    fn draw_frame(&mut self, delta_time: f32);
+    // Advances the simulation by one fixed step of `step` seconds, with the actions `input` holds
+    fn update(&mut self, step: f32, input: &Input);
+    // Renders between the last two simulation states, `alpha` of the way from the older one
+    fn draw_frame(&mut self, alpha: f32);
     fn recreate_swapchain(&mut self);
-    fn cleanup_swapchain(&self);
+    fn cleanup_swapchain(&mut self);
     fn wait_device_idle(&self);
     fn resize_framebuffer(&mut self);
-    fn window_ref(&self) -> &winit::window::Window;
+    // None for windows the host owns through their raw handles
+    fn window_ref(&self) -> Option<&winit::window::Window>;
 }
 
 pub struct ProgramProc {
@@ -38,8 +74,44 @@ impl ProgramProc {
         ProgramProc { event_loop }
     }
 
-    pub fn main_loop<A: 'static + VulkanApp>(self, mut vulkan_app: A) {
+    // `commands` are what the drop-down console runs, Action::ToggleConsole opens and closes it.
+    // With the remote-control feature they are also served over TCP and WebSocket at
+    // `remote_control_address`, to the web pages of `remote_control_origins` in browsers.
+    pub fn main_loop<A: 'static + VulkanApp>(
+        self,
+        mut vulkan_app: A,
+        commands: CommandRegistry<A>,
+        input_map: InputMap,
+        remote_control_address: Option<&str>,
+        remote_control_origins: Vec<String>,
+    ) {
         let mut tick_counter = fps_limiter::FPSLimiter::new();
+        let mut console = Console::default();
+        let mut input = Input::new(input_map);
+        #[cfg(feature = "gamepad")]
+        let mut gamepads = gilrs::Gilrs::new()
+            .map_err(|err| tracing::warn!("No gamepad input: {}", err))
+            .ok();
+        #[cfg(feature = "remote-control")]
+        let mut remote_control = remote_control_address.map(|address| {
+            RemoteControlServer::bind(address, remote_control_origins).unwrap_or_else(|err| {
+                panic!(
+                    "Failed to listen for remote control on {}: {}.",
+                    address, err
+                )
+            })
+        });
+        #[cfg(not(feature = "remote-control"))]
+        let _ = remote_control_origins;
+        #[cfg(not(feature = "remote-control"))]
+        if let Some(address) = remote_control_address {
+            tracing::warn!(
+                "Not listening on {}, built without the remote-control feature",
+                address
+            );
+        }
+        let mut timestep = FixedTimestep::new(SIMULATION_TIMESTEP, MAX_SIMULATION_STEPS_PER_FRAME);
+        let mut last_frame = Instant::now();
 
         self.event_loop
             .run(move |event, _, control_flow| match event {
@@ -48,32 +120,105 @@ impl ProgramProc {
                         vulkan_app.wait_device_idle();
                         *control_flow = ControlFlow::Exit
                     }
-                    WindowEvent::KeyboardInput { input, .. } => match input {
-                        KeyboardInput {
-                            virtual_keycode,
-                            state,
-                            ..
-                        } => match (virtual_keycode, state) {
-                            (Some(WINDOW_KEYCODE_EXIT), ElementState::Pressed) => {
-                                vulkan_app.wait_device_idle();
-                                *control_flow = ControlFlow::Exit
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(key),
+                                state,
+                                ..
+                            },
+                        ..
+                    } => {
+                        let binding = Binding::Key(key);
+                        if state == ElementState::Released {
+                            input.set_level(binding, 0.0);
+                            return;
+                        }
+                        let mut is_handled = false;
+                        for action in input.map.actions(binding) {
+                            is_handled |=
+                                on_action(action, &mut vulkan_app, &mut console, control_flow);
+                        }
+                        if is_handled {
+                            // The console may have just taken the keyboard
+                            input.release_keys();
+                        } else if console.is_open {
+                            match key {
+                                VirtualKeyCode::Back => console.backspace(),
+                                VirtualKeyCode::Up => console.recall(true),
+                                VirtualKeyCode::Down => console.recall(false),
+                                VirtualKeyCode::Return => {
+                                    // The console is a terminal session: commands and
+                                    // their output go to stdout, not to the log
+                                    if let Some(line) = console.submit() {
+                                        println!("> {}", line);
+                                        match commands.execute(&mut vulkan_app, &line) {
+                                            Ok(output) if output.is_empty() => {}
+                                            Ok(output) => println!("{}", output),
+                                            Err(message) => println!("{}", message),
+                                        }
+                                    }
+                                }
+                                _ => {}
                             }
-                            _ => {}
-                        },
-                    },
+                            show_console(&vulkan_app, &console);
+                        } else {
+                            input.set_level(binding, 1.0);
+                        }
+                    }
+                    // Releases are lost while another window has the focus
+                    WindowEvent::Focused(false) => input.release_keys(),
+                    WindowEvent::ReceivedCharacter(character) if console.is_open => {
+                        console.type_char(character);
+                        show_console(&vulkan_app, &console);
+                    }
                     WindowEvent::Resized(_new_size) => {
                         vulkan_app.wait_device_idle();
                         vulkan_app.resize_framebuffer();
                     }
+                    // Moving to a monitor with another DPI resizes the surface without a
+                    // Resized event on every platform
+                    WindowEvent::ScaleFactorChanged { .. } => {
+                        vulkan_app.wait_device_idle();
+                        vulkan_app.resize_framebuffer();
+                    }
                     _ => {}
                 },
                 Event::MainEventsCleared => {
-                    vulkan_app.window_ref().request_redraw();
+                    #[cfg(feature = "gamepad")]
+                    while let Some(gilrs::Event { event, .. }) =
+                        gamepads.as_mut().and_then(gilrs::Gilrs::next_event)
+                    {
+                        match event {
+                            gilrs::EventType::ButtonPressed(button, _) => {
+                                for action in input.map.actions(Binding::Button(button)) {
+                                    on_action(action, &mut vulkan_app, &mut console, control_flow);
+                                }
+                            }
+                            // Also sent for digital buttons, at 0 or 1
+                            gilrs::EventType::ButtonChanged(button, value, _) => {
+                                input.set_level(Binding::Button(button), value)
+                            }
+                            gilrs::EventType::AxisChanged(axis, value, _) => {
+                                input.set_axis(axis, value)
+                            }
+                            gilrs::EventType::Disconnected => input.release_gamepad(),
+                            _ => {}
+                        }
+                    }
+                    #[cfg(feature = "remote-control")]
+                    if let Some(server) = remote_control.as_mut() {
+                        server.poll(|line| commands.execute(&mut vulkan_app, line));
+                    }
+                    if let Some(window) = vulkan_app.window_ref() {
+                        window.request_redraw();
+                    }
                 }
                 Event::RedrawRequested(_window_id) => {
-                    let delta_time = tick_counter.delta_time();
-                    vulkan_app.draw_frame(delta_time);
+                    advance_frame(&mut vulkan_app, &input, &mut timestep, &mut last_frame);
 
+                    // A status line rewritten in place on the terminal, which a log
+                    // record per frame could not be
                     if IS_PAINT_FPS_COUNTER {
                         print!("FPS: {}\r", tick_counter.fps());
                     }
@@ -87,3 +232,105 @@ impl ProgramProc {
             })
     }
 }
+
+// A VulkanApp in a window of an event loop the host owns, e.g. an egui_winit app. The host
+// forwards the events of the app's window to handle_window_event and calls render_frame when it
+// redraws. There is no console and the one-shot actions are left to the host.
+pub struct HostedApp<A: VulkanApp> {
+    pub vulkan_app: A,
+    pub input: Input,
+    timestep: FixedTimestep,
+    last_frame: Instant,
+}
+
+impl<A: VulkanApp> HostedApp<A> {
+    pub fn new(vulkan_app: A, input_map: InputMap) -> Self {
+        HostedApp {
+            vulkan_app,
+            input: Input::new(input_map),
+            timestep: FixedTimestep::new(SIMULATION_TIMESTEP, MAX_SIMULATION_STEPS_PER_FRAME),
+            last_frame: Instant::now(),
+        }
+    }
+
+    // Events of other windows must not be passed in
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => {
+                let level = match state {
+                    ElementState::Pressed => 1.0,
+                    ElementState::Released => 0.0,
+                };
+                self.input.set_level(Binding::Key(*key), level);
+            }
+            WindowEvent::Focused(false) => self.input.release_keys(),
+            WindowEvent::Resized(_) | WindowEvent::ScaleFactorChanged { .. } => {
+                self.vulkan_app.wait_device_idle();
+                self.vulkan_app.resize_framebuffer();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn render_frame(&mut self) {
+        advance_frame(
+            &mut self.vulkan_app,
+            &self.input,
+            &mut self.timestep,
+            &mut self.last_frame,
+        );
+    }
+}
+
+// Runs the simulation steps that came due since `last_frame` and draws between the last two
+fn advance_frame<A: VulkanApp>(
+    vulkan_app: &mut A,
+    input: &Input,
+    timestep: &mut FixedTimestep,
+    last_frame: &mut Instant,
+) {
+    let now = Instant::now();
+    let (steps, alpha) = timestep.advance(now - *last_frame);
+    *last_frame = now;
+    for _ in 0..steps {
+        vulkan_app.update(timestep.step_seconds(), input);
+    }
+    vulkan_app.draw_frame(alpha);
+}
+
+// Runs the one-shot actions, true when `action` is one of them. Exit closes the console before it
+// closes the window.
+fn on_action<A: VulkanApp>(
+    action: Action,
+    vulkan_app: &mut A,
+    console: &mut Console,
+    control_flow: &mut ControlFlow,
+) -> bool {
+    match action {
+        Action::Exit if !console.is_open => {
+            vulkan_app.wait_device_idle();
+            *control_flow = ControlFlow::Exit;
+        }
+        Action::Exit | Action::ToggleConsole => {
+            console.toggle();
+            show_console(vulkan_app, console);
+        }
+        _ => return false,
+    }
+    true
+}
+
+// The console's input line shows in the window title
+fn show_console<A: VulkanApp>(vulkan_app: &A, console: &Console) {
+    if let Some(window) = vulkan_app.window_ref() {
+        window.set_title(&console.title(WINDOW_TITLE));
+    }
+}
@@ -0,0 +1,28 @@
+// Fails when traced frames still allocate on the render thread once the first frames sized the
+// frame context. Runs `ash_rt allocations`, so it needs a display and a GPU with ray tracing:
+// `cargo test --features alloc-count --test allocations`.
+use std::process::Command;
+
+#[test]
+fn frames_do_not_allocate_after_warm_up() {
+    let output = Command::new(env!("CARGO_BIN_EXE_ash_rt"))
+        .arg("allocations")
+        .output()
+        .expect("Failed to run ash_rt.");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "ash_rt allocations failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let report = stdout
+        .lines()
+        .find(|line| line.contains(" allocations in "))
+        .unwrap_or_else(|| panic!("No allocation count in: {}", stdout));
+    let allocations: u64 = report
+        .split_whitespace()
+        .next()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or_else(|| panic!("Unreadable allocation count: {}", report));
+    assert_eq!(allocations, 0, "{}", report);
+}
@@ -0,0 +1,68 @@
+// Compares renders with utility::image_diff. The diff command runs anywhere, the renders need a
+// display and a GPU with ray tracing: `cargo test --test render_diff -- --ignored`.
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use ash_rt::utility::image_diff::{self, DiffSettings};
+use image::{Rgb, RgbImage};
+
+fn output_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+fn run_diff(a: &Path, b: &Path, heatmap: &Path) -> i32 {
+    Command::new(env!("CARGO_BIN_EXE_ash_rt"))
+        .arg("diff")
+        .args([a, b, heatmap])
+        .status()
+        .expect("Failed to run ash_rt.")
+        .code()
+        .expect("ash_rt diff was killed.")
+}
+
+#[test]
+fn diff_command_exits_with_1_on_mismatches() {
+    let image = RgbImage::from_fn(32, 32, |x, y| Rgb([(x * 8) as u8, (y * 8) as u8, 64]));
+    let mut changed = image.clone();
+    changed.put_pixel(3, 4, Rgb([255, 255, 255]));
+    let (a, b) = (output_path("diff_a.png"), output_path("diff_b.png"));
+    image.save(&a).unwrap();
+    changed.save(&b).unwrap();
+
+    let heatmap = output_path("diff_same.png");
+    assert_eq!(run_diff(&a, &a, &heatmap), 0);
+    let heatmap = output_path("diff_changed.png");
+    assert_eq!(run_diff(&a, &b, &heatmap), 1);
+    let heatmap = image::open(&heatmap)
+        .expect("No heatmap saved.")
+        .into_rgb8();
+    assert_eq!(heatmap.get_pixel(3, 4)[0], 255);
+}
+
+fn render(path: &Path) {
+    let output = Command::new(env!("CARGO_BIN_EXE_ash_rt"))
+        .arg("tiled")
+        .args(["1", "1"])
+        .arg(path)
+        .args(["--reset", "--deterministic"])
+        .output()
+        .expect("Failed to run ash_rt.");
+    assert!(
+        output.status.success(),
+        "ash_rt tiled failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+#[ignore = "needs a display and a GPU with ray tracing"]
+fn deterministic_renders_match() {
+    let (first, second) = (output_path("render_a.png"), output_path("render_b.png"));
+    render(&first);
+    render(&second);
+    let diff = image_diff::diff_files(&first, &second, &DiffSettings::default()).unwrap();
+    if !diff.report.matches() {
+        diff.heatmap.save(output_path("render_diff.png")).unwrap();
+    }
+    assert!(diff.report.matches(), "{:?}", diff.report);
+}